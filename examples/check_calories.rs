@@ -40,10 +40,8 @@ fn main() -> Result<()> {
             })?;
 
             println!("Total calories table schema:");
-            for schema_row in schema_rows {
-                if let Ok((name, data_type)) = schema_row {
-                    println!("  {} ({})", name, data_type);
-                }
+            for (name, data_type) in schema_rows.flatten() {
+                println!("  {} ({})", name, data_type);
             }
 
             // Show a sample record with specific fields we know exist
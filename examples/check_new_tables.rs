@@ -36,10 +36,8 @@ fn main() -> Result<()> {
                 })?;
 
                 println!("Schema:");
-                for schema_row in schema_rows {
-                    if let Ok((name, data_type)) = schema_row {
-                        println!("  {} ({})", name, data_type);
-                    }
+                for (name, data_type) in schema_rows.flatten() {
+                    println!("  {} ({})", name, data_type);
                 }
 
                 // Show a sample record
@@ -0,0 +1,44 @@
+// Example of embedding the import pipeline in another program: reads the health records
+// added since the last run, writes them to InfluxDB and saves the import state
+//
+// cargo run --example embed_health_import -- <health.db> <url> <org> <bucket> <token>
+use home_db_importer::{HealthDataReader, InfluxClient, Sink, StateStore};
+use std::env;
+use std::error::Error;
+use std::time::Duration;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [source, url, org, bucket, token] = args.as_slice() else {
+        return Err("usage: embed_health_import <health.db> <url> <org> <bucket> <token>".into());
+    };
+
+    // Source: only the records after the last one imported for each data type
+    let state_store = StateStore::file("embedded_import_state.json");
+    let mut state = state_store.load_import_state(source).await?;
+    let reader = HealthDataReader::new(source);
+    let records_map = reader.get_health_data_since_per_type(
+        |data_type| state.last_imported_for(data_type.as_str()).into(),
+        None,
+    )?;
+
+    // Sink: the client converts the records to points and writes them in batches
+    let client = InfluxClient::builder(url, bucket)
+        .org(org)
+        .token(token)
+        .retries(3, Duration::from_secs(2))
+        .build()?;
+    let written = client.write_health_records(&records_map).await?;
+
+    // State: advance each data type to the latest record written
+    for (record_type, records) in &records_map {
+        if let Some(latest) = records.iter().map(|r| r.timestamp).max() {
+            state.record_import(record_type.as_str(), latest, records.len());
+        }
+    }
+    state_store.save_import_state(&state).await?;
+
+    println!("Imported {} health records", written);
+    Ok(())
+}
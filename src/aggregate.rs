@@ -0,0 +1,453 @@
+use crate::derived::{DerivedPoints, DerivedStage};
+use crate::health_data::{HealthDataReader, HealthDataType, HealthRecord, ReadFrom};
+use crate::influx_client::DataPoint;
+use chrono::{DateTime, Duration, NaiveDate, Offset, TimeZone, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+
+/// Measurement the steps of each day are written to
+pub const DAILY_STEPS_MEASUREMENT: &str = "daily_steps";
+
+/// Measurement the active calories of each day are written to, in kcal
+pub const DAILY_ACTIVE_CALORIES_MEASUREMENT: &str = "daily_active_calories";
+
+/// Measurement the total calories of each day are written to, in kcal
+pub const DAILY_TOTAL_CALORIES_MEASUREMENT: &str = "daily_total_calories";
+
+/// Measurement the minutes slept each night are written to, on the day it ended
+pub const DAILY_SLEEP_MEASUREMENT: &str = "daily_sleep_minutes";
+
+/// Measurement the average heart rate of each day is written to
+pub const DAILY_HEART_RATE_MEASUREMENT: &str = "daily_heart_rate";
+
+/// Measurement the resting heart rate of each day is written to: its lowest hourly average
+pub const DAILY_RESTING_HEART_RATE_MEASUREMENT: &str = "daily_resting_heart_rate";
+
+/// Measurement the minutes slept each night above the sleep target are written to, on the
+/// day it ended; negative below it
+pub const SLEEP_BALANCE_MEASUREMENT: &str = "sleep_balance";
+
+/// Measurement the minutes of sleep missing from the target over the last 7 nights are
+/// written to; negative when more was slept
+pub const SLEEP_DEBT_MEASUREMENT: &str = "sleep_debt";
+
+/// Nights the sleep debt adds up
+const SLEEP_DEBT_NIGHTS: i64 = 7;
+
+/// Heart rate samples an hour needs for its average to count towards the resting heart
+/// rate, so a single low reading is not taken for it
+const RESTING_HOUR_SAMPLES: usize = 3;
+
+/// Per-day rollups written alongside the raw records
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum DailyAggregate {
+    /// Steps per day
+    Steps,
+    /// Active and total kcal per day
+    Calories,
+    /// Minutes slept per night
+    Sleep,
+    /// Average and resting heart rate per day
+    HeartRate,
+}
+
+impl DailyAggregate {
+    /// Every aggregate
+    pub const ALL: [DailyAggregate; 4] = [
+        DailyAggregate::Steps,
+        DailyAggregate::Calories,
+        DailyAggregate::Sleep,
+        DailyAggregate::HeartRate,
+    ];
+
+    /// Gets the data types the aggregate is computed from
+    pub fn data_types(self) -> &'static [HealthDataType] {
+        match self {
+            DailyAggregate::Steps => &[HealthDataType::Steps],
+            DailyAggregate::Calories => &[
+                HealthDataType::ActiveCalories,
+                HealthDataType::TotalCalories,
+            ],
+            DailyAggregate::Sleep => &[HealthDataType::Sleep],
+            DailyAggregate::HeartRate => &[HealthDataType::HeartRate],
+        }
+    }
+}
+
+impl fmt::Display for DailyAggregate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DailyAggregate::Steps => write!(f, "steps"),
+            DailyAggregate::Calories => write!(f, "calories"),
+            DailyAggregate::Sleep => write!(f, "sleep"),
+            DailyAggregate::HeartRate => write!(f, "heart-rate"),
+        }
+    }
+}
+
+/// Sleep stages spent awake, which do not count towards the minutes slept
+const AWAKE_STAGES: [&str; 2] = ["AWAKE", "OUT_OF_BED"];
+
+/// Gets the minutes of a sleep stage, from its start record
+fn sleep_minutes(record: &HealthRecord) -> Option<f64> {
+    if record.metadata.get("event_type").map(String::as_str) != Some("start") {
+        return None;
+    }
+    record.metadata.get("duration_minutes")?.parse().ok()
+}
+
+/// Gets the day a record counts towards, in the local time it was taken at (see
+/// [`HealthRecord::local_time`]): the day a sleep session ends, so a night counts towards
+/// the morning after, and the day any other record starts
+fn day_of(record: &HealthRecord, timezone: Tz) -> NaiveDate {
+    match (record.record_type, sleep_minutes(record)) {
+        (HealthDataType::Sleep, Some(minutes)) => {
+            let end = record.timestamp + Duration::seconds((minutes * 60.0) as i64);
+            record.local_time_of(end, timezone).date()
+        }
+        _ => record.local_time(timezone).date(),
+    }
+}
+
+/// Gets the time zone days are bucketed in when none is given: the system's, or UTC when
+/// it cannot be told
+pub fn system_timezone() -> Tz {
+    iana_time_zone::get_timezone()
+        .ok()
+        .and_then(|name| name.parse().ok())
+        .unwrap_or(Tz::UTC)
+}
+
+/// Gets the time `day` starts at in `timezone`, where its daily points are written. Days
+/// are not always 24 hours long: when a DST change skips midnight, the day starts at the
+/// change
+pub fn start_of_day(day: NaiveDate, timezone: Tz) -> DateTime<Utc> {
+    let midnight = day.and_hms_opt(0, 0, 0).unwrap();
+    match timezone.from_local_datetime(&midnight).earliest() {
+        Some(start) => start.with_timezone(&Utc),
+        None => {
+            // Midnight of the offset in effect before the change
+            let before = timezone.offset_from_utc_datetime(&(midnight - Duration::days(1)));
+            midnight.and_utc() - Duration::seconds(before.fix().local_minus_utc().into())
+        }
+    }
+}
+
+/// Gets the current day in `timezone`
+pub fn today(timezone: Tz) -> NaiveDate {
+    Utc::now().with_timezone(&timezone).date_naive()
+}
+
+/// Gets the days whose `aggregates` change with `records`
+pub fn days_touched(
+    records: &[HealthRecord],
+    aggregates: &[DailyAggregate],
+    timezone: Tz,
+) -> BTreeSet<NaiveDate> {
+    records
+        .iter()
+        .filter(|record| {
+            aggregates
+                .iter()
+                .any(|aggregate| aggregate.data_types().contains(&record.record_type))
+        })
+        .map(|record| day_of(record, timezone))
+        .collect()
+}
+
+/// Gets the time to read records from so that every record of `day` is included, sleep
+/// sessions that started the evening before too
+pub fn read_from(day: NaiveDate) -> DateTime<Utc> {
+    (day - Duration::days(1))
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+        - Duration::milliseconds(1)
+}
+
+/// Computes the `aggregates` of every day among `records`, which must hold every record
+/// of those days. Records count towards the day in the local time they were taken at, so
+/// days follow the time zones travelled through, or in `timezone` when their offset is
+/// unknown. Each is written at the start of its day in `timezone`, per app, so data written
+/// by two apps is not counted twice
+pub fn daily_aggregates(
+    records: &[HealthRecord],
+    aggregates: &[DailyAggregate],
+    timezone: Tz,
+) -> Vec<DataPoint> {
+    // Values by measurement, day and app
+    let mut sums: BTreeMap<(&str, NaiveDate, &str), f64> = BTreeMap::new();
+    // Heart rate samples by hour since the epoch, so the hour repeated when DST ends is
+    // not taken for one
+    let mut heart_rates: BTreeMap<(NaiveDate, &str), BTreeMap<i64, Vec<f64>>> = BTreeMap::new();
+    let mut sleep_sessions = HashSet::new();
+    let wanted = |aggregate| aggregates.contains(&aggregate);
+
+    for record in records {
+        let day = day_of(record, timezone);
+        let app = record
+            .metadata
+            .get("app_name")
+            .map(String::as_str)
+            .unwrap_or("unknown");
+        let (measurement, value) = match record.record_type {
+            HealthDataType::Steps if wanted(DailyAggregate::Steps) => {
+                (DAILY_STEPS_MEASUREMENT, record.value)
+            }
+            HealthDataType::ActiveCalories if wanted(DailyAggregate::Calories) => {
+                (DAILY_ACTIVE_CALORIES_MEASUREMENT, record.value)
+            }
+            HealthDataType::TotalCalories if wanted(DailyAggregate::Calories) => {
+                (DAILY_TOTAL_CALORIES_MEASUREMENT, record.value)
+            }
+            // Sleep sessions are read as a record per stage, so each counts once
+            HealthDataType::Sleep if wanted(DailyAggregate::Sleep) => {
+                let Some(minutes) = sleep_minutes(record) else {
+                    continue;
+                };
+                let stage = record.metadata.get("stage").map(String::as_str);
+                if stage.is_some_and(|stage| AWAKE_STAGES.contains(&stage)) {
+                    continue;
+                }
+                if !sleep_sessions.insert((record.row_id, record.timestamp, app)) {
+                    continue;
+                }
+                (DAILY_SLEEP_MEASUREMENT, minutes)
+            }
+            HealthDataType::HeartRate if wanted(DailyAggregate::HeartRate) => {
+                heart_rates
+                    .entry((day, app))
+                    .or_default()
+                    .entry(record.timestamp.timestamp().div_euclid(3600))
+                    .or_default()
+                    .push(record.value);
+                continue;
+            }
+            _ => continue,
+        };
+        *sums.entry((measurement, day, app)).or_insert(0.0) += value;
+    }
+
+    let point = |measurement: &str, day: NaiveDate, app: &str, value: f64| DataPoint {
+        measurement: measurement.to_string(),
+        time: start_of_day(day, timezone),
+        tags: [("app_name".to_string(), app.to_string())].into(),
+        field_value: (value * 100.0).round() / 100.0,
+        text_fields: BTreeMap::new(),
+    };
+    let mut points: Vec<DataPoint> = sums
+        .into_iter()
+        .map(|((measurement, day, app), value)| point(measurement, day, app, value))
+        .collect();
+
+    let average = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+    for ((day, app), hours) in heart_rates {
+        let samples: Vec<f64> = hours.values().flatten().copied().collect();
+        points.push(point(
+            DAILY_HEART_RATE_MEASUREMENT,
+            day,
+            app,
+            average(&samples),
+        ));
+        let resting = hours
+            .values()
+            .filter(|samples| samples.len() >= RESTING_HOUR_SAMPLES)
+            .map(|samples| average(samples))
+            .min_by(f64::total_cmp);
+        if let Some(resting) = resting {
+            points.push(point(
+                DAILY_RESTING_HEART_RATE_MEASUREMENT,
+                day,
+                app,
+                resting,
+            ));
+        }
+    }
+    points
+}
+
+/// Gets the days whose sleep debt changes with the nights ending on `days`: from the first
+/// to 6 days after the last, as nights leave the debt a week later, but not after `today`
+pub fn sleep_debt_days(
+    days: &BTreeSet<NaiveDate>,
+    today: NaiveDate,
+) -> Option<(NaiveDate, NaiveDate)> {
+    let first = *days.first()?;
+    let last = *days.last()? + Duration::days(SLEEP_DEBT_NIGHTS - 1);
+    Some((first, last.min(today).max(first)))
+}
+
+/// Gets the time to read records from so that the sleep debt from `day` on can be
+/// computed, with the nights before it that it adds up
+pub fn sleep_debt_read_from(day: NaiveDate) -> DateTime<Utc> {
+    read_from(day - Duration::days(SLEEP_DEBT_NIGHTS - 1))
+}
+
+/// Computes the sleep balance of each night from `from` to `until` against a target of
+/// `target_minutes`, and the sleep debt of each of those days, written at their start in
+/// `timezone`. `records` must hold the sleep of the 6 days before `from` too, see
+/// [`sleep_debt_read_from`]
+///
+/// A night recorded by several apps counts as the longest of them; nights without sleep
+/// records are left out of the debt rather than counted as not slept
+pub fn sleep_debt(
+    records: &[HealthRecord],
+    target_minutes: f64,
+    from: NaiveDate,
+    until: NaiveDate,
+    timezone: Tz,
+) -> Vec<DataPoint> {
+    let mut nights: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    for point in daily_aggregates(records, &[DailyAggregate::Sleep], timezone) {
+        let night = point.time.with_timezone(&timezone).date_naive();
+        let minutes = nights.entry(night).or_default();
+        *minutes = minutes.max(point.field_value);
+    }
+
+    let point = |measurement: &str, day: NaiveDate, value: f64| DataPoint {
+        measurement: measurement.to_string(),
+        time: start_of_day(day, timezone),
+        tags: HashMap::new(),
+        field_value: (value * 100.0).round() / 100.0,
+        text_fields: BTreeMap::new(),
+    };
+    let mut points = Vec::new();
+    for day in from.iter_days().take_while(|day| *day <= until) {
+        if let Some(minutes) = nights.get(&day) {
+            points.push(point(
+                SLEEP_BALANCE_MEASUREMENT,
+                day,
+                minutes - target_minutes,
+            ));
+        }
+        let debt: f64 = nights
+            .range(day - Duration::days(SLEEP_DEBT_NIGHTS - 1)..=day)
+            .map(|(_, minutes)| target_minutes - minutes)
+            .sum();
+        points.push(point(SLEEP_DEBT_MEASUREMENT, day, debt));
+    }
+    points
+}
+
+/// Writes the daily aggregates again for the days the records written fall on, from all
+/// their records
+pub struct DailyAggregateStage {
+    aggregates: Vec<DailyAggregate>,
+    inputs: Vec<HealthDataType>,
+    timezone: Tz,
+    days: BTreeSet<NaiveDate>,
+}
+
+impl DailyAggregateStage {
+    /// Creates the stage for `aggregates`, with days in `timezone`
+    pub fn new(aggregates: &[DailyAggregate], timezone: Tz) -> Self {
+        DailyAggregateStage {
+            aggregates: aggregates.to_vec(),
+            inputs: aggregates
+                .iter()
+                .flat_map(|aggregate| aggregate.data_types())
+                .copied()
+                .collect(),
+            timezone,
+            days: BTreeSet::new(),
+        }
+    }
+}
+
+impl DerivedStage for DailyAggregateStage {
+    fn name(&self) -> &str {
+        "daily aggregates"
+    }
+
+    fn inputs(&self) -> &[HealthDataType] {
+        &self.inputs
+    }
+
+    fn prepare(
+        &mut self,
+        _reader: &HealthDataReader,
+        written: &[HealthRecord],
+    ) -> Result<Option<ReadFrom>, Box<dyn Error>> {
+        self.days = days_touched(written, &self.aggregates, self.timezone);
+        Ok(self
+            .days
+            .first()
+            .map(|first| ReadFrom::Timestamp(read_from(*first))))
+    }
+
+    fn compute(&self, records: &[HealthRecord]) -> DerivedPoints {
+        DerivedPoints::Single(
+            daily_aggregates(records, &self.aggregates, self.timezone)
+                .into_iter()
+                .filter(|point| {
+                    let day = point.time.with_timezone(&self.timezone).date_naive();
+                    self.days.contains(&day)
+                })
+                .collect(),
+        )
+    }
+
+    fn describe(&self) -> String {
+        format!(" for {} days", self.days.len())
+    }
+}
+
+/// Writes the sleep debt again for the days the nights written leave it on
+pub struct SleepDebtStage {
+    target_minutes: f64,
+    timezone: Tz,
+    days: Option<(NaiveDate, NaiveDate)>,
+}
+
+impl SleepDebtStage {
+    /// Creates the stage for a nightly target of `target_minutes`, with days in `timezone`
+    pub fn new(target_minutes: f64, timezone: Tz) -> Self {
+        SleepDebtStage {
+            target_minutes,
+            timezone,
+            days: None,
+        }
+    }
+}
+
+impl DerivedStage for SleepDebtStage {
+    fn name(&self) -> &str {
+        "sleep debt"
+    }
+
+    fn inputs(&self) -> &[HealthDataType] {
+        &[HealthDataType::Sleep]
+    }
+
+    /// Nights leave the sleep debt a week after they end
+    fn prepare(
+        &mut self,
+        _reader: &HealthDataReader,
+        written: &[HealthRecord],
+    ) -> Result<Option<ReadFrom>, Box<dyn Error>> {
+        let days = days_touched(written, &[DailyAggregate::Sleep], self.timezone);
+        self.days = sleep_debt_days(&days, today(self.timezone));
+        Ok(self
+            .days
+            .map(|(from, _)| ReadFrom::Timestamp(sleep_debt_read_from(from))))
+    }
+
+    fn compute(&self, records: &[HealthRecord]) -> DerivedPoints {
+        let points = match self.days {
+            Some((from, until)) => {
+                sleep_debt(records, self.target_minutes, from, until, self.timezone)
+            }
+            None => Vec::new(),
+        };
+        DerivedPoints::Single(points)
+    }
+
+    fn describe(&self) -> String {
+        self.days
+            .map(|(from, _)| format!(" since {}", from))
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,69 @@
+use crate::influx_client::{DataPoint, MultiFieldPoint};
+use clap::ValueEnum;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt;
+
+/// Tags that identify the person or their devices: the apps and devices that recorded the
+/// data and the titles given to exercise sessions
+pub const IDENTIFYING_TAGS: [&str; 4] = ["app_name", "package_name", "device", "title"];
+
+/// How identifying tags are hidden before points are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Anonymize {
+    /// Replace each value with a short hash, so series of different apps stay apart
+    Hash,
+    /// Leave the tags out
+    Strip,
+}
+
+impl fmt::Display for Anonymize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anonymize::Hash => write!(f, "hash"),
+            Anonymize::Strip => write!(f, "strip"),
+        }
+    }
+}
+
+impl Anonymize {
+    /// Hides the identifying tags among `tags`
+    pub fn tags(self, tags: &mut HashMap<String, String>) {
+        for tag in IDENTIFYING_TAGS {
+            match self {
+                Anonymize::Strip => {
+                    tags.remove(tag);
+                }
+                Anonymize::Hash => {
+                    if let Some(value) = tags.get_mut(tag) {
+                        *value = pseudonym(value);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Gets a copy of `point` with its identifying tags hidden
+    pub fn point(self, point: &DataPoint) -> DataPoint {
+        let mut point = point.clone();
+        self.tags(&mut point.tags);
+        point
+    }
+
+    /// Gets a copy of `point` with its identifying tags hidden
+    pub fn multi_field_point(self, point: &MultiFieldPoint) -> MultiFieldPoint {
+        let mut point = point.clone();
+        self.tags(&mut point.tags);
+        point
+    }
+}
+
+/// Gets the pseudonym of a tag value: the first 12 hex digits of its SHA-256, the same on
+/// every run so the series written before and after match
+pub fn pseudonym(value: &str) -> String {
+    Sha256::digest(value.as_bytes())
+        .iter()
+        .take(6)
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
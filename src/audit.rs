@@ -0,0 +1,187 @@
+use crate::influx_client::{Precision, STATE_MEASUREMENT};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::sync::{Mutex, OnceLock};
+
+static AUDIT_LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// Sets the audit log every write to InfluxDB is recorded in; only the first call has an
+/// effect. Until one is set, writes are not recorded
+pub fn set_audit_log(log: AuditLog) {
+    let _ = AUDIT_LOG.set(log);
+}
+
+/// Gets the audit log set with `set_audit_log`, if any
+pub fn audit_log() -> Option<&'static AuditLog> {
+    AUDIT_LOG.get()
+}
+
+/// Makes up an identifier for this run: the time it started, to the second, and the
+/// process ID
+pub fn new_run_id() -> String {
+    format!(
+        "{}-{}",
+        Utc::now().format("%Y%m%dT%H%M%SZ"),
+        std::process::id()
+    )
+}
+
+/// A line of the audit log: the points of one measurement in one write request
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    /// The run that wrote them
+    pub run_id: String,
+    pub written_at: DateTime<Utc>,
+    /// The bucket (the database on InfluxDB 1.x) they were written to
+    pub bucket: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention_policy: Option<String>,
+    pub measurement: String,
+    /// Time of the oldest point
+    pub start: DateTime<Utc>,
+    /// Time of the newest point
+    pub end: DateTime<Utc>,
+    pub points: usize,
+}
+
+impl AuditEntry {
+    /// Checks whether the entry may have written a point of `measurement` at `time`
+    pub fn covers(&self, measurement: &str, time: DateTime<Utc>) -> bool {
+        self.measurement == measurement && self.start <= time && time <= self.end
+    }
+}
+
+/// An append-only file of JSON lines, one [`AuditEntry`] per measurement of every write
+/// request, to tell which run wrote which points
+#[derive(Debug)]
+pub struct AuditLog {
+    path: String,
+    run_id: String,
+    /// Keeps the entries of concurrent writes from interleaving
+    lock: Mutex<()>,
+}
+
+impl AuditLog {
+    /// Records the writes of the run `run_id` in the file at `path`
+    pub fn new(path: &str, run_id: &str) -> Self {
+        AuditLog {
+            path: path.to_string(),
+            run_id: run_id.to_string(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Appends the entries of `body`, line protocol with timestamps in `precision` that was
+    /// just written to `bucket`
+    pub fn record(
+        &self,
+        bucket: &str,
+        retention_policy: Option<&str>,
+        body: &str,
+        precision: Precision,
+    ) -> Result<(), Box<dyn Error>> {
+        let entries = batch_entries(
+            &self.run_id,
+            bucket,
+            retention_policy,
+            body,
+            precision,
+            Utc::now(),
+        );
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut lines = String::new();
+        for entry in &entries {
+            lines.push_str(&serde_json::to_string(entry)?);
+            lines.push('\n');
+        }
+
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(lines.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// Reads the measurement and time of a line of line protocol with timestamps in `precision`
+fn measurement_and_time(line: &str, precision: Precision) -> Option<(String, DateTime<Utc>)> {
+    let mut measurement = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => measurement.push(chars.next()?),
+            ',' | ' ' => break,
+            c => measurement.push(c),
+        }
+    }
+    let timestamp: i64 = line.rsplit(' ').next()?.parse().ok()?;
+    let time = match precision {
+        Precision::Nanoseconds => Some(DateTime::from_timestamp_nanos(timestamp)),
+        Precision::Microseconds => DateTime::from_timestamp_micros(timestamp),
+        Precision::Milliseconds => DateTime::from_timestamp_millis(timestamp),
+        Precision::Seconds => DateTime::from_timestamp(timestamp, 0),
+    }?;
+    Some((measurement, time))
+}
+
+/// Gets the audit entries of a write request: one per measurement of its line protocol
+/// `body`, by name, leaving out the import state stored in InfluxDB
+pub fn batch_entries(
+    run_id: &str,
+    bucket: &str,
+    retention_policy: Option<&str>,
+    body: &str,
+    precision: Precision,
+    written_at: DateTime<Utc>,
+) -> Vec<AuditEntry> {
+    let mut measurements: BTreeMap<String, AuditEntry> = BTreeMap::new();
+    let points = body
+        .lines()
+        .filter_map(|line| measurement_and_time(line, precision))
+        .filter(|(measurement, _)| measurement != STATE_MEASUREMENT);
+    for (measurement, time) in points {
+        let entry = measurements
+            .entry(measurement.clone())
+            .or_insert_with(|| AuditEntry {
+                run_id: run_id.to_string(),
+                written_at,
+                bucket: bucket.to_string(),
+                retention_policy: retention_policy.map(String::from),
+                measurement,
+                start: time,
+                end: time,
+                points: 0,
+            });
+        entry.start = entry.start.min(time);
+        entry.end = entry.end.max(time);
+        entry.points += 1;
+    }
+    measurements.into_values().collect()
+}
+
+/// Reads the entries of an audit log, oldest first; lines that are not entries are skipped
+pub fn read_audit_log(path: &str) -> Result<Vec<AuditEntry>, Box<dyn Error>> {
+    let mut entries = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        if let Ok(entry) = serde_json::from_str(&line?) {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
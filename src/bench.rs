@@ -0,0 +1,99 @@
+use crate::influx_client::DataPoint;
+use crate::pipeline::Pipeline;
+use crate::sink::NullSink;
+use crate::source::Source;
+use crate::state_management::ImportState;
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How fast one stage of an import went
+#[derive(Debug, Clone, PartialEq)]
+pub struct StageResult {
+    /// What was measured
+    pub stage: String,
+    /// Records or points the stage processed
+    pub items: usize,
+    pub elapsed: Duration,
+}
+
+impl StageResult {
+    fn measure<T>(stage: &str, run: impl FnOnce() -> (T, usize)) -> (T, StageResult) {
+        let started = Instant::now();
+        let (output, items) = run();
+        let result = StageResult {
+            stage: stage.to_string(),
+            items,
+            elapsed: started.elapsed(),
+        };
+        (output, result)
+    }
+
+    /// Items processed per second
+    pub fn per_second(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.items as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Measures each stage of importing every record of a source, without InfluxDB:
+/// reading the records, converting them to points, serializing the points as line
+/// protocol, and the whole pipeline into a sink that drops each batch once serialized,
+/// once for every batch size
+pub async fn run<S>(
+    source: Arc<S>,
+    batch_sizes: &[usize],
+) -> Result<Vec<StageResult>, Box<dyn Error>>
+where
+    S: Source + Send + Sync + 'static,
+    S::Record: Clone,
+{
+    let mut results = Vec::new();
+
+    let (records, read) = StageResult::measure("Read records", || {
+        let records: Result<Vec<S::Record>, Box<dyn Error>> = source
+            .records_since(&ImportState::new(""))
+            .map(|stream| stream.collect());
+        let count = records.as_ref().map(Vec::len).unwrap_or_default();
+        (records, count)
+    });
+    let records = records?;
+    results.push(read);
+
+    let (points, convert) = StageResult::measure("Convert to points", || {
+        let points: Vec<DataPoint> = records
+            .iter()
+            .filter_map(|record| source.to_points(record).ok())
+            .flatten()
+            .collect();
+        let count = points.len();
+        (points, count)
+    });
+    results.push(convert);
+
+    let (_, serialize) = StageResult::measure("Serialize line protocol", || {
+        let lines: Vec<String> = points.iter().map(DataPoint::to_line_protocol).collect();
+        std::hint::black_box(&lines);
+        ((), lines.len())
+    });
+    results.push(serialize);
+
+    for &batch_size in batch_sizes {
+        let sink = NullSink::new(batch_size);
+        let started = Instant::now();
+        let summary = Pipeline::new(&sink)
+            .run(Arc::clone(&source), records.clone())
+            .await?;
+        results.push(StageResult {
+            stage: format!("Pipeline, batches of {}", batch_size),
+            items: summary.points,
+            elapsed: started.elapsed(),
+        });
+    }
+
+    Ok(results)
+}
@@ -0,0 +1,1012 @@
+use crate::aggregate::DailyAggregate;
+use crate::anonymize::Anonymize;
+use crate::convert::{fund_tag, ColumnTags, HeaderRole};
+use crate::derived::DerivedMetric;
+use crate::fx::{FxConversion, FxRates, DEFAULT_BASE_CURRENCY};
+use crate::grafana::AnnotatedSession;
+use crate::health_data::{DataTypeSelector, HealthDataType};
+use crate::mqtt::TopicMapping;
+use crate::nav::NavFund;
+use crate::portfolio::Holding;
+use crate::schedule::{parse_interval, CronSchedule};
+use crate::zones::HeartRateZones;
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+
+/// Template written by the `init` command
+pub const CONFIG_TEMPLATE: &str = r#"# Configuration for home-db-importer
+# Use it with: home-db-importer --config influx-import.toml <command>
+# Every setting can be overridden with the matching command line flag or environment
+# variable (e.g., `state_file` with --state-file or HDI_STATE_FILE).
+# Commented out settings use their defaults.
+
+# InfluxDB connection, used by import-funds, import-health-data and resume-spool
+[influxdb]
+url = "http://localhost:8086"
+org = "myorg"
+token = "your_token"
+# InfluxDB 2.x bucket, or 1.x database (`database` takes precedence when both are set)
+bucket = "home"
+# database = "home"
+# retention_policy = "autogen"
+# Seconds to wait for a connection and for each request (0 disables the timeout)
+# connect_timeout = 10
+# request_timeout = 60
+
+# Import state, used by both imports and the state commands
+[state]
+# Where to keep import state: "file", "influxdb", an http(s) URL or an s3://bucket/key URL
+# backend = "file"
+# Number of rotated copies of the state file to keep before updating it
+# backups = 3
+# Where sources given as http(s):// or s3:// URLs are downloaded to
+# source_cache = ".source_cache"
+
+# Funds CSV import (import-funds, validate-csv)
+[funds]
+source = "funds.csv"
+# Measurement the rows are written to
+measurement = "funds"
+time_column = "Date"
+time_format = "%Y-%m-%d"
+header_rows = 2
+# What each header row holds, from the top: "fund", "measurement", "account", "currency",
+# "ignore" or "tag:<name>"; columns no row names a measurement for are written to the
+# measurement above
+# header_roles = ["fund", "measurement"]
+# Key of the tag naming the fund of each point, with the funds and transactions profiles
+# tag_key = "fondo"
+# Layout of the CSV file: "funds", "dsmr" for a smart meter P1 logger export, "weather"
+# for an Ecowitt or Weather Underground station export, "solar" for a SolarEdge, Fronius
+# or Growatt inverter export, "meter" for a log of manual meter readings or "transactions"
+# for fund buys, sells and dividends
+# profile = "funds"
+# With the weather profile: the station tag (the file name by default), and "metric" or
+# "imperial" units
+# station = "garden"
+# units = "metric"
+# Overrides the [influxdb] bucket for this source
+# bucket = "finance"
+state_file = ".import_state.json"
+# spool_file = ".import_spool.lp"
+# Write a JSON summary of every run to this file
+# report_file = "funds_report.json"
+# What to do when the source file was replaced: "ask", "continue" or "restart"
+# on_source_change = "ask"
+# What to do with a watermark newer than the source data: "warn" or "reset"
+# on_invalid_watermark = "warn"
+# Tags for some columns, so an export holding several accounts or currencies writes them
+# apart: by position (from 1) or by a header, in any header row
+# [[funds.columns]]
+# header = "Fund A"
+# account = "broker"
+# currency = "USD"
+#
+# [[funds.columns]]
+# column = 4
+# account = "pension"
+
+# Health Connect import (import-health-data)
+[health]
+source = "health_connect_export.db"
+# Or a directory of dated exports: the last by name is imported, the others merged into it
+# source = "exports/"
+# Older exports (files or directories) whose records are merged into those of the source,
+# skipping the records it already holds
+# merge_sources = ["exports/2024-01.db", "exports/2024-02.db"]
+# Overrides the [influxdb] bucket for this source
+# bucket = "health_data"
+# Only import these data types, or groups of them ("group:body", "group:activity" or
+# "group:sleep"); all types are imported when omitted
+# data_types = ["HeartRate", "Steps", "Sleep", "Weight"]
+# Or import every data type but these
+# exclude_types = ["HeartRate", "SleepState"]
+state_file = ".health_import_state.json"
+# spool_file = ".health_import_spool.lp"
+# report_file = "health_report.json"
+# Save the import state after every N batches written (0 saves only at the end)
+# checkpoint_every = 10
+# Also save it after the records of every period of this length, oldest first
+# chunk_period = "7d"
+# Stream HeartRate and StepsCadence through a cursor, with at most this many of their points
+# in memory, instead of reading them whole first
+# max_in_flight_points = 100000
+# on_source_change = "ask"
+# on_invalid_watermark = "warn"
+# What to do with records InfluxDB already has a point for: "overwrite", "skip" or "error"
+# on_conflict = "overwrite"
+# Also write per-day rollups of the days imported records fall on, to daily_* measurements:
+# "steps", "calories", "sleep" (minutes per night) and "heart-rate" (average and resting)
+# daily_aggregates = ["steps", "calories", "sleep", "heart-rate"]
+# Time zone days start in, for records without a zone offset of their own too (the
+# system's when omitted)
+# timezone = "Europe/Rome"
+# Also write the training load of workouts (TRIMP from the heart rate during them) and its
+# 7-day acute and 28-day chronic averages, for this maximum and resting heart rate
+# max_heart_rate = 190
+# resting_heart_rate = 60
+# Also write each night's sleep against this target, and the sleep debt of the last 7 nights
+# sleep_target = "8h"
+# Also write the min, mean and max of each window of these data types to a second bucket,
+# e.g. one keeping them longer than the raw points
+# downsample_bucket = "health_long_term"
+# downsample_interval = "5m"
+# downsample_types = ["HeartRate"]
+# Apps whose sleep sessions are kept when several apps recorded the same night, preferred
+# first; otherwise the session with the most stages is kept
+# sleep_priority = ["Sleep as Android", "Fitbit"]
+# Also write the sleep stages as a regular series, a point per interval holding the current
+# stage, to SleepHypnogram
+# sleep_hypnogram = "1m"
+# Also write metrics derived from the imported records: "body-composition" (weight, body
+# fat and lean mass recorded together, as one BodyComposition point), "bmi" (at every
+# weight, with the Height records or this height in meters) and "workout-summary" (heart
+# rate, calories and distance of every exercise session, as one WorkoutSummary point)
+# derived_metrics = ["body-composition", "bmi", "workout-summary"]
+# height = 1.75
+# Also "heart-rate-zones": minutes per day and per workout in each zone, starting at these
+# heart rates (50, 60, 70, 80 and 90% of max_heart_rate when omitted)
+# zone_boundaries = [95, 114, 133, 152, 171]
+# Delete the points of the last 30 days whose records were deleted in Health Connect, or
+# edited to another time
+# reconcile_days = 30
+# Hide the apps, devices and exercise titles in tags, for a shared or hosted InfluxDB:
+# "hash" (a stable pseudonym) or "strip"
+# anonymize = "hash"
+
+# Exercise and sleep sessions pushed to Grafana as annotations (import-health-data), so
+# they show as regions over heart rate panels. Needs a service account token that can
+# write annotations; sessions already annotated are not pushed again
+# [grafana]
+# url = "http://localhost:3000"
+# token = "your_grafana_token"
+# Only show them on this dashboard, by UID (every dashboard querying their tags otherwise)
+# dashboard = "health"
+# Sessions to annotate: "exercise" and "sleep"
+# annotate = ["exercise", "sleep"]
+# Tags added to every annotation, besides home-db-importer and the kind of session
+# tags = ["health"]
+
+# MQTT subscription (mqtt): writes sensor readings as they are published
+[mqtt]
+host = "localhost"
+# port = 1883
+# client_id = "home-db-importer"
+# username = "importer"
+# password = "secret"
+# Overrides the [influxdb] bucket for live data
+# bucket = "sensors"
+# spool_file = ".mqtt_spool.lp"
+# Seconds between writes of the points received
+# flush_interval = 10
+
+# Each topic maps messages to points. {name} levels match any value and can be used in
+# the measurement and tags, as can {topic}; {field} is the payload field being written
+# [[mqtt.topics]]
+# topic = "zigbee2mqtt/{device}"
+# measurement = "{field}"
+# fields = ["temperature", "humidity", "battery"]
+# tags = { device = "{device}", source = "zigbee" }
+#
+# A payload that is just a number needs no fields
+# [[mqtt.topics]]
+# topic = "shellies/{device}/relay/0/power"
+# measurement = "power"
+# tags = { device = "{device}" }
+
+# Day-ahead electricity prices (import-prices), fetched from aWATTar for AT or DE-LU
+# unless a CSV export is given
+[prices]
+zone = "DE-LU"
+# source = "nordpool_prices.csv"
+# Days of past prices to fetch on the first run
+# days = 7
+# bucket = "energy"
+state_file = ".prices_state.json"
+# spool_file = ".prices_spool.lp"
+
+# Fund NAVs fetched from a JSON HTTP API (fetch-nav), one request per fund, written with the
+# same fund tags as import-funds
+# [nav]
+# api_url = "https://api.example.com/funds/{isin}/nav"
+# JSONPath of the NAV, and of its date (the day it was fetched when omitted)
+# price_path = "$.data.nav"
+# time_path = "$.data.date"
+# measurement = "price"
+# Key of the tag naming the fund, as under [funds]
+# tag_key = "fondo"
+# spool_file = ".nav_spool.lp"
+#
+# [[nav.funds]]
+# isin = "IE00B4L5Y983"
+# name = "Fund A"
+# currency = "USD"
+
+# Fund values also written in a base currency (import-funds and fetch-nav), to a measurement
+# named after it (e.g., price_eur) and tagged with the currency they were in. Values written
+# as $1,234.5 or €1,234.5 show their currency; the others are in the base currency unless
+# listed under [fx.funds]
+# [fx]
+# base = "EUR"
+# Fetch the ECB's daily reference rates, from Frankfurter unless api_url is set
+# fetch = true
+# api_url = "https://api.frankfurter.app"
+# Fixed rates, in base currency per unit, used when no daily rate is fetched
+# [fx.rates]
+# USD = 0.92
+# [fx.funds]
+# "Fund B" = "USD"
+
+# Funds held, whose value, return since the previous NAV and profit or loss (value minus
+# cost) import-funds writes to portfolio_value, portfolio_return and portfolio_pnl, per
+# fund and for the total
+# [portfolio]
+# Measurement of the NAVs in the funds export (e.g., price_eur to use [fx] values)
+# measurement = "price"
+#
+# [[portfolio.holdings]]
+# fund = "Fund A"
+# quantity = 12.5
+# cost = 1000.0
+# Or follow the buys and sells of a transactions export (see the transactions profile);
+# the profit or loss is then the unrealized gain on the cost basis
+# transactions = "transactions.csv"
+
+# Jobs, run all at once by the run-all command, or by the daemon command on their cron
+# schedules in local time (minute hour day-of-month month day-of-week). A job takes the
+# settings of its command's section above, and can override any of them. Each job keeps
+# its own state in .<name>_state.json and spools to .<name>_spool.lp unless it sets
+# state_file or spool_file
+# [[jobs]]
+# name = "funds"
+# command = "import-funds"
+# schedule = "0 3 * * *"
+#
+# [[jobs]]
+# name = "health"
+# command = "import-health-data"
+# schedule = "0 * * * *"
+# data_types = ["HeartRate", "Steps"]
+"#;
+
+/// Settings loaded from a TOML configuration file
+/// Every value is optional; command line flags take precedence over it
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub influxdb: InfluxDbConfig,
+    #[serde(default)]
+    pub state: StateConfig,
+    #[serde(default)]
+    pub funds: FundsConfig,
+    #[serde(default)]
+    pub health: HealthConfig,
+    #[serde(default)]
+    pub grafana: GrafanaConfig,
+    #[serde(default)]
+    pub mqtt: MqttConfig,
+    #[serde(default)]
+    pub prices: PricesConfig,
+    #[serde(default)]
+    pub nav: NavConfig,
+    #[serde(default)]
+    pub fx: FxConfig,
+    #[serde(default)]
+    pub portfolio: PortfolioConfig,
+    #[serde(default)]
+    pub jobs: Vec<JobConfig>,
+}
+
+/// The `[influxdb]` section: connection settings shared by every command that writes
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct InfluxDbConfig {
+    pub url: Option<String>,
+    pub org: Option<String>,
+    pub token: Option<String>,
+    pub bucket: Option<String>,
+    pub database: Option<String>,
+    pub retention_policy: Option<String>,
+    pub connect_timeout: Option<u64>,
+    pub request_timeout: Option<u64>,
+}
+
+/// The `[state]` section: where import state is kept
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StateConfig {
+    pub backend: Option<String>,
+    pub backups: Option<usize>,
+    pub source_cache: Option<String>,
+}
+
+/// The `[funds]` section: the funds CSV import
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FundsConfig {
+    pub source: Option<String>,
+    pub measurement: Option<String>,
+    pub time_column: Option<String>,
+    pub time_format: Option<String>,
+    pub header_rows: Option<usize>,
+    pub header_roles: Option<Vec<String>>,
+    pub tag_key: Option<String>,
+    pub profile: Option<String>,
+    pub station: Option<String>,
+    pub units: Option<String>,
+    pub bucket: Option<String>,
+    pub database: Option<String>,
+    pub state_file: Option<String>,
+    pub spool_file: Option<String>,
+    pub report_file: Option<String>,
+    pub on_source_change: Option<String>,
+    pub on_invalid_watermark: Option<String>,
+    /// Tags of some columns, e.g. the account or currency they belong to
+    #[serde(default)]
+    pub columns: Vec<ColumnTags>,
+}
+
+/// The `[health]` section: the Health Connect import
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HealthConfig {
+    pub source: Option<String>,
+    pub merge_sources: Option<Vec<String>>,
+    pub data_types: Option<Vec<String>>,
+    pub exclude_types: Option<Vec<String>>,
+    pub bucket: Option<String>,
+    pub database: Option<String>,
+    pub state_file: Option<String>,
+    pub spool_file: Option<String>,
+    pub report_file: Option<String>,
+    pub checkpoint_every: Option<usize>,
+    pub chunk_period: Option<String>,
+    pub max_in_flight_points: Option<u64>,
+    pub on_source_change: Option<String>,
+    pub on_invalid_watermark: Option<String>,
+    pub on_conflict: Option<String>,
+    pub daily_aggregates: Option<Vec<String>>,
+    pub timezone: Option<String>,
+    pub max_heart_rate: Option<u32>,
+    pub resting_heart_rate: Option<u32>,
+    pub sleep_target: Option<String>,
+    pub downsample_bucket: Option<String>,
+    pub downsample_interval: Option<String>,
+    pub downsample_types: Option<Vec<String>>,
+    pub sleep_priority: Option<Vec<String>>,
+    pub sleep_hypnogram: Option<String>,
+    pub derived_metrics: Option<Vec<String>>,
+    pub zone_boundaries: Option<Vec<u32>>,
+    pub height: Option<f64>,
+    pub reconcile_days: Option<u32>,
+    pub anonymize: Option<String>,
+}
+
+/// The `[grafana]` section: health sessions pushed to Grafana as annotations
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GrafanaConfig {
+    pub url: Option<String>,
+    pub token: Option<String>,
+    pub dashboard: Option<String>,
+    pub annotate: Option<Vec<String>>,
+    pub tags: Option<Vec<String>>,
+}
+
+/// The `[mqtt]` section: the MQTT subscription, with the topics to write
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub bucket: Option<String>,
+    pub database: Option<String>,
+    pub spool_file: Option<String>,
+    pub flush_interval: Option<u64>,
+    #[serde(default)]
+    pub topics: Vec<TopicMapping>,
+}
+
+/// The `[prices]` section: the day-ahead electricity price import
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PricesConfig {
+    pub source: Option<String>,
+    pub zone: Option<String>,
+    pub days: Option<u32>,
+    pub api_url: Option<String>,
+    pub time_format: Option<String>,
+    pub bucket: Option<String>,
+    pub database: Option<String>,
+    pub state_file: Option<String>,
+    pub spool_file: Option<String>,
+    pub report_file: Option<String>,
+}
+
+/// The `[nav]` section: the fund NAV API, with the funds to fetch
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NavConfig {
+    pub api_url: Option<String>,
+    pub price_path: Option<String>,
+    pub time_path: Option<String>,
+    pub measurement: Option<String>,
+    pub tag_key: Option<String>,
+    pub bucket: Option<String>,
+    pub database: Option<String>,
+    pub spool_file: Option<String>,
+    #[serde(default)]
+    pub funds: Vec<NavFund>,
+}
+
+/// The `[fx]` section: fund values also written in a base currency
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FxConfig {
+    pub base: Option<String>,
+    /// Whether to fetch daily reference rates
+    pub fetch: Option<bool>,
+    pub api_url: Option<String>,
+    /// Fixed rates, in units of the base currency per unit of each currency
+    #[serde(default)]
+    pub rates: BTreeMap<String, f64>,
+    /// Currencies of funds whose values show no currency symbol, by fund name
+    #[serde(default)]
+    pub funds: BTreeMap<String, String>,
+}
+
+impl FxConfig {
+    /// Builds the conversion the section sets up, without the daily rates it may fetch;
+    /// None when it sets neither fixed rates nor fetching
+    pub fn conversion(&self) -> Option<FxConversion> {
+        if !self.fetch.unwrap_or(false) && self.rates.is_empty() {
+            return None;
+        }
+        let mut rates = FxRates::new(self.base.as_deref().unwrap_or(DEFAULT_BASE_CURRENCY));
+        for (currency, rate) in &self.rates {
+            rates = rates.with_rate(currency, *rate);
+        }
+        let mut conversion = FxConversion::new(rates);
+        for (fund, currency) in &self.funds {
+            conversion = conversion.with_fund_currency(&fund_tag(fund), currency);
+        }
+        Some(conversion)
+    }
+}
+
+/// The `[portfolio]` section: the funds held, whose performance import-funds derives
+#[derive(Deserialize, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PortfolioConfig {
+    /// Measurement of the NAVs, as in the second header row of the funds export
+    pub measurement: Option<String>,
+    #[serde(default)]
+    pub holdings: Vec<Holding>,
+    /// Transactions export the holdings follow instead
+    pub transactions: Option<String>,
+}
+
+/// A `[[jobs]]` entry: an import run on a schedule by the daemon command
+#[derive(Deserialize, Debug, PartialEq)]
+pub struct JobConfig {
+    pub name: String,
+    /// The import command to run (import-funds, import-health-data, import-prices or fetch-nav)
+    pub command: String,
+    /// Cron expression for when to run the job
+    pub schedule: Option<String>,
+    /// Settings that override the command's section, by the same names
+    #[serde(flatten)]
+    pub settings: BTreeMap<String, toml::Value>,
+}
+
+/// Commands that can be run as jobs
+const JOB_COMMANDS: [&str; 4] = [
+    "import-funds",
+    "import-health-data",
+    "import-prices",
+    "fetch-nav",
+];
+
+impl JobConfig {
+    /// Returns the job's settings as (argument name, value) pairs, including its own state
+    /// and spool files. Lists are joined with commas and false flags are left out
+    pub fn settings(&self) -> Vec<(String, String)> {
+        let mut settings: Vec<(String, String)> = self
+            .settings
+            .iter()
+            .filter(|(_, value)| value.as_bool() != Some(false))
+            .map(|(name, value)| (name.clone(), setting_value(value)))
+            .collect();
+
+        if !self.settings.contains_key("state_file") {
+            settings.push((
+                "state_file".to_string(),
+                format!(".{}_state.json", self.name),
+            ));
+        }
+        if !self.settings.contains_key("spool_file") {
+            settings.push(("spool_file".to_string(), format!(".{}_spool.lp", self.name)));
+        }
+        settings
+    }
+}
+
+/// Formats a setting value the way it is passed on the command line
+fn setting_value(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(value) => value.clone(),
+        toml::Value::Array(values) => values
+            .iter()
+            .map(setting_value)
+            .collect::<Vec<_>>()
+            .join(","),
+        other => other.to_string(),
+    }
+}
+
+/// Fills in a section of a config template: each of `values` (key, TOML value) replaces the
+/// first line of the section setting that key, commented out or not, and `comments` are
+/// added below the section header
+pub fn fill_template(
+    template: &str,
+    section: &str,
+    comments: &[String],
+    values: &[(&str, String)],
+) -> String {
+    let header = format!("[{}]", section);
+    let mut in_section = false;
+    let mut filled: Vec<&str> = Vec::new();
+    let mut output = String::new();
+    for line in template.lines() {
+        let uncommented = line.trim_start_matches("# ");
+        if uncommented.starts_with('[') {
+            in_section = line == header;
+        }
+        let setting = values.iter().find(|(key, _)| {
+            in_section
+                && !filled.contains(key)
+                && uncommented.split(" = ").next() == Some(*key)
+                && uncommented.contains(" = ")
+        });
+        match setting {
+            Some((key, value)) => {
+                output.push_str(&format!("{} = {}\n", key, value));
+                filled.push(key);
+            }
+            None => {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+        if line == header {
+            for comment in comments {
+                output.push_str(&format!("# {}\n", comment));
+            }
+        }
+    }
+    output
+}
+
+/// Adds a setting if it is present in the configuration
+fn push<T: ToString>(
+    settings: &mut Vec<(&'static str, String)>,
+    name: &'static str,
+    value: &Option<T>,
+) {
+    if let Some(value) = value {
+        settings.push((name, value.to_string()));
+    }
+}
+
+impl InfluxDbConfig {
+    fn settings(&self, settings: &mut Vec<(&'static str, String)>) {
+        push(settings, "url", &self.url);
+        push(settings, "org", &self.org);
+        push(settings, "token", &self.token);
+        push(settings, "bucket", &self.bucket);
+        push(settings, "database", &self.database);
+        push(settings, "retention_policy", &self.retention_policy);
+        push(settings, "connect_timeout", &self.connect_timeout);
+        push(settings, "request_timeout", &self.request_timeout);
+    }
+}
+
+impl StateConfig {
+    fn settings(&self, settings: &mut Vec<(&'static str, String)>) {
+        push(settings, "state_backend", &self.backend);
+        push(settings, "state_backups", &self.backups);
+        push(settings, "source_cache", &self.source_cache);
+    }
+}
+
+impl FundsConfig {
+    fn settings(&self, settings: &mut Vec<(&'static str, String)>) {
+        push(settings, "source", &self.source);
+        push(settings, "measurement", &self.measurement);
+        push(settings, "time_column", &self.time_column);
+        push(settings, "time_format", &self.time_format);
+        push(settings, "header_rows", &self.header_rows);
+        push(
+            settings,
+            "header_roles",
+            &self.header_roles.as_ref().map(|roles| roles.join(",")),
+        );
+        push(settings, "tag_key", &self.tag_key);
+        push(settings, "profile", &self.profile);
+        push(settings, "station", &self.station);
+        push(settings, "units", &self.units);
+        push(settings, "bucket", &self.bucket);
+        push(settings, "database", &self.database);
+        push(settings, "state_file", &self.state_file);
+        push(settings, "spool_file", &self.spool_file);
+        push(settings, "report_file", &self.report_file);
+        push(settings, "on_source_change", &self.on_source_change);
+        push(settings, "on_invalid_watermark", &self.on_invalid_watermark);
+    }
+}
+
+impl HealthConfig {
+    fn settings(&self, settings: &mut Vec<(&'static str, String)>) {
+        push(settings, "source", &self.source);
+        push(
+            settings,
+            "merge_sources",
+            &self.merge_sources.as_ref().map(|s| s.join(",")),
+        );
+        push(
+            settings,
+            "data_types",
+            &self.data_types.as_ref().map(|t| t.join(",")),
+        );
+        push(
+            settings,
+            "exclude_types",
+            &self.exclude_types.as_ref().map(|t| t.join(",")),
+        );
+        push(settings, "bucket", &self.bucket);
+        push(settings, "database", &self.database);
+        push(settings, "state_file", &self.state_file);
+        push(settings, "spool_file", &self.spool_file);
+        push(settings, "report_file", &self.report_file);
+        push(settings, "checkpoint_every", &self.checkpoint_every);
+        push(settings, "chunk_period", &self.chunk_period);
+        push(settings, "max_in_flight_points", &self.max_in_flight_points);
+        push(settings, "on_source_change", &self.on_source_change);
+        push(settings, "on_invalid_watermark", &self.on_invalid_watermark);
+        push(settings, "on_conflict", &self.on_conflict);
+        push(
+            settings,
+            "daily_aggregates",
+            &self.daily_aggregates.as_ref().map(|a| a.join(",")),
+        );
+        push(settings, "timezone", &self.timezone);
+        push(settings, "max_heart_rate", &self.max_heart_rate);
+        push(settings, "resting_heart_rate", &self.resting_heart_rate);
+        push(settings, "sleep_target", &self.sleep_target);
+        push(settings, "downsample_bucket", &self.downsample_bucket);
+        push(settings, "downsample_interval", &self.downsample_interval);
+        push(
+            settings,
+            "downsample_types",
+            &self.downsample_types.as_ref().map(|t| t.join(",")),
+        );
+        push(
+            settings,
+            "sleep_priority",
+            &self.sleep_priority.as_ref().map(|a| a.join(",")),
+        );
+        push(settings, "sleep_hypnogram", &self.sleep_hypnogram);
+        push(
+            settings,
+            "derived_metrics",
+            &self.derived_metrics.as_ref().map(|m| m.join(",")),
+        );
+        push(
+            settings,
+            "zone_boundaries",
+            &self.zone_boundaries.as_ref().map(|bounds| {
+                bounds
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            }),
+        );
+        push(settings, "height", &self.height);
+        push(settings, "reconcile_days", &self.reconcile_days);
+        push(settings, "anonymize", &self.anonymize);
+    }
+}
+
+impl GrafanaConfig {
+    fn settings(&self, settings: &mut Vec<(&'static str, String)>) {
+        push(settings, "grafana_url", &self.url);
+        push(settings, "grafana_token", &self.token);
+        push(settings, "grafana_dashboard", &self.dashboard);
+        push(
+            settings,
+            "annotate",
+            &self.annotate.as_ref().map(|s| s.join(",")),
+        );
+        push(
+            settings,
+            "annotation_tags",
+            &self.tags.as_ref().map(|tags| tags.join(",")),
+        );
+    }
+}
+
+impl MqttConfig {
+    fn settings(&self, settings: &mut Vec<(&'static str, String)>) {
+        push(settings, "host", &self.host);
+        push(settings, "port", &self.port);
+        push(settings, "client_id", &self.client_id);
+        push(settings, "username", &self.username);
+        push(settings, "password", &self.password);
+        push(settings, "bucket", &self.bucket);
+        push(settings, "database", &self.database);
+        push(settings, "spool_file", &self.spool_file);
+        push(settings, "flush_interval", &self.flush_interval);
+    }
+}
+
+impl PricesConfig {
+    fn settings(&self, settings: &mut Vec<(&'static str, String)>) {
+        push(settings, "source", &self.source);
+        push(settings, "zone", &self.zone);
+        push(settings, "days", &self.days);
+        push(settings, "api_url", &self.api_url);
+        push(settings, "time_format", &self.time_format);
+        push(settings, "bucket", &self.bucket);
+        push(settings, "database", &self.database);
+        push(settings, "state_file", &self.state_file);
+        push(settings, "spool_file", &self.spool_file);
+        push(settings, "report_file", &self.report_file);
+    }
+}
+
+impl NavConfig {
+    fn settings(&self, settings: &mut Vec<(&'static str, String)>) {
+        push(settings, "api_url", &self.api_url);
+        push(settings, "price_path", &self.price_path);
+        push(settings, "time_path", &self.time_path);
+        push(settings, "measurement", &self.measurement);
+        push(settings, "tag_key", &self.tag_key);
+        push(settings, "bucket", &self.bucket);
+        push(settings, "database", &self.database);
+        push(settings, "spool_file", &self.spool_file);
+    }
+}
+
+impl Config {
+    /// Parses a configuration file's contents, rejecting unknown sections and settings
+    pub fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(contents)
+    }
+
+    /// Loads and validates a configuration file
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+        let config =
+            Self::parse(&contents).map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+        config
+            .validate()
+            .map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+        Ok(config)
+    }
+
+    /// Checks the jobs for duplicate names, unknown commands and invalid schedules, the
+    /// MQTT topics for invalid patterns and templates, the funds column tags, the exchange
+    /// rates and the holdings
+    pub fn validate(&self) -> Result<(), String> {
+        for (currency, rate) in &self.fx.rates {
+            if !rate.is_finite() || *rate <= 0.0 {
+                return Err(format!("[fx] rate of {} must be positive", currency));
+            }
+        }
+        for aggregate in self.health.daily_aggregates.iter().flatten() {
+            DailyAggregate::from_str(aggregate, true).map_err(|_| {
+                format!(
+                    "[health] daily_aggregates: unknown aggregate '{}'",
+                    aggregate
+                )
+            })?;
+        }
+        if let (Some(max), Some(resting)) =
+            (self.health.max_heart_rate, self.health.resting_heart_rate)
+        {
+            if resting >= max {
+                return Err(format!(
+                    "[health] resting_heart_rate {} must be below max_heart_rate {}",
+                    resting, max
+                ));
+            }
+        }
+        for metric in self.health.derived_metrics.iter().flatten() {
+            DerivedMetric::from_str(metric, true)
+                .map_err(|_| format!("[health] derived_metrics: unknown metric '{}'", metric))?;
+        }
+        if let Some(height) = self.health.height {
+            if !(height > 0.0 && height < 3.0) {
+                return Err(format!("[health] height {} must be in meters", height));
+            }
+        }
+        if let Some(bounds) = &self.health.zone_boundaries {
+            HeartRateZones::new(bounds).map_err(|e| format!("[health] zone_boundaries: {}", e))?;
+        }
+        if let Some(anonymize) = &self.health.anonymize {
+            Anonymize::from_str(anonymize, true)
+                .map_err(|_| format!("[health] anonymize: unknown mode '{}'", anonymize))?;
+        }
+        if let Some(timezone) = &self.health.timezone {
+            timezone
+                .parse::<Tz>()
+                .map_err(|_| format!("[health] timezone: unknown time zone '{}'", timezone))?;
+        }
+        if let Some(period) = &self.health.chunk_period {
+            parse_interval(period).map_err(|e| format!("[health] chunk_period: {}", e))?;
+        }
+        if let Some(target) = &self.health.sleep_target {
+            parse_interval(target).map_err(|e| format!("[health] sleep_target: {}", e))?;
+        }
+        if let Some(interval) = &self.health.sleep_hypnogram {
+            parse_interval(interval).map_err(|e| format!("[health] sleep_hypnogram: {}", e))?;
+        }
+        if let Some(interval) = &self.health.downsample_interval {
+            parse_interval(interval).map_err(|e| format!("[health] downsample_interval: {}", e))?;
+        }
+        for data_type in self.health.exclude_types.iter().flatten() {
+            data_type
+                .parse::<DataTypeSelector>()
+                .map_err(|e| format!("[health] exclude_types: {}", e))?;
+        }
+        for data_type in self.health.downsample_types.iter().flatten() {
+            data_type
+                .parse::<HealthDataType>()
+                .map_err(|e| format!("[health] downsample_types: {}", e))?;
+        }
+        for session in self.grafana.annotate.iter().flatten() {
+            AnnotatedSession::from_str(session, true)
+                .map_err(|_| format!("[grafana] annotate: unknown session '{}'", session))?;
+        }
+        for role in self.funds.header_roles.iter().flatten() {
+            role.parse::<HeaderRole>()
+                .map_err(|e| format!("[funds] header_roles: {}", e))?;
+        }
+        for entry in &self.funds.columns {
+            if entry.column.is_none() && entry.header.is_none() {
+                return Err("a [[funds.columns]] entry needs a column or a header".to_string());
+            }
+            if entry.column == Some(0) {
+                return Err("[[funds.columns]] count columns from 1".to_string());
+            }
+            if entry.account.is_none() && entry.currency.is_none() {
+                return Err("a [[funds.columns]] entry needs an account or a currency".to_string());
+            }
+        }
+        let mut funds = Vec::new();
+        for holding in &self.portfolio.holdings {
+            if !holding.quantity.is_finite() || holding.cost.is_some_and(|cost| !cost.is_finite()) {
+                return Err(format!("holding of '{}' is not a number", holding.fund));
+            }
+            if funds.contains(&&holding.fund) {
+                return Err(format!("more than one holding of '{}'", holding.fund));
+            }
+            funds.push(&holding.fund);
+        }
+
+        for mapping in &self.mqtt.topics {
+            mapping
+                .validate()
+                .map_err(|e| format!("MQTT topic '{}': {}", mapping.topic, e))?;
+        }
+
+        let mut names = Vec::new();
+        for job in &self.jobs {
+            if job.name.is_empty() {
+                return Err("a job has an empty name".to_string());
+            }
+            if names.contains(&&job.name) {
+                return Err(format!("more than one job is named '{}'", job.name));
+            }
+            names.push(&job.name);
+
+            if !JOB_COMMANDS.contains(&job.command.as_str()) {
+                return Err(format!(
+                    "job '{}' has unknown command '{}': expected one of {}",
+                    job.name,
+                    job.command,
+                    JOB_COMMANDS.join(", ")
+                ));
+            }
+            if let Some(schedule) = &job.schedule {
+                CronSchedule::parse(schedule).map_err(|e| format!("job '{}': {}", job.name, e))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Finds a job by name
+    pub fn job(&self, name: &str) -> Option<&JobConfig> {
+        self.jobs.iter().find(|job| job.name == name)
+    }
+
+    /// Gets a setting of a job: its own, or else the one its command's section gives it
+    pub fn job_setting(&self, job: &JobConfig, name: &str) -> Option<String> {
+        job.settings()
+            .into_iter()
+            .find(|(setting, _)| setting == name)
+            .map(|(_, value)| value)
+            .or_else(|| {
+                self.settings_for(&job.command)
+                    .into_iter()
+                    .find(|(setting, _)| *setting == name)
+                    .map(|(_, value)| value)
+            })
+    }
+
+    /// Returns the settings for a command (e.g., "import-funds" or "state reset") as
+    /// (argument name, value) pairs. Source sections come before [influxdb], so when
+    /// both set a value (e.g., the bucket), the first one for a name wins
+    pub fn settings_for(&self, command: &str) -> Vec<(&'static str, String)> {
+        let mut settings = Vec::new();
+        match command {
+            "import-funds" => {
+                self.funds.settings(&mut settings);
+                self.state.settings(&mut settings);
+                self.influxdb.settings(&mut settings);
+            }
+            "import-health-data" => {
+                self.health.settings(&mut settings);
+                self.grafana.settings(&mut settings);
+                self.state.settings(&mut settings);
+                self.influxdb.settings(&mut settings);
+            }
+            "import-prices" => {
+                self.prices.settings(&mut settings);
+                self.state.settings(&mut settings);
+                self.influxdb.settings(&mut settings);
+            }
+            "fetch-nav" => {
+                self.nav.settings(&mut settings);
+                self.influxdb.settings(&mut settings);
+            }
+            "resume-spool" | "prune" | "export" => self.influxdb.settings(&mut settings),
+            "mqtt" => {
+                self.mqtt.settings(&mut settings);
+                self.influxdb.settings(&mut settings);
+            }
+            "doctor" => {
+                self.state.settings(&mut settings);
+                self.influxdb.settings(&mut settings);
+            }
+            "health-stats" | "extract" => push(&mut settings, "source", &self.health.source),
+            "compare" | "gap-report" | "backfill" => {
+                push(&mut settings, "source", &self.health.source);
+                push(&mut settings, "bucket", &self.health.bucket);
+                push(&mut settings, "database", &self.health.database);
+                self.influxdb.settings(&mut settings);
+            }
+            "template-from-measurement" => {
+                self.funds.settings(&mut settings);
+                self.influxdb.settings(&mut settings);
+            }
+            "validate-csv" => {
+                push(&mut settings, "source", &self.funds.source);
+                push(&mut settings, "header_rows", &self.funds.header_rows);
+            }
+            "state reset" | "state set" => self.state.settings(&mut settings),
+            _ => {}
+        }
+        settings
+    }
+}
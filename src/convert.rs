@@ -0,0 +1,372 @@
+use crate::csv_parser::CsvRecord;
+use crate::health_data::HealthRecord;
+use crate::influx_client::DataPoint;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+/// Key of the tag naming the fund of a point unless configured otherwise
+pub const DEFAULT_FUND_TAG_KEY: &str = "fondo";
+
+/// Turns a fund name into its `fondo` tag value, as funds imports write it: line breaks and
+/// spaces become underscores
+pub fn fund_tag(name: &str) -> String {
+    name.replace(['\n', '\r'], " ")
+        .replace(' ', "_")
+        .replace("__", "_")
+}
+
+/// A `[[funds.columns]]` entry: tags added to the points of some columns of a funds
+/// export, so one export can hold several accounts or currencies
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ColumnTags {
+    /// Position of the column, counting from 1 as spreadsheets do
+    pub column: Option<usize>,
+    /// Header of the columns, in any header row (e.g., the fund name)
+    pub header: Option<String>,
+    /// The `account` tag
+    pub account: Option<String>,
+    /// The `currency` tag, which the `[fx]` conversion also goes by
+    pub currency: Option<String>,
+}
+
+impl ColumnTags {
+    /// Checks whether the entry applies to the column at `index` (from 0) with `headers`
+    pub fn matches(&self, index: usize, headers: &[&str]) -> bool {
+        self.column.is_none_or(|column| column == index + 1)
+            && self
+                .header
+                .as_deref()
+                .is_none_or(|header| headers.iter().any(|value| value.trim() == header.trim()))
+    }
+
+    /// Adds the tags of the entries matching a column to `tags`; later entries win
+    fn apply(
+        entries: &[ColumnTags],
+        index: usize,
+        headers: &[&str],
+        tags: &mut HashMap<String, String>,
+    ) {
+        for entry in entries.iter().filter(|entry| entry.matches(index, headers)) {
+            if let Some(account) = &entry.account {
+                tags.insert("account".to_string(), account.clone());
+            }
+            if let Some(currency) = &entry.currency {
+                tags.insert("currency".to_string(), currency.to_uppercase());
+            }
+        }
+    }
+}
+
+/// What a header row of a funds export holds for each column
+#[derive(Debug, Clone, PartialEq)]
+pub enum HeaderRole {
+    /// The fund, written to the fund tag (`fondo` by default)
+    Fund,
+    /// The measurement the column is written to
+    Measurement,
+    /// The `account` tag
+    Account,
+    /// The `currency` tag
+    Currency,
+    /// A tag of another name
+    Tag(String),
+    /// Nothing to write, e.g. an ISIN or a description
+    Ignore,
+}
+
+impl HeaderRole {
+    /// Gets the roles of `rows` header rows when none are configured: the fund, then the
+    /// measurement, and nothing in the rows after
+    pub fn defaults(rows: usize) -> Vec<HeaderRole> {
+        [HeaderRole::Fund, HeaderRole::Measurement]
+            .into_iter()
+            .chain(std::iter::repeat(HeaderRole::Ignore))
+            .take(rows)
+            .collect()
+    }
+}
+
+impl FromStr for HeaderRole {
+    type Err = String;
+
+    /// Parses a role name, ignoring case: fund, measurement, account, currency, ignore or
+    /// `tag:<name>`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if let Some(name) = value.strip_prefix("tag:") {
+            let name = name.trim();
+            if name.is_empty() {
+                return Err("tag: needs the name of the tag".to_string());
+            }
+            return Ok(HeaderRole::Tag(name.to_string()));
+        }
+        match value.to_lowercase().as_str() {
+            "fund" | "fondo" => Ok(HeaderRole::Fund),
+            "measurement" => Ok(HeaderRole::Measurement),
+            "account" => Ok(HeaderRole::Account),
+            "currency" => Ok(HeaderRole::Currency),
+            "ignore" | "skip" => Ok(HeaderRole::Ignore),
+            other => Err(format!(
+                "unknown header role '{}': expected fund, measurement, account, currency, \
+                 ignore or tag:<name>",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HeaderRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderRole::Fund => write!(f, "fund"),
+            HeaderRole::Measurement => write!(f, "measurement"),
+            HeaderRole::Account => write!(f, "account"),
+            HeaderRole::Currency => write!(f, "currency"),
+            HeaderRole::Tag(name) => write!(f, "tag:{}", name),
+            HeaderRole::Ignore => write!(f, "ignore"),
+        }
+    }
+}
+
+/// How the columns of a funds export are turned into points
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FundsLayout {
+    /// Role of each header row, from the top; [`HeaderRole::defaults`] when empty
+    pub header_roles: Vec<HeaderRole>,
+    /// Measurement of the columns no header row names one for, e.g. with a single header row
+    pub measurement: Option<String>,
+    /// Tags added to the points of some columns
+    pub column_tags: Vec<ColumnTags>,
+    /// Key of the fund tag; [`DEFAULT_FUND_TAG_KEY`] when unset
+    pub tag_key: Option<String>,
+    /// Whether values that are not numbers (e.g. a notes or status column) are kept as string
+    /// fields of the points of their row, named after their column, instead of being dropped
+    pub text_fields: bool,
+}
+
+impl FundsLayout {
+    /// Gets the key of the fund tag
+    pub fn tag_key(&self) -> &str {
+        self.tag_key.as_deref().unwrap_or(DEFAULT_FUND_TAG_KEY)
+    }
+
+    /// Gets the role of each of `rows` header rows; rows without one are ignored
+    pub fn roles(&self, rows: usize) -> Result<Vec<HeaderRole>, String> {
+        if self.header_roles.is_empty() {
+            return Ok(HeaderRole::defaults(rows));
+        }
+        if self.header_roles.len() > rows {
+            return Err(format!(
+                "{} header roles given for {} header rows",
+                self.header_roles.len(),
+                rows
+            ));
+        }
+        let mut roles = self.header_roles.clone();
+        roles.resize(rows, HeaderRole::Ignore);
+        Ok(roles)
+    }
+}
+
+/// Currency symbols recognized in fund values, with the currency they stand for
+pub const CURRENCY_SYMBOLS: [(char, &str); 2] = [('$', "USD"), ('€', "EUR")];
+
+/// Gets the currency a fund value was written in, if it shows a symbol
+pub fn fund_currency(value: &str) -> Option<&'static str> {
+    CURRENCY_SYMBOLS
+        .iter()
+        .find(|(symbol, _)| value.contains(*symbol))
+        .map(|(_, currency)| *currency)
+}
+
+/// Parses a fund value as funds exports write it: currency amounts (`€1,234.5`) lose their
+/// symbol and thousands separators, and percentages their sign
+pub fn parse_fund_value(value: &str) -> Option<f64> {
+    let mut value = value.to_string();
+
+    // first let's check if the value is a currency
+    if value.contains('$') || value.contains('€') {
+        // Remove the currency symbol and any commas
+        value = value.replace(['$', '€', ','], "").trim().to_string();
+    }
+
+    // then let's check if the value is a percentage
+    if value.ends_with('%') {
+        // Remove the percentage symbol
+        value = value.trim_end_matches('%').to_string();
+    }
+
+    value.parse::<f64>().ok()
+}
+
+/// Converts a CSV record to multiple InfluxDB data points
+/// Each column (except the timestamp column) becomes a separate measurement
+/// To be used for funds records
+pub fn funds_record_to_points(
+    record: &CsvRecord,
+    time_column: &str,
+    time_format: &str,
+) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+    let points = funds_record_to_points_with_currency(
+        record,
+        time_column,
+        time_format,
+        &FundsLayout::default(),
+    )?;
+    Ok(points.into_iter().map(|(point, _)| point).collect())
+}
+
+/// A funds point, with the currency its value was written in when it showed one
+pub type FundPoint = (DataPoint, Option<&'static str>);
+
+/// Converts a funds CSV record like [`funds_record_to_points`], along with the currency
+/// each value was written in, reading the header rows and tagging the columns as `layout`
+/// says
+pub fn funds_record_to_points_with_currency(
+    record: &CsvRecord,
+    time_column: &str,
+    time_format: &str,
+    layout: &FundsLayout,
+) -> Result<Vec<FundPoint>, Box<dyn Error>> {
+    let roles = layout.roles(record.header_values.len())?;
+
+    let mut data_points = Vec::new();
+
+    // Get the timestamp value from the specified column
+    let time_column_index = match record.column_indexes.get(time_column) {
+        Some(idx) => *idx,
+        None => return Err(format!("Time column '{}' not found", time_column).into()),
+    };
+
+    // Ensure the time column index is valid
+    if time_column_index >= record.values.len() {
+        return Err(format!("Time column index {} out of bounds", time_column_index).into());
+    }
+
+    // Parse the timestamp value
+    let time_value = &record.values[time_column_index];
+    let naive_dt = match NaiveDateTime::parse_from_str(time_value, time_format) {
+        Ok(dt) => dt,
+        Err(e) => return Err(format!("Failed to parse timestamp '{}': {}", time_value, e).into()),
+    };
+    let timestamp = DateTime::from_naive_utc_and_offset(naive_dt, Utc);
+
+    // Values that are not numbers become string fields of the row's points if asked to; as
+    // fields they are not indexed, so free text such as notes adds no series
+    let text_fields: BTreeMap<String, String> = if layout.text_fields {
+        record
+            .column_indexes
+            .iter()
+            .filter(|(col_name, _)| *col_name != time_column)
+            .filter_map(|(col_name, col_idx)| {
+                let value = record.values.get(*col_idx)?.trim();
+                let name = col_name.split('.').next_back().unwrap_or(col_name);
+                // The number of the point is its value field
+                if value.is_empty() || name == "value" || parse_fund_value(value).is_some() {
+                    return None;
+                }
+                Some((name.to_string(), value.to_string()))
+            })
+            .collect()
+    } else {
+        BTreeMap::new()
+    };
+
+    // Process each column (except timestamp) as a separate measurement
+    for (col_name, col_idx) in &record.column_indexes {
+        // Skip the timestamp column
+        if col_name == time_column {
+            continue;
+        }
+
+        // Skip columns with invalid indices
+        if *col_idx >= record.values.len() {
+            continue;
+        }
+
+        match parse_fund_value(&record.values[*col_idx]) {
+            Some(float_value) => {
+                // This column contains a numeric value - create a data point
+                let mut tags = HashMap::new();
+
+                let mut measurement = None;
+
+                // Extract tags and the measurement from the header rows for this column
+                for (row, role) in record.header_values.iter().zip(&roles) {
+                    let Some(value) = row.get(*col_idx).filter(|value| !value.trim().is_empty())
+                    else {
+                        continue;
+                    };
+                    let (tag, value) = match role {
+                        HeaderRole::Fund => (layout.tag_key(), fund_tag(value)),
+                        HeaderRole::Account => ("account", value.trim().to_string()),
+                        HeaderRole::Currency => ("currency", value.trim().to_uppercase()),
+                        HeaderRole::Tag(name) => (name.as_str(), value.trim().to_string()),
+                        HeaderRole::Measurement => {
+                            measurement = Some(value.as_str());
+                            continue;
+                        }
+                        HeaderRole::Ignore => continue,
+                    };
+                    tags.insert(tag.to_string(), value);
+                }
+                if !layout.column_tags.is_empty() {
+                    let headers: Vec<&str> = record
+                        .header_values
+                        .iter()
+                        .filter_map(|row| row.get(*col_idx).map(String::as_str))
+                        .collect();
+                    ColumnTags::apply(&layout.column_tags, *col_idx, &headers, &mut tags);
+                }
+                // Use the configured measurement, or else the column name, as a fallback if
+                // no header row names one
+                let measurement = measurement
+                    .or(layout.measurement.as_deref())
+                    .unwrap_or_else(|| col_name.split('.').next_back().unwrap_or(col_name));
+
+                // Create the data point
+                data_points.push((
+                    DataPoint {
+                        measurement: measurement.to_string(),
+                        time: timestamp,
+                        tags,
+                        field_value: float_value,
+                        text_fields: text_fields.clone(),
+                    },
+                    fund_currency(&record.values[*col_idx]),
+                ));
+            }
+            None => {
+                // Non-numeric values are not points of their own; with `text_fields` they
+                // are added to the row's points
+                continue;
+            }
+        }
+    }
+
+    if data_points.is_empty() {
+        return Err("No valid measurements found in record".into());
+    }
+
+    Ok(data_points)
+}
+
+/// Converts a health record to an InfluxDB data point
+/// The metadata becomes tags, along with the record type for easier querying
+pub fn health_record_to_point(record: &HealthRecord) -> DataPoint {
+    let mut tags = record.metadata.clone();
+    tags.insert("record_type".to_string(), record.record_type.to_string());
+
+    DataPoint {
+        measurement: record.record_type.to_string(),
+        time: record.timestamp,
+        tags,
+        field_value: record.value,
+        text_fields: BTreeMap::new(),
+    }
+}
@@ -1,15 +1,29 @@
+use crate::convert::{funds_record_to_points_with_currency, ColumnTags, FundsLayout, HeaderRole};
+use crate::fx::FxConversion;
+use crate::influx_client::DataPoint;
+use crate::source::{RecordStream, Source};
+use crate::state_management::ImportState;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use csv::{ReaderBuilder, StringRecord};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
 use std::path::Path;
+use std::sync::OnceLock;
 
 /// Represents a parser for CSV files
 pub struct CsvParser {
     file_path: String,
     header_rows: usize,
     time_column_index: Option<usize>, // Typically the first column (0)
+    time_column: String,
+    time_format: String,
+    fx: Option<FxConversion>,
+    layout: FundsLayout,
+    /// The records of the file, parsed the first time they are read when importing
+    records: OnceLock<Vec<CsvRecord>>,
 }
 
 /// Represents a parsed CSV record
@@ -33,7 +47,6 @@ impl CsvRecord {
     }
 
     /// Gets a measurement value for a specific column by name
-    #[allow(dead_code)]
     pub fn get_measurement_value(&self, column_name: &str) -> Option<&str> {
         if let Some(idx) = self.column_indexes.get(column_name) {
             if *idx < self.values.len() {
@@ -44,7 +57,6 @@ impl CsvRecord {
     }
 
     /// Gets all measurement columns (excluding the time column)
-    #[allow(dead_code)]
     pub fn get_measurement_columns(&self) -> Vec<&String> {
         self.column_indexes
             .keys()
@@ -84,6 +96,26 @@ impl fmt::Display for CsvRecord {
     }
 }
 
+/// What [`CsvParser::clean`] did to a file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CleanReport {
+    /// Delimiter the file was read with
+    pub delimiter: char,
+    pub header_rows: usize,
+    /// Data rows written to the cleaned copy
+    pub rows_written: usize,
+    /// Data rows left out, in file order
+    pub dropped: Vec<DroppedRow>,
+}
+
+/// A row [`CsvParser::clean`] left out
+#[derive(Debug, Clone, PartialEq)]
+pub struct DroppedRow {
+    /// Line the row starts on, counting from 1
+    pub line: u64,
+    pub reason: String,
+}
+
 impl CsvParser {
     /// Creates a new CSV parser for the given file path
     pub fn new(file_path: &str) -> Self {
@@ -91,6 +123,11 @@ impl CsvParser {
             file_path: file_path.to_string(),
             header_rows: 1,             // Default to 1 header row
             time_column_index: Some(0), // Default to first column as timestamp
+            time_column: "timestamp".to_string(),
+            time_format: "%Y-%m-%d %H:%M:%S".to_string(),
+            fx: None,
+            layout: FundsLayout::default(),
+            records: OnceLock::new(),
         }
     }
 
@@ -102,20 +139,62 @@ impl CsvParser {
 
     /// Sets the column index to use as the timestamp
     /// Use None to indicate there is no timestamp column
-    #[allow(dead_code)]
     pub fn with_time_column_index(mut self, index: Option<usize>) -> Self {
         self.time_column_index = index;
         self
     }
 
+    /// Sets the name of the column the timestamps are read from when importing, and their format
+    pub fn with_time_column(mut self, column: &str, format: &str) -> Self {
+        self.time_column = column.to_string();
+        self.time_format = format.to_string();
+        self
+    }
+
+    /// Also writes the values of the records in the base currency of `fx` when importing
+    pub fn with_fx(mut self, fx: FxConversion) -> Self {
+        self.fx = Some(fx);
+        self
+    }
+
+    /// Sets the tags added to the points of some columns when importing
+    pub fn with_column_tags(mut self, column_tags: Vec<ColumnTags>) -> Self {
+        self.layout.column_tags = column_tags;
+        self
+    }
+
+    /// Sets what each header row holds when importing, from the top; by default the first
+    /// names the fund and the second the measurement
+    pub fn with_header_roles(mut self, roles: Vec<HeaderRole>) -> Self {
+        self.layout.header_roles = roles;
+        self
+    }
+
+    /// Sets the key of the tag the fund of each point is written to when importing
+    pub fn with_tag_key(mut self, tag_key: &str) -> Self {
+        self.layout.tag_key = Some(tag_key.to_string());
+        self
+    }
+
+    /// Keeps the values that are not numbers as string fields of the points of their row when
+    /// importing, named after their column, instead of dropping them
+    pub fn with_text_fields(mut self, text_fields: bool) -> Self {
+        self.layout.text_fields = text_fields;
+        self
+    }
+
+    /// Sets the measurement of the columns no header row names one for when importing
+    pub fn with_measurement(mut self, measurement: &str) -> Self {
+        self.layout.measurement = Some(measurement.to_string());
+        self
+    }
+
     /// Gets the number of header rows
-    #[allow(dead_code)]
     pub fn header_rows(&self) -> usize {
         self.header_rows
     }
 
     /// Gets the time column index
-    #[allow(dead_code)]
     pub fn time_column_index(&self) -> Option<usize> {
         self.time_column_index
     }
@@ -170,13 +249,33 @@ impl CsvParser {
             };
 
             // Replace spaces with underscores
-            let final_header = header.replace(' ', "_");
+            let mut final_header = header.replace(' ', "_");
+
+            // The same fund and measurement may appear again, e.g. in another account
+            let mut copy = 1;
+            while column_headers.contains(&final_header) {
+                copy += 1;
+                final_header = format!("{}_{}", header.replace(' ', "_"), copy);
+            }
             column_headers.push(final_header);
         }
 
         column_headers
     }
 
+    /// Reads the names of the columns from the header rows, as the records are keyed by
+    pub fn headers(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(File::open(&self.file_path)?);
+        let header_rows = rdr
+            .records()
+            .take(self.header_rows)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(self.process_headers(&header_rows))
+    }
+
     /// Parse the CSV file and return the records
     pub fn parse(&self) -> Result<Vec<CsvRecord>, Box<dyn Error>> {
         // Check if file exists before attempting to parse
@@ -259,7 +358,6 @@ impl CsvParser {
     }
 
     /// Generates a formatted string representation of the parsed CSV data
-    #[allow(dead_code)]
     pub fn format_parsed_data(&self) -> Result<String, Box<dyn Error>> {
         let records = self.parse()?;
 
@@ -491,6 +589,208 @@ impl CsvParser {
 
         Ok(output)
     }
+
+    /// Writes a normalized copy of the file to `writer`: comma-separated, quoted only where
+    /// needed, with header cells trimmed and their line breaks and runs of spaces turned into
+    /// single spaces, and data values trimmed. The delimiter of the file (comma, semicolon or
+    /// tab) is detected from its first line. Rows that cannot be imported are left out and
+    /// listed in the report, while trailing empty cells past the header are dropped
+    pub fn clean<W: Write>(&self, writer: W) -> Result<CleanReport, Box<dyn Error>> {
+        if !self.file_exists() {
+            return Err(format!("File does not exist: {}", self.file_path).into());
+        }
+
+        let mut first_line = String::new();
+        BufReader::new(File::open(&self.file_path)?).read_line(&mut first_line)?;
+        let delimiter = detect_delimiter(&first_line);
+
+        let mut rdr = ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .delimiter(delimiter)
+            .from_reader(File::open(&self.file_path)?);
+        let mut csv_writer = csv::Writer::from_writer(writer);
+        let mut report = CleanReport {
+            delimiter: delimiter as char,
+            ..CleanReport::default()
+        };
+
+        let mut columns = 0;
+        for (row, result) in rdr.byte_records().enumerate() {
+            let record = result?;
+            let line = record.position().map_or(row as u64 + 1, |pos| pos.line());
+            let mut drop_row = |reason: String| {
+                report.dropped.push(DroppedRow { line, reason });
+            };
+
+            let values: Vec<&str> = match record.iter().map(std::str::from_utf8).collect() {
+                Ok(values) => values,
+                Err(_) => {
+                    drop_row("not valid UTF-8".to_string());
+                    continue;
+                }
+            };
+
+            if row < self.header_rows {
+                let cells: Vec<String> = values
+                    .iter()
+                    .enumerate()
+                    .map(|(column, value)| {
+                        // A byte order mark would become part of the first header
+                        let value = if row == 0 && column == 0 {
+                            value.trim_start_matches('\u{feff}')
+                        } else {
+                            value
+                        };
+                        value.split_whitespace().collect::<Vec<_>>().join(" ")
+                    })
+                    .collect();
+                columns = columns.max(cells.len());
+                csv_writer.write_record(&cells)?;
+                report.header_rows += 1;
+                continue;
+            }
+
+            let mut values: Vec<&str> = values.iter().map(|value| value.trim()).collect();
+            if report.header_rows == 0 && columns == 0 {
+                // Without header rows the first data row sets the columns
+                columns = values.len();
+            }
+            while values.len() > columns && values.last() == Some(&"") {
+                values.pop();
+            }
+            if values.iter().all(|value| value.is_empty()) {
+                drop_row("empty row".to_string());
+            } else if values.len() != columns {
+                drop_row(format!("{} values for {} columns", values.len(), columns));
+            } else if self
+                .time_column_index
+                .is_some_and(|index| values[index].is_empty())
+            {
+                drop_row("no timestamp".to_string());
+            } else {
+                csv_writer.write_record(&values)?;
+                report.rows_written += 1;
+            }
+        }
+        csv_writer.flush()?;
+        Ok(report)
+    }
+}
+
+impl Source for CsvParser {
+    type Record = CsvRecord;
+
+    fn validate(&self) -> Result<String, Box<dyn Error>> {
+        if !self.file_exists() {
+            return Err(format!("File does not exist: {}", self.file_path).into());
+        }
+        File::open(&self.file_path)?;
+        self.layout.roles(self.header_rows)?;
+        Ok(format!(
+            "CSV file: {} ({} header rows, time column '{}')",
+            self.file_path, self.header_rows, self.time_column
+        ))
+    }
+
+    /// Reads the records after the last imported timestamp; records whose timestamp cannot
+    /// be parsed are included to be safe. The file is only parsed once, so finding the FX
+    /// rates the new records need and checking the watermark don't parse it again
+    fn records_since(
+        &self,
+        watermark: &ImportState,
+    ) -> Result<RecordStream<CsvRecord>, Box<dyn Error>> {
+        let records = match self.records.get() {
+            Some(records) => records.clone(),
+            None => {
+                let records = self.parse()?;
+                self.records.get_or_init(|| records).clone()
+            }
+        };
+        let Some(last_imported) = watermark.last_imported_timestamp else {
+            return Ok(RecordStream::new(records));
+        };
+
+        let total = records.len();
+        let new_records: Vec<CsvRecord> = records
+            .into_iter()
+            .filter(|record| {
+                self.timestamp(record)
+                    .is_none_or(|timestamp| timestamp > last_imported)
+            })
+            .collect();
+        let already_imported = total - new_records.len();
+        Ok(RecordStream::new(new_records).with_already_imported(already_imported))
+    }
+
+    fn timestamp(&self, record: &CsvRecord) -> Option<DateTime<Utc>> {
+        let time_idx = record.column_indexes.get(&self.time_column)?;
+        let time_value = record.values.get(*time_idx)?;
+        NaiveDateTime::parse_from_str(time_value, &self.time_format)
+            .ok()
+            .map(|naive_dt| DateTime::from_naive_utc_and_offset(naive_dt, Utc))
+    }
+
+    fn to_points(&self, record: &CsvRecord) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+        let points = funds_record_to_points_with_currency(
+            record,
+            &self.time_column,
+            &self.time_format,
+            &self.layout,
+        )?;
+        match &self.fx {
+            Some(fx) => fx.add_normalized(points),
+            None => Ok(points.into_iter().map(|(point, _)| point).collect()),
+        }
+    }
+}
+
+/// Keeps the oldest `limit` CSV records, plus any sharing the timestamp of the last one kept,
+/// since the next import skips everything at or before that timestamp
+pub fn oldest_records(
+    mut records: Vec<CsvRecord>,
+    limit: usize,
+    time_column: &str,
+    time_format: &str,
+) -> Vec<CsvRecord> {
+    let timestamp = |record: &CsvRecord| {
+        let time_idx = record.column_indexes.get(time_column)?;
+        NaiveDateTime::parse_from_str(record.values.get(*time_idx)?, time_format).ok()
+    };
+    if records.len() <= limit {
+        return records;
+    }
+
+    records.sort_by_key(timestamp);
+    let last_kept = timestamp(&records[limit - 1]);
+    let ties = records[limit..]
+        .iter()
+        .take_while(|record| timestamp(record) == last_kept)
+        .count();
+    records.truncate(limit + ties);
+    records
+}
+
+/// Detects the delimiter of a CSV file from its first line: the most frequent of comma,
+/// semicolon and tab, outside quotes, or a comma when none appears
+pub fn detect_delimiter(line: &str) -> u8 {
+    let mut counts = [(b',', 0), (b';', 0), (b'\t', 0)];
+    let mut quoted = false;
+    for byte in line.bytes() {
+        if byte == b'"' {
+            quoted = !quoted;
+        } else if !quoted {
+            if let Some((_, count)) = counts.iter_mut().find(|(delimiter, _)| *delimiter == byte) {
+                *count += 1;
+            }
+        }
+    }
+    counts
+        .iter()
+        .filter(|(_, count)| *count > 0)
+        .rev()
+        .max_by_key(|(_, count)| *count)
+        .map_or(b',', |(delimiter, _)| *delimiter)
 }
 
 #[cfg(test)]
@@ -0,0 +1,453 @@
+use crate::health_data::{Distance, HealthDataReader, HealthDataType, HealthRecord, ReadFrom};
+use crate::influx_client::{DataPoint, InfluxClient, MultiFieldPoint};
+use chrono::{DateTime, Duration, Utc};
+use clap::ValueEnum;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+
+/// Measurement scale snapshots are written to, with a field per value recorded
+pub const BODY_COMPOSITION_MEASUREMENT: &str = "BodyComposition";
+
+/// Measurement the BMI at each weight record is written to
+pub const BMI_MEASUREMENT: &str = "BMI";
+
+/// Measurement the summary of each exercise session is written to
+pub const WORKOUT_SUMMARY_MEASUREMENT: &str = "WorkoutSummary";
+
+/// Metrics derived from the records of an import and written alongside them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum DerivedMetric {
+    /// Weight, body fat and lean mass recorded together, as one point
+    BodyComposition,
+    /// Body mass index at every weight record
+    Bmi,
+    /// Heart rate, calories and distance of every exercise session, as one point
+    WorkoutSummary,
+    /// Minutes in each heart rate zone, per day and per workout
+    HeartRateZones,
+}
+
+impl fmt::Display for DerivedMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DerivedMetric::BodyComposition => write!(f, "body-composition"),
+            DerivedMetric::Bmi => write!(f, "bmi"),
+            DerivedMetric::WorkoutSummary => write!(f, "workout-summary"),
+            DerivedMetric::HeartRateZones => write!(f, "heart-rate-zones"),
+        }
+    }
+}
+
+/// The points a derived stage computes
+#[derive(Debug, Clone)]
+pub enum DerivedPoints {
+    /// Points holding a single value
+    Single(Vec<DataPoint>),
+    /// Points holding values recorded together, a field each
+    MultiField(Vec<MultiFieldPoint>),
+}
+
+impl DerivedPoints {
+    /// Gets the number of points
+    pub fn len(&self) -> usize {
+        match self {
+            DerivedPoints::Single(points) => points.len(),
+            DerivedPoints::MultiField(points) => points.len(),
+        }
+    }
+
+    /// Tells whether there are no points
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A metric the health import derives from the records it wrote, written after them
+///
+/// The import runs every enabled stage the same way: the stage looks at the records written
+/// and tells where its inputs must be read from to derive everything they touch again, the
+/// records of its inputs are read from there, and the points it computes from them are
+/// written. A stage without inputs computes its points from the records written
+pub trait DerivedStage {
+    /// Name of the stage, in the progress output and the run journal
+    fn name(&self) -> &str;
+
+    /// Data types the stage computes its points from
+    fn inputs(&self) -> &[HealthDataType];
+
+    /// Prepares the stage for the records written, reading anything else it needs, and gives
+    /// where to read its inputs from; `None` when the records touch nothing it derives
+    fn prepare(
+        &mut self,
+        reader: &HealthDataReader,
+        written: &[HealthRecord],
+    ) -> Result<Option<ReadFrom>, Box<dyn Error>>;
+
+    /// Computes the points from the records of the inputs read
+    fn compute(&self, records: &[HealthRecord]) -> DerivedPoints;
+
+    /// Describes the points written, after their count
+    fn describe(&self) -> String {
+        String::new()
+    }
+
+    /// Warns about records the points written left out, if any
+    fn warning(&self, _written: usize) -> Option<String> {
+        None
+    }
+}
+
+/// Runs a derived stage for the records written, writing its points with `client`
+/// Gives the number of points written, or `None` when the records touched nothing it derives
+pub async fn run_stage(
+    stage: &mut dyn DerivedStage,
+    reader: &HealthDataReader,
+    client: &InfluxClient,
+    written: &[HealthRecord],
+) -> Result<Option<usize>, Box<dyn Error>> {
+    let Some(since) = stage.prepare(reader, written)? else {
+        return Ok(None);
+    };
+    let points = if stage.inputs().is_empty() {
+        stage.compute(written)
+    } else {
+        let records: Vec<HealthRecord> = reader
+            .get_health_data_since_per_type(|_| since, Some(stage.inputs()))?
+            .into_values()
+            .flatten()
+            .collect();
+        stage.compute(&records)
+    };
+    match &points {
+        DerivedPoints::Single(points) => client.write_points(points).await?,
+        DerivedPoints::MultiField(points) => client.write_multi_field_points(points).await?,
+    }
+    Ok(Some(points.len()))
+}
+
+/// Writes the weight, body fat and lean body mass recorded together as one point, see
+/// [`body_composition`]
+pub struct BodyCompositionStage;
+
+impl DerivedStage for BodyCompositionStage {
+    fn name(&self) -> &str {
+        "body composition"
+    }
+
+    fn inputs(&self) -> &[HealthDataType] {
+        &[]
+    }
+
+    fn prepare(
+        &mut self,
+        _reader: &HealthDataReader,
+        _written: &[HealthRecord],
+    ) -> Result<Option<ReadFrom>, Box<dyn Error>> {
+        Ok(Some(ReadFrom::Beginning))
+    }
+
+    fn compute(&self, records: &[HealthRecord]) -> DerivedPoints {
+        DerivedPoints::MultiField(body_composition(records))
+    }
+}
+
+/// Writes the BMI at every weight record written, see [`bmi`]
+pub struct BmiStage {
+    height: Option<f64>,
+    heights: Vec<HealthRecord>,
+    weights: usize,
+}
+
+impl BmiStage {
+    /// Creates the stage, with `height` in meters, or else the Height records of the export
+    pub fn new(height: Option<f64>) -> Self {
+        BmiStage {
+            height,
+            heights: Vec::new(),
+            weights: 0,
+        }
+    }
+}
+
+impl DerivedStage for BmiStage {
+    fn name(&self) -> &str {
+        "BMI"
+    }
+
+    fn inputs(&self) -> &[HealthDataType] {
+        &[]
+    }
+
+    /// Heights are read in full, as the last one may be from long before this import
+    fn prepare(
+        &mut self,
+        reader: &HealthDataReader,
+        written: &[HealthRecord],
+    ) -> Result<Option<ReadFrom>, Box<dyn Error>> {
+        self.weights = written
+            .iter()
+            .filter(|record| record.record_type == HealthDataType::Weight)
+            .count();
+        if self.height.is_none() && self.weights > 0 {
+            self.heights = reader.get_height_since(ReadFrom::Beginning)?;
+        }
+        Ok(Some(ReadFrom::Beginning))
+    }
+
+    fn compute(&self, records: &[HealthRecord]) -> DerivedPoints {
+        DerivedPoints::Single(bmi(records, &self.heights, self.height))
+    }
+
+    fn warning(&self, written: usize) -> Option<String> {
+        (written < self.weights).then(|| {
+            format!(
+                "no height for {} weight records; import Height records or pass --height",
+                self.weights - written
+            )
+        })
+    }
+}
+
+/// Writes the summary of the sessions written, or with samples or calories written, see
+/// [`workout_summaries`]
+#[derive(Default)]
+pub struct WorkoutSummaryStage {
+    distances: Vec<Distance>,
+}
+
+impl DerivedStage for WorkoutSummaryStage {
+    fn name(&self) -> &str {
+        "workout summaries"
+    }
+
+    fn inputs(&self) -> &[HealthDataType] {
+        &[
+            HealthDataType::ExerciseSession,
+            HealthDataType::HeartRate,
+            HealthDataType::TotalCalories,
+        ]
+    }
+
+    /// A session is summarized again when samples or calories recorded during it arrive
+    /// late, so the read starts a day before the oldest of them
+    fn prepare(
+        &mut self,
+        reader: &HealthDataReader,
+        written: &[HealthRecord],
+    ) -> Result<Option<ReadFrom>, Box<dyn Error>> {
+        let Some(oldest) = written
+            .iter()
+            .filter(|record| self.inputs().contains(&record.record_type))
+            .map(|record| record.timestamp)
+            .min()
+        else {
+            return Ok(None);
+        };
+        let since = ReadFrom::Timestamp(oldest - Duration::days(1));
+        self.distances = reader.get_distances_since(since)?;
+        Ok(Some(since))
+    }
+
+    fn compute(&self, records: &[HealthRecord]) -> DerivedPoints {
+        DerivedPoints::MultiField(workout_summaries(records, &self.distances))
+    }
+}
+
+/// Gets the field a body composition value is written to
+fn body_composition_field(data_type: HealthDataType) -> Option<&'static str> {
+    match data_type {
+        HealthDataType::Weight => Some("weight"),
+        HealthDataType::BodyFat => Some("body_fat"),
+        HealthDataType::LeanBodyMass => Some("lean_body_mass"),
+        _ => None,
+    }
+}
+
+/// Merges the weight, body fat and lean body mass an app recorded at the same time into
+/// one `BodyComposition` point, with a field for each (grams, percent and grams), so a
+/// scale snapshot is a single row to query. Values recorded alone are left out
+pub fn body_composition(records: &[HealthRecord]) -> Vec<MultiFieldPoint> {
+    let mut snapshots: BTreeMap<(DateTime<Utc>, &str), BTreeMap<String, f64>> = BTreeMap::new();
+    for record in records {
+        let Some(field) = body_composition_field(record.record_type) else {
+            continue;
+        };
+        let app = record
+            .metadata
+            .get("app_name")
+            .map(String::as_str)
+            .unwrap_or("unknown");
+        snapshots
+            .entry((record.timestamp, app))
+            .or_default()
+            .insert(field.to_string(), record.value);
+    }
+
+    snapshots
+        .into_iter()
+        .filter(|(_, fields)| fields.len() > 1)
+        .map(|((time, app), fields)| MultiFieldPoint {
+            measurement: BODY_COMPOSITION_MEASUREMENT.to_string(),
+            time,
+            tags: HashMap::from([("app_name".to_string(), app.to_string())]),
+            fields,
+        })
+        .collect()
+}
+
+/// Computes the BMI at every weight record among `records`: the weight in kg over the
+/// square of the height in meters
+///
+/// `height` is used when given; otherwise the latest of `heights` (Height records, in any
+/// order) recorded at or before the weight, or the first one for weights recorded before
+/// any height. Weights are skipped when there is no height at all
+pub fn bmi(
+    records: &[HealthRecord],
+    heights: &[HealthRecord],
+    height: Option<f64>,
+) -> Vec<DataPoint> {
+    let mut heights: Vec<(DateTime<Utc>, f64)> = heights
+        .iter()
+        .filter(|record| record.record_type == HealthDataType::Height && record.value > 0.0)
+        .map(|record| (record.timestamp, record.value))
+        .collect();
+    heights.sort_by_key(|(time, _)| *time);
+    let height_at = |time: DateTime<Utc>| {
+        height.or_else(|| {
+            let recorded = heights.partition_point(|(height_time, _)| *height_time <= time);
+            heights
+                .get(recorded.saturating_sub(1))
+                .map(|(_, height)| *height)
+        })
+    };
+
+    records
+        .iter()
+        .filter(|record| record.record_type == HealthDataType::Weight)
+        .filter_map(|record| {
+            let height = height_at(record.timestamp)?;
+            let bmi = record.value / 1000.0 / (height * height);
+            let tags = record
+                .metadata
+                .get("app_name")
+                .map(|app| HashMap::from([("app_name".to_string(), app.clone())]))
+                .unwrap_or_default();
+            Some(DataPoint {
+                measurement: BMI_MEASUREMENT.to_string(),
+                time: record.timestamp,
+                tags,
+                field_value: (bmi * 10.0).round() / 10.0,
+                text_fields: BTreeMap::new(),
+            })
+        })
+        .collect()
+}
+
+/// Gets the end of a record spanning time (an exercise session or calories burned)
+fn end_time(record: &HealthRecord) -> Option<DateTime<Utc>> {
+    record
+        .metadata
+        .get("end_time_millis")
+        .and_then(|millis| millis.parse().ok())
+        .and_then(DateTime::from_timestamp_millis)
+}
+
+/// A span of time, from its start to its end
+type Span = (DateTime<Utc>, DateTime<Utc>);
+
+/// Gets the part of `amount`, spread evenly from `start` to `end`, that falls within
+/// `from` to `until`, or None when the two spans do not overlap
+fn prorated(amount: f64, (start, end): Span, (from, until): Span) -> Option<f64> {
+    if end <= start {
+        return (from <= start && start < until).then_some(amount);
+    }
+    let overlap = (end.min(until) - start.max(from)).num_milliseconds();
+    (overlap > 0).then(|| amount * overlap as f64 / (end - start).num_milliseconds() as f64)
+}
+
+/// Summarizes every exercise session among `records` in one `WorkoutSummary` point at its
+/// start, tagged with its app and exercise type, with the fields:
+///
+/// - `duration_minutes`
+/// - `avg_heart_rate` and `max_heart_rate`, out of the HeartRate samples taken during it
+/// - `calories`, the TotalCalories burned during it; records spanning its start or end
+///   count for the part of them it covers
+/// - `distance_meters`, out of `distances`, prorated the same way
+///
+/// The heart rate, calories and distance fields are left out when nothing was recorded
+/// during the session
+pub fn workout_summaries(records: &[HealthRecord], distances: &[Distance]) -> Vec<MultiFieldPoint> {
+    let mut heart_rate: Vec<(DateTime<Utc>, f64)> = records
+        .iter()
+        .filter(|record| record.record_type == HealthDataType::HeartRate)
+        .map(|record| (record.timestamp, record.value))
+        .collect();
+    heart_rate.sort_by_key(|(time, _)| *time);
+    let calories: Vec<(Span, f64)> = records
+        .iter()
+        .filter(|record| record.record_type == HealthDataType::TotalCalories)
+        .filter_map(|record| Some(((record.timestamp, end_time(record)?), record.value)))
+        .collect();
+
+    let mut points = Vec::new();
+    for session in records
+        .iter()
+        .filter(|record| record.record_type == HealthDataType::ExerciseSession)
+    {
+        let Some(end) = end_time(session) else {
+            continue;
+        };
+        let window = (session.timestamp, end);
+        let mut fields = BTreeMap::from([(
+            "duration_minutes".to_string(),
+            (end - session.timestamp).num_seconds() as f64 / 60.0,
+        )]);
+
+        let first = heart_rate.partition_point(|(time, _)| *time < session.timestamp);
+        let last = heart_rate.partition_point(|(time, _)| *time < end);
+        let during = &heart_rate[first..last];
+        if !during.is_empty() {
+            let sum: f64 = during.iter().map(|(_, bpm)| bpm).sum();
+            let max = during.iter().map(|(_, bpm)| *bpm).fold(f64::MIN, f64::max);
+            fields.insert(
+                "avg_heart_rate".to_string(),
+                (sum / during.len() as f64 * 10.0).round() / 10.0,
+            );
+            fields.insert("max_heart_rate".to_string(), max);
+        }
+
+        let burned: Vec<f64> = calories
+            .iter()
+            .filter_map(|(span, value)| prorated(*value, *span, window))
+            .collect();
+        if !burned.is_empty() {
+            fields.insert("calories".to_string(), burned.iter().sum::<f64>().round());
+        }
+
+        let covered: Vec<f64> = distances
+            .iter()
+            .filter_map(|distance| {
+                prorated(distance.meters, (distance.start, distance.end), window)
+            })
+            .collect();
+        if !covered.is_empty() {
+            fields.insert(
+                "distance_meters".to_string(),
+                covered.iter().sum::<f64>().round(),
+            );
+        }
+
+        let tags: HashMap<String, String> = ["app_name", "exercise_type"]
+            .into_iter()
+            .filter_map(|key| Some((key.to_string(), session.metadata.get(key)?.clone())))
+            .collect();
+        points.push(MultiFieldPoint {
+            measurement: WORKOUT_SUMMARY_MEASUREMENT.to_string(),
+            time: session.timestamp,
+            tags,
+            fields,
+        });
+    }
+    points
+}
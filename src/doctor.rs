@@ -0,0 +1,468 @@
+use crate::csv_parser::CsvParser;
+use crate::health_data::HealthDataReader;
+use crate::import::{create_influx_client, create_state_store, EXIT_ERROR};
+use crate::influx_client::{
+    InfluxClient, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use crate::inspect::SourceKind;
+use crate::output;
+use crate::state_management::read_state_file;
+use crate::state_store::StateBackend;
+use chrono::Utc;
+use clap::Args;
+use std::process;
+
+/// The options of `doctor`
+#[derive(Args)]
+pub struct DoctorArgs {
+    /// Source file to check (a funds CSV file or a Health Connect SQLite export)
+    #[arg(short, long, env = "HDI_SOURCE")]
+    pub source: Option<String>,
+
+    /// Kind of source; detected from the file extension when omitted (.db, .sqlite and
+    /// .sqlite3 are health exports)
+    #[arg(long, value_enum, env = "HDI_KIND")]
+    pub kind: Option<SourceKind>,
+
+    /// Number of header rows in the CSV file
+    #[arg(long, default_value = "1", env = "HDI_HEADER_ROWS")]
+    pub header_rows: usize,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: Option<String>,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to write into (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+
+    /// State file to check; defaults to the import's default state file for the source
+    #[arg(long, env = "HDI_STATE_FILE")]
+    pub state_file: Option<String>,
+
+    /// Where import state is kept: file, influxdb (the importer_state measurement), an
+    /// http(s) URL or an s3://bucket/key URL
+    #[arg(long, default_value = "file", env = "HDI_STATE_BACKEND")]
+    pub state_backend: StateBackend,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+}
+
+/// Prints the results of `doctor` checks, counting warnings and failures
+#[derive(Default)]
+struct DoctorReport {
+    warnings: usize,
+    failures: usize,
+}
+
+impl DoctorReport {
+    fn pass(&mut self, check: &str, detail: &str) {
+        println!("{}", output::success(&format!("{}: {}", check, detail)));
+    }
+
+    fn warn(&mut self, check: &str, problem: &str, fix: &str) {
+        println!("{}", output::warning(&format!("{}: {}", check, problem)));
+        println!("  Fix: {}", fix);
+        self.warnings += 1;
+    }
+
+    fn fail(&mut self, check: &str, problem: &str, fix: &str) {
+        println!("{}", output::failure(&format!("{}: {}", check, problem)));
+        println!("  Fix: {}", fix);
+        self.failures += 1;
+    }
+}
+
+/// Checks that a source file can be read and, for health exports, that its schema is
+/// the one the importer expects
+fn check_source(report: &mut DoctorReport, source: &str, kind: SourceKind, header_rows: usize) {
+    if let Err(e) = std::fs::File::open(source) {
+        report.fail(
+            "Source",
+            &format!("cannot read {}: {}", source, e),
+            "Check the --source path (relative paths start from the current directory) and the file's permissions",
+        );
+        return;
+    }
+
+    match kind {
+        SourceKind::Funds => match CsvParser::new(source).with_header_rows(header_rows).parse() {
+            Ok(records) if records.is_empty() => report.warn(
+                "Source",
+                &format!(
+                    "{} has no data rows after {} header row(s)",
+                    source, header_rows
+                ),
+                "Check --header-rows; run validate-csv --details to see how the file is read",
+            ),
+            Ok(records) => report.pass(
+                "Source",
+                &format!("{} has {} data rows", source, records.len()),
+            ),
+            Err(e) => report.fail(
+                "Source",
+                &format!("cannot parse {}: {}", source, e),
+                "Run validate-csv --details to see how the file is read",
+            ),
+        },
+        SourceKind::Health => {
+            let check = match HealthDataReader::new(source).check_schema() {
+                Ok(check) => check,
+                Err(e) => {
+                    report.fail(
+                        "Source",
+                        &format!("cannot read {} as an SQLite database: {}", source, e),
+                        "Export the database again from Health Connect, or use --kind funds for a CSV file",
+                    );
+                    return;
+                }
+            };
+            report.pass(
+                "Source",
+                &format!("{} is a readable SQLite database", source),
+            );
+
+            let mut changed: Vec<String> = check
+                .missing_columns
+                .iter()
+                .map(|(table, column)| format!("missing column {}.{}", table, column))
+                .collect();
+            changed.extend(check.renamed_columns.iter().map(|renamed| {
+                format!(
+                    "{}.{} renamed to {}?",
+                    renamed.table, renamed.expected, renamed.found
+                )
+            }));
+            changed.extend(check.type_mismatches.iter().map(|mismatch| {
+                format!(
+                    "{}.{} is {}, expected {}",
+                    mismatch.table, mismatch.column, mismatch.found, mismatch.expected
+                )
+            }));
+            if !changed.is_empty() {
+                report.fail(
+                    "SQLite schema",
+                    &changed.join(", "),
+                    "The export format may have changed; run health-stats and report the output",
+                );
+            } else if check.missing_tables.contains(&"application_info_table") {
+                report.fail(
+                    "SQLite schema",
+                    "missing application_info_table",
+                    "Make sure the file is a Health Connect export, not another SQLite database",
+                );
+            } else if !check.missing_tables.is_empty() {
+                report.warn(
+                    "SQLite schema",
+                    &format!("missing tables: {}", check.missing_tables.join(", ")),
+                    "Nothing to do if no app records these data types; they are skipped by imports",
+                );
+            } else {
+                report.pass(
+                    "SQLite schema",
+                    "all expected tables and columns are present",
+                );
+            }
+        }
+    }
+}
+
+/// Suggests a fix for a failed InfluxDB read or write check
+fn influx_access_fix(error: &str, access: &str) -> String {
+    let error = error.to_lowercase();
+    if error.contains("401") || error.contains("unauthorized") || error.contains("authoriz") {
+        "Check --token; it must be a valid token for this server".to_string()
+    } else if error.contains("403") || error.contains("forbidden") {
+        format!("Give the token {} permission on the bucket", access)
+    } else if error.contains("404") || error.contains("not found") {
+        "Check --bucket (or --database) and --org; the bucket must already exist".to_string()
+    } else {
+        format!(
+            "Check that the token has {} permission on the bucket",
+            access
+        )
+    }
+}
+
+/// Checks that InfluxDB is reachable, that its clock agrees with ours and that the token
+/// can read from and write to the bucket
+async fn check_influxdb(
+    report: &mut DoctorReport,
+    url: &str,
+    influx_client: Option<&InfluxClient>,
+    has_org: bool,
+    has_token: bool,
+) {
+    let Some(influx_client) = influx_client else {
+        report.fail(
+            "InfluxDB",
+            "no bucket or database given",
+            "Set --bucket (or --database for InfluxDB 1.x), e.g. in the [influxdb] section of the config file",
+        );
+        return;
+    };
+
+    let server = match influx_client.ping().await {
+        Ok(server) => server,
+        Err(e) => {
+            report.fail(
+                "InfluxDB",
+                &format!("cannot reach {}: {}", url, e),
+                "Check --url and that InfluxDB is running; raise --connect-timeout on slow networks",
+            );
+            return;
+        }
+    };
+    report.pass(
+        "InfluxDB",
+        &format!("{} is reachable (version {})", url, server.version),
+    );
+
+    match server.date {
+        Some(date) => {
+            let offset = (Utc::now() - date).num_seconds();
+            if offset.abs() > 60 {
+                report.warn(
+                    "Clock",
+                    &format!(
+                        "the local clock is {}s {} the server's",
+                        offset.abs(),
+                        if offset > 0 { "ahead of" } else { "behind" }
+                    ),
+                    "Synchronize the clock (e.g., enable NTP); watermarks are checked against the local time",
+                );
+            } else {
+                report.pass("Clock", "the local clock agrees with the server's");
+            }
+        }
+        None => report.pass("Clock", "the server did not report its time; not checked"),
+    }
+
+    if !has_token {
+        report.fail(
+            "Authentication",
+            "no token given",
+            "Set --token or HDI_TOKEN (for InfluxDB 1.x, use \"username:password\")",
+        );
+        return;
+    }
+    if !has_org && server.version.trim_start_matches('v').starts_with("2.") {
+        report.fail(
+            "Authentication",
+            "no organization given",
+            "Set --org to the organization that owns the bucket",
+        );
+        return;
+    }
+
+    match influx_client.check_read_access().await {
+        Ok(()) => report.pass("Authentication", "the token can read from the bucket"),
+        Err(e) => report.fail(
+            "Authentication",
+            &format!("reading from the bucket failed: {}", e),
+            &influx_access_fix(&e.to_string(), "read"),
+        ),
+    }
+    match influx_client.check_write_access().await {
+        Ok(()) => report.pass("Write access", "the token can write to the bucket"),
+        Err(e) => report.fail(
+            "Write access",
+            &format!("writing to the bucket failed: {}", e),
+            &influx_access_fix(&e.to_string(), "write"),
+        ),
+    }
+}
+
+/// Checks that the import state can be read and saved
+async fn check_state(
+    report: &mut DoctorReport,
+    backend: &StateBackend,
+    state_file: &str,
+    source: &str,
+    influx_client: Option<InfluxClient>,
+    connect_timeout: u64,
+    request_timeout: u64,
+) {
+    if *backend != StateBackend::File {
+        let Some(influx_client) = influx_client.or_else(|| {
+            // Only the influxdb backend needs a client
+            matches!(backend, StateBackend::Http(_) | StateBackend::S3(_))
+                .then(|| InfluxClient::builder("", "").dry_run(true).build().ok())
+                .flatten()
+        }) else {
+            report.fail(
+                "State",
+                "the influxdb state backend needs a bucket",
+                "Set --bucket, or use --state-backend file",
+            );
+            return;
+        };
+        let state_store = create_state_store(
+            backend,
+            state_file,
+            connect_timeout,
+            request_timeout,
+            || influx_client,
+        );
+        match state_store.load_import_state(source).await {
+            Ok(_) => report.pass("State", &format!("{} is readable", state_store.describe())),
+            Err(e) => report.fail(
+                "State",
+                &format!("cannot read {}: {}", state_store.describe(), e),
+                "Check --state-backend and that the state server is reachable",
+            ),
+        }
+        return;
+    }
+
+    if std::path::Path::new(state_file).exists() {
+        if let Err(e) = read_state_file(state_file) {
+            report.fail(
+                "State",
+                &format!("{} is invalid: {}", state_file, e),
+                &format!(
+                    "Restore a backup ({}.1) or remove the file to import everything again",
+                    state_file
+                ),
+            );
+            return;
+        }
+        match std::fs::OpenOptions::new().append(true).open(state_file) {
+            Ok(_) => report.pass("State", &format!("{} is valid and writable", state_file)),
+            Err(e) => report.fail(
+                "State",
+                &format!("{} is not writable: {}", state_file, e),
+                "Check the permissions of the state file",
+            ),
+        }
+        return;
+    }
+
+    // Create and remove a file next to it to check that it can be created
+    let probe = format!("{}.doctor", state_file);
+    match std::fs::write(&probe, "") {
+        Ok(()) => {
+            let _ = std::fs::remove_file(&probe);
+            report.pass(
+                "State",
+                &format!("{} does not exist yet and can be created", state_file),
+            );
+        }
+        Err(e) => report.fail(
+            "State",
+            &format!("{} cannot be created: {}", state_file, e),
+            "Check that its directory exists and is writable, or use --state-file",
+        ),
+    }
+}
+
+/// Checks the source, InfluxDB and the import state the options point to, printing how to
+/// fix each problem found. Exits with EXIT_ERROR when any check failed
+pub async fn run(args: DoctorArgs) {
+    let DoctorArgs {
+        source,
+        kind,
+        header_rows,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        state_file,
+        state_backend,
+        connect_timeout,
+        request_timeout,
+    } = args;
+
+    let kind = source
+        .as_deref()
+        .map(|source| kind.unwrap_or_else(|| SourceKind::detect(source)));
+    let mut report = DoctorReport::default();
+
+    if let (Some(source), Some(kind)) = (&source, kind) {
+        check_source(&mut report, source, kind, header_rows);
+    }
+
+    let bucket = database.or(bucket);
+    let influx_client = bucket.as_ref().map(|bucket| {
+        create_influx_client(
+            InfluxClient::builder(&url, bucket).token(token.as_deref().unwrap_or_default()),
+            org.as_deref().unwrap_or_default(),
+            retention_policy.as_deref(),
+            connect_timeout,
+            request_timeout,
+        )
+    });
+    check_influxdb(
+        &mut report,
+        &url,
+        influx_client.as_ref(),
+        org.is_some(),
+        token.is_some(),
+    )
+    .await;
+
+    let state_file = state_file.or_else(|| {
+        kind.map(|kind| match kind {
+            SourceKind::Funds => ".import_state.json".to_string(),
+            SourceKind::Health => ".health_import_state.json".to_string(),
+        })
+    });
+    if let Some(state_file) = state_file {
+        check_state(
+            &mut report,
+            &state_backend,
+            &state_file,
+            source.as_deref().unwrap_or_default(),
+            influx_client,
+            connect_timeout,
+            request_timeout,
+        )
+        .await;
+    }
+
+    println!();
+    if report.failures > 0 {
+        println!(
+            "{}",
+            output::failure(&format!(
+                "{} problem(s) found, {} warning(s)",
+                report.failures, report.warnings
+            ))
+        );
+        process::exit(EXIT_ERROR);
+    } else if report.warnings > 0 {
+        println!(
+            "{}",
+            output::warning(&format!(
+                "No problems found, {} warning(s)",
+                report.warnings
+            ))
+        );
+    } else {
+        println!("{}", output::success("All checks passed"));
+    }
+}
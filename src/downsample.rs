@@ -0,0 +1,138 @@
+use crate::convert::health_record_to_point;
+use crate::derived::{DerivedPoints, DerivedStage};
+use crate::health_data::{HealthDataReader, HealthDataType, HealthRecord, ReadFrom};
+use crate::influx_client::DataPoint;
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::time::Duration;
+
+/// Tag telling the minimum, mean and maximum of a window apart
+pub const AGGREGATE_TAG: &str = "aggregate";
+
+/// Downsamples points to the minimum, mean and maximum of each fixed window, for a bucket
+/// with a longer retention than the raw points
+///
+/// Windows are aligned to the Unix epoch, so every run puts a point in the same window.
+/// Each series (measurement and tags) is downsampled on its own, and the three values are
+/// written to its measurement at the start of the window, tagged `aggregate=min|mean|max`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Downsampler {
+    interval_millis: i64,
+}
+
+impl Downsampler {
+    /// Creates a downsampler to windows of `interval`
+    pub fn new(interval: Duration) -> Self {
+        Downsampler {
+            interval_millis: (interval.as_millis() as i64).max(1),
+        }
+    }
+
+    /// Gets the start of the window `time` falls in
+    pub fn window(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        let millis = time.timestamp_millis();
+        Utc.timestamp_millis_opt(millis - millis.rem_euclid(self.interval_millis))
+            .unwrap()
+    }
+
+    /// Gets the windows `points` fall in, which must be downsampled again
+    pub fn windows(&self, points: &[DataPoint]) -> BTreeSet<DateTime<Utc>> {
+        points.iter().map(|point| self.window(point.time)).collect()
+    }
+
+    /// Downsamples `points`, which must hold every point of the windows they fall in
+    pub fn downsample(&self, points: &[DataPoint]) -> Vec<DataPoint> {
+        // Values by window and series, the tags sorted so equal series group together
+        type Series<'a> = (DateTime<Utc>, &'a str, Vec<(&'a String, &'a String)>);
+        let mut windows: BTreeMap<Series, Vec<f64>> = BTreeMap::new();
+        for point in points {
+            let mut tags: Vec<_> = point.tags.iter().collect();
+            tags.sort();
+            windows
+                .entry((self.window(point.time), &point.measurement, tags))
+                .or_default()
+                .push(point.field_value);
+        }
+
+        let mut downsampled = Vec::new();
+        for ((time, measurement, tags), values) in windows {
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            for (aggregate, value) in [("min", min), ("mean", mean), ("max", max)] {
+                let mut tags: HashMap<String, String> = tags
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect();
+                tags.insert(AGGREGATE_TAG.to_string(), aggregate.to_string());
+                downsampled.push(DataPoint {
+                    measurement: measurement.to_string(),
+                    time,
+                    tags,
+                    field_value: (value * 100.0).round() / 100.0,
+                    text_fields: BTreeMap::new(),
+                });
+            }
+        }
+        downsampled
+    }
+}
+
+/// Downsamples again the windows the records written fall in, from all their points
+pub struct DownsampleStage {
+    downsampler: Downsampler,
+    data_types: Vec<HealthDataType>,
+    windows: BTreeSet<DateTime<Utc>>,
+}
+
+impl DownsampleStage {
+    /// Creates the stage downsampling the records of `data_types` with `downsampler`
+    pub fn new(downsampler: Downsampler, data_types: &[HealthDataType]) -> Self {
+        DownsampleStage {
+            downsampler,
+            data_types: data_types.to_vec(),
+            windows: BTreeSet::new(),
+        }
+    }
+}
+
+impl DerivedStage for DownsampleStage {
+    fn name(&self) -> &str {
+        "downsampled"
+    }
+
+    fn inputs(&self) -> &[HealthDataType] {
+        &self.data_types
+    }
+
+    fn prepare(
+        &mut self,
+        _reader: &HealthDataReader,
+        written: &[HealthRecord],
+    ) -> Result<Option<ReadFrom>, Box<dyn Error>> {
+        let points: Vec<DataPoint> = written
+            .iter()
+            .filter(|record| self.data_types.contains(&record.record_type))
+            .map(health_record_to_point)
+            .collect();
+        self.windows = self.downsampler.windows(&points);
+        Ok(self
+            .windows
+            .first()
+            .map(|first| ReadFrom::Timestamp(*first - TimeDelta::milliseconds(1))))
+    }
+
+    fn compute(&self, records: &[HealthRecord]) -> DerivedPoints {
+        let points: Vec<DataPoint> = records
+            .iter()
+            .map(health_record_to_point)
+            .filter(|point| self.windows.contains(&self.downsampler.window(point.time)))
+            .collect();
+        DerivedPoints::Single(self.downsampler.downsample(&points))
+    }
+
+    fn describe(&self) -> String {
+        format!(" for {} windows", self.windows.len())
+    }
+}
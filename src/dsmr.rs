@@ -0,0 +1,322 @@
+use crate::influx_client::DataPoint;
+use crate::source::{RecordStream, Source};
+use crate::state_management::ImportState;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use csv::ReaderBuilder;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+
+/// Measurement electricity usage is written to, in kWh per interval
+pub const ELECTRICITY_MEASUREMENT: &str = "electricity";
+
+/// Measurement gas usage is written to, in m³ per interval
+pub const GAS_MEASUREMENT: &str = "gas";
+
+/// A cumulative register of a smart meter, as read from its P1 port
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Register {
+    /// Electricity delivered to the home on tariff 1 (OBIS 1.8.1), in kWh
+    DeliveredTariff1,
+    /// Electricity delivered to the home on tariff 2 (OBIS 1.8.2), in kWh
+    DeliveredTariff2,
+    /// Electricity returned to the grid on tariff 1 (OBIS 2.8.1), in kWh
+    ReturnedTariff1,
+    /// Electricity returned to the grid on tariff 2 (OBIS 2.8.2), in kWh
+    ReturnedTariff2,
+    /// Gas delivered (OBIS 24.2.1), in m³
+    Gas,
+}
+
+impl Register {
+    /// Every register, in the order they are written
+    pub const ALL: [Register; 5] = [
+        Register::DeliveredTariff1,
+        Register::DeliveredTariff2,
+        Register::ReturnedTariff1,
+        Register::ReturnedTariff2,
+        Register::Gas,
+    ];
+
+    /// Column names P1 loggers use for the register: the OBIS code, DSMR-reader's export
+    /// and ESPHome's sensor names
+    fn aliases(&self) -> &'static [&'static str] {
+        match self {
+            Register::DeliveredTariff1 => &[
+                "1.8.1",
+                "electricity_delivered_1",
+                "energy_delivered_tariff1",
+                "delivered_1",
+            ],
+            Register::DeliveredTariff2 => &[
+                "1.8.2",
+                "electricity_delivered_2",
+                "energy_delivered_tariff2",
+                "delivered_2",
+            ],
+            Register::ReturnedTariff1 => &[
+                "2.8.1",
+                "electricity_returned_1",
+                "energy_returned_tariff1",
+                "returned_1",
+            ],
+            Register::ReturnedTariff2 => &[
+                "2.8.2",
+                "electricity_returned_2",
+                "energy_returned_tariff2",
+                "returned_2",
+            ],
+            Register::Gas => &["24.2.1", "extra_device_delivered", "gas_delivered", "gas"],
+        }
+    }
+
+    /// Converts the usage of an interval to a point
+    fn to_point(self, time: DateTime<Utc>, usage: f64) -> DataPoint {
+        let (measurement, tags) = match self {
+            Register::Gas => (GAS_MEASUREMENT, vec![]),
+            Register::DeliveredTariff1 => (
+                ELECTRICITY_MEASUREMENT,
+                vec![("direction", "delivered"), ("tariff", "1")],
+            ),
+            Register::DeliveredTariff2 => (
+                ELECTRICITY_MEASUREMENT,
+                vec![("direction", "delivered"), ("tariff", "2")],
+            ),
+            Register::ReturnedTariff1 => (
+                ELECTRICITY_MEASUREMENT,
+                vec![("direction", "returned"), ("tariff", "1")],
+            ),
+            Register::ReturnedTariff2 => (
+                ELECTRICITY_MEASUREMENT,
+                vec![("direction", "returned"), ("tariff", "2")],
+            ),
+        };
+        DataPoint {
+            measurement: measurement.to_string(),
+            time,
+            tags: tags
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            field_value: usage,
+            text_fields: BTreeMap::new(),
+        }
+    }
+}
+
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.aliases()[1])
+    }
+}
+
+/// The registers of a meter at one moment
+#[derive(Debug, Clone, PartialEq)]
+pub struct DsmrReading {
+    pub timestamp: DateTime<Utc>,
+    pub registers: HashMap<Register, f64>,
+}
+
+/// What a meter counted between two consecutive readings
+#[derive(Debug, Clone, PartialEq)]
+pub struct DsmrInterval {
+    /// Time of the previous reading
+    pub start: DateTime<Utc>,
+    /// Time of this reading; the points are written at it
+    pub end: DateTime<Utc>,
+    /// Usage per register; registers that went backwards (a replaced meter) are left out
+    pub usage: Vec<(Register, f64)>,
+}
+
+/// Reads the CSV export of a DSMR P1 logger and turns its ever-growing registers into the
+/// usage of each interval between consecutive readings
+///
+/// Columns are found by name in the first row (see `Register` for the names known); the
+/// delimiter may be a comma or a semicolon, and values may carry a unit (`1234.567*kWh`)
+pub struct DsmrReader {
+    file_path: String,
+    time_column: String,
+    time_format: String,
+}
+
+/// Parses a register value, ignoring a unit after the number
+fn parse_value(text: &str) -> Option<f64> {
+    let number: String = text
+        .trim()
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || matches!(c, '.' | '-' | '+'))
+        .collect();
+    number.parse().ok()
+}
+
+impl DsmrReader {
+    /// Creates a reader for a P1 logger export, with its time in a `timestamp` column
+    pub fn new(file_path: &str) -> Self {
+        DsmrReader {
+            file_path: file_path.to_string(),
+            time_column: "timestamp".to_string(),
+            time_format: "%Y-%m-%d %H:%M:%S".to_string(),
+        }
+    }
+
+    /// Sets the column holding the time of each reading, and its format for times that are
+    /// not RFC 3339; those are taken as UTC
+    pub fn with_time_column(mut self, column: &str, format: &str) -> Self {
+        self.time_column = column.to_string();
+        self.time_format = format.to_string();
+        self
+    }
+
+    fn parse_time(&self, text: &str) -> Option<DateTime<Utc>> {
+        let text = text.trim();
+        DateTime::parse_from_rfc3339(text)
+            .map(|time| time.with_timezone(&Utc))
+            .ok()
+            .or_else(|| {
+                NaiveDateTime::parse_from_str(text, &self.time_format)
+                    .ok()
+                    .map(|naive| naive.and_utc())
+            })
+    }
+
+    /// Reads every reading in the file, oldest first; rows without a valid time are skipped
+    pub fn readings(&self) -> Result<Vec<DsmrReading>, Box<dyn Error>> {
+        let contents = fs::read_to_string(&self.file_path)
+            .map_err(|e| format!("Failed to read {}: {}", self.file_path, e))?;
+        let first_line = contents.lines().next().unwrap_or_default();
+        let delimiter = if first_line.contains(';') && !first_line.contains(',') {
+            b';'
+        } else {
+            b','
+        };
+
+        let mut reader = ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_reader(contents.as_bytes());
+        let headers: Vec<String> = reader
+            .headers()?
+            .iter()
+            .map(|header| header.trim().to_lowercase())
+            .collect();
+
+        let time_index = headers
+            .iter()
+            .position(|header| *header == self.time_column.to_lowercase())
+            .ok_or_else(|| format!("No time column '{}' in the header", self.time_column))?;
+        let columns: Vec<(Register, usize)> = Register::ALL
+            .iter()
+            .filter_map(|register| {
+                headers
+                    .iter()
+                    .position(|header| register.aliases().contains(&header.as_str()))
+                    .map(|index| (*register, index))
+            })
+            .collect();
+        if columns.is_empty() {
+            return Err(
+                "No meter register columns (e.g. electricity_delivered_1) in the header".into(),
+            );
+        }
+
+        let mut readings = Vec::new();
+        for row in reader.records() {
+            let row = row?;
+            let Some(timestamp) = row.get(time_index).and_then(|text| self.parse_time(text)) else {
+                continue;
+            };
+            let registers = columns
+                .iter()
+                .filter_map(|(register, index)| Some((*register, parse_value(row.get(*index)?)?)))
+                .collect();
+            readings.push(DsmrReading {
+                timestamp,
+                registers,
+            });
+        }
+        readings.sort_by_key(|reading| reading.timestamp);
+        Ok(readings)
+    }
+}
+
+/// Computes the usage between each pair of consecutive readings. A register missing from
+/// either reading, or lower in the later one because the meter was replaced, is left out
+pub fn intervals(readings: &[DsmrReading]) -> Vec<DsmrInterval> {
+    readings
+        .windows(2)
+        .filter(|pair| pair[1].timestamp > pair[0].timestamp)
+        .map(|pair| {
+            let (previous, current) = (&pair[0], &pair[1]);
+            let usage = Register::ALL
+                .iter()
+                .filter_map(|register| {
+                    let delta =
+                        current.registers.get(register)? - previous.registers.get(register)?;
+                    // Meters count in thousandths, so anything smaller is float noise
+                    let delta = (delta * 1e6).round() / 1e6;
+                    (delta >= 0.0).then_some((*register, delta))
+                })
+                .collect();
+            DsmrInterval {
+                start: previous.timestamp,
+                end: current.timestamp,
+                usage,
+            }
+        })
+        .collect()
+}
+
+impl Source for DsmrReader {
+    type Record = DsmrInterval;
+
+    fn validate(&self) -> Result<String, Box<dyn Error>> {
+        let readings = self.readings()?;
+        let registers: Vec<String> = Register::ALL
+            .iter()
+            .filter(|register| {
+                readings
+                    .iter()
+                    .any(|reading| reading.registers.contains_key(register))
+            })
+            .map(|register| register.to_string())
+            .collect();
+        Ok(format!(
+            "DSMR export: {} ({} readings; registers: {})",
+            self.file_path,
+            readings.len(),
+            registers.join(", ")
+        ))
+    }
+
+    /// Reads the intervals ending after the last imported timestamp; the first of them starts
+    /// at the last reading imported, so no usage is lost between runs
+    fn records_since(
+        &self,
+        watermark: &ImportState,
+    ) -> Result<RecordStream<DsmrInterval>, Box<dyn Error>> {
+        let all = intervals(&self.readings()?);
+        let total = all.len();
+        let new_intervals: Vec<DsmrInterval> = match watermark.last_imported_timestamp {
+            Some(last_imported) => all
+                .into_iter()
+                .filter(|interval| interval.end > last_imported)
+                .collect(),
+            None => all,
+        };
+        let already_imported = total - new_intervals.len();
+        Ok(RecordStream::new(new_intervals).with_already_imported(already_imported))
+    }
+
+    fn timestamp(&self, record: &DsmrInterval) -> Option<DateTime<Utc>> {
+        Some(record.end)
+    }
+
+    fn to_points(&self, record: &DsmrInterval) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+        Ok(record
+            .usage
+            .iter()
+            .map(|(register, usage)| register.to_point(record.end, *usage))
+            .collect())
+    }
+}
@@ -0,0 +1,456 @@
+use crate::bench;
+use crate::convert::{funds_record_to_points, health_record_to_point};
+use crate::csv_parser::CsvParser;
+use crate::extract::{self, ExtractFormat};
+use crate::health_data::{take_oldest, DataTypeSelector, HealthDataReader, ReadFrom};
+use crate::import::{EXIT_ERROR, EXIT_NOTHING_NEW, EXIT_SOURCE_ERROR};
+use crate::influx_client::DataPoint;
+use crate::inspect::SourceKind;
+use crate::output;
+use chrono::NaiveDate;
+use clap::Args;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+
+/// The options of `validate-csv`
+#[derive(Args)]
+pub struct ValidateCsvArgs {
+    /// The CSV file to validate
+    #[arg(short, long, env = "HDI_SOURCE")]
+    pub source: String,
+
+    /// Show detailed information about the CSV structure
+    #[arg(short, long, env = "HDI_DETAILS")]
+    pub details: bool,
+
+    /// Number of header rows in CSV file
+    #[arg(long, default_value = "1", env = "HDI_HEADER_ROWS")]
+    pub header_rows: usize,
+
+    /// Write a normalized copy of the file here: comma-separated, with trimmed headers
+    /// and values, and without the rows that cannot be imported, which are listed
+    #[arg(long, env = "HDI_FIX_OUTPUT")]
+    pub fix_output: Option<String>,
+}
+
+/// Prints the validation report of a CSV file, and writes a cleaned copy to `--fix-output`
+/// when given
+pub fn validate_csv(args: ValidateCsvArgs) {
+    let ValidateCsvArgs {
+        source,
+        details,
+        header_rows,
+        fix_output,
+    } = args;
+    println!("Validating CSV file: '{}'", source);
+    println!("  Header rows: {}", header_rows);
+
+    // Show information about the details flag
+    if details {
+        println!("Details mode: ON - Will show all CSV records");
+    } else {
+        println!("Details mode: OFF - Use --details flag to see full CSV content");
+    }
+
+    // Create parser with specified number of header rows
+    let parser = CsvParser::new(&source).with_header_rows(header_rows);
+
+    match parser.validate(details) {
+        Ok(report) => {
+            println!("{}", report);
+        }
+        Err(e) => {
+            eprintln!("Validation error: {}", e);
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    }
+
+    if let Some(fix_output) = fix_output {
+        let file = match File::create(&fix_output) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Failed to create '{}': {}", fix_output, e);
+                process::exit(EXIT_ERROR);
+            }
+        };
+        let report = match parser.clean(BufWriter::new(file)) {
+            Ok(report) => report,
+            Err(e) => {
+                eprintln!("Failed to write '{}': {}", fix_output, e);
+                process::exit(EXIT_SOURCE_ERROR);
+            }
+        };
+
+        let delimiter = match report.delimiter {
+            '\t' => "tab".to_string(),
+            delimiter => format!("'{}'", delimiter),
+        };
+        println!(
+            "{}",
+            output::success(&format!(
+                "Wrote the header and {} data rows to '{}' (read with {} as the delimiter)",
+                report.rows_written, fix_output, delimiter
+            ))
+        );
+        if !report.dropped.is_empty() {
+            println!(
+                "{}",
+                output::warning(&format!("Left out {} rows:", report.dropped.len()))
+            );
+            let rows: Vec<Vec<String>> = report
+                .dropped
+                .iter()
+                .map(|row| vec![row.line.to_string(), row.reason.clone()])
+                .collect();
+            println!("{}", output::table(&["Line", "Reason"], &rows));
+        }
+    }
+}
+
+/// The options of `preview`
+#[derive(Args)]
+pub struct PreviewArgs {
+    /// The funds CSV file or Health Connect SQLite export to convert
+    #[arg(short, long, env = "HDI_SOURCE")]
+    pub source: String,
+
+    /// Kind of source; detected from the file extension when omitted (.db, .sqlite and
+    /// .sqlite3 are health exports)
+    #[arg(long, value_enum, env = "HDI_KIND")]
+    pub kind: Option<SourceKind>,
+
+    /// Number of points to show
+    #[arg(short = 'n', long, default_value = "10", env = "HDI_LIMIT")]
+    pub limit: usize,
+
+    /// Timestamp column name in CSV
+    #[arg(long, default_value = "timestamp", env = "HDI_TIME_COLUMN")]
+    pub time_column: String,
+
+    /// Timestamp format (e.g., "YYYY-MM-DD HH:MM:SS")
+    #[arg(long, default_value = "%Y-%m-%d %H:%M:%S", env = "HDI_TIME_FORMAT")]
+    pub time_format: String,
+
+    /// Number of header rows in CSV file
+    #[arg(long, default_value = "1", env = "HDI_HEADER_ROWS")]
+    pub header_rows: usize,
+
+    /// Only preview specific health data types, or groups of them (comma-separated)
+    #[arg(long, value_delimiter = ',', env = "HDI_DATA_TYPES")]
+    pub data_types: Option<Vec<DataTypeSelector>>,
+}
+
+/// Prints the first points a source converts to, oldest first for health exports
+pub fn preview(args: PreviewArgs) {
+    let PreviewArgs {
+        source,
+        kind,
+        limit,
+        time_column,
+        time_format,
+        header_rows,
+        data_types,
+    } = args;
+    let mut points = Vec::new();
+
+    let total_records = match kind.unwrap_or_else(|| SourceKind::detect(&source)) {
+        SourceKind::Funds => {
+            let records = match CsvParser::new(&source)
+                .with_header_rows(header_rows)
+                .parse()
+            {
+                Ok(records) => records,
+                Err(e) => {
+                    eprintln!("Error parsing CSV: {}", e);
+                    process::exit(EXIT_SOURCE_ERROR);
+                }
+            };
+
+            // Records are converted in file order until there are enough points
+            for (index, record) in records.iter().enumerate() {
+                if points.len() >= limit {
+                    break;
+                }
+                match funds_record_to_points(record, &time_column, &time_format) {
+                    Ok(record_points) => points.extend(record_points),
+                    Err(e) => eprintln!(
+                        "{}",
+                        output::warning(&format!("Record {}: {}", index + 1, e))
+                    ),
+                }
+            }
+            records.len()
+        }
+        SourceKind::Health => {
+            let reader = HealthDataReader::new(&source);
+            let records_map = match reader.get_health_data_since_per_type(
+                |_| ReadFrom::Beginning,
+                data_types
+                    .map(|names| DataTypeSelector::expand(&names))
+                    .as_deref(),
+            ) {
+                Ok(records_map) => records_map,
+                Err(e) => {
+                    eprintln!("Error retrieving health data: {}", e);
+                    process::exit(EXIT_SOURCE_ERROR);
+                }
+            };
+            let total_records = records_map.values().map(|v| v.len()).sum();
+
+            // The oldest records, across data types, are the first ones imported
+            let (oldest, _) = take_oldest(records_map, limit);
+            points.extend(oldest.values().flatten().map(health_record_to_point));
+            points.sort_by(|a, b| {
+                a.time
+                    .cmp(&b.time)
+                    .then_with(|| a.measurement.cmp(&b.measurement))
+            });
+            total_records
+        }
+    };
+    points.truncate(limit);
+
+    println!(
+        "Showing the first {} points converted from '{}' ({} records in total)",
+        points.len(),
+        source,
+        total_records
+    );
+    for (index, point) in points.iter().enumerate() {
+        let mut tags: Vec<String> = point
+            .tags
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        tags.sort();
+
+        println!();
+        println!("Point {}:", index + 1);
+        println!("  Measurement: {}", point.measurement);
+        println!("  Time: {}", point.time);
+        println!("  Tags: {}", tags.join(", "));
+        println!("  Fields: value={}", point.field_value);
+    }
+}
+
+/// The options of `bench`
+#[derive(Args)]
+pub struct BenchArgs {
+    /// The funds CSV file or Health Connect SQLite export to measure
+    #[arg(short, long, env = "HDI_SOURCE")]
+    pub source: String,
+
+    /// Kind of source; detected from the file extension when omitted (.db, .sqlite and
+    /// .sqlite3 are health exports)
+    #[arg(long, value_enum, env = "HDI_KIND")]
+    pub kind: Option<SourceKind>,
+
+    /// Batch sizes to run the pipeline with (comma-separated)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_value = "1000",
+        env = "HDI_BATCH_SIZE"
+    )]
+    pub batch_size: Vec<usize>,
+
+    /// Timestamp column name in CSV
+    #[arg(long, default_value = "timestamp", env = "HDI_TIME_COLUMN")]
+    pub time_column: String,
+
+    /// Timestamp format (e.g., "YYYY-MM-DD HH:MM:SS")
+    #[arg(long, default_value = "%Y-%m-%d %H:%M:%S", env = "HDI_TIME_FORMAT")]
+    pub time_format: String,
+
+    /// Number of header rows in CSV file
+    #[arg(long, default_value = "1", env = "HDI_HEADER_ROWS")]
+    pub header_rows: usize,
+
+    /// Only measure specific health data types, or groups of them (comma-separated)
+    #[arg(long, value_delimiter = ',', env = "HDI_DATA_TYPES")]
+    pub data_types: Option<Vec<DataTypeSelector>>,
+}
+
+/// Prints how long each stage of the pipeline takes on a source, for each batch size
+pub async fn bench(args: BenchArgs) {
+    let BenchArgs {
+        source,
+        kind,
+        batch_size,
+        time_column,
+        time_format,
+        header_rows,
+        data_types,
+    } = args;
+    let results = match kind.unwrap_or_else(|| SourceKind::detect(&source)) {
+        SourceKind::Funds => {
+            let parser = CsvParser::new(&source)
+                .with_header_rows(header_rows)
+                .with_time_column(&time_column, &time_format);
+            bench::run(Arc::new(parser), &batch_size).await
+        }
+        SourceKind::Health => {
+            let reader = HealthDataReader::new(&source)
+                .with_data_types(data_types.map(|names| DataTypeSelector::expand(&names)));
+            bench::run(Arc::new(reader), &batch_size).await
+        }
+    };
+    let results = match results {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("Error reading {}: {}", source, e);
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    };
+
+    let rows: Vec<Vec<String>> = results
+        .iter()
+        .map(|result| {
+            vec![
+                result.stage.clone(),
+                result.items.to_string(),
+                format!("{:.3}", result.elapsed.as_secs_f64()),
+                format!("{:.0}", result.per_second()),
+            ]
+        })
+        .collect();
+    println!("Benchmark of '{}'", source);
+    println!(
+        "{}",
+        output::table(&["Stage", "Items", "Seconds", "Items/sec"], &rows)
+    );
+}
+
+/// The options of `health-stats`
+#[derive(Args)]
+pub struct HealthStatsArgs {
+    /// The SQLite database file to summarize
+    #[arg(short, long, env = "HDI_SOURCE")]
+    pub source: String,
+}
+
+/// Prints the records, time range, daily rate and apps of each table of a Health Connect
+/// export
+pub fn health_stats(args: HealthStatsArgs) {
+    let HealthStatsArgs { source } = args;
+    let reader = HealthDataReader::new(&source);
+    let all_stats = match reader.table_stats() {
+        Ok(all_stats) => all_stats,
+        Err(e) => {
+            eprintln!("Failed to read {}: {}", source, e);
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    };
+
+    println!("Health Connect export: {}", source);
+    for stats in all_stats {
+        println!();
+        println!("{} ({})", stats.data_type, stats.table);
+        if !stats.exists {
+            println!("  Not in this export");
+            continue;
+        }
+        println!("  Records: {}", stats.records);
+        if let (Some(earliest), Some(latest)) = (stats.earliest, stats.latest) {
+            println!("  From: {}", earliest);
+            println!("  To: {}", latest);
+        }
+        if let Some(rate) = stats.records_per_day() {
+            println!("  Daily rate: ~{:.1} records/day", rate);
+        }
+        if !stats.apps.is_empty() {
+            println!("  Apps: {}", stats.apps.join(", "));
+        }
+    }
+}
+
+/// The options of `extract`
+#[derive(Args)]
+pub struct ExtractArgs {
+    /// The SQLite database file to extract from
+    #[arg(short, long, env = "HDI_SOURCE")]
+    pub source: String,
+
+    /// Data types or groups of them to extract (comma-separated); all types when omitted
+    #[arg(long, value_delimiter = ',', env = "HDI_DATA_TYPES")]
+    pub data_types: Option<Vec<DataTypeSelector>>,
+
+    /// First day to extract (YYYY-MM-DD, UTC); extracts everything when omitted
+    #[arg(long, env = "HDI_FROM")]
+    pub from: Option<NaiveDate>,
+
+    /// File format
+    #[arg(long, value_enum, default_value_t = ExtractFormat::Csv, env = "HDI_FORMAT")]
+    pub format: ExtractFormat,
+
+    /// Directory the files are written to, named after their measurement
+    #[arg(long, default_value = ".", env = "HDI_OUTPUT_DIR")]
+    pub output_dir: String,
+}
+
+/// Writes the points of a Health Connect export to one file per measurement
+pub fn extract(args: ExtractArgs) {
+    let ExtractArgs {
+        source,
+        data_types,
+        from,
+        format,
+        output_dir,
+    } = args;
+    // Reads are exclusive of their starting point, so start just before the first day
+    let since = match from {
+        Some(from) => ReadFrom::Timestamp(
+            from.and_time(chrono::NaiveTime::MIN).and_utc() - chrono::Duration::milliseconds(1),
+        ),
+        None => ReadFrom::Beginning,
+    };
+    let reader = HealthDataReader::new(&source);
+    let records_map = match reader.get_health_data_since_per_type(
+        |_| since,
+        data_types
+            .map(|names| DataTypeSelector::expand(&names))
+            .as_deref(),
+    ) {
+        Ok(records_map) => records_map,
+        Err(e) => {
+            eprintln!("Error retrieving health data: {}", e);
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    };
+    let points: Vec<DataPoint> = records_map
+        .values()
+        .flatten()
+        .map(health_record_to_point)
+        .collect();
+    if points.is_empty() {
+        progress!("No records to extract");
+        process::exit(EXIT_NOTHING_NEW);
+    }
+
+    if let Err(e) = std::fs::create_dir_all(&output_dir) {
+        eprintln!("Failed to create {}: {}", output_dir, e);
+        process::exit(EXIT_ERROR);
+    }
+    for (measurement, mut points) in extract::by_measurement(points) {
+        points.sort_by_key(|point| point.time);
+        let path = Path::new(&output_dir).join(format!("{}.{}", measurement, format.extension()));
+        let written = File::create(&path)
+            .map_err(|e| e.into())
+            .and_then(|file| extract::write_points(&points, format, BufWriter::new(file)));
+        match written {
+            Ok(()) => progress!(
+                "{}: {} points to {}",
+                measurement,
+                points.len(),
+                path.display()
+            ),
+            Err(e) => {
+                eprintln!("Failed to write {}: {}", path.display(), e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::influx_client::DataPoint;
+use chrono::SecondsFormat;
+use clap::ValueEnum;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use std::io::Write;
+
+/// File formats records can be extracted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExtractFormat {
+    /// One row per point, with a column per tag
+    Csv,
+    /// One JSON object per line
+    Ndjson,
+}
+
+impl ExtractFormat {
+    /// Gets the extension of files in this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExtractFormat::Csv => "csv",
+            ExtractFormat::Ndjson => "ndjson",
+        }
+    }
+}
+
+impl fmt::Display for ExtractFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.extension())
+    }
+}
+
+/// Groups points by measurement, each of which is extracted to a file of its own
+pub fn by_measurement(points: Vec<DataPoint>) -> BTreeMap<String, Vec<DataPoint>> {
+    let mut measurements: BTreeMap<String, Vec<DataPoint>> = BTreeMap::new();
+    for point in points {
+        measurements
+            .entry(point.measurement.clone())
+            .or_default()
+            .push(point);
+    }
+    measurements
+}
+
+/// Writes points as they would be written to InfluxDB: their time (RFC 3339, UTC),
+/// measurement, value and tags
+///
+/// CSV files have a column for every tag of any point, in name order, left empty for the
+/// points without it
+pub fn write_points<W: Write>(
+    points: &[DataPoint],
+    format: ExtractFormat,
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ExtractFormat::Csv => {
+            let tags: BTreeSet<&String> =
+                points.iter().flat_map(|point| point.tags.keys()).collect();
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            let mut header = vec!["time", "measurement", "value"];
+            header.extend(tags.iter().map(|tag| tag.as_str()));
+            csv_writer.write_record(&header)?;
+            for point in points {
+                let mut row = vec![
+                    point.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+                    point.measurement.clone(),
+                    point.field_value.to_string(),
+                ];
+                row.extend(
+                    tags.iter()
+                        .map(|tag| point.tags.get(*tag).cloned().unwrap_or_default()),
+                );
+                csv_writer.write_record(&row)?;
+            }
+            csv_writer.flush()?;
+        }
+        ExtractFormat::Ndjson => {
+            for point in points {
+                let tags: BTreeMap<&String, &String> = point.tags.iter().collect();
+                let line = serde_json::json!({
+                    "time": point.time.to_rfc3339_opts(SecondsFormat::Millis, true),
+                    "measurement": point.measurement,
+                    "value": point.field_value,
+                    "tags": tags,
+                });
+                writeln!(writer, "{}", line)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use crate::convert::{FundPoint, CURRENCY_SYMBOLS, DEFAULT_FUND_TAG_KEY};
+use crate::influx_client::DataPoint;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::time::Duration;
+
+/// Currency fund values are normalized to unless configured otherwise
+pub const DEFAULT_BASE_CURRENCY: &str = "EUR";
+
+/// API daily reference rates are fetched from unless configured otherwise: Frankfurter,
+/// which serves the ECB's rates
+pub const DEFAULT_FX_API_URL: &str = "https://api.frankfurter.app";
+
+/// Exchange rates from other currencies to a base currency: fixed ones, or the daily
+/// reference rates published by a central bank
+#[derive(Debug, Clone, PartialEq)]
+pub struct FxRates {
+    base: String,
+    /// Units of the base currency per unit of a currency
+    fixed: HashMap<String, f64>,
+    daily: HashMap<String, BTreeMap<NaiveDate, f64>>,
+}
+
+impl FxRates {
+    /// Creates an empty set of rates to `base`
+    pub fn new(base: &str) -> Self {
+        FxRates {
+            base: base.to_uppercase(),
+            fixed: HashMap::new(),
+            daily: HashMap::new(),
+        }
+    }
+
+    /// Gets the currency the rates convert to
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    /// Sets a rate used whatever the date, in units of the base currency per unit of
+    /// `currency`
+    pub fn with_rate(mut self, currency: &str, rate: f64) -> Self {
+        self.fixed.insert(currency.to_uppercase(), rate);
+        self
+    }
+
+    /// Adds the rates of `currency` by the day they were published
+    pub fn add_daily_rates(&mut self, currency: &str, rates: BTreeMap<NaiveDate, f64>) {
+        self.daily
+            .entry(currency.to_uppercase())
+            .or_default()
+            .extend(rates);
+    }
+
+    /// Gets the rate of `currency` at `time`: the last daily rate published on or before
+    /// its day, since none are published on weekends and holidays, or else the fixed rate
+    pub fn rate(&self, currency: &str, time: DateTime<Utc>) -> Option<f64> {
+        let currency = currency.to_uppercase();
+        if currency == self.base {
+            return Some(1.0);
+        }
+        self.daily
+            .get(&currency)
+            .and_then(|rates| rates.range(..=time.date_naive()).next_back())
+            .map(|(_, rate)| *rate)
+            .or_else(|| self.fixed.get(&currency).copied())
+    }
+
+    /// Fetches the daily rates of `currencies` from `start` to `end` from a Frankfurter
+    /// compatible API at `api_url`
+    pub async fn fetch(
+        &mut self,
+        api_url: &str,
+        currencies: &BTreeSet<String>,
+        start: NaiveDate,
+        end: NaiveDate,
+        timeout: Option<Duration>,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        let client = builder.build()?;
+
+        let url = format!("{}/{}..{}", api_url.trim_end_matches('/'), start, end);
+        for currency in currencies {
+            let response = client
+                .get(&url)
+                .query(&[("from", currency.as_str()), ("to", self.base.as_str())])
+                .send()
+                .await
+                .map_err(|e| format!("Failed to fetch {} rates from {}: {}", currency, url, e))?;
+            let status = response.status();
+            let body = response.text().await?;
+            if !status.is_success() {
+                return Err(format!("{} answered {}: {}", url, status, body.trim()).into());
+            }
+            let rates = parse_frankfurter(&body, &self.base)?;
+            self.add_daily_rates(currency, rates);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct FrankfurterResponse {
+    rates: BTreeMap<NaiveDate, HashMap<String, f64>>,
+}
+
+/// Parses a time series response of the Frankfurter API: the rate to `base` on each day
+pub fn parse_frankfurter(
+    body: &str,
+    base: &str,
+) -> Result<BTreeMap<NaiveDate, f64>, Box<dyn Error>> {
+    let response: FrankfurterResponse = serde_json::from_str(body)
+        .map_err(|e| format!("Unexpected exchange rate response: {}", e))?;
+    Ok(response
+        .rates
+        .into_iter()
+        .filter_map(|(day, rates)| Some((day, *rates.get(base)?)))
+        .collect())
+}
+
+/// Writes fund values in the base currency too: each point gets a copy in a measurement
+/// named after the base currency (`price_eur` for `price`), converted at the rate of its
+/// day and tagged with the `currency` it was in
+///
+/// A point's currency is its `currency` tag, or the one configured for its fund, or else
+/// the one its value was written in (`$1,234.5`); values with none are taken to be in the
+/// base currency
+#[derive(Debug, Clone)]
+pub struct FxConversion {
+    rates: FxRates,
+    /// Currencies by fund tag
+    fund_currencies: HashMap<String, String>,
+    tag_key: String,
+}
+
+impl FxConversion {
+    /// Creates a conversion using `rates`
+    pub fn new(rates: FxRates) -> Self {
+        FxConversion {
+            rates,
+            fund_currencies: HashMap::new(),
+            tag_key: DEFAULT_FUND_TAG_KEY.to_string(),
+        }
+    }
+
+    /// Sets the key of the tag funds are told apart by (`fondo` by default)
+    pub fn with_tag_key(mut self, tag_key: &str) -> Self {
+        self.tag_key = tag_key.to_string();
+        self
+    }
+
+    /// Sets the currency of a fund, by its fund tag
+    pub fn with_fund_currency(mut self, fund: &str, currency: &str) -> Self {
+        self.fund_currencies
+            .insert(fund.to_string(), currency.to_uppercase());
+        self
+    }
+
+    /// Gets the rates used
+    pub fn rates(&self) -> &FxRates {
+        &self.rates
+    }
+
+    /// Gets the rates, to add the daily ones once fetched
+    pub fn rates_mut(&mut self) -> &mut FxRates {
+        &mut self.rates
+    }
+
+    /// Gets the currencies that may need converting: the funds' and those recognized in
+    /// written values, other than the base currency
+    pub fn currencies(&self) -> BTreeSet<String> {
+        self.fund_currencies
+            .values()
+            .cloned()
+            .chain(
+                CURRENCY_SYMBOLS
+                    .iter()
+                    .map(|(_, currency)| currency.to_string()),
+            )
+            .filter(|currency| *currency != self.rates.base)
+            .collect()
+    }
+
+    /// Gets the measurement the base currency copy of a measurement is written to
+    pub fn measurement(&self, measurement: &str) -> String {
+        format!("{}_{}", measurement, self.rates.base.to_lowercase())
+    }
+
+    /// Adds the base currency copy of each point; `written_in` is the currency its value
+    /// was written in, when it showed one
+    pub fn add_normalized(&self, points: Vec<FundPoint>) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+        let mut converted = Vec::with_capacity(points.len() * 2);
+        for (point, written_in) in points {
+            let currency = point
+                .tags
+                .get("currency")
+                .or_else(|| {
+                    let fund = point.tags.get(&self.tag_key)?;
+                    self.fund_currencies.get(fund)
+                })
+                .map(String::as_str)
+                .or(written_in)
+                .unwrap_or(&self.rates.base)
+                .to_uppercase();
+            let rate = self.rates.rate(&currency, point.time).ok_or_else(|| {
+                format!(
+                    "No {} to {} rate for {}",
+                    currency,
+                    self.rates.base,
+                    point.time.date_naive()
+                )
+            })?;
+
+            let mut tags = point.tags.clone();
+            tags.insert("currency".to_string(), currency);
+            let normalized = DataPoint {
+                measurement: self.measurement(&point.measurement),
+                time: point.time,
+                tags,
+                // Rates have six significant digits at most
+                field_value: (point.field_value * rate * 1e6).round() / 1e6,
+                text_fields: BTreeMap::new(),
+            };
+            converted.push(point);
+            converted.push(normalized);
+        }
+        Ok(converted)
+    }
+}
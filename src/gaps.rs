@@ -0,0 +1,177 @@
+use crate::extract::ExtractFormat;
+use crate::health_data::{gap_ranges, HealthDataType, HealthRecord};
+use crate::influx_client::InfluxClient;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::io::Write;
+use std::time::Duration;
+
+/// A run of records of one data type, consecutive in time, that are all missing from
+/// InfluxDB
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gap {
+    pub data_type: HealthDataType,
+    /// Time of the first missing record
+    pub start: DateTime<Utc>,
+    /// Time of the last missing record
+    pub end: DateTime<Utc>,
+    /// Number of records missing, counting those sharing a timestamp once
+    pub missing: usize,
+}
+
+/// Gets the start of the window of `window` (counted from the Unix epoch) a time falls in
+pub fn window_start(time: DateTime<Utc>, window: Duration) -> DateTime<Utc> {
+    let window_millis = window.as_secs().max(1) as i64 * 1000;
+    let start = time.timestamp_millis().div_euclid(window_millis) * window_millis;
+    DateTime::from_timestamp_millis(start).unwrap_or(time)
+}
+
+/// Counts records per window of `window`, by the start of each window, the way
+/// `InfluxClient::count_points_per_window` counts the points they are written as
+pub fn count_per_window(
+    records: &[HealthRecord],
+    window: Duration,
+) -> BTreeMap<DateTime<Utc>, u64> {
+    let mut counts = BTreeMap::new();
+    for record in records {
+        *counts
+            .entry(window_start(record.timestamp, window))
+            .or_default() += 1;
+    }
+    counts
+}
+
+/// What InfluxDB holds of a data type: the time ranges whose points were fetched, as
+/// their counts differ from the source (see `health_data::gap_ranges`), and the timestamps
+/// (Unix milliseconds) of those points. Records outside the fetched ranges are taken to be
+/// present, as their counts agree
+#[derive(Debug, Clone, Default)]
+pub struct Presence {
+    checked: Vec<(DateTime<Utc>, DateTime<Utc>)>,
+    existing: HashSet<i64>,
+}
+
+impl Presence {
+    pub fn new(checked: Vec<(DateTime<Utc>, DateTime<Utc>)>, existing: HashSet<i64>) -> Self {
+        Self { checked, existing }
+    }
+
+    /// Queries InfluxDB for the points of `data_type` from `start_time` (inclusive) to
+    /// `end_time` (exclusive), given the records of that type in the source: their counts
+    /// per `window` first, then the timestamps in the windows whose counts differ
+    pub async fn query(
+        influx_client: &InfluxClient,
+        data_type: HealthDataType,
+        records: &[HealthRecord],
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        window: Duration,
+    ) -> Result<Self, Box<dyn Error>> {
+        let source_counts = count_per_window(records, window);
+        let sink_counts = influx_client
+            .count_points_per_window(data_type.as_str(), start_time, end_time, window)
+            .await?;
+        let checked = gap_ranges(&source_counts, &sink_counts, window);
+        if checked.is_empty() {
+            return Ok(Self::default());
+        }
+        let existing = influx_client
+            .get_existing_timestamps_in(data_type.as_str(), &checked)
+            .await?;
+        Ok(Self { checked, existing })
+    }
+
+    /// Whether InfluxDB is missing the point of a record taken at `time`
+    pub fn is_missing(&self, time: DateTime<Utc>) -> bool {
+        self.checked
+            .iter()
+            .any(|(start, end)| *start <= time && time < *end)
+            && !self.existing.contains(&time.timestamp_millis())
+    }
+}
+
+/// Finds the gaps among the records of a data type, in any order: the records InfluxDB
+/// is missing, grouped into runs that no record present in InfluxDB interrupts
+pub fn find_gaps(
+    data_type: HealthDataType,
+    records: &[HealthRecord],
+    presence: &Presence,
+) -> Vec<Gap> {
+    let mut times: Vec<DateTime<Utc>> = records
+        .iter()
+        .filter(|record| record.record_type == data_type)
+        .map(|record| record.timestamp)
+        .collect();
+    times.sort();
+    times.dedup();
+
+    let mut gaps: Vec<Gap> = Vec::new();
+    let mut in_gap = false;
+    for time in times {
+        if !presence.is_missing(time) {
+            in_gap = false;
+            continue;
+        }
+        match gaps.last_mut() {
+            Some(gap) if in_gap => {
+                gap.end = time;
+                gap.missing += 1;
+            }
+            _ => gaps.push(Gap {
+                data_type,
+                start: time,
+                end: time,
+                missing: 1,
+            }),
+        }
+        in_gap = true;
+    }
+    gaps
+}
+
+/// Keeps the records InfluxDB is missing
+pub fn missing_records(records: Vec<HealthRecord>, presence: &Presence) -> Vec<HealthRecord> {
+    records
+        .into_iter()
+        .filter(|record| presence.is_missing(record.timestamp))
+        .collect()
+}
+
+/// Writes gaps as CSV (a `data_type`, `start`, `end` and `missing` column) or NDJSON (an
+/// object with the same keys per line), with times in RFC 3339, UTC
+pub fn write_gaps<W: Write>(
+    gaps: &[Gap],
+    format: ExtractFormat,
+    mut writer: W,
+) -> Result<(), Box<dyn Error>> {
+    let time = |time: DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::Millis, true);
+    match format {
+        ExtractFormat::Csv => {
+            let mut csv_writer = csv::Writer::from_writer(writer);
+            csv_writer.write_record(["data_type", "start", "end", "missing"])?;
+            for gap in gaps {
+                csv_writer.write_record([
+                    gap.data_type.as_str().to_string(),
+                    time(gap.start),
+                    time(gap.end),
+                    gap.missing.to_string(),
+                ])?;
+            }
+            csv_writer.flush()?;
+        }
+        ExtractFormat::Ndjson => {
+            for gap in gaps {
+                let line = serde_json::json!({
+                    "data_type": gap.data_type.as_str(),
+                    "start": time(gap.start),
+                    "end": time(gap.end),
+                    "missing": gap.missing,
+                });
+                writeln!(writer, "{}", line)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,320 @@
+use crate::health_data::{HealthDataType, HealthRecord};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fmt;
+use std::time::Duration as Timeout;
+
+/// Tag every annotation pushed by the importer carries, so dashboards can query them
+pub const ANNOTATION_TAG: &str = "home-db-importer";
+
+/// Sessions that can be pushed as annotations
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+pub enum AnnotatedSession {
+    /// Exercise sessions, with their title, type and duration
+    Exercise,
+    /// Sleep sessions, with their duration
+    Sleep,
+}
+
+impl fmt::Display for AnnotatedSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnnotatedSession::Exercise => write!(f, "exercise"),
+            AnnotatedSession::Sleep => write!(f, "sleep"),
+        }
+    }
+}
+
+/// Names of the Health Connect exercise types, by code
+const EXERCISE_TYPES: [(i64, &str); 56] = [
+    (0, "Workout"),
+    (2, "Badminton"),
+    (4, "Baseball"),
+    (5, "Basketball"),
+    (8, "Biking"),
+    (9, "Stationary biking"),
+    (10, "Boot camp"),
+    (11, "Boxing"),
+    (13, "Calisthenics"),
+    (14, "Cricket"),
+    (16, "Dancing"),
+    (25, "Elliptical"),
+    (26, "Exercise class"),
+    (27, "Fencing"),
+    (28, "American football"),
+    (29, "Australian football"),
+    (31, "Frisbee"),
+    (32, "Golf"),
+    (33, "Guided breathing"),
+    (34, "Gymnastics"),
+    (35, "Handball"),
+    (36, "HIIT"),
+    (37, "Hiking"),
+    (38, "Ice hockey"),
+    (39, "Ice skating"),
+    (44, "Martial arts"),
+    (46, "Paddling"),
+    (47, "Paragliding"),
+    (48, "Pilates"),
+    (50, "Racquetball"),
+    (51, "Rock climbing"),
+    (52, "Roller hockey"),
+    (53, "Rowing"),
+    (54, "Rowing machine"),
+    (55, "Rugby"),
+    (56, "Running"),
+    (57, "Treadmill running"),
+    (58, "Sailing"),
+    (59, "Scuba diving"),
+    (60, "Skating"),
+    (61, "Skiing"),
+    (62, "Snowboarding"),
+    (63, "Snowshoeing"),
+    (64, "Soccer"),
+    (65, "Softball"),
+    (66, "Squash"),
+    (68, "Stair climbing"),
+    (70, "Strength training"),
+    (71, "Stretching"),
+    (72, "Surfing"),
+    (73, "Open water swimming"),
+    (74, "Pool swimming"),
+    (75, "Table tennis"),
+    (76, "Tennis"),
+    (79, "Walking"),
+    (83, "Yoga"),
+];
+
+/// Gets the name of a Health Connect exercise type
+pub fn exercise_type_name(code: i64) -> Option<&'static str> {
+    EXERCISE_TYPES
+        .iter()
+        .find(|(known, _)| *known == code)
+        .map(|(_, name)| *name)
+}
+
+/// A span of time marked on Grafana dashboards
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Annotation {
+    /// Start, in Unix milliseconds
+    pub time: i64,
+    /// End, in Unix milliseconds
+    pub time_end: i64,
+    pub text: String,
+    pub tags: Vec<String>,
+}
+
+impl Annotation {
+    fn new(start: DateTime<Utc>, end: DateTime<Utc>, text: String, tags: Vec<String>) -> Self {
+        Annotation {
+            time: start.timestamp_millis(),
+            time_end: end.timestamp_millis().max(start.timestamp_millis()),
+            text,
+            tags,
+        }
+    }
+}
+
+/// Formats a number of minutes as `1 h 5 min`
+fn format_minutes(minutes: f64) -> String {
+    let minutes = minutes.round() as i64;
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{} min", minutes),
+        (hours, 0) => format!("{} h", hours),
+        (hours, minutes) => format!("{} h {} min", hours, minutes),
+    }
+}
+
+/// Turns the exercise and sleep sessions among `records` into annotations, one per
+/// session, for the kinds of session in `sessions`
+///
+/// Sleep is read as several records per session, so sessions are told apart by their row
+pub fn session_annotations(
+    records: &[HealthRecord],
+    sessions: &[AnnotatedSession],
+) -> Vec<Annotation> {
+    let mut annotations = Vec::new();
+    let mut sleep_rows = HashSet::new();
+    for record in records {
+        let minutes = record
+            .metadata
+            .get("duration_minutes")
+            .and_then(|minutes| minutes.parse::<f64>().ok())
+            .unwrap_or(record.value);
+        match record.record_type {
+            HealthDataType::ExerciseSession if sessions.contains(&AnnotatedSession::Exercise) => {
+                let code = record
+                    .metadata
+                    .get("exercise_type")
+                    .and_then(|code| code.parse().ok());
+                let kind = code.and_then(exercise_type_name).unwrap_or("Exercise");
+                let end = record
+                    .metadata
+                    .get("end_time_millis")
+                    .and_then(|millis| millis.parse().ok())
+                    .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+                    .unwrap_or(record.timestamp + Duration::seconds((minutes * 60.0) as i64));
+                let title = record
+                    .metadata
+                    .get("title")
+                    .map(|title| title.trim())
+                    .filter(|title| !title.is_empty() && *title != "Unknown");
+                let text = match title {
+                    Some(title) => format!("{}: {} ({})", kind, title, format_minutes(minutes)),
+                    None => format!("{} ({})", kind, format_minutes(minutes)),
+                };
+                let tags = vec![
+                    ANNOTATION_TAG.to_string(),
+                    "exercise".to_string(),
+                    kind.to_lowercase(),
+                ];
+                annotations.push(Annotation::new(record.timestamp, end, text, tags));
+            }
+            HealthDataType::Sleep if sessions.contains(&AnnotatedSession::Sleep) => {
+                let is_start =
+                    record.metadata.get("event_type").map(String::as_str) == Some("start");
+                if !is_start
+                    || !sleep_rows
+                        .insert(record.row_id.unwrap_or(record.timestamp.timestamp_millis()))
+                {
+                    continue;
+                }
+                let end = record.timestamp + Duration::seconds((minutes * 60.0) as i64);
+                let text = format!("Sleep ({})", format_minutes(minutes));
+                let tags = vec![ANNOTATION_TAG.to_string(), "sleep".to_string()];
+                annotations.push(Annotation::new(record.timestamp, end, text, tags));
+            }
+            _ => {}
+        }
+    }
+    annotations
+}
+
+/// An annotation as Grafana returns it
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExistingAnnotation {
+    time: i64,
+    #[serde(default)]
+    text: String,
+}
+
+/// Pushes annotations to Grafana's HTTP API, as organization annotations or on one dashboard
+#[derive(Debug, Clone)]
+pub struct GrafanaAnnotator {
+    url: String,
+    token: String,
+    dashboard_uid: Option<String>,
+    tags: Vec<String>,
+    timeout: Option<Timeout>,
+}
+
+impl GrafanaAnnotator {
+    /// Creates an annotator for the Grafana at `url`, authenticating with a service account
+    /// token
+    pub fn new(url: &str, token: &str) -> Self {
+        GrafanaAnnotator {
+            url: url.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            dashboard_uid: None,
+            tags: Vec::new(),
+            timeout: None,
+        }
+    }
+
+    /// Only shows the annotations on the dashboard with `uid`
+    pub fn with_dashboard(mut self, uid: &str) -> Self {
+        self.dashboard_uid = Some(uid.to_string());
+        self
+    }
+
+    /// Adds `tags` to every annotation
+    pub fn with_tags(mut self, tags: Vec<String>) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// Sets how long to wait for each request
+    pub fn with_timeout(mut self, timeout: Option<Timeout>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    fn client(&self) -> Result<reqwest::Client, Box<dyn Error>> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Pushes `annotations`, skipping those Grafana already has at the same time with the
+    /// same text, so importing a session again does not mark it twice. Returns how many
+    /// were pushed
+    pub async fn push(&self, annotations: &[Annotation]) -> Result<usize, Box<dyn Error>> {
+        let (Some(from), Some(to)) = (
+            annotations.iter().map(|a| a.time).min(),
+            annotations.iter().map(|a| a.time).max(),
+        ) else {
+            return Ok(0);
+        };
+        let client = self.client()?;
+        let url = format!("{}/api/annotations", self.url);
+
+        let response = client
+            .get(&url)
+            .bearer_auth(&self.token)
+            .query(&[
+                ("from", from.to_string()),
+                ("to", to.to_string()),
+                ("tags", ANNOTATION_TAG.to_string()),
+                ("limit", "10000".to_string()),
+            ])
+            .send()
+            .await
+            .map_err(|e| format!("Failed to read the annotations from {}: {}", url, e))?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(format!("{} answered {}: {}", url, status, body.trim()).into());
+        }
+        let existing: Vec<ExistingAnnotation> = serde_json::from_str(&body)
+            .map_err(|e| format!("Unexpected annotations response: {}", e))?;
+        let existing: HashSet<(i64, &str)> = existing
+            .iter()
+            .map(|annotation| (annotation.time, annotation.text.as_str()))
+            .collect();
+
+        let mut pushed = 0;
+        for annotation in annotations {
+            if existing.contains(&(annotation.time, annotation.text.as_str())) {
+                continue;
+            }
+            let mut annotation = annotation.clone();
+            annotation.tags.extend(self.tags.iter().cloned());
+            let mut body = serde_json::to_value(&annotation)?;
+            if let Some(uid) = &self.dashboard_uid {
+                body["dashboardUID"] = uid.clone().into();
+            }
+            let response = client
+                .post(&url)
+                .bearer_auth(&self.token)
+                .header("Content-Type", "application/json")
+                .body(body.to_string())
+                .send()
+                .await
+                .map_err(|e| format!("Failed to push an annotation to {}: {}", url, e))?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                return Err(format!("{} answered {}: {}", url, status, body.trim()).into());
+            }
+            pushed += 1;
+        }
+        Ok(pushed)
+    }
+}
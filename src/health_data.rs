@@ -1,894 +1,1321 @@
-use chrono::{DateTime, TimeZone, Utc};
-use rusqlite::{Connection, Result as SqliteResult, Row};
-use std::collections::HashMap;
+use crate::convert::health_record_to_point;
+use crate::influx_client::DataPoint;
+use crate::output;
+use crate::source::{RecordStream, Source};
+use crate::state_management::ImportState;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use clap::ValueEnum;
+use rusqlite::{Connection, OpenFlags, Result as SqliteResult, Row};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
 use std::path::Path;
+use std::str::FromStr;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long a query waits for another process writing to the database to release its lock
+const BUSY_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Represents a client for reading Health Connect data from SQLite
+#[derive(Debug, Clone)]
 pub struct HealthDataReader {
     db_path: String,
+    data_types: Option<Vec<HealthDataType>>,
+    sleep_priority: Vec<String>,
+    merged: Vec<String>,
+    link_workouts: bool,
 }
 
-/// Represents a health data record extracted from SQLite
-#[derive(Debug, Clone)]
-pub struct HealthRecord {
-    #[allow(dead_code)] // Used by InfluxClient when converting to data points
-    pub record_type: String, // Type of health record (e.g., "HeartRate", "Steps")
-    pub timestamp: DateTime<Utc>, // When the measurement was taken
-    pub value: f64,               // The measurement value
-    pub metadata: HashMap<String, String>, // Additional data like device info, etc.
+/// A health data type that can be imported
+/// Its name is used in state files, --data-types and as the InfluxDB measurement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, ValueEnum)]
+#[value(rename_all = "PascalCase")]
+pub enum HealthDataType {
+    HeartRate,
+    Steps,
+    /// Steps per minute at each cadence sample, for intraday step curves
+    StepsCadence,
+    /// Sleep stages, as start and end points
+    Sleep,
+    /// Duration of each sleep stage, in minutes
+    SleepDuration,
+    /// Sleep stage values, for state timelines
+    SleepState,
+    Weight,
+    ActiveCalories,
+    TotalCalories,
+    BasalMetabolicRate,
+    BodyFat,
+    /// Lean body mass, in grams
+    LeanBodyMass,
+    /// Height, in meters
+    Height,
+    ExerciseSession,
 }
 
-impl HealthDataReader {
-    /// Creates a new HealthDataReader
-    pub fn new(db_path: &str) -> Self {
-        HealthDataReader {
-            db_path: db_path.to_string(),
+impl HealthDataType {
+    /// Every health data type, in the order they are read
+    pub const ALL: [HealthDataType; 14] = [
+        HealthDataType::HeartRate,
+        HealthDataType::Steps,
+        HealthDataType::StepsCadence,
+        HealthDataType::Sleep,
+        HealthDataType::SleepDuration,
+        HealthDataType::SleepState,
+        HealthDataType::Weight,
+        HealthDataType::ActiveCalories,
+        HealthDataType::TotalCalories,
+        HealthDataType::BasalMetabolicRate,
+        HealthDataType::BodyFat,
+        HealthDataType::LeanBodyMass,
+        HealthDataType::Height,
+        HealthDataType::ExerciseSession,
+    ];
+
+    /// Data types read from a series table, one row per sample, which a few years of
+    /// recordings can grow to tens of millions of rows
+    pub const SERIES: [HealthDataType; 2] =
+        [HealthDataType::HeartRate, HealthDataType::StepsCadence];
+
+    /// Gets the data types to read: the `included` ones (every type when None) but the
+    /// `excluded` ones. None reads every type, as when neither is given
+    pub fn select(
+        included: Option<Vec<HealthDataType>>,
+        excluded: &[HealthDataType],
+    ) -> Option<Vec<HealthDataType>> {
+        if excluded.is_empty() {
+            return included;
         }
+        let included = included.unwrap_or_else(|| HealthDataType::ALL.to_vec());
+        Some(
+            included
+                .into_iter()
+                .filter(|data_type| !excluded.contains(data_type))
+                .collect(),
+        )
     }
 
-    /// Checks if the database file exists
-    pub fn db_exists(&self) -> bool {
-        Path::new(&self.db_path).exists()
-    }
-
-    /// Opens a connection to the database
-    pub fn open_connection(&self) -> SqliteResult<Connection> {
-        Connection::open(&self.db_path)
-    }
-
-    /// Validates the database structure
-    pub fn validate_db(&self) -> Result<String, Box<dyn Error>> {
-        if !self.db_exists() {
-            return Err(format!("Database file does not exist: {}", self.db_path).into());
+    /// The name of the data type, e.g. "HeartRate"
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HealthDataType::HeartRate => "HeartRate",
+            HealthDataType::Steps => "Steps",
+            HealthDataType::StepsCadence => "StepsCadence",
+            HealthDataType::Sleep => "Sleep",
+            HealthDataType::SleepDuration => "SleepDuration",
+            HealthDataType::SleepState => "SleepState",
+            HealthDataType::Weight => "Weight",
+            HealthDataType::ActiveCalories => "ActiveCalories",
+            HealthDataType::TotalCalories => "TotalCalories",
+            HealthDataType::BasalMetabolicRate => "BasalMetabolicRate",
+            HealthDataType::BodyFat => "BodyFat",
+            HealthDataType::LeanBodyMass => "LeanBodyMass",
+            HealthDataType::Height => "Height",
+            HealthDataType::ExerciseSession => "ExerciseSession",
         }
+    }
 
-        let conn = self.open_connection()?;
-
-        // Get a list of tables in the database
-        let mut stmt = conn.prepare("SELECT name FROM sqlite_master WHERE type='table'")?;
-        let tables: Vec<String> = stmt
-            .query_map([], |row| row.get(0))?
-            .collect::<SqliteResult<Vec<String>>>()?;
-
-        let mut output = String::new();
-        output.push_str(&format!("Database: {}\n", self.db_path));
-        output.push_str(&format!("Found {} tables:\n", tables.len()));
-
-        // Check for specific tables and their record counts
-        let tables_to_check = [
-            "heart_rate_record_table",
-            "steps_record_table",
-            "sleep_session_record_table",
-            "weight_record_table",
-            "active_calories_burned_record_table",
-            "total_calories_burned_record_table",
-            "basal_metabolic_rate_record_table",
-            "body_fat_record_table",
-            "exercise_session_record_table",
-        ];
-
-        for table in &tables_to_check {
-            output.push_str(&format!("  - {}\n", table));
-
-            // Get sample record count
-            if let Ok(mut count_stmt) = conn.prepare(&format!("SELECT COUNT(*) FROM {}", table)) {
-                if let Ok(count) = count_stmt.query_row([], |row| row.get::<_, i64>(0)) {
-                    output.push_str(&format!("      Records: {}\n", count));
-                }
-            } else {
-                output.push_str("      Table does not exist or cannot be accessed\n");
+    /// Gets the tables the records of this type are read from, the apps that wrote them
+    /// included
+    fn tables(self) -> &'static [&'static str] {
+        match self {
+            HealthDataType::HeartRate => &[
+                "heart_rate_record_table",
+                "heart_rate_record_series_table",
+                "application_info_table",
+            ],
+            HealthDataType::Sleep | HealthDataType::SleepDuration | HealthDataType::SleepState => {
+                &[
+                    "sleep_session_record_table",
+                    "sleep_stages_table",
+                    "application_info_table",
+                ]
+            }
+            HealthDataType::Steps => &["steps_record_table", "application_info_table"],
+            HealthDataType::StepsCadence => &[
+                "StepsCadenceRecordTable",
+                "steps_cadence_record_table",
+                "application_info_table",
+            ],
+            HealthDataType::Weight => &["weight_record_table", "application_info_table"],
+            HealthDataType::ActiveCalories => &[
+                "active_calories_burned_record_table",
+                "application_info_table",
+            ],
+            HealthDataType::TotalCalories => &[
+                "total_calories_burned_record_table",
+                "application_info_table",
+            ],
+            HealthDataType::BasalMetabolicRate => &[
+                "basal_metabolic_rate_record_table",
+                "application_info_table",
+            ],
+            HealthDataType::BodyFat => &["body_fat_record_table", "application_info_table"],
+            HealthDataType::LeanBodyMass => {
+                &["lean_body_mass_record_table", "application_info_table"]
+            }
+            HealthDataType::Height => &["height_record_table", "application_info_table"],
+            HealthDataType::ExerciseSession => {
+                &["exercise_session_record_table", "application_info_table"]
             }
         }
-
-        Ok(output)
     }
 
-    /// Retrieves heart rate data after a specific timestamp
-    pub fn get_heart_rate_since(
-        &self,
-        since: Option<DateTime<Utc>>,
-    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
-        if !self.db_exists() {
-            return Err(format!("Database file does not exist: {}", self.db_path).into());
+    /// Gets the table holding one row per record of this type, with its row_id and
+    /// last_modified_time
+    fn record_table(self) -> &'static str {
+        match self {
+            HealthDataType::HeartRate => "heart_rate_record_table",
+            HealthDataType::Steps => "steps_record_table",
+            HealthDataType::StepsCadence => "StepsCadenceRecordTable",
+            HealthDataType::Sleep | HealthDataType::SleepDuration | HealthDataType::SleepState => {
+                "sleep_session_record_table"
+            }
+            HealthDataType::Weight => "weight_record_table",
+            HealthDataType::ActiveCalories => "active_calories_burned_record_table",
+            HealthDataType::TotalCalories => "total_calories_burned_record_table",
+            HealthDataType::BasalMetabolicRate => "basal_metabolic_rate_record_table",
+            HealthDataType::BodyFat => "body_fat_record_table",
+            HealthDataType::LeanBodyMass => "lean_body_mass_record_table",
+            HealthDataType::Height => "height_record_table",
+            HealthDataType::ExerciseSession => "exercise_session_record_table",
         }
+    }
 
-        let conn = self.open_connection()?;
-        let mut records = Vec::new();
-
-        // Updated query based on the actual schema (heart_rate_record_table and heart_rate_record_series_table)
-        let query = match since {
-            Some(timestamp) => {
-                let _unix_timestamp = timestamp.timestamp_millis();
-                "SELECT hrs.epoch_millis, hrs.beats_per_minute, ai.app_name 
+    /// Query for the newest record time and the highest row_id of the data type
+    fn latest_query(self) -> &'static str {
+        match self {
+            HealthDataType::HeartRate => {
+                "SELECT MAX(hrs.epoch_millis), MAX(hr.row_id)
                  FROM heart_rate_record_series_table hrs
-                 JOIN heart_rate_record_table hr ON hrs.parent_key = hr.row_id
-                 LEFT JOIN application_info_table ai ON hr.app_info_id = ai.row_id
-                 WHERE hrs.epoch_millis > ? 
-                 ORDER BY hrs.epoch_millis ASC"
-                    .to_string()
+                 JOIN heart_rate_record_table hr ON hrs.parent_key = hr.row_id"
             }
-            None => "SELECT hrs.epoch_millis, hrs.beats_per_minute, ai.app_name
-                 FROM heart_rate_record_series_table hrs
-                 JOIN heart_rate_record_table hr ON hrs.parent_key = hr.row_id
-                 LEFT JOIN application_info_table ai ON hr.app_info_id = ai.row_id
-                 ORDER BY hrs.epoch_millis ASC"
-                .to_string(),
-        };
-
-        let mut stmt = match conn.prepare(&query) {
-            Ok(stmt) => stmt,
-            Err(e) => {
-                // If the table doesn''t exist yet, return empty results
-                if e.to_string().contains("no such table") {
-                    return Ok(Vec::new());
-                }
-                return Err(Box::new(e));
+            HealthDataType::Steps => "SELECT MAX(start_time), MAX(row_id) FROM steps_record_table",
+            HealthDataType::StepsCadence => {
+                "SELECT MAX(scs.epoch_millis), MAX(sc.row_id)
+                 FROM steps_cadence_record_table scs
+                 JOIN StepsCadenceRecordTable sc ON scs.parent_key = sc.row_id"
             }
-        };
-
-        let mut rows = match since {
-            Some(timestamp) => {
-                let unix_timestamp = timestamp.timestamp_millis();
-                stmt.query([unix_timestamp])?
+            // Sleep records go up to the end of each session
+            HealthDataType::Sleep | HealthDataType::SleepDuration | HealthDataType::SleepState => {
+                "SELECT MAX(end_time), MAX(row_id) FROM sleep_session_record_table"
             }
-            None => stmt.query([])?,
-        };
-
-        while let Some(row_result) = rows.next()? {
-            match self.map_heart_rate_row(row_result) {
-                Ok(record) => records.push(record),
-                Err(e) => eprintln!("Error reading heart rate record: {}", e),
+            HealthDataType::Weight => "SELECT MAX(time), MAX(row_id) FROM weight_record_table",
+            HealthDataType::ActiveCalories => {
+                "SELECT MAX(start_time), MAX(row_id) FROM active_calories_burned_record_table"
+            }
+            HealthDataType::TotalCalories => {
+                "SELECT MAX(start_time), MAX(row_id) FROM total_calories_burned_record_table"
+            }
+            HealthDataType::BasalMetabolicRate => {
+                "SELECT MAX(time), MAX(row_id) FROM basal_metabolic_rate_record_table"
+            }
+            HealthDataType::BodyFat => "SELECT MAX(time), MAX(row_id) FROM body_fat_record_table",
+            HealthDataType::LeanBodyMass => {
+                "SELECT MAX(time), MAX(row_id) FROM lean_body_mass_record_table"
+            }
+            HealthDataType::Height => "SELECT MAX(time), MAX(row_id) FROM height_record_table",
+            HealthDataType::ExerciseSession => {
+                "SELECT MAX(start_time), MAX(row_id) FROM exercise_session_record_table"
             }
         }
-
-        Ok(records)
     }
+}
 
-    /// Maps a database row to a HeartRate HealthRecord
-    fn map_heart_rate_row(&self, row: &Row) -> SqliteResult<HealthRecord> {
-        let time_millis: i64 = row.get(0)?;
-        let value: i64 = row.get(1)?; // beats_per_minute is an INTEGER in the schema
-        let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
+impl fmt::Display for HealthDataType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
-        let timestamp = Utc
-            .timestamp_millis_opt(time_millis)
-            .single()
-            .unwrap_or_else(Utc::now);
+impl FromStr for HealthDataType {
+    type Err = String;
 
-        let mut metadata = HashMap::new();
-        metadata.insert("app_name".to_string(), app_name);
+    /// Parses a data type name, ignoring case
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        HealthDataType::ALL
+            .into_iter()
+            .find(|data_type| data_type.as_str().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown health data type: {}", name))
+    }
+}
 
-        Ok(HealthRecord {
-            record_type: "HeartRate".to_string(),
-            timestamp,
-            value: value as f64, // Convert INTEGER to f64
-            metadata,
-        })
+/// Groups of data types that can be named instead of listing them, wherever data types
+/// are listed: by name, then the data types in the group
+pub const DATA_TYPE_GROUPS: [(&str, &[HealthDataType]); 3] = [
+    (
+        "body",
+        &[
+            HealthDataType::Weight,
+            HealthDataType::BodyFat,
+            HealthDataType::LeanBodyMass,
+            HealthDataType::Height,
+        ],
+    ),
+    (
+        "activity",
+        &[
+            HealthDataType::Steps,
+            HealthDataType::StepsCadence,
+            HealthDataType::ActiveCalories,
+            HealthDataType::TotalCalories,
+            HealthDataType::ExerciseSession,
+        ],
+    ),
+    (
+        "sleep",
+        &[
+            HealthDataType::Sleep,
+            HealthDataType::SleepDuration,
+            HealthDataType::SleepState,
+        ],
+    ),
+];
+
+/// A data type, or a group of them from [`DATA_TYPE_GROUPS`], as named in a list of data
+/// types
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DataTypeSelector(&'static [HealthDataType]);
+
+impl DataTypeSelector {
+    /// Gets the data types selected
+    pub fn data_types(self) -> &'static [HealthDataType] {
+        self.0
     }
 
-    /// Retrieves step count data after a specific timestamp
-    pub fn get_steps_since(
-        &self,
-        since: Option<DateTime<Utc>>,
-    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
-        if !self.db_exists() {
-            return Err(format!("Database file does not exist: {}", self.db_path).into());
+    /// Gets the data types of `selectors`, each once, in the order they were named
+    pub fn expand(selectors: &[DataTypeSelector]) -> Vec<HealthDataType> {
+        let mut data_types = Vec::new();
+        for data_type in selectors.iter().flat_map(|selector| selector.data_types()) {
+            if !data_types.contains(data_type) {
+                data_types.push(*data_type);
+            }
         }
+        data_types
+    }
+}
 
-        let conn = self.open_connection()?;
-        let mut records = Vec::new();
+impl FromStr for DataTypeSelector {
+    type Err = String;
 
-        // Updated query based on the actual schema (steps_record_table)
-        let query = match since {
-            Some(timestamp) => {
-                let _unix_timestamp = timestamp.timestamp_millis();
-                "SELECT start_time, count, ai.app_name
-                 FROM steps_record_table sr
-                 LEFT JOIN application_info_table ai ON sr.app_info_id = ai.row_id
-                 WHERE start_time > ? 
-                 ORDER BY start_time ASC"
-                    .to_string()
-            }
-            None => "SELECT start_time, count, ai.app_name
-                 FROM steps_record_table sr
-                 LEFT JOIN application_info_table ai ON sr.app_info_id = ai.row_id
-                 ORDER BY start_time ASC"
-                .to_string(),
+    /// Parses a data type name, or a group name after `group:`, ignoring case. A group may
+    /// also be named alone when no data type has its name: `sleep` is the `Sleep` type, and
+    /// `group:sleep` the sleep group
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let group = |name: &str| {
+            DATA_TYPE_GROUPS
+                .iter()
+                .find(|(group, _)| group.eq_ignore_ascii_case(name))
+                .map(|(_, data_types)| DataTypeSelector(data_types))
         };
+        if let Some(group_name) = name.strip_prefix("group:") {
+            return group(group_name)
+                .ok_or_else(|| format!("Unknown health data type group: {}", group_name));
+        }
+        HealthDataType::ALL
+            .iter()
+            .position(|data_type| data_type.as_str().eq_ignore_ascii_case(name))
+            .map(|index| DataTypeSelector(&HealthDataType::ALL[index..=index]))
+            .or_else(|| group(name))
+            .ok_or_else(|| format!("Unknown health data type or group: {}", name))
+    }
+}
 
-        let mut stmt = match conn.prepare(&query) {
-            Ok(stmt) => stmt,
-            Err(e) => {
-                // If the table doesn''t exist yet, return empty results
-                if e.to_string().contains("no such table") {
-                    return Ok(Vec::new());
-                }
-                return Err(Box::new(e));
-            }
-        };
+/// Represents a health data record extracted from SQLite
+#[derive(Debug, Clone)]
+pub struct HealthRecord {
+    pub record_type: HealthDataType,       // Type of health record
+    pub timestamp: DateTime<Utc>,          // When the measurement was taken
+    pub value: f64,                        // The measurement value
+    pub metadata: HashMap<String, String>, // Additional data like device info, etc.
+    pub row_id: Option<i64>, // row_id of the source record in its *_record_table, if any
+    pub zone_offset: Option<i32>, // Seconds the local time was ahead of UTC, if recorded
+}
 
-        let mut rows = match since {
-            Some(timestamp) => {
-                let unix_timestamp = timestamp.timestamp_millis();
-                stmt.query([unix_timestamp])?
-            }
-            None => stmt.query([])?,
-        };
+impl HealthRecord {
+    /// Gets the local time the record was taken at, by its zone offset, or in `timezone`
+    /// when the export has none
+    pub fn local_time(&self, timezone: Tz) -> NaiveDateTime {
+        self.local_time_of(self.timestamp, timezone)
+    }
 
-        while let Some(row_result) = rows.next()? {
-            match self.map_steps_row(row_result) {
-                Ok(record) => records.push(record),
-                Err(e) => eprintln!("Error reading steps record: {}", e),
-            }
+    /// Gets the local time of another instant of the record, such as its end, the same
+    /// way. `timezone` tells whether a DST change came in between; the zone offset is
+    /// taken to hold throughout
+    pub fn local_time_of(&self, time: DateTime<Utc>, timezone: Tz) -> NaiveDateTime {
+        match self.zone_offset {
+            Some(offset) => (time + chrono::Duration::seconds(offset.into())).naive_utc(),
+            None => time.with_timezone(&timezone).naive_local(),
         }
-
-        Ok(records)
     }
+}
 
-    /// Maps a database row to a Steps HealthRecord
-    fn map_steps_row(&self, row: &Row) -> SqliteResult<HealthRecord> {
-        let time_millis: i64 = row.get(0)?;
-        let value: i64 = row.get(1)?; // count is an INTEGER in the schema
-        let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
+/// The newest record of a data type in the database
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatestRecord {
+    pub timestamp: DateTime<Utc>,
+    /// Highest row_id in the record table, which need not belong to the newest record
+    pub row_id: i64,
+}
 
-        let timestamp = Utc
-            .timestamp_millis_opt(time_millis)
-            .single()
-            .unwrap_or_else(Utc::now);
+/// Record tables summarized by `HealthDataReader::table_stats`: the data type, the table, the
+/// FROM clause (aliasing the table holding app_info_id as `a`) and the column with each
+/// record's time. Heart rate samples live in the series table, one row per measurement
+const STATS_TABLES: [(HealthDataType, &str, &str, &str); 12] = [
+    (
+        HealthDataType::HeartRate,
+        "heart_rate_record_series_table",
+        "heart_rate_record_series_table r JOIN heart_rate_record_table a ON r.parent_key = a.row_id",
+        "r.epoch_millis",
+    ),
+    (HealthDataType::Steps, "steps_record_table", "steps_record_table a", "a.start_time"),
+    (
+        HealthDataType::StepsCadence,
+        "steps_cadence_record_table",
+        "steps_cadence_record_table r JOIN StepsCadenceRecordTable a ON r.parent_key = a.row_id",
+        "r.epoch_millis",
+    ),
+    (
+        HealthDataType::Sleep,
+        "sleep_session_record_table",
+        "sleep_session_record_table a",
+        "a.start_time",
+    ),
+    (HealthDataType::Weight, "weight_record_table", "weight_record_table a", "a.time"),
+    (
+        HealthDataType::ActiveCalories,
+        "active_calories_burned_record_table",
+        "active_calories_burned_record_table a",
+        "a.start_time",
+    ),
+    (
+        HealthDataType::TotalCalories,
+        "total_calories_burned_record_table",
+        "total_calories_burned_record_table a",
+        "a.start_time",
+    ),
+    (
+        HealthDataType::BasalMetabolicRate,
+        "basal_metabolic_rate_record_table",
+        "basal_metabolic_rate_record_table a",
+        "a.time",
+    ),
+    (HealthDataType::BodyFat, "body_fat_record_table", "body_fat_record_table a", "a.time"),
+    (
+        HealthDataType::LeanBodyMass,
+        "lean_body_mass_record_table",
+        "lean_body_mass_record_table a",
+        "a.time",
+    ),
+    (HealthDataType::Height, "height_record_table", "height_record_table a", "a.time"),
+    (
+        HealthDataType::ExerciseSession,
+        "exercise_session_record_table",
+        "exercise_session_record_table a",
+        "a.start_time",
+    ),
+];
+
+/// Columns the importer reads from each Health Connect table, with the type affinity it
+/// reads them as
+const EXPECTED_COLUMNS: [(&str, &[(&str, &str)]); 16] = [
+    (
+        "application_info_table",
+        &[("row_id", "INTEGER"), ("app_name", "TEXT")],
+    ),
+    (
+        "heart_rate_record_table",
+        &[("row_id", "INTEGER"), ("app_info_id", "INTEGER")],
+    ),
+    (
+        "heart_rate_record_series_table",
+        &[
+            ("parent_key", "INTEGER"),
+            ("epoch_millis", "INTEGER"),
+            ("beats_per_minute", "INTEGER"),
+        ],
+    ),
+    (
+        "steps_record_table",
+        &[
+            ("row_id", "INTEGER"),
+            ("start_time", "INTEGER"),
+            ("count", "INTEGER"),
+            ("app_info_id", "INTEGER"),
+        ],
+    ),
+    (
+        "StepsCadenceRecordTable",
+        &[("row_id", "INTEGER"), ("app_info_id", "INTEGER")],
+    ),
+    (
+        "steps_cadence_record_table",
+        &[
+            ("parent_key", "INTEGER"),
+            ("epoch_millis", "INTEGER"),
+            ("rate", "REAL"),
+        ],
+    ),
+    (
+        "sleep_session_record_table",
+        &[
+            ("row_id", "INTEGER"),
+            ("start_time", "INTEGER"),
+            ("end_time", "INTEGER"),
+            ("app_info_id", "INTEGER"),
+        ],
+    ),
+    (
+        "sleep_stages_table",
+        &[
+            ("parent_key", "INTEGER"),
+            ("stage_type", "INTEGER"),
+            ("stage_start_time", "INTEGER"),
+        ],
+    ),
+    (
+        "weight_record_table",
+        &[
+            ("row_id", "INTEGER"),
+            ("time", "INTEGER"),
+            ("weight", "REAL"),
+            ("app_info_id", "INTEGER"),
+        ],
+    ),
+    (
+        "active_calories_burned_record_table",
+        &[
+            ("row_id", "INTEGER"),
+            ("start_time", "INTEGER"),
+            ("end_time", "INTEGER"),
+            ("energy", "REAL"),
+            ("app_info_id", "INTEGER"),
+        ],
+    ),
+    (
+        "total_calories_burned_record_table",
+        &[
+            ("row_id", "INTEGER"),
+            ("start_time", "INTEGER"),
+            ("end_time", "INTEGER"),
+            ("energy", "REAL"),
+            ("app_info_id", "INTEGER"),
+        ],
+    ),
+    (
+        "basal_metabolic_rate_record_table",
+        &[
+            ("row_id", "INTEGER"),
+            ("time", "INTEGER"),
+            ("basal_metabolic_rate", "REAL"),
+            ("app_info_id", "INTEGER"),
+        ],
+    ),
+    (
+        "body_fat_record_table",
+        &[
+            ("row_id", "INTEGER"),
+            ("time", "INTEGER"),
+            ("percentage", "REAL"),
+            ("app_info_id", "INTEGER"),
+        ],
+    ),
+    (
+        "lean_body_mass_record_table",
+        &[
+            ("row_id", "INTEGER"),
+            ("time", "INTEGER"),
+            ("mass", "REAL"),
+            ("app_info_id", "INTEGER"),
+        ],
+    ),
+    (
+        "height_record_table",
+        &[
+            ("row_id", "INTEGER"),
+            ("time", "INTEGER"),
+            ("height", "REAL"),
+            ("app_info_id", "INTEGER"),
+        ],
+    ),
+    (
+        "exercise_session_record_table",
+        &[
+            ("row_id", "INTEGER"),
+            ("start_time", "INTEGER"),
+            ("end_time", "INTEGER"),
+            ("exercise_type", "INTEGER"),
+            ("title", "TEXT"),
+            ("app_info_id", "INTEGER"),
+        ],
+    ),
+];
+
+/// Columns every Health Connect record table has besides those the importer reads, which
+/// are never taken for a renamed column
+const COMMON_COLUMNS: [&str; 11] = [
+    "uuid",
+    "last_modified_time",
+    "client_record_id",
+    "client_record_version",
+    "device_info_id",
+    "recording_method",
+    "dedupe_hash",
+    "local_date",
+    "zone_offset",
+    "start_zone_offset",
+    "end_zone_offset",
+];
+
+/// How a Health Connect export differs from the tables and columns the importer reads
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SchemaCheck {
+    /// Tables that are not in the export; their data types are skipped by imports
+    pub missing_tables: Vec<&'static str>,
+    /// (table, column) pairs missing from tables that are in the export, with no column
+    /// that looks like their new name
+    pub missing_columns: Vec<(&'static str, &'static str)>,
+    /// Columns missing from their table next to one with a similar name
+    pub renamed_columns: Vec<RenamedColumn>,
+    /// Columns declared with a type the importer cannot read them as
+    pub type_mismatches: Vec<TypeMismatch>,
+}
 
-        let mut metadata = HashMap::new();
-        metadata.insert("app_name".to_string(), app_name);
+/// A column the importer reads that seems to have been renamed in the export
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenamedColumn {
+    pub table: &'static str,
+    pub expected: &'static str,
+    /// The column of the table with the closest name
+    pub found: String,
+}
 
-        Ok(HealthRecord {
-            record_type: "Steps".to_string(),
-            timestamp,
-            value: value as f64, // Convert INTEGER to f64
-            metadata,
-        })
+/// A column declared with a type other than the one the importer reads it as
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeMismatch {
+    pub table: &'static str,
+    pub column: &'static str,
+    /// Type affinity the importer reads the column as: INTEGER, REAL or TEXT
+    pub expected: &'static str,
+    /// Type the column is declared with in the export
+    pub found: String,
+}
+
+impl SchemaCheck {
+    /// Checks if the importer can read every table and column it expects
+    pub fn is_ok(&self) -> bool {
+        self.missing_tables.is_empty()
+            && self.missing_columns.is_empty()
+            && self.renamed_columns.is_empty()
+            && self.type_mismatches.is_empty()
     }
 
-    /// Retrieves sleep data after a specific timestamp
-    pub fn get_sleep_since(
-        &self,
-        since: Option<DateTime<Utc>>,
-    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
-        if !self.db_exists() {
-            return Err(format!("Database file does not exist: {}", self.db_path).into());
+    /// Describes the problems with the tables a data type is read from, if any
+    pub fn problems_for(&self, data_type: HealthDataType) -> Vec<String> {
+        let tables = data_type.tables();
+        let mut problems: Vec<String> = Vec::new();
+        for table in self
+            .missing_tables
+            .iter()
+            .filter(|table| tables.contains(table))
+        {
+            problems.push(format!("missing table {}", table));
         }
+        for (table, column) in self
+            .missing_columns
+            .iter()
+            .filter(|(table, _)| tables.contains(table))
+        {
+            problems.push(format!("missing column {}.{}", table, column));
+        }
+        for renamed in self
+            .renamed_columns
+            .iter()
+            .filter(|renamed| tables.contains(&renamed.table))
+        {
+            problems.push(format!(
+                "column {}.{} missing, renamed to {}?",
+                renamed.table, renamed.expected, renamed.found
+            ));
+        }
+        for mismatch in self
+            .type_mismatches
+            .iter()
+            .filter(|mismatch| tables.contains(&mismatch.table))
+        {
+            problems.push(format!(
+                "column {}.{} is {}, expected {}",
+                mismatch.table, mismatch.column, mismatch.found, mismatch.expected
+            ));
+        }
+        problems
+    }
+}
 
-        let conn = self.open_connection()?;
-        let mut records = Vec::new();
+/// Summary of one record table in a Health Connect export
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableStats {
+    pub data_type: HealthDataType,
+    pub table: &'static str,
+    /// False when the export has no such table; the other fields are then empty
+    pub exists: bool,
+    pub records: i64,
+    pub earliest: Option<DateTime<Utc>>,
+    pub latest: Option<DateTime<Utc>>,
+    /// Names of the apps that wrote the records, "unknown" for records without one
+    pub apps: Vec<String>,
+}
 
-        // Query for sleep records based on sleep_session_record_table and sleep_stages_table
-        let query = match since {
-            Some(timestamp) => {
-                let _unix_timestamp = timestamp.timestamp_millis();
-                "SELECT ss.start_time, ss.end_time, st.stage_type, ai.app_name
-                 FROM sleep_session_record_table ss
-                 JOIN sleep_stages_table st ON st.parent_key = ss.row_id
-                 LEFT JOIN application_info_table ai ON ss.app_info_id = ai.row_id
-                 WHERE ss.start_time > ? 
-                 ORDER BY ss.start_time ASC, st.stage_start_time ASC"
-                    .to_string()
-            }
-            None => "SELECT ss.start_time, ss.end_time, st.stage_type, ai.app_name
-                 FROM sleep_session_record_table ss
-                 JOIN sleep_stages_table st ON st.parent_key = ss.row_id
-                 LEFT JOIN application_info_table ai ON ss.app_info_id = ai.row_id
-                 ORDER BY ss.start_time ASC, st.stage_start_time ASC"
-                .to_string(),
-        };
+impl TableStats {
+    /// Average number of records per day between the earliest and latest record
+    /// Spans shorter than a day count as one day
+    pub fn records_per_day(&self) -> Option<f64> {
+        let days = (self.latest? - self.earliest?).num_seconds() as f64 / 86_400.0;
+        Some(self.records as f64 / days.max(1.0))
+    }
+}
 
-        let mut stmt = match conn.prepare(&query) {
-            Ok(stmt) => stmt,
-            Err(e) => {
-                // If the table doesn't exist yet, return empty results
-                if e.to_string().contains("no such table") {
-                    return Ok(Vec::new());
-                }
-                return Err(Box::new(e));
-            }
-        };
+/// One stage of a sleep session, from when it started until the next one did
+#[derive(Debug, Clone, PartialEq)]
+pub struct SleepStage {
+    /// row_id of the session in sleep_session_record_table
+    pub session_row_id: i64,
+    pub app_name: String,
+    /// Health Connect stage type, see [`stage_name`]
+    pub stage_type: i64,
+    pub start: DateTime<Utc>,
+    /// When the next stage starts, or the session ends for its last stage
+    pub end: DateTime<Utc>,
+}
+
+/// Distance covered over a span of time, as Health Connect records it alongside workouts
+#[derive(Debug, Clone, PartialEq)]
+pub struct Distance {
+    pub app_name: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub meters: f64,
+}
+
+/// Thread reading the records of a series, see `HealthDataReader::stream_series`; it
+/// returns how many records it read, or the error that stopped it
+pub type SeriesReader = thread::JoinHandle<Result<usize, String>>;
+
+/// Where an incremental read of a health data table starts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadFrom {
+    /// Read every record
+    Beginning,
+    /// Read records with a timestamp after this one
+    Timestamp(DateTime<Utc>),
+    /// Read records whose row_id in the record table is greater than this one
+    /// Unlike a timestamp, this also picks up records that were inserted later with an
+    /// earlier timestamp (e.g., after a delayed watch sync)
+    RowId(i64),
+    /// Read records whose row_id is greater than `row_id`, or that were modified after
+    /// `modified`, so records edited in Health Connect after they were imported are read again
+    Changes {
+        row_id: i64,
+        modified: DateTime<Utc>,
+    },
+}
+
+impl From<Option<DateTime<Utc>>> for ReadFrom {
+    fn from(since: Option<DateTime<Utc>>) -> Self {
+        match since {
+            Some(timestamp) => ReadFrom::Timestamp(timestamp),
+            None => ReadFrom::Beginning,
+        }
+    }
+}
 
-        let mut rows = match since {
-            Some(timestamp) => {
-                let unix_timestamp = timestamp.timestamp_millis();
-                stmt.query([unix_timestamp])?
+impl ReadFrom {
+    /// Returns the WHERE clause for this starting point (empty when reading everything)
+    /// and the values to bind to it
+    /// Modification times are read from the `last_modified_time` column of the table
+    /// `row_id_column` belongs to
+    fn filter(&self, time_column: &str, row_id_column: &str) -> (String, Vec<i64>) {
+        match self {
+            ReadFrom::Beginning => (String::new(), Vec::new()),
+            ReadFrom::Timestamp(timestamp) => (
+                format!("WHERE {} > ?", time_column),
+                vec![timestamp.timestamp_millis()],
+            ),
+            ReadFrom::RowId(row_id) => (format!("WHERE {} > ?", row_id_column), vec![*row_id]),
+            ReadFrom::Changes { row_id, modified } => {
+                let modified_column = format!(
+                    "{}last_modified_time",
+                    row_id_column.trim_end_matches("row_id")
+                );
+                (
+                    format!("WHERE ({} > ? OR {} > ?)", row_id_column, modified_column),
+                    vec![*row_id, modified.timestamp_millis()],
+                )
             }
-            None => stmt.query([])?,
-        };
+        }
+    }
 
-        while let Some(row_result) = rows.next()? {
-            match self.map_sleep_row(row_result) {
-                Ok(stage_records) => {
-                    // Extend the records vec with all the records for this sleep stage
-                    records.extend(stage_records);
+    /// Combines the starting points of data types read by the same query, so that
+    /// none of them misses records. Row ids and timestamps cannot be compared, so
+    /// mixing them reads everything
+    pub fn earliest(self, other: ReadFrom) -> ReadFrom {
+        match (self, other) {
+            (ReadFrom::Timestamp(a), ReadFrom::Timestamp(b)) => ReadFrom::Timestamp(a.min(b)),
+            (ReadFrom::RowId(a), ReadFrom::RowId(b)) => ReadFrom::RowId(a.min(b)),
+            (
+                ReadFrom::Changes { row_id, modified },
+                ReadFrom::Changes {
+                    row_id: other_row_id,
+                    modified: other_modified,
+                },
+            ) => ReadFrom::Changes {
+                row_id: row_id.min(other_row_id),
+                modified: modified.min(other_modified),
+            },
+            // Reading changes too only reads more than the type resuming by row_id needs
+            (ReadFrom::Changes { row_id, modified }, ReadFrom::RowId(other))
+            | (ReadFrom::RowId(other), ReadFrom::Changes { row_id, modified }) => {
+                ReadFrom::Changes {
+                    row_id: row_id.min(other),
+                    modified,
                 }
-                Err(e) => eprintln!("Error reading sleep record: {}", e),
             }
+            _ => ReadFrom::Beginning,
         }
+    }
+}
 
-        Ok(records)
+impl HealthDataReader {
+    /// Creates a new HealthDataReader
+    pub fn new(db_path: &str) -> Self {
+        HealthDataReader {
+            db_path: db_path.to_string(),
+            data_types: None,
+            sleep_priority: Vec::new(),
+            merged: Vec::new(),
+            link_workouts: false,
+        }
     }
 
-    /// Maps a database row to multiple Sleep HealthRecords (start and end points)
-    fn map_sleep_row(&self, row: &Row) -> SqliteResult<Vec<HealthRecord>> {
-        let start_time_millis: i64 = row.get(0)?;
-        let end_time_millis: i64 = row.get(1)?;
-        let stage_type: i64 = row.get(2)?;
-        let app_name: String = row.get(3).unwrap_or_else(|_| "unknown".to_string());
+    /// Limits the records read when importing to these data types; None reads all of them
+    pub fn with_data_types(mut self, data_types: Option<Vec<HealthDataType>>) -> Self {
+        self.data_types = data_types;
+        self
+    }
 
-        let start_timestamp = Utc
-            .timestamp_millis_opt(start_time_millis)
-            .single()
-            .unwrap_or_else(Utc::now);
+    /// Sets the apps whose sleep sessions are kept when several apps recorded the same
+    /// night, preferred first; apps not listed come after them
+    pub fn with_sleep_priority(mut self, apps: Vec<String>) -> Self {
+        self.sleep_priority = apps;
+        self
+    }
 
-        let end_timestamp = Utc
-            .timestamp_millis_opt(end_time_millis)
-            .single()
-            .unwrap_or_else(Utc::now);
-
-        // Calculate duration in minutes as the value
-        let duration_millis = end_time_millis - start_time_millis;
-        let duration_minutes = duration_millis as f64 / (1000.0 * 60.0);
-
-        // Convert stage type integer to descriptive string
-        let stage_description = match stage_type {
-            1 => "AWAKE",
-            2 => "SLEEPING",
-            3 => "OUT_OF_BED",
-            4 => "LIGHT",
-            5 => "DEEP",
-            6 => "REM",
-            _ => "UNKNOWN",
-        };
+    /// Tags the heart rate samples taken during an exercise session with the session's
+    /// id (`exercise_session`) and type (`exercise_type`)
+    pub fn with_workout_links(mut self, link_workouts: bool) -> Self {
+        self.link_workouts = link_workouts;
+        self
+    }
 
-        // Numeric value for the sleep stage (useful for visualization in Grafana)
-        let stage_value = match stage_type {
-            1 => 0.0,  // AWAKE
-            2 => 1.0,  // SLEEPING (generic)
-            3 => 0.0,  // OUT_OF_BED
-            4 => 2.0,  // LIGHT
-            5 => 3.0,  // DEEP
-            6 => 4.0,  // REM
-            _ => -1.0, // UNKNOWN
-        };
+    /// Merges the records of older exports into those of this database, preferred first
+    /// where several hold the same record
+    pub fn with_merged_sources(mut self, paths: Vec<String>) -> Self {
+        self.merged = paths;
+        self
+    }
 
-        let mut results = Vec::new();
-
-        // Create metadata for the start point
-        let mut start_metadata = HashMap::new();
-        start_metadata.insert("app_name".to_string(), app_name.clone());
-        start_metadata.insert("stage".to_string(), stage_description.to_string());
-        start_metadata.insert("stage_type".to_string(), stage_type.to_string());
-        start_metadata.insert("event_type".to_string(), "start".to_string());
-        start_metadata.insert("duration_minutes".to_string(), duration_minutes.to_string());
-
-        // Start point - Main data point with stage value
-        results.push(HealthRecord {
-            record_type: "Sleep".to_string(),
-            timestamp: start_timestamp,
-            value: stage_value, // Use stage value for visualization
-            metadata: start_metadata,
-        });
+    /// Checks if the database file exists
+    pub fn db_exists(&self) -> bool {
+        Path::new(&self.db_path).exists()
+    }
 
-        // Create metadata for the end point
-        let mut end_metadata = HashMap::new();
-        end_metadata.insert("app_name".to_string(), app_name.clone());
-        end_metadata.insert("stage".to_string(), stage_description.to_string());
-        end_metadata.insert("stage_type".to_string(), stage_type.to_string());
-        end_metadata.insert("event_type".to_string(), "end".to_string());
-        end_metadata.insert("duration_minutes".to_string(), duration_minutes.to_string());
-
-        // End point
-        results.push(HealthRecord {
-            record_type: "Sleep".to_string(),
-            timestamp: end_timestamp,
-            value: 0.0, // End of this sleep stage
-            metadata: end_metadata,
-        });
+    /// Opens a read-only connection to the database, which an import never modifies
+    pub fn open_connection(&self) -> SqliteResult<Connection> {
+        self.open_read_only_connection()
+    }
 
-        // Add a sleep session record with duration for Grafana
-        let mut duration_metadata = HashMap::new();
-        duration_metadata.insert("app_name".to_string(), app_name.clone());
-        duration_metadata.insert("stage".to_string(), stage_description.to_string());
-        duration_metadata.insert("stage_type".to_string(), stage_type.to_string());
-        duration_metadata.insert("record_subtype".to_string(), "duration".to_string());
-
-        // Additional point for duration - can be used with Grafana Bar Gauge
-        results.push(HealthRecord {
-            record_type: "SleepDuration".to_string(),
-            timestamp: start_timestamp,
-            value: duration_minutes, // Duration in minutes for bar charts
-            metadata: duration_metadata,
-        });
+    /// Opens a read-only connection to the database, so extractions running on several
+    /// threads each get their own connection and never take a write lock
+    ///
+    /// Queries wait up to `BUSY_TIMEOUT` for the lock of another process writing to the
+    /// database. A database in a directory that cannot be written (a read-only mount or
+    /// backup), with no write-ahead log beside it, is opened as immutable, since SQLite
+    /// could not create the files it uses to share the log between connections
+    fn open_read_only_connection(&self) -> SqliteResult<Connection> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY
+            | OpenFlags::SQLITE_OPEN_NO_MUTEX
+            | OpenFlags::SQLITE_OPEN_URI;
+        let conn = if self.is_immutable() {
+            Connection::open_with_flags(format!("{}?immutable=1", file_uri(&self.db_path)), flags)?
+        } else {
+            Connection::open_with_flags(file_uri(&self.db_path), flags)?
+        };
+        conn.busy_timeout(BUSY_TIMEOUT)?;
+        Ok(conn)
+    }
 
-        // Add a sleep state point for continuous state visualization
-        let mut state_metadata = HashMap::new();
-        state_metadata.insert("app_name".to_string(), app_name);
-        state_metadata.insert("stage".to_string(), stage_description.to_string());
-        state_metadata.insert("stage_type".to_string(), stage_type.to_string());
-
-        // State point for Grafana State Timeline visualization
-        results.push(HealthRecord {
-            record_type: "SleepState".to_string(),
-            timestamp: start_timestamp,
-            value: stage_value, // Numeric value representing the sleep stage
-            metadata: state_metadata,
-        });
+    /// Checks if the database can only be read as an immutable file: nothing can be
+    /// written beside it, and it has no write-ahead log that would be ignored
+    fn is_immutable(&self) -> bool {
+        let path = Path::new(&self.db_path);
+        let directory = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let read_only = std::fs::metadata(directory)
+            .map(|metadata| metadata.permissions().readonly())
+            .unwrap_or(false);
+        read_only && !Path::new(&format!("{}-wal", self.db_path)).exists()
+    }
+
+    /// Validates the database structure: compares it with the tables and columns each data
+    /// type is read from, and counts the records of each
+    pub fn validate_db(&self) -> Result<String, Box<dyn Error>> {
+        let check = self.check_schema()?;
+        let conn = self.open_connection()?;
+        let tables: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type='table'",
+            [],
+            |row| row.get(0),
+        )?;
+
+        let mut output = String::new();
+        output.push_str(&format!("Database: {}\n", self.db_path));
+        output.push_str(&format!("Found {} tables\n", tables));
+        for data_type in HealthDataType::ALL {
+            let problems = check.problems_for(data_type);
+            let table = data_type.record_table();
+            if problems.is_empty() {
+                let records: i64 =
+                    conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                        row.get(0)
+                    })?;
+                output.push_str(&format!("  - {}: {} records\n", data_type, records));
+            } else if check.missing_tables.contains(&table) {
+                output.push_str(&format!(
+                    "  - {}: no {}, skipped by imports\n",
+                    data_type, table
+                ));
+            } else {
+                output.push_str(&format!("  - {}: cannot be read\n", data_type));
+                for problem in problems {
+                    output.push_str(&format!("      {}\n", problem));
+                }
+            }
+        }
 
-        Ok(results)
+        Ok(output)
     }
 
-    /// Retrieves weight data after a specific timestamp
-    pub fn get_weight_since(
-        &self,
-        since: Option<DateTime<Utc>>,
-    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+    /// Compares the export's schema with the tables and columns the importer reads
+    ///
+    /// A missing column is taken as renamed when the table has a column with a similar name
+    /// (e.g. `count` and `step_count`), and columns are compared by the type affinity SQLite
+    /// derives from their declared type; REAL columns may also be declared as integers
+    pub fn check_schema(&self) -> Result<SchemaCheck, Box<dyn Error>> {
         if !self.db_exists() {
             return Err(format!("Database file does not exist: {}", self.db_path).into());
         }
-
         let conn = self.open_connection()?;
-        let mut records = Vec::new();
 
-        // Query for weight records
-        let query = match since {
-            Some(timestamp) => {
-                let _unix_timestamp = timestamp.timestamp_millis();
-                "SELECT wr.time, wr.weight, ai.app_name
-                 FROM weight_record_table wr
-                 LEFT JOIN application_info_table ai ON wr.app_info_id = ai.row_id
-                 WHERE wr.time > ? 
-                 ORDER BY wr.time ASC"
-                    .to_string()
+        let mut check = SchemaCheck::default();
+        for (table, columns) in EXPECTED_COLUMNS {
+            let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+            let existing = stmt
+                .query_map([], |row| {
+                    Ok((row.get::<_, String>(1)?, row.get::<_, String>(2)?))
+                })?
+                .collect::<SqliteResult<Vec<(String, String)>>>()?;
+
+            // PRAGMA table_info returns no rows for a missing table
+            if existing.is_empty() {
+                check.missing_tables.push(table);
+                continue;
             }
-            None => "SELECT wr.time, wr.weight, ai.app_name
-                 FROM weight_record_table wr
-                 LEFT JOIN application_info_table ai ON wr.app_info_id = ai.row_id
-                 ORDER BY wr.time ASC"
-                .to_string(),
-        };
-
-        let mut stmt = match conn.prepare(&query) {
-            Ok(stmt) => stmt,
-            Err(e) => {
-                // If the table doesn't exist yet, return empty results
-                if e.to_string().contains("no such table") {
-                    return Ok(Vec::new());
+            for &(column, expected) in columns {
+                if let Some((_, declared)) = existing.iter().find(|(name, _)| name == column) {
+                    if !affinity_matches(expected, declared) {
+                        check.type_mismatches.push(TypeMismatch {
+                            table,
+                            column,
+                            expected,
+                            found: declared.clone(),
+                        });
+                    }
+                    continue;
                 }
-                return Err(Box::new(e));
-            }
-        };
-
-        let mut rows = match since {
-            Some(timestamp) => {
-                let unix_timestamp = timestamp.timestamp_millis();
-                stmt.query([unix_timestamp])?
-            }
-            None => stmt.query([])?,
-        };
 
-        while let Some(row_result) = rows.next()? {
-            match self.map_weight_row(row_result) {
-                Ok(record) => records.push(record),
-                Err(e) => eprintln!("Error reading weight record: {}", e),
+                let renamed = existing
+                    .iter()
+                    .map(|(name, _)| name)
+                    .filter(|name| {
+                        !COMMON_COLUMNS.contains(&name.as_str())
+                            && !columns.iter().any(|(expected, _)| expected == name)
+                    })
+                    .filter_map(|name| similar_names(column, name).map(|distance| (distance, name)))
+                    .min();
+                match renamed {
+                    Some((_, found)) => check.renamed_columns.push(RenamedColumn {
+                        table,
+                        expected: column,
+                        found: found.clone(),
+                    }),
+                    None => check.missing_columns.push((table, column)),
+                }
             }
         }
-
-        Ok(records)
+        Ok(check)
     }
 
-    /// Maps a database row to a Weight HealthRecord
-    fn map_weight_row(&self, row: &Row) -> SqliteResult<HealthRecord> {
-        let time_millis: i64 = row.get(0)?;
-        let weight_value: f64 = row.get(1)?;
-        let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
-
-        let timestamp = Utc
-            .timestamp_millis_opt(time_millis)
-            .single()
-            .unwrap_or_else(Utc::now);
-
-        let mut metadata = HashMap::new();
-        metadata.insert("app_name".to_string(), app_name);
-        metadata.insert("unit".to_string(), "g".to_string());
-
-        Ok(HealthRecord {
-            record_type: "Weight".to_string(),
-            timestamp,
-            value: weight_value,
-            metadata,
-        })
-    }
-
-    /// Retrieves active calories data after a specific timestamp
-    pub fn get_active_calories_since(
-        &self,
-        since: Option<DateTime<Utc>>,
-    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+    /// Summarizes every record table the importer reads: row counts, time range and apps
+    pub fn table_stats(&self) -> Result<Vec<TableStats>, Box<dyn Error>> {
         if !self.db_exists() {
             return Err(format!("Database file does not exist: {}", self.db_path).into());
         }
-
         let conn = self.open_connection()?;
-        let mut records = Vec::new();
-
-        // Query for active calories records
-        let query = match since {
-            Some(timestamp) => {
-                let _unix_timestamp = timestamp.timestamp_millis();
-                "SELECT acb.start_time, acb.end_time, acb.energy, ai.app_name
-                 FROM active_calories_burned_record_table acb
-                 LEFT JOIN application_info_table ai ON acb.app_info_id = ai.row_id
-                 WHERE acb.start_time > ? 
-                 ORDER BY acb.start_time ASC"
-                    .to_string()
-            }
-            None => "SELECT acb.start_time, acb.end_time, acb.energy, ai.app_name
-                 FROM active_calories_burned_record_table acb
-                 LEFT JOIN application_info_table ai ON acb.app_info_id = ai.row_id
-                 ORDER BY acb.start_time ASC"
-                .to_string(),
-        };
 
-        let mut stmt = match conn.prepare(&query) {
-            Ok(stmt) => stmt,
-            Err(e) => {
-                // If the table doesn't exist yet, return empty results
-                if e.to_string().contains("no such table") {
-                    return Ok(Vec::new());
+        let mut all_stats = Vec::new();
+        for (data_type, table, from, time_column) in STATS_TABLES {
+            let mut stats = TableStats {
+                data_type,
+                table,
+                exists: false,
+                records: 0,
+                earliest: None,
+                latest: None,
+                apps: Vec::new(),
+            };
+
+            let summary = conn.query_row(
+                &format!(
+                    "SELECT COUNT(*), MIN({0}), MAX({0}) FROM {1}",
+                    time_column, from
+                ),
+                [],
+                |row| {
+                    Ok((
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, Option<i64>>(1)?,
+                        row.get::<_, Option<i64>>(2)?,
+                    ))
+                },
+            );
+            let (records, earliest, latest) = match summary {
+                Ok(summary) => summary,
+                // A missing table is reported rather than failing the whole summary
+                Err(e) if e.to_string().contains("no such table") => {
+                    all_stats.push(stats);
+                    continue;
                 }
-                return Err(Box::new(e));
-            }
-        };
+                Err(e) => return Err(Box::new(e)),
+            };
+            stats.exists = true;
+            stats.records = records;
+            stats.earliest = earliest.and_then(|millis| Utc.timestamp_millis_opt(millis).single());
+            stats.latest = latest.and_then(|millis| Utc.timestamp_millis_opt(millis).single());
+
+            let mut stmt = conn.prepare(&format!(
+                "SELECT DISTINCT COALESCE(ai.app_name, 'unknown')
+                 FROM {}
+                 LEFT JOIN application_info_table ai ON a.app_info_id = ai.row_id
+                 ORDER BY 1",
+                from
+            ))?;
+            stats.apps = stmt
+                .query_map([], |row| row.get(0))?
+                .collect::<SqliteResult<Vec<String>>>()?;
+
+            all_stats.push(stats);
+        }
+        Ok(all_stats)
+    }
 
-        let mut rows = match since {
-            Some(timestamp) => {
-                let unix_timestamp = timestamp.timestamp_millis();
-                stmt.query([unix_timestamp])?
-            }
-            None => stmt.query([])?,
+    /// Gets the newest record timestamp and the highest row_id of a data type in the database,
+    /// or None when there are no records of that type
+    pub fn latest_record(
+        &self,
+        data_type: HealthDataType,
+    ) -> Result<Option<LatestRecord>, Box<dyn Error>> {
+        let query = data_type.latest_query();
+
+        let conn = self.open_connection()?;
+        let latest = match conn.query_row(query, [], |row| {
+            Ok((row.get::<_, Option<i64>>(0)?, row.get::<_, Option<i64>>(1)?))
+        }) {
+            Ok(latest) => latest,
+            // A missing table has no records
+            Err(e) if e.to_string().contains("no such table") => return Ok(None),
+            Err(e) => return Err(Box::new(e)),
         };
 
-        while let Some(row_result) = rows.next()? {
-            match self.map_active_calories_row(row_result) {
-                Ok(record) => records.push(record),
-                Err(e) => eprintln!("Error reading active calories record: {}", e),
-            }
+        match latest {
+            (Some(time_millis), Some(row_id)) => Ok(Utc
+                .timestamp_millis_opt(time_millis)
+                .single()
+                .map(|timestamp| LatestRecord { timestamp, row_id })),
+            _ => Ok(None),
         }
-
-        Ok(records)
     }
 
-    /// Maps a database row to an ActiveCalories HealthRecord
-    fn map_active_calories_row(&self, row: &Row) -> SqliteResult<HealthRecord> {
-        let start_time_millis: i64 = row.get(0)?;
-        let end_time_millis: i64 = row.get(1)?;
-        let energy_value: f64 = row.get(2)?;
-        let app_name: String = row.get(3).unwrap_or_else(|_| "unknown".to_string());
-
-        let timestamp = Utc
-            .timestamp_millis_opt(start_time_millis)
-            .single()
-            .unwrap_or_else(Utc::now);
-
-        // Calculate duration in minutes
-        let duration_millis = end_time_millis - start_time_millis;
-        let duration_minutes = duration_millis as f64 / (1000.0 * 60.0);
-
-        let mut metadata = HashMap::new();
-        metadata.insert("app_name".to_string(), app_name);
-        metadata.insert("unit".to_string(), "kcal".to_string());
-        metadata.insert("duration_minutes".to_string(), duration_minutes.to_string());
-        metadata.insert(
-            "end_time".to_string(),
-            Utc.timestamp_millis_opt(end_time_millis)
-                .single()
-                .unwrap_or_else(Utc::now)
-                .to_rfc3339(),
+    /// Gets the latest modification time of the records of a data type, or None when there
+    /// are no records of that type
+    pub fn latest_modified(
+        &self,
+        data_type: HealthDataType,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let query = format!(
+            "SELECT MAX(last_modified_time) FROM {}",
+            data_type.record_table()
         );
 
-        Ok(HealthRecord {
-            record_type: "ActiveCalories".to_string(),
-            timestamp,
-            value: energy_value,
-            metadata,
-        })
+        let conn = self.open_read_only_connection()?;
+        let modified = match conn.query_row(&query, [], |row| row.get::<_, Option<i64>>(0)) {
+            Ok(modified) => modified,
+            // A missing table has no records
+            Err(e) if e.to_string().contains("no such table") => return Ok(None),
+            Err(e) => return Err(Box::new(e)),
+        };
+        Ok(modified.and_then(|millis| Utc.timestamp_millis_opt(millis).single()))
     }
 
-    /// Retrieves total calories burned data after a specific timestamp
-    pub fn get_total_calories_since(
+    /// Retrieves heart rate data after a specific timestamp
+    pub fn get_heart_rate_since(
         &self,
-        since: Option<DateTime<Utc>>,
+        since: impl Into<ReadFrom>,
     ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
-        if !self.db_exists() {
-            return Err(format!("Database file does not exist: {}", self.db_path).into());
-        }
+        self.extract(&HEART_RATE, since.into())
+    }
 
-        let conn = self.open_connection()?;
-        let mut records = Vec::new();
+    /// Retrieves step count data after a specific timestamp
+    pub fn get_steps_since(
+        &self,
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        self.extract(&STEPS, since.into())
+    }
 
-        // Query for total calories records
-        let query = match since {
-            Some(timestamp) => {
-                let _unix_timestamp = timestamp.timestamp_millis();
-                "SELECT tcb.start_time, tcb.end_time, tcb.energy, ai.app_name
-                 FROM total_calories_burned_record_table tcb
-                 LEFT JOIN application_info_table ai ON tcb.app_info_id = ai.row_id
-                 WHERE tcb.start_time > ? 
-                 ORDER BY tcb.start_time ASC"
-                    .to_string()
-            }
-            None => "SELECT tcb.start_time, tcb.end_time, tcb.energy, ai.app_name
-                 FROM total_calories_burned_record_table tcb
-                 LEFT JOIN application_info_table ai ON tcb.app_info_id = ai.row_id
-                 ORDER BY tcb.start_time ASC"
-                .to_string(),
-        };
+    /// Retrieves the step cadence samples after a specific timestamp; exports without
+    /// cadence tables have none
+    pub fn get_steps_cadence_since(
+        &self,
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        self.extract(&STEPS_CADENCE, since.into())
+    }
 
-        let mut stmt = match conn.prepare(&query) {
-            Ok(stmt) => stmt,
-            Err(e) => {
-                // If the table doesn't exist yet, return empty results
-                if e.to_string().contains("no such table") {
-                    return Ok(Vec::new());
-                }
-                return Err(Box::new(e));
-            }
-        };
+    /// Retrieves sleep data after a specific timestamp
+    pub fn get_sleep_since(
+        &self,
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        self.extract(&SLEEP, since.into())
+    }
 
-        let mut rows = match since {
-            Some(timestamp) => {
-                let unix_timestamp = timestamp.timestamp_millis();
-                stmt.query([unix_timestamp])?
-            }
-            None => stmt.query([])?,
-        };
+    /// Retrieves weight data after a specific timestamp
+    pub fn get_weight_since(
+        &self,
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        self.extract(&WEIGHT, since.into())
+    }
 
-        while let Some(row_result) = rows.next()? {
-            match self.map_total_calories_row(row_result) {
-                Ok(record) => records.push(record),
-                Err(e) => eprintln!("Error reading total calories record: {}", e),
-            }
-        }
+    /// Retrieves active calories data after a specific timestamp
+    pub fn get_active_calories_since(
+        &self,
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        self.extract(&ACTIVE_CALORIES, since.into())
+    }
 
-        Ok(records)
+    /// Retrieves total calories burned data after a specific timestamp
+    pub fn get_total_calories_since(
+        &self,
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        self.extract(&TOTAL_CALORIES, since.into())
     }
 
-    /// Maps a database row to a TotalCalories HealthRecord
-    fn map_total_calories_row(&self, row: &Row) -> SqliteResult<HealthRecord> {
-        let start_time_millis: i64 = row.get(0)?;
-        let end_time_millis: i64 = row.get(1)?;
-        let energy_value: f64 = row.get(2)?;
-        let app_name: String = row.get(3).unwrap_or_else(|_| "unknown".to_string());
+    /// Retrieves basal metabolic rate data after a specific timestamp
+    pub fn get_basal_metabolic_rate_since(
+        &self,
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        self.extract(&BASAL_METABOLIC_RATE, since.into())
+    }
 
-        let start_timestamp = Utc
-            .timestamp_millis_opt(start_time_millis)
-            .single()
-            .unwrap_or_else(Utc::now);
-
-        // Calculate duration in hours for metadata
-        let duration_millis = end_time_millis - start_time_millis;
-        let duration_hours = duration_millis as f64 / (1000.0 * 60.0 * 60.0);
-
-        let mut metadata = HashMap::new();
-        metadata.insert("app_name".to_string(), app_name);
-        metadata.insert("unit".to_string(), "calories".to_string());
-        metadata.insert("duration_hours".to_string(), duration_hours.to_string());
-        metadata.insert(
-            "start_time_millis".to_string(),
-            start_time_millis.to_string(),
-        );
-        metadata.insert("end_time_millis".to_string(), end_time_millis.to_string());
+    /// Retrieves body fat percentage data after a specific timestamp
+    pub fn get_body_fat_since(
+        &self,
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        self.extract(&BODY_FAT, since.into())
+    }
 
-        Ok(HealthRecord {
-            record_type: "TotalCalories".to_string(),
-            timestamp: start_timestamp,
-            value: energy_value,
-            metadata,
-        })
+    /// Retrieves lean body mass data after a specific timestamp
+    pub fn get_lean_body_mass_since(
+        &self,
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        self.extract(&LEAN_BODY_MASS, since.into())
     }
 
-    /// Retrieves basal metabolic rate data after a specific timestamp
-    pub fn get_basal_metabolic_rate_since(
+    /// Retrieves height data after a specific timestamp
+    pub fn get_height_since(
         &self,
-        since: Option<DateTime<Utc>>,
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        self.extract(&HEIGHT, since.into())
+    }
+
+    /// Retrieves exercise session data after a specific timestamp
+    pub fn get_exercise_sessions_since(
+        &self,
+        since: impl Into<ReadFrom>,
     ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        self.extract(&EXERCISE_SESSION, since.into())
+    }
+
+    /// Retrieves the stages of the sleep sessions started after a specific timestamp, with
+    /// their own start times rather than those of their session
+    pub fn get_sleep_stages_since(
+        &self,
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<SleepStage>, Box<dyn Error>> {
         if !self.db_exists() {
             return Err(format!("Database file does not exist: {}", self.db_path).into());
         }
 
-        let conn = self.open_connection()?;
-        let mut records = Vec::new();
-
-        // Query for basal metabolic rate records
-        let query = match since {
-            Some(timestamp) => {
-                let _unix_timestamp = timestamp.timestamp_millis();
-                "SELECT bmr.time, bmr.basal_metabolic_rate, ai.app_name
-                 FROM basal_metabolic_rate_record_table bmr
-                 LEFT JOIN application_info_table ai ON bmr.app_info_id = ai.row_id
-                 WHERE bmr.time > ? 
-                 ORDER BY bmr.time ASC"
-                    .to_string()
-            }
-            None => "SELECT bmr.time, bmr.basal_metabolic_rate, ai.app_name
-                 FROM basal_metabolic_rate_record_table bmr
-                 LEFT JOIN application_info_table ai ON bmr.app_info_id = ai.row_id
-                 ORDER BY bmr.time ASC"
-                .to_string(),
-        };
-
+        let conn = self.open_read_only_connection()?;
+        let (filter, params) = since.into().filter("ss.start_time", "ss.row_id");
+        let query = format!(
+            "SELECT ss.row_id, ss.end_time, st.stage_type, st.stage_start_time, ai.app_name
+             FROM sleep_session_record_table ss
+             JOIN sleep_stages_table st ON st.parent_key = ss.row_id
+             LEFT JOIN application_info_table ai ON ss.app_info_id = ai.row_id
+             {}
+             ORDER BY ss.start_time ASC, ss.row_id ASC, st.stage_start_time ASC",
+            filter
+        );
         let mut stmt = match conn.prepare(&query) {
             Ok(stmt) => stmt,
-            Err(e) => {
-                // If the table doesn't exist yet, return empty results
-                if e.to_string().contains("no such table") {
-                    return Ok(Vec::new());
-                }
-                return Err(Box::new(e));
-            }
+            Err(e) if e.to_string().contains("no such table") => return Ok(Vec::new()),
+            Err(e) => return Err(Box::new(e)),
         };
-
-        let mut rows = match since {
-            Some(timestamp) => {
-                let unix_timestamp = timestamp.timestamp_millis();
-                stmt.query([unix_timestamp])?
-            }
-            None => stmt.query([])?,
+        let map_row = |row: &Row| -> SqliteResult<(i64, i64, i64, i64, String)> {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3)?,
+                row.get(4).unwrap_or_else(|_| "unknown".to_string()),
+            ))
         };
-
-        while let Some(row_result) = rows.next()? {
-            match self.map_basal_metabolic_rate_row(row_result) {
-                Ok(record) => records.push(record),
-                Err(e) => eprintln!("Error reading basal metabolic rate record: {}", e),
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), map_row)?;
+
+        let millis = |millis: i64| Utc.timestamp_millis_opt(millis).single();
+        let mut stages: Vec<SleepStage> = Vec::new();
+        for row in rows {
+            let (session_row_id, session_end, stage_type, stage_start, app_name) = row?;
+            let (Some(start), Some(end)) = (millis(stage_start), millis(session_end)) else {
+                continue;
+            };
+            // The stage before, of the same session, ends where this one starts
+            if let Some(previous) = stages.last_mut() {
+                if previous.session_row_id == session_row_id {
+                    previous.end = start;
+                }
             }
+            stages.push(SleepStage {
+                session_row_id,
+                app_name,
+                stage_type,
+                start,
+                end,
+            });
         }
-
-        Ok(records)
-    }
-
-    /// Maps a database row to a BasalMetabolicRate HealthRecord
-    fn map_basal_metabolic_rate_row(&self, row: &Row) -> SqliteResult<HealthRecord> {
-        let time_millis: i64 = row.get(0)?;
-        let bmr_value: f64 = row.get(1)?;
-        let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
-
-        let timestamp = Utc
-            .timestamp_millis_opt(time_millis)
-            .single()
-            .unwrap_or_else(Utc::now);
-
-        let mut metadata = HashMap::new();
-        metadata.insert("app_name".to_string(), app_name);
-        metadata.insert("unit".to_string(), "calories_per_day".to_string());
-
-        Ok(HealthRecord {
-            record_type: "BasalMetabolicRate".to_string(),
-            timestamp,
-            value: bmr_value,
-            metadata,
-        })
+        Ok(stages)
     }
 
-    /// Retrieves body fat percentage data after a specific timestamp
-    pub fn get_body_fat_since(
+    /// Retrieves the distances recorded after a specific timestamp, which no data type
+    /// imports on its own but workout summaries add up
+    pub fn get_distances_since(
         &self,
-        since: Option<DateTime<Utc>>,
-    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        since: impl Into<ReadFrom>,
+    ) -> Result<Vec<Distance>, Box<dyn Error>> {
         if !self.db_exists() {
             return Err(format!("Database file does not exist: {}", self.db_path).into());
         }
 
-        let conn = self.open_connection()?;
-        let mut records = Vec::new();
-
-        // Query for body fat records
-        let query = match since {
-            Some(timestamp) => {
-                let _unix_timestamp = timestamp.timestamp_millis();
-                "SELECT bf.time, bf.percentage, ai.app_name
-                 FROM body_fat_record_table bf
-                 LEFT JOIN application_info_table ai ON bf.app_info_id = ai.row_id
-                 WHERE bf.time > ? 
-                 ORDER BY bf.time ASC"
-                    .to_string()
-            }
-            None => "SELECT bf.time, bf.percentage, ai.app_name
-                 FROM body_fat_record_table bf
-                 LEFT JOIN application_info_table ai ON bf.app_info_id = ai.row_id
-                 ORDER BY bf.time ASC"
-                .to_string(),
-        };
-
+        let conn = self.open_read_only_connection()?;
+        let (filter, params) = since.into().filter("d.start_time", "d.row_id");
+        let query = format!(
+            "SELECT d.start_time, d.end_time, d.distance, ai.app_name
+             FROM distance_record_table d
+             LEFT JOIN application_info_table ai ON d.app_info_id = ai.row_id
+             {}
+             ORDER BY d.start_time ASC",
+            filter
+        );
         let mut stmt = match conn.prepare(&query) {
             Ok(stmt) => stmt,
-            Err(e) => {
-                // If the table doesn't exist yet, return empty results
-                if e.to_string().contains("no such table") {
-                    return Ok(Vec::new());
-                }
-                return Err(Box::new(e));
-            }
+            Err(e) if e.to_string().contains("no such table") => return Ok(Vec::new()),
+            Err(e) => return Err(Box::new(e)),
         };
-
-        let mut rows = match since {
-            Some(timestamp) => {
-                let unix_timestamp = timestamp.timestamp_millis();
-                stmt.query([unix_timestamp])?
-            }
-            None => stmt.query([])?,
+        let map_row = |row: &Row| -> SqliteResult<(i64, i64, f64, String)> {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                row.get(2)?,
+                row.get(3).unwrap_or_else(|_| "unknown".to_string()),
+            ))
         };
-
-        while let Some(row_result) = rows.next()? {
-            match self.map_body_fat_row(row_result) {
-                Ok(record) => records.push(record),
-                Err(e) => eprintln!("Error reading body fat record: {}", e),
+        let rows = stmt.query_map(rusqlite::params_from_iter(params), map_row)?;
+
+        let millis = |millis: i64| Utc.timestamp_millis_opt(millis).single();
+        let mut distances = Vec::new();
+        for row in rows {
+            let (start, end, meters, app_name) = row?;
+            if let (Some(start), Some(end)) = (millis(start), millis(end)) {
+                distances.push(Distance {
+                    app_name,
+                    start,
+                    end,
+                    meters,
+                });
             }
         }
-
-        Ok(records)
+        Ok(distances)
     }
 
-    /// Maps a database row to a BodyFat HealthRecord
-    fn map_body_fat_row(&self, row: &Row) -> SqliteResult<HealthRecord> {
-        let time_millis: i64 = row.get(0)?;
-        let percentage_value: f64 = row.get(1)?;
-        let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
-
-        let timestamp = Utc
-            .timestamp_millis_opt(time_millis)
-            .single()
-            .unwrap_or_else(Utc::now);
-
-        let mut metadata = HashMap::new();
-        metadata.insert("app_name".to_string(), app_name);
-        metadata.insert("unit".to_string(), "percentage".to_string());
-
-        Ok(HealthRecord {
-            record_type: "BodyFat".to_string(),
-            timestamp,
-            value: percentage_value,
-            metadata,
-        })
-    }
-
-    /// Retrieves exercise session data after a specific timestamp
-    pub fn get_exercise_sessions_since(
+    /// Runs the query of an extractor from a starting point and maps its rows to records
+    /// A missing table has no records, since older exports may lack newer data types
+    fn extract(
         &self,
-        since: Option<DateTime<Utc>>,
+        extractor: &Extractor,
+        since: ReadFrom,
     ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        if !self.merged.is_empty() {
+            return self.extract_merged(extractor, since);
+        }
         if !self.db_exists() {
             return Err(format!("Database file does not exist: {}", self.db_path).into());
         }
 
-        let conn = self.open_connection()?;
+        let conn = self.open_read_only_connection()?;
         let mut records = Vec::new();
 
-        // Query for exercise session records
-        let query = match since {
-            Some(timestamp) => {
-                let _unix_timestamp = timestamp.timestamp_millis();
-                "SELECT es.start_time, es.end_time, es.exercise_type, es.title, ai.app_name
-                 FROM exercise_session_record_table es
-                 LEFT JOIN application_info_table ai ON es.app_info_id = ai.row_id
-                 WHERE es.start_time > ? 
-                 ORDER BY es.start_time ASC"
-                    .to_string()
-            }
-            None => "SELECT es.start_time, es.end_time, es.exercise_type, es.title, ai.app_name
-                 FROM exercise_session_record_table es
-                 LEFT JOIN application_info_table ai ON es.app_info_id = ai.row_id
-                 ORDER BY es.start_time ASC"
-                .to_string(),
-        };
+        let (filter, params) = since.filter(extractor.time_column, extractor.row_id_column);
+        let query = extractor
+            .query
+            .replace("{filter}", &filter)
+            .replace("{zone_offset}", zone_offset_column(&conn, extractor)?);
 
         let mut stmt = match conn.prepare(&query) {
             Ok(stmt) => stmt,
             Err(e) => {
-                // If the table doesn't exist yet, return empty results
                 if e.to_string().contains("no such table") {
                     return Ok(Vec::new());
                 }
@@ -896,499 +1323,1700 @@ impl HealthDataReader {
             }
         };
 
-        let mut rows = match since {
-            Some(timestamp) => {
-                let unix_timestamp = timestamp.timestamp_millis();
-                stmt.query([unix_timestamp])?
-            }
-            None => stmt.query([])?,
-        };
+        let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
 
         while let Some(row_result) = rows.next()? {
-            match self.map_exercise_session_row(row_result) {
-                Ok(record) => records.push(record),
-                Err(e) => eprintln!("Error reading exercise session record: {}", e),
+            match (extractor.map_row)(row_result) {
+                Ok(row_records) => records.extend(row_records),
+                Err(e) => eprintln!("Error reading {} record: {}", extractor.name, e),
             }
         }
 
+        if extractor.data_types.contains(&HealthDataType::Sleep) {
+            records = dedupe_sleep_sessions(records, &self.sleep_priority);
+        }
+        if extractor.data_types.contains(&HealthDataType::HeartRate) {
+            self.link_workouts(&conn, &mut records)?;
+        }
         Ok(records)
     }
 
-    /// Maps a database row to an ExerciseSession HealthRecord
-    fn map_exercise_session_row(&self, row: &Row) -> SqliteResult<HealthRecord> {
-        let start_time_millis: i64 = row.get(0)?;
-        let end_time_millis: i64 = row.get(1)?;
-        let exercise_type: i64 = row.get(2)?;
-        let title: String = row.get(3).unwrap_or_else(|_| "Unknown".to_string());
-        let app_name: String = row.get(4).unwrap_or_else(|_| "unknown".to_string());
-
-        let start_timestamp = Utc
-            .timestamp_millis_opt(start_time_millis)
-            .single()
-            .unwrap_or_else(Utc::now);
-
-        // Calculate duration in minutes
-        let duration_millis = end_time_millis - start_time_millis;
-        let duration_minutes = duration_millis as f64 / (1000.0 * 60.0);
-
-        let mut metadata = HashMap::new();
-        metadata.insert("app_name".to_string(), app_name);
-        metadata.insert("exercise_type".to_string(), exercise_type.to_string());
-        metadata.insert("title".to_string(), title);
-        metadata.insert("duration_minutes".to_string(), duration_minutes.to_string());
-        metadata.insert(
-            "start_time_millis".to_string(),
-            start_time_millis.to_string(),
-        );
-        metadata.insert("end_time_millis".to_string(), end_time_millis.to_string());
-        metadata.insert("unit".to_string(), "minutes".to_string());
-
-        Ok(HealthRecord {
-            record_type: "ExerciseSession".to_string(),
-            timestamp: start_timestamp,
-            value: duration_minutes, // Use duration as the value for visualization
-            metadata,
-        })
-    }
-
-    /// Gets all available health data since a specific timestamp
-    pub fn get_all_health_data_since(
+    /// Tags the heart rate records taken during an exercise session with its id and type,
+    /// when workout links are on. The id is the session's Health Connect UUID, the same in
+    /// every export; samples in overlapping sessions go to the one started last
+    fn link_workouts(
         &self,
-        since: Option<DateTime<Utc>>,
-    ) -> Result<HashMap<String, Vec<HealthRecord>>, Box<dyn Error>> {
-        let mut all_data = HashMap::new();
-
-        // Get heart rate data
-        match self.get_heart_rate_since(since) {
-            Ok(records) => {
-                if !records.is_empty() {
-                    all_data.insert("HeartRate".to_string(), records);
-                }
-            }
-            Err(e) => eprintln!("Error fetching heart rate data: {}", e),
-        }
-
-        // Get steps data
-        match self.get_steps_since(since) {
-            Ok(records) => {
-                if !records.is_empty() {
-                    all_data.insert("Steps".to_string(), records);
-                }
-            }
-            Err(e) => eprintln!("Error fetching steps data: {}", e),
-        }
-
-        // Get sleep data - this now includes multiple record types
-        match self.get_sleep_since(since) {
-            Ok(records) => {
-                if !records.is_empty() {
-                    // Split sleep records by record_type
-                    let mut sleep_records = Vec::new();
-                    let mut sleep_duration_records = Vec::new();
-                    let mut sleep_state_records = Vec::new();
-
-                    for record in records {
-                        match record.record_type.as_str() {
-                            "Sleep" => sleep_records.push(record),
-                            "SleepDuration" => sleep_duration_records.push(record),
-                            "SleepState" => sleep_state_records.push(record),
-                            _ => sleep_records.push(record), // Default case
-                        }
-                    }
-
-                    // Add each record type to the map
-                    if !sleep_records.is_empty() {
-                        all_data.insert("Sleep".to_string(), sleep_records);
-                    }
-                    if !sleep_duration_records.is_empty() {
-                        all_data.insert("SleepDuration".to_string(), sleep_duration_records);
-                    }
-                    if !sleep_state_records.is_empty() {
-                        all_data.insert("SleepState".to_string(), sleep_state_records);
-                    }
-                }
-            }
-            Err(e) => eprintln!("Error fetching sleep data: {}", e),
-        }
-
-        // Get weight data
-        match self.get_weight_since(since) {
-            Ok(records) => {
-                if !records.is_empty() {
-                    all_data.insert("Weight".to_string(), records);
-                }
-            }
-            Err(e) => eprintln!("Error fetching weight data: {}", e),
+        conn: &Connection,
+        records: &mut [HealthRecord],
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.link_workouts {
+            return Ok(());
         }
+        let (Some(first), Some(last)) = (
+            records.iter().map(|record| record.timestamp).min(),
+            records.iter().map(|record| record.timestamp).max(),
+        ) else {
+            return Ok(());
+        };
 
-        // Get active calories data
-        match self.get_active_calories_since(since) {
-            Ok(records) => {
-                if !records.is_empty() {
-                    all_data.insert("ActiveCalories".to_string(), records);
-                }
-            }
-            Err(e) => eprintln!("Error fetching active calories data: {}", e),
+        let mut stmt = match conn.prepare(
+            "SELECT start_time, end_time, exercise_type,
+                    CASE WHEN uuid IS NULL THEN CAST(row_id AS TEXT) ELSE lower(hex(uuid)) END
+             FROM exercise_session_record_table
+             WHERE end_time > ?1 AND start_time <= ?2
+             ORDER BY start_time ASC",
+        ) {
+            Ok(stmt) => stmt,
+            Err(e) if e.to_string().contains("no such") => return Ok(()),
+            Err(e) => return Err(Box::new(e)),
+        };
+        let sessions = stmt
+            .query_map([first.timestamp_millis(), last.timestamp_millis()], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, i64>(2)?,
+                    row.get::<_, String>(3)?,
+                ))
+            })?
+            .collect::<SqliteResult<Vec<_>>>()?;
+        if sessions.is_empty() {
+            return Ok(());
         }
 
-        // Get total calories data
-        match self.get_total_calories_since(since) {
-            Ok(records) => {
-                if !records.is_empty() {
-                    all_data.insert("TotalCalories".to_string(), records);
-                }
-            }
-            Err(e) => eprintln!("Error fetching total calories data: {}", e),
+        // The latest end among the sessions started so far, to stop looking back early
+        let mut latest_end = Vec::with_capacity(sessions.len());
+        for (_, end, _, _) in &sessions {
+            latest_end.push(latest_end.last().copied().unwrap_or(i64::MIN).max(*end));
         }
-
-        // Get basal metabolic rate data
-        match self.get_basal_metabolic_rate_since(since) {
-            Ok(records) => {
-                if !records.is_empty() {
-                    all_data.insert("BasalMetabolicRate".to_string(), records);
-                }
+        for record in records
+            .iter_mut()
+            .filter(|record| record.record_type == HealthDataType::HeartRate)
+        {
+            let time = record.timestamp.timestamp_millis();
+            let started = sessions.partition_point(|(start, _, _, _)| *start <= time);
+            let session = (0..started)
+                .rev()
+                .take_while(|&index| latest_end[index] > time)
+                .map(|index| &sessions[index])
+                .find(|(_, end, _, _)| *end > time);
+            if let Some((_, _, exercise_type, id)) = session {
+                record
+                    .metadata
+                    .insert("exercise_session".to_string(), id.clone());
+                record
+                    .metadata
+                    .insert("exercise_type".to_string(), exercise_type.to_string());
             }
-            Err(e) => eprintln!("Error fetching basal metabolic rate data: {}", e),
         }
+        Ok(())
+    }
 
-        // Get body fat data
-        match self.get_body_fat_since(since) {
-            Ok(records) => {
-                if !records.is_empty() {
-                    all_data.insert("BodyFat".to_string(), records);
+    /// Runs the query of an extractor on this database and each merged export, keeping the
+    /// first of the records found in several (the same data type, time and metadata)
+    ///
+    /// The row_ids of one export mean nothing in another, so the records of merged exports
+    /// have none; their sleep sessions are deduplicated within each export
+    fn extract_merged(
+        &self,
+        extractor: &Extractor,
+        since: ReadFrom,
+    ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
+        let mut records = self
+            .without_merged(&self.db_path)
+            .extract(extractor, since)?;
+        let mut seen: HashSet<String> = records.iter().map(merge_key).collect();
+        for path in &self.merged {
+            let merged = self.without_merged(path).extract(extractor, since)?;
+            for mut record in merged {
+                if seen.insert(merge_key(&record)) {
+                    record.row_id = None;
+                    records.push(record);
                 }
             }
-            Err(e) => eprintln!("Error fetching body fat data: {}", e),
         }
+        records.sort_by_key(|record| record.timestamp);
+        Ok(records)
+    }
 
-        // Get exercise session data
-        match self.get_exercise_sessions_since(since) {
-            Ok(records) => {
-                if !records.is_empty() {
-                    all_data.insert("ExerciseSession".to_string(), records);
-                }
-            }
-            Err(e) => eprintln!("Error fetching exercise session data: {}", e),
+    /// Gets a reader of another export with the same settings and nothing to merge
+    fn without_merged(&self, path: &str) -> HealthDataReader {
+        HealthDataReader {
+            db_path: path.to_string(),
+            merged: Vec::new(),
+            ..self.clone()
         }
+    }
 
-        Ok(all_data)
+    /// Gets all available health data since a specific timestamp
+    pub fn get_all_health_data_since(
+        &self,
+        since: Option<DateTime<Utc>>,
+    ) -> Result<HashMap<HealthDataType, Vec<HealthRecord>>, Box<dyn Error>> {
+        self.get_health_data_since_per_type(|_| since.into(), None)
     }
 
     /// Gets health data for specific data types since a specific timestamp
-    /// data_types: List of data types to include (e.g., ["HeartRate", "Steps", "TotalCalories"])
-    /// Available types: HeartRate, Steps, Sleep, SleepDuration, SleepState, Weight, ActiveCalories, TotalCalories, BasalMetabolicRate, BodyFat, ExerciseSession
     pub fn get_filtered_health_data_since(
         &self,
         since: Option<DateTime<Utc>>,
-        data_types: &[String],
-    ) -> Result<HashMap<String, Vec<HealthRecord>>, Box<dyn Error>> {
-        let mut all_data = HashMap::new();
+        data_types: &[HealthDataType],
+    ) -> Result<HashMap<HealthDataType, Vec<HealthRecord>>, Box<dyn Error>> {
+        self.get_health_data_since_per_type(|_| since.into(), Some(data_types))
+    }
 
-        // Helper function to check if a data type should be included
-        let should_include = |data_type: &str| -> bool {
-            data_types
-                .iter()
-                .any(|dt| dt.eq_ignore_ascii_case(data_type))
-        };
+    /// Gets health data using a separate starting point for each data type
+    /// since: Returns where to resume a data type from
+    /// data_types: Optional list of data types to include; all types are included when None
+    pub fn get_health_data_since_per_type<F>(
+        &self,
+        since: F,
+        data_types: Option<&[HealthDataType]>,
+    ) -> Result<HashMap<HealthDataType, Vec<HealthRecord>>, Box<dyn Error>>
+    where
+        F: Fn(HealthDataType) -> ReadFrom + Sync,
+    {
+        // Each table is queried on its own thread and connection, so a full-history import
+        // waits for the largest table rather than for all of them in turn
+        let extracted: Vec<Vec<HealthRecord>> = thread::scope(|scope| {
+            let handles: Vec<_> = EXTRACTORS
+                .into_iter()
+                .map(|extractor| {
+                    let since = &since;
+                    scope.spawn(move || self.extract_included(extractor, since, data_types))
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap_or_default())
+                .collect()
+        });
 
-        // Get heart rate data
-        if should_include("HeartRate") {
-            match self.get_heart_rate_since(since) {
-                Ok(records) => {
-                    if !records.is_empty() {
-                        all_data.insert("HeartRate".to_string(), records);
-                    }
-                }
-                Err(e) => eprintln!("Error fetching heart rate data: {}", e),
-            }
+        let mut all_data: HashMap<HealthDataType, Vec<HealthRecord>> = HashMap::new();
+        for record in extracted.into_iter().flatten() {
+            all_data.entry(record.record_type).or_default().push(record);
         }
 
-        // Get steps data
-        if should_include("Steps") {
-            match self.get_steps_since(since) {
-                Ok(records) => {
-                    if !records.is_empty() {
-                        all_data.insert("Steps".to_string(), records);
-                    }
-                }
-                Err(e) => eprintln!("Error fetching steps data: {}", e),
-            }
-        }
+        Ok(all_data)
+    }
 
-        // Get sleep data - this includes multiple record types
-        if should_include("Sleep")
-            || should_include("SleepDuration")
-            || should_include("SleepState")
-        {
-            match self.get_sleep_since(since) {
-                Ok(records) => {
-                    if !records.is_empty() {
-                        // Split sleep records by record_type
-                        let mut sleep_records = Vec::new();
-                        let mut sleep_duration_records = Vec::new();
-                        let mut sleep_state_records = Vec::new();
-
-                        for record in records {
-                            match record.record_type.as_str() {
-                                "Sleep" => sleep_records.push(record),
-                                "SleepDuration" => sleep_duration_records.push(record),
-                                "SleepState" => sleep_state_records.push(record),
-                                _ => sleep_records.push(record), // Default case
-                            }
-                        }
-
-                        // Add each record type to the map based on what was requested
-                        if should_include("Sleep") && !sleep_records.is_empty() {
-                            all_data.insert("Sleep".to_string(), sleep_records);
-                        }
-                        if should_include("SleepDuration") && !sleep_duration_records.is_empty() {
-                            all_data.insert("SleepDuration".to_string(), sleep_duration_records);
-                        }
-                        if should_include("SleepState") && !sleep_state_records.is_empty() {
-                            all_data.insert("SleepState".to_string(), sleep_state_records);
-                        }
-                    }
-                }
-                Err(e) => eprintln!("Error fetching sleep data: {}", e),
-            }
-        }
+    /// Reads the records of the requested data types an extractor produces
+    /// Errors are reported rather than returned, so one unreadable table does not stop the
+    /// other data types from being imported
+    fn extract_included(
+        &self,
+        extractor: &Extractor,
+        since: impl Fn(HealthDataType) -> ReadFrom,
+        data_types: Option<&[HealthDataType]>,
+    ) -> Vec<HealthRecord> {
+        let included: Vec<HealthDataType> = extractor
+            .data_types
+            .iter()
+            .copied()
+            .filter(|data_type| data_types.is_none_or(|types| types.contains(data_type)))
+            .collect();
+
+        // Data types read by the same query start from the earliest of their
+        // starting points
+        let Some(extractor_since) = included
+            .iter()
+            .map(|&data_type| since(data_type))
+            .reduce(ReadFrom::earliest)
+        else {
+            return Vec::new();
+        };
 
-        // Get weight data
-        if should_include("Weight") {
-            match self.get_weight_since(since) {
-                Ok(records) => {
-                    if !records.is_empty() {
-                        all_data.insert("Weight".to_string(), records);
-                    }
-                }
-                Err(e) => eprintln!("Error fetching weight data: {}", e),
+        match self.extract(extractor, extractor_since) {
+            Ok(mut records) => {
+                records.retain(|record| included.contains(&record.record_type));
+                records
             }
-        }
-
-        // Get active calories data
-        if should_include("ActiveCalories") {
-            match self.get_active_calories_since(since) {
-                Ok(records) => {
-                    if !records.is_empty() {
-                        all_data.insert("ActiveCalories".to_string(), records);
-                    }
-                }
-                Err(e) => eprintln!("Error fetching active calories data: {}", e),
+            Err(e) => {
+                eprintln!("Error fetching {} data: {}", extractor.name, e);
+                Vec::new()
             }
         }
+    }
 
-        // Get total calories data
-        if should_include("TotalCalories") {
-            match self.get_total_calories_since(since) {
-                Ok(records) => {
-                    if !records.is_empty() {
-                        all_data.insert("TotalCalories".to_string(), records);
-                    }
-                }
-                Err(e) => eprintln!("Error fetching total calories data: {}", e),
-            }
+    /// Gets where an import resumes a data type from: its last imported row_id, along with
+    /// the records modified since its last import, or its last imported timestamp for
+    /// states written before row_ids were tracked
+    fn read_from(&self, watermark: &ImportState, data_type: HealthDataType) -> ReadFrom {
+        match (
+            watermark.last_row_id_for(data_type.as_str()),
+            watermark.last_modified_for(data_type.as_str()),
+        ) {
+            // Row_ids only hold within one export, so merged ones resume by time
+            _ if !self.merged.is_empty() => watermark.last_imported_for(data_type.as_str()).into(),
+            (Some(row_id), Some(modified)) => ReadFrom::Changes { row_id, modified },
+            (Some(row_id), None) => ReadFrom::RowId(row_id),
+            (None, _) => watermark.last_imported_for(data_type.as_str()).into(),
         }
+    }
 
-        // Get basal metabolic rate data
-        if should_include("BasalMetabolicRate") {
-            match self.get_basal_metabolic_rate_since(since) {
-                Ok(records) => {
-                    if !records.is_empty() {
-                        all_data.insert("BasalMetabolicRate".to_string(), records);
-                    }
-                }
-                Err(e) => eprintln!("Error fetching basal metabolic rate data: {}", e),
-            }
+    /// Reads the new records of a series data type (see [`HealthDataType::SERIES`]) through
+    /// a cursor, on a thread of its own, instead of collecting them first: the records come
+    /// in time order, in pages of up to `page_size` that the thread only reads past once
+    /// the previous one was taken, so a series of any length is imported in bounded memory
+    ///
+    /// Merged sources cannot be streamed, as their records are deduplicated across exports
+    pub fn stream_series(
+        &self,
+        data_type: HealthDataType,
+        watermark: &ImportState,
+        page_size: usize,
+    ) -> Result<
+        (
+            impl Iterator<Item = HealthRecord> + Send + 'static,
+            SeriesReader,
+        ),
+        Box<dyn Error>,
+    > {
+        if !HealthDataType::SERIES.contains(&data_type) {
+            return Err(format!("{} is not read from a series table", data_type).into());
         }
-
-        // Get body fat data
-        if should_include("BodyFat") {
-            match self.get_body_fat_since(since) {
-                Ok(records) => {
-                    if !records.is_empty() {
-                        all_data.insert("BodyFat".to_string(), records);
-                    }
-                }
-                Err(e) => eprintln!("Error fetching body fat data: {}", e),
-            }
+        if !self.merged.is_empty() {
+            return Err("records of merged sources cannot be streamed".into());
+        }
+        if !self.db_exists() {
+            return Err(format!("Database file does not exist: {}", self.db_path).into());
         }
+        let Some(extractor) = EXTRACTORS
+            .into_iter()
+            .find(|extractor| extractor.data_types == [data_type])
+        else {
+            return Err(format!("no query reads {} records", data_type).into());
+        };
 
-        // Get exercise session data
-        if should_include("ExerciseSession") {
-            match self.get_exercise_sessions_since(since) {
-                Ok(records) => {
-                    if !records.is_empty() {
-                        all_data.insert("ExerciseSession".to_string(), records);
-                    }
+        let since = self.read_from(watermark, data_type);
+        let page_size = page_size.max(1);
+        // A page is only handed over once the last one was taken
+        let (page_tx, page_rx) = mpsc::sync_channel::<Vec<HealthRecord>>(0);
+        let reader = self.clone();
+        let handle = thread::spawn(move || {
+            reader
+                .read_pages(extractor, since, page_size, |page| {
+                    page_tx.send(page).is_ok()
+                })
+                .map_err(|e| e.to_string())
+        });
+        Ok((page_rx.into_iter().flatten(), handle))
+    }
+
+    /// Runs an extractor's query and hands its records to `send` in pages of up to
+    /// `page_size`, until the rows run out or `send` returns false, and returns how many
+    /// records were read
+    fn read_pages(
+        &self,
+        extractor: &Extractor,
+        since: ReadFrom,
+        page_size: usize,
+        mut send: impl FnMut(Vec<HealthRecord>) -> bool,
+    ) -> Result<usize, Box<dyn Error>> {
+        let conn = self.open_read_only_connection()?;
+        let (filter, params) = since.filter(extractor.time_column, extractor.row_id_column);
+        let query = extractor
+            .query
+            .replace("{filter}", &filter)
+            .replace("{zone_offset}", zone_offset_column(&conn, extractor)?);
+        let mut stmt = match conn.prepare(&query) {
+            Ok(stmt) => stmt,
+            Err(e) if e.to_string().contains("no such table") => return Ok(0),
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        let mut rows = stmt.query(rusqlite::params_from_iter(params))?;
+        let mut read = 0;
+        let mut page = Vec::with_capacity(page_size);
+        while let Some(row) = rows.next()? {
+            match (extractor.map_row)(row) {
+                Ok(row_records) => page.extend(row_records),
+                Err(e) => eprintln!("Error reading {} record: {}", extractor.name, e),
+            }
+            if page.len() >= page_size {
+                read += page.len();
+                self.link_workouts(&conn, &mut page)?;
+                // The receiver is gone when writing failed
+                if !send(std::mem::replace(&mut page, Vec::with_capacity(page_size))) {
+                    return Ok(read);
                 }
-                Err(e) => eprintln!("Error fetching exercise session data: {}", e),
             }
         }
-
-        Ok(all_data)
+        if !page.is_empty() {
+            read += page.len();
+            self.link_workouts(&conn, &mut page)?;
+            send(page);
+        }
+        Ok(read)
     }
 
-    /// Retrieves heart rate data with gap-filling for the last week
-    /// This method checks what data already exists in InfluxDB and only imports missing data points
+    /// Retrieves the heart rate samples of the last `days_back` days that are missing from
+    /// InfluxDB
+    /// Samples are first counted per `window` (counted from the Unix epoch) on both sides;
+    /// only the windows whose counts disagree have their existing timestamps fetched and
+    /// compared, so a mostly synced range costs little more than two count queries
     pub async fn get_heart_rate_with_gap_filling(
         &self,
         influx_client: &crate::influx_client::InfluxClient,
         days_back: i64,
+        window: Duration,
     ) -> Result<Vec<HealthRecord>, Box<dyn Error>> {
         if !self.db_exists() {
             return Err(format!("Database file does not exist: {}", self.db_path).into());
         }
 
-        println!(
+        progress!(
             "Starting heart rate gap-filling for the last {} days",
             days_back
         );
 
-        // Get existing timestamps from InfluxDB
-        let existing_timestamps = influx_client
-            .get_existing_heart_rate_timestamps(days_back)
-            .await?;
-
         let conn = self.open_connection()?;
         let mut records = Vec::new();
 
-        // Calculate the time range for the last week
         let end_time = Utc::now();
         let start_time = end_time - chrono::Duration::days(days_back);
         let start_timestamp_millis = start_time.timestamp_millis();
+        let window_millis = window.as_secs().max(1) as i64 * 1000;
 
-        println!();
-        println!("📊 Heart Rate Gap-Filling Analysis");
-        println!("=====================================");
-        println!(
+        progress!();
+        progress!("{}", output::bold("Heart Rate Gap-Filling Analysis"));
+        progress!("=====================================");
+        progress!(
             "Time range: {} to {} ({} days)",
             start_time.format("%Y-%m-%d %H:%M:%S"),
             end_time.format("%Y-%m-%d %H:%M:%S"),
             days_back
         );
-        println!(
-            "InfluxDB existing data points: {}",
-            existing_timestamps.len()
-        );
-
-        // First, count total records in the time range to show progress
-        let count_query = "SELECT COUNT(*) FROM heart_rate_record_series_table hrs
-                          WHERE hrs.epoch_millis >= ?";
-
-        let total_db_records = match conn.prepare(count_query) {
-            Ok(mut stmt) => stmt
-                .query_row([start_timestamp_millis], |row| row.get::<_, i64>(0))
-                .unwrap_or(0),
-            Err(_) => 0,
-        };
 
-        println!(
+        let source_counts =
+            match count_heart_rate_per_window(&conn, start_timestamp_millis, window_millis) {
+                Ok(counts) => counts,
+                Err(e) if e.to_string().contains("no such table") => {
+                    progress!("Heart rate table not found in database");
+                    return Ok(Vec::new());
+                }
+                Err(e) => return Err(Box::new(e)),
+            };
+        let total_db_records: u64 = source_counts.values().sum();
+        progress!(
             "SQLite database records (time range):   {}",
             total_db_records
         );
-        println!();
 
         if total_db_records == 0 {
+            progress!();
             println!(
-                "⚠️  No heart rate data found in SQLite database for the specified time range"
+                "{}",
+                output::warning(
+                    "No heart rate data found in SQLite database for the specified time range"
+                )
             );
             return Ok(Vec::new());
         }
 
-        println!("🔍 Processing records and checking for gaps...");
-
-        // Query for heart rate records from the last week
-        let query = "SELECT hrs.epoch_millis, hrs.beats_per_minute, ai.app_name
-                     FROM heart_rate_record_series_table hrs
-                     LEFT JOIN heart_rate_record_table hrr ON hrs.parent_key = hrr.row_id
-                     LEFT JOIN application_info_table ai ON hrr.app_info_id = ai.row_id
-                     WHERE hrs.epoch_millis >= ?
-                     ORDER BY hrs.epoch_millis ASC";
-
-        let mut stmt = match conn.prepare(query) {
-            Ok(stmt) => stmt,
+        let measurement = HealthDataType::HeartRate.as_str();
+        let sink_counts = match influx_client
+            .count_points_per_window(measurement, start_time, end_time, window)
+            .await
+        {
+            Ok(counts) => counts,
+            // Stop instead of importing everything as duplicates
+            Err(e) if crate::influx_client::is_timeout(e.as_ref()) => return Err(e),
             Err(e) => {
-                // If the table doesn't exist, return empty results
-                if e.to_string().contains("no such table") {
-                    println!("Heart rate table not found in database");
-                    return Ok(Vec::new());
-                }
-                return Err(Box::new(e));
+                progress!("Warning: Failed to count existing heart rate data: {}", e);
+                progress!("Comparing the timestamps of every window instead");
+                BTreeMap::new()
             }
         };
+        progress!(
+            "InfluxDB existing data points:          {}",
+            sink_counts.values().sum::<u64>()
+        );
+
+        let ranges = gap_ranges(&source_counts, &sink_counts, window);
+        let disagreeing: HashSet<i64> = source_counts
+            .iter()
+            .filter(|(start, count)| sink_counts.get(start) != Some(count))
+            .map(|(start, _)| start.timestamp_millis())
+            .collect();
+        let total_to_check: u64 = source_counts
+            .iter()
+            .filter(|(start, _)| disagreeing.contains(&start.timestamp_millis()))
+            .map(|(_, count)| count)
+            .sum();
+        progress!(
+            "Windows whose counts disagree:          {} of {} ({} records to check)",
+            disagreeing.len(),
+            source_counts.len(),
+            total_to_check
+        );
+        progress!();
 
-        let mut rows = stmt.query([start_timestamp_millis])?;
         let mut total_count = 0;
         let mut new_count = 0;
-        let mut duplicate_count = 0;
-        let progress_interval = std::cmp::max(1, total_db_records / 10); // Show progress every 10%
-
-        while let Some(row_result) = rows.next()? {
-            total_count += 1;
-
-            // Show progress every 10% or for smaller datasets, every 1000 records
-            if total_count % progress_interval == 0 || total_count % 1000 == 0 {
-                let progress_percent = (total_count as f64 / total_db_records as f64) * 100.0;
-                println!(
-                    "  Progress: {:.1}% ({}/{} records processed, {} gaps found so far)",
-                    progress_percent, total_count, total_db_records, new_count
-                );
-            }
-
-            // Get the timestamp from the row to check if it already exists
-            let time_millis: i64 = row_result.get(0)?;
-
-            // Check if this timestamp already exists in InfluxDB
-            if existing_timestamps.contains(&time_millis) {
-                duplicate_count += 1;
-                continue; // Skip this record as it already exists
-            }
+        if !ranges.is_empty() {
+            let existing_timestamps = match influx_client
+                .get_existing_timestamps_in(measurement, &ranges)
+                .await
+            {
+                Ok(timestamps) => timestamps,
+                Err(e) if crate::influx_client::is_timeout(e.as_ref()) => return Err(e),
+                Err(e) => {
+                    progress!("Warning: Failed to query existing heart rate data: {}", e);
+                    progress!("Proceeding with normal import (may result in duplicates)");
+                    HashSet::new()
+                }
+            };
+
+            progress!("Processing records and checking for gaps...");
+
+            // Query for heart rate records from the start of the range
+            let query = "SELECT hrs.epoch_millis, hrs.beats_per_minute, ai.app_name
+                         FROM heart_rate_record_series_table hrs
+                         LEFT JOIN heart_rate_record_table hrr ON hrs.parent_key = hrr.row_id
+                         LEFT JOIN application_info_table ai ON hrr.app_info_id = ai.row_id
+                         WHERE hrs.epoch_millis >= ?
+                         ORDER BY hrs.epoch_millis ASC";
+            let mut stmt = conn.prepare(query)?;
+            let mut rows = stmt.query([start_timestamp_millis])?;
+            // Show progress every 10%
+            let progress_interval = std::cmp::max(1, total_to_check / 10);
+
+            while let Some(row_result) = rows.next()? {
+                let time_millis: i64 = row_result.get(0)?;
+                // Samples in windows whose counts agree are all in InfluxDB already
+                if !disagreeing.contains(&(time_millis.div_euclid(window_millis) * window_millis)) {
+                    continue;
+                }
+                total_count += 1;
+
+                if total_count % progress_interval == 0 || total_count % 1000 == 0 {
+                    let progress_percent = (total_count as f64 / total_to_check as f64) * 100.0;
+                    progress!(
+                        "  Progress: {:.1}% ({}/{} records checked, {} gaps found so far)",
+                        progress_percent,
+                        total_count,
+                        total_to_check,
+                        new_count
+                    );
+                }
 
-            // This is a new record, add it to the import list
-            match self.map_heart_rate_row(row_result) {
-                Ok(record) => {
-                    records.push(record);
-                    new_count += 1;
+                if existing_timestamps.contains(&time_millis) {
+                    continue;
+                }
+                match map_heart_rate_row(row_result) {
+                    Ok(row_records) => {
+                        records.extend(row_records);
+                        new_count += 1;
+                    }
+                    Err(e) => eprintln!("Error reading heart rate record: {}", e),
                 }
-                Err(e) => eprintln!("Error reading heart rate record: {}", e),
             }
         }
 
-        println!();
-        println!("📈 Gap-Filling Summary");
-        println!("======================");
-        println!(
+        let duplicate_count = total_db_records - new_count;
+        progress!();
+        progress!("{}", output::bold("Gap-Filling Summary"));
+        progress!("======================");
+        progress!(
             "SQLite database records (last {} days): {}",
-            days_back, total_count
+            days_back,
+            total_db_records
         );
-        println!(
+        progress!("Records checked one by one:              {}", total_count);
+        progress!(
             "InfluxDB existing records:               {}",
             duplicate_count
         );
-        println!("Gap-filled records to import:            {}", new_count);
-        println!();
-
-        if total_count > 0 {
-            let coverage_percent = (duplicate_count as f64 / total_count as f64) * 100.0;
-            println!(
-                "📊 Data Coverage: {:.1}% ({} of {} records already in InfluxDB)",
-                coverage_percent, duplicate_count, total_count
+        progress!("Gap-filled records to import:            {}", new_count);
+        progress!();
+
+        let coverage_percent = (duplicate_count as f64 / total_db_records as f64) * 100.0;
+        progress!(
+            "Data Coverage: {:.1}% ({} of {} records already in InfluxDB)",
+            coverage_percent,
+            duplicate_count,
+            total_db_records
+        );
+        if new_count > 0 {
+            progress!(
+                "Action: {} new records will be imported to fill gaps",
+                new_count
             );
-
-            if new_count > 0 {
-                println!(
-                    "🔄 Action: {} new records will be imported to fill gaps",
-                    new_count
-                );
-            } else {
-                println!("✅ Action: No gaps found - all data is already in InfluxDB");
-            }
         } else {
-            println!(
-                "⚠️  No heart rate data found in SQLite database for the specified time range"
+            progress!(
+                "{}",
+                output::success("Action: No gaps found - all data is already in InfluxDB")
             );
         }
 
+        self.link_workouts(&conn, &mut records)?;
         Ok(records)
     }
 }
+
+/// Keeps one sleep session per night when the sessions of several apps overlap, so the
+/// stages of the same night are not written twice: the session of the app earliest in
+/// `priority`, then the one with the most stages, as the most detailed, then the longest
+fn dedupe_sleep_sessions(records: Vec<HealthRecord>, priority: &[String]) -> Vec<HealthRecord> {
+    struct Session<'a> {
+        row_id: i64,
+        app: &'a str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        stages: usize,
+    }
+
+    // Every record of a session carries its row_id; the stage records span the session
+    let mut sessions: HashMap<i64, Session> = HashMap::new();
+    for record in &records {
+        let (Some(row_id), HealthDataType::Sleep) = (record.row_id, record.record_type) else {
+            continue;
+        };
+        let session = sessions.entry(row_id).or_insert_with(|| Session {
+            row_id,
+            app: record
+                .metadata
+                .get("app_name")
+                .map(String::as_str)
+                .unwrap_or("unknown"),
+            start: record.timestamp,
+            end: record.timestamp,
+            stages: 0,
+        });
+        session.start = session.start.min(record.timestamp);
+        session.end = session.end.max(record.timestamp);
+        if record.metadata.get("event_type").map(String::as_str) == Some("start") {
+            session.stages += 1;
+        }
+    }
+
+    let rank = |app: &str| {
+        priority
+            .iter()
+            .position(|preferred| preferred.eq_ignore_ascii_case(app))
+            .unwrap_or(priority.len())
+    };
+    let mut sessions: Vec<Session> = sessions.into_values().collect();
+    sessions.sort_by(|a, b| {
+        rank(a.app)
+            .cmp(&rank(b.app))
+            .then(b.stages.cmp(&a.stages))
+            .then((b.end - b.start).cmp(&(a.end - a.start)))
+            .then(a.row_id.cmp(&b.row_id))
+    });
+
+    let mut kept: Vec<&Session> = Vec::new();
+    let mut dropped = HashSet::new();
+    for session in &sessions {
+        if kept
+            .iter()
+            .any(|other| session.start < other.end && other.start < session.end)
+        {
+            dropped.insert(session.row_id);
+        } else {
+            kept.push(session);
+        }
+    }
+    if dropped.is_empty() {
+        return records;
+    }
+    records
+        .into_iter()
+        .filter(|record| {
+            record
+                .row_id
+                .is_none_or(|row_id| !dropped.contains(&row_id))
+        })
+        .collect()
+}
+
+/// Reads one Health Connect table into records of one or more data types
+/// Adding a data type takes a `HealthDataType` variant and an extractor with its row mapper
+struct Extractor {
+    /// Data types of the records; types read from the same table share one query
+    data_types: &'static [HealthDataType],
+    /// Describes the records in error messages
+    name: &'static str,
+    /// Query selecting the records, with `{filter}` where the starting point's WHERE clause goes
+    query: &'static str,
+    /// Columns compared with a timestamp or row_id starting point
+    time_column: &'static str,
+    row_id_column: &'static str,
+    /// Column holding the UTC offset of the records, selected where the query has
+    /// `{zone_offset}`; NULL is selected instead in exports that predate it
+    zone_offset_column: &'static str,
+    /// Maps a row of the query to its records
+    map_row: fn(&Row) -> SqliteResult<Vec<HealthRecord>>,
+}
+
+const HEART_RATE: Extractor = Extractor {
+    data_types: &[HealthDataType::HeartRate],
+    name: "heart rate",
+    query: "SELECT hrs.epoch_millis, hrs.beats_per_minute, ai.app_name, hr.row_id, {zone_offset}
+                 FROM heart_rate_record_series_table hrs
+                 JOIN heart_rate_record_table hr ON hrs.parent_key = hr.row_id
+                 LEFT JOIN application_info_table ai ON hr.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY hrs.epoch_millis ASC",
+    time_column: "hrs.epoch_millis",
+    row_id_column: "hr.row_id",
+    zone_offset_column: "hr.start_zone_offset",
+    map_row: map_heart_rate_row,
+};
+
+const STEPS: Extractor = Extractor {
+    data_types: &[HealthDataType::Steps],
+    name: "steps",
+    query: "SELECT start_time, count, ai.app_name, sr.row_id, {zone_offset}
+                 FROM steps_record_table sr
+                 LEFT JOIN application_info_table ai ON sr.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY start_time ASC",
+    time_column: "start_time",
+    row_id_column: "sr.row_id",
+    zone_offset_column: "sr.start_zone_offset",
+    map_row: map_steps_row,
+};
+
+const STEPS_CADENCE: Extractor = Extractor {
+    data_types: &[HealthDataType::StepsCadence],
+    name: "steps cadence",
+    query: "SELECT scs.epoch_millis, scs.rate, ai.app_name, sc.row_id, {zone_offset}
+                 FROM steps_cadence_record_table scs
+                 JOIN StepsCadenceRecordTable sc ON scs.parent_key = sc.row_id
+                 LEFT JOIN application_info_table ai ON sc.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY scs.epoch_millis ASC",
+    time_column: "scs.epoch_millis",
+    row_id_column: "sc.row_id",
+    zone_offset_column: "sc.start_zone_offset",
+    map_row: map_steps_cadence_row,
+};
+
+const SLEEP: Extractor = Extractor {
+    data_types: &[
+        HealthDataType::Sleep,
+        HealthDataType::SleepDuration,
+        HealthDataType::SleepState,
+    ],
+    name: "sleep",
+    query: "SELECT ss.start_time, ss.end_time, st.stage_type, ai.app_name, ss.row_id, {zone_offset}
+                 FROM sleep_session_record_table ss
+                 JOIN sleep_stages_table st ON st.parent_key = ss.row_id
+                 LEFT JOIN application_info_table ai ON ss.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY ss.start_time ASC, st.stage_start_time ASC",
+    time_column: "ss.start_time",
+    row_id_column: "ss.row_id",
+    zone_offset_column: "ss.start_zone_offset",
+    map_row: map_sleep_row,
+};
+
+const WEIGHT: Extractor = Extractor {
+    data_types: &[HealthDataType::Weight],
+    name: "weight",
+    query: "SELECT wr.time, wr.weight, ai.app_name, wr.row_id, {zone_offset}
+                 FROM weight_record_table wr
+                 LEFT JOIN application_info_table ai ON wr.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY wr.time ASC",
+    time_column: "wr.time",
+    row_id_column: "wr.row_id",
+    zone_offset_column: "wr.zone_offset",
+    map_row: map_weight_row,
+};
+
+const ACTIVE_CALORIES: Extractor = Extractor {
+    data_types: &[HealthDataType::ActiveCalories],
+    name: "active calories",
+    query: "SELECT acb.start_time, acb.end_time, acb.energy, ai.app_name, acb.row_id, {zone_offset}
+                 FROM active_calories_burned_record_table acb
+                 LEFT JOIN application_info_table ai ON acb.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY acb.start_time ASC",
+    time_column: "acb.start_time",
+    row_id_column: "acb.row_id",
+    zone_offset_column: "acb.start_zone_offset",
+    map_row: map_active_calories_row,
+};
+
+const TOTAL_CALORIES: Extractor = Extractor {
+    data_types: &[HealthDataType::TotalCalories],
+    name: "total calories",
+    query: "SELECT tcb.start_time, tcb.end_time, tcb.energy, ai.app_name, tcb.row_id, {zone_offset}
+                 FROM total_calories_burned_record_table tcb
+                 LEFT JOIN application_info_table ai ON tcb.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY tcb.start_time ASC",
+    time_column: "tcb.start_time",
+    row_id_column: "tcb.row_id",
+    zone_offset_column: "tcb.start_zone_offset",
+    map_row: map_total_calories_row,
+};
+
+const BASAL_METABOLIC_RATE: Extractor = Extractor {
+    data_types: &[HealthDataType::BasalMetabolicRate],
+    name: "basal metabolic rate",
+    query: "SELECT bmr.time, bmr.basal_metabolic_rate, ai.app_name, bmr.row_id, {zone_offset}
+                 FROM basal_metabolic_rate_record_table bmr
+                 LEFT JOIN application_info_table ai ON bmr.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY bmr.time ASC",
+    time_column: "bmr.time",
+    row_id_column: "bmr.row_id",
+    zone_offset_column: "bmr.zone_offset",
+    map_row: map_basal_metabolic_rate_row,
+};
+
+const BODY_FAT: Extractor = Extractor {
+    data_types: &[HealthDataType::BodyFat],
+    name: "body fat",
+    query: "SELECT bf.time, bf.percentage, ai.app_name, bf.row_id, {zone_offset}
+                 FROM body_fat_record_table bf
+                 LEFT JOIN application_info_table ai ON bf.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY bf.time ASC",
+    time_column: "bf.time",
+    row_id_column: "bf.row_id",
+    zone_offset_column: "bf.zone_offset",
+    map_row: map_body_fat_row,
+};
+
+const LEAN_BODY_MASS: Extractor = Extractor {
+    data_types: &[HealthDataType::LeanBodyMass],
+    name: "lean body mass",
+    query: "SELECT lbm.time, lbm.mass, ai.app_name, lbm.row_id, {zone_offset}
+                 FROM lean_body_mass_record_table lbm
+                 LEFT JOIN application_info_table ai ON lbm.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY lbm.time ASC",
+    time_column: "lbm.time",
+    row_id_column: "lbm.row_id",
+    zone_offset_column: "lbm.zone_offset",
+    map_row: map_lean_body_mass_row,
+};
+
+const HEIGHT: Extractor = Extractor {
+    data_types: &[HealthDataType::Height],
+    name: "height",
+    query: "SELECT h.time, h.height, ai.app_name, h.row_id, {zone_offset}
+                 FROM height_record_table h
+                 LEFT JOIN application_info_table ai ON h.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY h.time ASC",
+    time_column: "h.time",
+    row_id_column: "h.row_id",
+    zone_offset_column: "h.zone_offset",
+    map_row: map_height_row,
+};
+
+const EXERCISE_SESSION: Extractor = Extractor {
+    data_types: &[HealthDataType::ExerciseSession],
+    name: "exercise session",
+    query: "SELECT es.start_time, es.end_time, es.exercise_type, es.title, ai.app_name, es.row_id, {zone_offset}
+                 FROM exercise_session_record_table es
+                 LEFT JOIN application_info_table ai ON es.app_info_id = ai.row_id
+                 {filter}
+                 ORDER BY es.start_time ASC",
+    time_column: "es.start_time",
+    row_id_column: "es.row_id",
+    zone_offset_column: "es.start_zone_offset",
+    map_row: map_exercise_session_row,
+};
+
+/// Gets what an extractor selects as the zone offset: its column, or NULL when the record
+/// table lacks it (or is missing)
+fn zone_offset_column(conn: &Connection, extractor: &Extractor) -> SqliteResult<&'static str> {
+    let table = extractor.data_types[0].record_table();
+    let column = extractor
+        .zone_offset_column
+        .split_once('.')
+        .map_or(extractor.zone_offset_column, |(_, column)| column);
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        [table, column],
+        |row| row.get(0),
+    )?;
+    Ok(if exists {
+        extractor.zone_offset_column
+    } else {
+        "NULL"
+    })
+}
+
+/// Every extractor, in the order data types are read
+const EXTRACTORS: [&Extractor; 12] = [
+    &HEART_RATE,
+    &STEPS,
+    &STEPS_CADENCE,
+    &SLEEP,
+    &WEIGHT,
+    &ACTIVE_CALORIES,
+    &TOTAL_CALORIES,
+    &BASAL_METABOLIC_RATE,
+    &BODY_FAT,
+    &LEAN_BODY_MASS,
+    &HEIGHT,
+    &EXERCISE_SESSION,
+];
+
+/// Maps a database row to a HeartRate HealthRecord
+fn map_heart_rate_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(3).ok();
+    let zone_offset: Option<i32> = row.get(4).ok().flatten();
+    let time_millis: i64 = row.get(0)?;
+    let value: i64 = row.get(1)?; // beats_per_minute is an INTEGER in the schema
+    let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
+
+    let timestamp = Utc
+        .timestamp_millis_opt(time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    let mut metadata = HashMap::new();
+    metadata.insert("app_name".to_string(), app_name);
+
+    Ok(vec![HealthRecord {
+        record_type: HealthDataType::HeartRate,
+        timestamp,
+        value: value as f64, // Convert INTEGER to f64
+        metadata,
+        row_id,
+        zone_offset,
+    }])
+}
+
+/// Maps a database row to a StepsCadence HealthRecord: one sample of the step rate
+fn map_steps_cadence_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(3).ok();
+    let zone_offset: Option<i32> = row.get(4).ok().flatten();
+    let time_millis: i64 = row.get(0)?;
+    let rate: f64 = row.get(1)?;
+    let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
+
+    let timestamp = Utc
+        .timestamp_millis_opt(time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    let mut metadata = HashMap::new();
+    metadata.insert("app_name".to_string(), app_name);
+    metadata.insert("unit".to_string(), "steps/min".to_string());
+
+    Ok(vec![HealthRecord {
+        record_type: HealthDataType::StepsCadence,
+        timestamp,
+        value: rate,
+        metadata,
+        row_id,
+        zone_offset,
+    }])
+}
+
+/// Maps a database row to a Steps HealthRecord
+fn map_steps_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(3).ok();
+    let zone_offset: Option<i32> = row.get(4).ok().flatten();
+    let time_millis: i64 = row.get(0)?;
+    let value: i64 = row.get(1)?; // count is an INTEGER in the schema
+    let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
+
+    let timestamp = Utc
+        .timestamp_millis_opt(time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    let mut metadata = HashMap::new();
+    metadata.insert("app_name".to_string(), app_name);
+
+    Ok(vec![HealthRecord {
+        record_type: HealthDataType::Steps,
+        timestamp,
+        value: value as f64, // Convert INTEGER to f64
+        metadata,
+        row_id,
+        zone_offset,
+    }])
+}
+
+/// Gets the name of a Health Connect sleep stage type
+pub fn stage_name(stage_type: i64) -> &'static str {
+    match stage_type {
+        1 => "AWAKE",
+        2 => "SLEEPING",
+        3 => "OUT_OF_BED",
+        4 => "LIGHT",
+        5 => "DEEP",
+        6 => "REM",
+        _ => "UNKNOWN",
+    }
+}
+
+/// Gets the value a sleep stage type is written as, deeper sleep higher, so Grafana can
+/// chart it
+pub fn stage_value(stage_type: i64) -> f64 {
+    match stage_type {
+        1 => 0.0,  // AWAKE
+        2 => 1.0,  // SLEEPING (generic)
+        3 => 0.0,  // OUT_OF_BED
+        4 => 2.0,  // LIGHT
+        5 => 3.0,  // DEEP
+        6 => 4.0,  // REM
+        _ => -1.0, // UNKNOWN
+    }
+}
+
+/// Maps a database row to multiple Sleep HealthRecords (start and end points)
+fn map_sleep_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(4).ok();
+    let zone_offset: Option<i32> = row.get(5).ok().flatten();
+    let start_time_millis: i64 = row.get(0)?;
+    let end_time_millis: i64 = row.get(1)?;
+    let stage_type: i64 = row.get(2)?;
+    let app_name: String = row.get(3).unwrap_or_else(|_| "unknown".to_string());
+
+    let start_timestamp = Utc
+        .timestamp_millis_opt(start_time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    let end_timestamp = Utc
+        .timestamp_millis_opt(end_time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    // Calculate duration in minutes as the value; epoch millis count elapsed time, so a
+    // night across a DST change keeps its real length
+    let duration_millis = end_time_millis - start_time_millis;
+    let duration_minutes = duration_millis as f64 / (1000.0 * 60.0);
+
+    let stage_description = stage_name(stage_type);
+    let stage_value = stage_value(stage_type);
+
+    let mut results = Vec::new();
+
+    // Create metadata for the start point
+    let mut start_metadata = HashMap::new();
+    start_metadata.insert("app_name".to_string(), app_name.clone());
+    start_metadata.insert("stage".to_string(), stage_description.to_string());
+    start_metadata.insert("stage_type".to_string(), stage_type.to_string());
+    start_metadata.insert("event_type".to_string(), "start".to_string());
+    start_metadata.insert("duration_minutes".to_string(), duration_minutes.to_string());
+
+    // Start point - Main data point with stage value
+    results.push(HealthRecord {
+        record_type: HealthDataType::Sleep,
+        timestamp: start_timestamp,
+        value: stage_value, // Use stage value for visualization
+        metadata: start_metadata,
+        row_id,
+        zone_offset,
+    });
+
+    // Create metadata for the end point
+    let mut end_metadata = HashMap::new();
+    end_metadata.insert("app_name".to_string(), app_name.clone());
+    end_metadata.insert("stage".to_string(), stage_description.to_string());
+    end_metadata.insert("stage_type".to_string(), stage_type.to_string());
+    end_metadata.insert("event_type".to_string(), "end".to_string());
+    end_metadata.insert("duration_minutes".to_string(), duration_minutes.to_string());
+
+    // End point
+    results.push(HealthRecord {
+        record_type: HealthDataType::Sleep,
+        timestamp: end_timestamp,
+        value: 0.0, // End of this sleep stage
+        metadata: end_metadata,
+        row_id,
+        zone_offset,
+    });
+
+    // Add a sleep session record with duration for Grafana
+    let mut duration_metadata = HashMap::new();
+    duration_metadata.insert("app_name".to_string(), app_name.clone());
+    duration_metadata.insert("stage".to_string(), stage_description.to_string());
+    duration_metadata.insert("stage_type".to_string(), stage_type.to_string());
+    duration_metadata.insert("record_subtype".to_string(), "duration".to_string());
+
+    // Additional point for duration - can be used with Grafana Bar Gauge
+    results.push(HealthRecord {
+        record_type: HealthDataType::SleepDuration,
+        timestamp: start_timestamp,
+        value: duration_minutes, // Duration in minutes for bar charts
+        metadata: duration_metadata,
+        row_id,
+        zone_offset,
+    });
+
+    // Add a sleep state point for continuous state visualization
+    let mut state_metadata = HashMap::new();
+    state_metadata.insert("app_name".to_string(), app_name);
+    state_metadata.insert("stage".to_string(), stage_description.to_string());
+    state_metadata.insert("stage_type".to_string(), stage_type.to_string());
+
+    // State point for Grafana State Timeline visualization
+    results.push(HealthRecord {
+        record_type: HealthDataType::SleepState,
+        timestamp: start_timestamp,
+        value: stage_value, // Numeric value representing the sleep stage
+        metadata: state_metadata,
+        row_id,
+        zone_offset,
+    });
+
+    Ok(results)
+}
+
+/// Maps a database row to a Weight HealthRecord
+fn map_weight_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(3).ok();
+    let zone_offset: Option<i32> = row.get(4).ok().flatten();
+    let time_millis: i64 = row.get(0)?;
+    let weight_value: f64 = row.get(1)?;
+    let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
+
+    let timestamp = Utc
+        .timestamp_millis_opt(time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    let mut metadata = HashMap::new();
+    metadata.insert("app_name".to_string(), app_name);
+    metadata.insert("unit".to_string(), "g".to_string());
+
+    Ok(vec![HealthRecord {
+        record_type: HealthDataType::Weight,
+        timestamp,
+        value: weight_value,
+        metadata,
+        row_id,
+        zone_offset,
+    }])
+}
+
+/// Maps a database row to an ActiveCalories HealthRecord
+fn map_active_calories_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(4).ok();
+    let zone_offset: Option<i32> = row.get(5).ok().flatten();
+    let start_time_millis: i64 = row.get(0)?;
+    let end_time_millis: i64 = row.get(1)?;
+    let energy_value: f64 = row.get(2)?;
+    let app_name: String = row.get(3).unwrap_or_else(|_| "unknown".to_string());
+
+    let timestamp = Utc
+        .timestamp_millis_opt(start_time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    // Calculate duration in minutes
+    let duration_millis = end_time_millis - start_time_millis;
+    let duration_minutes = duration_millis as f64 / (1000.0 * 60.0);
+
+    let mut metadata = HashMap::new();
+    metadata.insert("app_name".to_string(), app_name);
+    metadata.insert("unit".to_string(), "kcal".to_string());
+    metadata.insert("duration_minutes".to_string(), duration_minutes.to_string());
+    metadata.insert(
+        "end_time".to_string(),
+        Utc.timestamp_millis_opt(end_time_millis)
+            .single()
+            .unwrap_or_else(Utc::now)
+            .to_rfc3339(),
+    );
+
+    Ok(vec![HealthRecord {
+        record_type: HealthDataType::ActiveCalories,
+        timestamp,
+        value: energy_value,
+        metadata,
+        row_id,
+        zone_offset,
+    }])
+}
+
+/// Maps a database row to a TotalCalories HealthRecord
+fn map_total_calories_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(4).ok();
+    let zone_offset: Option<i32> = row.get(5).ok().flatten();
+    let start_time_millis: i64 = row.get(0)?;
+    let end_time_millis: i64 = row.get(1)?;
+    let energy_value: f64 = row.get(2)?;
+    let app_name: String = row.get(3).unwrap_or_else(|_| "unknown".to_string());
+
+    let start_timestamp = Utc
+        .timestamp_millis_opt(start_time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    // Calculate duration in hours for metadata
+    let duration_millis = end_time_millis - start_time_millis;
+    let duration_hours = duration_millis as f64 / (1000.0 * 60.0 * 60.0);
+
+    let mut metadata = HashMap::new();
+    metadata.insert("app_name".to_string(), app_name);
+    metadata.insert("unit".to_string(), "calories".to_string());
+    metadata.insert("duration_hours".to_string(), duration_hours.to_string());
+    metadata.insert(
+        "start_time_millis".to_string(),
+        start_time_millis.to_string(),
+    );
+    metadata.insert("end_time_millis".to_string(), end_time_millis.to_string());
+
+    Ok(vec![HealthRecord {
+        record_type: HealthDataType::TotalCalories,
+        timestamp: start_timestamp,
+        value: energy_value,
+        metadata,
+        row_id,
+        zone_offset,
+    }])
+}
+
+/// Maps a database row to a BasalMetabolicRate HealthRecord
+fn map_basal_metabolic_rate_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(3).ok();
+    let zone_offset: Option<i32> = row.get(4).ok().flatten();
+    let time_millis: i64 = row.get(0)?;
+    let bmr_value: f64 = row.get(1)?;
+    let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
+
+    let timestamp = Utc
+        .timestamp_millis_opt(time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    let mut metadata = HashMap::new();
+    metadata.insert("app_name".to_string(), app_name);
+    metadata.insert("unit".to_string(), "calories_per_day".to_string());
+
+    Ok(vec![HealthRecord {
+        record_type: HealthDataType::BasalMetabolicRate,
+        timestamp,
+        value: bmr_value,
+        metadata,
+        row_id,
+        zone_offset,
+    }])
+}
+
+/// Maps a database row to a BodyFat HealthRecord
+fn map_body_fat_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(3).ok();
+    let zone_offset: Option<i32> = row.get(4).ok().flatten();
+    let time_millis: i64 = row.get(0)?;
+    let percentage_value: f64 = row.get(1)?;
+    let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
+
+    let timestamp = Utc
+        .timestamp_millis_opt(time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    let mut metadata = HashMap::new();
+    metadata.insert("app_name".to_string(), app_name);
+    metadata.insert("unit".to_string(), "percentage".to_string());
+
+    Ok(vec![HealthRecord {
+        record_type: HealthDataType::BodyFat,
+        timestamp,
+        value: percentage_value,
+        metadata,
+        row_id,
+        zone_offset,
+    }])
+}
+
+/// Maps a database row to a LeanBodyMass HealthRecord
+fn map_lean_body_mass_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(3).ok();
+    let zone_offset: Option<i32> = row.get(4).ok().flatten();
+    let time_millis: i64 = row.get(0)?;
+    let mass_value: f64 = row.get(1)?;
+    let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
+
+    let timestamp = Utc
+        .timestamp_millis_opt(time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    let mut metadata = HashMap::new();
+    metadata.insert("app_name".to_string(), app_name);
+    metadata.insert("unit".to_string(), "g".to_string());
+
+    Ok(vec![HealthRecord {
+        record_type: HealthDataType::LeanBodyMass,
+        timestamp,
+        value: mass_value,
+        metadata,
+        row_id,
+        zone_offset,
+    }])
+}
+
+/// Maps a database row to a Height HealthRecord
+fn map_height_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(3).ok();
+    let zone_offset: Option<i32> = row.get(4).ok().flatten();
+    let time_millis: i64 = row.get(0)?;
+    let height_value: f64 = row.get(1)?;
+    let app_name: String = row.get(2).unwrap_or_else(|_| "unknown".to_string());
+
+    let timestamp = Utc
+        .timestamp_millis_opt(time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    let mut metadata = HashMap::new();
+    metadata.insert("app_name".to_string(), app_name);
+    metadata.insert("unit".to_string(), "m".to_string());
+
+    Ok(vec![HealthRecord {
+        record_type: HealthDataType::Height,
+        timestamp,
+        value: height_value,
+        metadata,
+        row_id,
+        zone_offset,
+    }])
+}
+
+/// Maps a database row to an ExerciseSession HealthRecord
+fn map_exercise_session_row(row: &Row) -> SqliteResult<Vec<HealthRecord>> {
+    let row_id: Option<i64> = row.get(5).ok();
+    let zone_offset: Option<i32> = row.get(6).ok().flatten();
+    let start_time_millis: i64 = row.get(0)?;
+    let end_time_millis: i64 = row.get(1)?;
+    let exercise_type: i64 = row.get(2)?;
+    let title: String = row.get(3).unwrap_or_else(|_| "Unknown".to_string());
+    let app_name: String = row.get(4).unwrap_or_else(|_| "unknown".to_string());
+
+    let start_timestamp = Utc
+        .timestamp_millis_opt(start_time_millis)
+        .single()
+        .unwrap_or_else(Utc::now);
+
+    // Calculate duration in minutes
+    let duration_millis = end_time_millis - start_time_millis;
+    let duration_minutes = duration_millis as f64 / (1000.0 * 60.0);
+
+    let mut metadata = HashMap::new();
+    metadata.insert("app_name".to_string(), app_name);
+    metadata.insert("exercise_type".to_string(), exercise_type.to_string());
+    metadata.insert("title".to_string(), title);
+    metadata.insert("duration_minutes".to_string(), duration_minutes.to_string());
+    metadata.insert(
+        "start_time_millis".to_string(),
+        start_time_millis.to_string(),
+    );
+    metadata.insert("end_time_millis".to_string(), end_time_millis.to_string());
+    metadata.insert("unit".to_string(), "minutes".to_string());
+
+    Ok(vec![HealthRecord {
+        record_type: HealthDataType::ExerciseSession,
+        timestamp: start_timestamp,
+        value: duration_minutes, // Use duration as the value for visualization
+        metadata,
+        row_id,
+        zone_offset,
+    }])
+}
+
+impl Source for HealthDataReader {
+    type Record = HealthRecord;
+
+    fn validate(&self) -> Result<String, Box<dyn Error>> {
+        self.validate_db()
+    }
+
+    /// Reads each data type from where its import stopped, see `read_from`
+    /// The tables are queried concurrently, each on its own thread, and the stream yields
+    /// the records of each table as soon as it and the tables before it are read
+    fn records_since(
+        &self,
+        watermark: &ImportState,
+    ) -> Result<RecordStream<HealthRecord>, Box<dyn Error>> {
+        if !self.db_exists() {
+            return Err(format!("Database file does not exist: {}", self.db_path).into());
+        }
+
+        let since: HashMap<HealthDataType, ReadFrom> = HealthDataType::ALL
+            .into_iter()
+            .map(|data_type| (data_type, self.read_from(watermark, data_type)))
+            .collect();
+
+        let handles: Vec<_> = EXTRACTORS
+            .into_iter()
+            .map(|extractor| {
+                let reader = self.clone();
+                let since = since.clone();
+                thread::spawn(move || {
+                    reader.extract_included(
+                        extractor,
+                        |data_type| since[&data_type],
+                        reader.data_types.as_deref(),
+                    )
+                })
+            })
+            .collect();
+        let records = handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap_or_default());
+        Ok(RecordStream::new(records))
+    }
+
+    fn timestamp(&self, record: &HealthRecord) -> Option<DateTime<Utc>> {
+        Some(record.timestamp)
+    }
+
+    fn to_points(&self, record: &HealthRecord) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+        Ok(vec![health_record_to_point(record)])
+    }
+}
+
+/// Groups records by their data type
+pub fn group_by_type(
+    records: impl IntoIterator<Item = HealthRecord>,
+) -> HashMap<HealthDataType, Vec<HealthRecord>> {
+    let mut records_map: HashMap<HealthDataType, Vec<HealthRecord>> = HashMap::new();
+    for record in records {
+        records_map
+            .entry(record.record_type)
+            .or_default()
+            .push(record);
+    }
+    records_map
+}
+
+/// Splits records into chunks of about `max_records`, in timestamp order across all data types
+/// Once a chunk is written, every record up to its latest timestamp is in InfluxDB, so the
+/// watermarks can safely be advanced to it. Records sharing a timestamp stay in the same chunk,
+/// since a watermark skips everything at or before it. A `max_records` of 0 disables splitting
+/// by count
+///
+/// With a `period`, chunks also never span two periods (counted from the Unix epoch), so a
+/// backfill advances week by week, say, however few records each week holds
+pub fn split_by_time(
+    records_map: HashMap<HealthDataType, Vec<HealthRecord>>,
+    max_records: usize,
+    period: Option<Duration>,
+) -> Vec<HashMap<HealthDataType, Vec<HealthRecord>>> {
+    let total: usize = records_map.values().map(|records| records.len()).sum();
+    if period.is_none() && (max_records == 0 || total <= max_records) {
+        return vec![records_map];
+    }
+
+    let mut all_records: Vec<(HealthDataType, HealthRecord)> = records_map
+        .into_iter()
+        .flat_map(|(record_type, records)| {
+            records.into_iter().map(move |record| (record_type, record))
+        })
+        .collect();
+    all_records.sort_by_key(|(_, record)| record.timestamp);
+
+    let period_of = |record: &HealthRecord| {
+        period.map(|period| {
+            record
+                .timestamp
+                .timestamp_millis()
+                .div_euclid(period.as_millis().max(1) as i64)
+        })
+    };
+    let mut chunks = Vec::new();
+    let mut chunk: HashMap<HealthDataType, Vec<HealthRecord>> = HashMap::new();
+    let mut chunk_len = 0;
+    let mut last_timestamp = None;
+    let mut chunk_period = None;
+    for (record_type, record) in all_records {
+        let full = max_records > 0 && chunk_len >= max_records;
+        let next_period = chunk_len > 0 && period_of(&record) != chunk_period;
+        if (full || next_period) && last_timestamp != Some(record.timestamp) {
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_len = 0;
+        }
+        last_timestamp = Some(record.timestamp);
+        chunk_period = period_of(&record);
+        chunk.entry(record_type).or_default().push(record);
+        chunk_len += 1;
+    }
+    if chunk_len > 0 || chunks.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Takes the oldest `limit` records across all data types, returning them and the records
+/// held back for a later import. Like `split_by_time`, records sharing the timestamp of the
+/// last one taken are kept with it, so the taken records can exceed `limit`
+pub fn take_oldest(
+    records_map: HashMap<HealthDataType, Vec<HealthRecord>>,
+    limit: usize,
+) -> (
+    HashMap<HealthDataType, Vec<HealthRecord>>,
+    HashMap<HealthDataType, Vec<HealthRecord>>,
+) {
+    let mut chunks = split_by_time(records_map, limit, None).into_iter();
+    let taken = chunks.next().unwrap_or_default();
+
+    let mut held_back: HashMap<HealthDataType, Vec<HealthRecord>> = HashMap::new();
+    for (record_type, records) in chunks.flatten() {
+        held_back.entry(record_type).or_default().extend(records);
+    }
+    (taken, held_back)
+}
+
+/// Gets the type affinity SQLite gives a column declared with this type
+/// https://www.sqlite.org/datatype3.html#determination_of_column_affinity
+fn affinity(declared: &str) -> &'static str {
+    let declared = declared.to_uppercase();
+    if declared.contains("INT") {
+        "INTEGER"
+    } else if ["CHAR", "CLOB", "TEXT"]
+        .iter()
+        .any(|name| declared.contains(name))
+    {
+        "TEXT"
+    } else if declared.is_empty() || declared.contains("BLOB") {
+        "BLOB"
+    } else if ["REAL", "FLOA", "DOUB"]
+        .iter()
+        .any(|name| declared.contains(name))
+    {
+        "REAL"
+    } else {
+        "NUMERIC"
+    }
+}
+
+/// Checks if a column declared with a type can be read as the expected affinity: any
+/// number reads as REAL, and columns declared without a type may hold anything
+fn affinity_matches(expected: &str, declared: &str) -> bool {
+    match (expected, affinity(declared)) {
+        (_, "BLOB") if declared.trim().is_empty() => true,
+        ("REAL", "INTEGER" | "NUMERIC") | ("INTEGER", "NUMERIC") => true,
+        (expected, found) => expected == found,
+    }
+}
+
+/// Tells how close a column name is to the expected one, if close enough to be its new
+/// name: the edit distance between the names without underscores, up to 2, or one name
+/// being the start or end of the other, word for word (`count` and `step_count`)
+fn similar_names(expected: &str, name: &str) -> Option<usize> {
+    let normalize = |name: &str| name.to_lowercase().replace('_', "");
+    let distance = edit_distance(&normalize(expected), &normalize(name));
+    if distance <= 2 {
+        return Some(distance);
+    }
+
+    let words = |name: &str| -> Vec<String> {
+        name.to_lowercase()
+            .split('_')
+            .filter(|word| !word.is_empty())
+            .map(str::to_string)
+            .collect()
+    };
+    let (expected, name) = (words(expected), words(name));
+    let (shorter, longer) = if expected.len() <= name.len() {
+        (&expected, &name)
+    } else {
+        (&name, &expected)
+    };
+    (!shorter.is_empty() && (longer.starts_with(shorter) || longer.ends_with(shorter)))
+        .then_some(distance)
+}
+
+/// Counts the single-character insertions, deletions and substitutions turning `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Gets the SQLite URI of a database file, escaping the characters URIs give a meaning to
+fn file_uri(path: &str) -> String {
+    let escaped = path
+        .replace('%', "%25")
+        .replace('?', "%3f")
+        .replace('#', "%23");
+    format!("file:{}", escaped)
+}
+
+/// Identifies a record across exports: its data type, time and metadata (app, device and
+/// the like), so a value edited between two exports is kept from the preferred one
+fn merge_key(record: &HealthRecord) -> String {
+    let metadata: BTreeMap<&String, &String> = record.metadata.iter().collect();
+    format!(
+        "{}|{}|{:?}",
+        record.record_type.as_str(),
+        record.timestamp.timestamp_millis(),
+        metadata
+    )
+}
+
+/// Lists the Health Connect exports at a path: the path itself when it is a file, or the
+/// SQLite files (.db, .sqlite, .sqlite3) of a directory, sorted by name so dated exports
+/// come oldest first
+pub fn export_files(path: &str) -> std::io::Result<Vec<String>> {
+    if !Path::new(path).is_dir() {
+        return Ok(vec![path.to_string()]);
+    }
+    let mut files: Vec<String> = std::fs::read_dir(path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| {
+            file.is_file()
+                && file
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .is_some_and(|extension| {
+                        ["db", "sqlite", "sqlite3"].contains(&extension.to_lowercase().as_str())
+                    })
+        })
+        .map(|file| file.to_string_lossy().into_owned())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Finds the points of a data type in InfluxDB that no longer have a record in the source,
+/// given their timestamps (as Unix milliseconds): records deleted in Health Connect, or
+/// edited to another time. `records` must cover every timestamp of `existing`
+pub fn stale_timestamps(
+    existing: &HashSet<i64>,
+    records: &[HealthRecord],
+    data_type: HealthDataType,
+) -> Vec<i64> {
+    let current: HashSet<i64> = records
+        .iter()
+        .filter(|record| record.record_type == data_type)
+        .map(|record| record.timestamp.timestamp_millis())
+        .collect();
+    let mut stale: Vec<i64> = existing.difference(&current).copied().collect();
+    stale.sort();
+    stale
+}
+
+/// Groups the stale timestamps of a data type (see [`stale_timestamps`]) into ranges of Unix
+/// milliseconds, from the first up to (not including) a millisecond after the last, so runs
+/// of stale points with no kept point or record between them are deleted together
+pub fn stale_ranges(
+    existing: &HashSet<i64>,
+    records: &[HealthRecord],
+    data_type: HealthDataType,
+) -> Vec<(i64, i64)> {
+    let stale: HashSet<i64> = stale_timestamps(existing, records, data_type)
+        .into_iter()
+        .collect();
+    let kept: BTreeSet<i64> = records
+        .iter()
+        .filter(|record| record.record_type == data_type)
+        .map(|record| record.timestamp.timestamp_millis())
+        .chain(existing.difference(&stale).copied())
+        .collect();
+
+    let mut stale: Vec<i64> = stale.into_iter().collect();
+    stale.sort();
+    let mut ranges: Vec<(i64, i64)> = Vec::new();
+    for millis in stale {
+        match ranges.last_mut() {
+            Some((_, stop)) if kept.range(*stop..millis).next().is_none() => *stop = millis + 1,
+            _ => ranges.push((millis, millis + 1)),
+        }
+    }
+    ranges
+}
+
+/// Counts the heart rate samples taken from `start_millis` on per window of `window_millis`
+/// (counted from the Unix epoch), by the start of each window
+fn count_heart_rate_per_window(
+    conn: &Connection,
+    start_millis: i64,
+    window_millis: i64,
+) -> SqliteResult<BTreeMap<DateTime<Utc>, u64>> {
+    let mut stmt = conn.prepare(
+        "SELECT hrs.epoch_millis / ?2 * ?2 AS window, COUNT(*)
+         FROM heart_rate_record_series_table hrs
+         WHERE hrs.epoch_millis >= ?1
+         GROUP BY window",
+    )?;
+    let rows = stmt.query_map([start_millis, window_millis], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?))
+    })?;
+    let mut counts = BTreeMap::new();
+    for row in rows {
+        let (start, count) = row?;
+        if let Some(start) = DateTime::from_timestamp_millis(start) {
+            counts.insert(start, count as u64);
+        }
+    }
+    Ok(counts)
+}
+
+/// Gets the time ranges whose points need comparing one by one: the windows of `window`
+/// whose count in the source differs from the count in InfluxDB, both by the start of the
+/// window. Windows are merged into one range unless a window whose counts agree lies
+/// between them, so the existing points are fetched in few queries
+pub fn gap_ranges(
+    source_counts: &BTreeMap<DateTime<Utc>, u64>,
+    sink_counts: &BTreeMap<DateTime<Utc>, u64>,
+    window: Duration,
+) -> Vec<(DateTime<Utc>, DateTime<Utc>)> {
+    let window = chrono::Duration::seconds(window.as_secs().max(1) as i64);
+    let mut ranges: Vec<(DateTime<Utc>, DateTime<Utc>)> = Vec::new();
+    let mut extending = false;
+    for (start, count) in source_counts {
+        if sink_counts.get(start) == Some(count) {
+            extending = false;
+            continue;
+        }
+        match ranges.last_mut() {
+            Some((_, end)) if extending => *end = *start + window,
+            _ => ranges.push((*start, *start + window)),
+        }
+        extending = true;
+    }
+    ranges
+}
+
+/// Counts records per UTC day, the way `InfluxClient::count_points_per_day` counts the
+/// points they are written as
+pub fn count_per_day(records: &[HealthRecord]) -> BTreeMap<NaiveDate, u64> {
+    let mut counts = BTreeMap::new();
+    for record in records {
+        *counts.entry(record.timestamp.date_naive()).or_default() += 1;
+    }
+    counts
+}
+
+/// Returns the row_id each data type can safely resume from after writing the first
+/// `written` chunks of records, as split by `split_by_time`
+/// Chunks are ordered by timestamp rather than row_id, so a row_id is only safe once
+/// every record with a lower or equal row_id has been written: it is the highest row_id
+/// written, capped below the lowest row_id still waiting in the remaining chunks
+pub fn safe_row_ids(chunks: &[Vec<HealthRecord>], written: usize) -> HashMap<HealthDataType, i64> {
+    let (done, remaining) = chunks.split_at(written.min(chunks.len()));
+
+    let mut row_ids: HashMap<HealthDataType, i64> = HashMap::new();
+    for record in done.iter().flatten() {
+        if let Some(row_id) = record.row_id {
+            let entry = row_ids.entry(record.record_type).or_insert(row_id);
+            *entry = (*entry).max(row_id);
+        }
+    }
+
+    for record in remaining.iter().flatten() {
+        if let Some(row_id) = record.row_id {
+            if let Some(entry) = row_ids.get_mut(&record.record_type) {
+                *entry = (*entry).min(row_id - 1);
+            }
+        }
+    }
+    row_ids
+}
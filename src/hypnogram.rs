@@ -0,0 +1,116 @@
+use crate::derived::{DerivedPoints, DerivedStage};
+use crate::health_data::{
+    stage_name, stage_value, HealthDataReader, HealthDataType, HealthRecord, ReadFrom, SleepStage,
+};
+use crate::influx_client::DataPoint;
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::error::Error;
+use std::time::Duration;
+
+/// Measurement the sampled sleep stages are written to
+pub const HYPNOGRAM_MEASUREMENT: &str = "SleepHypnogram";
+
+/// Samples sleep stages into a regular series: a point at every multiple of `interval`
+/// (aligned to the Unix epoch) holding the value of the stage under way, as Grafana's state
+/// timeline draws a series far better than start and end events. Each session ends with an
+/// AWAKE point, so its last stage does not carry on until the next night
+///
+/// Points are tagged with the app and the stage name, like the `Sleep` measurement. Times
+/// out of the range chrono can represent are skipped
+pub fn hypnogram(stages: &[SleepStage], interval: Duration) -> Vec<DataPoint> {
+    let step = (interval.as_millis() as i64).max(1);
+    let point = |millis: i64, app_name: &str, stage_type: i64| {
+        Some(DataPoint {
+            measurement: HYPNOGRAM_MEASUREMENT.to_string(),
+            time: Utc.timestamp_millis_opt(millis).single()?,
+            tags: HashMap::from([
+                ("app_name".to_string(), app_name.to_string()),
+                ("stage".to_string(), stage_name(stage_type).to_string()),
+            ]),
+            field_value: stage_value(stage_type),
+            text_fields: BTreeMap::new(),
+        })
+    };
+
+    let mut points = Vec::new();
+    for (index, stage) in stages.iter().enumerate() {
+        let start = stage.start.timestamp_millis();
+        let end = stage.end.timestamp_millis();
+        // The first multiple of the interval at or after the start
+        let mut time = start + (step - start.rem_euclid(step)) % step;
+        while time < end {
+            points.extend(point(time, &stage.app_name, stage.stage_type));
+            time += step;
+        }
+
+        let last_of_session = stages
+            .get(index + 1)
+            .is_none_or(|next| next.session_row_id != stage.session_row_id);
+        if last_of_session {
+            points.extend(point(end, &stage.app_name, 1));
+        }
+    }
+    points
+}
+
+/// Writes the hypnogram of the sleep sessions written, from their stages
+pub struct HypnogramStage {
+    interval: Duration,
+    stages: Vec<SleepStage>,
+    sessions: usize,
+}
+
+impl HypnogramStage {
+    /// Creates the stage sampling the stages every `interval`
+    pub fn new(interval: Duration) -> Self {
+        HypnogramStage {
+            interval,
+            stages: Vec::new(),
+            sessions: 0,
+        }
+    }
+}
+
+impl DerivedStage for HypnogramStage {
+    fn name(&self) -> &str {
+        "sleep hypnogram"
+    }
+
+    fn inputs(&self) -> &[HealthDataType] {
+        &[]
+    }
+
+    /// Stages are read with their own times, for the sessions written
+    fn prepare(
+        &mut self,
+        reader: &HealthDataReader,
+        written: &[HealthRecord],
+    ) -> Result<Option<ReadFrom>, Box<dyn Error>> {
+        let sessions: Vec<(i64, DateTime<Utc>)> = written
+            .iter()
+            .filter(|record| record.record_type == HealthDataType::Sleep)
+            .filter_map(|record| Some((record.row_id?, record.timestamp)))
+            .collect();
+        let Some(first) = sessions.iter().map(|(_, start)| *start).min() else {
+            return Ok(None);
+        };
+        let row_ids: HashSet<i64> = sessions.iter().map(|(row_id, _)| *row_id).collect();
+        let since = ReadFrom::Timestamp(first - TimeDelta::milliseconds(1));
+        self.stages = reader
+            .get_sleep_stages_since(since)?
+            .into_iter()
+            .filter(|stage| row_ids.contains(&stage.session_row_id))
+            .collect();
+        self.sessions = row_ids.len();
+        Ok(Some(since))
+    }
+
+    fn compute(&self, _records: &[HealthRecord]) -> DerivedPoints {
+        DerivedPoints::Single(hypnogram(&self.stages, self.interval))
+    }
+
+    fn describe(&self) -> String {
+        format!(" for {} sessions", self.sessions)
+    }
+}
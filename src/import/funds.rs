@@ -0,0 +1,539 @@
+use super::{
+    check_source_fingerprint, create_influx_client, create_state_store, fetch_fx_rates,
+    fetch_source, import_file_source, load_state_or_exit, lock_run, resolve_database, FileImport,
+    InvalidWatermarkAction, SingleWatermark, SourceChangeAction, EXIT_ERROR, EXIT_INTERRUPTED,
+    EXIT_SOURCE_ERROR,
+};
+use crate::config::Config;
+use crate::convert::{HeaderRole, DEFAULT_FUND_TAG_KEY};
+use crate::csv_parser::CsvParser;
+use crate::dsmr::DsmrReader;
+use crate::influx_client::{
+    ConflictPolicy, InfluxClient, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use crate::interrupt;
+use crate::meter::MeterReader;
+use crate::nav::DEFAULT_NAV_MEASUREMENT;
+use crate::portfolio::{Portfolio, PortfolioSource};
+use crate::schedule::parse_interval;
+use crate::solar::SolarReader;
+use crate::source::Source;
+use crate::state_management::{JournalEntry, SourceFingerprint};
+use crate::state_store::StateBackend;
+use crate::transactions::TransactionReader;
+use crate::weather::{UnitSystem, WeatherReader};
+use chrono::Utc;
+use clap::{Args, ValueEnum};
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Layouts of CSV file import-funds can read
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum CsvProfile {
+    /// Fund prices: a column per fund, with the measurement in the header rows
+    Funds,
+    /// DSMR P1 smart meter logger: cumulative kWh and gas registers, written as the usage
+    /// of each interval between readings
+    Dsmr,
+    /// Ecowitt or Weather Underground station: temperature, humidity, wind and rain, tagged
+    /// with the station
+    Weather,
+    /// SolarEdge, Fronius or Growatt inverter: energy produced per interval, as a whole and
+    /// per string, with counters written as their increase
+    Solar,
+    /// Manual meter readings: a column per meter, written with the daily consumption
+    /// interpolated between readings
+    Meter,
+    /// Fund buys, sells and dividends: written as the invested capital and the realized
+    /// and unrealized gains of each fund
+    Transactions,
+}
+
+/// The options of `import-funds`
+#[derive(Args)]
+pub struct FundsImportArgs {
+    /// The CSV file to import, or an http(s):// or s3://bucket/key URL to download it from
+    #[arg(short, long, required = true, env = "HDI_SOURCE")]
+    pub source: String,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to write into (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// Timestamp column name in CSV
+    #[arg(long, default_value = "timestamp", env = "HDI_TIME_COLUMN")]
+    pub time_column: String,
+
+    /// Timestamp format (e.g., "YYYY-MM-DD HH:MM:SS")
+    #[arg(long, default_value = "%Y-%m-%d %H:%M:%S", env = "HDI_TIME_FORMAT")]
+    pub time_format: String,
+
+    /// Measurement name in InfluxDB
+    #[arg(short, long, required = true, env = "HDI_MEASUREMENT")]
+    pub measurement: String,
+
+    /// Number of header rows in CSV file
+    #[arg(long, default_value = "1", env = "HDI_HEADER_ROWS")]
+    pub header_rows: usize,
+
+    /// What each header row holds, from the top: fund, measurement, account, currency,
+    /// ignore or tag:<name> (fund then measurement by default). Columns no row names a
+    /// measurement for are written to --measurement
+    #[arg(long, value_delimiter = ',', env = "HDI_HEADER_ROLES")]
+    pub header_roles: Option<Vec<HeaderRole>>,
+
+    /// With --profile funds or transactions: the key of the tag naming the fund of each
+    /// point
+    #[arg(long, default_value = DEFAULT_FUND_TAG_KEY, env = "HDI_TAG_KEY")]
+    pub tag_key: String,
+
+    /// With --profile funds: keep values that are not numbers (e.g. a notes or status
+    /// column) as string fields of the points of their row, named after their column,
+    /// instead of dropping them
+    #[arg(long, env = "HDI_KEEP_TEXT_COLUMNS")]
+    pub keep_text_columns: bool,
+
+    /// Layout of the CSV file
+    #[arg(long, value_enum, default_value_t = CsvProfile::Funds, env = "HDI_PROFILE")]
+    pub profile: CsvProfile,
+
+    /// With --profile weather: the `station` tag of the points (the file name by default)
+    #[arg(long, env = "HDI_STATION")]
+    pub station: Option<String>,
+
+    /// With --profile weather: the units readings are written in, whatever the export uses
+    #[arg(long, value_enum, default_value_t = UnitSystem::Metric, env = "HDI_UNITS")]
+    pub units: UnitSystem,
+
+    /// Run in dry-run mode (don't write to InfluxDB, just show queries)
+    #[arg(long, env = "HDI_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// With --dry-run, check which points InfluxDB already has and report per
+    /// measurement how many would be new
+    #[arg(long, requires = "dry_run", env = "HDI_DIFF")]
+    pub diff: bool,
+
+    /// Writes a JSON summary of each run (counts, skipped records, duration and the
+    /// import state before and after) to FILE
+    #[arg(long, value_name = "FILE", env = "HDI_REPORT_FILE")]
+    pub report_file: Option<String>,
+
+    /// Only import the oldest N new records, e.g. to try a new setup before a full
+    /// backfill; the next run continues with the rest
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), env = "HDI_LIMIT")]
+    pub limit: Option<u64>,
+
+    /// State file to track last imported timestamp
+    #[arg(long, default_value = ".import_state.json", env = "HDI_STATE_FILE")]
+    pub state_file: String,
+
+    /// Number of rotated copies of the state file to keep before updating it (0 disables)
+    #[arg(long, default_value = "3", env = "HDI_STATE_BACKUPS")]
+    pub state_backups: usize,
+
+    /// Where to keep import state: file, influxdb (the importer_state measurement), an
+    /// http(s) URL or an s3://bucket/key URL
+    #[arg(long, default_value = "file", env = "HDI_STATE_BACKEND")]
+    pub state_backend: StateBackend,
+
+    /// Directory sources given as URLs are downloaded to; they are only downloaded again
+    /// once they change
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = ".source_cache",
+        env = "HDI_SOURCE_CACHE"
+    )]
+    pub source_cache: String,
+
+    /// Force import all records, ignoring state file
+    #[arg(long, env = "HDI_FORCE_ALL")]
+    pub force_all: bool,
+
+    /// What to do when the source file was replaced by a different export since the last import
+    #[arg(long, value_enum, default_value_t = SourceChangeAction::Ask, env = "HDI_ON_SOURCE_CHANGE")]
+    pub on_source_change: SourceChangeAction,
+
+    /// What to do when a watermark is in the future or newer than the source data
+    #[arg(long, value_enum, default_value_t = InvalidWatermarkAction::Warn, env = "HDI_ON_INVALID_WATERMARK")]
+    pub on_invalid_watermark: InvalidWatermarkAction,
+
+    /// Spool file where points that fail to write are saved for `resume-spool`
+    #[arg(long, default_value = ".import_spool.lp", env = "HDI_SPOOL_FILE")]
+    pub spool_file: String,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+
+    /// Keep running and import again whenever the source file changes, checking at this
+    /// interval (e.g., 30s, 15m, 1h)
+    #[arg(long, value_parser = parse_interval, env = "HDI_WATCH")]
+    pub watch: Option<Duration>,
+}
+
+/// Imports the new records of a CSV file, read with the reader of its profile. With
+/// --profile funds, the `[funds]`, `[fx]` and `[portfolio]` sections of `config_file` add column
+/// tags, converted values and portfolio valuations. --watch is left to the caller, which
+/// runs this again whenever the source changes
+pub async fn run(args: FundsImportArgs, config_file: Option<&str>, lock_file: Option<&str>) {
+    let FundsImportArgs {
+        source,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        time_column,
+        time_format,
+        measurement,
+        header_rows,
+        header_roles,
+        tag_key,
+        keep_text_columns,
+        profile,
+        station,
+        units,
+        dry_run,
+        diff,
+        report_file,
+        limit,
+        state_file,
+        state_backups,
+        state_backend,
+        source_cache,
+        force_all,
+        on_source_change,
+        on_invalid_watermark,
+        spool_file,
+        connect_timeout,
+        request_timeout,
+        watch: _,
+    } = args;
+
+    let source = fetch_source(
+        source,
+        &source_cache,
+        connect_timeout,
+        request_timeout,
+        false,
+    )
+    .await;
+
+    let _lock = lock_run(lock_file, &format!("import-funds of '{}'", source));
+    interrupt::install(EXIT_INTERRUPTED);
+
+    match profile {
+        CsvProfile::Funds => {
+            progress!("Importing funds data from '{}' into InfluxDB", source)
+        }
+        CsvProfile::Dsmr => {
+            progress!("Importing smart meter data from '{}' into InfluxDB", source)
+        }
+        CsvProfile::Weather => {
+            progress!("Importing weather data from '{}' into InfluxDB", source)
+        }
+        CsvProfile::Solar => {
+            progress!("Importing solar production from '{}' into InfluxDB", source)
+        }
+        CsvProfile::Meter => {
+            progress!("Importing meter readings from '{}' into InfluxDB", source)
+        }
+        CsvProfile::Transactions => {
+            progress!(
+                "Importing fund transactions from '{}' into InfluxDB",
+                source
+            )
+        }
+    }
+    progress!("  URL: {}", url);
+    progress!("  Organization: {}", org);
+    let bucket = resolve_database(bucket, database);
+    progress!("  Bucket/database: {}", bucket);
+    if let Some(rp) = &retention_policy {
+        progress!("  Retention policy: {}", rp);
+    }
+    progress!("  Measurement: {}", measurement);
+    progress!("  Time column: {} (format: {})", time_column, time_format);
+    progress!("  Header rows: {}", header_rows);
+    if let Some(roles) = &header_roles {
+        let roles: Vec<String> = roles.iter().map(HeaderRole::to_string).collect();
+        progress!("  Header roles: {}", roles.join(", "));
+    }
+    if keep_text_columns {
+        progress!("  Text columns: kept as string fields");
+    }
+    progress!("  Dry-run mode: {}", if dry_run { "ON" } else { "OFF" });
+    progress!("  State file: {}", state_file);
+
+    let mut journal = JournalEntry::new("import-funds", &source, &format!("{} ({})", url, bucket));
+    journal.dry_run = dry_run;
+    journal
+        .filters
+        .push(format!("measurement: {}", measurement));
+    if force_all {
+        journal.filters.push("force all".to_string());
+    }
+    if let Some(limit) = limit {
+        journal.filters.push(format!("limit: {}", limit));
+    }
+
+    // Open the state store; dry runs never write state to InfluxDB
+    let state_store = create_state_store(
+        &state_backend,
+        &state_file,
+        connect_timeout,
+        request_timeout,
+        || {
+            create_influx_client(
+                InfluxClient::builder(&url, &bucket)
+                    .token(&token)
+                    .dry_run(dry_run),
+                &org,
+                retention_policy.as_deref(),
+                connect_timeout,
+                request_timeout,
+            )
+        },
+    );
+    if state_backend != StateBackend::File {
+        progress!("  State backend: {}", state_store.describe());
+    }
+
+    // Load the import state
+    let mut import_state = load_state_or_exit(&state_store, &source).await;
+    let source_fingerprint = if force_all {
+        SourceFingerprint::compute(&source).ok()
+    } else {
+        check_source_fingerprint(&mut import_state, &source, on_source_change)
+    };
+
+    if force_all {
+        progress!("Force import all records (--force-all flag is set)");
+        import_state.last_imported_timestamp = None;
+    } else if let Some(timestamp) = import_state.last_imported_timestamp {
+        progress!("Skipping records before: {}", timestamp);
+        progress!(
+            "Previously imported: {} records",
+            import_state.records_imported
+        );
+    } else {
+        progress!("No previous import state found, importing all records");
+    }
+
+    // Read the records after the watermark
+    let settings = FileImport {
+        url,
+        org,
+        bucket,
+        token,
+        retention_policy,
+        connect_timeout,
+        request_timeout,
+        measurement: measurement.clone(),
+        dry_run,
+        diff,
+        limit,
+        force_all,
+        on_invalid_watermark,
+        on_conflict: ConflictPolicy::Overwrite,
+        anonymize: None,
+        spool_file,
+        state_backups,
+        report_file,
+        check_watermark: true,
+    };
+    match profile {
+        CsvProfile::Funds => {
+            let mut parser = CsvParser::new(&source)
+                .with_header_rows(header_rows)
+                .with_time_column(&time_column, &time_format)
+                .with_header_roles(header_roles.unwrap_or_default())
+                .with_measurement(&measurement)
+                .with_tag_key(&tag_key)
+                .with_text_fields(keep_text_columns);
+            let config = match config_file.map(Config::load) {
+                Some(Ok(config)) => config,
+                Some(Err(e)) => {
+                    eprintln!("{}", e);
+                    process::exit(EXIT_ERROR);
+                }
+                None => Config::default(),
+            };
+            if !config.funds.columns.is_empty() {
+                progress!(
+                    "  Column tags: {} [[funds.columns]] entries",
+                    config.funds.columns.len()
+                );
+                parser = parser.with_column_tags(config.funds.columns);
+            }
+            let fx = config.fx;
+            if let Some(conversion) = fx.conversion() {
+                let mut conversion = conversion.with_tag_key(&tag_key);
+                // Rates are only needed from the oldest record not imported yet; the
+                // import reads the records parsed here instead of parsing the file again
+                let since = parser
+                    .records_since(&import_state)
+                    .ok()
+                    .and_then(|records| {
+                        records.filter_map(|record| parser.timestamp(&record)).min()
+                    })
+                    .unwrap_or_else(Utc::now)
+                    .date_naive();
+                fetch_fx_rates(&mut conversion, &fx, since, request_timeout).await;
+                progress!(
+                    "  Currency: values also written in {}, to {} measurements",
+                    conversion.rates().base(),
+                    conversion.measurement("<measurement>")
+                );
+                parser = parser.with_fx(conversion);
+            }
+            let holdings = config.portfolio.holdings;
+            let transactions = match &config.portfolio.transactions {
+                Some(path) => match TransactionReader::new(path).transactions() {
+                    Ok(transactions) => transactions,
+                    Err(e) => {
+                        eprintln!("Failed to read the transactions in {}: {}", path, e);
+                        process::exit(EXIT_SOURCE_ERROR);
+                    }
+                },
+                None => Vec::new(),
+            };
+            if holdings.is_empty() && transactions.is_empty() {
+                import_file_source(
+                    Arc::new(parser),
+                    SingleWatermark,
+                    settings,
+                    state_store,
+                    import_state,
+                    journal,
+                    source_fingerprint,
+                )
+                .await;
+            } else {
+                let nav_measurement = config
+                    .portfolio
+                    .measurement
+                    .unwrap_or_else(|| DEFAULT_NAV_MEASUREMENT.to_string());
+                progress!(
+                    "  Portfolio: {} holdings and {} transactions, valued at their {} NAVs",
+                    holdings.len(),
+                    transactions.len(),
+                    nav_measurement
+                );
+                let portfolio = Portfolio::new(&holdings, &nav_measurement)
+                    .with_tag_key(&tag_key)
+                    .with_transactions(transactions);
+                import_file_source(
+                    Arc::new(PortfolioSource::new(parser, portfolio)),
+                    SingleWatermark,
+                    settings,
+                    state_store,
+                    import_state,
+                    journal,
+                    source_fingerprint,
+                )
+                .await;
+            }
+        }
+        CsvProfile::Dsmr => {
+            let reader = DsmrReader::new(&source).with_time_column(&time_column, &time_format);
+            import_file_source(
+                Arc::new(reader),
+                SingleWatermark,
+                settings,
+                state_store,
+                import_state,
+                journal,
+                source_fingerprint,
+            )
+            .await;
+        }
+        CsvProfile::Weather => {
+            let mut reader = WeatherReader::new(&source)
+                .with_units(units)
+                .with_time_column(&time_column, &time_format);
+            if let Some(station) = &station {
+                reader = reader.with_station(station);
+            }
+            import_file_source(
+                Arc::new(reader),
+                SingleWatermark,
+                settings,
+                state_store,
+                import_state,
+                journal,
+                source_fingerprint,
+            )
+            .await;
+        }
+        CsvProfile::Solar => {
+            let reader = SolarReader::new(&source).with_time_column(&time_column, &time_format);
+            import_file_source(
+                Arc::new(reader),
+                SingleWatermark,
+                settings,
+                state_store,
+                import_state,
+                journal,
+                source_fingerprint,
+            )
+            .await;
+        }
+        CsvProfile::Meter => {
+            let reader = MeterReader::new(&source).with_time_column(&time_column, &time_format);
+            import_file_source(
+                Arc::new(reader),
+                SingleWatermark,
+                settings,
+                state_store,
+                import_state,
+                journal,
+                source_fingerprint,
+            )
+            .await;
+        }
+        CsvProfile::Transactions => {
+            let reader = TransactionReader::new(&source)
+                .with_time_column(&time_column, &time_format)
+                .with_tag_key(&tag_key);
+            import_file_source(
+                Arc::new(reader),
+                SingleWatermark,
+                settings,
+                state_store,
+                import_state,
+                journal,
+                source_fingerprint,
+            )
+            .await;
+        }
+    }
+}
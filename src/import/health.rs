@@ -0,0 +1,1309 @@
+use super::{
+    check_source_fingerprint, create_influx_client, create_state_store, fetch_source, finish_run,
+    handle_invalid_watermark, import_file_source, load_state_or_exit, lock_run, merge_order,
+    read_new_records, resolve_database, stream_records, FileImport, ImportHooks, ImportRun,
+    InvalidWatermarkAction, SourceChangeAction, EXIT_ERROR, EXIT_INTERRUPTED, EXIT_SINK_ERROR,
+    EXIT_SOURCE_ERROR,
+};
+use crate::aggregate::{self, DailyAggregate, DailyAggregateStage, SleepDebtStage};
+use crate::anonymize::Anonymize;
+use crate::derived::{
+    self, BmiStage, BodyCompositionStage, DerivedMetric, DerivedStage, WorkoutSummaryStage,
+};
+use crate::downsample::{DownsampleStage, Downsampler};
+use crate::grafana::{session_annotations, AnnotatedSession, GrafanaAnnotator};
+use crate::health_data::{
+    group_by_type, safe_row_ids, split_by_time, stale_ranges, stale_timestamps, take_oldest,
+    DataTypeSelector, HealthDataReader, HealthDataType, HealthRecord, ReadFrom,
+};
+use crate::hypnogram::HypnogramStage;
+use crate::influx_client::{
+    ConflictCheck, ConflictPolicy, InfluxClient, DEFAULT_CONNECT_TIMEOUT_SECS,
+    DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use crate::interrupt::{self, Interrupted};
+use crate::output;
+use crate::schedule::parse_interval;
+use crate::state_management::{JournalEntry, SourceFingerprint};
+use crate::state_store::StateBackend;
+use crate::training::{TrainingLoad, TrainingLoadStage, DEFAULT_RESTING_HEART_RATE};
+use crate::zones::{HeartRateZones, HeartRateZonesStage};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use clap::Args;
+use std::collections::{BTreeMap, BTreeSet};
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The options of `import-health-data`
+#[derive(Args)]
+pub struct HealthImportArgs {
+    /// The SQLite database file to import, or a directory of exports: the last by name
+    /// is imported and the others merged into it. An http(s):// or s3://bucket/key URL
+    /// of a database or a Health Connect zip file is downloaded first
+    #[arg(short, long, required = true, env = "HDI_SOURCE")]
+    pub source: String,
+
+    /// Older exports (files or directories, comma-separated) whose records are merged
+    /// into those of the source, skipping the records it already holds
+    #[arg(long, value_delimiter = ',', env = "HDI_MERGE_SOURCES")]
+    pub merge_sources: Vec<String>,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to write into (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// State file to track last imported timestamp
+    #[arg(
+        long,
+        default_value = ".health_import_state.json",
+        env = "HDI_STATE_FILE"
+    )]
+    pub state_file: String,
+
+    /// Number of rotated copies of the state file to keep before updating it (0 disables)
+    #[arg(long, default_value = "3", env = "HDI_STATE_BACKUPS")]
+    pub state_backups: usize,
+
+    /// Where to keep import state: file, influxdb (the importer_state measurement), an
+    /// http(s) URL or an s3://bucket/key URL
+    #[arg(long, default_value = "file", env = "HDI_STATE_BACKEND")]
+    pub state_backend: StateBackend,
+
+    /// Directory sources given as URLs are downloaded to; they are only downloaded again
+    /// once they change
+    #[arg(
+        long,
+        value_name = "DIR",
+        default_value = ".source_cache",
+        env = "HDI_SOURCE_CACHE"
+    )]
+    pub source_cache: String,
+
+    /// Force import all records, ignoring state file
+    #[arg(long, env = "HDI_FORCE_ALL")]
+    pub force_all: bool,
+
+    /// What to do with records InfluxDB already has a point of the same measurement,
+    /// tags and time for; skip and error query the existing points before each write
+    #[arg(long, value_enum, default_value_t = ConflictPolicy::Overwrite, env = "HDI_ON_CONFLICT")]
+    pub on_conflict: ConflictPolicy,
+
+    /// Same as --on-conflict skip: leave out the records InfluxDB already has, so
+    /// importing them again (e.g. with --force-all) writes nothing twice
+    #[arg(long, conflicts_with = "on_conflict", env = "HDI_IDEMPOTENT")]
+    pub idempotent: bool,
+
+    /// What to do when the source file was replaced by a different export since the last import
+    #[arg(long, value_enum, default_value_t = SourceChangeAction::Ask, env = "HDI_ON_SOURCE_CHANGE")]
+    pub on_source_change: SourceChangeAction,
+
+    /// What to do when a watermark is in the future or newer than the source data
+    #[arg(long, value_enum, default_value_t = InvalidWatermarkAction::Warn, env = "HDI_ON_INVALID_WATERMARK")]
+    pub on_invalid_watermark: InvalidWatermarkAction,
+
+    /// Run in dry-run mode (don't write to InfluxDB, just show queries)
+    #[arg(long, env = "HDI_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// With --dry-run, check which points InfluxDB already has and report per
+    /// measurement how many would be new
+    #[arg(long, requires = "dry_run", env = "HDI_DIFF")]
+    pub diff: bool,
+
+    /// Writes a JSON summary of each run (counts, skipped records, duration and the
+    /// import state before and after) to FILE
+    #[arg(long, value_name = "FILE", env = "HDI_REPORT_FILE")]
+    pub report_file: Option<String>,
+
+    /// Only import the oldest N new records, e.g. to try a new setup before a full
+    /// backfill; the next run continues with the rest
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), env = "HDI_LIMIT")]
+    pub limit: Option<u64>,
+
+    /// Only import specific data types, or groups of them: group:body, group:activity or
+    /// group:sleep (comma-separated)
+    #[arg(long, value_delimiter = ',', env = "HDI_DATA_TYPES")]
+    pub data_types: Option<Vec<DataTypeSelector>>,
+
+    /// Import every data type (or every one of --data-types) but these, or those of
+    /// these groups (comma-separated)
+    #[arg(long, value_delimiter = ',', env = "HDI_EXCLUDE_TYPES")]
+    pub exclude_types: Vec<DataTypeSelector>,
+
+    /// Enable heart rate gap-filling mode (checks InfluxDB for existing data in the last N days and fills gaps).
+    /// Note: Gap-filling mode only imports heart rate data and does not update the state file.
+    /// Run normal sync first to update state, then use gap-filling as a maintenance operation.
+    #[arg(long, env = "HDI_GAP_FILL_HEART_RATE")]
+    pub gap_fill_heart_rate: Option<i64>,
+
+    /// Windows (e.g., 1h or 1d) whose heart rate counts are compared with InfluxDB when
+    /// gap-filling; only the timestamps of windows whose counts differ are compared
+    #[arg(long, value_parser = parse_interval, default_value = "1h", env = "HDI_GAP_FILL_WINDOW")]
+    pub gap_fill_window: Duration,
+
+    /// Spool file where points that fail to write are saved for `resume-spool`
+    #[arg(
+        long,
+        default_value = ".health_import_spool.lp",
+        env = "HDI_SPOOL_FILE"
+    )]
+    pub spool_file: String,
+
+    /// Save the import state after every N batches written, so an interrupted import
+    /// resumes near where it stopped (0 saves only at the end)
+    #[arg(long, default_value = "10", env = "HDI_CHECKPOINT_EVERY")]
+    pub checkpoint_every: usize,
+
+    /// Also save the import state after the records of every period of this length
+    /// (e.g., 7d), oldest first, so a long backfill keeps its progress week by week
+    #[arg(long, value_parser = parse_interval, env = "HDI_CHUNK_PERIOD")]
+    pub chunk_period: Option<Duration>,
+
+    /// Stream the series data types (HeartRate and StepsCadence) to InfluxDB through a
+    /// cursor, with at most about this many of their points in memory, instead of
+    /// reading them whole first; for years of samples on a small machine. At least 4, as
+    /// the points are split between the reader's pages and the pipeline's channels
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(4..),
+        conflicts_with_all = ["limit", "gap_fill_heart_rate"],
+        env = "HDI_MAX_IN_FLIGHT_POINTS"
+    )]
+    pub max_in_flight_points: Option<u64>,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB or Grafana request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+
+    /// Keep running and import again whenever the source file changes, checking at this
+    /// interval (e.g., 30s, 15m, 1h)
+    #[arg(long, value_parser = parse_interval, env = "HDI_WATCH")]
+    pub watch: Option<Duration>,
+
+    /// Grafana URL to push the imported sessions to as annotations
+    #[arg(long, requires = "grafana_token", env = "HDI_GRAFANA_URL")]
+    pub grafana_url: Option<String>,
+
+    /// Grafana service account token, with permission to write annotations
+    #[arg(long, env = "HDI_GRAFANA_TOKEN", hide_env_values = true)]
+    pub grafana_token: Option<String>,
+
+    /// UID of the dashboard the annotations are shown on (all dashboards querying their
+    /// tags by default)
+    #[arg(long, env = "HDI_GRAFANA_DASHBOARD")]
+    pub grafana_dashboard: Option<String>,
+
+    /// Sessions pushed to Grafana as annotations (comma-separated)
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_value = "exercise",
+        env = "HDI_ANNOTATE"
+    )]
+    pub annotate: Vec<AnnotatedSession>,
+
+    /// Tags added to every annotation (comma-separated)
+    #[arg(long, value_delimiter = ',', env = "HDI_ANNOTATION_TAGS")]
+    pub annotation_tags: Vec<String>,
+
+    /// Also write per-day rollups of the days imported records fall on (comma-separated):
+    /// steps, calories, sleep or heart-rate
+    #[arg(long, value_enum, value_delimiter = ',', env = "HDI_DAILY_AGGREGATES")]
+    pub daily_aggregates: Vec<DailyAggregate>,
+
+    /// Time zone the days of daily aggregates, training load, sleep debt and heart rate
+    /// zones start in (e.g., Europe/Rome), also used for records without a zone offset
+    /// of their own; defaults to the system's
+    #[arg(long, env = "HDI_TIMEZONE")]
+    pub timezone: Option<Tz>,
+
+    /// Also write the training load of workouts (TRIMP from the heart rate during them)
+    /// and its 7-day acute and 28-day chronic averages, for this maximum heart rate
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), env = "HDI_MAX_HEART_RATE")]
+    pub max_heart_rate: Option<u32>,
+
+    /// Resting heart rate the training load is computed for
+    #[arg(long, default_value_t = DEFAULT_RESTING_HEART_RATE, env = "HDI_RESTING_HEART_RATE")]
+    pub resting_heart_rate: u32,
+
+    /// Also write each night's sleep against this target (e.g., 8h or 450m) and the
+    /// sleep debt of the last 7 nights
+    #[arg(long, value_parser = parse_interval, env = "HDI_SLEEP_TARGET")]
+    pub sleep_target: Option<Duration>,
+
+    /// Also write the min, mean and max of each window of the imported data types to
+    /// this bucket (or 1.x database), e.g. one with a longer retention
+    #[arg(long, env = "HDI_DOWNSAMPLE_BUCKET")]
+    pub downsample_bucket: Option<String>,
+
+    /// Window the points written to --downsample-bucket are aggregated over
+    #[arg(long, value_parser = parse_interval, default_value = "5m", env = "HDI_DOWNSAMPLE_INTERVAL")]
+    pub downsample_interval: Duration,
+
+    /// Data types written to --downsample-bucket (comma-separated)
+    #[arg(
+        long,
+        value_delimiter = ',',
+        ignore_case = true,
+        default_value = "HeartRate",
+        env = "HDI_DOWNSAMPLE_TYPES"
+    )]
+    pub downsample_types: Vec<HealthDataType>,
+
+    /// Apps whose sleep sessions are kept when several apps recorded the same night,
+    /// preferred first (comma-separated); otherwise the session with the most stages
+    #[arg(long, value_delimiter = ',', env = "HDI_SLEEP_PRIORITY")]
+    pub sleep_priority: Vec<String>,
+
+    /// Also write the sleep stages as a regular series with a point at this interval
+    /// (e.g., 1m) holding the current stage, for Grafana's state timeline
+    #[arg(long, value_parser = parse_interval, env = "HDI_SLEEP_HYPNOGRAM")]
+    pub sleep_hypnogram: Option<Duration>,
+
+    /// Tag the heart rate samples taken during an exercise session with the session's
+    /// id and exercise type (exercise_session and exercise_type tags)
+    #[arg(long, env = "HDI_LINK_WORKOUTS")]
+    pub link_workouts: bool,
+
+    /// Also write metrics derived from the imported records (comma-separated):
+    /// body-composition, bmi, workout-summary or heart-rate-zones
+    #[arg(long, value_enum, value_delimiter = ',', env = "HDI_DERIVED_METRICS")]
+    pub derived_metrics: Vec<DerivedMetric>,
+
+    /// Lowest heart rate of each zone (comma-separated, increasing) the heart-rate-zones
+    /// metric uses; 50, 60, 70, 80 and 90% of --max-heart-rate by default
+    #[arg(long, value_delimiter = ',', env = "HDI_ZONE_BOUNDARIES")]
+    pub zone_boundaries: Vec<u32>,
+
+    /// Height in meters the BMI is computed with, instead of the Height records
+    #[arg(long, env = "HDI_HEIGHT")]
+    pub height: Option<f64>,
+
+    /// Delete the points of the last N days whose records are no longer in the source
+    /// (deleted in Health Connect, or edited to another time)
+    #[arg(long, env = "HDI_RECONCILE_DAYS")]
+    pub reconcile_days: Option<u32>,
+
+    /// Hide identifying tags (apps, devices and exercise titles) before writing:
+    /// hash replaces them with a stable pseudonym, strip leaves them out
+    #[arg(long, value_enum, env = "HDI_ANONYMIZE")]
+    pub anonymize: Option<Anonymize>,
+}
+
+/// The steps of a health data import: watermarks per data type, series streamed before the
+/// other records are read, heart rate gap-filling, and the points derived once the records
+/// are written
+struct HealthImport {
+    requested_data_types: Option<Vec<HealthDataType>>,
+    streamed_types: Vec<HealthDataType>,
+    max_in_flight_points: Option<u64>,
+    gap_fill_heart_rate: Option<i64>,
+    gap_fill_window: Duration,
+    checkpoint_every: usize,
+    chunk_period: Option<Duration>,
+    reconcile_days: Option<u32>,
+    annotator: Option<GrafanaAnnotator>,
+    annotate: Vec<AnnotatedSession>,
+    anonymize: bool,
+    /// Written to their own client when they have one, to the import's otherwise
+    stages: Vec<(Box<dyn DerivedStage>, Option<InfluxClient>)>,
+    /// Taken before reading, so records modified while importing are read again
+    modified_marks: Vec<(HealthDataType, DateTime<Utc>)>,
+    held_back: bool,
+}
+
+impl HealthImport {
+    fn requested(&self, data_type: HealthDataType) -> bool {
+        self.requested_data_types
+            .as_ref()
+            .is_none_or(|types| types.contains(&data_type))
+    }
+
+    /// Streams the series tables to InfluxDB, saving the import state after each, and
+    /// returns the number of records and points written
+    async fn stream_series(
+        &self,
+        reader: &Arc<HealthDataReader>,
+        sink: &ConflictCheck<'_>,
+        run: &mut ImportRun,
+    ) -> (usize, usize) {
+        let mut streamed_records = 0;
+        let mut streamed_points = 0;
+        for &data_type in &self.streamed_types {
+            // Two pages held by the reader, and two channels of the pipeline
+            let page_size = self.max_in_flight_points.unwrap_or_default() as usize / 4;
+            // Every record up to the newest one now in the table is read
+            let latest = match reader.latest_record(data_type) {
+                Ok(Some(latest)) => latest,
+                Ok(None) => continue,
+                Err(e) => {
+                    run.fail(
+                        format!("Error reading {} records: {}", data_type, e),
+                        EXIT_SOURCE_ERROR,
+                    )
+                    .await
+                }
+            };
+            let (records, series_reader) =
+                match reader.stream_series(data_type, &run.import_state, page_size) {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        run.fail(
+                            format!("Error reading {} records: {}", data_type, e),
+                            EXIT_SOURCE_ERROR,
+                        )
+                        .await
+                    }
+                };
+            progress!("Streaming {} records...", data_type);
+            let summary = match stream_records(sink, reader, records, page_size).await {
+                Ok(summary) => summary,
+                Err(e) if e.is::<Interrupted>() => {
+                    let message = format!(
+                        "Interrupted while streaming {}; the next run streams it again",
+                        data_type
+                    );
+                    println!("{}", output::warning(&message));
+                    run.journal.errors.push(message);
+                    finish_run(
+                        &run.state_store,
+                        run.journal.clone(),
+                        run.settings.report_file.as_deref(),
+                    )
+                    .await;
+                    process::exit(EXIT_INTERRUPTED);
+                }
+                Err(e) => {
+                    run.fail(
+                        format!("Error writing {} to InfluxDB: {}", data_type, e),
+                        EXIT_SINK_ERROR,
+                    )
+                    .await
+                }
+            };
+            // A read that failed part way leaves the watermark where it was
+            let read = match series_reader.join() {
+                Ok(read) => read,
+                Err(_) => Err("the reading thread panicked".to_string()),
+            };
+            let read = match read {
+                Ok(read) => read,
+                Err(e) => {
+                    run.fail(
+                        format!("Error reading {} records: {}", data_type, e),
+                        EXIT_SOURCE_ERROR,
+                    )
+                    .await
+                }
+            };
+            if read == 0 {
+                continue;
+            }
+            progress!(
+                "{}",
+                output::success(&format!(
+                    "Streamed {} {} records as {} points",
+                    read, data_type, summary.points
+                ))
+            );
+            streamed_records += read;
+            streamed_points += summary.points;
+            *run.journal
+                .records
+                .entry(data_type.to_string())
+                .or_default() += read;
+
+            if run.updates_state {
+                run.back_up_state();
+                run.import_state
+                    .record_import(data_type.as_str(), latest.timestamp, read);
+                run.import_state
+                    .record_row_id(data_type.as_str(), latest.row_id);
+                run.save_checkpoint(data_type.as_str()).await;
+            }
+        }
+        (streamed_records, streamed_points)
+    }
+
+    /// Deletes the points of the last `days` that are no longer in the source, or moved in it
+    async fn reconcile(
+        &self,
+        reader: &HealthDataReader,
+        influx_client: &InfluxClient,
+        run: &mut ImportRun,
+        days: u32,
+    ) {
+        let start = Utc::now() - chrono::Duration::days(days.into());
+        let end = Utc::now() + chrono::Duration::days(1);
+        let data_types = self
+            .requested_data_types
+            .clone()
+            .unwrap_or_else(|| HealthDataType::ALL.to_vec());
+        // Sessions that started before the window can still end inside it
+        let since = ReadFrom::Timestamp(start - chrono::Duration::days(2));
+        let records: Vec<HealthRecord> =
+            match reader.get_health_data_since_per_type(|_| since, Some(&data_types)) {
+                Ok(records_map) => records_map.into_values().flatten().collect(),
+                Err(e) => {
+                    run.fail(
+                        format!("Error reading health data to reconcile: {}", e),
+                        EXIT_SOURCE_ERROR,
+                    )
+                    .await
+                }
+            };
+
+        let mut deleted = 0;
+        for data_type in data_types {
+            let (stale, ranges) = match influx_client
+                .get_existing_timestamps(data_type.as_str(), start, end)
+                .await
+            {
+                Ok(existing) => (
+                    stale_timestamps(&existing, &records, data_type).len(),
+                    stale_ranges(&existing, &records, data_type),
+                ),
+                Err(e) => {
+                    eprintln!("Warning: could not reconcile {}: {}", data_type, e);
+                    continue;
+                }
+            };
+            // Only the series this importer wrote for the apps in the export are deleted
+            let record_type = data_type.to_string();
+            let apps: BTreeSet<&str> = records
+                .iter()
+                .filter(|record| record.record_type == data_type)
+                .filter_map(|record| record.metadata.get("app_name"))
+                .map(String::as_str)
+                .collect();
+            let tag_sets: Vec<Vec<(&str, &str)>> = if apps.is_empty() {
+                vec![vec![("record_type", record_type.as_str())]]
+            } else {
+                apps.iter()
+                    .map(|app| vec![("record_type", record_type.as_str()), ("app_name", *app)])
+                    .collect()
+            };
+            for (first, last) in ranges {
+                let (Some(time), Some(stop)) = (
+                    DateTime::from_timestamp_millis(first),
+                    DateTime::from_timestamp_millis(last),
+                ) else {
+                    continue;
+                };
+                for tags in &tag_sets {
+                    if let Err(e) = influx_client
+                        .delete_range(data_type.as_str(), tags, time, stop)
+                        .await
+                    {
+                        run.fail(
+                            format!("Error deleting stale {} points: {}", data_type, e),
+                            EXIT_SINK_ERROR,
+                        )
+                        .await;
+                    }
+                }
+            }
+            deleted += stale;
+        }
+        if deleted > 0 {
+            progress!("Reconcile: deleted {} stale points", deleted);
+            run.journal.records.insert("deleted".to_string(), deleted);
+        }
+    }
+
+    /// Marks the sessions among the records written in Grafana
+    async fn annotate(
+        &self,
+        annotator: &GrafanaAnnotator,
+        run: &ImportRun,
+        written: &[HealthRecord],
+    ) {
+        // Anonymized imports keep exercise titles out of Grafana too
+        let mut records = written.to_vec();
+        if self.anonymize {
+            for record in &mut records {
+                record.metadata.remove("title");
+            }
+        }
+        let annotations = session_annotations(&records, &self.annotate);
+        if run.settings.dry_run {
+            progress!(
+                "Would have pushed {} session annotations to Grafana",
+                annotations.len()
+            );
+        } else {
+            match annotator.push(&annotations).await {
+                Ok(pushed) => progress!(
+                    "Pushed {} of {} session annotations to Grafana",
+                    pushed,
+                    annotations.len()
+                ),
+                // The points are written, so the import itself succeeded
+                Err(e) => {
+                    eprintln!("Warning: failed to push annotations to Grafana: {}", e)
+                }
+            }
+        }
+    }
+}
+
+impl ImportHooks<HealthDataReader> for HealthImport {
+    /// Gap-filling is a maintenance operation, which leaves the import state alone
+    fn updates_state(&self, settings: &FileImport) -> bool {
+        !settings.dry_run && self.gap_fill_heart_rate.is_none()
+    }
+
+    fn check_watermarks(&self, reader: &HealthDataReader, run: &mut ImportRun) {
+        for data_type in HealthDataType::ALL {
+            if !self.requested(data_type)
+                || (run
+                    .import_state
+                    .last_imported_for(data_type.as_str())
+                    .is_none()
+                    && run
+                        .import_state
+                        .last_row_id_for(data_type.as_str())
+                        .is_none())
+            {
+                continue;
+            }
+
+            let latest = match reader.latest_record(data_type) {
+                Ok(latest) => latest,
+                Err(e) => {
+                    eprintln!(
+                        "Warning: could not check the {} watermark: {}",
+                        data_type, e
+                    );
+                    continue;
+                }
+            };
+            if let Some(problem) = run.import_state.check_watermark(
+                Some(data_type.as_str()),
+                latest.map(|latest| latest.timestamp),
+                latest.map(|latest| latest.row_id),
+            ) {
+                handle_invalid_watermark(
+                    &mut run.import_state,
+                    Some(data_type.as_str()),
+                    &problem,
+                    run.settings.on_invalid_watermark,
+                );
+            }
+        }
+    }
+
+    async fn before_read(
+        &mut self,
+        reader: &Arc<HealthDataReader>,
+        sink: &ConflictCheck<'_>,
+        run: &mut ImportRun,
+    ) -> (usize, usize) {
+        if self.gap_fill_heart_rate.is_none() {
+            self.modified_marks = HealthDataType::ALL
+                .into_iter()
+                .filter(|&data_type| self.requested(data_type))
+                .filter_map(|data_type| {
+                    let modified = reader.latest_modified(data_type).ok()??;
+                    Some((data_type, modified))
+                })
+                .collect();
+        }
+        self.stream_series(reader, sink, run).await
+    }
+
+    async fn read(
+        &mut self,
+        reader: &HealthDataReader,
+        influx_client: &InfluxClient,
+        run: &mut ImportRun,
+    ) -> Vec<HealthRecord> {
+        progress!("Retrieving health data...");
+        let Some(days_back) = self.gap_fill_heart_rate else {
+            return read_new_records(
+                reader,
+                &run.import_state,
+                &run.state_store,
+                &mut run.journal,
+                run.settings.report_file.as_deref(),
+            )
+            .await;
+        };
+
+        progress!("Gap-filling mode: Only importing heart rate data (assuming other data types are already synced)");
+        progress!(
+            "\nHeart rate gap-filling enabled for the last {} days",
+            days_back
+        );
+        progress!("Gap-filling mode: Only heart rate data will be imported");
+        progress!("  (Other data types assumed to be already synced)");
+        match reader
+            .get_heart_rate_with_gap_filling(influx_client, days_back, self.gap_fill_window)
+            .await
+        {
+            Ok(gap_fill_records) if gap_fill_records.is_empty() => {
+                progress!(
+                    "{}",
+                    output::success("No heart rate gaps found - all data is up to date")
+                );
+                gap_fill_records
+            }
+            Ok(gap_fill_records) => {
+                progress!(
+                    "{}",
+                    output::success(&format!(
+                        "Adding {} gap-filled heart rate records",
+                        gap_fill_records.len()
+                    ))
+                );
+                gap_fill_records
+            }
+            Err(e) => {
+                run.fail(
+                    output::failure(&format!("Heart rate gap-filling failed: {}", e)),
+                    EXIT_SINK_ERROR,
+                )
+                .await
+            }
+        }
+    }
+
+    fn preview(&self, records: &[HealthRecord]) {
+        progress!("Found {} health records to import:", records.len());
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for record in records {
+            *counts.entry(record.record_type.to_string()).or_default() += 1;
+        }
+        let rows: Vec<Vec<String>> = counts
+            .into_iter()
+            .map(|(record_type, count)| vec![record_type, count.to_string()])
+            .collect();
+        progress!("{}", output::table(&["Data type", "Records"], &rows));
+    }
+
+    /// Takes the oldest records across all data types
+    fn limit(
+        &mut self,
+        _reader: &HealthDataReader,
+        records: Vec<HealthRecord>,
+        limit: usize,
+    ) -> (Vec<HealthRecord>, Vec<HealthRecord>) {
+        let (taken, held_back) = take_oldest(group_by_type(records), limit);
+        self.held_back = !held_back.is_empty();
+        (
+            taken.into_values().flatten().collect(),
+            held_back.into_values().flatten().collect(),
+        )
+    }
+
+    /// Splits the records at every --checkpoint-every batches and --chunk-period
+    fn chunks(
+        &self,
+        records: Vec<HealthRecord>,
+        influx_client: &InfluxClient,
+        run: &ImportRun,
+    ) -> Vec<Vec<HealthRecord>> {
+        let checkpoint_records = if run.updates_state {
+            self.checkpoint_every * influx_client.batch_size()
+        } else {
+            0
+        };
+        let chunk_period = self.chunk_period.filter(|_| run.updates_state);
+        let chunks: Vec<Vec<HealthRecord>> =
+            split_by_time(group_by_type(records), checkpoint_records, chunk_period)
+                .into_iter()
+                .map(|chunk| chunk.into_values().flatten().collect())
+                .collect();
+        if chunks.len() > 1 {
+            let mut bounds = Vec::new();
+            if checkpoint_records > 0 {
+                bounds.push(format!("up to {} records", checkpoint_records));
+            }
+            if let Some(period) = chunk_period {
+                bounds.push(format!("{}s of records", period.as_secs()));
+            }
+            progress!(
+                "Writing in {} chunks of {}, saving the import state after each",
+                chunks.len(),
+                bounds.join(" and at most ")
+            );
+        }
+        chunks
+    }
+
+    /// Advances each data type to the latest record imported for it
+    fn record_chunk(
+        &self,
+        _reader: &HealthDataReader,
+        run: &mut ImportRun,
+        chunks: &[Vec<HealthRecord>],
+        index: usize,
+        _points: usize,
+    ) {
+        for (record_type, records) in group_by_type(chunks[index].iter().cloned()) {
+            *run.journal
+                .records
+                .entry(record_type.to_string())
+                .or_default() += records.len();
+            if run.updates_state {
+                if let Some(latest) = records.iter().map(|r| r.timestamp).max() {
+                    run.import_state
+                        .record_import(record_type.as_str(), latest, records.len());
+                }
+            }
+        }
+        if run.updates_state {
+            for (record_type, row_id) in safe_row_ids(chunks, index + 1) {
+                run.import_state.record_row_id(record_type.as_str(), row_id);
+            }
+        }
+    }
+
+    async fn after_write(
+        &mut self,
+        reader: &Arc<HealthDataReader>,
+        influx_client: &InfluxClient,
+        run: &mut ImportRun,
+        written: &[HealthRecord],
+        complete: bool,
+    ) {
+        // Records modified up to the marks are imported once every record read is written
+        if run.updates_state && complete && !self.held_back {
+            for (data_type, modified) in &self.modified_marks {
+                run.import_state
+                    .record_modified(data_type.as_str(), *modified);
+            }
+        }
+
+        // Points deleted or moved in the source are deleted once the import is written
+        if let Some(days) = self
+            .reconcile_days
+            .filter(|_| self.gap_fill_heart_rate.is_none() && complete)
+        {
+            self.reconcile(reader, influx_client, run, days).await;
+        }
+
+        // Sessions are only marked once their records are written
+        if let Some(annotator) = &self.annotator {
+            self.annotate(annotator, run, written).await;
+        }
+
+        // Derived metrics are computed again for everything the records written touch
+        for (stage, client) in &mut self.stages {
+            let client = client.as_ref().unwrap_or(influx_client);
+            match derived::run_stage(stage.as_mut(), reader.as_ref(), client, written).await {
+                Ok(Some(points)) => {
+                    if let Some(warning) = stage.warning(points) {
+                        eprintln!("Warning: {}", warning);
+                    }
+                    if points > 0 {
+                        progress!(
+                            "{}: {} points{}",
+                            capitalize(stage.name()),
+                            points,
+                            stage.describe()
+                        );
+                        run.journal.records.insert(stage.name().to_string(), points);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    run.fail(
+                        format!("Error writing {} to InfluxDB: {}", stage.name(), e),
+                        EXIT_SINK_ERROR,
+                    )
+                    .await
+                }
+            }
+        }
+    }
+
+    fn state_not_updated(&self, run: &ImportRun, latest_timestamp: Option<DateTime<Utc>>) {
+        if run.settings.dry_run {
+            progress!("Dry-run mode: State file not updated");
+            if let Some(ts) = latest_timestamp {
+                progress!("Would update last imported timestamp to: {}", ts);
+            }
+        } else if self.gap_fill_heart_rate.is_some() {
+            progress!("Gap-filling mode: State file not updated");
+            progress!(
+                "Gap-filling is a maintenance operation - run normal sync first to update state"
+            );
+            if let Some(ts) = latest_timestamp {
+                progress!("Latest gap-filled timestamp: {}", ts);
+            }
+        }
+    }
+}
+
+/// Gives `name` with its first letter in upper case, to start a line of output with
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Imports the new records of a Health Connect export, and the points derived from them.
+/// --watch is left to the caller, which runs this again whenever the source changes
+pub async fn run(args: HealthImportArgs, lock_file: Option<&str>) {
+    let HealthImportArgs {
+        source,
+        merge_sources,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        state_file,
+        state_backups,
+        state_backend,
+        source_cache,
+        force_all,
+        on_conflict,
+        idempotent,
+        on_source_change,
+        on_invalid_watermark,
+        dry_run,
+        diff,
+        report_file,
+        limit,
+        data_types,
+        exclude_types,
+        gap_fill_heart_rate,
+        gap_fill_window,
+        spool_file,
+        checkpoint_every,
+        chunk_period,
+        max_in_flight_points,
+        connect_timeout,
+        request_timeout,
+        watch: _,
+        grafana_url,
+        grafana_token,
+        grafana_dashboard,
+        annotate,
+        annotation_tags,
+        daily_aggregates,
+        timezone,
+        max_heart_rate,
+        resting_heart_rate,
+        sleep_target,
+        downsample_bucket,
+        downsample_interval,
+        downsample_types,
+        sleep_priority,
+        sleep_hypnogram,
+        link_workouts,
+        derived_metrics,
+        zone_boundaries,
+        height,
+        reconcile_days,
+        anonymize,
+    } = args;
+
+    let source = fetch_source(
+        source,
+        &source_cache,
+        connect_timeout,
+        request_timeout,
+        true,
+    )
+    .await;
+
+    let _lock = lock_run(lock_file, &format!("import-health-data of '{}'", source));
+    interrupt::install(EXIT_INTERRUPTED);
+
+    let (source, merged_sources) = match merge_order(&source, &merge_sources) {
+        Ok(sources) => sources,
+        Err(e) => {
+            eprintln!("Error listing the exports to import: {}", e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+    progress!("Importing health data from SQLite database: '{}'", source);
+    for merged in &merged_sources {
+        progress!("  Merging: '{}'", merged);
+    }
+    progress!("  URL: {}", url);
+    progress!("  Organization: {}", org);
+    let bucket = resolve_database(bucket, database);
+    progress!("  Bucket/database: {}", bucket);
+    if let Some(rp) = &retention_policy {
+        progress!("  Retention policy: {}", rp);
+    }
+    progress!("  Dry-run mode: {}", if dry_run { "ON" } else { "OFF" });
+    progress!("  State file: {}", state_file);
+    let annotator = grafana_url.as_deref().map(|grafana_url| {
+        let sessions: Vec<String> = annotate.iter().map(ToString::to_string).collect();
+        progress!(
+            "  Grafana annotations: {} sessions, to {}",
+            sessions.join(" and "),
+            grafana_url
+        );
+        let mut annotator =
+            GrafanaAnnotator::new(grafana_url, grafana_token.as_deref().unwrap_or_default())
+                .with_tags(annotation_tags)
+                .with_timeout((request_timeout > 0).then(|| Duration::from_secs(request_timeout)));
+        if let Some(uid) = &grafana_dashboard {
+            annotator = annotator.with_dashboard(uid);
+        }
+        annotator
+    });
+
+    let training_load = max_heart_rate.map(|max_heart_rate| {
+        if resting_heart_rate >= max_heart_rate {
+            eprintln!(
+                "The resting heart rate ({}) must be below the maximum heart rate ({})",
+                resting_heart_rate, max_heart_rate
+            );
+            process::exit(EXIT_ERROR);
+        }
+        progress!(
+            "  Training load: heart rate {} to {} bpm",
+            resting_heart_rate,
+            max_heart_rate
+        );
+        TrainingLoad::new(max_heart_rate).with_resting_heart_rate(resting_heart_rate)
+    });
+
+    let heart_rate_zones = derived_metrics
+        .contains(&DerivedMetric::HeartRateZones)
+        .then(|| match (zone_boundaries.is_empty(), max_heart_rate) {
+            (false, _) => HeartRateZones::new(&zone_boundaries),
+            (true, Some(max_heart_rate)) => Ok(HeartRateZones::from_max_heart_rate(max_heart_rate)),
+            (true, None) => {
+                Err("heart-rate-zones needs --zone-boundaries or --max-heart-rate".to_string())
+            }
+        })
+        .map(|zones| {
+            zones.unwrap_or_else(|e| {
+                eprintln!("Invalid heart rate zones: {}", e);
+                process::exit(EXIT_ERROR);
+            })
+        });
+
+    let downsampling = downsample_bucket.as_deref().map(|downsample_bucket| {
+        progress!(
+            "  Downsampled: {} in {}s windows, to {}",
+            downsample_types
+                .iter()
+                .map(|data_type| data_type.as_str())
+                .collect::<Vec<_>>()
+                .join(","),
+            downsample_interval.as_secs(),
+            downsample_bucket
+        );
+        // The long-term bucket has a retention of its own, so no retention policy
+        let client = create_influx_client(
+            InfluxClient::builder(&url, downsample_bucket)
+                .token(&token)
+                .dry_run(dry_run)
+                .anonymize(anonymize),
+            &org,
+            None,
+            connect_timeout,
+            request_timeout,
+        );
+        (Downsampler::new(downsample_interval), client)
+    });
+
+    let timezone = timezone.unwrap_or_else(aggregate::system_timezone);
+
+    if height.is_some_and(|height| !(height > 0.0 && height < 3.0)) {
+        eprintln!("The height must be in meters, e.g. 1.75");
+        process::exit(EXIT_ERROR);
+    }
+
+    let requested_data_types = HealthDataType::select(
+        data_types.map(|names| DataTypeSelector::expand(&names)),
+        &DataTypeSelector::expand(&exclude_types),
+    );
+    if requested_data_types
+        .as_ref()
+        .is_some_and(|types| types.is_empty())
+    {
+        eprintln!("--exclude-types leaves no data types to import");
+        process::exit(EXIT_ERROR);
+    }
+    let data_types_filter = requested_data_types.as_ref().map(|types| {
+        types
+            .iter()
+            .map(|data_type| data_type.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    });
+    progress!(
+        "  Data types filter: {}",
+        data_types_filter.as_deref().unwrap_or("All types")
+    );
+
+    // Series tables are streamed on their own when memory is bounded
+    let streamed_types: Vec<HealthDataType> = match max_in_flight_points {
+        Some(_) if !merged_sources.is_empty() => {
+            eprintln!("Warning: merged sources are read whole, --max-in-flight-points is ignored");
+            Vec::new()
+        }
+        Some(max_points) => {
+            let streamed: Vec<HealthDataType> = HealthDataType::SERIES
+                .into_iter()
+                .filter(|data_type| {
+                    requested_data_types
+                        .as_ref()
+                        .is_none_or(|types| types.contains(data_type))
+                })
+                .collect();
+            if !streamed.is_empty() {
+                progress!(
+                    "  Streamed: {}, at most {} points in flight",
+                    streamed
+                        .iter()
+                        .map(|data_type| data_type.as_str())
+                        .collect::<Vec<_>>()
+                        .join(","),
+                    max_points
+                );
+            }
+            streamed
+        }
+        None => Vec::new(),
+    };
+
+    let mut journal = JournalEntry::new(
+        "import-health-data",
+        &source,
+        &format!("{} ({})", url, bucket),
+    );
+    journal.dry_run = dry_run;
+    if let Some(types) = &data_types_filter {
+        journal.filters.push(format!("data types: {}", types));
+    }
+    if let Some(anonymize) = anonymize {
+        journal.filters.push(format!("anonymize: {}", anonymize));
+    }
+    if link_workouts {
+        journal.filters.push("workout links".to_string());
+    }
+    if !merged_sources.is_empty() {
+        journal
+            .filters
+            .push(format!("merged: {}", merged_sources.join(", ")));
+    }
+    if let Some(days_back) = gap_fill_heart_rate {
+        journal
+            .filters
+            .push(format!("heart rate gap-fill: {} days", days_back));
+    }
+    if force_all {
+        journal.filters.push("force all".to_string());
+    }
+    if let Some(limit) = limit {
+        journal.filters.push(format!("limit: {}", limit));
+    }
+    let on_conflict = if idempotent {
+        ConflictPolicy::Skip
+    } else {
+        on_conflict
+    };
+    if on_conflict != ConflictPolicy::Overwrite {
+        journal
+            .filters
+            .push(format!("on conflict: {}", on_conflict));
+    }
+    if let Some(max_points) = max_in_flight_points.filter(|_| !streamed_types.is_empty()) {
+        journal
+            .filters
+            .push(format!("max in-flight points: {}", max_points));
+    }
+
+    // Open the state store; dry runs never write state to InfluxDB
+    let state_store = create_state_store(
+        &state_backend,
+        &state_file,
+        connect_timeout,
+        request_timeout,
+        || {
+            create_influx_client(
+                InfluxClient::builder(&url, &bucket)
+                    .token(&token)
+                    .dry_run(dry_run),
+                &org,
+                retention_policy.as_deref(),
+                connect_timeout,
+                request_timeout,
+            )
+        },
+    );
+    if state_backend != StateBackend::File {
+        progress!("  State backend: {}", state_store.describe());
+    }
+
+    // Load the import state
+    let mut import_state = load_state_or_exit(&state_store, &source).await;
+    let source_fingerprint = if force_all {
+        SourceFingerprint::compute(&source).ok()
+    } else {
+        check_source_fingerprint(&mut import_state, &source, on_source_change)
+    };
+
+    if force_all {
+        progress!("Force import all records (--force-all flag is set)");
+        import_state.last_imported_timestamp = None;
+        import_state.data_types.clear();
+    } else if let Some(timestamp) = import_state.last_imported_timestamp {
+        progress!(
+            "Previously imported: {} records",
+            import_state.records_imported
+        );
+        if import_state.data_types.is_empty() {
+            progress!("Skipping records before: {}", timestamp);
+        } else {
+            for (data_type, type_state) in &import_state.data_types {
+                match type_state.last_imported_timestamp {
+                    Some(ts) => progress!(
+                        "  - {}: skipping records before {} ({} imported)",
+                        data_type,
+                        ts,
+                        type_state.records_imported
+                    ),
+                    None => progress!("  - {}: no records imported yet", data_type),
+                }
+            }
+        }
+    } else {
+        progress!("No previous import state found, importing all records");
+    }
+
+    // Create a HealthDataReader to read from the SQLite database
+    let reader = Arc::new(
+        HealthDataReader::new(&source)
+            .with_data_types(HealthDataType::select(
+                requested_data_types.clone(),
+                &streamed_types,
+            ))
+            .with_sleep_priority(sleep_priority)
+            .with_merged_sources(merged_sources)
+            .with_workout_links(link_workouts),
+    );
+
+    let mut stages: Vec<(Box<dyn DerivedStage>, Option<InfluxClient>)> = Vec::new();
+    if !daily_aggregates.is_empty() {
+        stages.push((
+            Box::new(DailyAggregateStage::new(&daily_aggregates, timezone)),
+            None,
+        ));
+    }
+    if let Some(training_load) = training_load {
+        stages.push((
+            Box::new(TrainingLoadStage::new(training_load, timezone)),
+            None,
+        ));
+    }
+    if let Some(target) = sleep_target {
+        let target_minutes = target.as_secs_f64() / 60.0;
+        stages.push((
+            Box::new(SleepDebtStage::new(target_minutes, timezone)),
+            None,
+        ));
+    }
+    if let Some((downsampler, downsample_client)) = downsampling {
+        stages.push((
+            Box::new(DownsampleStage::new(downsampler, &downsample_types)),
+            Some(downsample_client),
+        ));
+    }
+    if let Some(interval) = sleep_hypnogram {
+        stages.push((Box::new(HypnogramStage::new(interval)), None));
+    }
+    if derived_metrics.contains(&DerivedMetric::BodyComposition) {
+        stages.push((Box::new(BodyCompositionStage), None));
+    }
+    if derived_metrics.contains(&DerivedMetric::Bmi) {
+        stages.push((Box::new(BmiStage::new(height)), None));
+    }
+    if let Some(zones) = heart_rate_zones {
+        stages.push((Box::new(HeartRateZonesStage::new(zones, timezone)), None));
+    }
+    if derived_metrics.contains(&DerivedMetric::WorkoutSummary) {
+        stages.push((Box::new(WorkoutSummaryStage::default()), None));
+    }
+
+    let hooks = HealthImport {
+        requested_data_types,
+        streamed_types,
+        max_in_flight_points,
+        gap_fill_heart_rate,
+        gap_fill_window,
+        checkpoint_every,
+        chunk_period,
+        reconcile_days,
+        annotator,
+        annotate,
+        anonymize: anonymize.is_some(),
+        stages,
+        modified_marks: Vec::new(),
+        held_back: false,
+    };
+    let settings = FileImport {
+        url,
+        org,
+        bucket,
+        token,
+        retention_policy,
+        connect_timeout,
+        request_timeout,
+        // Records are counted per data type in the journal instead
+        measurement: String::new(),
+        dry_run,
+        diff,
+        limit,
+        force_all,
+        on_invalid_watermark,
+        on_conflict,
+        anonymize,
+        spool_file,
+        state_backups,
+        report_file,
+        // Gap-filling reads heart rate records from before the watermarks
+        check_watermark: gap_fill_heart_rate.is_none(),
+    };
+    import_file_source(
+        reader,
+        hooks,
+        settings,
+        state_store,
+        import_state,
+        journal,
+        source_fingerprint,
+    )
+    .await;
+}
@@ -0,0 +1,1095 @@
+use crate::anonymize::Anonymize;
+use crate::config::FxConfig;
+use crate::fx::{FxConversion, DEFAULT_FX_API_URL};
+use crate::health_data::export_files;
+use crate::influx_client::{
+    ConflictCheck, ConflictPolicy, DataPoint, InfluxClient, InfluxClientBuilder,
+};
+use crate::interrupt::{self, Interrupted};
+use crate::output;
+use crate::pipeline::{Pipeline, PipelineSummary};
+use crate::remote::{self, RemoteSource, S3Config};
+use crate::report::{self, Phase, SkipReason};
+use crate::run_lock::RunLock;
+use crate::sink::Sink;
+use crate::source::Source;
+use crate::state_management::{ImportState, JournalEntry, RunReport, SourceFingerprint};
+use crate::state_store::{StateBackend, StateStore};
+use crate::upload;
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use clap::ValueEnum;
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+pub mod funds;
+pub mod health;
+pub mod mqtt;
+pub mod nav;
+pub mod prices;
+pub mod spool;
+
+/// Exit codes, so that scripts can tell "nothing new" apart from real failures
+/// Errors not covered by a more specific code (e.g., invalid arguments) exit with EXIT_ERROR
+pub const EXIT_ERROR: i32 = 1;
+/// The import found no new records
+pub const EXIT_NOTHING_NEW: i32 = 2;
+/// Some points could not be written and were spooled for `resume-spool`
+pub const EXIT_PARTIAL_WRITE: i32 = 3;
+/// The source file or database could not be read
+pub const EXIT_SOURCE_ERROR: i32 = 4;
+/// InfluxDB could not be reached or rejected the data
+pub const EXIT_SINK_ERROR: i32 = 5;
+/// The import state could not be read or saved
+pub const EXIT_STATE_ERROR: i32 = 6;
+/// The import was stopped by Ctrl-C or SIGTERM, following the shell's 128 + SIGINT convention
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Set by --watch and the daemon to a file where the import they run writes its run
+/// report, so they can read its statistics whatever the state backend
+pub const RUN_REPORT_ENV: &str = "HDI_RUN_REPORT";
+
+/// How to handle a source file that no longer matches the fingerprint in the state file
+#[derive(Clone, Copy, ValueEnum)]
+pub enum SourceChangeAction {
+    /// Ask interactively, failing when not running in a terminal
+    Ask,
+    /// Keep the existing watermarks and import incrementally
+    Continue,
+    /// Discard the existing state and import everything from the beginning
+    Restart,
+}
+
+/// How to handle a watermark that does not match the source data
+#[derive(Clone, Copy, ValueEnum)]
+pub enum InvalidWatermarkAction {
+    /// Print a warning and import incrementally anyway
+    Warn,
+    /// Clear the watermark and import that data again from the beginning
+    Reset,
+}
+
+/// Handles a watermark problem found by `ImportState::check_watermark` according to `action`
+fn handle_invalid_watermark(
+    import_state: &mut ImportState,
+    data_type: Option<&str>,
+    problem: &str,
+    action: InvalidWatermarkAction,
+) {
+    let label = data_type.unwrap_or("Import state");
+    match action {
+        InvalidWatermarkAction::Warn => {
+            println!("{}", output::warning(&format!("{}: {}", label, problem)));
+            println!(
+                "  New records may be skipped; use --on-invalid-watermark reset to re-import them"
+            );
+        }
+        InvalidWatermarkAction::Reset => {
+            println!("{}", output::warning(&format!("{}: {}", label, problem)));
+            println!("  Clearing the watermark and importing from the beginning");
+            import_state.clear_watermark(data_type);
+        }
+    }
+}
+
+/// Compares the source file with the fingerprint recorded in the import state and handles a
+/// replaced file according to `action`. Returns the current fingerprint to store on save
+pub fn check_source_fingerprint(
+    import_state: &mut ImportState,
+    source: &str,
+    action: SourceChangeAction,
+) -> Option<SourceFingerprint> {
+    let fingerprint = match SourceFingerprint::compute(source) {
+        Ok(fingerprint) => fingerprint,
+        Err(e) => {
+            eprintln!("Warning: could not fingerprint source file: {}", e);
+            return None;
+        }
+    };
+
+    match import_state.source_replaced(source) {
+        Ok(false) => return Some(fingerprint),
+        Ok(true) => {}
+        Err(e) => {
+            eprintln!(
+                "Warning: could not compare source file with the state: {}",
+                e
+            );
+            return Some(fingerprint);
+        }
+    }
+
+    println!(
+        "{}",
+        output::warning("The source file differs from the one previously imported")
+    );
+    if let Some(previous) = &import_state.source_fingerprint {
+        println!("  Previous: {}", previous);
+    }
+    println!("  Current:  {}", fingerprint);
+
+    let action = match action {
+        SourceChangeAction::Ask => prompt_source_change_action(),
+        action => action,
+    };
+    match action {
+        SourceChangeAction::Restart => {
+            println!("Restarting the import from the beginning");
+            *import_state = ImportState::new(source);
+        }
+        _ => println!("Continuing incrementally from the existing watermarks"),
+    }
+    Some(fingerprint)
+}
+
+/// Asks the user whether to continue or restart after the source file was replaced
+fn prompt_source_change_action() -> SourceChangeAction {
+    if !io::stdin().is_terminal() {
+        eprintln!("Refusing to import a replaced source file without confirmation");
+        eprintln!("Pass --on-source-change continue or --on-source-change restart to choose");
+        process::exit(EXIT_ERROR);
+    }
+
+    loop {
+        print!("[c]ontinue incrementally, [r]estart from the beginning or [a]bort? ");
+        let _ = io::stdout().flush();
+
+        let mut answer = String::new();
+        if io::stdin().lock().read_line(&mut answer).unwrap_or(0) == 0 {
+            process::exit(EXIT_ERROR);
+        }
+        match answer.trim().to_lowercase().as_str() {
+            "c" | "continue" => return SourceChangeAction::Continue,
+            "r" | "restart" => return SourceChangeAction::Restart,
+            "a" | "abort" => {
+                println!("Import aborted");
+                process::exit(EXIT_ERROR);
+            }
+            _ => println!("Please answer c, r or a"),
+        }
+    }
+}
+
+/// Opens the state store selected with --state-backend, exiting on failure
+/// The InfluxDB client is only created for the influxdb backend; HTTP and S3 backends use
+/// the connect and request timeouts
+pub fn create_state_store(
+    backend: &StateBackend,
+    state_file: &str,
+    connect_timeout: u64,
+    request_timeout: u64,
+    influx_client: impl FnOnce() -> InfluxClient,
+) -> StateStore {
+    let (connect_timeout, request_timeout) = (
+        timeout_from_secs(connect_timeout),
+        timeout_from_secs(request_timeout),
+    );
+    let store = match backend {
+        StateBackend::File => return StateStore::file(state_file),
+        StateBackend::InfluxDb => return StateStore::influxdb(influx_client(), state_file),
+        StateBackend::Http(url) => StateStore::http(url, connect_timeout, request_timeout),
+        StateBackend::S3(url) => {
+            StateStore::s3(url, S3Config::from_env(), connect_timeout, request_timeout)
+        }
+    };
+    match store {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Invalid state backend URL: {}", e);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+/// Loads the import state for a source, exiting when a remote state cannot be read
+pub async fn load_state_or_exit(state_store: &StateStore, source: &str) -> ImportState {
+    match state_store.load_import_state(source).await {
+        Ok(state) => state,
+        Err(e) => {
+            eprintln!(
+                "Failed to load import state from {}: {}",
+                state_store.describe(),
+                e
+            );
+            process::exit(EXIT_STATE_ERROR);
+        }
+    }
+}
+
+/// Completes a journal entry and appends it to the state store, then writes the run
+/// report to --report-file and for --watch or the daemon
+async fn finish_run(state_store: &StateStore, mut entry: JournalEntry, report_file: Option<&str>) {
+    entry.finished_at = Some(Utc::now());
+    let report = RunReport::new(
+        entry.clone(),
+        state_store.loaded_state(),
+        state_store.saved_state(),
+    );
+    let report_files = report_file
+        .map(String::from)
+        .into_iter()
+        .chain(std::env::var(RUN_REPORT_ENV).ok());
+    for report_file in report_files {
+        if let Err(e) = report.write(&report_file) {
+            eprintln!("Failed to write run report to {}: {}", report_file, e);
+        }
+    }
+    if let Err(e) = state_store.record_run(entry).await {
+        eprintln!("Failed to record run in journal: {}", e);
+    }
+}
+
+/// Reports a fatal error and records the failed run in the journal before the caller exits
+pub async fn fail_run(
+    state_store: &StateStore,
+    mut entry: JournalEntry,
+    report_file: Option<&str>,
+    message: String,
+) {
+    eprintln!("{}", message);
+    entry.errors.push(message);
+    finish_run(state_store, entry, report_file).await;
+}
+
+/// Checks that a source can be read, failing the run when it cannot
+async fn validate_source<S: Source>(
+    source: &S,
+    state_store: &StateStore,
+    journal: &JournalEntry,
+    report_file: Option<&str>,
+) {
+    report::reporter().phase_started(Phase::Validate, None);
+    match source.validate() {
+        Ok(summary) => {
+            report::reporter().phase_finished(Phase::Validate, 0);
+            progress!("{}", summary);
+        }
+        Err(e) => {
+            fail_run(
+                state_store,
+                journal.clone(),
+                report_file,
+                format!("Failed to validate source: {}", e),
+            )
+            .await;
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    }
+}
+
+/// Reads the records a source has after the watermark in the import state, noting the
+/// ones skipped as already imported in the journal; fails the run when the source cannot
+/// be read
+async fn read_new_records<S: Source>(
+    source: &S,
+    import_state: &ImportState,
+    state_store: &StateStore,
+    journal: &mut JournalEntry,
+    report_file: Option<&str>,
+) -> Vec<S::Record> {
+    report::reporter().phase_started(Phase::Read, None);
+    let stream = match source.records_since(import_state) {
+        Ok(stream) => stream,
+        Err(e) => {
+            fail_run(
+                state_store,
+                journal.clone(),
+                report_file,
+                format!("Error reading from source: {}", e),
+            )
+            .await;
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    };
+
+    let already_imported = stream.already_imported();
+    let records: Vec<S::Record> = stream.collect();
+    report::reporter().phase_finished(Phase::Read, records.len());
+    if already_imported > 0 {
+        report::reporter().records_skipped(already_imported, &SkipReason::AlreadyImported);
+        journal
+            .skipped
+            .insert("already imported".to_string(), already_imported);
+    }
+    records
+}
+
+/// Writes records through the pipeline, oldest first across all their types, so they are
+/// converted while earlier batches are being written, and returns the number of points
+/// written. A failed batch stops the writes, so no point is written after an unwritten one
+pub async fn write_records<K: Sink, S: Source + Send + Sync + 'static>(
+    sink: &K,
+    source: &Arc<S>,
+    mut records: Vec<S::Record>,
+) -> Result<usize, Box<dyn Error>> {
+    source.sort_by_time(&mut records);
+    report::reporter().phase_started(Phase::Write, Some(records.len()));
+    let summary = Pipeline::new(sink).run(Arc::clone(source), records).await?;
+    report::reporter().phase_finished(Phase::Write, summary.points);
+    if summary.failed_records > 0 {
+        eprintln!("Failed to convert {} records", summary.failed_records);
+    }
+    Ok(summary.points)
+}
+
+/// Writes records read through a cursor as they come, which must be oldest first, with at
+/// most `capacity` of them, and of their points, queued between the stages of the pipeline
+async fn stream_records<K, S, I>(
+    sink: &K,
+    source: &Arc<S>,
+    records: I,
+    capacity: usize,
+) -> Result<PipelineSummary, Box<dyn Error>>
+where
+    K: Sink,
+    S: Source + Send + Sync + 'static,
+    I: IntoIterator<Item = S::Record> + Send + 'static,
+    I::IntoIter: Send,
+{
+    report::reporter().phase_started(Phase::Write, None);
+    let summary = Pipeline::new(sink)
+        .with_capacity(capacity)
+        .run(Arc::clone(source), records)
+        .await?;
+    report::reporter().phase_finished(Phase::Write, summary.points);
+    if summary.failed_records > 0 {
+        eprintln!("Failed to convert {} records", summary.failed_records);
+    }
+    Ok(summary)
+}
+
+/// Settings of a file import that do not depend on the kind of source
+pub struct FileImport {
+    pub url: String,
+    pub org: String,
+    pub bucket: String,
+    pub token: String,
+    pub retention_policy: Option<String>,
+    pub connect_timeout: u64,
+    pub request_timeout: u64,
+    pub measurement: String,
+    pub dry_run: bool,
+    pub diff: bool,
+    pub limit: Option<u64>,
+    pub force_all: bool,
+    pub on_invalid_watermark: InvalidWatermarkAction,
+    pub on_conflict: ConflictPolicy,
+    pub anonymize: Option<Anonymize>,
+    pub spool_file: String,
+    pub state_backups: usize,
+    pub report_file: Option<String>,
+    /// Whether a watermark in the future or past the newest record is a problem; day-ahead
+    /// prices are in the future by design
+    pub check_watermark: bool,
+}
+
+/// A file import under way: its settings, and the import state and journal entry it updates
+pub struct ImportRun {
+    pub settings: FileImport,
+    pub state_store: StateStore,
+    pub import_state: ImportState,
+    pub journal: JournalEntry,
+    source_fingerprint: Option<SourceFingerprint>,
+    /// Whether the import state is saved; dry runs never save it
+    pub updates_state: bool,
+    state_backed_up: bool,
+}
+
+impl ImportRun {
+    /// Records the failed run in the journal and exits with `code`
+    pub async fn fail(&self, message: String, code: i32) -> ! {
+        fail_run(
+            &self.state_store,
+            self.journal.clone(),
+            self.settings.report_file.as_deref(),
+            message,
+        )
+        .await;
+        process::exit(code);
+    }
+
+    /// Backs up the state from before this run, once rather than at every checkpoint, and
+    /// notes the fingerprint of the source in the import state
+    pub fn back_up_state(&mut self) {
+        if self.state_backed_up {
+            return;
+        }
+        if let Err(e) = self.state_store.backup(self.settings.state_backups) {
+            eprintln!("Warning: failed to back up state file: {}", e);
+        }
+        if self.source_fingerprint.is_some() {
+            self.import_state.source_fingerprint = self.source_fingerprint.clone();
+        }
+        self.state_backed_up = true;
+    }
+
+    /// Saves the import state part way through; a failure only loses the checkpoint
+    pub async fn save_checkpoint(&mut self, name: &str) {
+        self.import_state.last_run = Some(Utc::now());
+        match self.state_store.save_import_state(&self.import_state).await {
+            Ok(_) => progress!("{}: import state saved", name),
+            Err(e) => eprintln!("Warning: failed to save checkpoint: {}", e),
+        }
+    }
+}
+
+/// The steps of a file import that depend on the kind of source. The defaults import a file
+/// whose records share one watermark, written all at once
+pub trait ImportHooks<S: Source> {
+    /// Checks whether a run saves the import state
+    fn updates_state(&self, settings: &FileImport) -> bool {
+        !settings.dry_run
+    }
+
+    /// Makes sure no watermark in the import state can skip the newest records in the source
+    fn check_watermarks(&self, source: &S, run: &mut ImportRun) {
+        // A file that cannot be parsed fails when it is read instead
+        if let Ok(records) = source.records_since(&ImportState::new("")) {
+            let records: Vec<S::Record> = records.collect();
+            let latest_in_source = source.latest_timestamp(&records);
+            if let Some(problem) = run
+                .import_state
+                .check_watermark(None, latest_in_source, None)
+            {
+                handle_invalid_watermark(
+                    &mut run.import_state,
+                    None,
+                    &problem,
+                    run.settings.on_invalid_watermark,
+                );
+            }
+        }
+    }
+
+    /// Runs before the records are read, returning the number of records and points it wrote
+    fn before_read(
+        &mut self,
+        _source: &Arc<S>,
+        _sink: &ConflictCheck<'_>,
+        _run: &mut ImportRun,
+    ) -> impl Future<Output = (usize, usize)> {
+        async { (0, 0) }
+    }
+
+    /// Reads the records to import, those after the watermark
+    fn read(
+        &mut self,
+        source: &S,
+        _influx_client: &InfluxClient,
+        run: &mut ImportRun,
+    ) -> impl Future<Output = Vec<S::Record>> {
+        async move {
+            read_new_records(
+                source,
+                &run.import_state,
+                &run.state_store,
+                &mut run.journal,
+                run.settings.report_file.as_deref(),
+            )
+            .await
+        }
+    }
+
+    /// Shows what is about to be imported
+    fn preview(&self, records: &[S::Record]) {
+        progress!(
+            "\nPreview of data to be imported: {} records",
+            records.len()
+        );
+    }
+
+    /// Takes the oldest `limit` records, returning the others to hold back. Held back records
+    /// are not written, but keep the watermarks from advancing past them
+    fn limit(
+        &mut self,
+        source: &S,
+        records: Vec<S::Record>,
+        limit: usize,
+    ) -> (Vec<S::Record>, Vec<S::Record>) {
+        (source.oldest(records, limit), Vec::new())
+    }
+
+    /// Splits the records into chunks, written oldest first with the import state saved
+    /// after each
+    fn chunks(
+        &self,
+        records: Vec<S::Record>,
+        _influx_client: &InfluxClient,
+        _run: &ImportRun,
+    ) -> Vec<Vec<S::Record>> {
+        vec![records]
+    }
+
+    /// Notes the chunk at `index`, written as `points` points, in the journal and the import
+    /// state. `chunks` are all of them, including the ones not written yet
+    fn record_chunk(
+        &self,
+        source: &S,
+        run: &mut ImportRun,
+        chunks: &[Vec<S::Record>],
+        index: usize,
+        points: usize,
+    ) {
+        let chunk = &chunks[index];
+        *run.journal
+            .records
+            .entry(run.settings.measurement.clone())
+            .or_default() += points;
+        if run.updates_state {
+            if let Some(latest) = source.latest_timestamp(chunk) {
+                run.import_state.last_imported_timestamp = Some(latest);
+                run.import_state.records_imported += chunk.len();
+            }
+        }
+    }
+
+    /// Runs once the chunks are written: `written` are the records written, and `complete`
+    /// tells whether every chunk was
+    fn after_write(
+        &mut self,
+        _source: &Arc<S>,
+        _influx_client: &InfluxClient,
+        _run: &mut ImportRun,
+        _written: &[S::Record],
+        _complete: bool,
+    ) -> impl Future<Output = ()> {
+        async {}
+    }
+
+    /// Explains why a run that does not update the import state left it as it was
+    fn state_not_updated(&self, run: &ImportRun, latest_timestamp: Option<DateTime<Utc>>) {
+        if run.settings.dry_run {
+            progress!("Dry-run mode: State file not updated");
+            if let Some(ts) = latest_timestamp {
+                progress!("Would update last imported timestamp to: {}", ts);
+            }
+        }
+    }
+}
+
+/// The steps of a file whose records share one watermark
+pub struct SingleWatermark;
+
+impl<S: Source> ImportHooks<S> for SingleWatermark {}
+
+/// Imports the records of a file source after the watermarks in the import state: validates
+/// the source, writes the new records and advances the watermarks to the latest ones
+/// written, with `hooks` for the steps that depend on the kind of source
+pub async fn import_file_source<S, H>(
+    source: Arc<S>,
+    mut hooks: H,
+    settings: FileImport,
+    state_store: StateStore,
+    import_state: ImportState,
+    journal: JournalEntry,
+    source_fingerprint: Option<SourceFingerprint>,
+) where
+    S: Source + Send + Sync + 'static,
+    S::Record: Clone,
+    H: ImportHooks<S>,
+{
+    let updates_state = hooks.updates_state(&settings);
+    let mut run = ImportRun {
+        settings,
+        state_store,
+        import_state,
+        journal,
+        source_fingerprint,
+        updates_state,
+        state_backed_up: false,
+    };
+
+    validate_source(
+        source.as_ref(),
+        &run.state_store,
+        &run.journal,
+        run.settings.report_file.as_deref(),
+    )
+    .await;
+
+    // Make sure no watermark can skip the newest records in the source
+    if run.settings.check_watermark && !run.settings.force_all {
+        hooks.check_watermarks(source.as_ref(), &mut run);
+    }
+
+    let influx_client = {
+        let settings = &run.settings;
+        create_influx_client(
+            if settings.dry_run {
+                InfluxClient::builder(&settings.url, &settings.bucket)
+                    .token(&settings.token)
+                    .dry_run(true)
+            } else {
+                InfluxClient::builder(&settings.url, &settings.bucket)
+                    .token(&settings.token)
+                    .spool_file(&settings.spool_file)
+            }
+            .anonymize(settings.anonymize),
+            &settings.org,
+            settings.retention_policy.as_deref(),
+            settings.connect_timeout,
+            settings.request_timeout,
+        )
+    };
+    // Records are written through this; points the hooks derive from them are not, as they
+    // are rewritten whenever their records change
+    let sink = ConflictCheck::new(&influx_client, run.settings.on_conflict);
+
+    let (streamed_records, streamed_points) = hooks.before_read(&source, &sink, &mut run).await;
+
+    let records = hooks.read(source.as_ref(), &influx_client, &mut run).await;
+    if records.is_empty() && streamed_records == 0 {
+        progress!("No new records to import");
+        finish_run(
+            &run.state_store,
+            run.journal,
+            run.settings.report_file.as_deref(),
+        )
+        .await;
+        process::exit(EXIT_NOTHING_NEW);
+    }
+    if !records.is_empty() {
+        hooks.preview(&records);
+    }
+
+    // Hold back everything after the oldest --limit records for the next run
+    let (records, held_back) = match run.settings.limit {
+        Some(limit) if records.len() > limit as usize => {
+            let total = records.len();
+            let (taken, held_back) = hooks.limit(source.as_ref(), records, limit as usize);
+            progress!(
+                "Limited to the oldest {} of {} records; the next run imports the rest",
+                taken.len(),
+                total
+            );
+            run.journal
+                .skipped
+                .insert("over --limit".to_string(), total - taken.len());
+            (taken, held_back)
+        }
+        _ => (records, Vec::new()),
+    };
+
+    // The watermarks advance to the latest records written
+    let latest_timestamp = source.latest_timestamp(&records);
+
+    let mut chunks = hooks.chunks(records, &influx_client, &run);
+    let chunk_count = chunks.len();
+    // Held back records are never written, but keep the watermarks from advancing past them
+    if !held_back.is_empty() {
+        chunks.push(held_back);
+    }
+
+    if run.updates_state {
+        run.back_up_state();
+    }
+
+    // Write the records to InfluxDB, oldest chunk first
+    let mut count = 0;
+    let mut interrupted = None;
+    let mut written_chunks = 0;
+    for (index, chunk) in chunks.iter().take(chunk_count).enumerate() {
+        // Chunks already written stay covered by the state saved below
+        if interrupt::requested() {
+            interrupted = Some(0);
+            break;
+        }
+        // Nothing is left to write when only the records written before reading were new
+        if chunk.is_empty() {
+            written_chunks += 1;
+            continue;
+        }
+        match write_records(&sink, &source, chunk.clone()).await {
+            Ok(written) => {
+                count += written;
+                written_chunks += 1;
+                hooks.record_chunk(source.as_ref(), &mut run, &chunks, index, written);
+            }
+            Err(e) if e.is::<Interrupted>() => {
+                interrupted = e.downcast_ref::<Interrupted>().map(|i| i.written_points);
+                break;
+            }
+            Err(e) => {
+                run.fail(format!("Error writing to InfluxDB: {}", e), EXIT_SINK_ERROR)
+                    .await;
+            }
+        }
+
+        // The last chunk is saved with the final state below
+        if run.updates_state && index + 1 < chunk_count {
+            run.save_checkpoint(&format!("Checkpoint {}/{}", index + 1, chunk_count))
+                .await;
+        }
+    }
+
+    let written: Vec<S::Record> = chunks
+        .iter()
+        .take(written_chunks)
+        .flatten()
+        .cloned()
+        .collect();
+    let complete = interrupted.is_none() && written_chunks == chunk_count;
+    hooks
+        .after_write(&source, &influx_client, &mut run, &written, complete)
+        .await;
+
+    if run.settings.diff {
+        let points: Vec<DataPoint> = chunks
+            .iter()
+            .take(chunk_count)
+            .flatten()
+            .filter_map(|record| source.to_points(record).ok())
+            .flatten()
+            .collect();
+        if let Err(e) = report_diff(&influx_client, &points).await {
+            run.fail(e, EXIT_SINK_ERROR).await;
+        }
+    }
+
+    let mode_prefix = if run.settings.dry_run {
+        "Would have"
+    } else {
+        "Successfully"
+    };
+    // Points left out by --on-conflict skip went through the pipeline but were not written
+    let skipped = sink.skipped_points();
+    progress!(
+        "{} imported {} data points to InfluxDB",
+        mode_prefix,
+        (count + streamed_points).saturating_sub(skipped)
+    );
+    if skipped > 0 {
+        progress!(
+            "Left out {} points InfluxDB already had (--on-conflict skip)",
+            skipped
+        );
+        run.journal
+            .skipped
+            .insert("already in InfluxDB".to_string(), skipped);
+    }
+    report_spooled_points(&influx_client, &run.settings.spool_file);
+    record_spooled_points(&mut run.journal, &influx_client, &run.settings.spool_file);
+
+    if let Some(partial) = interrupted {
+        let records_in = |taken: usize| -> usize { chunks.iter().take(taken).map(Vec::len).sum() };
+        let (imported, total) = (records_in(written_chunks), records_in(chunk_count));
+        let message = format!(
+            "Interrupted: imported {} of {} records; the next run continues with the other {}",
+            imported,
+            total,
+            total - imported
+        );
+        println!("{}", output::warning(&message));
+        if partial > 0 {
+            eprintln!(
+                "  {} points of the interrupted batch were written and will be written again",
+                partial
+            );
+        }
+        run.journal.errors.push(message);
+    }
+
+    // Save the import state
+    let mut state_saved = true;
+    if run.updates_state {
+        if latest_timestamp.is_some() || streamed_records > 0 {
+            run.import_state.last_run = Some(Utc::now());
+            report::reporter().phase_started(Phase::SaveState, None);
+            match run.state_store.save_import_state(&run.import_state).await {
+                Ok(_) => {
+                    report::reporter().phase_finished(Phase::SaveState, count + streamed_points);
+                    progress!(
+                        "Updated import state saved to {}",
+                        run.state_store.describe()
+                    )
+                }
+                Err(e) => {
+                    eprintln!("Failed to save import state: {}", e);
+                    run.journal
+                        .errors
+                        .push(format!("Failed to save import state: {}", e));
+                    state_saved = false;
+                }
+            }
+        }
+    } else {
+        hooks.state_not_updated(&run, latest_timestamp);
+    }
+    finish_run(
+        &run.state_store,
+        run.journal,
+        run.settings.report_file.as_deref(),
+    )
+    .await;
+    if interrupted.is_some() && state_saved {
+        process::exit(EXIT_INTERRUPTED);
+    }
+    exit_after_import(&influx_client, state_saved);
+}
+
+/// Converts a timeout given in seconds into a Duration, treating 0 as "no timeout"
+fn timeout_from_secs(secs: u64) -> Option<Duration> {
+    if secs == 0 {
+        None
+    } else {
+        Some(Duration::from_secs(secs))
+    }
+}
+
+/// Picks the database to write to: --database (InfluxDB 1.x) takes precedence over --bucket
+pub fn resolve_database(bucket: Option<String>, database: Option<String>) -> String {
+    // clap guarantees that at least one of the two is present
+    database.or(bucket).unwrap_or_default()
+}
+
+/// Lists the exports of an import: the one read from (the source, or the last export of a
+/// source directory) and those merged into it, newest first by file name, so that the
+/// records of newer exports are kept over the same records of older ones
+fn merge_order(source: &str, merge_sources: &[String]) -> io::Result<(String, Vec<String>)> {
+    let mut exports = export_files(source)?;
+    let Some(primary) = exports.pop() else {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no SQLite files in {}", source),
+        ));
+    };
+    for path in merge_sources {
+        exports.extend(export_files(path)?);
+    }
+    let mut listed = HashSet::new();
+    exports.retain(|path| *path != primary && listed.insert(path.clone()));
+    exports.sort_by(|a, b| Path::new(b).file_name().cmp(&Path::new(a).file_name()));
+    Ok((primary, exports))
+}
+
+/// Applies the command line organization, retention policy and timeouts to an InfluxDB client
+/// and builds it, exiting on failure
+pub fn create_influx_client(
+    builder: InfluxClientBuilder,
+    org: &str,
+    retention_policy: Option<&str>,
+    connect_timeout: u64,
+    request_timeout: u64,
+) -> InfluxClient {
+    let builder = builder
+        .org(org)
+        .connect_timeout(timeout_from_secs(connect_timeout))
+        .request_timeout(timeout_from_secs(request_timeout));
+    let builder = match retention_policy {
+        Some(rp) => builder.retention_policy(rp),
+        None => builder,
+    };
+
+    match builder.build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Failed to create InfluxDB client: {}", e);
+            process::exit(EXIT_SINK_ERROR);
+        }
+    }
+}
+
+/// Notes points that were spooled instead of written as an error of the run
+fn record_spooled_points(
+    journal: &mut JournalEntry,
+    influx_client: &InfluxClient,
+    spool_file: &str,
+) {
+    let spooled = influx_client.spooled_points();
+    if spooled > 0 {
+        journal
+            .errors
+            .push(format!("{} points were spooled to {}", spooled, spool_file));
+    }
+}
+
+/// Prints how many of the points a dry run would write are new to InfluxDB, per measurement
+async fn report_diff(influx_client: &InfluxClient, points: &[DataPoint]) -> Result<(), String> {
+    let diff = influx_client
+        .diff_with_existing(points)
+        .await
+        .map_err(|e| format!("Error comparing with existing data: {}", e))?;
+
+    println!("\nCompared with the data in InfluxDB:");
+    let rows: Vec<Vec<String>> = diff
+        .iter()
+        .map(|(measurement, counts)| {
+            vec![
+                measurement.clone(),
+                counts.new.to_string(),
+                counts.existing.to_string(),
+            ]
+        })
+        .collect();
+    println!(
+        "{}",
+        output::table(&["Measurement", "New", "Already present"], &rows)
+    );
+
+    let existing: usize = diff.values().map(|counts| counts.existing).sum();
+    if existing > 0 {
+        println!(
+            "{}",
+            output::warning(&format!(
+                "{} of {} points have timestamps already in InfluxDB; importing would write them again",
+                existing,
+                points.len()
+            ))
+        );
+    } else {
+        println!(
+            "{}",
+            output::success(&format!("All {} points are new to InfluxDB", points.len()))
+        );
+    }
+    Ok(())
+}
+
+/// Exits with a distinct code when an import wrote its data but could not save its state,
+/// or when part of it was spooled instead of written
+pub fn exit_after_import(influx_client: &InfluxClient, state_saved: bool) {
+    if !state_saved {
+        process::exit(EXIT_STATE_ERROR);
+    }
+    if influx_client.spooled_points() > 0 {
+        process::exit(EXIT_PARTIAL_WRITE);
+    }
+}
+
+/// Tells the user about points that were spooled instead of written
+pub fn report_spooled_points(influx_client: &InfluxClient, spool_file: &str) {
+    let spooled = influx_client.spooled_points();
+    if spooled > 0 {
+        println!(
+            "{}",
+            output::warning(&format!(
+                "{} points could not be written and were spooled to {}",
+                spooled, spool_file
+            ))
+        );
+        println!(
+            "  Run `resume-spool --spool-file {}` to retry them",
+            spool_file
+        );
+    }
+}
+
+/// Downloads a --source given as a URL into the cache directory and gets the path of the
+/// copy to import, exiting with EXIT_SOURCE_ERROR when that fails; local sources are kept
+/// as they are. With `health_export`, the database inside a downloaded zip file is imported
+pub async fn fetch_source(
+    source: String,
+    cache_dir: &str,
+    connect_timeout: u64,
+    request_timeout: u64,
+    health_export: bool,
+) -> String {
+    if !remote::is_remote(&source) {
+        return source;
+    }
+    let remote = RemoteSource::new(&source, cache_dir)
+        .with_s3(S3Config::from_env())
+        .with_connect_timeout(timeout_from_secs(connect_timeout))
+        .with_request_timeout(timeout_from_secs(request_timeout));
+    let fetched = match remote.fetch().await {
+        Ok(fetched) => fetched,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    };
+    if fetched.downloaded {
+        progress!(
+            "Downloaded {} ({} bytes) to '{}'",
+            remote.describe(),
+            fetched.bytes,
+            fetched.path.display()
+        );
+    } else {
+        progress!(
+            "{} has not changed since the last download; using '{}'",
+            remote.describe(),
+            fetched.path.display()
+        );
+    }
+
+    let mut path = fetched.path;
+    if health_export && upload::is_zip(&path).unwrap_or(false) {
+        let database = path.with_extension("export.db");
+        if fetched.downloaded || !database.is_file() {
+            let stored = File::open(&path)
+                .map_err(Box::<dyn Error>::from)
+                .and_then(|zip| {
+                    upload::store_export(
+                        zip,
+                        &database.display().to_string(),
+                        Local::now(),
+                        u64::MAX,
+                    )
+                });
+            if let Err(e) = stored {
+                eprintln!("Error reading the export in '{}': {}", path.display(), e);
+                process::exit(EXIT_SOURCE_ERROR);
+            }
+        }
+        path = database;
+    }
+    path.display().to_string()
+}
+
+/// Fetches the daily exchange rates of a conversion from `since` until today, when the `[fx]`
+/// section asks for them. Rates are published on working days only, so the fetch starts a
+/// week early to cover the first days
+pub async fn fetch_fx_rates(
+    conversion: &mut FxConversion,
+    fx: &FxConfig,
+    since: NaiveDate,
+    request_timeout: u64,
+) {
+    if !fx.fetch.unwrap_or(false) {
+        return;
+    }
+    let api_url = fx.api_url.as_deref().unwrap_or(DEFAULT_FX_API_URL);
+    let currencies = conversion.currencies();
+    let timeout = (request_timeout > 0).then(|| Duration::from_secs(request_timeout));
+    let start = since - chrono::Duration::days(7);
+    let today = Utc::now().date_naive();
+    if let Err(e) = conversion
+        .rates_mut()
+        .fetch(api_url, &currencies, start, today, timeout)
+        .await
+    {
+        eprintln!("Failed to fetch exchange rates: {}", e);
+        process::exit(EXIT_SOURCE_ERROR);
+    }
+}
+
+/// Takes the --lock-file lock for a run that writes to InfluxDB, waiting while another
+/// run holds it. The lock is held until the process exits
+pub fn lock_run(lock_file: Option<&str>, holder: &str) -> Option<RunLock> {
+    let lock_file = lock_file?;
+    let lock = RunLock::acquire(lock_file, holder, |current| {
+        progress!(
+            "Waiting for the lock on '{}', held by {}",
+            lock_file,
+            if current.is_empty() {
+                "another run"
+            } else {
+                current
+            }
+        )
+    });
+    match lock {
+        Ok(lock) => Some(lock),
+        Err(e) => {
+            eprintln!("Failed to lock '{}': {}", lock_file, e);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
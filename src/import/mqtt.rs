@@ -0,0 +1,161 @@
+use super::{
+    create_influx_client, report_spooled_points, resolve_database, EXIT_ERROR, EXIT_INTERRUPTED,
+    EXIT_SOURCE_ERROR,
+};
+use crate::config::Config;
+use crate::influx_client::{
+    InfluxClient, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use crate::interrupt;
+use crate::mqtt::{self, MqttOptions, DEFAULT_FLUSH_INTERVAL_SECS, DEFAULT_MQTT_PORT};
+use clap::Args;
+use std::process;
+use std::time::Duration;
+
+/// The options of `mqtt`
+#[derive(Args)]
+pub struct MqttArgs {
+    /// MQTT broker host
+    #[arg(long, default_value = "localhost", env = "HDI_MQTT_HOST")]
+    pub host: String,
+
+    /// MQTT broker port
+    #[arg(long, default_value_t = DEFAULT_MQTT_PORT, env = "HDI_MQTT_PORT")]
+    pub port: u16,
+
+    /// Client ID to connect with; it must be unique on the broker
+    #[arg(long, default_value = "home-db-importer", env = "HDI_MQTT_CLIENT_ID")]
+    pub client_id: String,
+
+    /// MQTT username
+    #[arg(long, env = "HDI_MQTT_USERNAME")]
+    pub username: Option<String>,
+
+    /// MQTT password
+    #[arg(long, env = "HDI_MQTT_PASSWORD", hide_env_values = true)]
+    pub password: Option<String>,
+
+    /// Seconds between writes of the points received
+    #[arg(long, default_value_t = DEFAULT_FLUSH_INTERVAL_SECS, env = "HDI_FLUSH_INTERVAL")]
+    pub flush_interval: u64,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to write into (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// Spool file where points that fail to write are saved for `resume-spool`
+    #[arg(long, default_value = ".mqtt_spool.lp", env = "HDI_SPOOL_FILE")]
+    pub spool_file: String,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+}
+
+/// Subscribes to the [[mqtt.topics]] of `config_file` and writes their readings to
+/// InfluxDB until stopped
+pub async fn run(args: MqttArgs, config_file: Option<&str>) {
+    let MqttArgs {
+        host,
+        port,
+        client_id,
+        username,
+        password,
+        flush_interval,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        spool_file,
+        connect_timeout,
+        request_timeout,
+    } = args;
+
+    // Topics are tables, so they can only come from the config file
+    let Some(config_file) = config_file else {
+        eprintln!("mqtt reads its [[mqtt.topics]] from a config file; pass it with --config");
+        process::exit(EXIT_ERROR);
+    };
+    let topics = match Config::load(config_file) {
+        Ok(config) => config.mqtt.topics,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+    if topics.is_empty() {
+        eprintln!("No [[mqtt.topics]] in {}", config_file);
+        process::exit(EXIT_ERROR);
+    }
+    interrupt::install(EXIT_INTERRUPTED);
+
+    let bucket = resolve_database(bucket, database);
+    progress!("Writing MQTT messages from {}:{} into InfluxDB", host, port);
+    progress!("  URL: {}", url);
+    progress!("  Bucket/database: {}", bucket);
+    progress!("  Topics: {}", topics.len());
+
+    let influx_client = create_influx_client(
+        InfluxClient::builder(&url, &bucket)
+            .token(&token)
+            .spool_file(&spool_file),
+        &org,
+        retention_policy.as_deref(),
+        connect_timeout,
+        request_timeout,
+    );
+    let mut options = MqttOptions::new(client_id, host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(username) = username {
+        options.set_credentials(username, password.unwrap_or_default());
+    }
+
+    match mqtt::subscribe(
+        options,
+        &topics,
+        &influx_client,
+        Duration::from_secs(flush_interval.max(1)),
+    )
+    .await
+    {
+        Ok(summary) => {
+            progress!(
+                "Stopped after {} messages: wrote {} points, {} messages could not be converted",
+                summary.messages,
+                summary.points,
+                summary.failed_messages
+            );
+            report_spooled_points(&influx_client, &spool_file);
+        }
+        Err(e) => {
+            eprintln!("MQTT subscription failed: {}", e);
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    }
+}
@@ -0,0 +1,214 @@
+use super::{
+    create_influx_client, exit_after_import, fetch_fx_rates, report_spooled_points,
+    resolve_database, EXIT_ERROR, EXIT_INTERRUPTED, EXIT_SINK_ERROR, EXIT_SOURCE_ERROR,
+};
+use crate::config::Config;
+use crate::convert::DEFAULT_FUND_TAG_KEY;
+use crate::influx_client::{
+    InfluxClient, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use crate::interrupt;
+use crate::nav::{NavApi, DEFAULT_NAV_MEASUREMENT};
+use crate::schedule::parse_interval;
+use chrono::Utc;
+use clap::Args;
+use std::process;
+use std::time::Duration;
+
+/// The options of `fetch-nav`
+#[derive(Args)]
+pub struct NavFetchArgs {
+    /// URL of a fund's NAV, with {isin} where its ISIN goes
+    #[arg(long, env = "HDI_NAV_API_URL")]
+    pub api_url: Option<String>,
+
+    /// JSONPath of the NAV in the response, e.g. $.data.nav
+    #[arg(long, env = "HDI_NAV_PRICE_PATH")]
+    pub price_path: Option<String>,
+
+    /// JSONPath of the NAV's date; NAVs are written at midnight UTC of the day they were
+    /// fetched when omitted
+    #[arg(long, env = "HDI_NAV_TIME_PATH")]
+    pub time_path: Option<String>,
+
+    /// Measurement name in InfluxDB
+    #[arg(short, long, default_value = DEFAULT_NAV_MEASUREMENT, env = "HDI_MEASUREMENT")]
+    pub measurement: String,
+
+    /// Key of the tag naming the fund of each NAV
+    #[arg(long, default_value = DEFAULT_FUND_TAG_KEY, env = "HDI_TAG_KEY")]
+    pub tag_key: String,
+
+    /// Keep running and fetch again at this interval (e.g., 30m, 1h)
+    #[arg(long, value_parser = parse_interval, env = "HDI_EVERY")]
+    pub every: Option<Duration>,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to write into (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// Run in dry-run mode (don't write to InfluxDB, just show queries)
+    #[arg(long, env = "HDI_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Spool file where points that fail to write are saved for `resume-spool`
+    #[arg(long, default_value = ".nav_spool.lp", env = "HDI_SPOOL_FILE")]
+    pub spool_file: String,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB or NAV API request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+}
+
+/// Fetches the NAV of the [[nav.funds]] of `config_file` and writes it to InfluxDB, once
+/// or --every interval until stopped
+pub async fn run(args: NavFetchArgs, config_file: Option<&str>) {
+    let NavFetchArgs {
+        api_url,
+        price_path,
+        time_path,
+        measurement,
+        tag_key,
+        every,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        dry_run,
+        spool_file,
+        connect_timeout,
+        request_timeout,
+    } = args;
+
+    // Funds are tables, so they can only come from the config file
+    let Some(config_file) = config_file else {
+        eprintln!("fetch-nav reads its [[nav.funds]] from a config file; pass it with --config");
+        process::exit(EXIT_ERROR);
+    };
+    let (funds, fx) = match Config::load(config_file) {
+        Ok(config) => (config.nav.funds, config.fx),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+    if funds.is_empty() {
+        eprintln!("No [[nav.funds]] in {}", config_file);
+        process::exit(EXIT_ERROR);
+    }
+    let (Some(api_url), Some(price_path)) = (api_url, price_path) else {
+        eprintln!("fetch-nav needs --api-url and --price-path (api_url and price_path in [nav])");
+        process::exit(EXIT_ERROR);
+    };
+    let mut api = NavApi::new(&api_url, &price_path)
+        .with_measurement(&measurement)
+        .with_tag_key(&tag_key);
+    if let Some(time_path) = &time_path {
+        api = api.with_time_path(time_path);
+    }
+    let mut conversion = fx.conversion().map(|conversion| {
+        let mut conversion = conversion.with_tag_key(&tag_key);
+        for fund in &funds {
+            if let Some(currency) = &fund.currency {
+                conversion = conversion.with_fund_currency(&fund.tag(), currency);
+            }
+        }
+        conversion
+    });
+    interrupt::install(EXIT_INTERRUPTED);
+
+    let bucket = resolve_database(bucket, database);
+    progress!("Fetching the NAV of {} funds from {}", funds.len(), api_url);
+    progress!("  URL: {}", url);
+    progress!("  Bucket/database: {}", bucket);
+    progress!("  Measurement: {}", measurement);
+    progress!("  Dry-run mode: {}", if dry_run { "ON" } else { "OFF" });
+
+    let influx_client = create_influx_client(
+        InfluxClient::builder(&url, &bucket)
+            .token(&token)
+            .dry_run(dry_run)
+            .spool_file(&spool_file),
+        &org,
+        retention_policy.as_deref(),
+        connect_timeout,
+        request_timeout,
+    );
+    let timeout = (request_timeout > 0).then(|| Duration::from_secs(request_timeout));
+    loop {
+        let (points, errors) = match api.fetch(&funds, timeout).await {
+            Ok(fetched) => fetched,
+            Err(e) => {
+                eprintln!("Failed to fetch NAVs: {}", e);
+                process::exit(EXIT_SOURCE_ERROR);
+            }
+        };
+        for (isin, error) in &errors {
+            eprintln!("Failed to fetch the NAV of {}: {}", isin, error);
+        }
+        let fetched = points.len();
+        let mut points = points;
+        if let Some(conversion) = &mut conversion {
+            fetch_fx_rates(conversion, &fx, Utc::now().date_naive(), request_timeout).await;
+            let with_currency = points.iter().cloned().map(|point| (point, None)).collect();
+            match conversion.add_normalized(with_currency) {
+                Ok(converted) => points = converted,
+                Err(e) => eprintln!("Failed to convert NAVs: {}", e),
+            }
+        }
+        if !points.is_empty() {
+            if let Err(e) = influx_client.write_points(&points).await {
+                eprintln!("Failed to write NAVs: {}", e);
+                process::exit(EXIT_SINK_ERROR);
+            }
+        }
+        progress!(
+            "[{}] Wrote the NAV of {} of {} funds",
+            Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+            fetched,
+            funds.len()
+        );
+        report_spooled_points(&influx_client, &spool_file);
+
+        let Some(every) = every else {
+            if !errors.is_empty() {
+                process::exit(EXIT_SOURCE_ERROR);
+            }
+            exit_after_import(&influx_client, true);
+            break;
+        };
+        let next = std::time::Instant::now() + every;
+        while std::time::Instant::now() < next && !interrupt::requested() {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
+        if interrupt::requested() {
+            break;
+        }
+    }
+}
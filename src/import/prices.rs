@@ -0,0 +1,241 @@
+use super::{
+    create_influx_client, create_state_store, fail_run, import_file_source, load_state_or_exit,
+    lock_run, resolve_database, FileImport, InvalidWatermarkAction, SingleWatermark,
+    EXIT_INTERRUPTED, EXIT_SOURCE_ERROR,
+};
+use crate::influx_client::{
+    ConflictPolicy, InfluxClient, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use crate::interrupt;
+use crate::prices::{PriceSeries, PRICE_MEASUREMENT};
+use crate::state_management::JournalEntry;
+use crate::state_store::StateBackend;
+use chrono::Utc;
+use clap::Args;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The options of `import-prices`
+#[derive(Args)]
+pub struct PricesImportArgs {
+    /// CSV export to import (e.g. from Nord Pool); prices are fetched from aWATTar when omitted
+    #[arg(short, long, env = "HDI_SOURCE")]
+    pub source: Option<String>,
+
+    /// Bidding zone: AT or DE-LU when fetching from aWATTar, or the zone of a CSV export
+    /// with a single price column
+    #[arg(long, required_unless_present = "source", env = "HDI_ZONE")]
+    pub zone: Option<String>,
+
+    /// Days of past prices to fetch on the first run
+    #[arg(long, default_value_t = 7, env = "HDI_DAYS")]
+    pub days: u32,
+
+    /// Price API endpoint, instead of aWATTar's for the zone
+    #[arg(long, env = "HDI_API_URL")]
+    pub api_url: Option<String>,
+
+    /// Format of times in the CSV export that are not RFC 3339 or Unix milliseconds
+    #[arg(long, default_value = "%Y-%m-%d %H:%M:%S", env = "HDI_TIME_FORMAT")]
+    pub time_format: String,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to write into (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// Run in dry-run mode (don't write to InfluxDB, just show queries)
+    #[arg(long, env = "HDI_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Writes a JSON summary of each run to FILE
+    #[arg(long, value_name = "FILE", env = "HDI_REPORT_FILE")]
+    pub report_file: Option<String>,
+
+    /// State file to track last imported timestamp
+    #[arg(long, default_value = ".prices_state.json", env = "HDI_STATE_FILE")]
+    pub state_file: String,
+
+    /// Number of rotated copies of the state file to keep before updating it (0 disables)
+    #[arg(long, default_value = "3", env = "HDI_STATE_BACKUPS")]
+    pub state_backups: usize,
+
+    /// Where to keep import state: file, influxdb (the importer_state measurement), an
+    /// http(s) URL or an s3://bucket/key URL
+    #[arg(long, default_value = "file", env = "HDI_STATE_BACKEND")]
+    pub state_backend: StateBackend,
+
+    /// Force import all prices, ignoring state file
+    #[arg(long, env = "HDI_FORCE_ALL")]
+    pub force_all: bool,
+
+    /// Spool file where points that fail to write are saved for `resume-spool`
+    #[arg(long, default_value = ".prices_spool.lp", env = "HDI_SPOOL_FILE")]
+    pub spool_file: String,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB or price API request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+}
+
+/// Imports the day-ahead prices of a CSV export, or those aWATTar published since the last
+/// import, into the prices measurement
+pub async fn run(args: PricesImportArgs, lock_file: Option<&str>) {
+    let PricesImportArgs {
+        source,
+        zone,
+        days,
+        api_url,
+        time_format,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        dry_run,
+        report_file,
+        state_file,
+        state_backups,
+        state_backend,
+        force_all,
+        spool_file,
+        connect_timeout,
+        request_timeout,
+    } = args;
+
+    // aWATTar prices are kept apart from a CSV export's by their state key
+    let origin = match (&source, &zone) {
+        (Some(source), _) => source.clone(),
+        (None, Some(zone)) => format!("awattar:{}", zone.to_uppercase()),
+        (None, None) => unreachable!("clap requires --zone without --source"),
+    };
+    let _lock = lock_run(lock_file, &format!("import-prices of '{}'", origin));
+    interrupt::install(EXIT_INTERRUPTED);
+
+    progress!("Importing day-ahead prices from '{}' into InfluxDB", origin);
+    progress!("  URL: {}", url);
+    progress!("  Organization: {}", org);
+    let bucket = resolve_database(bucket, database);
+    progress!("  Bucket/database: {}", bucket);
+    if let Some(rp) = &retention_policy {
+        progress!("  Retention policy: {}", rp);
+    }
+    progress!("  Dry-run mode: {}", if dry_run { "ON" } else { "OFF" });
+    progress!("  State file: {}", state_file);
+
+    let mut journal = JournalEntry::new("import-prices", &origin, &format!("{} ({})", url, bucket));
+    journal.dry_run = dry_run;
+    if force_all {
+        journal.filters.push("force all".to_string());
+    }
+
+    // Open the state store; dry runs never write state to InfluxDB
+    let state_store = create_state_store(
+        &state_backend,
+        &state_file,
+        connect_timeout,
+        request_timeout,
+        || {
+            create_influx_client(
+                InfluxClient::builder(&url, &bucket)
+                    .token(&token)
+                    .dry_run(dry_run),
+                &org,
+                retention_policy.as_deref(),
+                connect_timeout,
+                request_timeout,
+            )
+        },
+    );
+    if state_backend != StateBackend::File {
+        progress!("  State backend: {}", state_store.describe());
+    }
+
+    let mut import_state = load_state_or_exit(&state_store, &origin).await;
+    if force_all {
+        progress!("Force import all prices (--force-all flag is set)");
+        import_state.last_imported_timestamp = None;
+    } else if let Some(timestamp) = import_state.last_imported_timestamp {
+        progress!("Skipping prices before: {}", timestamp);
+    } else {
+        progress!("No previous import state found, importing all prices");
+    }
+
+    let series = match (&source, &zone) {
+        (Some(source), zone) => PriceSeries::from_csv(source, zone.as_deref(), &time_format),
+        (None, Some(zone)) => {
+            // Day-ahead prices for tomorrow are published around noon
+            let start = import_state
+                .last_imported_timestamp
+                .unwrap_or_else(|| Utc::now() - chrono::Duration::days(days.into()));
+            let end = Utc::now() + chrono::Duration::days(2);
+            let timeout = (request_timeout > 0).then(|| Duration::from_secs(request_timeout));
+            PriceSeries::fetch_awattar(zone, start, end, api_url.as_deref(), timeout).await
+        }
+        (None, None) => unreachable!("clap requires --zone without --source"),
+    };
+    let series = match series {
+        Ok(series) => series,
+        Err(e) => {
+            fail_run(&state_store, journal, report_file.as_deref(), e.to_string()).await;
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    };
+
+    let settings = FileImport {
+        url,
+        org,
+        bucket,
+        token,
+        retention_policy,
+        connect_timeout,
+        request_timeout,
+        measurement: PRICE_MEASUREMENT.to_string(),
+        dry_run,
+        diff: false,
+        limit: None,
+        force_all,
+        on_invalid_watermark: InvalidWatermarkAction::Warn,
+        on_conflict: ConflictPolicy::Overwrite,
+        anonymize: None,
+        spool_file,
+        state_backups,
+        report_file,
+        check_watermark: false,
+    };
+    import_file_source(
+        Arc::new(series),
+        SingleWatermark,
+        settings,
+        state_store,
+        import_state,
+        journal,
+        None,
+    )
+    .await;
+}
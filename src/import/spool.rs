@@ -0,0 +1,147 @@
+use super::{
+    create_influx_client, lock_run, resolve_database, EXIT_INTERRUPTED, EXIT_NOTHING_NEW,
+    EXIT_PARTIAL_WRITE, EXIT_SINK_ERROR, EXIT_SOURCE_ERROR,
+};
+use crate::influx_client::{
+    InfluxClient, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use crate::interrupt;
+use crate::output;
+use crate::spool::{load_spool, save_spool};
+use clap::Args;
+use std::process;
+
+/// The options of `resume-spool`
+#[derive(Args)]
+pub struct ResumeSpoolArgs {
+    /// The spool file containing unsent points in line protocol
+    #[arg(short, long, required = true, env = "HDI_SPOOL_FILE")]
+    pub spool_file: String,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to write into (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+}
+
+/// Writes the points of a spool file to InfluxDB, keeping in it those that could not be
+/// written
+pub async fn run(args: ResumeSpoolArgs, lock_file: Option<&str>) {
+    let ResumeSpoolArgs {
+        spool_file,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        connect_timeout,
+        request_timeout,
+    } = args;
+
+    let _lock = lock_run(lock_file, &format!("resume-spool of '{}'", spool_file));
+    interrupt::install(EXIT_INTERRUPTED);
+
+    progress!("Resuming spooled points from '{}'", spool_file);
+    progress!("  URL: {}", url);
+    progress!("  Organization: {}", org);
+    let bucket = resolve_database(bucket, database);
+    progress!("  Bucket/database: {}", bucket);
+    if let Some(rp) = &retention_policy {
+        progress!("  Retention policy: {}", rp);
+    }
+
+    let lines = match load_spool(&spool_file) {
+        Ok(lines) => lines,
+        Err(e) => {
+            eprintln!("Error reading spool file: {}", e);
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    };
+
+    if lines.is_empty() {
+        progress!("No spooled points to resume");
+        process::exit(EXIT_NOTHING_NEW);
+    }
+
+    progress!("Found {} spooled points", lines.len());
+
+    let influx_client = create_influx_client(
+        InfluxClient::builder(&url, &bucket).token(&token),
+        &org,
+        retention_policy.as_deref(),
+        connect_timeout,
+        request_timeout,
+    );
+
+    let mut written = 0;
+    for chunk in lines.chunks(influx_client.batch_size()) {
+        if interrupt::requested() {
+            match save_spool(&spool_file, &lines[written..]) {
+                Ok(_) => println!(
+                    "{}",
+                    output::warning(&format!(
+                        "Interrupted: wrote {} points, {} remain in {}",
+                        written,
+                        lines.len() - written,
+                        spool_file
+                    ))
+                ),
+                Err(e) => eprintln!("Failed to update spool file: {}", e),
+            }
+            process::exit(EXIT_INTERRUPTED);
+        }
+        if let Err(e) = influx_client.write_line_protocol(chunk).await {
+            eprintln!("Error writing spooled points to InfluxDB: {}", e);
+
+            // Keep only the points that were not written yet
+            match save_spool(&spool_file, &lines[written..]) {
+                Ok(_) => eprintln!(
+                    "Wrote {} points, {} remain in {}",
+                    written,
+                    lines.len() - written,
+                    spool_file
+                ),
+                Err(e) => eprintln!("Failed to update spool file: {}", e),
+            }
+            process::exit(if written > 0 {
+                EXIT_PARTIAL_WRITE
+            } else {
+                EXIT_SINK_ERROR
+            });
+        }
+        written += chunk.len();
+    }
+
+    if let Err(e) = save_spool(&spool_file, &[]) {
+        eprintln!("Failed to remove spool file: {}", e);
+    }
+    progress!("Successfully wrote {} spooled points to InfluxDB", written);
+}
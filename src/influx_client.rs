@@ -1,17 +1,274 @@
-use crate::csv_parser::CsvRecord;
-use crate::health_data::HealthRecord;
-use chrono::{DateTime, Duration, NaiveDateTime, Utc};
-use influxdb::{Client, InfluxDbWriteable, ReadQuery, Timestamp};
-use serde::Serialize;
-use std::collections::{HashMap, HashSet};
+use crate::anonymize::Anonymize;
+use crate::audit;
+use crate::interrupt::{self, Interrupted};
+use crate::sink::Sink;
+use crate::spool::append_to_spool;
+use chrono::{DateTime, Duration, NaiveDate, SecondsFormat, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration as StdDuration;
+
+/// Batch size - balance between performance and memory usage
+/// InfluxDB typically handles batches of up to 5000 points efficiently
+pub const BATCH_SIZE: usize = 1000;
+
+/// Default number of seconds to wait for a connection to InfluxDB
+pub const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+/// Default number of seconds to wait for a single InfluxDB request to complete
+pub const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 300;
+
+/// Measurement that holds import state documents when InfluxDB is used as the state backend
+pub const STATE_MEASUREMENT: &str = "importer_state";
+
+/// Longest string field value InfluxDB stores, in bytes (64 KB)
+pub const MAX_STRING_FIELD_BYTES: usize = 65_535;
+
+/// Default number of times a failed write is retried before giving up
+pub const DEFAULT_RETRIES: u32 = 0;
+
+/// Default delay before the first retry of a failed write; each further retry waits twice as long
+pub const DEFAULT_RETRY_DELAY: StdDuration = StdDuration::from_secs(1);
 
 /// Represents a client for connecting to InfluxDB
+/// Create one with `InfluxClient::builder`
 pub struct InfluxClient {
-    client: Client,
-    // org: String,
-    // bucket: String,
+    http_client: reqwest::Client,
+    url: String,
+    org: String,
+    bucket: String,
+    auth: Auth,
+    retention_policy: Option<String>,
+    dry_run: bool,
+    batch_size: usize,
+    retries: u32,
+    retry_delay: StdDuration,
+    precision: Precision,
+    connect_timeout: Option<StdDuration>,
+    request_timeout: Option<StdDuration>,
+    spool_file: Option<String>,
+    spooled_points: AtomicUsize,
+    anonymize: Option<Anonymize>,
+}
+
+/// How requests authenticate with InfluxDB
+#[derive(Debug, Clone, PartialEq)]
+enum Auth {
+    None,
+    /// InfluxDB 2.x API token, or `username:password` for InfluxDB 1.8+
+    Token(String),
+    /// InfluxDB 1.x username and password
+    Basic {
+        username: String,
+        password: String,
+    },
+}
+
+/// Timestamp precision of the points written by `write_points`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Precision {
+    #[default]
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl Precision {
+    /// The value of the `precision` parameter of the write endpoint
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Precision::Nanoseconds => "ns",
+            Precision::Microseconds => "us",
+            Precision::Milliseconds => "ms",
+            Precision::Seconds => "s",
+        }
+    }
+
+    /// Converts a time to a timestamp in this precision, truncating what does not fit
+    pub fn timestamp(self, time: &DateTime<Utc>) -> i64 {
+        match self {
+            Precision::Nanoseconds => time.timestamp_nanos_opt().unwrap_or_default(),
+            Precision::Microseconds => time.timestamp_micros(),
+            Precision::Milliseconds => time.timestamp_millis(),
+            Precision::Seconds => time.timestamp(),
+        }
+    }
+}
+
+/// Configures an InfluxClient. Everything except the URL and bucket has a default:
+/// no authentication, batches of `BATCH_SIZE` points, no retries, nanosecond precision,
+/// no timeouts and the system's root certificates
+#[derive(Debug, Clone)]
+pub struct InfluxClientBuilder {
+    url: String,
+    bucket: String,
+    org: String,
+    auth: Auth,
+    retention_policy: Option<String>,
     dry_run: bool,
+    batch_size: usize,
+    retries: u32,
+    retry_delay: StdDuration,
+    precision: Precision,
+    connect_timeout: Option<StdDuration>,
+    request_timeout: Option<StdDuration>,
+    spool_file: Option<String>,
+    ca_certificate: Option<String>,
+    accept_invalid_certs: bool,
+    anonymize: Option<Anonymize>,
+}
+
+impl InfluxClientBuilder {
+    /// Sets the InfluxDB 2.x organization used for Flux queries
+    pub fn org(mut self, org: &str) -> Self {
+        self.org = org.to_string();
+        self
+    }
+
+    /// Authenticates with an API token (InfluxDB 2.x, or `username:password` on 1.8+)
+    pub fn token(mut self, token: &str) -> Self {
+        self.auth = Auth::Token(token.to_string());
+        self
+    }
+
+    /// Authenticates with a username and password (InfluxDB 1.x)
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+        self.auth = Auth::Basic {
+            username: username.to_string(),
+            password: password.to_string(),
+        };
+        self
+    }
+
+    /// Sets the InfluxDB 1.x retention policy that points are written into
+    /// Without it, writes go to the database's default retention policy
+    pub fn retention_policy(mut self, retention_policy: &str) -> Self {
+        self.retention_policy = Some(retention_policy.to_string());
+        self
+    }
+
+    /// Logs what would be written instead of writing it
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Sets the number of points sent in each write request
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size;
+        self
+    }
+
+    /// Retries writes that fail with a connection error, a timeout, a 5xx or a 429 response
+    /// up to `retries` times, waiting `retry_delay` and then twice as long each time
+    pub fn retries(mut self, retries: u32, retry_delay: StdDuration) -> Self {
+        self.retries = retries;
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Sets the timestamp precision of the points written by `write_points`
+    pub fn precision(mut self, precision: Precision) -> Self {
+        self.precision = precision;
+        self
+    }
+
+    /// Sets how long to wait for a connection to InfluxDB; None waits indefinitely
+    pub fn connect_timeout(mut self, timeout: Option<StdDuration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets how long a single request may take; None waits indefinitely
+    pub fn request_timeout(mut self, timeout: Option<StdDuration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Sets a spool file where batches that fail to write are saved as line protocol,
+    /// so they can be retried later with `resume-spool`
+    pub fn spool_file(mut self, spool_file: &str) -> Self {
+        self.spool_file = Some(spool_file.to_string());
+        self
+    }
+
+    /// Trusts the PEM certificate at `path` in addition to the system's root certificates,
+    /// for servers with a self-signed or private CA certificate
+    pub fn ca_certificate(mut self, path: &str) -> Self {
+        self.ca_certificate = Some(path.to_string());
+        self
+    }
+
+    /// Skips verification of the server's TLS certificate. Only for testing
+    pub fn accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Hides identifying tags (apps, devices, titles) of every point written
+    pub fn anonymize(mut self, anonymize: Option<Anonymize>) -> Self {
+        self.anonymize = anonymize;
+        self
+    }
+
+    /// Creates the client; fails when the configuration is invalid or the CA certificate
+    /// cannot be read
+    pub fn build(self) -> Result<InfluxClient, Box<dyn Error>> {
+        if self.batch_size == 0 {
+            return Err("The batch size must be at least 1".into());
+        }
+
+        let mut http_builder = reqwest::Client::builder();
+        if let Some(timeout) = self.connect_timeout {
+            http_builder = http_builder.connect_timeout(timeout);
+        }
+        if let Some(timeout) = self.request_timeout {
+            http_builder = http_builder.timeout(timeout);
+        }
+        if let Some(path) = &self.ca_certificate {
+            let pem = std::fs::read(path)
+                .map_err(|e| format!("Failed to read CA certificate {}: {}", path, e))?;
+            let certificate = reqwest::Certificate::from_pem(&pem)
+                .map_err(|e| format!("Invalid CA certificate {}: {}", path, e))?;
+            http_builder = http_builder.add_root_certificate(certificate);
+        }
+        if self.accept_invalid_certs {
+            http_builder = http_builder.danger_accept_invalid_certs(true);
+        }
+        let http_client = http_builder.build()?;
+
+        Ok(InfluxClient {
+            http_client,
+            url: self.url.trim_end_matches('/').to_string(),
+            org: self.org,
+            bucket: self.bucket,
+            auth: self.auth,
+            retention_policy: self.retention_policy,
+            dry_run: self.dry_run,
+            batch_size: self.batch_size,
+            retries: self.retries,
+            retry_delay: self.retry_delay,
+            precision: self.precision,
+            connect_timeout: self.connect_timeout,
+            request_timeout: self.request_timeout,
+            spool_file: self.spool_file,
+            spooled_points: AtomicUsize::new(0),
+            anonymize: self.anonymize,
+        })
+    }
+}
+
+/// The response to an InfluxQL query: a result per statement, or an error for the request
+#[derive(Deserialize)]
+struct InfluxQlResults {
+    #[serde(default)]
+    results: Vec<serde_json::Value>,
+    error: Option<String>,
 }
 
 /// Represents a data point to be written to InfluxDB
@@ -25,168 +282,401 @@ pub struct DataPoint {
     pub tags: HashMap<String, String>,
     /// The field set for the data point
     pub field_value: f64,
+    /// String fields written alongside the value, e.g. the notes of a funds row
+    pub text_fields: BTreeMap<String, String>,
 }
 
-impl InfluxClient {
-    /// Creates a new InfluxDB client
-    pub fn new(url: &str, bucket: &str, token: &str) -> Self {
-        let client = Client::new(url, bucket).with_token(token);
+/// A point with several fields, for values recorded together that are queried together
+/// Written with `InfluxClient::write_multi_field_points`
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MultiFieldPoint {
+    pub measurement: String,
+    pub time: DateTime<Utc>,
+    pub tags: HashMap<String, String>,
+    /// Field values by name, serialized in name order
+    pub fields: BTreeMap<String, f64>,
+}
 
-        InfluxClient {
-            client,
-            // org: org.to_string(),
-            // bucket: bucket.to_string(),
-            dry_run: false,
+impl MultiFieldPoint {
+    /// Serializes the point as a single line of line protocol with nanosecond precision
+    pub fn to_line_protocol(&self) -> String {
+        let mut line = escape_line_protocol(&self.measurement, &[',', ' ']);
+
+        let mut tags: Vec<(&String, &String)> = self.tags.iter().collect();
+        tags.sort();
+        for (key, value) in tags {
+            if value.is_empty() {
+                continue;
+            }
+            line.push(',');
+            line.push_str(&escape_line_protocol(key, &[',', '=', ' ']));
+            line.push('=');
+            line.push_str(&escape_line_protocol(value, &[',', '=', ' ']));
         }
+
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(name, value)| {
+                format!("{}={}", escape_line_protocol(name, &[',', '=', ' ']), value)
+            })
+            .collect();
+        line.push(' ');
+        line.push_str(&fields.join(","));
+        line.push_str(&format!(
+            " {}",
+            Precision::Nanoseconds.timestamp(&self.time)
+        ));
+        line
     }
+}
 
-    /// Creates a new InfluxDB client in dry-run mode
-    pub fn new_dry_run(url: &str, bucket: &str, token: &str) -> Self {
-        let client = Client::new(url, bucket).with_token(token);
+/// What the /ping endpoint reports about an InfluxDB server
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerInfo {
+    pub version: String,
+    /// The server's clock, from the Date header, when it sent one
+    pub date: Option<DateTime<Utc>>,
+}
 
-        InfluxClient {
-            client,
-            // org: org.to_string(),
-            // bucket: bucket.to_string(),
-            dry_run: true,
+impl DataPoint {
+    /// Serializes the data point as a single line of InfluxDB line protocol
+    /// with nanosecond precision. Tags are sorted by key, as InfluxDB recommends
+    pub fn to_line_protocol(&self) -> String {
+        self.to_line_protocol_with_precision(Precision::Nanoseconds)
+    }
+
+    /// Serializes the data point as line protocol with the timestamp in `precision`
+    pub fn to_line_protocol_with_precision(&self, precision: Precision) -> String {
+        let mut line = escape_line_protocol(&self.measurement, &[',', ' ']);
+
+        let mut tags: Vec<(&String, &String)> = self.tags.iter().collect();
+        tags.sort();
+        for (key, value) in tags {
+            // Empty tag values are not allowed in line protocol
+            if value.is_empty() {
+                continue;
+            }
+            line.push(',');
+            line.push_str(&escape_line_protocol(key, &[',', '=', ' ']));
+            line.push('=');
+            line.push_str(&escape_line_protocol(value, &[',', '=', ' ']));
+        }
+
+        line.push_str(&format!(" value={}", self.field_value));
+        for (key, value) in &self.text_fields {
+            line.push(',');
+            line.push_str(&escape_line_protocol(key, &[',', '=', ' ']));
+            line.push_str(&format!(
+                "=\"{}\"",
+                escape_line_protocol(value, &['"', '\\'])
+            ));
         }
+        line.push_str(&format!(" {}", precision.timestamp(&self.time)));
+        line
     }
+}
 
-    /// Converts a CSV record to multiple InfluxDB data points
-    /// Each column (except the timestamp column) becomes a separate measurement
-    /// To be used for funds records
-    pub fn convert_funds_record(
-        &self,
-        record: &CsvRecord,
-        time_column: &str,
-        time_format: &str,
-    ) -> Result<Vec<DataPoint>, Box<dyn Error>> {
-        assert!(
-            record.header_values.len() == 2,
-            "There should be two header rows"
-        );
+impl InfluxClient {
+    /// Starts configuring a client that writes to `bucket` (the database on InfluxDB 1.x)
+    pub fn builder(url: &str, bucket: &str) -> InfluxClientBuilder {
+        InfluxClientBuilder {
+            url: url.to_string(),
+            bucket: bucket.to_string(),
+            org: String::new(),
+            auth: Auth::None,
+            retention_policy: None,
+            dry_run: false,
+            batch_size: BATCH_SIZE,
+            retries: DEFAULT_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            precision: Precision::default(),
+            connect_timeout: None,
+            request_timeout: None,
+            spool_file: None,
+            ca_certificate: None,
+            accept_invalid_certs: false,
+            anonymize: None,
+        }
+    }
 
-        let mut data_points = Vec::new();
+    /// Adds the configured credentials to a request
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match &self.auth {
+            Auth::None => request,
+            Auth::Token(token) => request.header("Authorization", format!("Token {}", token)),
+            Auth::Basic { username, password } => request.basic_auth(username, Some(password)),
+        }
+    }
+
+    /// Starts a Flux query request against the configured organization
+    fn flux_request(&self) -> reqwest::RequestBuilder {
+        let request = self
+            .http_client
+            .post(format!("{}/api/v2/query", self.url))
+            .query(&[("org", self.org.as_str())]);
+        self.authorize(request)
+            .header("Content-Type", "application/vnd.flux")
+            .header("Accept", "application/csv")
+    }
 
-        // Get the timestamp value from the specified column
-        let time_column_index = match record.column_indexes.get(time_column) {
-            Some(idx) => *idx,
-            None => return Err(format!("Time column '{}' not found", time_column).into()),
+    /// Runs an InfluxQL query against the bucket; statements other than SELECT and SHOW
+    /// are posted, as InfluxDB requires
+    async fn influxql_query(&self, query: &str) -> Result<InfluxQlResults, Box<dyn Error>> {
+        let url = format!("{}/query", self.url);
+        let lower = query.trim_start().to_lowercase();
+        let request = if lower.starts_with("select") || lower.starts_with("show") {
+            self.http_client.get(url)
+        } else {
+            self.http_client.post(url)
         };
+        let request = request.query(&[("db", self.bucket.as_str()), ("q", query)]);
+        let response = self
+            .authorize(request)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
 
-        // Ensure the time column index is valid
-        if time_column_index >= record.values.len() {
-            return Err(format!("Time column index {} out of bounds", time_column_index).into());
+        let status = response.status();
+        let body = response.text().await.map_err(|e| self.request_error(e))?;
+        let results: InfluxQlResults = serde_json::from_str(&body)
+            .map_err(|_| format!("InfluxDB rejected the query ({}): {}", status, body.trim()))?;
+        if let Some(error) = results.error {
+            return Err(format!("InfluxDB rejected the query ({}): {}", status, error).into());
         }
+        Ok(results)
+    }
 
-        // Parse the timestamp value
-        let time_value = &record.values[time_column_index];
-        let naive_dt = match NaiveDateTime::parse_from_str(time_value, time_format) {
-            Ok(dt) => dt,
-            Err(e) => {
-                return Err(format!("Failed to parse timestamp '{}': {}", time_value, e).into())
-            }
+    /// Gets the configured retention policy
+    pub fn retention_policy(&self) -> Option<&str> {
+        self.retention_policy.as_deref()
+    }
+
+    /// Returns the InfluxQL reference to a measurement, qualified with the
+    /// retention policy when one is configured
+    fn qualified_measurement(&self, measurement: &str) -> String {
+        match &self.retention_policy {
+            Some(rp) => format!("\"{}\".\"{}\"", rp, measurement),
+            None => format!("\"{}\"", measurement),
+        }
+    }
+
+    /// Gets the number of points sent in each write request
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Gets the configured connect timeout
+    pub fn connect_timeout(&self) -> Option<StdDuration> {
+        self.connect_timeout
+    }
+
+    /// Gets the configured request timeout
+    pub fn request_timeout(&self) -> Option<StdDuration> {
+        self.request_timeout
+    }
+
+    /// Gets the number of points written to the spool file instead of InfluxDB
+    pub fn spooled_points(&self) -> usize {
+        self.spooled_points.load(Ordering::Relaxed)
+    }
+
+    /// Checks whether the client only logs what it would write
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Saves points to the spool file instead of writing them, so they can be retried later
+    /// with `resume-spool`. Fails when no spool file is configured
+    pub fn spool_points(&self, points: &[DataPoint]) -> Result<(), Box<dyn Error>> {
+        let Some(spool_file) = &self.spool_file else {
+            return Err("No spool file is configured".into());
         };
-        let timestamp = DateTime::from_naive_utc_and_offset(naive_dt, Utc);
 
-        // Process each column (except timestamp) as a separate measurement
-        for (col_name, col_idx) in &record.column_indexes {
-            // Skip the timestamp column
-            if col_name == time_column {
-                continue;
-            }
+        let lines: Vec<String> = points.iter().map(DataPoint::to_line_protocol).collect();
+        append_to_spool(spool_file, &lines)?;
+        self.spooled_points
+            .fetch_add(points.len(), Ordering::Relaxed);
 
-            // Skip columns with invalid indices
-            if *col_idx >= record.values.len() {
-                continue;
-            }
+        eprintln!(
+            "Spooled {} unsent points to {}; run `resume-spool` to retry them",
+            points.len(),
+            spool_file
+        );
+        Ok(())
+    }
 
-            let mut value = record.values[*col_idx].clone();
+    /// Converts a request error into a boxed error, explaining timeouts
+    /// so the user knows which option to adjust
+    fn request_error(&self, error: reqwest::Error) -> Box<dyn Error> {
+        if !error.is_timeout() {
+            return error.into();
+        }
 
-            // Try to convert column value to float
+        // Only a configured timeout has a duration worth reporting
+        let after = |timeout: Option<StdDuration>| {
+            timeout
+                .map(|timeout| format!(" after {}s", timeout.as_secs()))
+                .unwrap_or_default()
+        };
+        let message = if error.is_connect() {
+            format!(
+                "Timed out connecting to InfluxDB{}; check that the server is reachable or raise --connect-timeout ({})",
+                after(self.connect_timeout),
+                error
+            )
+        } else {
+            format!(
+                "InfluxDB request timed out{}; raise --request-timeout or reduce the amount of data per request ({})",
+                after(self.request_timeout),
+                error
+            )
+        };
+        Box::new(Timeout { message })
+    }
 
-            // first let's check if the value is a currency
-            if value.contains('$') || value.contains('€') {
-                // Remove the currency symbol and any commas
-                value = value.replace(['$', '€', ','], "").trim().to_string();
-            }
+    /// Writes pre-serialized line protocol (nanosecond precision) to InfluxDB in a single request
+    pub async fn write_line_protocol(&self, lines: &[String]) -> Result<(), Box<dyn Error>> {
+        if lines.is_empty() {
+            return Ok(());
+        }
 
-            // then let's check if the value is a percentage
-            if value.ends_with('%') {
-                // Remove the percentage symbol
-                value = value.trim_end_matches('%').to_string();
-            }
+        if self.dry_run {
+            progress!(
+                "Dry-run mode: Would write {} lines of line protocol to InfluxDB",
+                lines.len()
+            );
+            return Ok(());
+        }
 
-            match value.parse::<f64>() {
-                Ok(float_value) => {
-                    // This column contains a numeric value - create a data point
-                    let mut tags = HashMap::new();
-
-                    // Extract tags from header rows for this column
-                    // Safely access the first header row and check if column index is valid
-                    if !record.header_values.is_empty() && *col_idx < record.header_values[0].len()
-                    {
-                        let header_value = &record.header_values[0][*col_idx]
-                            .replace(['\n', '\r'], " ")
-                            .replace(' ', "_")
-                            .replace("__", "_");
-
-                        if !header_value.is_empty() {
-                            tags.insert("fondo".to_string(), header_value.clone());
-                        }
-                    }
+        self.post_write(lines.join("\n"), Precision::Nanoseconds)
+            .await
+    }
 
-                    // Extract measurement from the second header row
-                    // Safely access the last header row and check if column index is valid
-                    let measurement = if record.header_values.len() > 1
-                        && *col_idx < record.header_values[1].len()
-                    {
-                        &record.header_values[1][*col_idx]
-                    } else {
-                        // Use column name as fallback if header information is not available
-                        col_name.split('.').next_back().unwrap_or(col_name)
-                    };
-
-                    // Create the data point
-                    data_points.push(DataPoint {
-                        measurement: measurement.to_string(),
-                        time: timestamp,
-                        tags,
-                        field_value: float_value,
-                    });
-                }
-                Err(_) => {
-                    // Non-numeric values could be skipped or handled differently
-                    // For now, we'll just skip them
-                    continue;
+    /// Checks that the token may write to the bucket, by writing no points at all
+    /// The server still checks authentication, permissions and that the bucket exists.
+    /// Nothing is written, so nothing is recorded in the audit log
+    pub async fn check_write_access(&self) -> Result<(), Box<dyn Error>> {
+        self.try_post_write("", self.precision)
+            .await
+            .map_err(|(error, _)| error)
+    }
+
+    /// Posts a write request, retrying failures that may be temporary, and records what it
+    /// wrote in the audit log, if one is set
+    async fn post_write(&self, body: String, precision: Precision) -> Result<(), Box<dyn Error>> {
+        self.post_write_unaudited(&body, precision).await?;
+        if let Some(log) = audit::audit_log() {
+            // The points are written by now, so a failure is only a warning
+            if let Err(e) = log.record(
+                &self.bucket,
+                self.retention_policy.as_deref(),
+                &body,
+                precision,
+            ) {
+                eprintln!("Failed to append to the audit log {}: {}", log.path(), e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Posts a write request, retrying failures that may be temporary
+    async fn post_write_unaudited(
+        &self,
+        body: &str,
+        precision: Precision,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut attempt = 0;
+        loop {
+            match self.try_post_write(body, precision).await {
+                Ok(()) => return Ok(()),
+                Err((error, true)) if attempt < self.retries => {
+                    let delay = self.retry_delay * 2u32.saturating_pow(attempt);
+                    attempt += 1;
+                    eprintln!(
+                        "Write failed ({}); retrying in {:.1}s (attempt {} of {})",
+                        error,
+                        delay.as_secs_f64(),
+                        attempt,
+                        self.retries
+                    );
+                    tokio::time::sleep(delay).await;
                 }
+                Err((error, _)) => return Err(error),
             }
         }
+    }
 
-        if data_points.is_empty() {
-            return Err("No valid measurements found in record".into());
+    /// Posts a single write request; a failed one comes with whether it is worth retrying
+    async fn try_post_write(
+        &self,
+        body: &str,
+        precision: Precision,
+    ) -> Result<(), (Box<dyn Error>, bool)> {
+        let mut params = vec![
+            ("db", self.bucket.as_str()),
+            ("precision", precision.as_str()),
+        ];
+        if let Some(rp) = &self.retention_policy {
+            params.push(("rp", rp.as_str()));
         }
 
-        Ok(data_points)
+        let request = self
+            .http_client
+            .post(format!("{}/write", self.url))
+            .query(&params);
+        let response = self
+            .authorize(request)
+            .body(body.to_string())
+            .send()
+            .await
+            .map_err(|e| (self.request_error(e), true))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let retryable =
+                status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS;
+            let body = response.text().await.unwrap_or_default();
+            let error = format!("InfluxDB rejected write ({}): {}", status, body.trim());
+            return Err((error.into(), retryable));
+        }
+
+        Ok(())
+    }
+
+    /// Writes points with several fields to InfluxDB in a single request
+    pub async fn write_multi_field_points(
+        &self,
+        points: &[MultiFieldPoint],
+    ) -> Result<(), Box<dyn Error>> {
+        let lines: Vec<String> = match self.anonymize {
+            Some(anonymize) => points
+                .iter()
+                .map(|point| anonymize.multi_field_point(point).to_line_protocol())
+                .collect(),
+            None => points
+                .iter()
+                .map(MultiFieldPoint::to_line_protocol)
+                .collect(),
+        };
+        self.write_line_protocol(&lines).await
     }
 
-    #[allow(dead_code)]
     /// Writes a data point to InfluxDB
     pub async fn write_point(&self, point: DataPoint) -> Result<String, Box<dyn Error>> {
-        // Create a write query for the data point
-        let mut write_query = Timestamp::from(point.time)
-            .into_query(point.measurement)
-            .add_field("value", point.field_value);
-        for (tag_name, tag_value) in point.tags {
-            write_query = write_query.add_tag(tag_name, tag_value);
-        }
+        let point = match self.anonymize {
+            Some(anonymize) => anonymize.point(&point),
+            None => point,
+        };
+        let line = point.to_line_protocol();
 
         if self.dry_run {
-            println!("Dry-run mode: Would write point: {:?}", write_query);
+            progress!("Dry-run mode: Would write point: {}", line);
             return Ok("Dry-run mode: Point not written".to_string());
         }
 
-        self.client.query(write_query).await.map_err(|e| e.into())
+        self.write_line_protocol(&[line]).await?;
+        Ok(String::new())
     }
 
     /// Writes multiple data points to InfluxDB in a single request
@@ -195,243 +685,1234 @@ impl InfluxClient {
             return Ok(());
         }
 
+        // Spooled points are anonymized too, as they are written later as they are
+        let anonymized: Vec<DataPoint>;
+        let points = match self.anonymize {
+            Some(anonymize) => {
+                anonymized = points.iter().map(|point| anonymize.point(point)).collect();
+                &anonymized
+            }
+            None => points,
+        };
+
         if self.dry_run {
-            println!(
+            progress!(
                 "Dry-run mode: Would write {} points to InfluxDB",
                 points.len()
             );
             for (i, point) in points.iter().enumerate() {
                 // Limit the number of points to display in dry-run mode
                 if i >= 10 && points.len() > 20 {
-                    println!("... and {} more points (not shown)", points.len() - 10);
+                    progress!("... and {} more points (not shown)", points.len() - 10);
                     break;
                 }
 
-                // Create a write query for the data point to display
-                let mut write_query = Timestamp::from(point.time)
-                    .into_query(&point.measurement)
-                    .add_field("value", point.field_value);
-                for (tag_name, tag_value) in point.tags.clone() {
-                    write_query = write_query.add_tag(tag_name, tag_value);
-                }
-
-                println!("[{}/{}] Query: {:?}", i + 1, points.len(), write_query);
+                progress!(
+                    "[{}/{}] {}",
+                    i + 1,
+                    points.len(),
+                    point.to_line_protocol_with_precision(self.precision)
+                );
             }
             return Ok(());
         }
 
-        // Batch size - balance between performance and memory usage
-        // InfluxDB typically handles batches of up to 5000 points efficiently
-        const BATCH_SIZE: usize = 1000;
-
         // Process points in batches to improve performance
-        for chunk in points.chunks(BATCH_SIZE) {
-            // Create a vector of write queries for this batch
-            let mut batch_queries = Vec::with_capacity(chunk.len());
-
-            for point in chunk {
-                // Create a write query for the data point
-                let mut write_query = Timestamp::from(point.time)
-                    .into_query(&point.measurement)
-                    .add_field("value", point.field_value);
-
-                // Add all tags to the query
-                for (tag_name, tag_value) in &point.tags {
-                    write_query = write_query.add_tag(tag_name, tag_value.clone());
-                }
-
-                batch_queries.push(write_query);
+        for (batch_index, chunk) in points.chunks(self.batch_size).enumerate() {
+            // Stop between batches, so the caller knows exactly what was written
+            if interrupt::requested() {
+                return Err(Box::new(Interrupted {
+                    written_points: batch_index * self.batch_size,
+                }));
             }
 
-            // Execute the batch write - the Vec<WriteQuery> is automatically handled by the client
-            match self.client.query(batch_queries).await {
-                Ok(_) => {}
-                Err(e) => {
-                    eprintln!("Error writing batch to InfluxDB: {}", e);
-                    return Err(e.into());
+            // Serialize the batch straight to line protocol and post it in one request,
+            // instead of building a WriteQuery for every point
+            let lines: Vec<String> = chunk
+                .iter()
+                .map(|point| point.to_line_protocol_with_precision(self.precision))
+                .collect();
+
+            if let Err(error) = self.post_write(lines.join("\n"), self.precision).await {
+                eprintln!("Error writing batch to InfluxDB: {}", error);
+
+                if self.spool_file.is_none() {
+                    return Err(error);
                 }
+
+                // Save this batch and everything after it so nothing already converted is lost
+                self.spool_points(&points[batch_index * self.batch_size..])?;
+                return Ok(());
             }
         }
 
         Ok(())
     }
 
-    /// Process and write all CSV records to InfluxDB
-    pub async fn write_funds_records(
+    /// Gets the timestamps (as Unix milliseconds) of a measurement's points in a time range
+    pub async fn get_existing_timestamps(
         &self,
-        records: &[CsvRecord],
-        time_column: &str,
-        time_format: &str,
-    ) -> Result<usize, Box<dyn Error>> {
-        let mut all_points = Vec::new();
-        let mut error_count = 0;
-        let mut success_count = 0;
-
-        for record in records {
-            match self.convert_funds_record(record, time_column, time_format) {
-                Ok(points) => {
-                    success_count += points.len();
-                    all_points.extend(points);
-                }
-                Err(e) => {
-                    eprintln!("Error converting record: {}", e);
-                    error_count += 1;
-                }
+        measurement: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<HashSet<i64>, Box<dyn Error>> {
+        self.get_existing_timestamps_in(measurement, &[(start_time, end_time)])
+            .await
+    }
+
+    /// Gets the timestamps (as Unix milliseconds) of a measurement's points in several time
+    /// ranges, with one query per range
+    pub async fn get_existing_timestamps_in(
+        &self,
+        measurement: &str,
+        ranges: &[(DateTime<Utc>, DateTime<Utc>)],
+    ) -> Result<HashSet<i64>, Box<dyn Error>> {
+        // InfluxDB 2.x buckets can only be queried with InfluxQL when a DBRP mapping
+        // exists, so use Flux on those servers
+        let use_flux = match self.server_version().await {
+            Ok(version) => {
+                progress!("Detected InfluxDB version {}", version);
+                is_flux_version(&version)
+            }
+            Err(e) => {
+                progress!(
+                    "Warning: Could not detect InfluxDB version ({}), using InfluxQL",
+                    e
+                );
+                false
             }
+        };
+
+        let mut timestamps = HashSet::new();
+        for &(start_time, end_time) in ranges {
+            timestamps.extend(if use_flux {
+                self.query_timestamps_flux(measurement, start_time, end_time)
+                    .await?
+            } else {
+                self.query_timestamps_influxql(measurement, start_time, end_time)
+                    .await?
+            });
         }
+        Ok(timestamps)
+    }
 
-        if self.dry_run {
-            println!(
-                "Dry-run mode: Would write {} data points to InfluxDB",
-                all_points.len()
-            );
+    /// Compares points with the data already in InfluxDB, counting per measurement how
+    /// many are new and how many have a timestamp that is already present
+    pub async fn diff_with_existing(
+        &self,
+        points: &[DataPoint],
+    ) -> Result<BTreeMap<String, PointDiff>, Box<dyn Error>> {
+        let mut existing = HashMap::new();
+        for (measurement, (start_time, end_time)) in measurement_ranges(points) {
+            progress!("Querying existing {} points", measurement);
+            // The Flux range stop is exclusive
+            let timestamps = self
+                .get_existing_timestamps(
+                    measurement,
+                    start_time,
+                    end_time + Duration::milliseconds(1),
+                )
+                .await?;
+            existing.insert(measurement.to_string(), timestamps);
+        }
+        Ok(diff_points(points, &existing))
+    }
+
+    /// Counts the points of a measurement per UTC day in a time range (start inclusive,
+    /// end exclusive), across all tag values. Days without points are left out
+    pub async fn count_points_per_day(
+        &self,
+        measurement: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<BTreeMap<NaiveDate, u64>, Box<dyn Error>> {
+        let counts = self
+            .count_points_per_window(
+                measurement,
+                start_time,
+                end_time,
+                StdDuration::from_secs(86400),
+            )
+            .await?;
+        let mut days = BTreeMap::new();
+        for (day, count) in counts {
+            *days.entry(day.date_naive()).or_default() += count;
+        }
+        Ok(days)
+    }
+
+    /// Counts the points of a measurement per window of `window` (counted from the Unix
+    /// epoch) in a time range (start inclusive, end exclusive), across all tag values, by
+    /// the start of each window. Windows without points are left out
+    pub async fn count_points_per_window(
+        &self,
+        measurement: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        window: StdDuration,
+    ) -> Result<BTreeMap<DateTime<Utc>, u64>, Box<dyn Error>> {
+        let window_secs = window.as_secs().max(1);
+        let counts = if is_flux_version(&self.server_version().await?) {
+            self.count_points_per_window_flux(measurement, start_time, end_time, window_secs)
+                .await?
         } else {
-            println!("Writing {} data points to InfluxDB", all_points.len());
+            self.count_points_per_window_influxql(measurement, start_time, end_time, window_secs)
+                .await?
+        };
+        // The first window can start at the start of the range rather than on its boundary
+        let mut windows = BTreeMap::new();
+        for (time, count) in counts {
+            let start = time.timestamp().div_euclid(window_secs as i64) * window_secs as i64;
+            if let Some(start) = DateTime::from_timestamp(start, 0) {
+                *windows.entry(start).or_default() += count;
+            }
         }
+        Ok(windows)
+    }
+
+    async fn count_points_per_window_influxql(
+        &self,
+        measurement: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        window_secs: u64,
+    ) -> Result<BTreeMap<DateTime<Utc>, u64>, Box<dyn Error>> {
+        let query = format!(
+            "SELECT count(\"value\") FROM {} WHERE time >= {}ms AND time < {}ms GROUP BY time({}s) fill(none)",
+            self.qualified_measurement(measurement),
+            start_time.timestamp_millis(),
+            end_time.timestamp_millis(),
+            window_secs
+        );
 
-        self.write_points(&all_points).await?;
+        let read_result = self.influxql_query(&query).await?;
 
-        if error_count > 0 {
-            eprintln!("Failed to convert {} records", error_count);
+        // Each row holds the start of the window followed by the count
+        let mut counts = BTreeMap::new();
+        let rows = read_result
+            .results
+            .iter()
+            .filter_map(|result| result.get("series")?.as_array())
+            .flatten()
+            .filter_map(|serie| serie.get("values")?.as_array())
+            .flatten();
+        for row in rows {
+            let time = row
+                .get(0)
+                .and_then(|value| value.as_str())
+                .and_then(|value| DateTime::parse_from_rfc3339(value).ok());
+            let count = row.get(1).and_then(|value| value.as_u64());
+            if let (Some(time), Some(count)) = (time, count) {
+                *counts.entry(time.with_timezone(&Utc)).or_default() += count;
+            }
         }
+        Ok(counts)
+    }
+
+    async fn count_points_per_window_flux(
+        &self,
+        measurement: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+        window_secs: u64,
+    ) -> Result<BTreeMap<DateTime<Utc>, u64>, Box<dyn Error>> {
+        // Series with different tags are grouped together, so that each window has one count
+        let query = format!(
+            "from(bucket: \"{}\")\n  \
+             |> range(start: {}, stop: {})\n  \
+             |> filter(fn: (r) => r._measurement == \"{}\" and r._field == \"value\")\n  \
+             |> group()\n  \
+             |> aggregateWindow(every: {}s, fn: count, createEmpty: false, timeSrc: \"_start\")\n  \
+             |> keep(columns: [\"_time\", \"_value\"])",
+            self.bucket,
+            start_time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            end_time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            escape_flux_string(measurement),
+            window_secs
+        );
+
+        let response = self
+            .flux_request()
+            .body(query)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
 
-        Ok(success_count)
+        let status = response.status();
+        let body = response.text().await.map_err(|e| self.request_error(e))?;
+        if !status.is_success() {
+            return Err(format!("Flux query failed ({}): {}", status, body.trim()).into());
+        }
+
+        Ok(parse_flux_window_counts(&body))
     }
 
-    /// Process and write all health records to InfluxDB
-    pub async fn write_health_records(
+    /// Lists the measurements of the bucket
+    pub async fn measurements(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        if !is_flux_version(&self.server_version().await?) {
+            let read_result = self.influxql_query("SHOW MEASUREMENTS").await?;
+            let measurements = read_result
+                .results
+                .iter()
+                .filter_map(|result| result.get("series")?.as_array())
+                .flatten()
+                .filter_map(|serie| serie.get("values")?.as_array())
+                .flatten()
+                .filter_map(|row| Some(row.get(0)?.as_str()?.to_string()))
+                .collect();
+            return Ok(measurements);
+        }
+
+        let query = format!(
+            "import \"influxdata/influxdb/schema\"\nschema.measurements(bucket: \"{}\")",
+            self.bucket
+        );
+        Ok(parse_flux_values(&self.flux_query(query).await?))
+    }
+
+    /// Reads the points of a measurement in a time range (start inclusive, end exclusive),
+    /// with all their numeric fields; fields of other types are left out
+    pub async fn read_points(
         &self,
-        records_map: &HashMap<String, Vec<HealthRecord>>,
-    ) -> Result<usize, Box<dyn Error>> {
-        let mut all_points = Vec::new();
-        let mut success_count = 0;
+        measurement: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<Vec<MultiFieldPoint>, Box<dyn Error>> {
+        if !is_flux_version(&self.server_version().await?) {
+            let query = format!(
+                "SELECT * FROM {} WHERE time >= {}ms AND time < {}ms GROUP BY *",
+                self.qualified_measurement(measurement),
+                start_time.timestamp_millis(),
+                end_time.timestamp_millis()
+            );
+            let read_result = self.influxql_query(&query).await?;
+            return Ok(parse_influxql_points(&read_result.results));
+        }
 
-        for (record_type, records) in records_map {
-            println!("Processing {} {} records", records.len(), record_type);
+        let query = format!(
+            "from(bucket: \"{}\")\n  \
+             |> range(start: {}, stop: {})\n  \
+             |> filter(fn: (r) => r._measurement == \"{}\")",
+            self.bucket,
+            start_time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            end_time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            escape_flux_string(measurement)
+        );
+        Ok(parse_flux_points(&self.flux_query(query).await?))
+    }
 
-            for record in records {
-                // Convert health record to InfluxDB data point
-                let mut tags = HashMap::new();
+    /// Reads the first point of each series of a measurement, with all their numeric fields,
+    /// to learn which tags and fields it has
+    pub async fn first_points(
+        &self,
+        measurement: &str,
+    ) -> Result<Vec<MultiFieldPoint>, Box<dyn Error>> {
+        if !is_flux_version(&self.server_version().await?) {
+            // LIMIT applies to each series of a GROUP BY
+            let query = format!(
+                "SELECT * FROM {} GROUP BY * LIMIT 1",
+                self.qualified_measurement(measurement)
+            );
+            let read_result = self.influxql_query(&query).await?;
+            return Ok(parse_influxql_points(&read_result.results));
+        }
 
-                // Add any metadata as tags
-                for (key, value) in &record.metadata {
-                    tags.insert(key.clone(), value.clone());
-                }
+        let query = format!(
+            "from(bucket: \"{}\")\n  \
+             |> range(start: 0)\n  \
+             |> filter(fn: (r) => r._measurement == \"{}\")\n  \
+             |> first()",
+            self.bucket,
+            escape_flux_string(measurement)
+        );
+        Ok(parse_flux_points(&self.flux_query(query).await?))
+    }
 
-                // Add record type as a tag for easier querying
-                tags.insert("record_type".to_string(), record_type.clone());
+    /// Runs a Flux query, giving the annotated CSV response
+    async fn flux_query(&self, query: String) -> Result<String, Box<dyn Error>> {
+        let response = self
+            .flux_request()
+            .body(query)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
 
-                // Create data point
-                let point = DataPoint {
-                    measurement: record_type.clone(),
-                    time: record.timestamp,
-                    tags,
-                    field_value: record.value,
-                };
+        let status = response.status();
+        let body = response.text().await.map_err(|e| self.request_error(e))?;
+        if !status.is_success() {
+            return Err(format!("Flux query failed ({}): {}", status, body.trim()).into());
+        }
+        Ok(body)
+    }
+
+    /// Deletes every point of a measurement recorded before `before`, with the delete API on
+    /// InfluxDB 2.x and a DELETE statement (across all retention policies) on 1.x
+    pub async fn delete_before(
+        &self,
+        measurement: &str,
+        before: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        self.delete_range(measurement, &[], DateTime::UNIX_EPOCH, before)
+            .await
+    }
+
+    /// Deletes the points of a measurement recorded from `start` up to (not including) `stop`,
+    /// like [`InfluxClient::delete_before`], keeping the series whose tags don't match every
+    /// pair of `tags`
+    pub async fn delete_range(
+        &self,
+        measurement: &str,
+        tags: &[(&str, &str)],
+        start: DateTime<Utc>,
+        stop: DateTime<Utc>,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.dry_run {
+            progress!(
+                "Dry-run mode: Would delete {} points from {} to {}",
+                measurement,
+                start.to_rfc3339_opts(SecondsFormat::Millis, true),
+                stop.to_rfc3339_opts(SecondsFormat::Millis, true)
+            );
+            return Ok(());
+        }
 
-                all_points.push(point);
-                success_count += 1;
+        if !is_flux_version(&self.server_version().await?) {
+            // Errors in the response fail the delete
+            let results = self
+                .influxql_query(&influxql_delete(measurement, tags, start, stop))
+                .await?;
+            if let Some(error) = results
+                .results
+                .iter()
+                .find_map(|result| result.get("error")?.as_str())
+            {
+                return Err(format!("InfluxDB rejected the delete: {}", error).into());
             }
+            return Ok(());
+        }
+
+        let request = self
+            .http_client
+            .post(format!("{}/api/v2/delete", self.url))
+            .query(&[("org", self.org.as_str()), ("bucket", self.bucket.as_str())]);
+        let response = self
+            .authorize(request)
+            .header("Content-Type", "application/json")
+            .body(flux_delete_body(measurement, tags, start, stop))
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Delete failed ({}): {}", status, body.trim()).into());
         }
+        Ok(())
+    }
+
+    /// Asks the server for its version using the /ping endpoint
+    pub async fn server_version(&self) -> Result<String, Box<dyn Error>> {
+        Ok(self.ping().await?.version)
+    }
+
+    /// Asks the server for its version and current time using the /ping endpoint
+    /// The endpoint needs no authentication, so this only checks that the server is reachable
+    pub async fn ping(&self) -> Result<ServerInfo, Box<dyn Error>> {
+        let response = self
+            .http_client
+            .get(format!("{}/ping", self.url))
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
 
+        let version = match response.headers().get("X-Influxdb-Version") {
+            Some(version) => version.to_str()?.to_string(),
+            None => return Err("Server did not report an InfluxDB version".into()),
+        };
+        let date = response
+            .headers()
+            .get("Date")
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+            .map(|date| date.with_timezone(&Utc));
+        Ok(ServerInfo { version, date })
+    }
+
+    /// Checks that the token may read from the bucket with a query that returns
+    /// at most one point
+    pub async fn check_read_access(&self) -> Result<(), Box<dyn Error>> {
+        if !is_flux_version(&self.server_version().await?) {
+            let read_result = self.influxql_query("SHOW MEASUREMENTS LIMIT 1").await?;
+            let error = read_result
+                .results
+                .iter()
+                .find_map(|result| result.get("error")?.as_str());
+            return match error {
+                Some(error) => Err(format!("InfluxQL query failed: {}", error).into()),
+                None => Ok(()),
+            };
+        }
+
+        let query = format!(
+            "from(bucket: \"{}\")\n  |> range(start: -1m)\n  |> limit(n: 1)",
+            self.bucket
+        );
+        let response = self
+            .flux_request()
+            .body(query)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("Flux query failed ({}): {}", status, body.trim()).into());
+        }
+        Ok(())
+    }
+
+    /// Stores a state document as a new point of the state measurement, tagged with its name
+    /// Older documents are kept as earlier points, so previous states can still be queried.
+    /// State is not imported data, so it is left out of the audit log
+    pub async fn write_state_document(
+        &self,
+        name: &str,
+        document: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        if document.len() > MAX_STRING_FIELD_BYTES {
+            return Err(format!(
+                "the state document is {} bytes, more than the {} bytes InfluxDB stores in a string field",
+                document.len(),
+                MAX_STRING_FIELD_BYTES
+            )
+            .into());
+        }
         if self.dry_run {
-            println!(
-                "Dry-run mode: Would write {} health data points to InfluxDB",
-                all_points.len()
-            );
+            progress!("Dry-run mode: Would write the state document to InfluxDB");
+            return Ok(());
+        }
+
+        let line = format!(
+            "{},name={} document=\"{}\" {}",
+            STATE_MEASUREMENT,
+            escape_line_protocol(name, &[',', '=', ' ']),
+            escape_line_protocol(document, &['\\', '"']),
+            Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        );
+        self.post_write_unaudited(&line, Precision::Nanoseconds)
+            .await
+    }
+
+    /// Reads the most recent state document with the given name, if any was stored
+    pub async fn read_state_document(&self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let version = self.server_version().await?;
+        if is_flux_version(&version) {
+            self.read_state_document_flux(name).await
         } else {
-            println!(
-                "Writing {} health data points to InfluxDB",
-                all_points.len()
-            );
+            self.read_state_document_influxql(name).await
         }
+    }
+
+    async fn read_state_document_influxql(
+        &self,
+        name: &str,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let query = format!(
+            "SELECT last(\"document\") FROM {} WHERE \"name\" = '{}'",
+            self.qualified_measurement(STATE_MEASUREMENT),
+            name.replace('\\', "\\\\").replace('\'', "\\'")
+        );
 
-        self.write_points(&all_points).await?;
+        let read_result = self.influxql_query(&query).await?;
 
-        Ok(success_count)
+        // The single row holds the time followed by the document
+        let document = read_result
+            .results
+            .first()
+            .and_then(|result| result.get("series"))
+            .and_then(|series| series.get(0))
+            .and_then(|serie| serie.get("values"))
+            .and_then(|values| values.get(0))
+            .and_then(|row| row.get(1))
+            .and_then(|value| value.as_str())
+            .map(|value| value.to_string());
+        Ok(document)
     }
 
-    /// Queries existing heart rate data from InfluxDB for the last week
-    /// Returns a set of timestamps (as Unix milliseconds) that already exist
-    pub async fn get_existing_heart_rate_timestamps(
+    async fn read_state_document_flux(&self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        let query = format!(
+            "from(bucket: \"{}\")\n  \
+             |> range(start: 0)\n  \
+             |> filter(fn: (r) => r._measurement == \"{}\" and r.name == \"{}\" and r._field == \"document\")\n  \
+             |> last()\n  \
+             |> keep(columns: [\"_value\"])",
+            self.bucket,
+            STATE_MEASUREMENT,
+            escape_flux_string(name)
+        );
+
+        let response = self
+            .flux_request()
+            .body(query)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
+
+        let status = response.status();
+        let body = response.text().await.map_err(|e| self.request_error(e))?;
+        if !status.is_success() {
+            return Err(format!("Flux query failed ({}): {}", status, body.trim()).into());
+        }
+
+        Ok(parse_flux_value(&body))
+    }
+
+    /// Gets the timestamps (as Unix milliseconds) of a measurement in a time range using InfluxQL
+    async fn query_timestamps_influxql(
         &self,
-        days_back: i64,
+        measurement: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
     ) -> Result<HashSet<i64>, Box<dyn Error>> {
-        let end_time = Utc::now();
-        let start_time = end_time - Duration::days(days_back);
+        let query = format!(
+            "SELECT time, value FROM {} WHERE time >= {}ms AND time <= {}ms",
+            self.qualified_measurement(measurement),
+            start_time.timestamp_millis(),
+            end_time.timestamp_millis()
+        );
+
+        let read_result = self.influxql_query(&query).await?;
+
+        let mut existing_timestamps = HashSet::new();
+        for result in &read_result.results {
+            let Some(series_array) = result.get("series").and_then(|s| s.as_array()) else {
+                continue;
+            };
+            for serie_value in series_array {
+                let Some(values_array) = serie_value.get("values").and_then(|v| v.as_array())
+                else {
+                    continue;
+                };
+                for value_row in values_array {
+                    // InfluxDB returns timestamps in RFC3339 format
+                    let timestamp = value_row
+                        .as_array()
+                        .and_then(|row| row.first())
+                        .and_then(|value| value.as_str())
+                        .and_then(|value| DateTime::parse_from_rfc3339(value).ok());
+                    if let Some(parsed_time) = timestamp {
+                        existing_timestamps.insert(parsed_time.timestamp_millis());
+                    }
+                }
+            }
+        }
 
-        // Convert to Unix timestamps in milliseconds
-        let start_timestamp = start_time.timestamp_millis();
-        let end_timestamp = end_time.timestamp_millis();
+        Ok(existing_timestamps)
+    }
 
-        // InfluxQL query to get existing heart rate timestamps
+    /// Gets the timestamps (as Unix milliseconds) of a measurement in a time range using Flux
+    async fn query_timestamps_flux(
+        &self,
+        measurement: &str,
+        start_time: DateTime<Utc>,
+        end_time: DateTime<Utc>,
+    ) -> Result<HashSet<i64>, Box<dyn Error>> {
         let query = format!(
-            "SELECT time, value FROM \"HeartRate\" WHERE time >= {}ms AND time <= {}ms",
-            start_timestamp, end_timestamp
+            "from(bucket: \"{}\")\n  \
+             |> range(start: {}, stop: {})\n  \
+             |> filter(fn: (r) => r._measurement == \"{}\" and r._field == \"value\")\n  \
+             |> keep(columns: [\"_time\"])",
+            self.bucket,
+            start_time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            end_time.to_rfc3339_opts(SecondsFormat::Millis, true),
+            escape_flux_string(measurement)
         );
 
-        println!(
-            "Querying existing heart rate data from {} to {} ({} days)",
-            start_time.format("%Y-%m-%d %H:%M:%S"),
-            end_time.format("%Y-%m-%d %H:%M:%S"),
-            days_back
-        );
+        let response = self
+            .flux_request()
+            .body(query)
+            .send()
+            .await
+            .map_err(|e| self.request_error(e))?;
 
-        if self.dry_run {
-            println!(
-                "  (Dry-run mode: Querying InfluxDB for existing data, but won't write new data)"
+        let status = response.status();
+        let body = response.text().await.map_err(|e| self.request_error(e))?;
+        if !status.is_success() {
+            return Err(format!("Flux query failed ({}): {}", status, body.trim()).into());
+        }
+
+        Ok(parse_flux_timestamps(&body))
+    }
+}
+
+impl Sink for InfluxClient {
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    async fn write_points(&self, points: &[DataPoint]) -> Result<(), Box<dyn Error>> {
+        InfluxClient::write_points(self, points).await
+    }
+
+    fn is_dry_run(&self) -> bool {
+        InfluxClient::is_dry_run(self)
+    }
+
+    fn spooled_points(&self) -> usize {
+        InfluxClient::spooled_points(self)
+    }
+
+    fn spool_points(&self, points: &[DataPoint]) -> Result<(), Box<dyn Error>> {
+        InfluxClient::spool_points(self, points)
+    }
+}
+
+/// What to do with a point when InfluxDB already has one of the same measurement, tags
+/// and time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ConflictPolicy {
+    /// Write it anyway, replacing the value of the existing point, as InfluxDB does
+    #[default]
+    Overwrite,
+    /// Leave it out, keeping the existing point
+    Skip,
+    /// Fail the write without writing any point of the batch
+    Error,
+}
+
+impl fmt::Display for ConflictPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConflictPolicy::Overwrite => write!(f, "overwrite"),
+            ConflictPolicy::Skip => write!(f, "skip"),
+            ConflictPolicy::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// Writes to InfluxDB, first checking the points of each write against those InfluxDB
+/// already has, with one query per measurement over the time range of its points, and
+/// handling the conflicts by a [`ConflictPolicy`]. Overwriting needs no query
+pub struct ConflictCheck<'a> {
+    client: &'a InfluxClient,
+    policy: ConflictPolicy,
+    skipped_points: AtomicUsize,
+}
+
+impl<'a> ConflictCheck<'a> {
+    pub fn new(client: &'a InfluxClient, policy: ConflictPolicy) -> Self {
+        Self {
+            client,
+            policy,
+            skipped_points: AtomicUsize::new(0),
+        }
+    }
+
+    /// Gets the number of points left out as InfluxDB already had them
+    pub fn skipped_points(&self) -> usize {
+        self.skipped_points.load(Ordering::Relaxed)
+    }
+
+    /// Splits points into those InfluxDB has no point for and those it has
+    async fn split_conflicts(
+        &self,
+        points: &[DataPoint],
+    ) -> Result<(Vec<DataPoint>, Vec<DataPoint>), Box<dyn Error>> {
+        let mut existing = Vec::new();
+        for (measurement, (start_time, end_time)) in measurement_ranges(points) {
+            // The range end is exclusive
+            existing.extend(
+                self.client
+                    .read_points(
+                        measurement,
+                        start_time,
+                        end_time + Duration::milliseconds(1),
+                    )
+                    .await?,
             );
         }
+        // Points are written with their tags anonymized, so they are compared that way too
+        Ok(match self.client.anonymize {
+            Some(anonymize) => {
+                let anonymized: Vec<DataPoint> =
+                    points.iter().map(|point| anonymize.point(point)).collect();
+                split_conflicts(&anonymized, &existing)
+            }
+            None => split_conflicts(points, &existing),
+        })
+    }
+}
 
-        let mut existing_timestamps = HashSet::new();
+impl Sink for ConflictCheck<'_> {
+    fn batch_size(&self) -> usize {
+        self.client.batch_size
+    }
 
-        match self.client.json_query(ReadQuery::new(query)).await {
-            Ok(read_result) => {
-                // Check if there are results
-                for result in &read_result.results {
-                    if let Some(series_value) = result.get("series") {
-                        if let Some(series_array) = series_value.as_array() {
-                            for serie_value in series_array {
-                                if let Some(values_value) = serie_value.get("values") {
-                                    if let Some(values_array) = values_value.as_array() {
-                                        for value_row in values_array {
-                                            if let Some(value_array) = value_row.as_array() {
-                                                if let Some(timestamp_value) = value_array.first() {
-                                                    if let Some(timestamp_str) =
-                                                        timestamp_value.as_str()
-                                                    {
-                                                        // InfluxDB returns timestamps in RFC3339 format
-                                                        if let Ok(parsed_time) =
-                                                            DateTime::parse_from_rfc3339(
-                                                                timestamp_str,
-                                                            )
-                                                        {
-                                                            let timestamp_millis =
-                                                                parsed_time.timestamp_millis();
-                                                            existing_timestamps
-                                                                .insert(timestamp_millis);
-                                                        }
-                                                    }
-                                                }
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
+    async fn write_points(&self, points: &[DataPoint]) -> Result<(), Box<dyn Error>> {
+        if points.is_empty() || self.policy == ConflictPolicy::Overwrite {
+            return self.client.write_points(points).await;
+        }
+        let (new, conflicting) = self.split_conflicts(points).await?;
+        match (self.policy, conflicting.first()) {
+            (ConflictPolicy::Error, Some(first)) => Err(format!(
+                "{} points are already in InfluxDB, e.g. {} at {}",
+                conflicting.len(),
+                first.measurement,
+                first.time.to_rfc3339_opts(SecondsFormat::Millis, true)
+            )
+            .into()),
+            _ => {
+                self.skipped_points
+                    .fetch_add(conflicting.len(), Ordering::Relaxed);
+                self.client.write_points(&new).await
+            }
+        }
+    }
+
+    fn is_dry_run(&self) -> bool {
+        self.client.is_dry_run()
+    }
+
+    fn spooled_points(&self) -> usize {
+        self.client.spooled_points()
+    }
+
+    fn spool_points(&self, points: &[DataPoint]) -> Result<(), Box<dyn Error>> {
+        self.client.spool_points(points)
+    }
+}
+
+/// How many of the points for a measurement would be new to InfluxDB
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PointDiff {
+    pub new: usize,
+    /// Points at a timestamp the measurement already has; writing them again overwrites
+    /// the existing points, or duplicates them when their tags differ
+    pub existing: usize,
+}
+
+/// Builds the body of an InfluxDB 2.x delete request for the points of a measurement
+/// recorded from `start` up to (not including) `stop`, in the series matching every pair of
+/// `tags`; the API includes both ends, so the stop sent is a nanosecond earlier
+pub fn flux_delete_body(
+    measurement: &str,
+    tags: &[(&str, &str)],
+    start: DateTime<Utc>,
+    stop: DateTime<Utc>,
+) -> String {
+    let mut predicate = format!("_measurement=\"{}\"", measurement.replace('"', "\\\""));
+    for (key, value) in tags {
+        predicate.push_str(&format!(" AND {}=\"{}\"", key, value.replace('"', "\\\"")));
+    }
+    serde_json::json!({
+        "start": start.to_rfc3339_opts(SecondsFormat::AutoSi, true),
+        "stop": (stop - Duration::nanoseconds(1)).to_rfc3339_opts(SecondsFormat::Nanos, true),
+        "predicate": predicate,
+    })
+    .to_string()
+}
+
+/// Builds the InfluxQL statement deleting the points of a measurement recorded from `start`
+/// up to (not including) `stop`, in the series matching every pair of `tags`
+pub fn influxql_delete(
+    measurement: &str,
+    tags: &[(&str, &str)],
+    start: DateTime<Utc>,
+    stop: DateTime<Utc>,
+) -> String {
+    let mut statement = format!(
+        "DELETE FROM \"{}\" WHERE ",
+        measurement.replace('"', "\\\"")
+    );
+    for (key, value) in tags {
+        statement.push_str(&format!(
+            "\"{}\" = '{}' AND ",
+            key.replace('"', "\\\""),
+            value.replace('\'', "\\'")
+        ));
+    }
+    statement.push_str(&format!(
+        "time >= {}ms AND time < {}ms",
+        start.timestamp_millis(),
+        stop.timestamp_millis()
+    ));
+    statement
+}
+
+/// Classifies points by whether their measurement already has a point at their timestamp,
+/// given the existing timestamps (as Unix milliseconds) of each measurement
+pub fn diff_points(
+    points: &[DataPoint],
+    existing: &HashMap<String, HashSet<i64>>,
+) -> BTreeMap<String, PointDiff> {
+    let mut diff: BTreeMap<String, PointDiff> = BTreeMap::new();
+    for point in points {
+        let counts = diff.entry(point.measurement.clone()).or_default();
+        let present = existing
+            .get(&point.measurement)
+            .is_some_and(|timestamps| timestamps.contains(&point.time.timestamp_millis()));
+        if present {
+            counts.existing += 1;
+        } else {
+            counts.new += 1;
+        }
+    }
+    diff
+}
+
+/// Splits points into those with no point among `existing` of the same measurement, tags
+/// and time (to the millisecond), and those with one
+pub fn split_conflicts(
+    points: &[DataPoint],
+    existing: &[MultiFieldPoint],
+) -> (Vec<DataPoint>, Vec<DataPoint>) {
+    // Empty tags are not written, so they do not tell points apart
+    fn key<'p>(
+        measurement: &'p str,
+        time: &DateTime<Utc>,
+        tags: &'p HashMap<String, String>,
+    ) -> (&'p str, i64, BTreeMap<&'p str, &'p str>) {
+        let tags = tags
+            .iter()
+            .filter(|(_, value)| !value.is_empty())
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        (measurement, time.timestamp_millis(), tags)
+    }
+
+    let existing: HashSet<_> = existing
+        .iter()
+        .map(|point| key(&point.measurement, &point.time, &point.tags))
+        .collect();
+    points
+        .iter()
+        .cloned()
+        .partition(|point| !existing.contains(&key(&point.measurement, &point.time, &point.tags)))
+}
+
+/// Gets the time range of the points of each measurement, first and last point included
+fn measurement_ranges(points: &[DataPoint]) -> BTreeMap<&str, (DateTime<Utc>, DateTime<Utc>)> {
+    let mut ranges: BTreeMap<&str, (DateTime<Utc>, DateTime<Utc>)> = BTreeMap::new();
+    for point in points {
+        let range = ranges
+            .entry(&point.measurement)
+            .or_insert((point.time, point.time));
+        range.0 = range.0.min(point.time);
+        range.1 = range.1.max(point.time);
+    }
+    ranges
+}
+
+/// Extracts the `_time` column (as Unix milliseconds) from an annotated CSV Flux response
+/// Each table in the response starts with its own header row
+pub fn parse_flux_timestamps(csv: &str) -> HashSet<i64> {
+    let mut timestamps = HashSet::new();
+    let mut time_index: Option<usize> = None;
+
+    for line in csv.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() || line.starts_with('#') {
+            // A blank line ends the current table
+            time_index = None;
+            continue;
+        }
+
+        let columns: Vec<&str> = line.split(',').collect();
+        match time_index {
+            None => time_index = columns.iter().position(|c| *c == "_time"),
+            Some(idx) => {
+                if let Some(parsed_time) = columns
+                    .get(idx)
+                    .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+                {
+                    timestamps.insert(parsed_time.timestamp_millis());
                 }
-                println!(
-                    "Found {} existing heart rate data points in InfluxDB",
-                    existing_timestamps.len()
-                );
             }
-            Err(e) => {
-                println!("Warning: Failed to query existing heart rate data: {}", e);
-                println!("Proceeding with normal import (may result in duplicates)");
+        }
+    }
+
+    timestamps
+}
+
+/// Extracts the per-day counts (`_time` and `_value` columns) from an annotated CSV Flux
+/// response, adding up the counts of tables that share a day
+pub fn parse_flux_daily_counts(csv: &str) -> BTreeMap<NaiveDate, u64> {
+    let mut counts = BTreeMap::new();
+    for (time, count) in parse_flux_window_counts(csv) {
+        *counts.entry(time.date_naive()).or_default() += count;
+    }
+    counts
+}
+
+/// Extracts the counts per window from an annotated CSV Flux response, by the `_time` of
+/// each window
+pub fn parse_flux_window_counts(csv: &str) -> BTreeMap<DateTime<Utc>, u64> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(csv.as_bytes());
+
+    let mut counts = BTreeMap::new();
+    let mut indexes: Option<(usize, usize)> = None;
+    for record in reader.records().flatten() {
+        // Each table starts with its own header row
+        let time_index = record.iter().position(|c| c == "_time");
+        let value_index = record.iter().position(|c| c == "_value");
+        if let (Some(time_index), Some(value_index)) = (time_index, value_index) {
+            indexes = Some((time_index, value_index));
+            continue;
+        }
+
+        let Some((time_index, value_index)) = indexes else {
+            continue;
+        };
+        let time = record
+            .get(time_index)
+            .and_then(|value| DateTime::parse_from_rfc3339(value).ok());
+        let count = record
+            .get(value_index)
+            .and_then(|value| value.parse::<u64>().ok());
+        if let (Some(time), Some(count)) = (time, count) {
+            *counts.entry(time.with_timezone(&Utc)).or_default() += count;
+        }
+    }
+    counts
+}
+
+/// Extracts the first `_value` from an annotated CSV Flux response
+/// Unlike timestamps, values can contain quoted commas and line breaks, so a CSV reader is used
+pub fn parse_flux_value(csv: &str) -> Option<String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(csv.as_bytes());
+
+    let mut value_index: Option<usize> = None;
+    for record in reader.records().flatten() {
+        match value_index {
+            None => value_index = record.iter().position(|c| c == "_value"),
+            Some(idx) => return record.get(idx).map(|value| value.to_string()),
+        }
+    }
+    None
+}
+
+/// Extracts every `_value` from an annotated CSV Flux response
+pub fn parse_flux_values(csv: &str) -> Vec<String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(csv.as_bytes());
+
+    let mut values = Vec::new();
+    let mut value_index: Option<usize> = None;
+    for record in reader.records().flatten() {
+        // Each table starts with its own header row
+        if let Some(idx) = record.iter().position(|c| c == "_value") {
+            value_index = Some(idx);
+            continue;
+        }
+        if let Some(value) = value_index.and_then(|idx| record.get(idx)) {
+            values.push(value.to_string());
+        }
+    }
+    values
+}
+
+/// Columns of a Flux response that are not tags
+const FLUX_COLUMNS: [&str; 9] = [
+    "",
+    "result",
+    "table",
+    "_start",
+    "_stop",
+    "_time",
+    "_value",
+    "_field",
+    "_measurement",
+];
+
+/// A series and time: the measurement, time and tags (sorted) a point is identified by
+type PointKey = (String, DateTime<Utc>, Vec<(String, String)>);
+
+/// Collects the fields of each point: rows hold one field each, and the rows of a point
+/// share its measurement, time and tags
+#[derive(Default)]
+struct PointFields {
+    points: BTreeMap<PointKey, BTreeMap<String, f64>>,
+}
+
+impl PointFields {
+    fn add(
+        &mut self,
+        measurement: &str,
+        time: DateTime<Utc>,
+        mut tags: Vec<(String, String)>,
+        field: &str,
+        value: f64,
+    ) {
+        tags.sort();
+        self.points
+            .entry((measurement.to_string(), time, tags))
+            .or_default()
+            .insert(field.to_string(), value);
+    }
+
+    fn into_points(self) -> Vec<MultiFieldPoint> {
+        self.points
+            .into_iter()
+            .map(|((measurement, time, tags), fields)| MultiFieldPoint {
+                measurement,
+                time,
+                tags: tags.into_iter().collect(),
+                fields,
+            })
+            .collect()
+    }
+}
+
+/// Extracts points from an annotated CSV Flux response with a row per field: the columns
+/// other than Flux's own are tags. Non-numeric fields are left out
+pub fn parse_flux_points(csv: &str) -> Vec<MultiFieldPoint> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .comment(Some(b'#'))
+        .from_reader(csv.as_bytes());
+
+    let mut points = PointFields::default();
+    let mut header: Option<Vec<String>> = None;
+    for record in reader.records().flatten() {
+        // Each table starts with its own header row
+        if record.iter().any(|c| c == "_time") && record.iter().any(|c| c == "_field") {
+            header = Some(record.iter().map(str::to_string).collect());
+            continue;
+        }
+        let Some(header) = &header else {
+            continue;
+        };
+
+        let column = |name: &str| {
+            header
+                .iter()
+                .position(|c| c == name)
+                .and_then(|idx| record.get(idx))
+        };
+        let time = column("_time")
+            .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+            .map(|time| time.with_timezone(&Utc));
+        let value = column("_value").and_then(|value| value.parse::<f64>().ok());
+        let (Some(time), Some(value), Some(field), Some(measurement)) =
+            (time, value, column("_field"), column("_measurement"))
+        else {
+            continue;
+        };
+        let tags = header
+            .iter()
+            .zip(record.iter())
+            .filter(|(name, value)| !FLUX_COLUMNS.contains(&name.as_str()) && !value.is_empty())
+            .map(|(name, value)| (name.clone(), value.to_string()))
+            .collect();
+        points.add(measurement, time, tags, field, value);
+    }
+    points.into_points()
+}
+
+/// Extracts points from the results of an InfluxQL `SELECT * ... GROUP BY *` query, whose
+/// series carry the tags and whose columns are the time and the fields. Non-numeric fields
+/// are left out
+pub fn parse_influxql_points(results: &[serde_json::Value]) -> Vec<MultiFieldPoint> {
+    let mut points = PointFields::default();
+    let series = results
+        .iter()
+        .filter_map(|result| result.get("series")?.as_array())
+        .flatten();
+    for serie in series {
+        let Some(measurement) = serie.get("name").and_then(|name| name.as_str()) else {
+            continue;
+        };
+        let tags: Vec<(String, String)> = serie
+            .get("tags")
+            .and_then(|tags| tags.as_object())
+            .into_iter()
+            .flatten()
+            .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+        let columns: Vec<&str> = serie
+            .get("columns")
+            .and_then(|columns| columns.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|column| column.as_str())
+            .collect();
+        let rows = serie
+            .get("values")
+            .and_then(|values| values.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|row| row.as_array());
+        for row in rows {
+            let Some(time) = row
+                .first()
+                .and_then(|time| time.as_str())
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| time.with_timezone(&Utc))
+            else {
+                continue;
+            };
+            for (field, value) in columns.iter().zip(row).skip(1) {
+                if let Some(value) = value.as_f64() {
+                    points.add(measurement, time, tags.clone(), field, value);
+                }
             }
         }
+    }
+    points.into_points()
+}
 
-        Ok(existing_timestamps)
+/// Checks whether a server version reported by /ping supports Flux queries against buckets
+fn is_flux_version(version: &str) -> bool {
+    version.trim_start_matches('v').starts_with("2.")
+}
+
+/// Escapes a value for a double-quoted Flux string literal
+pub fn escape_flux_string(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Returned by requests that ran into the connect or request timeout
+#[derive(Debug, Clone, PartialEq)]
+pub struct Timeout {
+    message: String,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for Timeout {}
+
+/// Checks whether an error was caused by a connect or request timeout
+pub(crate) fn is_timeout(error: &(dyn Error + 'static)) -> bool {
+    error.is::<Timeout>()
+}
+
+/// Escapes the given special characters with a backslash for line protocol
+fn escape_line_protocol(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
     }
+    escaped
 }
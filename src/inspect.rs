@@ -0,0 +1,242 @@
+use crate::config::{fill_template, CONFIG_TEMPLATE};
+use crate::csv_parser::{detect_delimiter, CsvParser};
+use crate::health_data::{HealthDataReader, HealthDataType};
+use chrono::{NaiveDate, NaiveDateTime};
+use clap::ValueEnum;
+use csv::ReaderBuilder;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// Timestamp formats import-funds can be set to read, tried in order
+const TIME_FORMATS: [&str; 7] = [
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%d/%m/%Y %H:%M:%S",
+    "%d/%m/%Y %H:%M",
+    "%d.%m.%Y %H:%M:%S",
+    "%m/%d/%Y %H:%M:%S",
+];
+
+/// Formats of dates without a time: they mark the first data row, but import-funds needs
+/// a time of day
+const DATE_FORMATS: [&str; 4] = ["%Y-%m-%d", "%d/%m/%Y", "%d.%m.%Y", "%m/%d/%Y"];
+
+/// Rows read from the top of a CSV file to find its header rows and timestamp format
+const SAMPLE_ROWS: usize = 50;
+
+/// The kinds of source files that can be imported
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum SourceKind {
+    /// A funds CSV file (import-funds)
+    Funds,
+    /// A Health Connect SQLite export (import-health-data)
+    Health,
+}
+
+impl SourceKind {
+    /// Guesses the kind of a source file from its extension
+    pub fn detect(source: &str) -> Self {
+        let extension = Path::new(source)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("db" | "sqlite" | "sqlite3") => SourceKind::Health,
+            _ => SourceKind::Funds,
+        }
+    }
+}
+
+/// What `init --from` found in a funds CSV file
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvInspection {
+    pub source: String,
+    /// Rows above the first one starting with a timestamp (1 when none does)
+    pub header_rows: usize,
+    /// Names of the columns, as the import reads them
+    pub columns: Vec<String>,
+    /// The format all the timestamps of the sampled rows have, if any import-funds reads
+    pub time_format: Option<&'static str>,
+    /// The first timestamp of the file, to show when no format fits
+    pub first_time: Option<String>,
+    /// Measurement suggested for the columns, after the file name
+    pub measurement: String,
+}
+
+/// What `init --from` found in a Health Connect export
+#[derive(Debug, Clone, PartialEq)]
+pub struct HealthInspection {
+    pub source: String,
+    /// Data types with records in the export, with their number of records
+    pub data_types: Vec<(HealthDataType, i64)>,
+}
+
+/// Checks whether a value is a timestamp or a date in one of the formats looked for
+fn is_time(value: &str) -> bool {
+    TIME_FORMATS
+        .iter()
+        .any(|format| NaiveDateTime::parse_from_str(value, format).is_ok())
+        || DATE_FORMATS
+            .iter()
+            .any(|format| NaiveDate::parse_from_str(value, format).is_ok())
+}
+
+/// Suggests a measurement name for a file: its name without the extension, in lowercase,
+/// with anything but letters and digits turned into underscores
+pub fn suggest_measurement(path: &str) -> String {
+    let stem = Path::new(path)
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .unwrap_or("funds");
+    let name: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = name.trim_matches('_');
+    if name.is_empty() {
+        "funds".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Inspects a funds CSV file: its header rows are the rows above the first one whose first
+/// column holds a timestamp, and its timestamp format the first of the known ones that all
+/// the sampled timestamps have. Files not separated by commas are refused, as import-funds
+/// cannot read them
+pub fn inspect_csv(path: &str) -> Result<CsvInspection, Box<dyn Error>> {
+    let mut first_line = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut first_line)?;
+    if detect_delimiter(&first_line) != b',' {
+        return Err(format!(
+            "{} is not comma-separated; write a copy that is with validate-csv --fix-output",
+            path
+        )
+        .into());
+    }
+
+    let mut rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(File::open(path)?);
+    let first_cells: Vec<String> = rdr
+        .records()
+        .take(SAMPLE_ROWS)
+        .map(|record| Ok(record?.get(0).unwrap_or_default().trim().to_string()))
+        .collect::<Result<_, csv::Error>>()?;
+
+    let header_rows = first_cells
+        .iter()
+        .position(|cell| is_time(cell))
+        .unwrap_or(1)
+        .max(1);
+    let times: Vec<&str> = first_cells
+        .iter()
+        .skip(header_rows)
+        .map(String::as_str)
+        .filter(|cell| !cell.is_empty())
+        .collect();
+    let time_format = TIME_FORMATS.into_iter().find(|format| {
+        !times.is_empty()
+            && times
+                .iter()
+                .all(|time| NaiveDateTime::parse_from_str(time, format).is_ok())
+    });
+
+    Ok(CsvInspection {
+        source: path.to_string(),
+        header_rows,
+        columns: CsvParser::new(path)
+            .with_header_rows(header_rows)
+            .headers()?,
+        time_format,
+        first_time: times.first().map(|time| time.to_string()),
+        measurement: suggest_measurement(path),
+    })
+}
+
+/// Inspects a Health Connect export for the data types it holds records of
+pub fn inspect_health(path: &str) -> Result<HealthInspection, Box<dyn Error>> {
+    let reader = HealthDataReader::new(path);
+    if !reader.db_exists() {
+        return Err(format!("Database file does not exist: {}", path).into());
+    }
+    let data_types = reader
+        .table_stats()?
+        .into_iter()
+        .filter(|stats| stats.exists && stats.records > 0)
+        .map(|stats| (stats.data_type, stats.records))
+        .collect();
+    Ok(HealthInspection {
+        source: path.to_string(),
+        data_types,
+    })
+}
+
+/// Quotes a string as a TOML value
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+impl CsvInspection {
+    /// Gets the config template with its `[funds]` section filled in from the file
+    pub fn config(&self) -> String {
+        let mut comments = vec![format!(
+            "Found in {}: {} header rows and the columns {}",
+            self.source,
+            self.header_rows,
+            self.columns.join(", ")
+        )];
+        let mut values = vec![
+            ("source", toml_string(&self.source)),
+            ("measurement", toml_string(&self.measurement)),
+            ("header_rows", self.header_rows.to_string()),
+        ];
+        if let Some(time_column) = self.columns.first() {
+            values.push(("time_column", toml_string(time_column)));
+        }
+        match (self.time_format, &self.first_time) {
+            (Some(time_format), _) => values.push(("time_format", toml_string(time_format))),
+            (None, Some(first_time)) => comments.push(format!(
+                "No known timestamp format fits '{}'; set time_format to one with a time of day",
+                first_time
+            )),
+            (None, None) => comments.push("No timestamps found below the header".to_string()),
+        }
+        fill_template(CONFIG_TEMPLATE, "funds", &comments, &values)
+    }
+}
+
+impl HealthInspection {
+    /// Gets the config template with its `[health]` section filled in from the export
+    pub fn config(&self) -> String {
+        let found: Vec<String> = self
+            .data_types
+            .iter()
+            .map(|(data_type, records)| format!("{} ({} records)", data_type, records))
+            .collect();
+        let comments = vec![
+            format!("Found in {}: {}", self.source, found.join(", ")),
+            "Each data type is written to the measurement of the same name".to_string(),
+        ];
+        let data_types: Vec<String> = self
+            .data_types
+            .iter()
+            .map(|(data_type, _)| toml_string(data_type.as_str()))
+            .collect();
+        let mut values = vec![("source", toml_string(&self.source))];
+        if !data_types.is_empty() {
+            values.push(("data_types", format!("[{}]", data_types.join(", "))));
+        }
+        fill_template(CONFIG_TEMPLATE, "health", &comments, &values)
+    }
+}
@@ -0,0 +1,72 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Asks imports to stop after the batch they are writing, as Ctrl-C or SIGTERM do
+pub fn request() {
+    REQUESTED.store(true, Ordering::Relaxed);
+}
+
+/// Checks whether the import should stop
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::Relaxed)
+}
+
+/// Catches Ctrl-C (SIGINT) and SIGTERM, so an import can stop between batches and keep
+/// its state consistent with what was written. A second signal exits at once with
+/// `exit_code`
+pub fn install(exit_code: i32) {
+    tokio::spawn(async move {
+        loop {
+            wait_for_signal().await;
+            if requested() {
+                eprintln!("Stopping immediately");
+                std::process::exit(exit_code);
+            }
+            eprintln!("Stopping after the current batch; press Ctrl-C again to stop immediately");
+            request();
+        }
+    });
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Returned by writes that stopped early because an interruption was requested
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interrupted {
+    /// Points written before stopping
+    pub written_points: usize,
+}
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "interrupted after writing {} points",
+            self.written_points
+        )
+    }
+}
+
+impl Error for Interrupted {}
@@ -0,0 +1,550 @@
+use crate::audit;
+use crate::config::{Config, JobConfig};
+use crate::import::{EXIT_ERROR, EXIT_NOTHING_NEW, RUN_REPORT_ENV};
+use crate::metrics::{Exporter, RunResult};
+use crate::output;
+use crate::remote;
+use crate::report::ProgressFormat;
+use crate::schedule::{source_version, CronSchedule};
+use crate::state_management::JournalEntry;
+use crate::trigger::TriggerServer;
+use chrono::{DateTime, Local, Utc};
+use clap::Command;
+use std::collections::BTreeMap;
+use std::io;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Runs this executable with the given arguments and waits for it to finish
+fn run_self(args: &[String]) -> io::Result<process::ExitStatus> {
+    spawn_self(args)?.wait()
+}
+
+/// Starts this executable with the given arguments
+fn spawn_self(args: &[String]) -> io::Result<process::Child> {
+    process::Command::new(std::env::current_exe()?)
+        .args(args)
+        .env_remove("HDI_WATCH")
+        .spawn()
+}
+
+/// Runs this executable like run_self, and reads back the journal entry of the import
+/// it ran, if it got far enough to record one. Several runs can be going at once
+fn run_reported(args: &[String]) -> (io::Result<process::ExitStatus>, Option<JournalEntry>) {
+    static RUNS: AtomicUsize = AtomicUsize::new(0);
+    let report_file = std::env::temp_dir().join(format!(
+        "home-db-importer-{}-{}.run.json",
+        process::id(),
+        RUNS.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_file(&report_file);
+
+    let status = std::env::current_exe().and_then(|exe| {
+        process::Command::new(exe)
+            .args(args)
+            .env_remove("HDI_WATCH")
+            .env(RUN_REPORT_ENV, &report_file)
+            .status()
+    });
+
+    let entry = std::fs::read_to_string(&report_file)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok());
+    let _ = std::fs::remove_file(&report_file);
+    (status, entry)
+}
+
+/// Tells how a child import ended, for the metrics
+fn run_result(status: &io::Result<process::ExitStatus>) -> RunResult {
+    match status {
+        Ok(status) if status.success() => RunResult::Success,
+        Ok(status) if status.code() == Some(EXIT_NOTHING_NEW) => RunResult::NothingNew,
+        _ => RunResult::Failure,
+    }
+}
+
+/// Starts publishing metrics for --watch or the daemon, exiting when that fails
+pub fn start_metrics(addr: Option<&str>, textfile: Option<&str>) -> Exporter {
+    match Exporter::start(addr, textfile.map(String::from)) {
+        Ok(exporter) => {
+            if let Some(addr) = addr {
+                progress!("Serving metrics at http://{}/metrics", addr);
+            }
+            exporter
+        }
+        Err(e) => {
+            eprintln!("Failed to start publishing metrics: {}", e);
+            process::exit(EXIT_ERROR);
+        }
+    }
+}
+
+/// Runs the current command again without --watch whenever the source file changes,
+/// checking every `interval`, and publishes metrics at `metrics_addr` or in `metrics_file`.
+/// Each import runs in a child process, so a failed import is retried at the next check
+/// instead of ending the watch
+pub fn watch_source(
+    source: &str,
+    interval: Duration,
+    metrics_addr: Option<&str>,
+    metrics_file: Option<&str>,
+) -> ! {
+    if remote::is_remote(source) {
+        eprintln!("--watch watches a local file; run a URL source on a schedule instead");
+        process::exit(EXIT_ERROR);
+    }
+    let exporter = start_metrics(metrics_addr, metrics_file);
+
+    let mut child_args = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--watch" {
+            args.next();
+        } else if !arg.starts_with("--watch=") {
+            child_args.push(arg);
+        }
+    }
+
+    progress!(
+        "Watching '{}' for changes every {} seconds",
+        source,
+        interval.as_secs()
+    );
+    let _ = exporter.update(|metrics| metrics.add_job(source));
+    let mut imported_version = None;
+    loop {
+        let version = source_version(source);
+        if version.is_some() && version != imported_version {
+            progress!(
+                "\n[{}] Importing '{}'",
+                Utc::now().format("%Y-%m-%d %H:%M:%S UTC"),
+                source
+            );
+            let started = std::time::Instant::now();
+            let (status, entry) = run_reported(&child_args);
+            match &status {
+                Ok(status) if status.success() || status.code() == Some(EXIT_NOTHING_NEW) => {
+                    imported_version = version
+                }
+                Ok(status) => eprintln!("Import failed ({}); retrying at the next check", status),
+                Err(e) => eprintln!("Failed to start the import: {}", e),
+            }
+            let updated = exporter.update(|metrics| {
+                metrics.record_run(
+                    source,
+                    run_result(&status),
+                    started.elapsed(),
+                    entry.as_ref(),
+                )
+            });
+            if let Err(e) = updated {
+                eprintln!(
+                    "{}",
+                    output::warning(&format!("Failed to write metrics: {}", e))
+                );
+            }
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Builds the arguments that run a configured job: its command of `cli`, with its settings
+/// as flags
+fn job_args(
+    cli: &Command,
+    config_file: &str,
+    job: &JobConfig,
+    lock_file: Option<&str>,
+    progress_format: ProgressFormat,
+) -> Result<Vec<String>, String> {
+    let sub_command = cli
+        .find_subcommand(&job.command)
+        .ok_or_else(|| format!("job '{}': unknown command '{}'", job.name, job.command))?;
+
+    let mut args = vec!["--config".to_string(), config_file.to_string()];
+    // --quiet, --progress-format, --no-color, --lock-file and --audit-log are global flags
+    // rather than job settings, so jobs inherit them
+    if progress_format == ProgressFormat::Json {
+        args.push("--progress-format".to_string());
+        args.push("json".to_string());
+    } else if output::is_quiet() {
+        args.push("--quiet".to_string());
+    }
+    if !output::color_enabled() {
+        args.push("--no-color".to_string());
+    }
+    if let Some(lock_file) = lock_file {
+        args.push("--lock-file".to_string());
+        args.push(lock_file.to_string());
+    }
+    // Each job is a run of its own in the audit log
+    if let Some(log) = audit::audit_log() {
+        args.push("--audit-log".to_string());
+        args.push(log.path().to_string());
+    }
+    args.push(job.command.clone());
+    for (name, value) in job.settings() {
+        if name == "watch" || name == "every" {
+            return Err(format!(
+                "job '{}': use schedule instead of {}",
+                job.name, name
+            ));
+        }
+        let arg = sub_command
+            .get_arguments()
+            .find(|arg| arg.get_id() == name.as_str())
+            .ok_or_else(|| {
+                format!(
+                    "job '{}': {} has no setting '{}'",
+                    job.name, job.command, name
+                )
+            })?;
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        args.push(format!("--{}", long));
+        if arg.get_action().takes_values() {
+            args.push(value);
+        }
+    }
+    Ok(args)
+}
+
+/// Loads the [[jobs]] of the config file, or only the named ones, with the arguments that
+/// run them. Exits when there is no config file or a job is invalid
+fn load_jobs(
+    cli: &Command,
+    config_file: Option<&str>,
+    only: &[String],
+    command: &str,
+    lock_file: Option<&str>,
+    progress_format: ProgressFormat,
+) -> Vec<(JobConfig, Vec<String>)> {
+    let Some(config_file) = config_file else {
+        eprintln!(
+            "{} runs the [[jobs]] of a config file; pass it with --config",
+            command
+        );
+        process::exit(EXIT_ERROR);
+    };
+    let config = match Config::load(config_file) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+    if let Some(name) = only.iter().find(|name| config.job(name).is_none()) {
+        eprintln!("No job named '{}' in {}", name, config_file);
+        process::exit(EXIT_ERROR);
+    }
+
+    let mut jobs = Vec::new();
+    for job in config.jobs {
+        if !only.is_empty() && !only.contains(&job.name) {
+            continue;
+        }
+        match job_args(cli, config_file, &job, lock_file, progress_format) {
+            Ok(args) => jobs.push((job, args)),
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+    if jobs.is_empty() {
+        eprintln!("No jobs to run in {}", config_file);
+        process::exit(EXIT_ERROR);
+    }
+    jobs
+}
+
+/// Runs the jobs of the config file once, one after another or all at the same time,
+/// then prints a summary of their results. Exits with EXIT_ERROR when any job failed
+pub fn run_all(
+    cli: &Command,
+    config_file: Option<&str>,
+    only: &[String],
+    parallel: bool,
+    lock_file: Option<&str>,
+    progress_format: ProgressFormat,
+) {
+    let jobs = load_jobs(
+        cli,
+        config_file,
+        only,
+        "run-all",
+        lock_file,
+        progress_format,
+    );
+    let started = std::time::Instant::now();
+
+    let mut results: Vec<(String, String, io::Result<process::ExitStatus>, Duration)> = Vec::new();
+    if parallel {
+        progress!("Running {} jobs in parallel", jobs.len());
+        let children: Vec<_> = jobs
+            .into_iter()
+            .map(|(job, args)| {
+                progress!("Starting job '{}'", job.name);
+                (job, std::time::Instant::now(), spawn_self(&args))
+            })
+            .collect();
+        for (job, job_started, child) in children {
+            let status = child.and_then(|mut child| child.wait());
+            results.push((job.name, job.command, status, job_started.elapsed()));
+        }
+    } else {
+        for (job, args) in jobs {
+            progress!("\nRunning job '{}' ({})", job.name, job.command);
+            let job_started = std::time::Instant::now();
+            let status = run_self(&args);
+            results.push((job.name, job.command, status, job_started.elapsed()));
+        }
+    }
+
+    println!("\nSummary of {} jobs:", results.len());
+    let mut failures = 0;
+    let mut rows = Vec::new();
+    for (name, command, status, elapsed) in &results {
+        let outcome = match status {
+            Ok(status) if status.success() => output::success("imported"),
+            Ok(status) if status.code() == Some(EXIT_NOTHING_NEW) => {
+                output::success("nothing new to import")
+            }
+            Ok(status) => {
+                failures += 1;
+                match status.code() {
+                    Some(code) => output::failure(&format!("failed (exit code {})", code)),
+                    None => output::failure(&format!("failed ({})", status)),
+                }
+            }
+            Err(e) => {
+                failures += 1;
+                output::failure(&format!("could not start: {}", e))
+            }
+        };
+        rows.push(vec![
+            name.clone(),
+            command.clone(),
+            format!("{:.1}s", elapsed.as_secs_f64()),
+            outcome,
+        ]);
+    }
+    println!(
+        "{}",
+        output::table(&["Job", "Command", "Time", "Result"], &rows)
+    );
+    let summary = format!(
+        "{} of {} jobs succeeded in {:.1}s",
+        results.len() - failures,
+        results.len(),
+        started.elapsed().as_secs_f64()
+    );
+    if failures > 0 {
+        println!("{}", output::failure(&summary));
+    } else {
+        println!("{}", output::success(&summary));
+    }
+
+    if failures > 0 {
+        process::exit(EXIT_ERROR);
+    }
+}
+
+/// Runs the scheduled jobs of the config file until the process is stopped
+/// Jobs run one at a time in child processes; a job that is still running when its next
+/// run is due skips that run
+pub fn run_daemon(
+    cli: &Command,
+    config_file: Option<&str>,
+    only: &[String],
+    lock_file: Option<&str>,
+    progress_format: ProgressFormat,
+    exporter: Exporter,
+) -> ! {
+    let mut jobs = Vec::new();
+    for (job, args) in load_jobs(
+        cli,
+        config_file,
+        only,
+        "The daemon",
+        lock_file,
+        progress_format,
+    ) {
+        let Some(schedule) = &job.schedule else {
+            progress!("Skipping job '{}': it has no schedule", job.name);
+            continue;
+        };
+        // Schedules were checked when loading the config
+        let schedule = CronSchedule::parse(schedule).expect("validated schedule");
+        let Some(next_run) = schedule.next_after(&Local::now()) else {
+            progress!("Skipping job '{}': its schedule never fires", job.name);
+            continue;
+        };
+        progress!(
+            "Job '{}' ({}): next run at {}",
+            job.name,
+            job.command,
+            next_run.format("%Y-%m-%d %H:%M")
+        );
+        jobs.push((job.name.clone(), schedule, args, next_run));
+    }
+    if jobs.is_empty() {
+        eprintln!("None of the jobs has a schedule");
+        process::exit(EXIT_ERROR);
+    }
+    let _ = exporter.update(|metrics| {
+        for (name, ..) in &jobs {
+            metrics.add_job(name);
+        }
+    });
+
+    loop {
+        let now = Local::now();
+        for (name, schedule, args, next_run) in jobs.iter_mut() {
+            if *next_run > now {
+                continue;
+            }
+            progress!(
+                "\n[{}] Running job '{}'",
+                Local::now().format("%Y-%m-%d %H:%M:%S"),
+                name
+            );
+            let started = std::time::Instant::now();
+            let (status, entry) = run_reported(args);
+            match &status {
+                Ok(status) if status.success() => progress!("Job '{}' finished", name),
+                Ok(status) if status.code() == Some(EXIT_NOTHING_NEW) => {
+                    progress!("Job '{}' finished: nothing new to import", name)
+                }
+                Ok(status) => eprintln!("Job '{}' failed ({})", name, status),
+                Err(e) => eprintln!("Failed to start job '{}': {}", name, e),
+            }
+            let updated = exporter.update(|metrics| {
+                metrics.record_run(name, run_result(&status), started.elapsed(), entry.as_ref())
+            });
+            if let Err(e) = updated {
+                eprintln!(
+                    "{}",
+                    output::warning(&format!("Failed to write metrics: {}", e))
+                );
+            }
+            // Runs missed while the job was busy are skipped
+            match schedule.next_after(&Local::now()) {
+                Some(next) => *next_run = next,
+                None => *next_run = DateTime::<Local>::MAX_UTC.into(),
+            }
+        }
+
+        // Wake up at least every minute, so clock changes do not delay runs for long
+        let next_run = jobs.iter().map(|job| job.3).min().unwrap_or(now);
+        let wait = (next_run - Local::now())
+            .to_std()
+            .unwrap_or_default()
+            .min(Duration::from_secs(60));
+        std::thread::sleep(wait);
+    }
+}
+
+/// Serves the jobs of the config file over HTTP until the process is stopped. Each request
+/// runs its job in a child process; different jobs can run at the same time
+#[allow(clippy::too_many_arguments)]
+pub fn run_serve(
+    cli: &Command,
+    config_file: Option<&str>,
+    only: &[String],
+    addr: &str,
+    token: Option<String>,
+    max_upload_mb: u64,
+    lock_file: Option<&str>,
+    progress_format: ProgressFormat,
+    exporter: Exporter,
+) -> ! {
+    let loaded = load_jobs(cli, config_file, only, "serve", lock_file, progress_format);
+    // load_jobs exits unless the config file could be loaded
+    let config = config_file
+        .and_then(|path| Config::load(path).ok())
+        .unwrap_or_default();
+    let mut uploads = BTreeMap::new();
+    for (job, _) in &loaded {
+        if job.command != "import-health-data" {
+            continue;
+        }
+        match config.job_setting(job, "source") {
+            Some(source) if remote::is_remote(&source) => {
+                progress!("Job '{}' takes no uploads: its source is a URL", job.name)
+            }
+            Some(source) => {
+                progress!("Job '{}' takes uploads to {}", job.name, source);
+                uploads.insert(job.name.clone(), source);
+            }
+            None => progress!("Job '{}' takes no uploads: it has no source", job.name),
+        }
+    }
+    let jobs: BTreeMap<String, Vec<String>> = loaded
+        .into_iter()
+        .map(|(job, args)| (job.name, args))
+        .collect();
+    let names: Vec<String> = jobs.keys().cloned().collect();
+    let _ = exporter.update(|metrics| {
+        for name in &names {
+            metrics.add_job(name);
+        }
+    });
+
+    let run = move |name: &str| {
+        progress!(
+            "\n[{}] Running job '{}'",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            name
+        );
+        let started = std::time::Instant::now();
+        let (status, entry) = run_reported(&jobs[name]);
+        match &status {
+            Ok(status) if status.success() => progress!("Job '{}' finished", name),
+            Ok(status) if status.code() == Some(EXIT_NOTHING_NEW) => {
+                progress!("Job '{}' finished: nothing new to import", name)
+            }
+            Ok(status) => eprintln!("Job '{}' failed ({})", name, status),
+            Err(e) => eprintln!("Failed to start job '{}': {}", name, e),
+        }
+        let result = run_result(&status);
+        let updated = exporter
+            .update(|metrics| metrics.record_run(name, result, started.elapsed(), entry.as_ref()));
+        if let Err(e) = updated {
+            eprintln!(
+                "{}",
+                output::warning(&format!("Failed to write metrics: {}", e))
+            );
+        }
+        (result, status.ok().and_then(|status| status.code()), entry)
+    };
+
+    let local = ["127.", "localhost:", "[::1]:"];
+    if token.is_none() && !local.iter().any(|prefix| addr.starts_with(prefix)) {
+        eprintln!(
+            "{}",
+            output::warning(&format!(
+                "Anyone who can reach {} can start the jobs; set --serve-token",
+                addr
+            ))
+        );
+    }
+    let server = Arc::new(
+        TriggerServer::new(&names, token, Box::new(run))
+            .with_uploads(uploads)
+            .with_max_upload(max_upload_mb.saturating_mul(1024 * 1024)),
+    );
+    progress!(
+        "Serving {} jobs at http://{} (POST /run/<job>, POST /upload/<job>, GET /status)",
+        names.len(),
+        addr
+    );
+    if let Err(e) = server.serve(addr) {
+        eprintln!("Failed to serve at {}: {}", addr, e);
+        process::exit(EXIT_ERROR);
+    }
+    process::exit(0)
+}
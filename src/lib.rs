@@ -1,4 +1,119 @@
+//! Imports home data into InfluxDB: funds CSV exports and Health Connect databases
+//!
+//! The `home-db-importer` binary is a command line front end to this library; the same
+//! pipeline can be embedded in other programs:
+//! - sources read records: [`csv_parser::CsvParser`], [`dsmr::DsmrReader`],
+//!   [`weather::WeatherReader`], [`solar::SolarReader`], [`meter::MeterReader`],
+//!   [`prices::PriceSeries`], [`transactions::TransactionReader`] and
+//!   [`health_data::HealthDataReader`], all implementing [`source::Source`]; [`mqtt`] writes live sensor readings
+//!   and [`nav`] fetches fund NAVs instead, and [`remote`] downloads source files given as
+//!   URLs
+//! - converters turn them into points: [`convert`], with [`fx`] adding fund values in a base
+//!   currency, [`portfolio`] the performance of the funds held, [`aggregate`] daily
+//!   health rollups, [`training`] the training load of workouts, [`zones`] the time in
+//!   heart rate zones, [`hypnogram`] sleep stages as a regular series, [`derived`] metrics
+//!   such as body composition and [`downsample`] the min, mean and max of fixed windows
+//!   for long-term storage; [`anonymize`] hides the apps, devices and titles in tags
+//! - sinks write the points: [`influx_client::InfluxClient`], with [`spool`] for failed writes,
+//!   or [`sink::MemorySink`] in tests; both implement [`sink::Sink`]; [`grafana`] marks
+//!   exercise and sleep sessions on Grafana dashboards and [`extract`] writes points to
+//!   CSV or NDJSON files instead
+//! - the state remembers what was imported: [`state_store::StateStore`] and
+//!   [`state_management::ImportState`]
+//!
+//! [`pipeline::Pipeline`] connects a source to InfluxDB, converting and writing records
+//! while the source is still being read, and tells a [`report::Reporter`] how it goes;
+//! [`import`] runs the whole import of a source file for the binary, from its watermarks to
+//! the saved import state, with [`import::health`] for Health Connect exports and
+//! [`import::funds`] for CSV files; [`import::prices`], [`import::nav`], [`import::mqtt`]
+//! and [`import::spool`] run the other commands that write to InfluxDB
+//!
+//! [`jobs`] runs imports again whenever their source changes and the `[[jobs]]` of the
+//! config file, once, on their schedules or when [`trigger`] starts them over HTTP, e.g.
+//! from a phone automation; [`upload`] stores the Health Connect exports it uploads
+//!
+//! [`gaps`] finds the records a past import left out of InfluxDB, [`audit`] records
+//! which run wrote which points, and [`doctor`] checks a setup before the first import.
+//! [`template`] lays out a funds CSV file after the series of an existing measurement, and
+//! [`inspect`] fills in the config file `init` writes from a source file
+//!
+//! The other commands of the binary are in [`examine`], which looks into source files
+//! without importing them, [`reconcile`], which compares a Health Connect export with
+//! InfluxDB and backfills what is missing, [`maintenance`], which prunes and exports
+//! measurements, [`state`], which shows and changes state files and reads the audit log,
+//! and [`setup`], which writes config files and CSV templates
+
+#[macro_use]
+pub mod output;
+
+// Sources
 pub mod csv_parser;
+pub mod dsmr;
 pub mod health_data;
+pub mod meter;
+pub mod mqtt;
+pub mod nav;
+pub mod prices;
+pub mod remote;
+pub mod solar;
+pub mod source;
+pub mod transactions;
+pub mod weather;
+
+// Converters
+pub mod aggregate;
+pub mod anonymize;
+pub mod convert;
+pub mod derived;
+pub mod downsample;
+pub mod fx;
+pub mod hypnogram;
+pub mod portfolio;
+pub mod training;
+pub mod zones;
+
+// Sinks
+pub mod extract;
+pub mod grafana;
 pub mod influx_client;
+pub mod sink;
+pub mod spool;
+
+// State
 pub mod state_management;
+pub mod state_store;
+
+// Running imports
+pub mod audit;
+pub mod bench;
+pub mod config;
+pub mod doctor;
+pub mod examine;
+pub mod gaps;
+pub mod import;
+pub mod inspect;
+pub mod interrupt;
+pub mod jobs;
+pub mod maintenance;
+pub mod metrics;
+pub mod pipeline;
+pub mod reconcile;
+pub mod report;
+pub mod run_lock;
+pub mod schedule;
+pub mod setup;
+pub mod state;
+pub mod template;
+pub mod trigger;
+pub mod upload;
+
+pub use convert::{funds_record_to_points, health_record_to_point};
+pub use csv_parser::{CsvParser, CsvRecord};
+pub use health_data::{HealthDataReader, HealthRecord};
+pub use influx_client::{DataPoint, InfluxClient, InfluxClientBuilder};
+pub use pipeline::Pipeline;
+pub use report::{ConsoleReporter, JsonReporter, Reporter, SilentReporter};
+pub use sink::{MemorySink, NullSink, Sink};
+pub use source::{RecordStream, Source};
+pub use state_management::{ImportState, JournalEntry};
+pub use state_store::{StateBackend, StateStore};
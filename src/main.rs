@@ -1,14 +1,30 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
-use clap::{Parser, Subcommand};
-mod csv_parser;
-mod health_data;
-mod influx_client;
-mod state_management;
-use csv_parser::CsvParser;
-use health_data::HealthDataReader;
-use influx_client::InfluxClient;
-use state_management::{load_import_state, save_import_state};
-use std::collections::HashMap;
+use clap::parser::ValueSource;
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use home_db_importer::audit::{self, AuditLog};
+use home_db_importer::config::Config;
+use home_db_importer::doctor::{self, DoctorArgs};
+use home_db_importer::examine::{
+    self, BenchArgs, ExtractArgs, HealthStatsArgs, PreviewArgs, ValidateCsvArgs,
+};
+use home_db_importer::import::funds::FundsImportArgs;
+use home_db_importer::import::health::HealthImportArgs;
+use home_db_importer::import::mqtt::MqttArgs;
+use home_db_importer::import::nav::NavFetchArgs;
+use home_db_importer::import::prices::PricesImportArgs;
+use home_db_importer::import::spool::ResumeSpoolArgs;
+use home_db_importer::import::{self, EXIT_ERROR};
+use home_db_importer::jobs;
+use home_db_importer::maintenance::{self, ExportArgs, PruneArgs};
+use home_db_importer::output;
+use home_db_importer::reconcile::{self, BackfillArgs, CompareArgs, GapReportArgs};
+use home_db_importer::report::{
+    self, ConsoleReporter, JsonReporter, ProgressFormat, SilentReporter,
+};
+use home_db_importer::setup::{self, InitArgs, TemplateFromMeasurementArgs};
+use home_db_importer::state::{self, AuditArgs, HistoryArgs, StateCommands};
+use home_db_importer::trigger::DEFAULT_MAX_UPLOAD_MB;
+use std::io;
 use std::process;
 
 #[derive(Parser)]
@@ -18,548 +34,384 @@ struct Cli {
     #[arg(short, long, action = clap::ArgAction::Count)]
     debug: u8,
 
-    /// Sets a custom config file
-    #[arg(short, long, value_name = "FILE")]
+    /// Reads settings from a TOML config file (see `init`); command line flags override it
+    #[arg(short, long, value_name = "FILE", env = "HDI_CONFIG")]
     config: Option<String>,
 
+    /// Only print warnings and errors; see the README for the exit codes
+    #[arg(short, long, env = "HDI_QUIET")]
+    quiet: bool,
+
+    /// How to report the progress of imports: messages for people, or one JSON object per
+    /// event on stdout for scripts (other progress messages are left out then)
+    #[arg(
+        long,
+        value_enum,
+        default_value = "console",
+        env = "HDI_PROGRESS_FORMAT"
+    )]
+    progress_format: ProgressFormat,
+
+    /// Never color the output; it is only colored when writing to a terminal anyway
+    #[arg(long, env = "HDI_NO_COLOR")]
+    no_color: bool,
+
+    /// Serves Prometheus metrics at http://<ADDR>/metrics while watching or running the daemon
+    /// or serve
+    #[arg(long, value_name = "ADDR", env = "HDI_METRICS_ADDR")]
+    metrics_addr: Option<String>,
+
+    /// Writes Prometheus metrics to FILE after every run while watching or running the
+    /// daemon or serve, for node_exporter's textfile collector
+    #[arg(long, value_name = "FILE", env = "HDI_METRICS_FILE")]
+    metrics_file: Option<String>,
+
+    /// Imports and resume-spool wait for each other when given the same lock FILE, e.g. so
+    /// that a scheduled run never overlaps a slow backfill writing to the same bucket
+    #[arg(long, value_name = "FILE", env = "HDI_LOCK_FILE")]
+    lock_file: Option<String>,
+
+    /// Appends a JSON line to FILE for each measurement of every write to InfluxDB, with the
+    /// run, bucket, time range and number of points, to tell later which run wrote what
+    #[arg(long, value_name = "FILE", env = "HDI_AUDIT_LOG")]
+    audit_log: Option<String>,
+
+    /// Names this run in the audit log (its start time and process ID by default)
+    #[arg(long, value_name = "ID", requires = "audit_log", env = "HDI_RUN_ID")]
+    run_id: Option<String>,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
+// Parsed once per run, so the size of the import variant does not matter
+#[allow(clippy::large_enum_variant)]
 enum Commands {
     /// Import data from a CSV file into InfluxDB
-    ImportFunds {
-        /// The CSV file to import
-        #[arg(short, long, required = true)]
-        source: String,
-
-        /// InfluxDB URL
-        #[arg(short, long, default_value = "http://localhost:8086")]
-        url: String,
-
-        /// InfluxDB organization
-        #[arg(short, long)]
-        org: String,
-
-        /// InfluxDB bucket/database
-        #[arg(short, long)]
-        bucket: String,
-
-        /// InfluxDB token for authentication
-        #[arg(short, long)]
-        token: String,
-
-        /// Timestamp column name in CSV
-        #[arg(long, default_value = "timestamp")]
-        time_column: String,
-
-        /// Timestamp format (e.g., "YYYY-MM-DD HH:MM:SS")
-        #[arg(long, default_value = "%Y-%m-%d %H:%M:%S")]
-        time_format: String,
-
-        /// Measurement name in InfluxDB
-        #[arg(short, long, required = true)]
-        measurement: String,
-
-        /// Number of header rows in CSV file
-        #[arg(long, default_value = "1")]
-        header_rows: usize,
-
-        /// Run in dry-run mode (don't write to InfluxDB, just show queries)
-        #[arg(long)]
-        dry_run: bool,
-
-        /// State file to track last imported timestamp
-        #[arg(long, default_value = ".import_state.json")]
-        state_file: String,
-
-        /// Force import all records, ignoring state file
-        #[arg(long)]
-        force_all: bool,
-    },
+    ImportFunds(FundsImportArgs),
 
     /// Import health data from a Health Connect SQLite export
-    ImportHealthData {
-        /// The SQLite database file to import
-        #[arg(short, long, required = true)]
-        source: String,
-
-        /// InfluxDB URL
-        #[arg(short, long, default_value = "http://localhost:8086")]
-        url: String,
-
-        /// InfluxDB organization
-        #[arg(short, long)]
-        org: String,
-
-        /// InfluxDB bucket/database
-        #[arg(short, long)]
-        bucket: String,
-
-        /// InfluxDB token for authentication
-        #[arg(short, long)]
-        token: String,
-
-        /// State file to track last imported timestamp
-        #[arg(long, default_value = ".health_import_state.json")]
-        state_file: String,
-
-        /// Force import all records, ignoring state file
-        #[arg(long)]
-        force_all: bool,
-
-        /// Run in dry-run mode (don't write to InfluxDB, just show queries)
-        #[arg(long)]
-        dry_run: bool,
-
-        /// Only import specific data types (comma-separated). Available: HeartRate,Steps,Sleep,Weight,TotalCalories,BasalMetabolicRate,BodyFat,ExerciseSession
-        #[arg(long)]
-        data_types: Option<String>,
-
-        /// Enable heart rate gap-filling mode (checks InfluxDB for existing data in the last N days and fills gaps).
-        /// Note: Gap-filling mode only imports heart rate data and does not update the state file.
-        /// Run normal sync first to update state, then use gap-filling as a maintenance operation.
-        #[arg(long)]
-        gap_fill_heart_rate: Option<i64>,
-    },
+    ImportHealthData(HealthImportArgs),
+
+    /// Retry writing points saved to a spool file by a failed import
+    ResumeSpool(ResumeSpoolArgs),
+
+    /// Delete points older than a number of days from selected measurements, for servers
+    /// where the bucket's retention cannot be changed
+    Prune(PruneArgs),
+
+    /// Back up measurements of a bucket to a line protocol file, which `resume-spool` can
+    /// write back
+    Export(ExportArgs),
+
+    /// Subscribe to the [[mqtt.topics]] of the config file and write every reading to
+    /// InfluxDB as it arrives, until stopped
+    Mqtt(MqttArgs),
+
+    /// Import day-ahead electricity prices from aWATTar or a CSV export into InfluxDB
+    ImportPrices(PricesImportArgs),
+
+    /// Fetch the current NAV of the [[nav.funds]] of the config file from a JSON HTTP API
+    /// and write it to InfluxDB with the same fund tags as import-funds
+    FetchNav(NavFetchArgs),
 
     /// Validate a CSV file format without importing
-    ValidateCSV {
-        /// The CSV file to validate
-        #[arg(short, long)]
-        source: String,
-
-        /// Show detailed information about the CSV structure
-        #[arg(short, long)]
-        details: bool,
-
-        /// Number of header rows in CSV file
-        #[arg(long, default_value = "1")]
-        header_rows: usize,
+    ValidateCSV(ValidateCsvArgs),
+
+    /// Print the first points an import would write, without connecting to InfluxDB or
+    /// reading the import state
+    Preview(PreviewArgs),
+
+    /// Measure how fast a source is read, converted and batched, without connecting to
+    /// InfluxDB or reading the import state
+    Bench(BenchArgs),
+
+    /// Summarize the record tables of a Health Connect SQLite export
+    HealthStats(HealthStatsArgs),
+
+    /// Write health data from a Health Connect export to CSV or NDJSON files, one per
+    /// measurement, converted as for InfluxDB but without writing to it
+    Extract(ExtractArgs),
+
+    /// Count records per day in a Health Connect export and points per day in InfluxDB side by
+    /// side, to find days that were not fully imported
+    Compare(CompareArgs),
+
+    /// List the intervals of health data in a Health Connect export that are missing from
+    /// InfluxDB, without importing anything, to inspect them before a backfill
+    GapReport(GapReportArgs),
+
+    /// Import the health data in a date range that InfluxDB is missing, leaving the import
+    /// state alone, to repair holes in past imports
+    Backfill(BackfillArgs),
+
+    /// Check the source file, InfluxDB connection and state file, suggesting fixes for problems
+    Doctor(DoctorArgs),
+
+    /// Inspect or modify an import state file
+    State {
+        #[command(subcommand)]
+        action: StateCommands,
+    },
+
+    /// List recent import runs recorded in a state file
+    History(HistoryArgs),
+
+    /// List the writes recorded in the --audit-log file, most recent first
+    Audit(AuditArgs),
+
+    /// Keep running and run the [[jobs]] of the config file on their schedules
+    Daemon {
+        /// Only run these jobs (comma-separated names); runs every scheduled job when omitted
+        #[arg(long, value_delimiter = ',', env = "HDI_JOB")]
+        job: Vec<String>,
     },
 
+    /// Serve an HTTP endpoint that runs the [[jobs]] of the config file on request:
+    /// POST /run/<job> starts a job, POST /upload/<job> stores the Health Connect export in
+    /// the body as the job's source first, GET /status shows how the jobs went
+    Serve {
+        /// Address to listen at
+        #[arg(
+            long,
+            value_name = "ADDR",
+            default_value = "127.0.0.1:8089",
+            env = "HDI_LISTEN"
+        )]
+        listen: String,
+
+        /// Token requests must send in an "Authorization: Bearer <TOKEN>" header
+        #[arg(long, value_name = "TOKEN", env = "HDI_SERVE_TOKEN")]
+        serve_token: Option<String>,
+
+        /// Largest export accepted by /upload/<job>, in megabytes
+        #[arg(long, value_name = "MB", default_value_t = DEFAULT_MAX_UPLOAD_MB, env = "HDI_MAX_UPLOAD_MB")]
+        max_upload_mb: u64,
+
+        /// Only serve these jobs (comma-separated names); serves every job when omitted
+        #[arg(long, value_delimiter = ',', env = "HDI_JOB")]
+        job: Vec<String>,
+    },
+
+    /// Run the [[jobs]] of the config file once and summarize the results
+    RunAll {
+        /// Only run these jobs (comma-separated names); runs every job when omitted
+        #[arg(long, value_delimiter = ',', env = "HDI_JOB")]
+        job: Vec<String>,
+
+        /// Run the jobs at the same time instead of one after another
+        #[arg(long, env = "HDI_PARALLEL")]
+        parallel: bool,
+    },
+
+    /// Print the header of a funds CSV file whose columns write to the series of an
+    /// existing measurement, with the [funds] config section that imports it
+    TemplateFromMeasurement(TemplateFromMeasurementArgs),
+
     /// Generate a template configuration file
-    Init {
-        /// Output file for the configuration
-        #[arg(short, long, default_value = "influx-import.toml")]
-        output: String,
+    Init(InitArgs),
+
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
     },
 }
 
+/// Adds the settings of the --config file to the command line arguments, skipping every
+/// argument that was given on the command line or through its HDI_* environment variable,
+/// so that both override the config file
+fn apply_config(args: Vec<String>) -> Vec<String> {
+    // Parse leniently first: required arguments may only be in the config file
+    let command = Cli::command().ignore_errors(true);
+    let matches = match command.clone().try_get_matches_from(&args) {
+        Ok(matches) => matches,
+        // Let the real parse report the problem
+        Err(_) => return args,
+    };
+    let Some(config_file) = matches.get_one::<String>("config") else {
+        return args;
+    };
+    let config = match Config::load(config_file) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+
+    // Settings are looked up for the innermost subcommand (e.g., "state reset")
+    let mut names = Vec::new();
+    let mut sub_command = &command;
+    let mut sub_matches = &matches;
+    while let Some((name, next_matches)) = sub_matches.subcommand() {
+        let Some(next_command) = sub_command.find_subcommand(name) else {
+            return args;
+        };
+        names.push(name);
+        sub_command = next_command;
+        sub_matches = next_matches;
+    }
+
+    let mut args = args;
+    let mut applied: Vec<&str> = Vec::new();
+    for (name, value) in config.settings_for(&names.join(" ")) {
+        let Some(long) = sub_command
+            .get_arguments()
+            .find(|arg| arg.get_id() == name)
+            .and_then(|arg| arg.get_long())
+        else {
+            continue;
+        };
+        // --bucket and --database select the same thing, so they are applied as one setting
+        let group: &[&str] = match name {
+            "bucket" | "database" => &["bucket", "database"],
+            _ => &[name],
+        };
+        if group.iter().any(|name| {
+            applied.contains(name)
+                || matches!(
+                    sub_matches.value_source(name),
+                    Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+                )
+        }) {
+            continue;
+        }
+        applied.push(name);
+        args.push(format!("--{}", long));
+        args.push(value);
+    }
+    args
+}
+
 #[tokio::main]
 async fn main() {
-    let cli = Cli::parse();
+    // Usage errors exit with EXIT_ERROR rather than clap's 2, which means "nothing new" here
+    let cli = match Cli::try_parse_from(apply_config(std::env::args().collect())) {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            process::exit(if e.use_stderr() { EXIT_ERROR } else { 0 });
+        }
+    };
+    output::set_quiet(cli.quiet || cli.progress_format == ProgressFormat::Json);
+    match cli.progress_format {
+        ProgressFormat::Json => report::set_reporter(Box::new(JsonReporter::stdout())),
+        ProgressFormat::Console if cli.quiet => report::set_reporter(Box::new(SilentReporter)),
+        ProgressFormat::Console => report::set_reporter(Box::new(ConsoleReporter)),
+    }
+    output::init_color(cli.no_color);
+    if let Some(path) = &cli.audit_log {
+        let run_id = cli.run_id.clone().unwrap_or_else(audit::new_run_id);
+        audit::set_audit_log(AuditLog::new(path, &run_id));
+    }
 
     match cli.command {
-        Commands::ImportFunds {
-            source,
-            url,
-            org,
-            bucket,
-            token,
-            time_column,
-            time_format,
-            measurement,
-            header_rows,
-            dry_run,
-            state_file,
-            force_all,
-        } => {
-            println!("Importing funds data from '{}' into InfluxDB", source);
-            println!("  URL: {}", url);
-            println!("  Organization: {}", org);
-            println!("  Bucket: {}", bucket);
-            println!("  Measurement: {}", measurement);
-            println!("  Time column: {} (format: {})", time_column, time_format);
-            println!("  Header rows: {}", header_rows);
-            println!("  Dry-run mode: {}", if dry_run { "ON" } else { "OFF" });
-            println!("  State file: {}", state_file);
-
-            // Load the import state
-            let mut import_state = load_import_state(&state_file, &source);
-
-            if force_all {
-                println!("Force import all records (--force-all flag is set)");
-                import_state.last_imported_timestamp = None;
-            } else if let Some(timestamp) = import_state.last_imported_timestamp {
-                println!("Skipping records before: {}", timestamp);
-                println!(
-                    "Previously imported: {} records",
-                    import_state.records_imported
+        Commands::ImportFunds(args) => {
+            if let Some(interval) = args.watch {
+                jobs::watch_source(
+                    &args.source,
+                    interval,
+                    cli.metrics_addr.as_deref(),
+                    cli.metrics_file.as_deref(),
                 );
-            } else {
-                println!("No previous import state found, importing all records");
-            }
-
-            // Create parser with the specified header rows
-            let parser = CsvParser::new(&source).with_header_rows(header_rows);
-
-            // Parse the CSV data
-            match parser.parse() {
-                Ok(records) => {
-                    println!("Successfully parsed {} records", records.len());
-
-                    // Filter records based on timestamp
-                    let filtered_records = if let Some(last_ts) =
-                        import_state.last_imported_timestamp
-                    {
-                        let filtered = records
-                            .iter()
-                            .filter(|record| {
-                                // Only include records with timestamp greater than last imported
-                                if let Some(time_idx) = record.column_indexes.get(&time_column) {
-                                    if let Some(time_value) = record.values.get(*time_idx) {
-                                        if let Ok(naive_dt) =
-                                            NaiveDateTime::parse_from_str(time_value, &time_format)
-                                        {
-                                            let record_time: DateTime<Utc> =
-                                                DateTime::from_naive_utc_and_offset(naive_dt, Utc);
-                                            return record_time > last_ts;
-                                        }
-                                    }
-                                }
-                                // If timestamp can't be parsed, include the record to be safe
-                                true
-                            })
-                            .cloned()
-                            .collect::<Vec<_>>();
-
-                        println!(
-                            "Filtered from {} to {} records (skipping previously imported)",
-                            records.len(),
-                            filtered.len()
-                        );
-                        filtered
-                    } else {
-                        records.clone()
-                    };
-
-                    if filtered_records.is_empty() {
-                        println!("No new records to import");
-                        return;
-                    }
-
-                    // Show a preview of the filtered data before importing
-                    println!(
-                        "\nPreview of data to be imported: {} records",
-                        filtered_records.len()
-                    );
-
-                    // Try to find the latest timestamp from the records we're about to import
-                    let mut latest_timestamp: Option<DateTime<Utc>> = None;
-                    for record in &filtered_records {
-                        if let Some(time_idx) = record.column_indexes.get(&time_column) {
-                            if let Some(time_value) = record.values.get(*time_idx) {
-                                if let Ok(naive_dt) =
-                                    NaiveDateTime::parse_from_str(time_value, &time_format)
-                                {
-                                    let record_time =
-                                        DateTime::from_naive_utc_and_offset(naive_dt, Utc);
-                                    if latest_timestamp.is_none()
-                                        || Some(record_time) > latest_timestamp
-                                    {
-                                        latest_timestamp = Some(record_time);
-                                    }
-                                }
-                            }
-                        }
-                    }
-
-                    if dry_run {
-                        println!("Dry-run mode enabled. No data will be written to InfluxDB.");
-
-                        // Create InfluxDB client in dry-run mode
-                        let influx_client = InfluxClient::new_dry_run(&url, &bucket, &token);
-
-                        match influx_client
-                            .write_funds_records(&filtered_records, &time_column, &time_format)
-                            .await
-                        {
-                            Ok(count) => {
-                                println!("Dry run complete: {} data points would have been sent to InfluxDB", count);
-
-                                // Update the import state but don't save it in dry run mode
-                                println!("In a real import, would update the state file with latest timestamp: {:?}", latest_timestamp);
-                            }
-                            Err(e) => {
-                                eprintln!("Error in dry-run: {}", e);
-                                process::exit(1);
-                            }
-                        }
-                    } else {
-                        // Create InfluxDB client and import the data
-                        let influx_client = InfluxClient::new(&url, &bucket, &token);
-
-                        match influx_client
-                            .write_funds_records(&filtered_records, &time_column, &time_format)
-                            .await
-                        {
-                            Ok(count) => {
-                                println!("Successfully imported {} data points to InfluxDB", count);
-
-                                // Update the import state
-                                if let Some(ts) = latest_timestamp {
-                                    import_state.last_imported_timestamp = Some(ts);
-                                    import_state.records_imported += filtered_records.len();
-
-                                    // Save the updated state
-                                    match save_import_state(&import_state, &state_file) {
-                                        Ok(_) => {
-                                            println!("Updated import state saved to {}", state_file)
-                                        }
-                                        Err(e) => eprintln!("Failed to save import state: {}", e),
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Error writing to InfluxDB: {}", e);
-                                process::exit(1);
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Error parsing CSV data: {}", e);
-                    process::exit(1);
-                }
             }
+            import::funds::run(args, cli.config.as_deref(), cli.lock_file.as_deref()).await;
         }
 
-        Commands::ImportHealthData {
-            source,
-            url,
-            bucket,
-            org,
-            token,
-            state_file,
-            force_all,
-            dry_run,
-            data_types,
-            gap_fill_heart_rate,
-        } => {
-            println!("Importing health data from SQLite database: '{}'", source);
-            println!("  URL: {}", url);
-            println!("  Organization: {}", org);
-            println!("  Bucket: {}", bucket);
-            println!("  Dry-run mode: {}", if dry_run { "ON" } else { "OFF" });
-            println!("  State file: {}", state_file);
-
-            // Parse data types filter if provided
-            let requested_data_types = if let Some(data_types_str) = data_types {
-                let types: Vec<String> = data_types_str
-                    .split(',')
-                    .map(|s| s.trim().to_string())
-                    .collect();
-                println!("  Data types filter: {:?}", types);
-                Some(types)
-            } else {
-                println!("  Data types filter: All types");
-                None
-            };
-
-            // Load the import state
-            let mut import_state = load_import_state(&state_file, &source);
-
-            if force_all {
-                println!("Force import all records (--force-all flag is set)");
-                import_state.last_imported_timestamp = None;
-            } else if let Some(timestamp) = import_state.last_imported_timestamp {
-                println!("Skipping records before: {}", timestamp);
-                println!(
-                    "Previously imported: {} records",
-                    import_state.records_imported
+        Commands::ImportHealthData(args) => {
+            if let Some(interval) = args.watch {
+                jobs::watch_source(
+                    &args.source,
+                    interval,
+                    cli.metrics_addr.as_deref(),
+                    cli.metrics_file.as_deref(),
                 );
-            } else {
-                println!("No previous import state found, importing all records");
             }
+            import::health::run(args, cli.lock_file.as_deref()).await;
+        }
 
-            // Create a HealthDataReader to read from the SQLite database
-            let reader = HealthDataReader::new(&source);
-
-            // Validate the database structure
-            match reader.validate_db() {
-                Ok(validation_info) => {
-                    println!("Database validation successful");
-                    println!("{}", validation_info);
-                }
-                Err(e) => {
-                    eprintln!("Failed to validate database: {}", e);
-                    process::exit(1);
-                }
-            }
+        Commands::Prune(args) => maintenance::prune(args).await,
 
-            // Create InfluxDB client early for gap-filling functionality
-            let influx_client = if dry_run {
-                InfluxClient::new_dry_run(&url, &bucket, &token)
-            } else {
-                InfluxClient::new(&url, &bucket, &token)
-            };
-
-            // Get health data since the last import timestamp
-            println!("Retrieving health data...");
-            let mut records_map = if let Some(_days_back) = gap_fill_heart_rate {
-                // Gap-filling mode: Only process heart rate data
-                println!("Gap-filling mode: Only importing heart rate data (assuming other data types are already synced)");
-                HashMap::new() // Start with empty map, will be populated by gap-filling
-            } else if let Some(data_types_filter) = requested_data_types {
-                // Use filtered retrieval
-                match reader.get_filtered_health_data_since(
-                    import_state.last_imported_timestamp,
-                    &data_types_filter,
-                ) {
-                    Ok(records) => records,
-                    Err(e) => {
-                        eprintln!("Error retrieving filtered health data: {}", e);
-                        process::exit(1);
-                    }
-                }
-            } else {
-                // Get all data types
-                match reader.get_all_health_data_since(import_state.last_imported_timestamp) {
-                    Ok(records) => records,
-                    Err(e) => {
-                        eprintln!("Error retrieving health data: {}", e);
-                        process::exit(1);
-                    }
-                }
-            };
-
-            // Handle heart rate gap-filling if requested
-            if let Some(days_back) = gap_fill_heart_rate {
-                println!(
-                    "\nHeart rate gap-filling enabled for the last {} days",
-                    days_back
-                );
-                println!("📋 Gap-filling mode: Only heart rate data will be imported");
-                println!("   (Other data types assumed to be already synced)");
-
-                match reader
-                    .get_heart_rate_with_gap_filling(&influx_client, days_back)
-                    .await
-                {
-                    Ok(gap_fill_records) => {
-                        if !gap_fill_records.is_empty() {
-                            println!(
-                                "✅ Adding {} gap-filled heart rate records",
-                                gap_fill_records.len()
-                            );
-                            // Add only the heart rate records with gap-filled data
-                            records_map.insert("HeartRate".to_string(), gap_fill_records);
-                        } else {
-                            println!("✅ No heart rate gaps found - all data is up to date");
-                            // Keep records_map empty since no gaps were found
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("❌ Heart rate gap-filling failed: {}", e);
-                        process::exit(1);
-                    }
-                }
-            }
+        Commands::Export(args) => maintenance::export(args).await,
 
-            // Count total records
-            let total_records: usize = records_map.values().map(|v| v.len()).sum();
+        Commands::ResumeSpool(args) => import::spool::run(args, cli.lock_file.as_deref()).await,
 
-            if total_records == 0 {
-                println!("No new health records to import");
-                return;
-            }
+        Commands::Mqtt(args) => import::mqtt::run(args, cli.config.as_deref()).await,
 
-            println!("Found {} health records to import:", total_records);
-            for (record_type, records) in &records_map {
-                println!("  - {}: {} records", record_type, records.len());
-            }
+        Commands::ImportPrices(args) => import::prices::run(args, cli.lock_file.as_deref()).await,
 
-            // Find the latest timestamp across all records
-            let mut latest_timestamp: Option<DateTime<Utc>> = None;
-            for records in records_map.values() {
-                for record in records {
-                    if latest_timestamp.is_none() || Some(record.timestamp) > latest_timestamp {
-                        latest_timestamp = Some(record.timestamp);
-                    }
-                }
-            }
+        Commands::FetchNav(args) => import::nav::run(args, cli.config.as_deref()).await,
 
-            // Write the health records to InfluxDB
-            match influx_client.write_health_records(&records_map).await {
-                Ok(count) => {
-                    let mode_prefix = if dry_run {
-                        "Would have"
-                    } else {
-                        "Successfully"
-                    };
-                    println!(
-                        "{} imported {} health data points to InfluxDB",
-                        mode_prefix, count
-                    );
-
-                    // Update and save the import state (unless in dry-run mode or gap-filling mode)
-                    if !dry_run && gap_fill_heart_rate.is_none() {
-                        if let Some(ts) = latest_timestamp {
-                            import_state.last_imported_timestamp = Some(ts);
-                            import_state.records_imported += total_records;
-
-                            // Save the updated state
-                            match save_import_state(&import_state, &state_file) {
-                                Ok(_) => {
-                                    println!("Updated import state saved to {}", state_file)
-                                }
-                                Err(e) => eprintln!("Failed to save import state: {}", e),
-                            }
-                        }
-                    } else if dry_run {
-                        println!("Dry-run mode: State file not updated");
-                        if let Some(ts) = latest_timestamp {
-                            println!("Would update last imported timestamp to: {}", ts);
-                        }
-                    } else if gap_fill_heart_rate.is_some() {
-                        println!("Gap-filling mode: State file not updated");
-                        println!("💡 Gap-filling is a maintenance operation - run normal sync first to update state");
-                        if let Some(ts) = latest_timestamp {
-                            println!("Latest gap-filled timestamp: {}", ts);
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Error writing health data to InfluxDB: {}", e);
-                    process::exit(1);
-                }
-            }
-        }
+        Commands::ValidateCSV(args) => examine::validate_csv(args),
 
-        Commands::ValidateCSV {
-            source,
-            details,
-            header_rows,
-        } => {
-            println!("Validating CSV file: '{}'", source);
-            println!("  Header rows: {}", header_rows);
-
-            // Show information about the details flag
-            if details {
-                println!("Details mode: ON - Will show all CSV records");
-            } else {
-                println!("Details mode: OFF - Use --details flag to see full CSV content");
-            }
+        Commands::Preview(args) => examine::preview(args),
 
-            // Create parser with specified number of header rows
-            let parser = CsvParser::new(&source).with_header_rows(header_rows);
-
-            match parser.validate(details) {
-                Ok(report) => {
-                    println!("{}", report);
-                }
-                Err(e) => {
-                    eprintln!("Validation error: {}", e);
-                    process::exit(1);
-                }
-            }
-        }
+        Commands::Bench(args) => examine::bench(args).await,
+
+        Commands::HealthStats(args) => examine::health_stats(args),
+
+        Commands::Extract(args) => examine::extract(args),
+
+        Commands::Compare(args) => reconcile::compare(args).await,
+
+        Commands::GapReport(args) => reconcile::gap_report(args).await,
+
+        Commands::Backfill(args) => reconcile::backfill(args, cli.lock_file.as_deref()).await,
+
+        Commands::Doctor(args) => doctor::run(args).await,
+
+        Commands::State { action } => state::run(action),
+
+        Commands::History(args) => state::history(args),
+
+        Commands::Audit(args) => state::audit(args, cli.audit_log.as_deref()),
+
+        Commands::Daemon { job } => jobs::run_daemon(
+            &Cli::command(),
+            cli.config.as_deref(),
+            &job,
+            cli.lock_file.as_deref(),
+            cli.progress_format,
+            jobs::start_metrics(cli.metrics_addr.as_deref(), cli.metrics_file.as_deref()),
+        ),
+
+        Commands::Serve {
+            listen,
+            serve_token,
+            max_upload_mb,
+            job,
+        } => jobs::run_serve(
+            &Cli::command(),
+            cli.config.as_deref(),
+            &job,
+            &listen,
+            serve_token,
+            max_upload_mb,
+            cli.lock_file.as_deref(),
+            cli.progress_format,
+            jobs::start_metrics(cli.metrics_addr.as_deref(), cli.metrics_file.as_deref()),
+        ),
+
+        Commands::RunAll { job, parallel } => jobs::run_all(
+            &Cli::command(),
+            cli.config.as_deref(),
+            &job,
+            parallel,
+            cli.lock_file.as_deref(),
+            cli.progress_format,
+        ),
+
+        Commands::TemplateFromMeasurement(args) => setup::template_from_measurement(args).await,
+
+        Commands::Init(args) => setup::init(args),
 
-        Commands::Init { output } => {
-            println!("Generating template configuration file: '{}'", output);
-            // Generate a template configuration file
+        Commands::Completions { shell } => {
+            let mut command = Cli::command();
+            let name = command.get_name().to_string();
+            clap_complete::generate(shell, &mut command, name, &mut io::stdout());
         }
     }
 
@@ -0,0 +1,259 @@
+use crate::import::{create_influx_client, resolve_database, EXIT_ERROR, EXIT_SINK_ERROR};
+use crate::influx_client::{
+    InfluxClient, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::Args;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process;
+
+/// The options of `prune`
+#[derive(Args)]
+pub struct PruneArgs {
+    /// Measurements to prune (comma-separated), e.g. HeartRate
+    #[arg(
+        short,
+        long,
+        required = true,
+        value_delimiter = ',',
+        env = "HDI_MEASUREMENT"
+    )]
+    pub measurement: Vec<String>,
+
+    /// Delete points recorded more than this many days ago
+    #[arg(long, required = true, env = "HDI_OLDER_THAN_DAYS")]
+    pub older_than_days: u32,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// Only show what would be deleted
+    #[arg(long, env = "HDI_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+}
+
+/// Deletes the points recorded before `--older-than-days` from each measurement, stopping
+/// at the first one that fails
+pub async fn prune(args: PruneArgs) {
+    let PruneArgs {
+        measurement,
+        older_than_days,
+        url,
+        org,
+        bucket,
+        database,
+        token,
+        dry_run,
+        connect_timeout,
+        request_timeout,
+    } = args;
+    if older_than_days == 0 {
+        eprintln!("--older-than-days must be at least 1");
+        process::exit(EXIT_ERROR);
+    }
+    let bucket = resolve_database(bucket, database);
+    let before = Utc::now() - chrono::Duration::days(older_than_days.into());
+    progress!(
+        "Pruning points before {} from {} ({})",
+        before.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        url,
+        bucket
+    );
+
+    let influx_client = create_influx_client(
+        InfluxClient::builder(&url, &bucket)
+            .token(&token)
+            .dry_run(dry_run),
+        &org,
+        None,
+        connect_timeout,
+        request_timeout,
+    );
+    for measurement in &measurement {
+        match influx_client.delete_before(measurement, before).await {
+            Ok(()) if !dry_run => progress!("  Pruned {}", measurement),
+            Ok(()) => {}
+            Err(e) => {
+                eprintln!("Error pruning {}: {}", measurement, e);
+                process::exit(EXIT_SINK_ERROR);
+            }
+        }
+    }
+}
+
+/// The options of `export`
+#[derive(Args)]
+pub struct ExportArgs {
+    /// The line protocol file to write
+    #[arg(long, required = true, env = "HDI_OUTPUT")]
+    pub output: String,
+
+    /// Measurements to export (comma-separated); all measurements when omitted
+    #[arg(short, long, value_delimiter = ',', env = "HDI_MEASUREMENT")]
+    pub measurement: Vec<String>,
+
+    /// First day to export (YYYY-MM-DD, UTC); exports from the start when omitted
+    #[arg(long, env = "HDI_FROM")]
+    pub from: Option<NaiveDate>,
+
+    /// Last day to export (YYYY-MM-DD, UTC); defaults to today
+    #[arg(long, env = "HDI_TO")]
+    pub to: Option<NaiveDate>,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to read from (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+}
+
+/// Writes the points of the measurements, or of every measurement in the bucket, to a line
+/// protocol file, reading 30 days at a time
+pub async fn export(args: ExportArgs) {
+    let ExportArgs {
+        output,
+        measurement,
+        from,
+        to,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        connect_timeout,
+        request_timeout,
+    } = args;
+    let to = to.unwrap_or_else(|| Utc::now().date_naive());
+    let start_time = from
+        .map(|from| from.and_time(chrono::NaiveTime::MIN).and_utc())
+        .unwrap_or(DateTime::UNIX_EPOCH);
+    let end_time = (to + chrono::Duration::days(1))
+        .and_time(chrono::NaiveTime::MIN)
+        .and_utc();
+    if start_time >= end_time {
+        eprintln!("--from {} is after --to {}", start_time.date_naive(), to);
+        process::exit(EXIT_ERROR);
+    }
+
+    let bucket = resolve_database(bucket, database);
+    let influx_client = create_influx_client(
+        InfluxClient::builder(&url, &bucket).token(&token),
+        &org,
+        retention_policy.as_deref(),
+        connect_timeout,
+        request_timeout,
+    );
+    let measurements = if measurement.is_empty() {
+        match influx_client.measurements().await {
+            Ok(measurements) => measurements,
+            Err(e) => {
+                eprintln!("Error listing measurements: {}", e);
+                process::exit(EXIT_SINK_ERROR);
+            }
+        }
+    } else {
+        measurement
+    };
+    progress!(
+        "Exporting {} measurements of {} ({}) to '{}'",
+        measurements.len(),
+        url,
+        bucket,
+        output
+    );
+
+    let mut writer = match File::create(&output) {
+        Ok(file) => BufWriter::new(file),
+        Err(e) => {
+            eprintln!("Failed to create {}: {}", output, e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+    let mut total = 0;
+    for measurement in &measurements {
+        // Read a window at a time, so a long history is never held in memory at once
+        let mut exported = 0;
+        let mut window_start = start_time;
+        while window_start < end_time {
+            let window_end = (window_start + chrono::Duration::days(30)).min(end_time);
+            let points = match influx_client
+                .read_points(measurement, window_start, window_end)
+                .await
+            {
+                Ok(points) => points,
+                Err(e) => {
+                    eprintln!("Error reading {}: {}", measurement, e);
+                    process::exit(EXIT_SINK_ERROR);
+                }
+            };
+            for point in &points {
+                if let Err(e) = writeln!(writer, "{}", point.to_line_protocol()) {
+                    eprintln!("Failed to write {}: {}", output, e);
+                    process::exit(EXIT_ERROR);
+                }
+            }
+            exported += points.len();
+            window_start = window_end;
+        }
+        progress!("  {}: {} points", measurement, exported);
+        total += exported;
+    }
+    if let Err(e) = writer.flush() {
+        eprintln!("Failed to write {}: {}", output, e);
+        process::exit(EXIT_ERROR);
+    }
+    progress!("Exported {} points to '{}'", total, output);
+}
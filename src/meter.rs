@@ -0,0 +1,302 @@
+use crate::influx_client::DataPoint;
+use crate::source::{RecordStream, Source};
+use crate::state_management::ImportState;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use csv::ReaderBuilder;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+
+/// Measurement the readings are written to, as read from the meter
+pub const READING_MEASUREMENT: &str = "meter_reading";
+
+/// Measurement the consumption of each day is written to, at midnight UTC
+pub const DAILY_CONSUMPTION_MEASUREMENT: &str = "meter_daily_consumption";
+
+/// Formats of the time column tried after the configured one
+const TIME_FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"];
+
+/// Formats of readings logged with a date only, taken at midnight
+const DATE_FORMATS: [&str; 3] = ["%Y-%m-%d", "%d.%m.%Y", "%d/%m/%Y"];
+
+/// Time columns looked for when the configured one is missing
+const TIME_COLUMNS: [&str; 4] = ["date", "time", "timestamp", "datetime"];
+
+/// What a meter showed at one moment
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeterReading {
+    pub timestamp: DateTime<Utc>,
+    pub meter: String,
+    pub value: f64,
+}
+
+/// The readings logged at one moment, with the daily consumption they complete
+#[derive(Debug, Clone)]
+pub struct MeterRecord {
+    pub timestamp: DateTime<Utc>,
+    pub points: Vec<DataPoint>,
+}
+
+/// Spreads the consumption between each pair of consecutive readings of a meter evenly over
+/// the time between them, and adds it up per meter and day (UTC). A reading lower than the
+/// one before, as after replacing the meter, gives no consumption for that interval
+///
+/// The last day of a meter only holds the consumption up to its latest reading
+pub fn daily_consumption(readings: &[MeterReading]) -> BTreeMap<(String, NaiveDate), f64> {
+    let mut days = BTreeMap::new();
+    for Interval {
+        meter,
+        start,
+        end,
+        usage,
+    } in intervals(readings)
+    {
+        let seconds = (end - start).num_seconds() as f64;
+        let mut day = start.date_naive();
+        while day <= end.date_naive() {
+            let day_start = day.and_hms_opt(0, 0, 0).unwrap().and_utc();
+            let overlap_start = start.max(day_start);
+            let overlap_end = end.min(day_start + Duration::days(1));
+            if overlap_end > overlap_start {
+                let share = (overlap_end - overlap_start).num_seconds() as f64 / seconds;
+                *days.entry((meter.clone(), day)).or_insert(0.0) += usage * share;
+            }
+            day += Duration::days(1);
+        }
+    }
+    days
+}
+
+/// What a meter counted between two consecutive readings
+struct Interval {
+    meter: String,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    usage: f64,
+}
+
+/// Pairs up consecutive readings of each meter
+fn intervals(readings: &[MeterReading]) -> Vec<Interval> {
+    let mut by_meter: BTreeMap<&str, Vec<&MeterReading>> = BTreeMap::new();
+    for reading in readings {
+        by_meter.entry(&reading.meter).or_default().push(reading);
+    }
+
+    let mut intervals = Vec::new();
+    for (meter, mut readings) in by_meter {
+        readings.sort_by_key(|reading| reading.timestamp);
+        for pair in readings.windows(2) {
+            let usage = pair[1].value - pair[0].value;
+            if pair[1].timestamp > pair[0].timestamp && usage >= 0.0 {
+                intervals.push(Interval {
+                    meter: meter.to_string(),
+                    start: pair[0].timestamp,
+                    end: pair[1].timestamp,
+                    usage,
+                });
+            }
+        }
+    }
+    intervals
+}
+
+/// Reads a log of manual meter readings, e.g. water and gas meters read off photos: a time
+/// column and a column per meter, named after it. Rows only need the meters read then
+///
+/// Each reading is written as is, along with the consumption of every day since the
+/// previous reading of its meter, interpolated between the two
+pub struct MeterReader {
+    file_path: String,
+    time_column: String,
+    time_format: String,
+}
+
+impl MeterReader {
+    /// Creates a reader for a readings log, with its time in a `date` column
+    pub fn new(file_path: &str) -> Self {
+        MeterReader {
+            file_path: file_path.to_string(),
+            time_column: "date".to_string(),
+            time_format: "%Y-%m-%d %H:%M:%S".to_string(),
+        }
+    }
+
+    /// Sets the column holding the time of each reading and its format; times are taken as
+    /// UTC. Without one, date, time, timestamp or datetime is used
+    pub fn with_time_column(mut self, column: &str, format: &str) -> Self {
+        self.time_column = column.to_string();
+        self.time_format = format.to_string();
+        self
+    }
+
+    fn parse_time(&self, text: &str) -> Option<DateTime<Utc>> {
+        let text = text.trim();
+        if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+            return Some(time.with_timezone(&Utc));
+        }
+        let time = std::iter::once(self.time_format.as_str())
+            .chain(TIME_FORMATS)
+            .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+            .or_else(|| {
+                DATE_FORMATS.iter().find_map(|format| {
+                    NaiveDate::parse_from_str(text, format)
+                        .ok()?
+                        .and_hms_opt(0, 0, 0)
+                })
+            })?;
+        Some(time.and_utc())
+    }
+
+    /// Reads every reading in the log, oldest first; rows without a valid time and empty
+    /// cells are skipped
+    pub fn readings(&self) -> Result<Vec<MeterReading>, Box<dyn Error>> {
+        let contents = fs::read_to_string(&self.file_path)
+            .map_err(|e| format!("Failed to read {}: {}", self.file_path, e))?;
+        let first_line = contents.lines().next().unwrap_or_default();
+        let semicolons = first_line.contains(';');
+        let mut reader = ReaderBuilder::new()
+            .delimiter(if semicolons { b';' } else { b',' })
+            .flexible(true)
+            .from_reader(contents.as_bytes());
+        let headers: Vec<String> = reader
+            .headers()?
+            .iter()
+            .map(|header| header.trim().to_string())
+            .collect();
+
+        let lowercase: Vec<String> = headers.iter().map(|h| h.to_lowercase()).collect();
+        let time_index = std::iter::once(self.time_column.to_lowercase())
+            .chain(TIME_COLUMNS.map(String::from))
+            .find_map(|column| lowercase.iter().position(|header| *header == column))
+            .ok_or_else(|| format!("No time column '{}' in the header", self.time_column))?;
+        // A unit after the name, as in `Water (m³)`, is not part of it
+        let meters: Vec<(usize, String)> = headers
+            .iter()
+            .enumerate()
+            .filter(|(index, header)| *index != time_index && !header.is_empty())
+            .map(|(index, header)| {
+                let name = header.split('(').next().unwrap_or(header).trim();
+                (index, name.to_string())
+            })
+            .collect();
+        if meters.is_empty() {
+            return Err("No meter columns next to the time column".into());
+        }
+
+        let mut readings = Vec::new();
+        for row in reader.records() {
+            let row = row?;
+            let Some(timestamp) = row.get(time_index).and_then(|text| self.parse_time(text)) else {
+                continue;
+            };
+            for (index, meter) in &meters {
+                let Some(text) = row.get(*index).map(str::trim) else {
+                    continue;
+                };
+                let text = if semicolons {
+                    text.replace(',', ".")
+                } else {
+                    text.to_string()
+                };
+                if let Ok(value) = text.parse() {
+                    readings.push(MeterReading {
+                        timestamp,
+                        meter: meter.clone(),
+                        value,
+                    });
+                }
+            }
+        }
+        readings.sort_by_key(|reading| reading.timestamp);
+        Ok(readings)
+    }
+
+    /// Reads the log as one record per time readings were taken, each with the readings
+    /// and the consumption of the days since the previous reading of each meter
+    pub fn records(&self) -> Result<Vec<MeterRecord>, Box<dyn Error>> {
+        let readings = self.readings()?;
+        let days = daily_consumption(&readings);
+        let mut previous: HashMap<&str, DateTime<Utc>> = HashMap::new();
+        let mut records: Vec<MeterRecord> = Vec::new();
+
+        for reading in &readings {
+            let mut points = vec![DataPoint {
+                measurement: READING_MEASUREMENT.to_string(),
+                time: reading.timestamp,
+                tags: HashMap::from([("meter".to_string(), reading.meter.clone())]),
+                field_value: reading.value,
+                text_fields: BTreeMap::new(),
+            }];
+            // Days shared with the previous interval are written again, with its share added
+            if let Some(start) = previous.insert(&reading.meter, reading.timestamp) {
+                let range = (reading.meter.clone(), start.date_naive())
+                    ..=(reading.meter.clone(), reading.timestamp.date_naive());
+                for ((meter, day), usage) in days.range(range) {
+                    points.push(DataPoint {
+                        measurement: DAILY_CONSUMPTION_MEASUREMENT.to_string(),
+                        time: day.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+                        tags: HashMap::from([("meter".to_string(), meter.clone())]),
+                        field_value: (usage * 1e6).round() / 1e6,
+                        text_fields: BTreeMap::new(),
+                    });
+                }
+            }
+
+            match records.last_mut() {
+                Some(record) if record.timestamp == reading.timestamp => {
+                    record.points.extend(points)
+                }
+                _ => records.push(MeterRecord {
+                    timestamp: reading.timestamp,
+                    points,
+                }),
+            }
+        }
+        Ok(records)
+    }
+}
+
+impl Source for MeterReader {
+    type Record = MeterRecord;
+
+    fn validate(&self) -> Result<String, Box<dyn Error>> {
+        let readings = self.readings()?;
+        let mut meters: Vec<&str> = readings.iter().map(|r| r.meter.as_str()).collect();
+        meters.sort();
+        meters.dedup();
+        Ok(format!(
+            "Meter readings: {} ({} readings; meters: {})",
+            self.file_path,
+            readings.len(),
+            meters.join(", ")
+        ))
+    }
+
+    /// Reads the readings after the last imported timestamp; their consumption is counted
+    /// from the reading before, so none is lost between runs
+    fn records_since(
+        &self,
+        watermark: &ImportState,
+    ) -> Result<RecordStream<MeterRecord>, Box<dyn Error>> {
+        let records = self.records()?;
+        let Some(last_imported) = watermark.last_imported_timestamp else {
+            return Ok(RecordStream::new(records));
+        };
+
+        let total = records.len();
+        let new_records: Vec<MeterRecord> = records
+            .into_iter()
+            .filter(|record| record.timestamp > last_imported)
+            .collect();
+        let already_imported = total - new_records.len();
+        Ok(RecordStream::new(new_records).with_already_imported(already_imported))
+    }
+
+    fn timestamp(&self, record: &MeterRecord) -> Option<DateTime<Utc>> {
+        Some(record.timestamp)
+    }
+
+    fn to_points(&self, record: &MeterRecord) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+        Ok(record.points.clone())
+    }
+}
@@ -0,0 +1,240 @@
+use crate::state_management::JournalEntry;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// How a run of an import ended
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunResult {
+    Success,
+    NothingNew,
+    Failure,
+}
+
+impl RunResult {
+    /// Gets the name of the result, as in the `result` label of the runs metric
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RunResult::Success => "success",
+            RunResult::NothingNew => "nothing_new",
+            RunResult::Failure => "failure",
+        }
+    }
+}
+
+/// Counters and gauges for the runs of one job (or watched source)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JobMetrics {
+    pub successful_runs: u64,
+    pub nothing_new_runs: u64,
+    pub failed_runs: u64,
+    pub records_imported: u64,
+    /// Errors recorded in the journal of each run, e.g. failed writes
+    pub errors: u64,
+    pub last_success: Option<DateTime<Utc>>,
+    pub last_run_duration: Option<Duration>,
+}
+
+/// Metrics about the imports run by --watch or the daemon command, in the Prometheus
+/// text format
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Metrics {
+    jobs: BTreeMap<String, JobMetrics>,
+}
+
+impl Metrics {
+    /// Adds a job with no runs yet, so that its metrics are exported from the start
+    pub fn add_job(&mut self, job: &str) {
+        self.jobs.entry(job.to_string()).or_default();
+    }
+
+    /// Gets the metrics of a job
+    pub fn job(&self, job: &str) -> Option<&JobMetrics> {
+        self.jobs.get(job)
+    }
+
+    /// Records a finished run, with the journal entry the import wrote for it, if any
+    pub fn record_run(
+        &mut self,
+        job: &str,
+        result: RunResult,
+        duration: Duration,
+        entry: Option<&JournalEntry>,
+    ) {
+        let metrics = self.jobs.entry(job.to_string()).or_default();
+        match result {
+            RunResult::Success => metrics.successful_runs += 1,
+            RunResult::NothingNew => metrics.nothing_new_runs += 1,
+            RunResult::Failure => metrics.failed_runs += 1,
+        }
+        if result != RunResult::Failure {
+            metrics.last_success = Some(Utc::now());
+        }
+        metrics.last_run_duration = Some(duration);
+
+        if let Some(entry) = entry {
+            if !entry.dry_run {
+                metrics.records_imported += entry.records.values().sum::<usize>() as u64;
+            }
+            metrics.errors += entry.errors.len() as u64;
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        let mut family = |name: &str, kind: &str, help: &str, samples: Vec<(String, String)>| {
+            output.push_str(&format!("# HELP {} {}\n", name, help));
+            output.push_str(&format!("# TYPE {} {}\n", name, kind));
+            for (labels, value) in samples {
+                output.push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+            }
+        };
+
+        let job_label = |job: &str| format!("job=\"{}\"", escape_label(job));
+        let per_job = |value: &dyn Fn(&JobMetrics) -> Option<String>| {
+            self.jobs
+                .iter()
+                .filter_map(|(job, metrics)| Some((job_label(job), value(metrics)?)))
+                .collect::<Vec<_>>()
+        };
+
+        let mut runs = Vec::new();
+        for (job, metrics) in &self.jobs {
+            for (result, count) in [
+                (RunResult::Success, metrics.successful_runs),
+                (RunResult::NothingNew, metrics.nothing_new_runs),
+                (RunResult::Failure, metrics.failed_runs),
+            ] {
+                runs.push((
+                    format!("{},result=\"{}\"", job_label(job), result.as_str()),
+                    count.to_string(),
+                ));
+            }
+        }
+        family(
+            "home_db_importer_runs_total",
+            "counter",
+            "Import runs by result",
+            runs,
+        );
+        family(
+            "home_db_importer_records_imported_total",
+            "counter",
+            "Records written to InfluxDB",
+            per_job(&|m| Some(m.records_imported.to_string())),
+        );
+        family(
+            "home_db_importer_errors_total",
+            "counter",
+            "Errors recorded by import runs, such as failed writes",
+            per_job(&|m| Some(m.errors.to_string())),
+        );
+        family(
+            "home_db_importer_last_success_timestamp_seconds",
+            "gauge",
+            "Unix time of the last run that did not fail",
+            per_job(&|m| m.last_success.map(|t| t.timestamp().to_string())),
+        );
+        family(
+            "home_db_importer_last_run_duration_seconds",
+            "gauge",
+            "Duration of the last run",
+            per_job(&|m| {
+                m.last_run_duration
+                    .map(|d| format!("{:.3}", d.as_secs_f64()))
+            }),
+        );
+        output
+    }
+
+    /// Writes the metrics to a node_exporter textfile collector file
+    /// The file is replaced in one step, so the collector never reads half of it
+    pub fn write_textfile(&self, path: &str) -> io::Result<()> {
+        let temp_path = format!("{}.tmp", path);
+        fs::write(&temp_path, self.render())?;
+        fs::rename(&temp_path, path)
+    }
+}
+
+/// Escapes a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Publishes metrics at an HTTP /metrics endpoint, to a textfile, or both
+pub struct Exporter {
+    metrics: Arc<Mutex<Metrics>>,
+    textfile: Option<String>,
+}
+
+impl Exporter {
+    /// Starts serving at http://<addr>/metrics when an address is given
+    pub fn start(addr: Option<&str>, textfile: Option<String>) -> io::Result<Self> {
+        let metrics = Arc::new(Mutex::new(Metrics::default()));
+        if let Some(addr) = addr {
+            serve(addr, metrics.clone())?;
+        }
+        let exporter = Exporter { metrics, textfile };
+        exporter.write_textfile()?;
+        Ok(exporter)
+    }
+
+    /// Updates the metrics, then rewrites the textfile if there is one
+    pub fn update(&self, update: impl FnOnce(&mut Metrics)) -> io::Result<()> {
+        if let Ok(mut metrics) = self.metrics.lock() {
+            update(&mut metrics);
+        }
+        self.write_textfile()
+    }
+
+    fn write_textfile(&self) -> io::Result<()> {
+        match (&self.textfile, self.metrics.lock()) {
+            (Some(path), Ok(metrics)) => metrics.write_textfile(path),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Serves the metrics at http://<addr>/metrics from a background thread
+fn serve(addr: &str, metrics: Arc<Mutex<Metrics>>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            let mut request_line = String::new();
+            if BufReader::new(&stream)
+                .read_line(&mut request_line)
+                .is_err()
+            {
+                continue;
+            }
+
+            let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+            let response = if path == "/metrics" {
+                let body = metrics.lock().map(|m| m.render()).unwrap_or_default();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,359 @@
+use crate::influx_client::DataPoint;
+use crate::interrupt;
+use crate::report::{self, SkipReason};
+use crate::sink::Sink;
+use chrono::{DateTime, TimeZone, Utc};
+use rumqttc::{AsyncClient, Event, Packet, QoS};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::time::Duration;
+
+/// Broker connection settings, re-exported so callers need not depend on rumqttc
+pub use rumqttc::MqttOptions;
+
+/// Default MQTT broker port
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+
+/// Default number of seconds between writes of the points received from the broker
+pub const DEFAULT_FLUSH_INTERVAL_SECS: u64 = 10;
+
+/// Time to wait before reconnecting after the connection to the broker failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// A `[[mqtt.topics]]` entry: which messages to write, and how to turn them into points
+///
+/// Templates (`measurement` and tag values) may use `{name}` for a level captured by the
+/// topic, `{topic}` for the whole topic and, in the measurement, `{field}` for the name of
+/// the payload field being written
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct TopicMapping {
+    /// Topic to subscribe to: a `{name}` level matches any value and captures it, `+` matches
+    /// any value and `#`, as the last level, matches the rest of the topic
+    pub topic: String,
+    /// Measurement the points are written to
+    pub measurement: String,
+    /// Payload fields to write, as dotted paths into a JSON object (e.g. `sensor.temperature`)
+    /// When empty, the whole payload must be a number
+    #[serde(default)]
+    pub fields: Vec<String>,
+    /// Tags added to every point, by name
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Payload field holding the time of the reading, as RFC 3339 or Unix seconds; the time
+    /// the message arrived is used when omitted
+    pub time_field: Option<String>,
+}
+
+/// A level of a topic pattern
+#[derive(Debug, Clone, PartialEq)]
+enum Level {
+    Literal(String),
+    Capture(String),
+    Any,
+    Rest,
+}
+
+/// Splits a topic pattern into levels, checking that `#` only comes last
+fn parse_pattern(pattern: &str) -> Result<Vec<Level>, String> {
+    let parts: Vec<&str> = pattern.split('/').collect();
+    let mut levels = Vec::new();
+    for (index, part) in parts.iter().enumerate() {
+        let level = match *part {
+            "#" if index + 1 == parts.len() => Level::Rest,
+            "#" => return Err(format!("'#' must be the last level of '{}'", pattern)),
+            "+" => Level::Any,
+            _ if part.starts_with('{') && part.ends_with('}') && part.len() > 2 => {
+                Level::Capture(part[1..part.len() - 1].to_string())
+            }
+            _ if part.contains(['+', '#', '{', '}']) => {
+                return Err(format!("invalid level '{}' in '{}'", part, pattern))
+            }
+            _ => Level::Literal(part.to_string()),
+        };
+        levels.push(level);
+    }
+    Ok(levels)
+}
+
+/// Replaces the `{name}` placeholders of a template with their values
+fn render(template: &str, values: &HashMap<String, String>) -> Result<String, String> {
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or_else(|| format!("unclosed '{{' in '{}'", template))?;
+        let name = &rest[start + 1..end];
+        let value = values
+            .get(name)
+            .ok_or_else(|| format!("unknown placeholder '{{{}}}' in '{}'", name, template))?;
+        rendered.push_str(&rest[..start]);
+        rendered.push_str(value);
+        rest = &rest[end + 1..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+/// Looks up a dotted path in a JSON value
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(value, |value, key| value.as_object()?.get(key))
+}
+
+/// Reads a JSON value as a number; booleans are 1 and 0, and numeric strings are parsed
+fn as_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::Bool(flag) => Some(if *flag { 1.0 } else { 0.0 }),
+        Value::String(text) => text.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Reads a JSON value as a time: RFC 3339, or Unix seconds
+fn as_time(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(text) => DateTime::parse_from_rfc3339(text)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+        Value::Number(number) => {
+            let secs = number.as_f64()?;
+            Utc.timestamp_millis_opt((secs * 1000.0) as i64).single()
+        }
+        _ => None,
+    }
+}
+
+impl TopicMapping {
+    /// Checks the topic pattern, and that the templates only use placeholders it provides
+    pub fn validate(&self) -> Result<(), String> {
+        let levels = parse_pattern(&self.topic)?;
+        let mut values: HashMap<String, String> = levels
+            .iter()
+            .filter_map(|level| match level {
+                Level::Capture(name) => Some((name.clone(), String::new())),
+                _ => None,
+            })
+            .collect();
+        values.insert("topic".to_string(), String::new());
+
+        for (name, template) in &self.tags {
+            render(template, &values).map_err(|e| format!("tag '{}': {}", name, e))?;
+        }
+        values.insert("field".to_string(), String::new());
+        render(&self.measurement, &values).map_err(|e| format!("measurement: {}", e))?;
+        Ok(())
+    }
+
+    /// Gets the filter to subscribe to, with captures replaced by `+`
+    pub fn filter(&self) -> String {
+        self.topic
+            .split('/')
+            .map(|part| if part.starts_with('{') { "+" } else { part })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Matches a topic against the pattern, returning the captured levels and the topic
+    /// itself by name, or None when it does not match
+    pub fn captures(&self, topic: &str) -> Option<HashMap<String, String>> {
+        let levels = parse_pattern(&self.topic).ok()?;
+        let parts: Vec<&str> = topic.split('/').collect();
+        let mut captures = HashMap::from([("topic".to_string(), topic.to_string())]);
+        for (index, level) in levels.iter().enumerate() {
+            match level {
+                Level::Rest => return Some(captures),
+                _ if index >= parts.len() => return None,
+                Level::Literal(literal) if literal != parts[index] => return None,
+                Level::Capture(name) => {
+                    captures.insert(name.clone(), parts[index].to_string());
+                }
+                _ => {}
+            }
+        }
+        (levels.len() == parts.len()).then_some(captures)
+    }
+
+    /// Converts a message on a matching topic to points, one per payload field present
+    pub fn to_points(
+        &self,
+        topic: &str,
+        payload: &[u8],
+        received: DateTime<Utc>,
+    ) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+        let mut values = self
+            .captures(topic)
+            .ok_or_else(|| format!("'{}' does not match '{}'", topic, self.topic))?;
+        let payload: Value = serde_json::from_slice(payload)
+            .map_err(|e| format!("payload is not JSON or a number: {}", e))?;
+
+        let time = match &self.time_field {
+            Some(field) => lookup(&payload, field)
+                .and_then(as_time)
+                .ok_or_else(|| format!("no valid time in payload field '{}'", field))?,
+            None => received,
+        };
+        let mut tags = HashMap::new();
+        for (name, template) in &self.tags {
+            tags.insert(name.clone(), render(template, &values)?);
+        }
+
+        let mut points = Vec::new();
+        if self.fields.is_empty() {
+            let value = as_number(&payload).ok_or("payload is not a number")?;
+            values.insert("field".to_string(), "value".to_string());
+            points.push(DataPoint {
+                measurement: render(&self.measurement, &values)?,
+                time,
+                tags,
+                field_value: value,
+                text_fields: BTreeMap::new(),
+            });
+            return Ok(points);
+        }
+
+        // Sensors often leave out fields they have nothing new for
+        for field in &self.fields {
+            let Some(value) = lookup(&payload, field).and_then(as_number) else {
+                continue;
+            };
+            let name = field.rsplit('.').next().unwrap_or(field);
+            values.insert("field".to_string(), name.to_string());
+            points.push(DataPoint {
+                measurement: render(&self.measurement, &values)?,
+                time,
+                tags: tags.clone(),
+                field_value: value,
+                text_fields: BTreeMap::new(),
+            });
+        }
+        if points.is_empty() {
+            return Err(
+                format!("payload has none of the fields {}", self.fields.join(", ")).into(),
+            );
+        }
+        Ok(points)
+    }
+}
+
+/// Converts a message with the first mapping whose topic matches; no points when none does
+pub fn message_to_points(
+    mappings: &[TopicMapping],
+    topic: &str,
+    payload: &[u8],
+    received: DateTime<Utc>,
+) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+    match mappings
+        .iter()
+        .find(|mapping| mapping.captures(topic).is_some())
+    {
+        Some(mapping) => mapping.to_points(topic, payload, received),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// What a subscription received and wrote
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct MqttSummary {
+    /// Messages received on the subscribed topics
+    pub messages: usize,
+    /// Messages that could not be converted to points
+    pub failed_messages: usize,
+    /// Points written, or spooled when InfluxDB could not take them
+    pub points: usize,
+}
+
+/// Subscribes to the topics of `mappings` and writes the points of every message to
+/// `sink`, once a batch is full or every `flush_interval`, until an interrupt is requested
+/// A lost connection to the broker is retried for as long as it runs
+pub async fn subscribe<K: Sink>(
+    options: MqttOptions,
+    mappings: &[TopicMapping],
+    sink: &K,
+    flush_interval: Duration,
+) -> Result<MqttSummary, Box<dyn Error>> {
+    let mut filters: Vec<String> = mappings.iter().map(TopicMapping::filter).collect();
+    filters.sort();
+    filters.dedup();
+
+    let (client, mut eventloop) = AsyncClient::new(options, 100);
+    let mut ticker = tokio::time::interval(flush_interval);
+    let mut summary = MqttSummary::default();
+    let mut buffer: Vec<DataPoint> = Vec::new();
+
+    while !interrupt::requested() {
+        tokio::select! {
+            event = eventloop.poll() => match event {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    // Subscriptions do not survive a reconnect with a clean session
+                    progress!("Connected to the MQTT broker; subscribing to {}", filters.join(", "));
+                    for filter in &filters {
+                        client.try_subscribe(filter.as_str(), QoS::AtLeastOnce)?;
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    summary.messages += 1;
+                    match message_to_points(mappings, &publish.topic, &publish.payload, Utc::now()) {
+                        Ok(points) => buffer.extend(points),
+                        Err(e) => {
+                            summary.failed_messages += 1;
+                            let reason = SkipReason::ConversionFailed(format!("{}: {}", publish.topic, e));
+                            report::reporter().records_skipped(1, &reason);
+                        }
+                    }
+                    if buffer.len() >= sink.batch_size() {
+                        flush(sink, &mut buffer, &mut summary).await;
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    eprintln!(
+                        "MQTT connection failed ({}); reconnecting in {}s",
+                        e,
+                        RECONNECT_DELAY.as_secs()
+                    );
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            },
+            _ = ticker.tick() => flush(sink, &mut buffer, &mut summary).await,
+        }
+    }
+
+    flush(sink, &mut buffer, &mut summary).await;
+    let _ = client.try_disconnect();
+    Ok(summary)
+}
+
+/// Writes the buffered points, spooling them when the write fails; points that cannot be
+/// spooled either are reported as lost, since the subscription keeps going
+async fn flush<K: Sink>(sink: &K, buffer: &mut Vec<DataPoint>, summary: &mut MqttSummary) {
+    if buffer.is_empty() {
+        return;
+    }
+
+    // A write stops at once when an interrupt was requested, so spool what is left
+    let written = if interrupt::requested() {
+        Err("the subscription is stopping".into())
+    } else {
+        sink.write_points(buffer).await
+    };
+    let result = match written {
+        Ok(()) => Ok(()),
+        Err(e) => sink
+            .spool_points(buffer)
+            .map_err(|spool_error| format!("{}; {}", e, spool_error)),
+    };
+    match result {
+        Ok(()) => {
+            summary.points += buffer.len();
+            report::reporter().batch_written(buffer.len(), summary.points);
+        }
+        Err(e) => eprintln!("Lost {} points: {}", buffer.len(), e),
+    }
+    buffer.clear();
+}
@@ -0,0 +1,224 @@
+use crate::convert::{fund_tag, parse_fund_value, DEFAULT_FUND_TAG_KEY};
+use crate::influx_client::DataPoint;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::time::Duration;
+
+/// Measurement NAVs are written to unless configured otherwise, as the second header row of
+/// a funds export usually names it
+pub const DEFAULT_NAV_MEASUREMENT: &str = "price";
+
+/// A `[[nav.funds]]` entry: a fund whose NAV is fetched
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NavFund {
+    /// ISIN of the fund, put in the URL template's `{isin}`
+    pub isin: String,
+    /// Name written to the `fondo` tag, as in the first header row of a funds export; the
+    /// ISIN when omitted
+    pub name: Option<String>,
+    /// Currency of the NAV, for the `[fx]` conversion; the base currency when omitted
+    pub currency: Option<String>,
+}
+
+impl NavFund {
+    /// Gets the `fondo` tag of the fund's points
+    pub fn tag(&self) -> String {
+        fund_tag(self.name.as_deref().unwrap_or(&self.isin))
+    }
+}
+
+/// Looks up a JSONPath in a JSON value. Only the subset price APIs need is supported: the
+/// root `$`, `.key` or `['key']` members and `[0]` array indexes, e.g. `$.data[0].nav`
+pub fn json_path<'a>(value: &'a Value, path: &str) -> Result<Option<&'a Value>, String> {
+    let mut rest = path.trim().strip_prefix('$').unwrap_or(path.trim());
+    let mut value = value;
+    while !rest.is_empty() {
+        let step;
+        if let Some(after) = rest.strip_prefix("['") {
+            let end = after
+                .find("']")
+                .ok_or_else(|| format!("unclosed [' in JSONPath '{}'", path))?;
+            step = value.get(&after[..end]);
+            rest = &after[end + 2..];
+        } else if let Some(after) = rest.strip_prefix('[') {
+            let end = after
+                .find(']')
+                .ok_or_else(|| format!("unclosed [ in JSONPath '{}'", path))?;
+            let index: usize = after[..end]
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid index '{}' in JSONPath '{}'", &after[..end], path))?;
+            step = value.get(index);
+            rest = &after[end + 1..];
+        } else {
+            // The first member may come without the dot, as in `data.nav`
+            let after = rest.strip_prefix('.').unwrap_or(rest);
+            let end = after.find(['.', '[']).unwrap_or(after.len());
+            if end == 0 {
+                return Err(format!("empty member in JSONPath '{}'", path));
+            }
+            step = value.get(&after[..end]);
+            rest = &after[end..];
+        }
+        match step {
+            Some(next) => value = next,
+            None => return Ok(None),
+        }
+    }
+    Ok(Some(value))
+}
+
+/// Reads a JSON value as a NAV: a number, or a string as funds exports write values
+fn as_price(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => parse_fund_value(text.trim()),
+        _ => None,
+    }
+}
+
+/// Reads a JSON value as the date of a NAV: RFC 3339, a `YYYY-MM-DD` date (at midnight UTC),
+/// or Unix seconds or milliseconds
+fn as_time(value: &Value) -> Option<DateTime<Utc>> {
+    match value {
+        Value::String(text) => {
+            let text = text.trim();
+            DateTime::parse_from_rfc3339(text)
+                .map(|time| time.with_timezone(&Utc))
+                .ok()
+                .or_else(|| {
+                    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+                    Some(date.and_hms_opt(0, 0, 0)?.and_utc())
+                })
+        }
+        Value::Number(number) => {
+            let number = number.as_i64()?;
+            // Seconds run out of ten digits in 2286
+            if number.abs() < 10_000_000_000 {
+                Utc.timestamp_opt(number, 0).single()
+            } else {
+                Utc.timestamp_millis_opt(number).single()
+            }
+        }
+        _ => None,
+    }
+}
+
+/// A JSON HTTP API serving the NAV of one fund per request
+#[derive(Debug, Clone, PartialEq)]
+pub struct NavApi {
+    /// URL of a fund's NAV, with `{isin}` where its ISIN goes
+    pub url_template: String,
+    /// JSONPath of the NAV in the response
+    pub price_path: String,
+    /// JSONPath of the NAV's date; without one, NAVs are written at midnight UTC of the day
+    /// they were fetched
+    pub time_path: Option<String>,
+    /// Measurement the NAVs are written to
+    pub measurement: String,
+    /// Key of the tag the fund of each NAV is written to
+    pub tag_key: String,
+}
+
+impl NavApi {
+    /// Creates an API reading the NAV at `price_path` from `url_template`
+    pub fn new(url_template: &str, price_path: &str) -> Self {
+        NavApi {
+            url_template: url_template.to_string(),
+            price_path: price_path.to_string(),
+            time_path: None,
+            measurement: DEFAULT_NAV_MEASUREMENT.to_string(),
+            tag_key: DEFAULT_FUND_TAG_KEY.to_string(),
+        }
+    }
+
+    /// Sets the JSONPath of the NAV's date
+    pub fn with_time_path(mut self, time_path: &str) -> Self {
+        self.time_path = Some(time_path.to_string());
+        self
+    }
+
+    /// Sets the measurement the NAVs are written to
+    pub fn with_measurement(mut self, measurement: &str) -> Self {
+        self.measurement = measurement.to_string();
+        self
+    }
+
+    /// Sets the key of the tag the fund of each NAV is written to
+    pub fn with_tag_key(mut self, tag_key: &str) -> Self {
+        self.tag_key = tag_key.to_string();
+        self
+    }
+
+    /// Gets the URL of a fund's NAV
+    pub fn url_for(&self, fund: &NavFund) -> String {
+        self.url_template.replace("{isin}", &fund.isin)
+    }
+
+    /// Converts a response for a fund to its point; `fetched` is when it was received
+    pub fn to_point(
+        &self,
+        fund: &NavFund,
+        body: &str,
+        fetched: DateTime<Utc>,
+    ) -> Result<DataPoint, Box<dyn Error>> {
+        let response: Value =
+            serde_json::from_str(body).map_err(|e| format!("response is not JSON: {}", e))?;
+        let price = json_path(&response, &self.price_path)?
+            .and_then(as_price)
+            .ok_or_else(|| format!("no NAV at '{}'", self.price_path))?;
+        let time = match &self.time_path {
+            Some(path) => json_path(&response, path)?
+                .and_then(as_time)
+                .ok_or_else(|| format!("no valid date at '{}'", path))?,
+            // Polling more than once a day rewrites the same point
+            None => fetched.date_naive().and_hms_opt(0, 0, 0).unwrap().and_utc(),
+        };
+        Ok(DataPoint {
+            measurement: self.measurement.clone(),
+            time,
+            tags: HashMap::from([(self.tag_key.clone(), fund.tag())]),
+            field_value: price,
+            text_fields: BTreeMap::new(),
+        })
+    }
+
+    /// Fetches the NAV of every fund, returning the points of the funds fetched and the
+    /// errors of the others by ISIN; one fund failing does not stop the rest
+    pub async fn fetch(
+        &self,
+        funds: &[NavFund],
+        timeout: Option<Duration>,
+    ) -> Result<(Vec<DataPoint>, Vec<(String, String)>), Box<dyn Error>> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        let client = builder.build()?;
+
+        let mut points = Vec::new();
+        let mut errors = Vec::new();
+        for fund in funds {
+            let url = self.url_for(fund);
+            let result = async {
+                let response = client.get(&url).send().await?;
+                let status = response.status();
+                let body = response.text().await?;
+                if !status.is_success() {
+                    return Err(format!("{} answered {}", url, status).into());
+                }
+                self.to_point(fund, &body, Utc::now())
+            }
+            .await;
+            match result {
+                Ok(point) => points.push(point),
+                Err(e) => errors.push((fund.isin.clone(), e.to_string())),
+            }
+        }
+        Ok((points, errors))
+    }
+}
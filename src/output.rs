@@ -0,0 +1,122 @@
+use std::io::{self, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Silences progress output (--quiet); errors and warnings are still printed
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Checks whether progress output is silenced
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Prints progress like `println!`, unless --quiet was given
+#[macro_export]
+macro_rules! progress {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            println!($($arg)*);
+        }
+    };
+}
+
+static COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Turns colored output on when both stdout and stderr are terminals, unless it is turned
+/// off with --no-color, the NO_COLOR environment variable or TERM=dumb
+pub fn init_color(no_color: bool) {
+    let enabled = !no_color
+        && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+        && std::env::var("TERM").ok().is_none_or(|term| term != "dumb")
+        && io::stdout().is_terminal()
+        && io::stderr().is_terminal();
+    COLOR.store(enabled, Ordering::Relaxed);
+}
+
+/// Checks whether output is colored
+pub fn color_enabled() -> bool {
+    COLOR.load(Ordering::Relaxed)
+}
+
+/// Wraps text in an ANSI style when colors are on
+fn paint(style: &str, text: &str) -> String {
+    if color_enabled() {
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn bold(text: &str) -> String {
+    paint("1", text)
+}
+
+/// A status line for something that went well
+pub fn success(message: &str) -> String {
+    paint("32", &format!("✔ {}", message))
+}
+
+/// A status line for something that needs attention but did not fail
+pub fn warning(message: &str) -> String {
+    paint("33", &format!("⚠ {}", message))
+}
+
+/// A status line for something that failed
+pub fn failure(message: &str) -> String {
+    paint("31", &format!("✖ {}", message))
+}
+
+/// Number of characters a terminal shows for text, leaving out ANSI styles
+fn visible_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut in_style = false;
+    for c in text.chars() {
+        match c {
+            '\x1b' => in_style = true,
+            'm' if in_style => in_style = false,
+            _ if !in_style => width += 1,
+            _ => {}
+        }
+    }
+    width
+}
+
+/// Formats rows as a table indented by two spaces, with a bold header row
+/// Columns of numbers (e.g., counts or durations) are aligned right, others left
+pub fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|h| visible_width(h)).collect();
+    let mut numeric = vec![!rows.is_empty(); header.len()];
+    for row in rows {
+        for (column, cell) in row.iter().enumerate().take(header.len()) {
+            widths[column] = widths[column].max(visible_width(cell));
+            numeric[column] &= cell.starts_with(|c: char| c.is_ascii_digit());
+        }
+    }
+
+    let format_row = |cells: Vec<&str>| {
+        let line = cells
+            .iter()
+            .take(widths.len())
+            .enumerate()
+            .map(|(column, cell)| {
+                let padding = " ".repeat(widths[column] - visible_width(cell));
+                if numeric[column] {
+                    format!("{}{}", padding, cell)
+                } else {
+                    format!("{}{}", cell, padding)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("  ");
+        format!("  {}", line.trim_end())
+    };
+
+    let mut lines = vec![bold(&format_row(header.to_vec()))];
+    for row in rows {
+        lines.push(format_row(row.iter().map(String::as_str).collect()));
+    }
+    lines.join("\n")
+}
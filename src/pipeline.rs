@@ -0,0 +1,172 @@
+use crate::influx_client::DataPoint;
+use crate::interrupt::Interrupted;
+use crate::report::{self, Reporter, SkipReason};
+use crate::sink::Sink;
+use crate::source::Source;
+use std::error::Error;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::task;
+
+/// Default number of records, and of converted records, each stage may queue before it
+/// waits for the next one
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 1000;
+
+/// What a pipeline run read and wrote
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PipelineSummary {
+    /// Records read from the source
+    pub records: usize,
+    /// Records that could not be converted to points
+    pub failed_records: usize,
+    /// Points written, or spooled when InfluxDB could not take them
+    pub points: usize,
+}
+
+/// Moves records from a source to a sink in four stages connected by bounded channels:
+/// the source is read and its records converted on blocking threads, while the points are
+/// batched and written on the async side. Reading overlaps with the HTTP writes, and a
+/// stage that gets ahead waits once its channel is full
+pub struct Pipeline<'a, K: Sink> {
+    sink: &'a K,
+    reporter: &'a dyn Reporter,
+    capacity: usize,
+}
+
+impl<'a, K: Sink> Pipeline<'a, K> {
+    /// Creates a pipeline that writes to `sink`, in batches of the sink's batch size
+    pub fn new(sink: &'a K) -> Self {
+        Pipeline {
+            sink,
+            reporter: report::reporter(),
+            capacity: DEFAULT_CHANNEL_CAPACITY,
+        }
+    }
+
+    /// Sets how many items each stage may queue before it waits for the next one
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets the reporter told about batches written and records skipped, instead of the
+    /// one set with `report::set_reporter`
+    pub fn with_reporter(mut self, reporter: &'a dyn Reporter) -> Self {
+        self.reporter = reporter;
+        self
+    }
+
+    /// Reads `records`, converts them with `source` and writes the points, in the order of
+    /// the records, one batch after the other
+    /// Records that cannot be converted are reported and skipped. A failed write stops the
+    /// pipeline, unless the sink can spool: then the rest of the points are spooled
+    pub async fn run<S, I>(
+        &self,
+        source: Arc<S>,
+        records: I,
+    ) -> Result<PipelineSummary, Box<dyn Error>>
+    where
+        S: Source + Send + Sync + 'static,
+        I: IntoIterator<Item = S::Record> + Send + 'static,
+        I::IntoIter: Send,
+    {
+        let (record_tx, mut record_rx) = mpsc::channel::<S::Record>(self.capacity);
+        // Conversion errors travel with the points, so they are reported from the async side
+        let (point_tx, mut point_rx) =
+            mpsc::channel::<Result<Vec<DataPoint>, String>>(self.capacity);
+
+        // Source: reading SQLite or CSV blocks, so it gets a thread of its own
+        let reader = task::spawn_blocking(move || {
+            let mut read = 0;
+            for record in records {
+                // The receiver is gone when writing failed
+                if record_tx.blocking_send(record).is_err() {
+                    break;
+                }
+                read += 1;
+            }
+            read
+        });
+
+        // Transform: convert records to points
+        let converter = task::spawn_blocking(move || {
+            while let Some(record) = record_rx.blocking_recv() {
+                let converted = source.to_points(&record).map_err(|e| e.to_string());
+                if point_tx.blocking_send(converted).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Batcher and sink
+        let batch_size = self.sink.batch_size();
+        let mut batch: Vec<DataPoint> = Vec::with_capacity(batch_size);
+        let mut points = 0;
+        let mut failed_records = 0;
+        let mut result = Ok(());
+        while let Some(converted) = point_rx.recv().await {
+            match converted {
+                Ok(converted) => batch.extend(converted),
+                Err(e) => {
+                    self.reporter
+                        .records_skipped(1, &SkipReason::ConversionFailed(e));
+                    failed_records += 1;
+                }
+            }
+            // Dry runs log the points once, at the end
+            while batch.len() >= batch_size && !self.sink.is_dry_run() {
+                let rest = batch.split_off(batch_size);
+                result = self.write_batch(&batch, points).await;
+                points += batch.len();
+                if result.is_ok() {
+                    self.reporter.batch_written(batch.len(), points);
+                }
+                batch = rest;
+                if result.is_err() {
+                    break;
+                }
+            }
+            if result.is_err() {
+                break;
+            }
+        }
+        if result.is_ok() && !batch.is_empty() {
+            result = self.write_batch(&batch, points).await;
+            points += batch.len();
+            if result.is_ok() {
+                self.reporter.batch_written(batch.len(), points);
+            }
+        }
+
+        // Closing the channel stops the other stages early when writing failed
+        drop(point_rx);
+        converter.await?;
+        let records = reader.await?;
+        result?;
+
+        Ok(PipelineSummary {
+            records,
+            failed_records,
+            points,
+        })
+    }
+
+    /// Writes a batch, or spools it once the sink has spooled points already:
+    /// InfluxDB is likely still unreachable, and each attempt could take a full timeout
+    async fn write_batch(&self, batch: &[DataPoint], written: usize) -> Result<(), Box<dyn Error>> {
+        if self.sink.spooled_points() > 0 {
+            return self.sink.spool_points(batch);
+        }
+
+        match self.sink.write_points(batch).await {
+            Err(e) => match e.downcast_ref::<Interrupted>() {
+                // Count the points written by earlier batches too
+                Some(interrupted) => Err(Box::new(Interrupted {
+                    written_points: written + interrupted.written_points,
+                })),
+                None => Err(e),
+            },
+            Ok(()) => Ok(()),
+        }
+    }
+}
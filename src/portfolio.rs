@@ -0,0 +1,278 @@
+use crate::convert::{fund_tag, DEFAULT_FUND_TAG_KEY};
+use crate::influx_client::DataPoint;
+use crate::source::{RecordStream, Source};
+use crate::state_management::ImportState;
+use crate::transactions::{CostBasis, Transaction};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::error::Error;
+
+/// Measurement the value of each position is written to: quantity times NAV
+pub const VALUE_MEASUREMENT: &str = "portfolio_value";
+
+/// Measurement the return of each position since its previous NAV is written to, in percent
+pub const RETURN_MEASUREMENT: &str = "portfolio_return";
+
+/// Measurement the profit or loss of each position is written to: value minus cost
+pub const PNL_MEASUREMENT: &str = "portfolio_pnl";
+
+/// Fund tag of the metrics of the whole portfolio
+pub const TOTAL_FUND: &str = "total";
+
+/// A `[[portfolio.holdings]]` entry: how much of a fund is held
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Holding {
+    /// Name of the fund, as in the first header row of a funds export
+    pub fund: String,
+    /// Number of units held
+    pub quantity: f64,
+    /// Amount paid for the units, in the currency of the NAV; no profit or loss is written
+    /// without it
+    pub cost: Option<f64>,
+}
+
+/// Derives the performance of a portfolio from the NAVs of its funds: the value, return
+/// and profit or loss of each position, and of the whole portfolio under the `total` fund
+///
+/// NAVs are fed in time order. A fund keeps its last NAV until the next one, and totals
+/// are only written once every holding has had one
+///
+/// Holdings are fixed, or follow a list of transactions: each NAV is then valued with the
+/// units and cost basis of the transactions up to its time, so the profit or loss is the
+/// unrealized gain
+#[derive(Debug, Clone)]
+pub struct Portfolio {
+    /// Holdings by fund tag
+    holdings: BTreeMap<String, Holding>,
+    nav_measurement: String,
+    tag_key: String,
+    last_navs: HashMap<String, f64>,
+    last_total: Option<f64>,
+    /// Transactions not applied yet, oldest first
+    transactions: VecDeque<Transaction>,
+    basis: CostBasis,
+}
+
+impl Portfolio {
+    /// Creates a portfolio of `holdings`, reading their NAVs from `nav_measurement` points
+    pub fn new(holdings: &[Holding], nav_measurement: &str) -> Self {
+        Portfolio {
+            holdings: holdings
+                .iter()
+                .map(|holding| (fund_tag(&holding.fund), holding.clone()))
+                .collect(),
+            nav_measurement: nav_measurement.to_string(),
+            tag_key: DEFAULT_FUND_TAG_KEY.to_string(),
+            last_navs: HashMap::new(),
+            last_total: None,
+            transactions: VecDeque::new(),
+            basis: CostBasis::new(),
+        }
+    }
+
+    /// Sets the key of the tag funds are told apart by, in the NAVs and the metrics
+    /// (`fondo` by default)
+    pub fn with_tag_key(mut self, tag_key: &str) -> Self {
+        self.tag_key = tag_key.to_string();
+        self
+    }
+
+    /// Makes the holdings follow `transactions`, in addition to the fixed ones
+    pub fn with_transactions(mut self, mut transactions: Vec<Transaction>) -> Self {
+        transactions.sort_by_key(|transaction| transaction.timestamp);
+        self.transactions = transactions.into();
+        self
+    }
+
+    /// Applies the transactions up to `time` to the holdings
+    fn apply_transactions(&mut self, time: DateTime<Utc>) -> Result<(), String> {
+        while let Some(transaction) = self.transactions.front() {
+            if transaction.timestamp > time {
+                break;
+            }
+            let transaction = self.transactions.pop_front().unwrap();
+            self.basis.apply(&transaction)?;
+            let position = &self.basis.positions()[&transaction.fund];
+            if position.units > 0.0 {
+                self.holdings.insert(
+                    transaction.fund.clone(),
+                    Holding {
+                        fund: transaction.fund.clone(),
+                        quantity: position.units,
+                        cost: Some(position.cost),
+                    },
+                );
+            } else {
+                self.holdings.remove(&transaction.fund);
+            }
+        }
+        Ok(())
+    }
+
+    /// Derives the metrics of the NAVs among `points`, all at time `time`; fails when the
+    /// transactions up to then sell more units than were held
+    pub fn metrics(
+        &mut self,
+        points: &[DataPoint],
+        time: DateTime<Utc>,
+    ) -> Result<Vec<DataPoint>, String> {
+        self.apply_transactions(time)?;
+        let metric = |measurement: &str, fund: &str, value: f64| DataPoint {
+            measurement: measurement.to_string(),
+            time,
+            tags: HashMap::from([(self.tag_key.clone(), fund.to_string())]),
+            // Percentages and amounts with more digits only show float noise
+            field_value: (value * 1e6).round() / 1e6,
+            text_fields: BTreeMap::new(),
+        };
+
+        let mut metrics = Vec::new();
+        let navs = points
+            .iter()
+            .filter(|point| point.measurement == self.nav_measurement)
+            .filter_map(|point| Some((point.tags.get(&self.tag_key)?, point.field_value)));
+        for (fund, nav) in navs {
+            // Funds bought later need their NAV before
+            let previous = self.last_navs.insert(fund.clone(), nav);
+            let Some(holding) = self.holdings.get(fund) else {
+                continue;
+            };
+            let value = holding.quantity * nav;
+            metrics.push(metric(VALUE_MEASUREMENT, fund, value));
+            if let Some(previous) = previous {
+                if previous != 0.0 {
+                    metrics.push(metric(
+                        RETURN_MEASUREMENT,
+                        fund,
+                        (nav / previous - 1.0) * 100.0,
+                    ));
+                }
+            }
+            if let Some(cost) = holding.cost {
+                metrics.push(metric(PNL_MEASUREMENT, fund, value - cost));
+            }
+        }
+        if metrics.is_empty()
+            || !self
+                .holdings
+                .keys()
+                .all(|fund| self.last_navs.contains_key(fund))
+        {
+            return Ok(metrics);
+        }
+
+        let total: f64 = self
+            .holdings
+            .iter()
+            .map(|(fund, holding)| holding.quantity * self.last_navs[fund])
+            .sum();
+        metrics.push(metric(VALUE_MEASUREMENT, TOTAL_FUND, total));
+        if let Some(previous) = self.last_total.replace(total) {
+            if previous != 0.0 {
+                metrics.push(metric(
+                    RETURN_MEASUREMENT,
+                    TOTAL_FUND,
+                    (total / previous - 1.0) * 100.0,
+                ));
+            }
+        }
+        let costs: Option<f64> = self.holdings.values().map(|holding| holding.cost).sum();
+        if let Some(cost) = costs {
+            metrics.push(metric(PNL_MEASUREMENT, TOTAL_FUND, total - cost));
+        }
+        Ok(metrics)
+    }
+}
+
+/// A record of a source with the portfolio metrics derived up to it
+#[derive(Debug, Clone)]
+pub struct PortfolioRecord {
+    pub timestamp: Option<DateTime<Utc>>,
+    /// The record's points followed by the metrics, or why it could not be converted
+    pub points: Result<Vec<DataPoint>, String>,
+}
+
+/// Adds the metrics of a [`Portfolio`] to the points of a funds source
+///
+/// Returns are counted from the NAV before, so the whole source is read every time and
+/// only the metrics of the records after the watermark are written
+pub struct PortfolioSource<S> {
+    source: S,
+    portfolio: Portfolio,
+}
+
+impl<S: Source> PortfolioSource<S> {
+    /// Wraps `source`, deriving the metrics of `portfolio`
+    pub fn new(source: S, portfolio: Portfolio) -> Self {
+        PortfolioSource { source, portfolio }
+    }
+
+    /// Reads every record of the source, oldest first, with its metrics
+    pub fn records(&self) -> Result<Vec<PortfolioRecord>, Box<dyn Error>> {
+        let mut records: Vec<S::Record> =
+            self.source.records_since(&ImportState::new(""))?.collect();
+        records.sort_by_key(|record| self.source.timestamp(record));
+
+        let mut portfolio = self.portfolio.clone();
+        Ok(records
+            .iter()
+            .map(|record| {
+                let timestamp = self.source.timestamp(record);
+                let points = self.source.to_points(record).map_err(|e| e.to_string());
+                let points = match (points, timestamp) {
+                    (Ok(mut points), Some(time)) => {
+                        portfolio.metrics(&points, time).map(|metrics| {
+                            points.extend(metrics);
+                            points
+                        })
+                    }
+                    (points, _) => points,
+                };
+                PortfolioRecord { timestamp, points }
+            })
+            .collect())
+    }
+}
+
+impl<S: Source> Source for PortfolioSource<S> {
+    type Record = PortfolioRecord;
+
+    fn validate(&self) -> Result<String, Box<dyn Error>> {
+        Ok(format!(
+            "{} (with the metrics of {} holdings and {} transactions)",
+            self.source.validate()?,
+            self.portfolio.holdings.len(),
+            self.portfolio.transactions.len()
+        ))
+    }
+
+    /// Reads the records after the last imported timestamp; records whose timestamp
+    /// cannot be parsed are included to be safe, as the source does
+    fn records_since(
+        &self,
+        watermark: &ImportState,
+    ) -> Result<RecordStream<PortfolioRecord>, Box<dyn Error>> {
+        let records = self.records()?;
+        let Some(last_imported) = watermark.last_imported_timestamp else {
+            return Ok(RecordStream::new(records));
+        };
+
+        let total = records.len();
+        let new_records: Vec<PortfolioRecord> = records
+            .into_iter()
+            .filter(|record| record.timestamp.is_none_or(|time| time > last_imported))
+            .collect();
+        let already_imported = total - new_records.len();
+        Ok(RecordStream::new(new_records).with_already_imported(already_imported))
+    }
+
+    fn timestamp(&self, record: &PortfolioRecord) -> Option<DateTime<Utc>> {
+        record.timestamp
+    }
+
+    fn to_points(&self, record: &PortfolioRecord) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+        record.points.clone().map_err(|e| e.into())
+    }
+}
@@ -0,0 +1,310 @@
+use crate::influx_client::DataPoint;
+use crate::source::{RecordStream, Source};
+use crate::state_management::ImportState;
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use csv::ReaderBuilder;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+use std::time::Duration;
+
+/// Measurement electricity prices are written to
+pub const PRICE_MEASUREMENT: &str = "power_price";
+
+/// Unit of prices whose export does not say otherwise
+pub const DEFAULT_PRICE_UNIT: &str = "EUR/MWh";
+
+/// The day-ahead price of electricity in a bidding zone, from the start of a market period
+#[derive(Debug, Clone, PartialEq)]
+pub struct Price {
+    pub start: DateTime<Utc>,
+    /// Bidding zone, e.g. `DE-LU` or `NO1`
+    pub zone: String,
+    pub price: f64,
+    /// Currency per energy unit, e.g. `EUR/MWh`
+    pub unit: String,
+}
+
+impl Price {
+    /// Converts the price to a point, tagged with its bidding zone and unit
+    pub fn to_point(&self) -> DataPoint {
+        DataPoint {
+            measurement: PRICE_MEASUREMENT.to_string(),
+            time: self.start,
+            tags: HashMap::from([
+                ("zone".to_string(), self.zone.clone()),
+                ("unit".to_string(), self.unit.clone()),
+            ]),
+            field_value: self.price,
+            text_fields: BTreeMap::new(),
+        }
+    }
+}
+
+/// Gets aWATTar's market data endpoint for a bidding zone: it publishes AT and DE-LU
+pub fn awattar_url(zone: &str) -> Result<&'static str, String> {
+    match zone.to_uppercase().as_str() {
+        "AT" => Ok("https://api.awattar.at/v1/marketdata"),
+        "DE" | "DE-LU" => Ok("https://api.awattar.de/v1/marketdata"),
+        _ => Err(format!(
+            "aWATTar has no prices for bidding zone '{}': use AT or DE-LU, or import a CSV export",
+            zone
+        )),
+    }
+}
+
+#[derive(Deserialize)]
+struct AwattarResponse {
+    data: Vec<AwattarPrice>,
+}
+
+#[derive(Deserialize)]
+struct AwattarPrice {
+    start_timestamp: i64,
+    marketprice: f64,
+    unit: String,
+}
+
+/// Parses a response of aWATTar's market data API
+pub fn parse_awattar(body: &str, zone: &str) -> Result<Vec<Price>, Box<dyn Error>> {
+    let response: AwattarResponse =
+        serde_json::from_str(body).map_err(|e| format!("Unexpected aWATTar response: {}", e))?;
+    response
+        .data
+        .into_iter()
+        .map(|price| {
+            let start = Utc
+                .timestamp_millis_opt(price.start_timestamp)
+                .single()
+                .ok_or_else(|| format!("Invalid start_timestamp {}", price.start_timestamp))?;
+            Ok(Price {
+                start,
+                zone: zone.to_string(),
+                price: price.marketprice,
+                // aWATTar writes "Eur/MWh"
+                unit: price.unit.replace("Eur", "EUR"),
+            })
+        })
+        .collect()
+}
+
+/// Day-ahead prices read from a CSV export or fetched from a price API, ready to import
+///
+/// Prices are only published for the next day, so the series is read up front and filtered
+/// by the watermark like a file
+#[derive(Debug, Clone)]
+pub struct PriceSeries {
+    origin: String,
+    prices: Vec<Price>,
+}
+
+impl PriceSeries {
+    /// Creates a series from prices read elsewhere; `origin` describes where they came from
+    pub fn new(origin: &str, mut prices: Vec<Price>) -> Self {
+        prices.sort_by_key(|price| price.start);
+        PriceSeries {
+            origin: origin.to_string(),
+            prices,
+        }
+    }
+
+    /// Fetches the prices of a bidding zone starting from `start` until `end` from aWATTar,
+    /// or from `api_url` when given
+    pub async fn fetch_awattar(
+        zone: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        api_url: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let url = match api_url {
+            Some(url) => url,
+            None => awattar_url(zone)?,
+        };
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = timeout {
+            builder = builder.timeout(timeout);
+        }
+        let response = builder
+            .build()?
+            .get(url)
+            .query(&[
+                ("start", start.timestamp_millis()),
+                ("end", end.timestamp_millis()),
+            ])
+            .send()
+            .await
+            .map_err(|e| format!("Failed to fetch prices from {}: {}", url, e))?;
+        let status = response.status();
+        let body = response.text().await?;
+        if !status.is_success() {
+            return Err(format!("{} answered {}: {}", url, status, body.trim()).into());
+        }
+        Ok(Self::new(url, parse_awattar(&body, zone)?))
+    }
+
+    /// Reads a CSV export with a time column (`start`, `time` or `timestamp`, or one whose
+    /// name contains "start") and one price column per bidding zone, named after the zone
+    /// as in Nord Pool's `NO1 (EUR)`. A single `price` or `marketprice` column takes
+    /// `default_zone`, and a `unit` column sets the unit of its row
+    ///
+    /// Semicolon-separated exports may use decimal commas. Times are RFC 3339, Unix
+    /// milliseconds or `time_format` in UTC
+    pub fn from_csv(
+        path: &str,
+        default_zone: Option<&str>,
+        time_format: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let first_line = contents.lines().next().unwrap_or_default();
+        let semicolons = first_line.contains(';');
+        let mut reader = ReaderBuilder::new()
+            .delimiter(if semicolons { b';' } else { b',' })
+            .flexible(true)
+            .from_reader(contents.as_bytes());
+        let headers: Vec<String> = reader
+            .headers()?
+            .iter()
+            .map(|header| header.trim().to_string())
+            .collect();
+        let lowercase: Vec<String> = headers.iter().map(|h| h.to_lowercase()).collect();
+
+        let time_index = ["start", "start_timestamp", "time", "timestamp"]
+            .iter()
+            .find_map(|name| lowercase.iter().position(|header| header == name))
+            .or_else(|| lowercase.iter().position(|header| header.contains("start")))
+            .ok_or("No time column (start, time or timestamp) in the header")?;
+        let unit_index = lowercase.iter().position(|header| header == "unit");
+
+        let mut columns: Vec<(usize, String, String)> = Vec::new();
+        for (index, header) in headers.iter().enumerate() {
+            let name = &lowercase[index];
+            if index == time_index || Some(index) == unit_index || name.contains("end") {
+                continue;
+            }
+            let (zone, currency) = match header.split_once('(') {
+                Some((zone, currency)) => (
+                    zone.trim().to_string(),
+                    Some(currency.trim_end_matches(')').trim().to_uppercase()),
+                ),
+                None => (header.clone(), None),
+            };
+            let zone = if matches!(name.as_str(), "price" | "marketprice") {
+                default_zone
+                    .ok_or_else(|| format!("Column '{}' needs a bidding zone (--zone)", header))?
+                    .to_string()
+            } else {
+                zone
+            };
+            let unit = match currency {
+                Some(currency) => format!("{}/MWh", currency),
+                None => DEFAULT_PRICE_UNIT.to_string(),
+            };
+            columns.push((index, zone, unit));
+        }
+        if columns.is_empty() {
+            return Err("No price columns in the header".into());
+        }
+
+        let mut prices = Vec::new();
+        for row in reader.records() {
+            let row = row?;
+            let Some(start) = row
+                .get(time_index)
+                .and_then(|text| parse_time(text, time_format))
+            else {
+                continue;
+            };
+            let row_unit = unit_index
+                .and_then(|index| row.get(index))
+                .map(|unit| unit.trim().replace("Eur", "EUR"))
+                .filter(|unit| !unit.is_empty());
+            for (index, zone, unit) in &columns {
+                let Some(text) = row.get(*index) else {
+                    continue;
+                };
+                let text = if semicolons {
+                    text.trim().replace(',', ".")
+                } else {
+                    text.trim().to_string()
+                };
+                // Zones without a price for the period are left empty or written as "-"
+                let Ok(price) = text.parse::<f64>() else {
+                    continue;
+                };
+                prices.push(Price {
+                    start,
+                    zone: zone.clone(),
+                    price,
+                    unit: row_unit.clone().unwrap_or_else(|| unit.clone()),
+                });
+            }
+        }
+        Ok(Self::new(path, prices))
+    }
+
+    /// Gets the prices, oldest first
+    pub fn prices(&self) -> &[Price] {
+        &self.prices
+    }
+}
+
+/// Parses a time as RFC 3339, Unix milliseconds or `format` in UTC
+fn parse_time(text: &str, format: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Utc));
+    }
+    if let Ok(millis) = text.parse::<i64>() {
+        return Utc.timestamp_millis_opt(millis).single();
+    }
+    NaiveDateTime::parse_from_str(text, format)
+        .ok()
+        .map(|naive| naive.and_utc())
+}
+
+impl Source for PriceSeries {
+    type Record = Price;
+
+    fn validate(&self) -> Result<String, Box<dyn Error>> {
+        let zones: BTreeSet<&str> = self
+            .prices
+            .iter()
+            .map(|price| price.zone.as_str())
+            .collect();
+        Ok(format!(
+            "Day-ahead prices: {} ({} prices; zones: {})",
+            self.origin,
+            self.prices.len(),
+            zones.into_iter().collect::<Vec<_>>().join(", ")
+        ))
+    }
+
+    fn records_since(
+        &self,
+        watermark: &ImportState,
+    ) -> Result<RecordStream<Price>, Box<dyn Error>> {
+        let Some(last_imported) = watermark.last_imported_timestamp else {
+            return Ok(RecordStream::new(self.prices.clone()));
+        };
+
+        let new_prices: Vec<Price> = self
+            .prices
+            .iter()
+            .filter(|price| price.start > last_imported)
+            .cloned()
+            .collect();
+        let already_imported = self.prices.len() - new_prices.len();
+        Ok(RecordStream::new(new_prices).with_already_imported(already_imported))
+    }
+
+    fn timestamp(&self, record: &Price) -> Option<DateTime<Utc>> {
+        Some(record.start)
+    }
+
+    fn to_points(&self, record: &Price) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+        Ok(vec![record.to_point()])
+    }
+}
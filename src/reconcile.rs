@@ -0,0 +1,593 @@
+use crate::extract::ExtractFormat;
+use crate::gaps::{self, Presence};
+use crate::health_data::{
+    count_per_day, DataTypeSelector, HealthDataReader, HealthDataType, ReadFrom,
+};
+use crate::import::{
+    create_influx_client, lock_run, resolve_database, write_records, EXIT_ERROR, EXIT_INTERRUPTED,
+    EXIT_NOTHING_NEW, EXIT_SINK_ERROR, EXIT_SOURCE_ERROR,
+};
+use crate::influx_client::{
+    InfluxClient, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use crate::interrupt::{self, Interrupted};
+use crate::output;
+use crate::schedule::parse_interval;
+use chrono::{NaiveDate, Utc};
+use clap::Args;
+use std::fs::File;
+use std::io::BufWriter;
+use std::process;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The options of `compare`
+#[derive(Args)]
+pub struct CompareArgs {
+    /// The SQLite database file to compare
+    #[arg(short, long, env = "HDI_SOURCE")]
+    pub source: String,
+
+    /// Data type to compare
+    #[arg(long, ignore_case = true, env = "HDI_DATA_TYPE")]
+    pub data_type: HealthDataType,
+
+    /// First day to compare (YYYY-MM-DD, UTC); defaults to 29 days before --to
+    #[arg(long, env = "HDI_FROM")]
+    pub from: Option<NaiveDate>,
+
+    /// Last day to compare (YYYY-MM-DD, UTC); defaults to today
+    #[arg(long, env = "HDI_TO")]
+    pub to: Option<NaiveDate>,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to read from (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+}
+
+/// Prints the records of a data type per day in the export next to its points per day in
+/// InfluxDB, marking the days that differ
+pub async fn compare(args: CompareArgs) {
+    let CompareArgs {
+        source,
+        data_type,
+        from,
+        to,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        connect_timeout,
+        request_timeout,
+    } = args;
+    let to = to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = from.unwrap_or(to - chrono::Duration::days(29));
+    if from > to {
+        eprintln!("--from {} is after --to {}", from, to);
+        process::exit(EXIT_ERROR);
+    }
+    let start_time = from.and_time(chrono::NaiveTime::MIN).and_utc();
+    let end_time = (to + chrono::Duration::days(1))
+        .and_time(chrono::NaiveTime::MIN)
+        .and_utc();
+
+    let bucket = resolve_database(bucket, database);
+    println!(
+        "Comparing {} per day from {} to {} (UTC)",
+        data_type, from, to
+    );
+    println!("  SQLite: {}", source);
+    println!("  InfluxDB: {} ({})", url, bucket);
+
+    // Reads are exclusive of their starting point, so start just before the first day
+    let reader = HealthDataReader::new(&source);
+    let records_map = match reader.get_health_data_since_per_type(
+        |_| ReadFrom::Timestamp(start_time - chrono::Duration::milliseconds(1)),
+        Some(std::slice::from_ref(&data_type)),
+    ) {
+        Ok(records_map) => records_map,
+        Err(e) => {
+            eprintln!("Error retrieving health data: {}", e);
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    };
+    let records: Vec<_> = records_map
+        .get(&data_type)
+        .into_iter()
+        .flatten()
+        .filter(|record| record.timestamp >= start_time && record.timestamp < end_time)
+        .cloned()
+        .collect();
+    let source_counts = count_per_day(&records);
+
+    let influx_client = create_influx_client(
+        InfluxClient::builder(&url, &bucket).token(&token),
+        &org,
+        retention_policy.as_deref(),
+        connect_timeout,
+        request_timeout,
+    );
+    let influx_counts = match influx_client
+        .count_points_per_day(data_type.as_str(), start_time, end_time)
+        .await
+    {
+        Ok(counts) => counts,
+        Err(e) => {
+            eprintln!("Error querying InfluxDB: {}", e);
+            process::exit(EXIT_SINK_ERROR);
+        }
+    };
+
+    println!();
+    println!("{:<12} {:>10} {:>10}", "Day", "SQLite", "InfluxDB");
+    let mut days = 0;
+    let mut differing_days = 0;
+    for day in from.iter_days().take_while(|day| *day <= to) {
+        let in_source = source_counts.get(&day).copied().unwrap_or(0);
+        let in_influx = influx_counts.get(&day).copied().unwrap_or(0);
+        days += 1;
+
+        let note = if in_source > in_influx {
+            format!(
+                "  {}",
+                output::warning(&format!("{} missing", in_source - in_influx))
+            )
+        } else if in_influx > in_source {
+            format!(
+                "  {}",
+                output::warning(&format!("{} extra", in_influx - in_source))
+            )
+        } else {
+            String::new()
+        };
+        if !note.is_empty() {
+            differing_days += 1;
+        }
+        println!("{:<12} {:>10} {:>10}{}", day, in_source, in_influx, note);
+    }
+
+    println!();
+    if differing_days == 0 {
+        println!("{}", output::success(&format!("All {} days match", days)));
+    } else {
+        println!(
+            "{}",
+            output::warning(&format!("{} of {} days differ", differing_days, days))
+        );
+    }
+}
+
+/// The options of `gap-report`
+#[derive(Args)]
+pub struct GapReportArgs {
+    /// The SQLite database file to check
+    #[arg(short, long, env = "HDI_SOURCE")]
+    pub source: String,
+
+    /// Data types or groups of them to check (comma-separated); all types when omitted
+    #[arg(long, value_delimiter = ',', env = "HDI_DATA_TYPES")]
+    pub data_types: Option<Vec<DataTypeSelector>>,
+
+    /// First day to check (YYYY-MM-DD, UTC); defaults to 29 days before --to
+    #[arg(long, env = "HDI_FROM")]
+    pub from: Option<NaiveDate>,
+
+    /// Last day to check (YYYY-MM-DD, UTC); defaults to today
+    #[arg(long, env = "HDI_TO")]
+    pub to: Option<NaiveDate>,
+
+    /// Windows (e.g., 1h or 1d) whose counts are compared with InfluxDB; only the
+    /// timestamps of windows whose counts differ are compared
+    #[arg(long, value_parser = parse_interval, default_value = "1h", env = "HDI_WINDOW")]
+    pub window: Duration,
+
+    /// File the gaps are also written to
+    #[arg(long, env = "HDI_OUTPUT")]
+    pub output: Option<String>,
+
+    /// Format of --output
+    #[arg(long, value_enum, default_value_t = ExtractFormat::Csv, env = "HDI_FORMAT")]
+    pub format: ExtractFormat,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to read from (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+}
+
+/// Lists the runs of records in the export that have no point in InfluxDB, and writes them
+/// to `--output` when given
+pub async fn gap_report(args: GapReportArgs) {
+    let GapReportArgs {
+        source,
+        data_types,
+        from,
+        to,
+        window,
+        output,
+        format,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        connect_timeout,
+        request_timeout,
+    } = args;
+    let to = to.unwrap_or_else(|| Utc::now().date_naive());
+    let from = from.unwrap_or(to - chrono::Duration::days(29));
+    if from > to {
+        eprintln!("--from {} is after --to {}", from, to);
+        process::exit(EXIT_ERROR);
+    }
+    let start_time = from.and_time(chrono::NaiveTime::MIN).and_utc();
+    let end_time = (to + chrono::Duration::days(1))
+        .and_time(chrono::NaiveTime::MIN)
+        .and_utc();
+    let data_types = data_types
+        .map(|names| DataTypeSelector::expand(&names))
+        .unwrap_or_else(|| HealthDataType::ALL.to_vec());
+
+    let bucket = resolve_database(bucket, database);
+    println!("Looking for gaps from {} to {} (UTC)", from, to);
+    println!("  SQLite: {}", source);
+    println!("  InfluxDB: {} ({})", url, bucket);
+
+    // Reads are exclusive of their starting point, so start just before the first day
+    let reader = HealthDataReader::new(&source);
+    let records_map = match reader.get_health_data_since_per_type(
+        |_| ReadFrom::Timestamp(start_time - chrono::Duration::milliseconds(1)),
+        Some(&data_types),
+    ) {
+        Ok(records_map) => records_map,
+        Err(e) => {
+            eprintln!("Error retrieving health data: {}", e);
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    };
+
+    let influx_client = create_influx_client(
+        InfluxClient::builder(&url, &bucket).token(&token),
+        &org,
+        retention_policy.as_deref(),
+        connect_timeout,
+        request_timeout,
+    );
+    let mut found = Vec::new();
+    for data_type in &data_types {
+        let records: Vec<_> = records_map
+            .get(data_type)
+            .into_iter()
+            .flatten()
+            .filter(|record| record.timestamp >= start_time && record.timestamp < end_time)
+            .cloned()
+            .collect();
+        if records.is_empty() {
+            continue;
+        }
+        progress!("Checking {} {} records", records.len(), data_type);
+        let presence = match Presence::query(
+            &influx_client,
+            *data_type,
+            &records,
+            start_time,
+            end_time,
+            window,
+        )
+        .await
+        {
+            Ok(presence) => presence,
+            Err(e) => {
+                eprintln!("Error querying InfluxDB: {}", e);
+                process::exit(EXIT_SINK_ERROR);
+            }
+        };
+        found.extend(gaps::find_gaps(*data_type, &records, &presence));
+    }
+
+    println!();
+    if found.is_empty() {
+        println!("{}", output::success("No gaps found"));
+    } else {
+        let rows: Vec<Vec<String>> = found
+            .iter()
+            .map(|gap| {
+                vec![
+                    gap.data_type.to_string(),
+                    gap.start.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                    gap.end.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+                    gap.missing.to_string(),
+                ]
+            })
+            .collect();
+        println!(
+            "{}",
+            output::table(
+                &["Data type", "First missing", "Last missing", "Records"],
+                &rows
+            )
+        );
+        let missing: usize = found.iter().map(|gap| gap.missing).sum();
+        println!(
+            "{}",
+            output::warning(&format!(
+                "{} records missing in {} gaps",
+                missing,
+                found.len()
+            ))
+        );
+    }
+
+    if let Some(path) = output {
+        let written = File::create(&path)
+            .map_err(|e| e.into())
+            .and_then(|file| gaps::write_gaps(&found, format, BufWriter::new(file)));
+        match written {
+            Ok(()) => println!("Wrote {} gaps to {}", found.len(), path),
+            Err(e) => {
+                eprintln!("Failed to write {}: {}", path, e);
+                process::exit(EXIT_ERROR);
+            }
+        }
+    }
+}
+
+/// The options of `backfill`
+#[derive(Args)]
+pub struct BackfillArgs {
+    /// The SQLite database file to import from
+    #[arg(short, long, env = "HDI_SOURCE")]
+    pub source: String,
+
+    /// Data types or groups of them to backfill (comma-separated); all types when omitted
+    #[arg(long, value_delimiter = ',', env = "HDI_DATA_TYPES")]
+    pub data_types: Option<Vec<DataTypeSelector>>,
+
+    /// First day to backfill (YYYY-MM-DD, UTC)
+    #[arg(long, env = "HDI_FROM")]
+    pub from: NaiveDate,
+
+    /// Last day to backfill (YYYY-MM-DD, UTC)
+    #[arg(long, env = "HDI_TO")]
+    pub to: NaiveDate,
+
+    /// Windows (e.g., 1h or 1d) whose counts are compared with InfluxDB; only the
+    /// timestamps of windows whose counts differ are compared
+    #[arg(long, value_parser = parse_interval, default_value = "1h", env = "HDI_WINDOW")]
+    pub window: Duration,
+
+    /// Run in dry-run mode (don't write to InfluxDB, just show queries)
+    #[arg(long, env = "HDI_DRY_RUN")]
+    pub dry_run: bool,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to write to (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+}
+
+/// Writes the records of the export that have no point in InfluxDB, whatever the import
+/// state says, holding the run lock while it does
+pub async fn backfill(args: BackfillArgs, lock_file: Option<&str>) {
+    let BackfillArgs {
+        source,
+        data_types,
+        from,
+        to,
+        window,
+        dry_run,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        connect_timeout,
+        request_timeout,
+    } = args;
+    if from > to {
+        eprintln!("--from {} is after --to {}", from, to);
+        process::exit(EXIT_ERROR);
+    }
+    let start_time = from.and_time(chrono::NaiveTime::MIN).and_utc();
+    let end_time = (to + chrono::Duration::days(1))
+        .and_time(chrono::NaiveTime::MIN)
+        .and_utc();
+    let data_types = data_types
+        .map(|names| DataTypeSelector::expand(&names))
+        .unwrap_or_else(|| HealthDataType::ALL.to_vec());
+
+    let _lock = lock_run(lock_file, &format!("backfill of '{}'", source));
+    interrupt::install(EXIT_INTERRUPTED);
+
+    let bucket = resolve_database(bucket, database);
+    progress!("Backfilling health data from {} to {} (UTC)", from, to);
+    progress!("  SQLite: {}", source);
+    progress!("  InfluxDB: {} ({})", url, bucket);
+    progress!("  Dry-run mode: {}", if dry_run { "ON" } else { "OFF" });
+
+    // Reads are exclusive of their starting point, so start just before the first day
+    let reader = Arc::new(HealthDataReader::new(&source).with_data_types(Some(data_types.clone())));
+    let records_map = match reader.get_health_data_since_per_type(
+        |_| ReadFrom::Timestamp(start_time - chrono::Duration::milliseconds(1)),
+        Some(&data_types),
+    ) {
+        Ok(records_map) => records_map,
+        Err(e) => {
+            eprintln!("Error retrieving health data: {}", e);
+            process::exit(EXIT_SOURCE_ERROR);
+        }
+    };
+
+    let influx_client = create_influx_client(
+        InfluxClient::builder(&url, &bucket)
+            .token(&token)
+            .dry_run(dry_run),
+        &org,
+        retention_policy.as_deref(),
+        connect_timeout,
+        request_timeout,
+    );
+
+    // Every record is checked against InfluxDB, whatever the import state says
+    let mut missing = Vec::new();
+    let mut rows = Vec::new();
+    for data_type in &data_types {
+        let records: Vec<_> = records_map
+            .get(data_type)
+            .into_iter()
+            .flatten()
+            .filter(|record| record.timestamp >= start_time && record.timestamp < end_time)
+            .cloned()
+            .collect();
+        if records.is_empty() {
+            continue;
+        }
+        let presence = match Presence::query(
+            &influx_client,
+            *data_type,
+            &records,
+            start_time,
+            end_time,
+            window,
+        )
+        .await
+        {
+            Ok(presence) => presence,
+            Err(e) => {
+                eprintln!("Error querying InfluxDB: {}", e);
+                process::exit(EXIT_SINK_ERROR);
+            }
+        };
+        let in_range = records.len();
+        let type_missing = gaps::missing_records(records, &presence);
+        rows.push(vec![
+            data_type.to_string(),
+            in_range.to_string(),
+            type_missing.len().to_string(),
+        ]);
+        missing.extend(type_missing);
+    }
+    if !rows.is_empty() {
+        progress!(
+            "{}",
+            output::table(&["Data type", "Records", "Missing"], &rows)
+        );
+    }
+
+    if missing.is_empty() {
+        progress!("{}", output::success("Nothing to backfill"));
+        process::exit(EXIT_NOTHING_NEW);
+    }
+
+    let records = missing.len();
+    match write_records(&influx_client, &reader, missing).await {
+        Ok(points) => {
+            let mode_prefix = if dry_run {
+                "Would have"
+            } else {
+                "Successfully"
+            };
+            progress!(
+                "{}",
+                output::success(&format!(
+                    "{} backfilled {} records as {} points",
+                    mode_prefix, records, points
+                ))
+            );
+        }
+        Err(e) if e.is::<Interrupted>() => {
+            eprintln!("Backfill interrupted; run it again to write the rest");
+            process::exit(EXIT_INTERRUPTED);
+        }
+        Err(e) => {
+            eprintln!("Error writing health data to InfluxDB: {}", e);
+            process::exit(EXIT_SINK_ERROR);
+        }
+    }
+}
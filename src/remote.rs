@@ -0,0 +1,437 @@
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// SHA-256 of an empty body, sent as the payload hash of S3 requests without one
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+/// Checks whether a source is a URL to download (http, https or s3) rather than a local path
+pub fn is_remote(source: &str) -> bool {
+    ["http://", "https://", "s3://"]
+        .iter()
+        .any(|scheme| source.starts_with(scheme))
+}
+
+/// Where s3:// sources are read from and the credentials to sign the requests with, from the
+/// usual AWS environment variables. Without credentials, objects are read anonymously
+#[derive(Debug, Clone, PartialEq)]
+pub struct S3Config {
+    /// URL of an S3-compatible service (e.g. MinIO), whose objects are addressed by path;
+    /// AWS itself when unset
+    pub endpoint: Option<String>,
+    pub region: String,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    pub session_token: Option<String>,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            endpoint: None,
+            region: "us-east-1".to_string(),
+            access_key_id: None,
+            secret_access_key: None,
+            session_token: None,
+        }
+    }
+}
+
+impl S3Config {
+    /// Reads `AWS_ENDPOINT_URL_S3` (or `AWS_ENDPOINT_URL`), `AWS_REGION` (or
+    /// `AWS_DEFAULT_REGION`, else us-east-1), `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and
+    /// `AWS_SESSION_TOKEN`
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+        S3Config {
+            endpoint: var("AWS_ENDPOINT_URL_S3").or_else(|| var("AWS_ENDPOINT_URL")),
+            region: var("AWS_REGION")
+                .or_else(|| var("AWS_DEFAULT_REGION"))
+                .unwrap_or_else(|| "us-east-1".to_string()),
+            access_key_id: var("AWS_ACCESS_KEY_ID"),
+            secret_access_key: var("AWS_SECRET_ACCESS_KEY"),
+            session_token: var("AWS_SESSION_TOKEN"),
+        }
+    }
+
+    /// Gets the HTTP URL of an object
+    pub fn object_url(&self, bucket: &str, key: &str) -> String {
+        let key = uri_encode(key.trim_start_matches('/'));
+        match &self.endpoint {
+            Some(endpoint) => format!("{}/{}/{}", endpoint.trim_end_matches('/'), bucket, key),
+            None => format!(
+                "https://{}.s3.{}.amazonaws.com/{}",
+                bucket, self.region, key
+            ),
+        }
+    }
+}
+
+/// Percent-encodes everything in a path but unreserved characters and slashes, as S3
+/// signatures expect
+fn uri_encode(path: &str) -> String {
+    let mut encoded = String::new();
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().into()
+}
+
+/// Signs an S3 request without a query string with AWS Signature Version 4, returning the
+/// Authorization header. `headers` are the headers to sign, with lowercase names; they must
+/// include host, x-amz-date (the time given as `time`) and x-amz-content-sha256
+pub fn sign_v4(
+    method: &str,
+    path: &str,
+    headers: &[(&str, &str)],
+    access_key_id: &str,
+    secret_access_key: &str,
+    region: &str,
+    time: DateTime<Utc>,
+) -> String {
+    let mut headers = headers.to_vec();
+    headers.sort();
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let payload_hash = headers
+        .iter()
+        .find(|(name, _)| *name == "x-amz-content-sha256")
+        .map_or(EMPTY_SHA256, |(_, value)| value);
+    let canonical_request = format!(
+        "{}\n{}\n\n{}\n{}\n{}",
+        method, path, canonical_headers, signed_headers, payload_hash
+    );
+
+    let date = time.format("%Y%m%d").to_string();
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        time.format("%Y%m%dT%H%M%SZ"),
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut key = hmac_sha256(
+        format!("AWS4{}", secret_access_key).as_bytes(),
+        date.as_bytes(),
+    );
+    for part in [region, "s3", "aws4_request"] {
+        key = hmac_sha256(&key, part.as_bytes());
+    }
+    format!(
+        "AWS4-HMAC-SHA256 Credential={}/{},SignedHeaders={},Signature={}",
+        access_key_id,
+        scope,
+        signed_headers,
+        hex(&hmac_sha256(&key, string_to_sign.as_bytes()))
+    )
+}
+
+/// Splits an `s3://bucket/key` URL into its bucket and key
+pub fn parse_s3_url(url: &str) -> Option<(&str, &str)> {
+    url.strip_prefix("s3://")?
+        .split_once('/')
+        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+}
+
+/// Builds a request for an S3 object, signed when credentials are set. `body` is sent
+/// with PUT requests
+pub fn s3_request(
+    client: &reqwest::Client,
+    s3: &S3Config,
+    method: reqwest::Method,
+    bucket: &str,
+    key: &str,
+    body: Option<Vec<u8>>,
+) -> Result<reqwest::RequestBuilder, Box<dyn Error>> {
+    let url = reqwest::Url::parse(&s3.object_url(bucket, key))?;
+    let payload_hash = match &body {
+        Some(body) => hex(&Sha256::digest(body)),
+        None => EMPTY_SHA256.to_string(),
+    };
+    let mut request = client.request(method.clone(), url.clone());
+    if let Some(body) = body {
+        request = request.body(body);
+    }
+    let (Some(access_key_id), Some(secret_access_key)) = (&s3.access_key_id, &s3.secret_access_key)
+    else {
+        return Ok(request);
+    };
+
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return Err(format!("{} has no host", url).into()),
+    };
+    let now = Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let mut headers = vec![
+        ("host", host.as_str()),
+        ("x-amz-content-sha256", payload_hash.as_str()),
+        ("x-amz-date", amz_date.as_str()),
+    ];
+    if let Some(token) = &s3.session_token {
+        headers.push(("x-amz-security-token", token));
+    }
+    let authorization = sign_v4(
+        method.as_str(),
+        url.path(),
+        &headers,
+        access_key_id,
+        secret_access_key,
+        &s3.region,
+        now,
+    );
+    for (name, value) in headers.iter().filter(|(name, _)| *name != "host") {
+        request = request.header(*name, *value);
+    }
+    Ok(request.header("authorization", authorization))
+}
+
+/// What is remembered about the last download of a source, to ask the server only for a
+/// newer copy next time
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CacheEntry {
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    pub downloaded_at: DateTime<Utc>,
+}
+
+/// The local copy of a remote source
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fetched {
+    pub path: PathBuf,
+    /// Whether it was downloaded now, rather than found unchanged since the last download
+    pub downloaded: bool,
+    pub bytes: u64,
+}
+
+/// A source file downloaded over HTTP(S) or from S3 before it is imported. Copies are kept
+/// in a cache directory, so a source that has not changed since the last download, by its
+/// ETag or Last-Modified time, is not downloaded again
+pub struct RemoteSource {
+    url: String,
+    cache_dir: PathBuf,
+    s3: S3Config,
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+}
+
+impl RemoteSource {
+    /// Downloads `url` (http://, https:// or s3://bucket/key) into `cache_dir`
+    pub fn new(url: &str, cache_dir: &str) -> Self {
+        RemoteSource {
+            url: url.to_string(),
+            cache_dir: PathBuf::from(cache_dir),
+            s3: S3Config::default(),
+            connect_timeout: None,
+            request_timeout: None,
+        }
+    }
+
+    /// Sets where s3:// URLs are read from and how requests are signed
+    pub fn with_s3(mut self, s3: S3Config) -> Self {
+        self.s3 = s3;
+        self
+    }
+
+    pub fn with_connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Sets how long to wait for the server to answer, and then for each part of the
+    /// download, so a stalled transfer fails instead of hanging while a slow one completes
+    pub fn with_request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Describes the source, without credentials
+    pub fn describe(&self) -> String {
+        match reqwest::Url::parse(&self.url) {
+            Ok(mut url) if !url.username().is_empty() || url.password().is_some() => {
+                let _ = url.set_username("");
+                let _ = url.set_password(None);
+                url.to_string()
+            }
+            _ => self.url.clone(),
+        }
+    }
+
+    /// Gets where the copy of the source is kept: a file named after a hash of the URL and
+    /// the last part of its path, so that different URLs never share a copy
+    pub fn cache_path(&self) -> PathBuf {
+        let name = self
+            .url
+            .split(['?', '#'])
+            .next()
+            .unwrap_or_default()
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty() && !name.contains(':'))
+            .unwrap_or("source");
+        let hash = hex(&Sha256::digest(self.url.as_bytes()));
+        self.cache_dir.join(format!("{}-{}", &hash[..16], name))
+    }
+
+    fn cache_entry_path(&self) -> PathBuf {
+        let mut path = self.cache_path().into_os_string();
+        path.push(".json");
+        PathBuf::from(path)
+    }
+
+    /// Reads what was remembered about the last download, if its copy is still there
+    fn cache_entry(&self) -> Option<CacheEntry> {
+        if !self.cache_path().is_file() {
+            return None;
+        }
+        let entry: CacheEntry =
+            serde_json::from_str(&fs::read_to_string(self.cache_entry_path()).ok()?).ok()?;
+        (entry.url == self.url).then_some(entry)
+    }
+
+    /// Builds the request for the source, signed when it is on S3 and credentials are set
+    fn request(&self, client: &reqwest::Client) -> Result<reqwest::RequestBuilder, Box<dyn Error>> {
+        if !self.url.starts_with("s3://") {
+            let mut url = reqwest::Url::parse(&self.url)?;
+            let username = url.username().to_string();
+            let password = url.password().map(String::from);
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+            let mut request = client.get(url);
+            if !username.is_empty() {
+                request = request.basic_auth(username, password);
+            }
+            return Ok(request);
+        }
+
+        let (bucket, key) = parse_s3_url(&self.url)
+            .ok_or_else(|| format!("{} is not an s3://bucket/key URL", self.describe()))?;
+        s3_request(client, &self.s3, reqwest::Method::GET, bucket, key, None)
+    }
+
+    /// Waits for `future` for up to the request timeout, if one is set
+    async fn within_timeout<T>(
+        &self,
+        future: impl std::future::Future<Output = T>,
+    ) -> Result<T, Box<dyn Error>> {
+        match self.request_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, future).await.map_err(|_| {
+                format!(
+                    "Timed out downloading {}: no data for {}s; raise --request-timeout if the server is slow",
+                    self.describe(),
+                    timeout.as_secs()
+                )
+                .into()
+            }),
+            None => Ok(future.await),
+        }
+    }
+
+    /// Downloads the source into the cache, unless the server answers that the cached copy
+    /// is still current. The copy is replaced in one step once the download is complete
+    pub async fn fetch(&self) -> Result<Fetched, Box<dyn Error>> {
+        let mut builder = reqwest::Client::builder();
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        let client = builder.build()?;
+
+        let cached = self.cache_entry();
+        let mut request = self.request(&client)?;
+        if let Some(entry) = &cached {
+            if let Some(etag) = &entry.etag {
+                request = request.header("if-none-match", etag);
+            }
+            if let Some(last_modified) = &entry.last_modified {
+                request = request.header("if-modified-since", last_modified);
+            }
+        }
+        let mut response = self.within_timeout(request.send()).await??;
+        let status = response.status();
+        let path = self.cache_path();
+        if status == reqwest::StatusCode::NOT_MODIFIED && cached.is_some() {
+            return Ok(Fetched {
+                bytes: fs::metadata(&path)?.len(),
+                path,
+                downloaded: false,
+            });
+        }
+        if !status.is_success() {
+            return Err(format!("Failed to download {} ({})", self.describe(), status).into());
+        }
+
+        let header = |name: &str| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        };
+        let entry = CacheEntry {
+            url: self.url.clone(),
+            etag: header("etag"),
+            last_modified: header("last-modified"),
+            downloaded_at: Utc::now(),
+        };
+
+        fs::create_dir_all(&self.cache_dir)?;
+        let partial = path.with_extension("part");
+        let written: Result<u64, Box<dyn Error>> = async {
+            let mut file = File::create(&partial)?;
+            let mut bytes = 0;
+            while let Some(chunk) = self.within_timeout(response.chunk()).await?? {
+                file.write_all(&chunk)?;
+                bytes += chunk.len() as u64;
+            }
+            file.sync_all()?;
+            Ok(bytes)
+        }
+        .await;
+        let bytes = match written {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                let _ = fs::remove_file(&partial);
+                return Err(e);
+            }
+        };
+        fs::rename(&partial, &path)?;
+        fs::write(self.cache_entry_path(), serde_json::to_string(&entry)?)?;
+        Ok(Fetched {
+            path,
+            downloaded: true,
+            bytes,
+        })
+    }
+}
@@ -0,0 +1,207 @@
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::{Mutex, OnceLock};
+
+static REPORTER: OnceLock<Box<dyn Reporter>> = OnceLock::new();
+
+/// Sets the reporter imports tell about their progress; only the first call has an effect
+/// Until one is set, progress is printed to the console
+pub fn set_reporter(reporter: Box<dyn Reporter>) {
+    let _ = REPORTER.set(reporter);
+}
+
+/// Gets the reporter set with `set_reporter`, or the console reporter
+pub fn reporter() -> &'static dyn Reporter {
+    match REPORTER.get() {
+        Some(reporter) => reporter.as_ref(),
+        None => &ConsoleReporter,
+    }
+}
+
+/// How import progress is reported
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum ProgressFormat {
+    /// Messages for people reading a terminal
+    Console,
+    /// One JSON object per line for each event
+    Json,
+}
+
+/// A step of an import
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    /// Checking that the source can be read
+    Validate,
+    /// Reading the records after the watermark
+    Read,
+    /// Converting records and writing the points
+    Write,
+    /// Saving the import state
+    SaveState,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Phase::Validate => "validate",
+            Phase::Read => "read",
+            Phase::Write => "write",
+            Phase::SaveState => "save state",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Why records were not written
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", content = "error", rename_all = "snake_case")]
+pub enum SkipReason {
+    /// The watermark already covers them
+    AlreadyImported,
+    /// They could not be converted to points
+    ConversionFailed(String),
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SkipReason::AlreadyImported => write!(f, "already imported"),
+            SkipReason::ConversionFailed(error) => write!(f, "conversion failed: {}", error),
+        }
+    }
+}
+
+/// Receives the progress of an import, keeping the library free of terminal output
+/// Every callback does nothing by default
+pub trait Reporter: Send + Sync {
+    /// A phase started; `items` is the number of records it works on, when known up front
+    fn phase_started(&self, _phase: Phase, _items: Option<usize>) {}
+
+    /// A phase finished; `items` is the number of records (or, for writes, points) it handled
+    fn phase_finished(&self, _phase: Phase, _items: usize) {}
+
+    /// A batch of `points` points was written, for `total` points written so far
+    fn batch_written(&self, _points: usize, _total: usize) {}
+
+    /// `count` records were skipped instead of written
+    fn records_skipped(&self, _count: usize, _reason: &SkipReason) {}
+}
+
+/// Prints progress for people reading a terminal, honoring --quiet
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsoleReporter;
+
+impl Reporter for ConsoleReporter {
+    fn phase_started(&self, phase: Phase, items: Option<usize>) {
+        if let (Phase::Write, Some(records)) = (phase, items) {
+            progress!("Writing {} records to InfluxDB", records);
+        }
+    }
+
+    fn phase_finished(&self, phase: Phase, items: usize) {
+        match phase {
+            Phase::Validate => progress!("Source validation successful"),
+            Phase::Read => progress!("Read {} new records", items),
+            Phase::Write | Phase::SaveState => {}
+        }
+    }
+
+    fn records_skipped(&self, count: usize, reason: &SkipReason) {
+        match reason {
+            SkipReason::AlreadyImported => {
+                progress!("Skipped {} previously imported records", count)
+            }
+            SkipReason::ConversionFailed(error) => eprintln!("Error converting record: {}", error),
+        }
+    }
+}
+
+/// Prints nothing
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SilentReporter;
+
+impl Reporter for SilentReporter {}
+
+/// Writes every event as a line of JSON, for scripts and log collectors
+pub struct JsonReporter {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+/// An event as `JsonReporter` writes it
+#[derive(Serialize)]
+struct JsonEvent<'a> {
+    time: DateTime<Utc>,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    PhaseStarted {
+        phase: Phase,
+        items: Option<usize>,
+    },
+    PhaseFinished {
+        phase: Phase,
+        items: usize,
+    },
+    BatchWritten {
+        points: usize,
+        total: usize,
+    },
+    RecordsSkipped {
+        count: usize,
+        reason: &'a SkipReason,
+    },
+}
+
+impl JsonReporter {
+    /// Creates a reporter writing to standard output
+    pub fn stdout() -> Self {
+        Self::new(io::stdout())
+    }
+
+    /// Creates a reporter writing to `out`
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        JsonReporter {
+            out: Mutex::new(Box::new(out)),
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        let event = JsonEvent {
+            time: Utc::now(),
+            event,
+        };
+        let Ok(line) = serde_json::to_string(&event) else {
+            return;
+        };
+        let mut out = self.out.lock().unwrap();
+        // Progress is best effort: a closed pipe must not fail the import
+        let _ = writeln!(out, "{}", line);
+        let _ = out.flush();
+    }
+}
+
+impl Reporter for JsonReporter {
+    fn phase_started(&self, phase: Phase, items: Option<usize>) {
+        self.emit(Event::PhaseStarted { phase, items });
+    }
+
+    fn phase_finished(&self, phase: Phase, items: usize) {
+        self.emit(Event::PhaseFinished { phase, items });
+    }
+
+    fn batch_written(&self, points: usize, total: usize) {
+        self.emit(Event::BatchWritten { points, total });
+    }
+
+    fn records_skipped(&self, count: usize, reason: &SkipReason) {
+        self.emit(Event::RecordsSkipped { count, reason });
+    }
+}
@@ -0,0 +1,61 @@
+use chrono::Utc;
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::process;
+
+/// An exclusive lock on a file, shared by every run given the same --lock-file
+/// The lock is released when this is dropped or the process ends, even if it crashes,
+/// so a stale lock file never blocks later runs
+pub struct RunLock {
+    _file: File,
+}
+
+impl RunLock {
+    /// Takes the lock if no other run holds it
+    /// When another run does, returns the holder recorded in the lock file instead
+    pub fn try_acquire(path: &str, holder: &str) -> io::Result<Result<Self, String>> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)?;
+        match file.try_lock() {
+            Ok(()) => Ok(Ok(Self::record_holder(file, holder)?)),
+            Err(TryLockError::WouldBlock) => {
+                let mut current = String::new();
+                file.read_to_string(&mut current)?;
+                Ok(Err(current.trim().to_string()))
+            }
+            Err(TryLockError::Error(e)) => Err(e),
+        }
+    }
+
+    /// Takes the lock, first waiting for the run holding it to finish
+    /// `waiting` is called with the current holder before waiting
+    pub fn acquire(path: &str, holder: &str, waiting: impl FnOnce(&str)) -> io::Result<Self> {
+        match Self::try_acquire(path, holder)? {
+            Ok(lock) => Ok(lock),
+            Err(current) => {
+                waiting(&current);
+                let file = OpenOptions::new().read(true).write(true).open(path)?;
+                file.lock()?;
+                Self::record_holder(file, holder)
+            }
+        }
+    }
+
+    /// Writes who holds the lock into the file, for the runs waiting for it
+    fn record_holder(mut file: File, holder: &str) -> io::Result<Self> {
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(
+            file,
+            "{} (pid {}, since {})",
+            holder,
+            process::id(),
+            Utc::now().format("%Y-%m-%d %H:%M:%S UTC")
+        )?;
+        Ok(RunLock { _file: file })
+    }
+}
@@ -0,0 +1,60 @@
+use chrono::{DateTime, TimeZone};
+use croner::Cron;
+use std::fs;
+use std::time::{Duration, SystemTime};
+
+/// Parses an interval like "90s", "15m", "1h" or "1d"; a plain number is in seconds
+pub fn parse_interval(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number: u64 = number
+        .parse()
+        .map_err(|_| format!("invalid interval '{}': expected e.g. 30s, 15m or 1h", value))?;
+    let seconds = match unit {
+        "" | "s" => number,
+        "m" => number * 60,
+        "h" => number * 60 * 60,
+        "d" => number * 60 * 60 * 24,
+        _ => {
+            return Err(format!(
+                "invalid interval unit '{}': expected s, m, h or d",
+                unit
+            ))
+        }
+    };
+    if seconds == 0 {
+        return Err("interval must be greater than zero".to_string());
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Modification time and size of a source file, used to tell whether it has new data
+/// Returns None when the file cannot be read, so that a file that appears is noticed
+pub fn source_version(path: &str) -> Option<(SystemTime, u64)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// A cron schedule in the standard five-field format, e.g. "0 3 * * *" for 03:00 every day
+#[derive(Debug)]
+pub struct CronSchedule {
+    cron: Cron,
+}
+
+impl CronSchedule {
+    pub fn parse(expression: &str) -> Result<Self, String> {
+        let cron = Cron::new(expression)
+            .parse()
+            .map_err(|e| format!("invalid schedule '{}': {}", expression, e))?;
+        Ok(CronSchedule { cron })
+    }
+
+    /// Returns the first time the schedule fires after `after`
+    pub fn next_after<Tz: TimeZone>(&self, after: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        self.cron.find_next_occurrence(after, false).ok()
+    }
+}
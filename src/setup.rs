@@ -0,0 +1,219 @@
+use crate::config::CONFIG_TEMPLATE;
+use crate::convert::DEFAULT_FUND_TAG_KEY;
+use crate::import::{
+    create_influx_client, resolve_database, EXIT_ERROR, EXIT_SINK_ERROR, EXIT_SOURCE_ERROR,
+};
+use crate::influx_client::{
+    InfluxClient, DEFAULT_CONNECT_TIMEOUT_SECS, DEFAULT_REQUEST_TIMEOUT_SECS,
+};
+use crate::inspect::{self, SourceKind};
+use crate::output;
+use crate::template::MeasurementTemplate;
+use clap::Args;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::process;
+
+/// The options of `template-from-measurement`
+#[derive(Args)]
+pub struct TemplateFromMeasurementArgs {
+    /// The measurement to lay the file out after
+    #[arg(short, long, env = "HDI_MEASUREMENT")]
+    pub measurement: String,
+
+    /// Key of the tag naming the fund of each point
+    #[arg(long, default_value = DEFAULT_FUND_TAG_KEY, env = "HDI_TAG_KEY")]
+    pub tag_key: String,
+
+    /// Name of the timestamp column
+    #[arg(long, default_value = "timestamp", env = "HDI_TIME_COLUMN")]
+    pub time_column: String,
+
+    /// Format of the timestamps, for the config section
+    #[arg(long, default_value = "%Y-%m-%d %H:%M:%S", env = "HDI_TIME_FORMAT")]
+    pub time_format: String,
+
+    /// File the CSV header is written to instead of stdout
+    #[arg(long, env = "HDI_OUTPUT")]
+    pub output: Option<String>,
+
+    /// InfluxDB URL
+    #[arg(short, long, default_value = "http://localhost:8086", env = "HDI_URL")]
+    pub url: String,
+
+    /// InfluxDB organization
+    #[arg(short, long, env = "HDI_ORG")]
+    pub org: String,
+
+    /// InfluxDB bucket (2.x) or database (1.x)
+    #[arg(short, long, required_unless_present = "database", env = "HDI_BUCKET")]
+    pub bucket: Option<String>,
+
+    /// InfluxDB 1.x database name (takes precedence over --bucket)
+    #[arg(long, env = "HDI_DATABASE")]
+    pub database: Option<String>,
+
+    /// InfluxDB 1.x retention policy to read from (defaults to the database's default policy)
+    #[arg(long, env = "HDI_RETENTION_POLICY")]
+    pub retention_policy: Option<String>,
+
+    /// InfluxDB token for authentication
+    #[arg(short, long, env = "HDI_TOKEN", hide_env_values = true)]
+    pub token: String,
+
+    /// Seconds to wait for a connection to InfluxDB (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_CONNECT_TIMEOUT_SECS, env = "HDI_CONNECT_TIMEOUT")]
+    pub connect_timeout: u64,
+
+    /// Seconds to wait for each InfluxDB request to complete (0 disables the timeout)
+    #[arg(long, default_value_t = DEFAULT_REQUEST_TIMEOUT_SECS, env = "HDI_REQUEST_TIMEOUT")]
+    pub request_timeout: u64,
+}
+
+/// Writes the CSV header laid out after the series of a measurement, to `--output` or
+/// stdout, and prints the `[funds]` section that imports the file
+pub async fn template_from_measurement(args: TemplateFromMeasurementArgs) {
+    let TemplateFromMeasurementArgs {
+        measurement,
+        tag_key,
+        time_column,
+        time_format,
+        output,
+        url,
+        org,
+        bucket,
+        database,
+        retention_policy,
+        token,
+        connect_timeout,
+        request_timeout,
+    } = args;
+    let bucket = resolve_database(bucket, database);
+    let influx_client = create_influx_client(
+        InfluxClient::builder(&url, &bucket).token(&token),
+        &org,
+        retention_policy.as_deref(),
+        connect_timeout,
+        request_timeout,
+    );
+    let points = match influx_client.first_points(&measurement).await {
+        Ok(points) => points,
+        Err(e) => {
+            eprintln!("Failed to read measurement '{}': {}", measurement, e);
+            process::exit(EXIT_SINK_ERROR);
+        }
+    };
+    if points.is_empty() {
+        eprintln!(
+            "Measurement '{}' has no points in {} ({})",
+            measurement, url, bucket
+        );
+        process::exit(EXIT_ERROR);
+    }
+    let template = MeasurementTemplate::from_points(&measurement, &points, &tag_key);
+
+    let written = match &output {
+        Some(path) => File::create(path)
+            .map_err(|e| e.into())
+            .and_then(|file| template.write_csv_header(&time_column, BufWriter::new(file))),
+        None => template.write_csv_header(&time_column, io::stdout().lock()),
+    };
+    if let Err(e) = written {
+        eprintln!("Failed to write the CSV header: {}", e);
+        process::exit(EXIT_ERROR);
+    }
+
+    let source = output.as_deref().unwrap_or("funds.csv");
+    if let Some(path) = &output {
+        println!(
+            "{}",
+            output::success(&format!(
+                "Wrote the header of {} series of '{}' to '{}'",
+                template.series.len(),
+                measurement,
+                path
+            ))
+        );
+    }
+    println!();
+    println!("# Add rows below the header, then import them with this section:");
+    print!(
+        "{}",
+        template.config_snippet(source, &time_column, &time_format)
+    );
+}
+
+/// The options of `init`
+#[derive(Args)]
+pub struct InitArgs {
+    /// Output file for the configuration
+    #[arg(short, long, default_value = "influx-import.toml", env = "HDI_OUTPUT")]
+    pub output: String,
+
+    /// Overwrite the output file if it already exists
+    #[arg(long, env = "HDI_FORCE")]
+    pub force: bool,
+
+    /// A funds CSV file or Health Connect SQLite export (told apart by the extension) to
+    /// fill in the [funds] or [health] section from: the header rows, columns and
+    /// timestamp format, or the data types found
+    #[arg(long, env = "HDI_FROM_SOURCE")]
+    pub from: Option<String>,
+}
+
+/// Writes the config file template, or a config file filled in from `--from`, refusing to
+/// overwrite an existing file without `--force`
+pub fn init(args: InitArgs) {
+    let InitArgs {
+        output,
+        force,
+        from,
+    } = args;
+    if !force && std::path::Path::new(&output).exists() {
+        eprintln!("{} already exists; use --force to overwrite it", output);
+        process::exit(EXIT_ERROR);
+    }
+
+    let config = match &from {
+        Some(source) => {
+            let inspected = match SourceKind::detect(source) {
+                SourceKind::Funds => inspect::inspect_csv(source).map(|inspection| {
+                    println!("Inspected funds CSV file '{}':", source);
+                    println!("  Header rows: {}", inspection.header_rows);
+                    println!("  Columns: {}", inspection.columns.join(", "));
+                    match inspection.time_format {
+                        Some(format) => println!("  Time format: {}", format),
+                        None => println!("{}", output::warning("  No known timestamp format fits")),
+                    }
+                    println!("  Measurement: {}", inspection.measurement);
+                    inspection.config()
+                }),
+                SourceKind::Health => inspect::inspect_health(source).map(|inspection| {
+                    println!("Inspected Health Connect export '{}':", source);
+                    for (data_type, records) in &inspection.data_types {
+                        println!("  {}: {} records", data_type, records);
+                    }
+                    inspection.config()
+                }),
+            };
+            match inspected {
+                Ok(config) => config,
+                Err(e) => {
+                    eprintln!("Failed to inspect '{}': {}", source, e);
+                    process::exit(EXIT_SOURCE_ERROR);
+                }
+            }
+        }
+        None => CONFIG_TEMPLATE.to_string(),
+    };
+
+    println!("Generating template configuration file: '{}'", output);
+    if let Err(e) = std::fs::write(&output, config) {
+        eprintln!("Failed to write {}: {}", output, e);
+        process::exit(EXIT_ERROR);
+    }
+    println!(
+        "Edit it, then run commands with: home-db-importer --config {} <command>",
+        output
+    );
+}
@@ -0,0 +1,233 @@
+use crate::convert::{funds_record_to_points, health_record_to_point};
+use crate::csv_parser::CsvRecord;
+use crate::health_data::{HealthDataType, HealthRecord};
+use crate::influx_client::{DataPoint, BATCH_SIZE};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// A place points are written to: InfluxDB, or memory in tests and benchmarks
+pub trait Sink {
+    /// Gets the number of points sent in each write request
+    fn batch_size(&self) -> usize;
+
+    /// Writes points, in requests of up to `batch_size` points
+    fn write_points(
+        &self,
+        points: &[DataPoint],
+    ) -> impl Future<Output = Result<(), Box<dyn Error>>>;
+
+    /// Checks whether the sink only logs what it would write
+    fn is_dry_run(&self) -> bool {
+        false
+    }
+
+    /// Gets the number of points saved for a later retry instead of written
+    fn spooled_points(&self) -> usize {
+        0
+    }
+
+    /// Saves points for a later retry instead of writing them
+    fn spool_points(&self, _points: &[DataPoint]) -> Result<(), Box<dyn Error>> {
+        Err("This sink has no spool".into())
+    }
+
+    /// Process and write all CSV records
+    fn write_funds_records(
+        &self,
+        records: &[CsvRecord],
+        time_column: &str,
+        time_format: &str,
+    ) -> impl Future<Output = Result<usize, Box<dyn Error>>> {
+        async move {
+            let mut all_points = Vec::new();
+            let mut error_count = 0;
+            let mut success_count = 0;
+
+            for record in records {
+                match funds_record_to_points(record, time_column, time_format) {
+                    Ok(points) => {
+                        success_count += points.len();
+                        all_points.extend(points);
+                    }
+                    Err(e) => {
+                        eprintln!("Error converting record: {}", e);
+                        error_count += 1;
+                    }
+                }
+            }
+
+            if self.is_dry_run() {
+                progress!(
+                    "Dry-run mode: Would write {} data points to InfluxDB",
+                    all_points.len()
+                );
+            } else {
+                progress!("Writing {} data points to InfluxDB", all_points.len());
+            }
+
+            self.write_points(&all_points).await?;
+
+            if error_count > 0 {
+                eprintln!("Failed to convert {} records", error_count);
+            }
+
+            Ok(success_count)
+        }
+    }
+
+    /// Process and write all health records
+    fn write_health_records(
+        &self,
+        records_map: &HashMap<HealthDataType, Vec<HealthRecord>>,
+    ) -> impl Future<Output = Result<usize, Box<dyn Error>>> {
+        async move {
+            let mut all_points = Vec::new();
+            let mut success_count = 0;
+
+            for (record_type, records) in records_map {
+                progress!("Processing {} {} records", records.len(), record_type);
+
+                for record in records {
+                    all_points.push(health_record_to_point(record));
+                    success_count += 1;
+                }
+            }
+
+            if self.is_dry_run() {
+                progress!(
+                    "Dry-run mode: Would write {} health data points to InfluxDB",
+                    all_points.len()
+                );
+            } else {
+                progress!(
+                    "Writing {} health data points to InfluxDB",
+                    all_points.len()
+                );
+            }
+
+            self.write_points(&all_points).await?;
+
+            Ok(success_count)
+        }
+    }
+}
+
+/// Identifies a point the way InfluxDB does: a later point with the same measurement,
+/// tag set and timestamp overwrites an earlier one
+type SeriesKey = (String, BTreeMap<String, String>, DateTime<Utc>);
+
+/// A sink that keeps every point written in memory, for tests and for measuring the
+/// importer without a server
+#[derive(Debug, Default)]
+pub struct MemorySink {
+    batch_size: Option<usize>,
+    requests: Mutex<Vec<Vec<DataPoint>>>,
+}
+
+impl MemorySink {
+    /// Creates an empty sink that writes in batches of `BATCH_SIZE` points
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of points recorded per write request
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = Some(batch_size.max(1));
+        self
+    }
+
+    /// Gets the points of each write request, in the order they were made
+    pub fn requests(&self) -> Vec<Vec<DataPoint>> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Gets every point written, in the order they were written
+    pub fn points(&self) -> Vec<DataPoint> {
+        self.requests().into_iter().flatten().collect()
+    }
+
+    /// Gets the points InfluxDB would end up storing: rewriting a point with the same
+    /// measurement, tags and timestamp replaces it. Sorted by measurement, tags and time
+    pub fn stored_points(&self) -> Vec<DataPoint> {
+        let mut stored: BTreeMap<SeriesKey, DataPoint> = BTreeMap::new();
+        for point in self.points() {
+            let tags = point
+                .tags
+                .iter()
+                .filter(|(_, value)| !value.is_empty())
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            stored.insert((point.measurement.clone(), tags, point.time), point);
+        }
+        stored.into_values().collect()
+    }
+
+    /// Forgets every point written so far
+    pub fn clear(&self) {
+        self.requests.lock().unwrap().clear();
+    }
+}
+
+impl Sink for MemorySink {
+    fn batch_size(&self) -> usize {
+        self.batch_size.unwrap_or(BATCH_SIZE)
+    }
+
+    async fn write_points(&self, points: &[DataPoint]) -> Result<(), Box<dyn Error>> {
+        let mut requests = self.requests.lock().unwrap();
+        for chunk in points.chunks(self.batch_size()) {
+            requests.push(chunk.to_vec());
+        }
+        Ok(())
+    }
+}
+
+/// A sink that serializes each batch as line protocol, as InfluxClient does before sending
+/// it, and then drops it; for measuring the importer without a server
+#[derive(Debug)]
+pub struct NullSink {
+    batch_size: usize,
+    requests: AtomicUsize,
+    points: AtomicUsize,
+}
+
+impl NullSink {
+    /// Creates a sink that serializes batches of `batch_size` points
+    pub fn new(batch_size: usize) -> Self {
+        NullSink {
+            batch_size: batch_size.max(1),
+            requests: AtomicUsize::new(0),
+            points: AtomicUsize::new(0),
+        }
+    }
+
+    /// Gets the number of write requests the points would have needed
+    pub fn requests(&self) -> usize {
+        self.requests.load(Ordering::Relaxed)
+    }
+
+    /// Gets the number of points written
+    pub fn points(&self) -> usize {
+        self.points.load(Ordering::Relaxed)
+    }
+}
+
+impl Sink for NullSink {
+    fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    async fn write_points(&self, points: &[DataPoint]) -> Result<(), Box<dyn Error>> {
+        for chunk in points.chunks(self.batch_size) {
+            let lines: Vec<String> = chunk.iter().map(DataPoint::to_line_protocol).collect();
+            std::hint::black_box(lines.join("\n"));
+            self.requests.fetch_add(1, Ordering::Relaxed);
+            self.points.fetch_add(chunk.len(), Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
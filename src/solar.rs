@@ -0,0 +1,324 @@
+use crate::influx_client::DataPoint;
+use crate::source::{RecordStream, Source};
+use crate::state_management::ImportState;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use csv::ReaderBuilder;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fs;
+
+/// Measurement energy produced is written to, in kWh per interval
+pub const ENERGY_MEASUREMENT: &str = "solar_energy";
+
+/// Measurement power is written to, in W
+pub const POWER_MEASUREMENT: &str = "solar_power";
+
+/// Measurement string voltages are written to, in V
+pub const VOLTAGE_MEASUREMENT: &str = "solar_voltage";
+
+/// Measurement string currents are written to, in A
+pub const CURRENT_MEASUREMENT: &str = "solar_current";
+
+/// `string` tag of values for the whole inverter rather than one of its strings
+pub const TOTAL_STRING: &str = "total";
+
+/// Formats of the time column tried after the configured one: SolarEdge and Fronius export
+/// day-first dates
+const TIME_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+    "%d.%m.%Y %H:%M",
+    "%d/%m/%Y %H:%M",
+];
+
+/// Time columns looked for when the configured one is missing
+const TIME_COLUMNS: [&str; 4] = ["time", "date and time", "timestamp", "date"];
+
+/// Words marking a counter that only grows, until it is reset
+const COUNTER_WORDS: [&str; 5] = ["total", "today", "daily", "lifetime", "cumulative"];
+
+/// Prefixes of the number of a string, as in `Voltage DC String 1`, `Epv2 today` or
+/// `MPPT 3 Power`
+const STRING_PREFIXES: [&str; 3] = ["string", "mppt", "pv"];
+
+/// A column of the export written as points
+#[derive(Debug, Clone, PartialEq)]
+struct Channel {
+    index: usize,
+    measurement: &'static str,
+    string: String,
+    /// Factor from the export's unit to the unit written
+    scale: f64,
+    /// Whether the column is a counter, written as the increase since the previous reading
+    counter: bool,
+}
+
+/// Reads the unit of a header, in parentheses or brackets
+fn header_unit(header: &str) -> Option<&str> {
+    let start = header.rfind(['(', '['])?;
+    let end = header[start..].find([')', ']'])? + start;
+    Some(header[start + 1..end].trim())
+}
+
+/// Finds the string a column belongs to from the number after one of `STRING_PREFIXES`
+fn string_number(name: &str) -> Option<String> {
+    STRING_PREFIXES.iter().find_map(|prefix| {
+        name.match_indices(prefix).find_map(|(at, _)| {
+            let digits: String = name[at + prefix.len()..]
+                .trim_start_matches([' ', '_', '-'])
+                .chars()
+                .take_while(char::is_ascii_digit)
+                .collect();
+            (!digits.is_empty()).then_some(digits)
+        })
+    })
+}
+
+/// Recognizes a column by its unit: energy in Wh, kWh or MWh, power in W or kW, and
+/// voltage and current of the DC strings. Other columns are not written
+fn channel(index: usize, header: &str, unit: Option<&str>) -> Option<Channel> {
+    let name = header.to_lowercase();
+    let (measurement, scale) = match unit?.to_lowercase().as_str() {
+        "wh" => (ENERGY_MEASUREMENT, 0.001),
+        "kwh" => (ENERGY_MEASUREMENT, 1.0),
+        "mwh" => (ENERGY_MEASUREMENT, 1000.0),
+        "w" => (POWER_MEASUREMENT, 1.0),
+        "kw" => (POWER_MEASUREMENT, 1000.0),
+        "v" => (VOLTAGE_MEASUREMENT, 1.0),
+        "a" => (CURRENT_MEASUREMENT, 1.0),
+        _ => return None,
+    };
+    Some(Channel {
+        index,
+        measurement,
+        string: string_number(&name).unwrap_or_else(|| TOTAL_STRING.to_string()),
+        scale,
+        counter: measurement == ENERGY_MEASUREMENT
+            && COUNTER_WORDS.iter().any(|word| name.contains(word)),
+    })
+}
+
+/// What an inverter produced at one moment, or in the interval ending then
+#[derive(Debug, Clone)]
+pub struct SolarRecord {
+    pub timestamp: DateTime<Utc>,
+    pub points: Vec<DataPoint>,
+}
+
+/// Reads the CSV export of a SolarEdge, Fronius or Growatt inverter: energy produced per
+/// interval, as a whole and per string, and the strings' power, voltage and current
+///
+/// Columns are recognized by their unit, in the header (`Energy (Wh)`, `Pac(W)`) or in a
+/// row under it (`[Wh]`, as Fronius Solar.web exports). Counters such as `Eac total` or
+/// `Epv1 today` are written as the energy produced since the previous reading; when a
+/// counter goes down it was reset, and the energy produced since is its new value
+pub struct SolarReader {
+    file_path: String,
+    time_column: String,
+    time_format: String,
+}
+
+impl SolarReader {
+    /// Creates a reader for an inverter export, with its time in a `Time` column
+    pub fn new(file_path: &str) -> Self {
+        SolarReader {
+            file_path: file_path.to_string(),
+            time_column: "Time".to_string(),
+            time_format: "%Y-%m-%d %H:%M:%S".to_string(),
+        }
+    }
+
+    /// Sets the column holding the time of each reading and its format; times are taken as
+    /// UTC. Without one, Time, Date and time, timestamp or Date is used
+    pub fn with_time_column(mut self, column: &str, format: &str) -> Self {
+        self.time_column = column.to_string();
+        self.time_format = format.to_string();
+        self
+    }
+
+    fn parse_time(&self, text: &str) -> Option<DateTime<Utc>> {
+        let text = text.trim();
+        if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+            return Some(time.with_timezone(&Utc));
+        }
+        std::iter::once(self.time_format.as_str())
+            .chain(TIME_FORMATS)
+            .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+            .map(|naive| naive.and_utc())
+    }
+
+    /// Reads every row with a valid time, oldest first, converting counters to the energy
+    /// produced since the previous row
+    pub fn records(&self) -> Result<Vec<SolarRecord>, Box<dyn Error>> {
+        let contents = fs::read_to_string(&self.file_path)
+            .map_err(|e| format!("Failed to read {}: {}", self.file_path, e))?;
+        let first_line = contents.lines().next().unwrap_or_default();
+        let delimiter = if first_line.contains(';') && !first_line.contains(',') {
+            b';'
+        } else {
+            b','
+        };
+        let mut reader = ReaderBuilder::new()
+            .delimiter(delimiter)
+            .flexible(true)
+            .from_reader(contents.as_bytes());
+        let headers: Vec<String> = reader
+            .headers()?
+            .iter()
+            .map(|header| header.trim().to_string())
+            .collect();
+        let mut rows = reader.records().collect::<Result<Vec<_>, _>>()?;
+
+        // Fronius puts the units in the first row
+        let units_row = rows.first().is_some_and(|row| {
+            row.iter()
+                .filter(|cell| !cell.trim().is_empty())
+                .all(|cell| cell.trim().starts_with('['))
+        });
+        let units: Vec<Option<String>> = headers
+            .iter()
+            .enumerate()
+            .map(|(index, header)| {
+                // Fronius headers end with the device number, as in `Energy | Symo (1)`
+                let from_row = rows
+                    .first()
+                    .filter(|_| units_row)
+                    .and_then(|row| header_unit(row.get(index)?.trim()));
+                from_row.or_else(|| header_unit(header)).map(String::from)
+            })
+            .collect();
+        if units_row {
+            rows.remove(0);
+        }
+
+        let lowercase: Vec<String> = headers.iter().map(|h| h.to_lowercase()).collect();
+        let time_index = std::iter::once(self.time_column.to_lowercase())
+            .chain(TIME_COLUMNS.map(String::from))
+            .find_map(|column| {
+                lowercase.iter().position(|header| {
+                    *header == column
+                        || header.split(['(', '[']).next().map(str::trim) == Some(column.as_str())
+                })
+            })
+            .ok_or_else(|| format!("No time column '{}' in the header", self.time_column))?;
+
+        // Counters only fill in for values no column gives per interval
+        let mut channels: Vec<Channel> = headers
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| *index != time_index)
+            .filter_map(|(index, header)| channel(index, header, units[index].as_deref()))
+            .collect();
+        channels.sort_by_key(|channel| (channel.counter, channel.index));
+        let mut seen = BTreeSet::new();
+        channels.retain(|channel| seen.insert((channel.measurement, channel.string.clone())));
+        channels.sort_by_key(|channel| channel.index);
+        if channels.is_empty() {
+            return Err("No energy, power, voltage or current columns in the header".into());
+        }
+
+        let mut readings: Vec<(DateTime<Utc>, Vec<Option<f64>>)> = rows
+            .iter()
+            .filter_map(|row| {
+                let timestamp = self.parse_time(row.get(time_index)?)?;
+                let values = channels
+                    .iter()
+                    .map(|channel| {
+                        let text = row.get(channel.index)?.trim();
+                        let text = if delimiter == b';' {
+                            text.replace(',', ".")
+                        } else {
+                            text.to_string()
+                        };
+                        text.parse::<f64>().ok()
+                    })
+                    .collect();
+                Some((timestamp, values))
+            })
+            .collect();
+        readings.sort_by_key(|(timestamp, _)| *timestamp);
+
+        let mut last_counters: HashMap<usize, f64> = HashMap::new();
+        let mut records = Vec::new();
+        for (timestamp, values) in readings {
+            let mut points = Vec::new();
+            for (channel, value) in channels.iter().zip(values) {
+                let Some(value) = value else {
+                    continue;
+                };
+                let value = if channel.counter {
+                    // The first reading of a counter only gives a starting point
+                    let Some(previous) = last_counters.insert(channel.index, value) else {
+                        continue;
+                    };
+                    if value >= previous {
+                        value - previous
+                    } else {
+                        value
+                    }
+                } else {
+                    value
+                };
+                let mut tags = HashMap::new();
+                tags.insert("string".to_string(), channel.string.clone());
+                points.push(DataPoint {
+                    measurement: channel.measurement.to_string(),
+                    time: timestamp,
+                    tags,
+                    // Counters in kWh with three decimals would otherwise show float noise
+                    field_value: (value * channel.scale * 1e6).round() / 1e6,
+                    text_fields: BTreeMap::new(),
+                });
+            }
+            records.push(SolarRecord { timestamp, points });
+        }
+        Ok(records)
+    }
+}
+
+impl Source for SolarReader {
+    type Record = SolarRecord;
+
+    fn validate(&self) -> Result<String, Box<dyn Error>> {
+        let records = self.records()?;
+        let series: BTreeSet<String> = records
+            .iter()
+            .flat_map(|record| &record.points)
+            .map(|point| format!("{} ({})", point.measurement, point.tags["string"]))
+            .collect();
+        Ok(format!(
+            "Solar inverter export: {} ({} readings; {})",
+            self.file_path,
+            records.len(),
+            series.into_iter().collect::<Vec<_>>().join(", ")
+        ))
+    }
+
+    /// Reads the readings after the last imported timestamp; counters are compared with the
+    /// reading before, so the energy produced between runs is not lost
+    fn records_since(
+        &self,
+        watermark: &ImportState,
+    ) -> Result<RecordStream<SolarRecord>, Box<dyn Error>> {
+        let records = self.records()?;
+        let Some(last_imported) = watermark.last_imported_timestamp else {
+            return Ok(RecordStream::new(records));
+        };
+
+        let total = records.len();
+        let new_records: Vec<SolarRecord> = records
+            .into_iter()
+            .filter(|record| record.timestamp > last_imported)
+            .collect();
+        let already_imported = total - new_records.len();
+        Ok(RecordStream::new(new_records).with_already_imported(already_imported))
+    }
+
+    fn timestamp(&self, record: &SolarRecord) -> Option<DateTime<Utc>> {
+        Some(record.timestamp)
+    }
+
+    fn to_points(&self, record: &SolarRecord) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+        Ok(record.points.clone())
+    }
+}
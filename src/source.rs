@@ -0,0 +1,100 @@
+use crate::influx_client::DataPoint;
+use crate::state_management::ImportState;
+use chrono::{DateTime, Utc};
+use std::error::Error;
+
+/// A place records are imported from, such as a funds CSV export or a Health Connect database
+///
+/// The import commands drive every source the same way: validate it, read the records
+/// the import state does not cover yet, convert them to points and advance the state to
+/// the latest record written. Records are written oldest first, and the state only
+/// advances past records once every batch holding their points was written (or spooled)
+pub trait Source {
+    /// A single record read from the source
+    type Record: Send + 'static;
+
+    /// Checks that the source can be read, returning a short summary of what it holds
+    fn validate(&self) -> Result<String, Box<dyn Error>>;
+
+    /// Reads the records after the watermark kept in the import state
+    fn records_since(
+        &self,
+        watermark: &ImportState,
+    ) -> Result<RecordStream<Self::Record>, Box<dyn Error>>;
+
+    /// Gets the time of a record, if it has a valid one
+    fn timestamp(&self, record: &Self::Record) -> Option<DateTime<Utc>>;
+
+    /// Converts a record to the points written to InfluxDB
+    fn to_points(&self, record: &Self::Record) -> Result<Vec<DataPoint>, Box<dyn Error>>;
+
+    /// Gets the time of the newest record, which the watermark advances to once they are written
+    fn latest_timestamp(&self, records: &[Self::Record]) -> Option<DateTime<Utc>> {
+        records
+            .iter()
+            .filter_map(|record| self.timestamp(record))
+            .max()
+    }
+
+    /// Orders records oldest first, whatever their type, keeping the order of records of the
+    /// same time; records without a valid time come first, and fail to convert anyway
+    fn sort_by_time(&self, records: &mut [Self::Record]) {
+        records.sort_by_key(|record| self.timestamp(record));
+    }
+
+    /// Keeps the oldest `limit` records, plus any sharing the time of the last one kept,
+    /// since the next import skips everything at or before that time
+    fn oldest(&self, mut records: Vec<Self::Record>, limit: usize) -> Vec<Self::Record> {
+        if records.len() <= limit {
+            return records;
+        }
+        if limit == 0 {
+            return Vec::new();
+        }
+
+        records.sort_by_key(|record| self.timestamp(record));
+        let last_kept = self.timestamp(&records[limit - 1]);
+        let ties = records[limit..]
+            .iter()
+            .take_while(|record| self.timestamp(record) == last_kept)
+            .count();
+        records.truncate(limit + ties);
+        records
+    }
+}
+
+/// The records a source read since a watermark
+pub struct RecordStream<R> {
+    records: Box<dyn Iterator<Item = R> + Send>,
+    already_imported: usize,
+}
+
+impl<R: Send + 'static> RecordStream<R> {
+    /// Creates a stream of records
+    pub fn new(records: impl IntoIterator<Item = R, IntoIter: Send + 'static>) -> Self {
+        RecordStream {
+            records: Box::new(records.into_iter()),
+            already_imported: 0,
+        }
+    }
+
+    /// Notes how many records the source read but skipped because the watermark covers them
+    /// Sources that filter while querying cannot tell, and leave it at 0
+    pub fn with_already_imported(mut self, count: usize) -> Self {
+        self.already_imported = count;
+        self
+    }
+
+    /// Gets the number of records skipped because they were already imported
+    pub fn already_imported(&self) -> usize {
+        self.already_imported
+    }
+}
+
+impl<R> Iterator for RecordStream<R> {
+    type Item = R;
+
+    fn next(&mut self) -> Option<R> {
+        self.records.next()
+    }
+}
@@ -0,0 +1,53 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+
+/// Appends line protocol lines to the spool file, creating it if needed
+pub fn append_to_spool(
+    spool_file: &str,
+    lines: &[String],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(spool_file)?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Loads all spooled line protocol lines, skipping blank lines
+/// Returns an empty list if the spool file does not exist
+pub fn load_spool(spool_file: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    if !Path::new(spool_file).exists() {
+        return Ok(Vec::new());
+    }
+
+    let file = File::open(spool_file)?;
+    let mut lines = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+    Ok(lines)
+}
+
+/// Replaces the spool file contents with the given lines
+/// The spool file is removed when there is nothing left to retry
+pub fn save_spool(spool_file: &str, lines: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    if lines.is_empty() {
+        if Path::new(spool_file).exists() {
+            fs::remove_file(spool_file)?;
+        }
+        return Ok(());
+    }
+
+    let mut file = File::create(spool_file)?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,362 @@
+use crate::audit::{self, AuditEntry};
+use crate::health_data::HealthDataType;
+use crate::import::{EXIT_ERROR, EXIT_STATE_ERROR};
+use crate::output;
+use crate::state_management::{
+    read_state_file, rotate_state_backups, save_state_file, ImportState,
+};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{Args, Subcommand};
+use std::process;
+
+/// The subcommands of `state`
+#[derive(Subcommand)]
+pub enum StateCommands {
+    /// Show the contents of a state file and check it for problems
+    Show {
+        /// The state file to show
+        #[arg(short, long, required = true, env = "HDI_STATE_FILE")]
+        state_file: String,
+
+        /// Only show this source file; shows every source when omitted
+        #[arg(long, env = "HDI_SOURCE")]
+        source: Option<String>,
+    },
+
+    /// Clear the watermark of one data type, or of the whole source, to force a re-import
+    Reset {
+        /// The state file to modify
+        #[arg(short, long, required = true, env = "HDI_STATE_FILE")]
+        state_file: String,
+
+        /// The source file to modify (only needed when the state file tracks several)
+        #[arg(long, env = "HDI_SOURCE")]
+        source: Option<String>,
+
+        /// Number of rotated copies of the state file to keep before updating it (0 disables)
+        #[arg(long, default_value = "3", env = "HDI_STATE_BACKUPS")]
+        state_backups: usize,
+
+        /// Only reset this data type (e.g., HeartRate); resets everything when omitted
+        #[arg(long, ignore_case = true, env = "HDI_DATA_TYPE")]
+        data_type: Option<HealthDataType>,
+    },
+
+    /// Manually move a watermark backward or forward; records after it are imported next run
+    Set {
+        /// The state file to modify
+        #[arg(short, long, required = true, env = "HDI_STATE_FILE")]
+        state_file: String,
+
+        /// The source file to modify (only needed when the state file tracks several)
+        #[arg(long, env = "HDI_SOURCE")]
+        source: Option<String>,
+
+        /// Number of rotated copies of the state file to keep before updating it (0 disables)
+        #[arg(long, default_value = "3", env = "HDI_STATE_BACKUPS")]
+        state_backups: usize,
+
+        /// The new last imported timestamp (RFC 3339, e.g., 2024-06-01T00:00:00Z)
+        #[arg(long, required = true, env = "HDI_TIMESTAMP")]
+        timestamp: DateTime<Utc>,
+
+        /// Only move this data type (e.g., HeartRate); moves every watermark when omitted
+        #[arg(long, ignore_case = true, env = "HDI_DATA_TYPE")]
+        data_type: Option<HealthDataType>,
+    },
+}
+
+/// Runs a `state` subcommand on the state file it names, backing the file up before
+/// changing it
+pub fn run(action: StateCommands) {
+    match action {
+        StateCommands::Show { state_file, source } => {
+            println!("State file: '{}'", state_file);
+
+            let mut states = match read_state_file(&state_file) {
+                Ok(states) => states,
+                Err(e) => {
+                    eprintln!("Invalid state file: {}", e);
+                    process::exit(EXIT_STATE_ERROR);
+                }
+            };
+
+            let shown: Vec<&ImportState> = match &source {
+                Some(source) => match states.select(Some(source)) {
+                    Ok(state) => vec![state],
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(EXIT_ERROR);
+                    }
+                },
+                None => states.sources.values().collect(),
+            };
+
+            let mut problem_count = 0;
+            for state in shown {
+                println!();
+                print!("{}", state);
+
+                let problems = state.validate();
+                problem_count += problems.len();
+                for problem in &problems {
+                    println!("  {}", output::warning(problem));
+                }
+            }
+
+            if problem_count == 0 {
+                println!("\n{}", output::success("State file is valid"));
+            } else {
+                println!(
+                    "\n{}",
+                    output::warning(&format!("Found {} problem(s)", problem_count))
+                );
+            }
+        }
+
+        StateCommands::Reset {
+            state_file,
+            source,
+            state_backups,
+            data_type,
+        } => {
+            let mut states = match read_state_file(&state_file) {
+                Ok(states) => states,
+                Err(e) => {
+                    eprintln!("Invalid state file: {}", e);
+                    process::exit(EXIT_STATE_ERROR);
+                }
+            };
+            let state = match states.select(source.as_deref()) {
+                Ok(state) => state,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(EXIT_ERROR);
+                }
+            };
+
+            let (target, previous) = match &data_type {
+                Some(data_type) => match state.reset_data_type(data_type.as_str()) {
+                    Ok(previous) => (data_type.as_str(), previous),
+                    Err(e) => {
+                        eprintln!("Cannot reset {}: {}", data_type, e);
+                        process::exit(EXIT_ERROR);
+                    }
+                },
+                None => ("all data types", state.reset_all()),
+            };
+
+            match previous {
+                Some(ts) => println!("Reset {} (was last imported at {})", target, ts),
+                None => println!("Reset {} (nothing was imported yet)", target),
+            }
+            println!(
+                "Record counters were kept: {} records imported",
+                state.records_imported
+            );
+
+            backup_state_file(&state_file, state_backups);
+            match save_state_file(&states, &state_file) {
+                Ok(_) => println!("Updated import state saved to {}", state_file),
+                Err(e) => {
+                    eprintln!("Failed to save import state: {}", e);
+                    process::exit(EXIT_STATE_ERROR);
+                }
+            }
+        }
+
+        StateCommands::Set {
+            state_file,
+            source,
+            state_backups,
+            timestamp,
+            data_type,
+        } => {
+            let mut states = match read_state_file(&state_file) {
+                Ok(states) => states,
+                Err(e) => {
+                    eprintln!("Invalid state file: {}", e);
+                    process::exit(EXIT_STATE_ERROR);
+                }
+            };
+            let state = match states.select(source.as_deref()) {
+                Ok(state) => state,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    process::exit(EXIT_ERROR);
+                }
+            };
+
+            let (target, previous) = match &data_type {
+                Some(data_type) => {
+                    match state.set_data_type_watermark(data_type.as_str(), timestamp) {
+                        Ok(previous) => (data_type.as_str(), previous),
+                        Err(e) => {
+                            eprintln!("Cannot set watermark for {}: {}", data_type, e);
+                            process::exit(EXIT_ERROR);
+                        }
+                    }
+                }
+                None => ("all data types", state.set_all_watermarks(timestamp)),
+            };
+
+            match previous {
+                Some(ts) => println!("Moved {} from {} to {}", target, ts, timestamp),
+                None => println!("Set {} to {} (nothing was imported yet)", target, timestamp),
+            }
+
+            backup_state_file(&state_file, state_backups);
+            match save_state_file(&states, &state_file) {
+                Ok(_) => println!("Updated import state saved to {}", state_file),
+                Err(e) => {
+                    eprintln!("Failed to save import state: {}", e);
+                    process::exit(EXIT_STATE_ERROR);
+                }
+            }
+        }
+    }
+}
+
+/// Rotates the backups of a state file before it is updated, warning on failure
+fn backup_state_file(state_file: &str, backups: usize) {
+    if let Err(e) = rotate_state_backups(state_file, backups) {
+        eprintln!("Warning: failed to back up state file: {}", e);
+    }
+}
+
+/// The options of `history`
+#[derive(Args)]
+pub struct HistoryArgs {
+    /// The state file to read
+    #[arg(short, long, required = true, env = "HDI_STATE_FILE")]
+    pub state_file: String,
+
+    /// Number of runs to show, most recent first
+    #[arg(short = 'n', long, default_value = "10", env = "HDI_LIMIT")]
+    pub limit: usize,
+}
+
+/// Prints the most recent runs of the journal of a state file
+pub fn history(args: HistoryArgs) {
+    let HistoryArgs { state_file, limit } = args;
+    let states = match read_state_file(&state_file) {
+        Ok(states) => states,
+        Err(e) => {
+            eprintln!("Invalid state file: {}", e);
+            process::exit(EXIT_STATE_ERROR);
+        }
+    };
+
+    if states.journal.is_empty() {
+        println!("No runs recorded in {}", state_file);
+        return;
+    }
+
+    println!(
+        "Showing {} of {} recorded runs, most recent first:",
+        limit.min(states.journal.len()),
+        states.journal.len()
+    );
+    for entry in states.journal.iter().rev().take(limit) {
+        println!();
+        print!("{}", entry);
+    }
+}
+
+/// The options of `audit`
+#[derive(Args)]
+pub struct AuditArgs {
+    /// Only the writes of this run
+    #[arg(long, env = "HDI_RUN")]
+    pub run: Option<String>,
+
+    /// Only the writes to this measurement
+    #[arg(short, long, env = "HDI_MEASUREMENT")]
+    pub measurement: Option<String>,
+
+    /// Only the writes that may have written a point of --measurement at this time
+    /// (RFC 3339, e.g. 2024-03-01T08:00:00Z)
+    #[arg(long, requires = "measurement", env = "HDI_AT")]
+    pub at: Option<DateTime<Utc>>,
+
+    /// Number of writes to show
+    #[arg(short = 'n', long, default_value = "20", env = "HDI_LIMIT")]
+    pub limit: usize,
+}
+
+/// Prints the writes of the audit log that match the filters, most recent first
+pub fn audit(args: AuditArgs, audit_log: Option<&str>) {
+    let AuditArgs {
+        run,
+        measurement,
+        at,
+        limit,
+    } = args;
+    let Some(path) = audit_log else {
+        eprintln!("Give the audit log to read with --audit-log");
+        process::exit(EXIT_ERROR);
+    };
+    let entries = match audit::read_audit_log(path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("Failed to read the audit log {}: {}", path, e);
+            process::exit(EXIT_ERROR);
+        }
+    };
+
+    let matching: Vec<&AuditEntry> = entries
+        .iter()
+        .filter(|entry| run.as_deref().is_none_or(|run| entry.run_id == run))
+        .filter(|entry| {
+            measurement
+                .as_deref()
+                .is_none_or(|measurement| entry.measurement == measurement)
+        })
+        .filter(|entry| match (&measurement, at) {
+            (Some(measurement), Some(at)) => entry.covers(measurement, at),
+            _ => true,
+        })
+        .collect();
+    if matching.is_empty() {
+        println!("No matching writes recorded in {}", path);
+        return;
+    }
+
+    let time = |time: &DateTime<Utc>| time.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let rows: Vec<Vec<String>> = matching
+        .iter()
+        .rev()
+        .take(limit)
+        .map(|entry| {
+            vec![
+                entry.run_id.clone(),
+                time(&entry.written_at),
+                entry.bucket.clone(),
+                entry.measurement.clone(),
+                time(&entry.start),
+                time(&entry.end),
+                entry.points.to_string(),
+            ]
+        })
+        .collect();
+    println!(
+        "Showing {} of {} matching writes, most recent first:",
+        rows.len(),
+        matching.len()
+    );
+    println!(
+        "{}",
+        output::table(
+            &[
+                "Run",
+                "Written",
+                "Bucket",
+                "Measurement",
+                "First point",
+                "Last point",
+                "Points"
+            ],
+            &rows
+        )
+    );
+}
@@ -1,14 +1,86 @@
 use chrono::{DateTime, Utc};
-use std::fs::File;
-use std::io::{Read, Write};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read};
 use std::path::Path;
 
 /// Structure to hold import state information
-#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone)]
 pub struct ImportState {
     pub last_imported_timestamp: Option<DateTime<Utc>>,
     pub source_file: String,
     pub records_imported: usize,
+    /// Import progress for each data type (e.g., "HeartRate", "Steps")
+    #[serde(default)]
+    pub data_types: BTreeMap<String, DataTypeState>,
+    /// When an import last updated this state
+    #[serde(default)]
+    pub last_run: Option<DateTime<Utc>>,
+    /// Fingerprint of the source file contents at the last import
+    #[serde(default)]
+    pub source_fingerprint: Option<SourceFingerprint>,
+}
+
+/// Identifies the contents of a source file, so a replaced export can be told apart
+/// from the one the watermarks were recorded against
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone)]
+pub struct SourceFingerprint {
+    pub size: u64,
+    /// Hex-encoded SHA-256 of the file contents
+    pub sha256: String,
+}
+
+impl SourceFingerprint {
+    /// Computes the fingerprint of a file
+    pub fn compute(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let (size, sha256) = hash_reader(File::open(path)?)?;
+        Ok(SourceFingerprint { size, sha256 })
+    }
+
+    /// Checks whether the fingerprinted contents are still the start of a file,
+    /// i.e. the file is unchanged or only had data appended to it
+    pub fn is_prefix_of(&self, path: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let file = File::open(path)?;
+        if file.metadata()?.len() < self.size {
+            return Ok(false);
+        }
+        let (_, sha256) = hash_reader(file.take(self.size))?;
+        Ok(sha256 == self.sha256)
+    }
+}
+
+/// Hashes everything a reader returns, giving the number of bytes read and the hex digest
+fn hash_reader<R: Read>(mut reader: R) -> io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let size = io::copy(&mut reader, &mut hasher)?;
+    let sha256 = hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    Ok((size, sha256))
+}
+
+impl fmt::Display for SourceFingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} bytes, sha256 {}", self.size, self.sha256)
+    }
+}
+
+/// Import progress for a single data type
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct DataTypeState {
+    pub last_imported_timestamp: Option<DateTime<Utc>>,
+    pub records_imported: usize,
+    /// Highest source row_id imported, for sources that have one (health data tables)
+    #[serde(default)]
+    pub last_row_id: Option<i64>,
+    /// Latest modification time of the source records when they were last all imported,
+    /// for sources that track it (health data tables)
+    #[serde(default)]
+    pub last_modified_time: Option<DateTime<Utc>>,
 }
 
 impl ImportState {
@@ -17,21 +89,570 @@ impl ImportState {
             last_imported_timestamp: None,
             source_file: source_file.to_string(),
             records_imported: 0,
+            data_types: BTreeMap::new(),
+            last_run: None,
+            source_fingerprint: None,
         }
     }
+
+    /// Checks whether the source file was replaced since the state was last saved
+    /// A file that only had data appended to it (e.g., a growing CSV) is not considered
+    /// replaced, and neither is any file when the state has no fingerprint yet
+    pub fn source_replaced(&self, source_file: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        match &self.source_fingerprint {
+            Some(fingerprint) => Ok(!fingerprint.is_prefix_of(source_file)?),
+            None => Ok(false),
+        }
+    }
+
+    /// Gets the last imported timestamp for a data type
+    /// State files written before per-type tracking only have the global timestamp,
+    /// which is used for every type until the first per-type entry is recorded.
+    /// After that, a data type without an entry is imported from the beginning
+    pub fn last_imported_for(&self, data_type: &str) -> Option<DateTime<Utc>> {
+        match self.data_types.get(data_type) {
+            Some(type_state) => type_state.last_imported_timestamp,
+            None if self.data_types.is_empty() => self.last_imported_timestamp,
+            None => None,
+        }
+    }
+
+    /// Gets the last imported row_id for a data type, if one was recorded
+    pub fn last_row_id_for(&self, data_type: &str) -> Option<i64> {
+        self.data_types
+            .get(data_type)
+            .and_then(|type_state| type_state.last_row_id)
+    }
+
+    /// Advances the row_id a data type resumes from
+    pub fn record_row_id(&mut self, data_type: &str, row_id: i64) {
+        let type_state = self.data_types.entry(data_type.to_string()).or_default();
+        if type_state.last_row_id.is_none_or(|id| row_id > id) {
+            type_state.last_row_id = Some(row_id);
+        }
+    }
+
+    /// Gets the modification time after which records of a data type were changed since
+    /// its last import, if one was recorded
+    pub fn last_modified_for(&self, data_type: &str) -> Option<DateTime<Utc>> {
+        self.data_types
+            .get(data_type)
+            .and_then(|type_state| type_state.last_modified_time)
+    }
+
+    /// Advances the modification time after which records of a data type count as changed
+    pub fn record_modified(&mut self, data_type: &str, modified: DateTime<Utc>) {
+        let type_state = self.data_types.entry(data_type.to_string()).or_default();
+        if type_state
+            .last_modified_time
+            .is_none_or(|time| modified > time)
+        {
+            type_state.last_modified_time = Some(modified);
+        }
+    }
+
+    /// Records a successful import of `count` records of a data type, up to `latest`
+    /// The global timestamp and counter are kept in sync as the overall maximum and total
+    pub fn record_import(&mut self, data_type: &str, latest: DateTime<Utc>, count: usize) {
+        let type_state = self.data_types.entry(data_type.to_string()).or_default();
+        if type_state
+            .last_imported_timestamp
+            .is_none_or(|ts| latest > ts)
+        {
+            type_state.last_imported_timestamp = Some(latest);
+        }
+        type_state.records_imported += count;
+
+        if self.last_imported_timestamp.is_none_or(|ts| latest > ts) {
+            self.last_imported_timestamp = Some(latest);
+        }
+        self.records_imported += count;
+    }
+
+    /// Finds the entry for a data type, matching existing entries case-insensitively
+    /// like the --data-types filter, and creating it if needed
+    fn data_type_entry(
+        &mut self,
+        data_type: &str,
+    ) -> Result<&mut DataTypeState, Box<dyn std::error::Error>> {
+        if self.data_types.is_empty() {
+            // Adding the first entry would make every other type start from scratch too
+            return Err(
+                "State has no per-type entries yet; change the whole state or run an import first"
+                    .into(),
+            );
+        }
+
+        let key = self
+            .data_types
+            .keys()
+            .find(|k| k.eq_ignore_ascii_case(data_type))
+            .cloned()
+            .unwrap_or_else(|| data_type.to_string());
+
+        Ok(self.data_types.entry(key).or_default())
+    }
+
+    /// Clears the watermark of a single data type so it is imported from the beginning again
+    /// Record counters are kept. Returns the previous watermark
+    pub fn reset_data_type(
+        &mut self,
+        data_type: &str,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn std::error::Error>> {
+        let type_state = self.data_type_entry(data_type)?;
+        type_state.last_row_id = None;
+        type_state.last_modified_time = None;
+        Ok(type_state.last_imported_timestamp.take())
+    }
+
+    /// Moves the watermark of a single data type backward or forward
+    /// Records after `timestamp` will be imported on the next run. Returns the previous watermark
+    pub fn set_data_type_watermark(
+        &mut self,
+        data_type: &str,
+        timestamp: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn std::error::Error>> {
+        let type_state = self.data_type_entry(data_type)?;
+        // The row_id no longer matches the watermark, so resume from the timestamp
+        type_state.last_row_id = None;
+        type_state.last_modified_time = None;
+        let previous = type_state.last_imported_timestamp.replace(timestamp);
+
+        // Keep the global timestamp the maximum of all data types
+        if self.last_imported_timestamp.is_none_or(|ts| timestamp > ts) {
+            self.last_imported_timestamp = Some(timestamp);
+        }
+        Ok(previous)
+    }
+
+    /// Moves the global watermark and every per-type watermark to `timestamp`
+    /// Returns the previous global watermark
+    pub fn set_all_watermarks(&mut self, timestamp: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        for type_state in self.data_types.values_mut() {
+            type_state.last_imported_timestamp = Some(timestamp);
+            type_state.last_row_id = None;
+            type_state.last_modified_time = None;
+        }
+        self.last_imported_timestamp.replace(timestamp)
+    }
+
+    /// Clears every watermark so the whole source is imported again
+    /// Record counters are kept. Returns the previous global watermark
+    pub fn reset_all(&mut self) -> Option<DateTime<Utc>> {
+        for type_state in self.data_types.values_mut() {
+            type_state.last_imported_timestamp = None;
+            type_state.last_row_id = None;
+            type_state.last_modified_time = None;
+        }
+        self.last_imported_timestamp.take()
+    }
+
+    /// Checks a watermark against the source data and returns the problem found, if any
+    /// A watermark in the future or past the newest record in the source (e.g., after a clock
+    /// glitch) silently skips every new record, and so does a row_id past the highest one.
+    /// `data_type` selects a per-type watermark; None checks the global one. `latest` is the
+    /// newest record timestamp in the source and `max_row_id` its highest row_id, if known
+    pub fn check_watermark(
+        &self,
+        data_type: Option<&str>,
+        latest: Option<DateTime<Utc>>,
+        max_row_id: Option<i64>,
+    ) -> Option<String> {
+        let (watermark, row_id) = match data_type {
+            Some(data_type) => (
+                self.last_imported_for(data_type),
+                self.last_row_id_for(data_type),
+            ),
+            None => (self.last_imported_timestamp, None),
+        };
+
+        // Imports resume from the row_id when there is one, regardless of timestamps
+        if let Some(row_id) = row_id {
+            return match max_row_id {
+                Some(max_row_id) if row_id > max_row_id => Some(format!(
+                    "last imported row_id {} is higher than the highest row_id in the source ({})",
+                    row_id, max_row_id
+                )),
+                _ => None,
+            };
+        }
+
+        let watermark = watermark?;
+        if watermark > Utc::now() {
+            return Some(format!(
+                "last imported timestamp {} is in the future",
+                watermark
+            ));
+        }
+        match latest {
+            Some(latest) if watermark > latest => Some(format!(
+                "last imported timestamp {} is newer than the newest record in the source ({})",
+                watermark, latest
+            )),
+            _ => None,
+        }
+    }
+
+    /// Clears a watermark found invalid by `check_watermark`, so it is imported from the
+    /// beginning again. State files without per-type entries are cleared as a whole
+    pub fn clear_watermark(&mut self, data_type: Option<&str>) {
+        match data_type {
+            Some(data_type) if !self.data_types.is_empty() => {
+                // Cannot fail once per-type entries exist
+                let _ = self.reset_data_type(data_type);
+            }
+            _ => {
+                self.reset_all();
+            }
+        }
+    }
+
+    /// Checks the state for inconsistencies and returns a list of problems found
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let now = Utc::now();
+
+        if self.source_file.is_empty() {
+            problems.push("Source file is empty".to_string());
+        } else if !Path::new(&self.source_file).exists() {
+            problems.push(format!("Source file does not exist: {}", self.source_file));
+        }
+
+        if let Some(ts) = self.last_imported_timestamp {
+            if ts > now {
+                problems.push(format!("Last imported timestamp is in the future: {}", ts));
+            }
+        }
+
+        for (data_type, type_state) in &self.data_types {
+            if let Some(ts) = type_state.last_imported_timestamp {
+                if ts > now {
+                    problems.push(format!(
+                        "{}: last imported timestamp is in the future: {}",
+                        data_type, ts
+                    ));
+                }
+                if self
+                    .last_imported_timestamp
+                    .is_none_or(|global| ts > global)
+                {
+                    problems.push(format!(
+                        "{}: last imported timestamp {} is newer than the global timestamp",
+                        data_type, ts
+                    ));
+                }
+            }
+        }
+
+        let per_type_records: usize = self.data_types.values().map(|t| t.records_imported).sum();
+        if per_type_records > self.records_imported {
+            problems.push(format!(
+                "Per-type record counts ({}) exceed the total records imported ({})",
+                per_type_records, self.records_imported
+            ));
+        }
+
+        problems
+    }
 }
 
-/// Loads the import state from a file
+impl fmt::Display for ImportState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Source: {}", self.source_file)?;
+        match &self.source_fingerprint {
+            Some(fingerprint) => writeln!(f, "Source fingerprint: {}", fingerprint)?,
+            None => writeln!(f, "Source fingerprint: none")?,
+        }
+        match self.last_run {
+            Some(ts) => writeln!(f, "Last run: {}", ts)?,
+            None => writeln!(f, "Last run: never")?,
+        }
+        match self.last_imported_timestamp {
+            Some(ts) => writeln!(f, "Last imported timestamp: {}", ts)?,
+            None => writeln!(f, "Last imported timestamp: none")?,
+        }
+        writeln!(f, "Records imported: {}", self.records_imported)?;
+
+        if self.data_types.is_empty() {
+            writeln!(f, "Data types: none tracked")?;
+        } else {
+            writeln!(f, "Data types:")?;
+            for (data_type, type_state) in &self.data_types {
+                let row_id = match type_state.last_row_id {
+                    Some(row_id) => format!(" (row_id {})", row_id),
+                    None => String::new(),
+                };
+                match type_state.last_imported_timestamp {
+                    Some(ts) => writeln!(
+                        f,
+                        "  - {}: {} records, last imported {}{}",
+                        data_type, type_state.records_imported, ts, row_id
+                    )?,
+                    None => writeln!(
+                        f,
+                        "  - {}: {} records, nothing imported yet",
+                        data_type, type_state.records_imported
+                    )?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Number of runs kept in the journal of a state file
+pub const MAX_JOURNAL_ENTRIES: usize = 100;
+
+/// All import states kept in a single state file, keyed by source file path
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Default)]
+pub struct StateFile {
+    pub sources: BTreeMap<String, ImportState>,
+    /// The most recent import runs, oldest first
+    #[serde(default)]
+    pub journal: Vec<JournalEntry>,
+}
+
+/// Statistics about a single import run
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone)]
+pub struct JournalEntry {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// The subcommand that was run (e.g., "import-health-data")
+    pub command: String,
+    pub source_file: String,
+    /// Where the data was written to (URL and bucket or database)
+    pub target: String,
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Filters that limited what was imported (e.g., "data types: HeartRate")
+    #[serde(default)]
+    pub filters: Vec<String>,
+    /// Number of records written for each data type or measurement
+    #[serde(default)]
+    pub records: BTreeMap<String, usize>,
+    /// Number of source records left out, by reason (e.g., "already imported")
+    #[serde(default)]
+    pub skipped: BTreeMap<String, usize>,
+    #[serde(default)]
+    pub errors: Vec<String>,
+}
+
+impl JournalEntry {
+    /// Starts a journal entry for a run beginning now
+    pub fn new(command: &str, source_file: &str, target: &str) -> Self {
+        JournalEntry {
+            started_at: Utc::now(),
+            finished_at: None,
+            command: command.to_string(),
+            source_file: source_file.to_string(),
+            target: target.to_string(),
+            dry_run: false,
+            filters: Vec::new(),
+            records: BTreeMap::new(),
+            skipped: BTreeMap::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn total_records(&self) -> usize {
+        self.records.values().sum()
+    }
+}
+
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let outcome = if self.errors.is_empty() {
+            "OK"
+        } else {
+            "FAILED"
+        };
+        let mode = if self.dry_run { " (dry run)" } else { "" };
+        writeln!(
+            f,
+            "{} {}{} - {}",
+            self.started_at.format("%Y-%m-%d %H:%M:%S UTC"),
+            self.command,
+            mode,
+            outcome
+        )?;
+        writeln!(f, "  Source: {}", self.source_file)?;
+        writeln!(f, "  Target: {}", self.target)?;
+        if let Some(finished_at) = self.finished_at {
+            writeln!(
+                f,
+                "  Duration: {}s",
+                (finished_at - self.started_at).num_seconds()
+            )?;
+        }
+        for filter in &self.filters {
+            writeln!(f, "  Filter: {}", filter)?;
+        }
+        if self.records.is_empty() {
+            writeln!(f, "  Records: none")?;
+        } else {
+            let counts: Vec<String> = self
+                .records
+                .iter()
+                .map(|(data_type, count)| format!("{}={}", data_type, count))
+                .collect();
+            writeln!(
+                f,
+                "  Records: {} ({} total)",
+                counts.join(", "),
+                self.total_records()
+            )?;
+        }
+        if !self.skipped.is_empty() {
+            let counts: Vec<String> = self
+                .skipped
+                .iter()
+                .map(|(reason, count)| format!("{}={}", reason, count))
+                .collect();
+            writeln!(f, "  Skipped: {}", counts.join(", "))?;
+        }
+        for error in &self.errors {
+            writeln!(f, "  Error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The summary of a run written by --report-file: its journal entry, with the import
+/// state from before and after the run
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone)]
+pub struct RunReport {
+    #[serde(flatten)]
+    pub run: JournalEntry,
+    pub duration_seconds: Option<f64>,
+    pub state_before: Option<ImportState>,
+    /// None when the run did not save the state (e.g., a dry run or a failed import)
+    pub state_after: Option<ImportState>,
+}
+
+impl RunReport {
+    pub fn new(
+        run: JournalEntry,
+        state_before: Option<ImportState>,
+        state_after: Option<ImportState>,
+    ) -> Self {
+        let duration_seconds = run
+            .finished_at
+            .map(|finished_at| (finished_at - run.started_at).num_milliseconds() as f64 / 1000.0);
+        RunReport {
+            run,
+            duration_seconds,
+            state_before,
+            state_after,
+        }
+    }
+
+    /// Writes the report as JSON, replacing the file in one step so readers never see
+    /// half of it
+    pub fn write(&self, report_file: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let temp_file = format!("{}.tmp", report_file);
+        fs::write(&temp_file, serde_json::to_string_pretty(self)?)?;
+        fs::rename(&temp_file, report_file)?;
+        Ok(())
+    }
+}
+
+impl StateFile {
+    /// Finds the key of the state for a source file
+    /// Sources are matched by path first, then by fingerprint, so a renamed file
+    /// (possibly with new data appended) keeps its import progress. Only sources whose
+    /// file is gone can be matched by fingerprint: a source still present keeps its state,
+    /// even when the new file is an appended copy of it
+    pub fn find_key(&self, source_file: &str) -> Option<String> {
+        if self.sources.contains_key(source_file) {
+            return Some(source_file.to_string());
+        }
+
+        self.sources
+            .iter()
+            .filter(|(key, _)| !Path::new(key.as_str()).exists())
+            .find(|(_, state)| {
+                state
+                    .source_fingerprint
+                    .as_ref()
+                    // An empty file is the start of every file
+                    .filter(|fingerprint| fingerprint.size > 0)
+                    .is_some_and(|fingerprint| {
+                        fingerprint.is_prefix_of(source_file).unwrap_or(false)
+                    })
+            })
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Removes and returns the state for a source file, updated to the given path
+    pub fn take(&mut self, source_file: &str) -> Option<ImportState> {
+        let key = self.find_key(source_file)?;
+        let mut state = self.sources.remove(&key)?;
+        state.source_file = source_file.to_string();
+        Some(state)
+    }
+
+    /// Adds or replaces the state for its source file
+    pub fn insert(&mut self, state: ImportState) {
+        self.sources.insert(state.source_file.clone(), state);
+    }
+
+    /// Appends a run to the journal, dropping the oldest runs beyond `MAX_JOURNAL_ENTRIES`
+    pub fn append_journal(&mut self, entry: JournalEntry) {
+        self.journal.push(entry);
+        if self.journal.len() > MAX_JOURNAL_ENTRIES {
+            let excess = self.journal.len() - MAX_JOURNAL_ENTRIES;
+            self.journal.drain(..excess);
+        }
+    }
+
+    /// Picks a single source to inspect or modify
+    /// The source can be omitted when the state file only tracks one
+    pub fn select(
+        &mut self,
+        source_file: Option<&str>,
+    ) -> Result<&mut ImportState, Box<dyn std::error::Error>> {
+        let key = match source_file {
+            Some(source_file) => self
+                .find_key(source_file)
+                .ok_or_else(|| format!("No state recorded for source: {}", source_file))?,
+            None if self.sources.len() == 1 => self.sources.keys().next().unwrap().clone(),
+            None if self.sources.is_empty() => return Err("State file has no sources".into()),
+            None => {
+                let sources: Vec<&str> = self.sources.keys().map(|k| k.as_str()).collect();
+                return Err(format!(
+                    "State file tracks several sources, pick one with --source: {}",
+                    sources.join(", ")
+                )
+                .into());
+            }
+        };
+        Ok(self.sources.get_mut(&key).unwrap())
+    }
+}
+
+/// Parses the contents of a state file
+/// Files written before multi-source support hold a single `ImportState`
+pub fn parse_state_file(contents: &str) -> Result<StateFile, serde_json::Error> {
+    let value: serde_json::Value = serde_json::from_str(contents)?;
+    if value.get("sources").is_some() {
+        serde_json::from_value(value)
+    } else {
+        let mut state_file = StateFile::default();
+        state_file.insert(serde_json::from_value(value)?);
+        Ok(state_file)
+    }
+}
+
+/// Loads the import state for a source file from a state file
 pub fn load_import_state(state_file: &str, source_file: &str) -> ImportState {
     if Path::new(state_file).exists() {
         match File::open(state_file) {
             Ok(mut file) => {
                 let mut contents = String::new();
                 if file.read_to_string(&mut contents).is_ok() {
-                    match serde_json::from_str::<ImportState>(&contents) {
-                        Ok(state) => {
-                            // Only use the state if it's for the same source file
-                            if state.source_file == source_file {
+                    match parse_state_file(&contents) {
+                        Ok(mut states) => {
+                            if let Some(state) = states.take(source_file) {
                                 return state;
                             }
                         }
@@ -51,13 +672,103 @@ pub fn load_import_state(state_file: &str, source_file: &str) -> ImportState {
     ImportState::new(source_file)
 }
 
-/// Saves the import state to a file
+/// Reads a state file, returning an error if it is missing or invalid
+/// Unlike `load_import_state`, this never falls back to a new state
+pub fn read_state_file(state_file: &str) -> Result<StateFile, Box<dyn std::error::Error>> {
+    if !Path::new(state_file).exists() {
+        return Err(format!("State file does not exist: {}", state_file).into());
+    }
+
+    let mut contents = String::new();
+    File::open(state_file)?.read_to_string(&mut contents)?;
+    Ok(parse_state_file(&contents)?)
+}
+
+/// Writes every source of a state file, replacing it in one step so a crash or Ctrl-C
+/// while writing never leaves it truncated
+pub fn save_state_file(
+    states: &StateFile,
+    state_file: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let temp_file = format!("{}.tmp", state_file);
+    fs::write(&temp_file, serde_json::to_string_pretty(states)?)?;
+    fs::rename(&temp_file, state_file)?;
+    Ok(())
+}
+
+/// Path of the n-th rotated backup of a state file (1 is the most recent)
+pub fn state_backup_path(state_file: &str, n: usize) -> String {
+    format!("{}.{}", state_file, n)
+}
+
+/// Keeps up to `backups` rotated copies of a state file before it is updated
+/// The current file becomes `<state_file>.1` and older copies shift up by one
+pub fn rotate_state_backups(state_file: &str, backups: usize) -> io::Result<()> {
+    if backups == 0 || !Path::new(state_file).exists() {
+        return Ok(());
+    }
+
+    for n in (1..backups).rev() {
+        let from = state_backup_path(state_file, n);
+        if Path::new(&from).exists() {
+            fs::rename(&from, state_backup_path(state_file, n + 1))?;
+        }
+    }
+    fs::copy(state_file, state_backup_path(state_file, 1))?;
+    Ok(())
+}
+
+/// Reads a state file to update it, starting a new one if it does not exist yet
+/// An unreadable file is an error, as saving over it would lose every other source
+fn read_state_file_to_update(state_file: &str) -> Result<StateFile, Box<dyn std::error::Error>> {
+    if !Path::new(state_file).exists() {
+        return Ok(StateFile::default());
+    }
+    read_state_file(state_file).map_err(|e| {
+        format!(
+            "Cannot update the state file {}, as it could not be read: {}",
+            state_file, e
+        )
+        .into()
+    })
+}
+
+/// Path of the file locked while a state file is updated
+pub fn state_lock_path(state_file: &str) -> String {
+    format!("{}.lock", state_file)
+}
+
+/// Reads, changes and saves a state file while holding an exclusive lock on its lock
+/// file, so imports sharing the state file (e.g. `run-all --parallel`) never save over
+/// each other's updates
+fn update_state_file(
+    state_file: &str,
+    update: impl FnOnce(&mut StateFile),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let lock = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(state_lock_path(state_file))?;
+    lock.lock()?;
+    let mut states = read_state_file_to_update(state_file)?;
+    update(&mut states);
+    save_state_file(&states, state_file)
+}
+
+/// Saves the import state of one source, keeping the other sources in the state file
+/// Fails without touching the state file when it exists but cannot be read
 pub fn save_import_state(
     state: &ImportState,
     state_file: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let json = serde_json::to_string_pretty(state)?;
-    let mut file = File::create(state_file)?;
-    file.write_all(json.as_bytes())?;
-    Ok(())
+    update_state_file(state_file, |states| {
+        states.take(&state.source_file);
+        states.insert(state.clone());
+    })
+}
+
+/// Appends a run to the journal of a state file, keeping its import states
+pub fn record_run(state_file: &str, entry: JournalEntry) -> Result<(), Box<dyn std::error::Error>> {
+    update_state_file(state_file, |states| states.append_journal(entry))
 }
@@ -0,0 +1,417 @@
+use crate::influx_client::{InfluxClient, MAX_STRING_FIELD_BYTES, STATE_MEASUREMENT};
+use crate::remote::{parse_s3_url, s3_request, S3Config};
+use crate::state_management::{
+    load_import_state, parse_state_file, record_run, rotate_state_backups, save_import_state,
+    ImportState, JournalEntry, StateFile,
+};
+use std::error::Error;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// How many times a remote state update is retried when another machine changed the state
+/// document between reading and writing it
+const MAX_UPDATE_ATTEMPTS: usize = 5;
+
+/// Where import state is kept
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateBackend {
+    /// A local JSON file (the --state-file path)
+    File,
+    /// The `importer_state` measurement of the target bucket or database
+    InfluxDb,
+    /// A JSON document read with GET and written with PUT (e.g., on a WebDAV share)
+    Http(String),
+    /// A JSON document stored as an S3 object, given as an s3://bucket/key URL
+    S3(String),
+}
+
+impl FromStr for StateBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "file" => Ok(StateBackend::File),
+            "influxdb" => Ok(StateBackend::InfluxDb),
+            url if url.starts_with("http://") || url.starts_with("https://") => {
+                Ok(StateBackend::Http(url.to_string()))
+            }
+            url if parse_s3_url(url).is_some() => Ok(StateBackend::S3(url.to_string())),
+            other => Err(format!(
+                "unknown state backend '{}': expected file, influxdb, an http(s) URL or an s3://bucket/key URL",
+                other
+            )),
+        }
+    }
+}
+
+enum Location {
+    File(String),
+    InfluxDb {
+        client: InfluxClient,
+        name: String,
+    },
+    Http {
+        http_client: reqwest::Client,
+        url: reqwest::Url,
+        username: String,
+        password: Option<String>,
+    },
+    S3 {
+        http_client: reqwest::Client,
+        s3: S3Config,
+        bucket: String,
+        key: String,
+    },
+}
+
+/// The version of a remote state document that an update is based on, so the update is
+/// only written if nobody changed the document since it was read
+enum Version {
+    /// No document was stored yet
+    Missing,
+    /// The document with this ETag
+    ETag(String),
+    /// A document without an ETag (always the case in InfluxDB), written unconditionally
+    Unknown,
+}
+
+/// Serializes states for the InfluxDB backend, which keeps them in a single string field:
+/// compact, since line protocol values cannot span lines, and without the oldest runs of the
+/// journal when it would not fit otherwise. States that do not fit even without a journal
+/// are an error
+pub fn influxdb_state_document(states: &StateFile) -> Result<String, Box<dyn Error>> {
+    let mut document = serde_json::to_string(states)?;
+    if document.len() <= MAX_STRING_FIELD_BYTES {
+        return Ok(document);
+    }
+    let mut trimmed = StateFile {
+        sources: states.sources.clone(),
+        journal: states.journal.clone(),
+    };
+    while document.len() > MAX_STRING_FIELD_BYTES && !trimmed.journal.is_empty() {
+        trimmed.journal.remove(0);
+        document = serde_json::to_string(&trimmed)?;
+    }
+    if document.len() > MAX_STRING_FIELD_BYTES {
+        return Err(format!(
+            "the import state of {} sources is {} bytes, more than the {} bytes the influxdb state backend can store; use another state backend",
+            trimmed.sources.len(),
+            document.len(),
+            MAX_STRING_FIELD_BYTES
+        )
+        .into());
+    }
+    Ok(document)
+}
+
+/// Builds the client for HTTP and S3 state backends
+fn http_client(
+    connect_timeout: Option<Duration>,
+    request_timeout: Option<Duration>,
+) -> reqwest::Result<reqwest::Client> {
+    let mut builder = reqwest::Client::builder();
+    if let Some(timeout) = connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = request_timeout {
+        builder = builder.timeout(timeout);
+    }
+    builder.build()
+}
+
+/// Reads and writes import state on one of the supported backends
+/// Remote backends store the whole state file as a single JSON document
+pub struct StateStore {
+    location: Location,
+    /// The import state as first loaded and as last saved, for run reports
+    loaded: Mutex<Option<ImportState>>,
+    saved: Mutex<Option<ImportState>>,
+}
+
+impl StateStore {
+    fn new(location: Location) -> Self {
+        StateStore {
+            location,
+            loaded: Mutex::new(None),
+            saved: Mutex::new(None),
+        }
+    }
+
+    /// Keeps state in a local file
+    pub fn file(state_file: &str) -> Self {
+        Self::new(Location::File(state_file.to_string()))
+    }
+
+    /// Keeps state in InfluxDB, under the given name (usually the --state-file value)
+    pub fn influxdb(client: InfluxClient, name: &str) -> Self {
+        Self::new(Location::InfluxDb {
+            client,
+            name: name.to_string(),
+        })
+    }
+
+    /// Keeps state at an HTTP URL. Credentials in the URL are sent with basic authentication
+    pub fn http(
+        url: &str,
+        connect_timeout: Option<Duration>,
+        request_timeout: Option<Duration>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut url = reqwest::Url::parse(url)?;
+        let username = url.username().to_string();
+        let password = url.password().map(|p| p.to_string());
+        url.set_username("")
+            .map_err(|_| format!("Invalid state URL: {}", url))?;
+        url.set_password(None)
+            .map_err(|_| format!("Invalid state URL: {}", url))?;
+
+        Ok(Self::new(Location::Http {
+            http_client: http_client(connect_timeout, request_timeout)?,
+            url,
+            username,
+            password,
+        }))
+    }
+
+    /// Keeps state in an S3 object, given as an s3://bucket/key URL, on the service and with
+    /// the credentials of `s3`
+    pub fn s3(
+        url: &str,
+        s3: S3Config,
+        connect_timeout: Option<Duration>,
+        request_timeout: Option<Duration>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (bucket, key) =
+            parse_s3_url(url).ok_or_else(|| format!("{} is not an s3://bucket/key URL", url))?;
+        Ok(Self::new(Location::S3 {
+            http_client: http_client(connect_timeout, request_timeout)?,
+            s3,
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        }))
+    }
+
+    /// Describes where state is kept, without credentials
+    pub fn describe(&self) -> String {
+        match &self.location {
+            Location::File(path) => path.clone(),
+            Location::InfluxDb { name, .. } => {
+                format!("InfluxDB measurement {} ({})", STATE_MEASUREMENT, name)
+            }
+            Location::Http { url, .. } => url.to_string(),
+            Location::S3 { bucket, key, .. } => format!("s3://{}/{}", bucket, key),
+        }
+    }
+
+    /// Reads the state document of a remote backend, if one was stored, with its version
+    async fn read_document(&self) -> Result<(Option<String>, Version), Box<dyn Error>> {
+        let request = match &self.location {
+            Location::File(_) => unreachable!("state files are read directly"),
+            Location::InfluxDb { client, name } => {
+                let document = client.read_state_document(name).await?;
+                return Ok((document, Version::Unknown));
+            }
+            Location::Http {
+                http_client,
+                url,
+                username,
+                password,
+            } => {
+                let request = http_client.get(url.clone());
+                if username.is_empty() {
+                    request
+                } else {
+                    request.basic_auth(username, password.as_ref())
+                }
+            }
+            Location::S3 {
+                http_client,
+                s3,
+                bucket,
+                key,
+            } => s3_request(http_client, s3, reqwest::Method::GET, bucket, key, None)?,
+        };
+        let response = request.send().await?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::NOT_FOUND {
+            return Ok((None, Version::Missing));
+        }
+        if !status.is_success() {
+            return Err(
+                format!("Failed to read state from {} ({})", self.describe(), status).into(),
+            );
+        }
+        let version = match response.headers().get("etag").and_then(|v| v.to_str().ok()) {
+            Some(etag) => Version::ETag(etag.to_string()),
+            None => Version::Unknown,
+        };
+        Ok((Some(response.text().await?), version))
+    }
+
+    /// Writes the state document of a remote backend, unless it changed since `version` was
+    /// read. Returns whether it was written
+    async fn write_document(
+        &self,
+        states: &StateFile,
+        version: &Version,
+    ) -> Result<bool, Box<dyn Error>> {
+        let request = match &self.location {
+            Location::File(_) => unreachable!("state files are written directly"),
+            Location::InfluxDb { client, name } => {
+                let document = influxdb_state_document(states)?;
+                client.write_state_document(name, &document).await?;
+                return Ok(true);
+            }
+            Location::Http {
+                http_client,
+                url,
+                username,
+                password,
+            } => {
+                let request = http_client
+                    .put(url.clone())
+                    .body(serde_json::to_string_pretty(states)?);
+                if username.is_empty() {
+                    request
+                } else {
+                    request.basic_auth(username, password.as_ref())
+                }
+            }
+            Location::S3 {
+                http_client,
+                s3,
+                bucket,
+                key,
+            } => {
+                let document = serde_json::to_string_pretty(states)?.into_bytes();
+                s3_request(
+                    http_client,
+                    s3,
+                    reqwest::Method::PUT,
+                    bucket,
+                    key,
+                    Some(document),
+                )?
+            }
+        };
+        let request = match version {
+            Version::Missing => request.header("If-None-Match", "*"),
+            Version::ETag(etag) => request.header("If-Match", etag),
+            Version::Unknown => request,
+        };
+        let response = request
+            .header("Content-Type", "application/json")
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status == reqwest::StatusCode::PRECONDITION_FAILED {
+            return Ok(false);
+        }
+        if !status.is_success() {
+            return Err(
+                format!("Failed to write state to {} ({})", self.describe(), status).into(),
+            );
+        }
+        Ok(true)
+    }
+
+    /// Reads all states of a remote backend, with the version of their document
+    /// Unlike a local file, a state that cannot be read is an error rather than a fresh start,
+    /// so an unreachable server never leads to the shared state being overwritten
+    async fn read_states(&self) -> Result<(StateFile, Version), Box<dyn Error>> {
+        match self.read_document().await? {
+            (Some(document), version) if !document.trim().is_empty() => {
+                Ok((parse_state_file(&document)?, version))
+            }
+            (_, version) => Ok((StateFile::default(), version)),
+        }
+    }
+
+    /// Reads, changes and writes the states of a remote backend. When another machine
+    /// changed them in between, the change is made again on top of its update
+    async fn update_states(&self, update: impl Fn(&mut StateFile)) -> Result<(), Box<dyn Error>> {
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let (mut states, version) = self.read_states().await?;
+            update(&mut states);
+            if self.write_document(&states, &version).await? {
+                return Ok(());
+            }
+        }
+        Err(format!(
+            "Failed to update the state at {}: it kept changing while it was being updated",
+            self.describe()
+        )
+        .into())
+    }
+
+    /// Loads the import state for a source file
+    pub async fn load_import_state(
+        &self,
+        source_file: &str,
+    ) -> Result<ImportState, Box<dyn Error>> {
+        let state = match &self.location {
+            Location::File(path) => load_import_state(path, source_file),
+            _ => {
+                let (mut states, _) = self.read_states().await?;
+                states
+                    .take(source_file)
+                    .unwrap_or_else(|| ImportState::new(source_file))
+            }
+        };
+        if let Ok(mut loaded) = self.loaded.lock() {
+            loaded.get_or_insert_with(|| state.clone());
+        }
+        Ok(state)
+    }
+
+    /// Saves the import state of one source, keeping the other sources
+    pub async fn save_import_state(&self, state: &ImportState) -> Result<(), Box<dyn Error>> {
+        match &self.location {
+            Location::File(path) => save_import_state(state, path)?,
+            _ => {
+                self.update_states(|states| {
+                    states.take(&state.source_file);
+                    states.insert(state.clone());
+                })
+                .await?
+            }
+        }
+        if let Ok(mut saved) = self.saved.lock() {
+            *saved = Some(state.clone());
+        }
+        Ok(())
+    }
+
+    /// The import state as this store first loaded it, if it loaded one
+    pub fn loaded_state(&self) -> Option<ImportState> {
+        self.loaded.lock().ok()?.clone()
+    }
+
+    /// The import state this store last saved, if it saved one
+    pub fn saved_state(&self) -> Option<ImportState> {
+        self.saved.lock().ok()?.clone()
+    }
+
+    /// Appends a run to the journal
+    pub async fn record_run(&self, entry: JournalEntry) -> Result<(), Box<dyn Error>> {
+        match &self.location {
+            Location::File(path) => record_run(path, entry),
+            _ => {
+                self.update_states(|states| states.append_journal(entry.clone()))
+                    .await
+            }
+        }
+    }
+
+    /// Rotates backups before the state is updated
+    /// Only local files are rotated: InfluxDB keeps every document as a separate point,
+    /// and HTTP servers and S3 buckets are expected to handle versioning themselves
+    pub fn backup(&self, backups: usize) -> std::io::Result<()> {
+        match &self.location {
+            Location::File(path) => rotate_state_backups(path, backups),
+            _ => Ok(()),
+        }
+    }
+}
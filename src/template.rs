@@ -0,0 +1,154 @@
+use crate::convert::{HeaderRole, DEFAULT_FUND_TAG_KEY};
+use crate::influx_client::MultiFieldPoint;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::Write;
+
+/// The tags and fields of the series of a measurement, read from InfluxDB, laid out as a
+/// funds CSV file (`import-funds`) whose columns write to those series: one header row per
+/// tag and one column per series
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeasurementTemplate {
+    pub measurement: String,
+    /// Keys of the tags, one header row each: the fund tag, `account` and `currency` first,
+    /// then the others by name
+    pub tag_keys: Vec<String>,
+    /// Tags of each series, one column each, in the order of `tag_keys`
+    pub series: Vec<BTreeMap<String, String>>,
+    /// Names of the fields of the measurement
+    pub fields: BTreeSet<String>,
+    /// Key of the tag naming the fund
+    pub tag_key: String,
+}
+
+impl MeasurementTemplate {
+    /// Gets the layout of a measurement from points of each of its series, e.g. from
+    /// `InfluxClient::first_points`, with `tag_key` naming the fund
+    pub fn from_points(measurement: &str, points: &[MultiFieldPoint], tag_key: &str) -> Self {
+        let mut series = BTreeSet::new();
+        let mut fields = BTreeSet::new();
+        for point in points
+            .iter()
+            .filter(|point| point.measurement == measurement)
+        {
+            series.insert(
+                point
+                    .tags
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect::<BTreeMap<_, _>>(),
+            );
+            fields.extend(point.fields.keys().cloned());
+        }
+
+        let rank = |key: &str| match key {
+            key if key == tag_key => 0,
+            "account" => 1,
+            "currency" => 2,
+            _ => 3,
+        };
+        let mut tag_keys: Vec<String> = series
+            .iter()
+            .flat_map(|tags| tags.keys().cloned())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        tag_keys.sort_by_key(|key| rank(key));
+
+        let value_of = |tags: &BTreeMap<String, String>| -> Vec<String> {
+            tag_keys
+                .iter()
+                .map(|key| tags.get(key).cloned().unwrap_or_default())
+                .collect()
+        };
+        let mut series: Vec<BTreeMap<String, String>> = series.into_iter().collect();
+        series.sort_by_key(|tags| value_of(tags));
+
+        Self {
+            measurement: measurement.to_string(),
+            tag_keys,
+            series,
+            fields,
+            tag_key: tag_key.to_string(),
+        }
+    }
+
+    /// Gets the role of each header row, from the top
+    pub fn header_roles(&self) -> Vec<HeaderRole> {
+        if self.tag_keys.is_empty() {
+            return vec![HeaderRole::Ignore];
+        }
+        self.tag_keys
+            .iter()
+            .map(|key| match key.as_str() {
+                key if key == self.tag_key => HeaderRole::Fund,
+                "account" => HeaderRole::Account,
+                "currency" => HeaderRole::Currency,
+                key => HeaderRole::Tag(key.to_string()),
+            })
+            .collect()
+    }
+
+    /// Writes the header rows of the CSV file: `time_column`, then a column per series
+    /// holding its tags, from the top in the order of the header roles. A measurement
+    /// without tags gets a single header row naming a single column after the measurement
+    pub fn write_csv_header<W: Write>(
+        &self,
+        time_column: &str,
+        writer: W,
+    ) -> Result<(), Box<dyn Error>> {
+        let mut csv_writer = csv::Writer::from_writer(writer);
+        if self.tag_keys.is_empty() {
+            csv_writer.write_record([time_column, self.measurement.as_str()])?;
+        }
+        for (row, key) in self.tag_keys.iter().enumerate() {
+            let mut cells = vec![if row == 0 { time_column } else { "" }];
+            cells.extend(
+                self.series
+                    .iter()
+                    .map(|tags| tags.get(key).map_or("", String::as_str)),
+            );
+            csv_writer.write_record(&cells)?;
+        }
+        csv_writer.flush()?;
+        Ok(())
+    }
+
+    /// Gets the fields import-funds cannot write, as it writes a single `value` field
+    pub fn other_fields(&self) -> Vec<&str> {
+        self.fields
+            .iter()
+            .map(String::as_str)
+            .filter(|field| *field != "value")
+            .collect()
+    }
+
+    /// Gets a `[funds]` section of the config file that imports `source`, a CSV file
+    /// starting with the header rows of [`Self::write_csv_header`], into the measurement
+    pub fn config_snippet(&self, source: &str, time_column: &str, time_format: &str) -> String {
+        let roles: Vec<String> = self
+            .header_roles()
+            .iter()
+            .map(|role| format!("\"{}\"", role))
+            .collect();
+        let mut snippet = String::from("[funds]\n");
+        snippet.push_str(&format!("source = \"{}\"\n", source));
+        snippet.push_str(&format!("measurement = \"{}\"\n", self.measurement));
+        snippet.push_str(&format!("time_column = \"{}\"\n", time_column));
+        snippet.push_str(&format!("time_format = \"{}\"\n", time_format));
+        snippet.push_str(&format!("header_rows = {}\n", roles.len()));
+        snippet.push_str(&format!("header_roles = [{}]\n", roles.join(", ")));
+        if self.tag_key != DEFAULT_FUND_TAG_KEY {
+            snippet.push_str(&format!("tag_key = \"{}\"\n", self.tag_key));
+        }
+        let other_fields = self.other_fields();
+        if !other_fields.is_empty() {
+            snippet.push_str(&format!(
+                "# import-funds writes a single `value` field; {} also has: {}\n",
+                self.measurement,
+                other_fields.join(", ")
+            ));
+        }
+        snippet
+    }
+}
@@ -0,0 +1,260 @@
+use crate::aggregate::{start_of_day, today};
+use crate::derived::{DerivedPoints, DerivedStage};
+use crate::health_data::{HealthDataReader, HealthDataType, HealthRecord, ReadFrom};
+use crate::influx_client::DataPoint;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+
+/// Measurement the load of each workout is written to, at its start
+pub const SESSION_LOAD_MEASUREMENT: &str = "training_load";
+
+/// Measurement the average daily load of the last 7 days is written to
+pub const ACUTE_LOAD_MEASUREMENT: &str = "training_load_acute";
+
+/// Measurement the average daily load of the last 28 days is written to
+pub const CHRONIC_LOAD_MEASUREMENT: &str = "training_load_chronic";
+
+/// Measurement the acute to chronic load ratio is written to
+pub const LOAD_RATIO_MEASUREMENT: &str = "training_load_ratio";
+
+/// Resting heart rate assumed when none is given
+pub const DEFAULT_RESTING_HEART_RATE: u32 = 60;
+
+/// Days averaged into the acute load
+const ACUTE_DAYS: i64 = 7;
+
+/// Days averaged into the chronic load
+const CHRONIC_DAYS: i64 = 28;
+
+/// Longest time a heart rate sample is taken to hold for, so a gap in the samples of a
+/// workout does not count as minutes at its last heart rate
+pub(crate) const MAX_SAMPLE_MINUTES: f64 = 5.0;
+
+/// Computes the training load of workouts from the heart rate recorded during them, as
+/// Banister's TRIMP: minutes weighted by the fraction of the heart rate reserve used,
+/// growing exponentially towards the maximum heart rate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrainingLoad {
+    resting_heart_rate: f64,
+    max_heart_rate: f64,
+}
+
+impl TrainingLoad {
+    /// Creates a calculator for a person with the given maximum heart rate, and a resting
+    /// heart rate of [`DEFAULT_RESTING_HEART_RATE`]
+    pub fn new(max_heart_rate: u32) -> Self {
+        Self {
+            resting_heart_rate: DEFAULT_RESTING_HEART_RATE as f64,
+            max_heart_rate: max_heart_rate as f64,
+        }
+    }
+
+    /// Sets the resting heart rate
+    pub fn with_resting_heart_rate(mut self, resting_heart_rate: u32) -> Self {
+        self.resting_heart_rate = resting_heart_rate as f64;
+        self
+    }
+
+    /// Gets the TRIMP of a minute at `bpm`
+    fn weight(&self, bpm: f64) -> f64 {
+        let reserve = ((bpm - self.resting_heart_rate)
+            / (self.max_heart_rate - self.resting_heart_rate))
+            .clamp(0.0, 1.0);
+        reserve * 0.64 * (1.92 * reserve).exp()
+    }
+
+    /// Gets the load of a workout from `start` to `end`, out of heart rate samples sorted
+    /// by time. Each sample holds until the next one
+    fn session_load(
+        &self,
+        samples: &[(DateTime<Utc>, f64)],
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Option<f64> {
+        let first = samples.partition_point(|(time, _)| *time < start);
+        let last = samples.partition_point(|(time, _)| *time < end);
+        let during = &samples[first..last];
+        if during.is_empty() {
+            return None;
+        }
+        let load = during
+            .iter()
+            .enumerate()
+            .map(|(index, (time, bpm))| {
+                let until = during.get(index + 1).map_or(end, |(next, _)| *next);
+                let minutes = (until - *time).num_seconds() as f64 / 60.0;
+                minutes.min(MAX_SAMPLE_MINUTES) * self.weight(*bpm)
+            })
+            .sum();
+        Some(load)
+    }
+
+    /// Computes the load of every workout among `records`, and the acute and chronic load
+    /// of every day from `from` to `until`. `records` must hold the exercise sessions and
+    /// heart rate of the 28 days before `from` too, see [`read_from`]
+    ///
+    /// A day's load is that of the workouts started on it, in their local time (see
+    /// [`HealthRecord::local_time`]); the acute and chronic loads average it over the last 7
+    /// and 28 days, days without workouts counting as 0, and are written at the start of
+    /// each day in `timezone`
+    pub fn points(
+        &self,
+        records: &[HealthRecord],
+        from: NaiveDate,
+        until: NaiveDate,
+        timezone: Tz,
+    ) -> Vec<DataPoint> {
+        let mut samples: Vec<(DateTime<Utc>, f64)> = records
+            .iter()
+            .filter(|record| record.record_type == HealthDataType::HeartRate)
+            .map(|record| (record.timestamp, record.value))
+            .collect();
+        samples.sort_by_key(|(time, _)| *time);
+
+        let mut points = Vec::new();
+        let mut daily: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+        for record in records {
+            if record.record_type != HealthDataType::ExerciseSession {
+                continue;
+            }
+            let Some(end) = record
+                .metadata
+                .get("end_time_millis")
+                .and_then(|millis| millis.parse().ok())
+                .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+            else {
+                continue;
+            };
+            let Some(load) = self.session_load(&samples, record.timestamp, end) else {
+                continue;
+            };
+            *daily.entry(record.local_time(timezone).date()).or_default() += load;
+
+            let tags: HashMap<String, String> = ["app_name", "exercise_type"]
+                .into_iter()
+                .filter_map(|key| Some((key.to_string(), record.metadata.get(key)?.clone())))
+                .collect();
+            points.push(DataPoint {
+                measurement: SESSION_LOAD_MEASUREMENT.to_string(),
+                time: record.timestamp,
+                tags,
+                field_value: round(load),
+                text_fields: BTreeMap::new(),
+            });
+        }
+
+        let average = |day: NaiveDate, days: i64| {
+            daily
+                .range(day - Duration::days(days - 1)..=day)
+                .map(|(_, load)| load)
+                .sum::<f64>()
+                / days as f64
+        };
+        for day in from.iter_days().take_while(|day| *day <= until) {
+            let time = start_of_day(day, timezone);
+            let point = |measurement: &str, value: f64| DataPoint {
+                measurement: measurement.to_string(),
+                time,
+                tags: HashMap::new(),
+                field_value: round(value),
+                text_fields: BTreeMap::new(),
+            };
+            let acute = average(day, ACUTE_DAYS);
+            let chronic = average(day, CHRONIC_DAYS);
+            points.push(point(ACUTE_LOAD_MEASUREMENT, acute));
+            points.push(point(CHRONIC_LOAD_MEASUREMENT, chronic));
+            if chronic > 0.0 {
+                points.push(point(LOAD_RATIO_MEASUREMENT, acute / chronic));
+            }
+        }
+        points
+    }
+}
+
+/// Rounds a load to 2 decimals
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+/// Gets the first day whose load changes with `records`: that of their oldest workout, or
+/// the day before their oldest heart rate sample, which can belong to a workout started then
+pub fn first_day_touched(records: &[HealthRecord], timezone: Tz) -> Option<NaiveDate> {
+    records
+        .iter()
+        .filter_map(|record| match record.record_type {
+            HealthDataType::ExerciseSession => Some(record.local_time(timezone).date()),
+            HealthDataType::HeartRate => {
+                Some(record.local_time(timezone).date() - Duration::days(1))
+            }
+            _ => None,
+        })
+        .min()
+}
+
+/// Gets the time to read records from so that the loads from `day` on can be computed,
+/// with the chronic load window before it
+pub fn read_from(day: NaiveDate) -> DateTime<Utc> {
+    (day - Duration::days(CHRONIC_DAYS))
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+        .and_utc()
+        - Duration::milliseconds(1)
+}
+
+/// Writes the training load again from the first day the records written change, up to
+/// today, as workouts change the loads of the days after them too
+pub struct TrainingLoadStage {
+    load: TrainingLoad,
+    timezone: Tz,
+    first: Option<NaiveDate>,
+}
+
+impl TrainingLoadStage {
+    /// Creates the stage for `load`, with days in `timezone`
+    pub fn new(load: TrainingLoad, timezone: Tz) -> Self {
+        TrainingLoadStage {
+            load,
+            timezone,
+            first: None,
+        }
+    }
+}
+
+impl DerivedStage for TrainingLoadStage {
+    fn name(&self) -> &str {
+        "training load"
+    }
+
+    fn inputs(&self) -> &[HealthDataType] {
+        &[HealthDataType::ExerciseSession, HealthDataType::HeartRate]
+    }
+
+    fn prepare(
+        &mut self,
+        _reader: &HealthDataReader,
+        written: &[HealthRecord],
+    ) -> Result<Option<ReadFrom>, Box<dyn Error>> {
+        self.first = first_day_touched(written, self.timezone);
+        Ok(self
+            .first
+            .map(|first| ReadFrom::Timestamp(read_from(first))))
+    }
+
+    fn compute(&self, records: &[HealthRecord]) -> DerivedPoints {
+        let points = match self.first {
+            Some(first) => self
+                .load
+                .points(records, first, today(self.timezone), self.timezone),
+            None => Vec::new(),
+        };
+        DerivedPoints::Single(points)
+    }
+
+    fn describe(&self) -> String {
+        self.first
+            .map(|first| format!(" since {}", first))
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,421 @@
+use crate::convert::{fund_tag, parse_fund_value, DEFAULT_FUND_TAG_KEY};
+use crate::influx_client::DataPoint;
+use crate::source::{RecordStream, Source};
+use crate::state_management::ImportState;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use csv::ReaderBuilder;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+
+/// Measurement the cost of the units held is written to
+pub const INVESTED_MEASUREMENT: &str = "invested_capital";
+
+/// Measurement the gains realized by sales and dividends are written to, added up over time
+pub const REALIZED_MEASUREMENT: &str = "realized_gain";
+
+/// Measurement the gain on the units held is written to, at the price of the last
+/// transaction
+pub const UNREALIZED_MEASUREMENT: &str = "unrealized_gain";
+
+/// Fund tag of the amounts of the whole portfolio
+pub const TOTAL_FUND: &str = "total";
+
+/// Formats of the time column tried after the configured one
+const TIME_FORMATS: [&str; 2] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"];
+
+/// Formats of transactions recorded with a date only, taken at midnight
+const DATE_FORMATS: [&str; 3] = ["%Y-%m-%d", "%d.%m.%Y", "%d/%m/%Y"];
+
+/// Columns looked for, by what they hold
+const TIME_COLUMNS: [&str; 4] = ["date", "time", "timestamp", "datetime"];
+const FUND_COLUMNS: [&str; 5] = ["fund", "fondo", "name", "security", "isin"];
+const KIND_COLUMNS: [&str; 4] = ["type", "action", "kind", "transaction"];
+const QUANTITY_COLUMNS: [&str; 4] = ["quantity", "units", "shares", "qty"];
+const PRICE_COLUMNS: [&str; 2] = ["price", "nav"];
+const AMOUNT_COLUMNS: [&str; 3] = ["amount", "total", "value"];
+const FEE_COLUMNS: [&str; 3] = ["fees", "fee", "commission"];
+
+/// What a transaction did to a position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransactionKind {
+    Buy,
+    Sell,
+    Dividend,
+}
+
+impl TransactionKind {
+    /// Reads the type of a transaction, as brokers write it
+    pub fn parse(text: &str) -> Option<Self> {
+        match text.trim().to_lowercase().as_str() {
+            "buy" | "purchase" | "subscription" => Some(TransactionKind::Buy),
+            "sell" | "sale" | "redemption" => Some(TransactionKind::Sell),
+            "dividend" | "distribution" | "income" => Some(TransactionKind::Dividend),
+            _ => None,
+        }
+    }
+}
+
+/// A buy, sell or dividend of a fund
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transaction {
+    pub timestamp: DateTime<Utc>,
+    /// The fund's `fondo` tag
+    pub fund: String,
+    pub kind: TransactionKind,
+    /// Units bought or sold; none for dividends
+    pub quantity: f64,
+    /// Price per unit, when known
+    pub price: Option<f64>,
+    /// Cash paid for a buy or received for a sale or dividend, before fees
+    pub amount: f64,
+    pub fees: f64,
+}
+
+/// A position in a fund, as the transactions so far left it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Position {
+    pub units: f64,
+    /// Cost of the units held, fees included
+    pub cost: f64,
+    /// Gains realized by sales and dividends, net of fees
+    pub realized: f64,
+    /// Price of the last buy or sale
+    pub last_price: Option<f64>,
+}
+
+impl Position {
+    /// Gets the gain on the units held at `price`
+    pub fn unrealized(&self, price: f64) -> f64 {
+        self.units * price - self.cost
+    }
+}
+
+/// Tracks the cost basis of each position with the average cost method: a sale takes its
+/// share of the position's cost, and realizes its proceeds minus that share
+#[derive(Debug, Clone, Default)]
+pub struct CostBasis {
+    positions: BTreeMap<String, Position>,
+}
+
+impl CostBasis {
+    /// Creates a cost basis without positions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Applies a transaction to its fund's position; selling more units than are held is
+    /// an error
+    pub fn apply(&mut self, transaction: &Transaction) -> Result<(), String> {
+        let position = self.positions.entry(transaction.fund.clone()).or_default();
+        match transaction.kind {
+            TransactionKind::Buy => {
+                position.units += transaction.quantity;
+                position.cost += transaction.amount + transaction.fees;
+            }
+            TransactionKind::Sell => {
+                // Float noise must not make a full sale look like selling too much
+                if transaction.quantity > position.units + 1e-9 {
+                    return Err(format!(
+                        "{} sells {} units of {} but only {} are held",
+                        transaction.timestamp,
+                        transaction.quantity,
+                        transaction.fund,
+                        position.units
+                    ));
+                }
+                let cost = if position.units > 0.0 {
+                    position.cost * transaction.quantity / position.units
+                } else {
+                    0.0
+                };
+                position.units = (position.units - transaction.quantity).max(0.0);
+                position.cost = if position.units > 1e-9 {
+                    position.cost - cost
+                } else {
+                    0.0
+                };
+                position.realized += transaction.amount - transaction.fees - cost;
+            }
+            TransactionKind::Dividend => {
+                position.realized += transaction.amount - transaction.fees;
+            }
+        }
+        if transaction.price.is_some() {
+            position.last_price = transaction.price;
+        }
+        Ok(())
+    }
+
+    /// Gets the position in a fund, by its `fondo` tag
+    pub fn position(&self, fund: &str) -> Option<&Position> {
+        self.positions.get(fund)
+    }
+
+    /// Gets every position, by `fondo` tag
+    pub fn positions(&self) -> &BTreeMap<String, Position> {
+        &self.positions
+    }
+}
+
+/// The transactions of one moment, with the amounts they leave
+#[derive(Debug, Clone)]
+pub struct TransactionRecord {
+    pub timestamp: DateTime<Utc>,
+    pub points: Vec<DataPoint>,
+}
+
+/// Reads a CSV export of fund transactions: a row per buy, sell or dividend, with its
+/// date, fund, type, quantity, price, amount and fees. Column names are matched loosely
+/// (`Units` or `Shares` for the quantity); the amount defaults to quantity times price,
+/// and the price to amount over quantity
+///
+/// Each transaction is written as the invested capital, realized gain and unrealized gain
+/// of its fund and of the whole portfolio, using the average cost method
+pub struct TransactionReader {
+    file_path: String,
+    time_column: String,
+    time_format: String,
+    tag_key: String,
+}
+
+impl TransactionReader {
+    /// Creates a reader for a transactions export, with its time in a `date` column
+    pub fn new(file_path: &str) -> Self {
+        TransactionReader {
+            file_path: file_path.to_string(),
+            time_column: "date".to_string(),
+            time_format: "%Y-%m-%d %H:%M:%S".to_string(),
+            tag_key: DEFAULT_FUND_TAG_KEY.to_string(),
+        }
+    }
+
+    /// Sets the key of the tag the fund of each point is written to (`fondo` by default)
+    pub fn with_tag_key(mut self, tag_key: &str) -> Self {
+        self.tag_key = tag_key.to_string();
+        self
+    }
+
+    /// Sets the column holding the time of each transaction and its format; times are taken
+    /// as UTC. Without one, date, time, timestamp or datetime is used
+    pub fn with_time_column(mut self, column: &str, format: &str) -> Self {
+        self.time_column = column.to_string();
+        self.time_format = format.to_string();
+        self
+    }
+
+    fn parse_time(&self, text: &str) -> Option<DateTime<Utc>> {
+        let text = text.trim();
+        if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+            return Some(time.with_timezone(&Utc));
+        }
+        let time = std::iter::once(self.time_format.as_str())
+            .chain(TIME_FORMATS)
+            .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+            .or_else(|| {
+                DATE_FORMATS.iter().find_map(|format| {
+                    NaiveDate::parse_from_str(text, format)
+                        .ok()?
+                        .and_hms_opt(0, 0, 0)
+                })
+            })?;
+        Some(time.and_utc())
+    }
+
+    /// Reads every transaction, oldest first; rows without a valid time, fund or type are
+    /// skipped, and rows with neither an amount nor a quantity and price are errors
+    pub fn transactions(&self) -> Result<Vec<Transaction>, Box<dyn Error>> {
+        let contents = fs::read_to_string(&self.file_path)
+            .map_err(|e| format!("Failed to read {}: {}", self.file_path, e))?;
+        let first_line = contents.lines().next().unwrap_or_default();
+        let semicolons = first_line.contains(';');
+        let mut reader = ReaderBuilder::new()
+            .delimiter(if semicolons { b';' } else { b',' })
+            .flexible(true)
+            .from_reader(contents.as_bytes());
+        let headers: Vec<String> = reader
+            .headers()?
+            .iter()
+            .map(|header| header.trim().to_lowercase())
+            .collect();
+        let column = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| headers.iter().position(|header| header == name))
+        };
+
+        let time_index = std::iter::once(self.time_column.to_lowercase())
+            .chain(TIME_COLUMNS.map(String::from))
+            .find_map(|column| headers.iter().position(|header| *header == column))
+            .ok_or_else(|| format!("No time column '{}' in the header", self.time_column))?;
+        let fund_index = column(&FUND_COLUMNS).ok_or("No fund column in the header")?;
+        let kind_index = column(&KIND_COLUMNS).ok_or("No type column in the header")?;
+        let quantity_index = column(&QUANTITY_COLUMNS);
+        let price_index = column(&PRICE_COLUMNS);
+        let amount_index = column(&AMOUNT_COLUMNS);
+        let fee_index = column(&FEE_COLUMNS);
+
+        let mut transactions = Vec::new();
+        for (line, row) in reader.records().enumerate() {
+            let row = row?;
+            let number = |index: Option<usize>| {
+                let text = row.get(index?)?.trim();
+                let text = if semicolons {
+                    text.replace(',', ".")
+                } else {
+                    text.to_string()
+                };
+                parse_fund_value(&text).map(f64::abs)
+            };
+            let Some(timestamp) = row.get(time_index).and_then(|text| self.parse_time(text)) else {
+                continue;
+            };
+            let Some(fund) = row.get(fund_index).map(str::trim).filter(|f| !f.is_empty()) else {
+                continue;
+            };
+            let Some(kind) = row.get(kind_index).and_then(TransactionKind::parse) else {
+                continue;
+            };
+
+            let quantity = number(quantity_index).unwrap_or(0.0);
+            let mut price = number(price_index);
+            let amount = match (number(amount_index), price) {
+                (Some(amount), _) => amount,
+                (None, Some(price)) if quantity > 0.0 => quantity * price,
+                _ => {
+                    return Err(format!(
+                        "Row {}: a {:?} of {} needs an amount, or a quantity and price",
+                        line + 2,
+                        kind,
+                        fund
+                    )
+                    .into())
+                }
+            };
+            if price.is_none() && kind != TransactionKind::Dividend && quantity > 0.0 {
+                price = Some(amount / quantity);
+            }
+            transactions.push(Transaction {
+                timestamp,
+                fund: fund_tag(fund),
+                kind,
+                quantity,
+                price,
+                amount,
+                fees: number(fee_index).unwrap_or(0.0),
+            });
+        }
+        // Stable, so transactions of the same moment keep the order of the export
+        transactions.sort_by_key(|transaction| transaction.timestamp);
+        Ok(transactions)
+    }
+
+    /// Reads the export as one record per time transactions were made, each with the
+    /// amounts of the funds they touched and the totals
+    pub fn records(&self) -> Result<Vec<TransactionRecord>, Box<dyn Error>> {
+        let transactions = self.transactions()?;
+        let mut basis = CostBasis::new();
+        let mut records: Vec<TransactionRecord> = Vec::new();
+
+        let mut index = 0;
+        while index < transactions.len() {
+            let timestamp = transactions[index].timestamp;
+            let mut funds = Vec::new();
+            while index < transactions.len() && transactions[index].timestamp == timestamp {
+                basis.apply(&transactions[index])?;
+                funds.push(transactions[index].fund.as_str());
+                index += 1;
+            }
+            funds.sort();
+            funds.dedup();
+
+            let point = |measurement: &str, fund: &str, value: f64| DataPoint {
+                measurement: measurement.to_string(),
+                time: timestamp,
+                tags: HashMap::from([(self.tag_key.clone(), fund.to_string())]),
+                field_value: (value * 1e6).round() / 1e6,
+                text_fields: BTreeMap::new(),
+            };
+            let mut points = Vec::new();
+            for fund in funds {
+                let position = &basis.positions()[fund];
+                points.push(point(INVESTED_MEASUREMENT, fund, position.cost));
+                points.push(point(REALIZED_MEASUREMENT, fund, position.realized));
+                if let Some(price) = position.last_price {
+                    points.push(point(
+                        UNREALIZED_MEASUREMENT,
+                        fund,
+                        position.unrealized(price),
+                    ));
+                }
+            }
+
+            let positions = basis.positions().values();
+            points.push(point(
+                INVESTED_MEASUREMENT,
+                TOTAL_FUND,
+                positions.clone().map(|position| position.cost).sum(),
+            ));
+            points.push(point(
+                REALIZED_MEASUREMENT,
+                TOTAL_FUND,
+                positions.clone().map(|position| position.realized).sum(),
+            ));
+            // Funds without a price are only a problem while units are held
+            let unrealized: Option<f64> = positions
+                .filter(|position| position.units > 0.0)
+                .map(|position| Some(position.unrealized(position.last_price?)))
+                .sum();
+            if let Some(unrealized) = unrealized {
+                points.push(point(UNREALIZED_MEASUREMENT, TOTAL_FUND, unrealized));
+            }
+            records.push(TransactionRecord { timestamp, points });
+        }
+        Ok(records)
+    }
+}
+
+impl Source for TransactionReader {
+    type Record = TransactionRecord;
+
+    fn validate(&self) -> Result<String, Box<dyn Error>> {
+        let transactions = self.transactions()?;
+        let mut funds: Vec<&str> = transactions.iter().map(|t| t.fund.as_str()).collect();
+        funds.sort();
+        funds.dedup();
+        Ok(format!(
+            "Fund transactions: {} ({} transactions; funds: {})",
+            self.file_path,
+            transactions.len(),
+            funds.join(", ")
+        ))
+    }
+
+    /// Reads the transactions after the last imported timestamp; the cost basis is built
+    /// from every transaction, so the amounts carry on from the earlier ones
+    fn records_since(
+        &self,
+        watermark: &ImportState,
+    ) -> Result<RecordStream<TransactionRecord>, Box<dyn Error>> {
+        let records = self.records()?;
+        let Some(last_imported) = watermark.last_imported_timestamp else {
+            return Ok(RecordStream::new(records));
+        };
+
+        let total = records.len();
+        let new_records: Vec<TransactionRecord> = records
+            .into_iter()
+            .filter(|record| record.timestamp > last_imported)
+            .collect();
+        let already_imported = total - new_records.len();
+        Ok(RecordStream::new(new_records).with_already_imported(already_imported))
+    }
+
+    fn timestamp(&self, record: &TransactionRecord) -> Option<DateTime<Utc>> {
+        Some(record.timestamp)
+    }
+
+    fn to_points(&self, record: &TransactionRecord) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+        Ok(record.points.clone())
+    }
+}
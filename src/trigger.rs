@@ -0,0 +1,421 @@
+use crate::metrics::RunResult;
+use crate::state_management::JournalEntry;
+use crate::upload;
+use chrono::{DateTime, Local, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+
+/// How long a connection may go without sending or taking data before it is closed by
+/// default, so idle clients do not hold a thread forever
+pub const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest upload accepted by default, in megabytes
+pub const DEFAULT_MAX_UPLOAD_MB: u64 = 512;
+
+/// Runs a job by name and tells how it ended: the result, the exit code of the import if it
+/// exited, and the journal entry it recorded, if it got that far
+pub type RunJob = dyn Fn(&str) -> (RunResult, Option<i32>, Option<JournalEntry>) + Send + Sync;
+
+/// A finished run of a job
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct JobRun {
+    pub started_at: DateTime<Utc>,
+    pub finished_at: DateTime<Utc>,
+    /// `success`, `nothing_new` or `failure`
+    pub result: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    /// The journal entry of the import, with the records it wrote and its errors
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journal: Option<JournalEntry>,
+}
+
+/// Whether a job is running, and how its last run triggered over HTTP ended
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct JobStatus {
+    pub running: bool,
+    pub last_run: Option<JobRun>,
+}
+
+/// A response to a request, with a JSON body
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: &impl Serialize) -> Self {
+        Response {
+            status,
+            body: serde_json::to_string(body).unwrap_or_default(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Response::json(status, &serde_json::json!({ "error": message }))
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            202 => "Accepted",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            409 => "Conflict",
+            411 => "Length Required",
+            413 => "Payload Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Starts jobs on HTTP requests, e.g. from a phone automation right after it uploads an
+/// export, and reports how they went:
+/// - `POST /run/<job>` starts a job and answers 202 right away; with `?wait` it answers
+///   when the job is done, with its run, and 500 if it failed
+/// - `POST /upload/<job>` stores the Health Connect export in the body where the job reads
+///   it, then starts the job like `/run/<job>`
+/// - `GET /status` shows whether each job is running and how its last run ended
+///
+/// A job that is still running is not started again, nor its source replaced (409). When
+/// a token is set, requests must send it in an `Authorization: Bearer <token>` header
+pub struct TriggerServer {
+    jobs: Mutex<BTreeMap<String, JobStatus>>,
+    token: Option<String>,
+    run: Box<RunJob>,
+    /// Where the exports uploaded for each job are stored, by job name
+    uploads: BTreeMap<String, String>,
+    /// Largest upload accepted, in bytes
+    max_upload: u64,
+    connection_timeout: Duration,
+}
+
+impl TriggerServer {
+    /// Serves the named jobs, running them with `run`
+    pub fn new(jobs: &[String], token: Option<String>, run: Box<RunJob>) -> Self {
+        TriggerServer {
+            jobs: Mutex::new(
+                jobs.iter()
+                    .map(|job| (job.clone(), JobStatus::default()))
+                    .collect(),
+            ),
+            token,
+            run,
+            uploads: BTreeMap::new(),
+            max_upload: DEFAULT_MAX_UPLOAD_MB * 1024 * 1024,
+            connection_timeout: CONNECTION_TIMEOUT,
+        }
+    }
+
+    /// Accepts uploads for the jobs given with their source: a file, which an upload
+    /// replaces, or a directory, which it is added to (see [`upload::upload_path`])
+    pub fn with_uploads(mut self, uploads: BTreeMap<String, String>) -> Self {
+        self.uploads = uploads;
+        self
+    }
+
+    /// Sets the largest upload accepted, in bytes; larger ones are refused (413) before any
+    /// of them is read
+    pub fn with_max_upload(mut self, bytes: u64) -> Self {
+        self.max_upload = bytes;
+        self
+    }
+
+    /// Sets how long a connection may go without sending or taking data before it is closed
+    pub fn with_connection_timeout(mut self, timeout: Duration) -> Self {
+        self.connection_timeout = timeout;
+        self
+    }
+
+    /// Gets the status of every job
+    pub fn status(&self) -> BTreeMap<String, JobStatus> {
+        self.jobs
+            .lock()
+            .map(|jobs| jobs.clone())
+            .unwrap_or_default()
+    }
+
+    fn authorized(&self, authorization: Option<&str>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let Some(bearer) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+            return false;
+        };
+        // Compared in constant time, so response times do not give the token away
+        bearer.trim().as_bytes().ct_eq(token.as_bytes()).into()
+    }
+
+    /// Marks a job as running, unless it is unknown or running already
+    fn reserve(&self, job: &str) -> Result<(), Response> {
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(status) = jobs.get_mut(job) else {
+            return Err(Response::error(404, &format!("no job named '{}'", job)));
+        };
+        if status.running {
+            return Err(Response::error(
+                409,
+                &format!("job '{}' is already running", job),
+            ));
+        }
+        status.running = true;
+        Ok(())
+    }
+
+    /// Starts a job marked as running, answering when it is done if `wait` is set. `extra`
+    /// fields are added to the response
+    fn start(
+        self: &Arc<Self>,
+        job: &str,
+        wait: bool,
+        extra: serde_json::Map<String, serde_json::Value>,
+    ) -> Response {
+        let server = self.clone();
+        let name = job.to_string();
+        let runner = thread::spawn(move || server.run_job(&name));
+        let mut body = extra;
+        body.insert("job".to_string(), job.into());
+        if !wait {
+            body.insert("started".to_string(), true.into());
+            return Response::json(202, &body);
+        }
+        let run = match runner.join() {
+            Ok(run) => run,
+            Err(_) => return Response::error(500, &format!("job '{}' panicked", job)),
+        };
+        let status = if run.result == RunResult::Failure.as_str() {
+            500
+        } else {
+            200
+        };
+        body.insert(
+            "run".to_string(),
+            serde_json::to_value(&run).unwrap_or_default(),
+        );
+        Response::json(status, &body)
+    }
+
+    /// Answers a request for `target` (a path with an optional query), made with `method`
+    /// and the value of its Authorization header, if any. Uploads are answered by `upload`
+    pub fn handle(
+        self: &Arc<Self>,
+        method: &str,
+        target: &str,
+        authorization: Option<&str>,
+    ) -> Response {
+        if !self.authorized(authorization) {
+            return Response::error(401, "missing or wrong token");
+        }
+
+        let (path, query) = split_target(target);
+        if path == "/status" {
+            if method != "GET" {
+                return Response::error(405, "use GET");
+            }
+            return Response::json(200, &serde_json::json!({ "jobs": self.status() }));
+        }
+        let Some(job) = path.strip_prefix("/run/") else {
+            return Response::error(404, "no such endpoint");
+        };
+        if method != "POST" {
+            return Response::error(405, "use POST");
+        }
+        if let Err(response) = self.reserve(job) {
+            return response;
+        }
+        self.start(job, has_param(query, "wait"), serde_json::Map::new())
+    }
+
+    /// Answers an upload to `target` (`/upload/<job>` with an optional query): stores the
+    /// export read from `body`, then starts the job. Nothing is stored while the job runs.
+    /// `content_length` is the length of the body, which is unknown for chunked uploads;
+    /// those are refused (411), like uploads larger than the limit (413)
+    pub fn upload(
+        self: &Arc<Self>,
+        method: &str,
+        target: &str,
+        authorization: Option<&str>,
+        content_length: Option<u64>,
+        body: impl Read,
+    ) -> Response {
+        if !self.authorized(authorization) {
+            return Response::error(401, "missing or wrong token");
+        }
+        let (path, query) = split_target(target);
+        let Some(job) = path.strip_prefix("/upload/") else {
+            return Response::error(404, "no such endpoint");
+        };
+        if method != "POST" && method != "PUT" {
+            return Response::error(405, "use POST or PUT");
+        }
+        let Some(destination) = self.uploads.get(job) else {
+            return Response::error(404, &format!("job '{}' takes no uploads", job));
+        };
+        let Some(content_length) = content_length else {
+            return Response::error(
+                411,
+                "send the upload with a Content-Length header; chunked uploads are not supported",
+            );
+        };
+        if content_length > self.max_upload {
+            return Response::error(
+                413,
+                &format!(
+                    "the upload is {} bytes, more than the limit of {} bytes",
+                    content_length, self.max_upload
+                ),
+            );
+        }
+        if let Err(response) = self.reserve(job) {
+            return response;
+        }
+
+        match upload::store_export(body, destination, Local::now(), self.max_upload) {
+            Ok(stored) => {
+                let mut extra = serde_json::Map::new();
+                extra.insert(
+                    "stored".to_string(),
+                    serde_json::to_value(&stored).unwrap_or_default(),
+                );
+                self.start(job, has_param(query, "wait"), extra)
+            }
+            Err(e) => {
+                if let Some(status) = self
+                    .jobs
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .get_mut(job)
+                {
+                    status.running = false;
+                }
+                Response::error(400, &e.to_string())
+            }
+        }
+    }
+
+    /// Runs a job that was marked as running, then records how it ended
+    fn run_job(&self, job: &str) -> JobRun {
+        let started_at = Utc::now();
+        let (result, exit_code, journal) = (self.run)(job);
+        let run = JobRun {
+            started_at,
+            finished_at: Utc::now(),
+            result: result.as_str(),
+            exit_code,
+            journal,
+        };
+        let mut jobs = self.jobs.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(status) = jobs.get_mut(job) {
+            status.running = false;
+            status.last_run = Some(run.clone());
+        }
+        run
+    }
+
+    /// Serves requests at `http://<addr>` until the process is stopped, each on its own thread.
+    /// Connections idle for longer than the connection timeout are closed
+    pub fn serve(self: &Arc<Self>, addr: &str) -> io::Result<()> {
+        let listener = TcpListener::bind(addr)?;
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            if stream
+                .set_read_timeout(Some(self.connection_timeout))
+                .is_err()
+                || stream
+                    .set_write_timeout(Some(self.connection_timeout))
+                    .is_err()
+            {
+                continue;
+            }
+            let server = self.clone();
+            thread::spawn(move || server.respond(stream));
+        }
+        Ok(())
+    }
+
+    /// Reads a request from a connection and writes the response; the body is only used by
+    /// uploads
+    fn respond(self: &Arc<Self>, mut stream: TcpStream) {
+        let mut reader = BufReader::new(&stream);
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).is_err() {
+            return;
+        }
+        let mut authorization = None;
+        let mut content_length = None;
+        let mut chunked = false;
+        loop {
+            let mut header = String::new();
+            match reader.read_line(&mut header) {
+                Ok(0) | Err(_) => break,
+                Ok(_) if header.trim().is_empty() => break,
+                Ok(_) => {}
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("authorization") {
+                    authorization = Some(value.trim().to_string());
+                } else if name.trim().eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse::<u64>().ok();
+                } else if name.trim().eq_ignore_ascii_case("transfer-encoding") {
+                    chunked = true;
+                }
+            }
+        }
+
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let target = parts.next().unwrap_or_default();
+        // A transfer encoding takes precedence over the length, and the body is then unknown
+        let content_length = content_length.filter(|_| !chunked);
+        let mut body = reader.take(content_length.unwrap_or(0));
+        let response = if target.starts_with("/upload/") {
+            self.upload(
+                method,
+                target,
+                authorization.as_deref(),
+                content_length,
+                &mut body,
+            )
+        } else {
+            self.handle(method, target, authorization.as_deref())
+        };
+        // Closing the connection with the body unread could reset it before the response,
+        // unless the body is too large to be worth reading
+        if body.limit() <= self.max_upload {
+            let _ = io::copy(&mut body, &mut io::sink());
+        }
+        let _ = stream.write_all(
+            format!(
+                "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                response.status,
+                response.reason(),
+                response.body.len(),
+                response.body
+            )
+            .as_bytes(),
+        );
+    }
+}
+
+/// Splits a request target into its path and query
+fn split_target(target: &str) -> (&str, &str) {
+    target.split_once('?').unwrap_or((target, ""))
+}
+
+/// Checks whether a query has a parameter, with or without a value
+fn has_param(query: &str, name: &str) -> bool {
+    query
+        .split('&')
+        .any(|param| param.split('=').next() == Some(name))
+}
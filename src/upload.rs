@@ -0,0 +1,133 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Name of the database in the zip files Health Connect exports
+pub const EXPORT_DB_NAME: &str = "health_connect_export.db";
+
+const SQLITE_MAGIC: &[u8] = b"SQLite format 3\0";
+const ZIP_MAGIC: &[u8] = b"PK\x03\x04";
+
+/// An uploaded export, stored where an import reads it
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct StoredExport {
+    pub path: String,
+    /// Size of the stored database
+    pub bytes: u64,
+    /// Whether the database was taken out of an uploaded zip file
+    pub from_zip: bool,
+}
+
+/// Gets where an export uploaded for the source `destination` is stored: the source file
+/// itself, or in a source directory, a new file named after the time, so that it is the
+/// directory's last file by name
+pub fn upload_path(destination: &str, now: DateTime<Local>) -> PathBuf {
+    let destination = Path::new(destination);
+    if destination.is_dir() {
+        destination.join(format!("{}.db", now.format("%Y-%m-%d-%H%M%S")))
+    } else {
+        destination.to_path_buf()
+    }
+}
+
+/// Checks whether a file starts with `magic`
+fn starts_with(path: &Path, magic: &[u8]) -> io::Result<bool> {
+    let mut start = Vec::new();
+    File::open(path)?
+        .take(magic.len() as u64)
+        .read_to_end(&mut start)?;
+    Ok(start == magic)
+}
+
+/// Checks whether a file is a zip file, as Health Connect exports are
+pub fn is_zip(path: &Path) -> io::Result<bool> {
+    starts_with(path, ZIP_MAGIC)
+}
+
+/// Writes the database of a Health Connect zip file to `to`: the entry named like the ones
+/// Health Connect exports, or else the only `.db` entry. Fails, rather than fill the disk,
+/// when the database is larger than `max_size` bytes
+fn extract_database(zip_path: &Path, to: &Path, max_size: u64) -> Result<(), Box<dyn Error>> {
+    let mut archive = zip::ZipArchive::new(File::open(zip_path)?)?;
+    let databases: Vec<String> = archive
+        .file_names()
+        .filter(|name| name.ends_with(".db"))
+        .map(String::from)
+        .collect();
+    let name = match databases
+        .iter()
+        .find(|name| Path::new(name).file_name() == Some(EXPORT_DB_NAME.as_ref()))
+    {
+        Some(name) => name,
+        None if databases.len() == 1 => &databases[0],
+        None if databases.is_empty() => return Err("the zip file holds no .db file".into()),
+        None => {
+            return Err(format!(
+                "the zip file holds several databases and none is named {}: {}",
+                EXPORT_DB_NAME,
+                databases.join(", ")
+            )
+            .into())
+        }
+    };
+    let entry = archive.by_name(name)?;
+    let size = io::copy(
+        &mut entry.take(max_size.saturating_add(1)),
+        &mut File::create(to)?,
+    )?;
+    if size > max_size {
+        return Err(format!(
+            "{} in the zip file is larger than the limit of {} bytes",
+            name, max_size
+        )
+        .into());
+    }
+    if !starts_with(to, SQLITE_MAGIC)? {
+        return Err(format!("{} in the zip file is not an SQLite database", name).into());
+    }
+    Ok(())
+}
+
+/// Stores an uploaded Health Connect export, a zip file or the SQLite database itself, at
+/// the `upload_path` of `destination`. The upload is written next to it first and moved in
+/// place when complete, so an import never reads part of it; nothing is stored when the
+/// upload is not an export. A database taken out of a zip file may be at most `max_size`
+/// bytes
+pub fn store_export(
+    mut body: impl Read,
+    destination: &str,
+    now: DateTime<Local>,
+    max_size: u64,
+) -> Result<StoredExport, Box<dyn Error>> {
+    let path = upload_path(destination, now);
+    let partial = path.with_extension("upload");
+    let extracted = path.with_extension("extracted");
+
+    let stored: Result<StoredExport, Box<dyn Error>> = (|| {
+        io::copy(&mut body, &mut File::create(&partial)?)?;
+        let from_zip = if starts_with(&partial, SQLITE_MAGIC)? {
+            fs::rename(&partial, &path)?;
+            false
+        } else if starts_with(&partial, ZIP_MAGIC)? {
+            extract_database(&partial, &extracted, max_size)?;
+            fs::rename(&extracted, &path)?;
+            true
+        } else {
+            return Err(
+                "not a Health Connect export: expected a zip file or an SQLite database".into(),
+            );
+        };
+        Ok(StoredExport {
+            path: path.display().to_string(),
+            bytes: fs::metadata(&path)?.len(),
+            from_zip,
+        })
+    })();
+
+    let _ = fs::remove_file(&partial);
+    let _ = fs::remove_file(&extracted);
+    stored
+}
@@ -0,0 +1,308 @@
+use crate::influx_client::DataPoint;
+use crate::source::{RecordStream, Source};
+use crate::state_management::ImportState;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use clap::ValueEnum;
+use csv::ReaderBuilder;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+
+/// Formats of the time column tried after the configured one: Ecowitt exports minutes
+/// only, Weather Underground's DateUTC has seconds
+const TIME_FORMATS: [&str; 3] = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y/%m/%d %H:%M"];
+
+/// Time columns looked for when the configured one is missing; Weather Underground's
+/// DateUTC comes first because its Time column is in local time
+const TIME_COLUMNS: [&str; 3] = ["dateutc", "time", "timestamp"];
+
+/// Unit system the readings are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum UnitSystem {
+    /// °C, km/h and mm
+    #[default]
+    Metric,
+    /// °F, mph and inches
+    Imperial,
+}
+
+/// The unit a column of the export is in
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unit {
+    Celsius,
+    Fahrenheit,
+    Percent,
+    Degrees,
+    MetersPerSecond,
+    KilometersPerHour,
+    MilesPerHour,
+    Knots,
+    Millimeters,
+    Inches,
+}
+
+impl Unit {
+    /// Parses a unit as exports write it, e.g. in `Outdoor Temperature(℃)`
+    fn parse(text: &str) -> Option<Unit> {
+        let unit = match text.trim().to_lowercase().as_str() {
+            "℃" | "°c" | "c" => Unit::Celsius,
+            "℉" | "°f" | "f" => Unit::Fahrenheit,
+            "%" => Unit::Percent,
+            "°" | "deg" | "degrees" => Unit::Degrees,
+            "m/s" => Unit::MetersPerSecond,
+            "km/h" | "kmh" => Unit::KilometersPerHour,
+            "mph" => Unit::MilesPerHour,
+            "knots" | "kn" => Unit::Knots,
+            "mm" | "mm/hr" | "mm/h" => Unit::Millimeters,
+            "in" | "in/hr" | "in/h" => Unit::Inches,
+            _ => return None,
+        };
+        Some(unit)
+    }
+
+    /// Converts a value to the unit system; rain rates convert like amounts
+    fn convert(self, value: f64, system: UnitSystem) -> f64 {
+        match (self, system) {
+            (Unit::Celsius, UnitSystem::Imperial) => value * 9.0 / 5.0 + 32.0,
+            (Unit::Fahrenheit, UnitSystem::Metric) => (value - 32.0) * 5.0 / 9.0,
+            (Unit::MetersPerSecond, UnitSystem::Metric) => value * 3.6,
+            (Unit::MetersPerSecond, UnitSystem::Imperial) => value * 2.236_936,
+            (Unit::KilometersPerHour, UnitSystem::Imperial) => value / 1.609_344,
+            (Unit::MilesPerHour, UnitSystem::Metric) => value * 1.609_344,
+            (Unit::Knots, UnitSystem::Metric) => value * 1.852,
+            (Unit::Knots, UnitSystem::Imperial) => value * 1.150_779,
+            (Unit::Millimeters, UnitSystem::Imperial) => value / 25.4,
+            (Unit::Inches, UnitSystem::Metric) => value * 25.4,
+            _ => value,
+        }
+    }
+}
+
+/// A column of the export written as points: its measurement, the tag telling it apart
+/// from the other columns of the same measurement, and its unit
+#[derive(Debug, Clone, PartialEq)]
+struct Channel {
+    index: usize,
+    measurement: &'static str,
+    tag: (&'static str, &'static str),
+    unit: Unit,
+}
+
+/// Recognizes a column of an Ecowitt export (`Outdoor Temperature(℉)`) or a Weather
+/// Underground one (`TemperatureF`); other columns are not written
+fn channel(index: usize, header: &str) -> Option<Channel> {
+    let header = header.trim();
+    let (name, unit) = match header.split_once('(') {
+        Some((name, unit)) => (
+            name.trim().to_lowercase(),
+            Unit::parse(unit.trim_end_matches(')')),
+        ),
+        None => (header.to_lowercase(), None),
+    };
+
+    let (measurement, tag, default_unit) = match name.as_str() {
+        // Ecowitt
+        "outdoor temperature" => ("temperature", ("sensor", "outdoor"), None),
+        "indoor temperature" => ("temperature", ("sensor", "indoor"), None),
+        "dew point" => ("temperature", ("sensor", "dew_point"), None),
+        "feels like" => ("temperature", ("sensor", "feels_like"), None),
+        "outdoor humidity" => ("humidity", ("sensor", "outdoor"), Some(Unit::Percent)),
+        "indoor humidity" => ("humidity", ("sensor", "indoor"), Some(Unit::Percent)),
+        "wind" | "wind speed" => ("wind", ("kind", "speed"), None),
+        "gust" | "wind gust" => ("wind", ("kind", "gust"), None),
+        "wind direction" => ("wind", ("kind", "direction"), Some(Unit::Degrees)),
+        "rain rate" => ("rain", ("kind", "rate"), None),
+        "event rain" => ("rain", ("kind", "event"), None),
+        "hourly rain" => ("rain", ("kind", "hourly"), None),
+        "daily rain" => ("rain", ("kind", "daily"), None),
+        // Weather Underground, with the unit in the name
+        "temperaturef" => ("temperature", ("sensor", "outdoor"), Some(Unit::Fahrenheit)),
+        "temperaturec" => ("temperature", ("sensor", "outdoor"), Some(Unit::Celsius)),
+        "dewpointf" => (
+            "temperature",
+            ("sensor", "dew_point"),
+            Some(Unit::Fahrenheit),
+        ),
+        "dewpointc" => ("temperature", ("sensor", "dew_point"), Some(Unit::Celsius)),
+        "humidity" => ("humidity", ("sensor", "outdoor"), Some(Unit::Percent)),
+        "windspeedmph" => ("wind", ("kind", "speed"), Some(Unit::MilesPerHour)),
+        "windspeedkmh" => ("wind", ("kind", "speed"), Some(Unit::KilometersPerHour)),
+        "windspeedgustmph" => ("wind", ("kind", "gust"), Some(Unit::MilesPerHour)),
+        "windspeedgustkmh" => ("wind", ("kind", "gust"), Some(Unit::KilometersPerHour)),
+        "winddirectiondegrees" => ("wind", ("kind", "direction"), Some(Unit::Degrees)),
+        "hourlyprecipin" => ("rain", ("kind", "rate"), Some(Unit::Inches)),
+        "hourlyprecipmm" => ("rain", ("kind", "rate"), Some(Unit::Millimeters)),
+        "dailyrainin" => ("rain", ("kind", "daily"), Some(Unit::Inches)),
+        "dailyrainmm" => ("rain", ("kind", "daily"), Some(Unit::Millimeters)),
+        _ => return None,
+    };
+    Some(Channel {
+        index,
+        measurement,
+        tag,
+        unit: unit.or(default_unit)?,
+    })
+}
+
+/// The readings of one row of a weather station export
+#[derive(Debug, Clone)]
+pub struct WeatherReading {
+    pub timestamp: DateTime<Utc>,
+    /// Converted points, one per column with a value
+    pub points: Vec<DataPoint>,
+}
+
+/// Reads the CSV export of an Ecowitt or Weather Underground station, writing temperature,
+/// humidity, wind and rain with a `station` tag
+///
+/// Columns are recognized by name and unit, so it does not matter whether the station
+/// exported imperial or metric units: they are converted to the unit system chosen
+pub struct WeatherReader {
+    file_path: String,
+    station: String,
+    units: UnitSystem,
+    time_column: String,
+    time_format: String,
+}
+
+impl WeatherReader {
+    /// Creates a reader for a station export, tagging its points with the file name
+    pub fn new(file_path: &str) -> Self {
+        let station = Path::new(file_path)
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("station")
+            .to_string();
+        WeatherReader {
+            file_path: file_path.to_string(),
+            station,
+            units: UnitSystem::default(),
+            time_column: "DateUTC".to_string(),
+            time_format: "%Y-%m-%d %H:%M:%S".to_string(),
+        }
+    }
+
+    /// Sets the `station` tag of the points
+    pub fn with_station(mut self, station: &str) -> Self {
+        self.station = station.to_string();
+        self
+    }
+
+    /// Sets the unit system the readings are written in
+    pub fn with_units(mut self, units: UnitSystem) -> Self {
+        self.units = units;
+        self
+    }
+
+    /// Sets the column holding the time of each reading and its format; times are taken as
+    /// UTC. Without one, DateUTC, Time or timestamp is used
+    pub fn with_time_column(mut self, column: &str, format: &str) -> Self {
+        self.time_column = column.to_string();
+        self.time_format = format.to_string();
+        self
+    }
+
+    fn parse_time(&self, text: &str) -> Option<DateTime<Utc>> {
+        let text = text.trim();
+        if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+            return Some(time.with_timezone(&Utc));
+        }
+        std::iter::once(self.time_format.as_str())
+            .chain(TIME_FORMATS)
+            .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+            .map(|naive| naive.and_utc())
+    }
+
+    /// Reads every row with a valid time, oldest first
+    pub fn readings(&self) -> Result<Vec<WeatherReading>, Box<dyn Error>> {
+        let contents = fs::read_to_string(&self.file_path)
+            .map_err(|e| format!("Failed to read {}: {}", self.file_path, e))?;
+        let mut reader = ReaderBuilder::new()
+            .flexible(true)
+            .from_reader(contents.as_bytes());
+        let headers: Vec<String> = reader.headers()?.iter().map(str::to_string).collect();
+
+        let lowercase: Vec<String> = headers.iter().map(|h| h.trim().to_lowercase()).collect();
+        let time_index = std::iter::once(self.time_column.to_lowercase())
+            .chain(TIME_COLUMNS.map(String::from))
+            .find_map(|column| lowercase.iter().position(|header| *header == column))
+            .ok_or_else(|| format!("No time column '{}' in the header", self.time_column))?;
+        let channels: Vec<Channel> = headers
+            .iter()
+            .enumerate()
+            .filter_map(|(index, header)| channel(index, header))
+            .collect();
+        if channels.is_empty() {
+            return Err("No temperature, humidity, wind or rain columns in the header".into());
+        }
+
+        let mut readings = Vec::new();
+        for row in reader.records() {
+            let row = row?;
+            let Some(timestamp) = row.get(time_index).and_then(|text| self.parse_time(text)) else {
+                continue;
+            };
+            // Missing readings are exported as "--" or left empty
+            let points = channels
+                .iter()
+                .filter_map(|channel| {
+                    let value: f64 = row.get(channel.index)?.trim().parse().ok()?;
+                    Some(DataPoint {
+                        measurement: channel.measurement.to_string(),
+                        time: timestamp,
+                        tags: HashMap::from([
+                            ("station".to_string(), self.station.clone()),
+                            (channel.tag.0.to_string(), channel.tag.1.to_string()),
+                        ]),
+                        field_value: channel.unit.convert(value, self.units),
+                        text_fields: BTreeMap::new(),
+                    })
+                })
+                .collect();
+            readings.push(WeatherReading { timestamp, points });
+        }
+        readings.sort_by_key(|reading| reading.timestamp);
+        Ok(readings)
+    }
+}
+
+impl Source for WeatherReader {
+    type Record = WeatherReading;
+
+    fn validate(&self) -> Result<String, Box<dyn Error>> {
+        let readings = self.readings()?;
+        Ok(format!(
+            "Weather station export: {} ({} readings, station '{}')",
+            self.file_path,
+            readings.len(),
+            self.station
+        ))
+    }
+
+    fn records_since(
+        &self,
+        watermark: &ImportState,
+    ) -> Result<RecordStream<WeatherReading>, Box<dyn Error>> {
+        let readings = self.readings()?;
+        let Some(last_imported) = watermark.last_imported_timestamp else {
+            return Ok(RecordStream::new(readings));
+        };
+
+        let total = readings.len();
+        let new_readings: Vec<WeatherReading> = readings
+            .into_iter()
+            .filter(|reading| reading.timestamp > last_imported)
+            .collect();
+        let already_imported = total - new_readings.len();
+        Ok(RecordStream::new(new_readings).with_already_imported(already_imported))
+    }
+
+    fn timestamp(&self, record: &WeatherReading) -> Option<DateTime<Utc>> {
+        Some(record.timestamp)
+    }
+
+    fn to_points(&self, record: &WeatherReading) -> Result<Vec<DataPoint>, Box<dyn Error>> {
+        Ok(record.points.clone())
+    }
+}
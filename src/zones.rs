@@ -0,0 +1,268 @@
+use crate::aggregate::{read_from, start_of_day};
+use crate::derived::{DerivedPoints, DerivedStage};
+use crate::health_data::{HealthDataReader, HealthDataType, HealthRecord, ReadFrom};
+use crate::influx_client::DataPoint;
+use crate::training::MAX_SAMPLE_MINUTES;
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+
+/// Measurement the minutes spent in each heart rate zone are written to
+pub const HEART_RATE_ZONES_MEASUREMENT: &str = "HeartRateZones";
+
+/// Lower bounds of the default zones, as fractions of the maximum heart rate
+const DEFAULT_ZONE_FRACTIONS: [f64; 5] = [0.5, 0.6, 0.7, 0.8, 0.9];
+
+/// Heart rate zones, by the lowest heart rate of each: zone 1 from the first bound up to
+/// the second, and so on, the last zone having no upper bound. Time below the first
+/// bound is in no zone
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeartRateZones {
+    lower_bounds: Vec<f64>,
+}
+
+impl HeartRateZones {
+    /// Creates zones starting at these heart rates, which must be increasing
+    pub fn new(lower_bounds: &[u32]) -> Result<Self, String> {
+        if lower_bounds.is_empty() {
+            return Err("no heart rate zone boundaries".to_string());
+        }
+        if lower_bounds.windows(2).any(|pair| pair[0] >= pair[1]) {
+            return Err(format!(
+                "heart rate zone boundaries must be increasing: {:?}",
+                lower_bounds
+            ));
+        }
+        Ok(Self {
+            lower_bounds: lower_bounds.iter().map(|bpm| *bpm as f64).collect(),
+        })
+    }
+
+    /// Creates the usual five zones, starting at 50, 60, 70, 80 and 90% of the maximum
+    /// heart rate
+    pub fn from_max_heart_rate(max_heart_rate: u32) -> Self {
+        Self {
+            lower_bounds: DEFAULT_ZONE_FRACTIONS
+                .iter()
+                .map(|fraction| (max_heart_rate as f64 * fraction).round())
+                .collect(),
+        }
+    }
+
+    /// Gets the zone of a heart rate, from 1, or None below the first zone
+    fn zone(&self, bpm: f64) -> Option<usize> {
+        let zone = self.lower_bounds.partition_point(|bound| *bound <= bpm);
+        (zone > 0).then_some(zone)
+    }
+
+    /// Adds the minutes of `samples`, sorted by time, to the zones of `minutes` (by zone,
+    /// from 1). Each sample holds until the next one or `end`, for up to 5 minutes
+    fn add_minutes(
+        &self,
+        samples: &[&HealthRecord],
+        end: Option<DateTime<Utc>>,
+        minutes: &mut [f64],
+    ) {
+        for (index, sample) in samples.iter().enumerate() {
+            let Some(zone) = self.zone(sample.value) else {
+                continue;
+            };
+            let until = samples.get(index + 1).map(|next| next.timestamp).or(end);
+            let held = until.map_or(0.0, |until| {
+                (until - sample.timestamp).num_seconds() as f64 / 60.0
+            });
+            minutes[zone - 1] += held.clamp(0.0, MAX_SAMPLE_MINUTES);
+        }
+    }
+
+    /// Computes the minutes spent in each zone on each of `days` and during each workout
+    /// started on them, out of `records`, which must hold their HeartRate samples and
+    /// ExerciseSession records. Days are those of the local time the samples were taken at,
+    /// see [`HealthRecord::local_time`]
+    ///
+    /// Day points are written at the start of the day in `timezone` with `period=day`, per
+    /// app, as two apps can
+    /// record the same heartbeats; workout points at the session's start with
+    /// `period=workout` and its `exercise_type`, out of the samples of the app that took
+    /// the most during it. Every zone gets a point, with 0 minutes when none were spent in it
+    pub fn points(
+        &self,
+        records: &[HealthRecord],
+        days: &BTreeSet<NaiveDate>,
+        timezone: Tz,
+    ) -> Vec<DataPoint> {
+        let mut samples: BTreeMap<&str, Vec<&HealthRecord>> = BTreeMap::new();
+        for record in records
+            .iter()
+            .filter(|record| record.record_type == HealthDataType::HeartRate)
+        {
+            samples.entry(app_name(record)).or_default().push(record);
+        }
+        for app_samples in samples.values_mut() {
+            app_samples.sort_by_key(|sample| sample.timestamp);
+        }
+
+        let mut points = Vec::new();
+        let zone_points = |time: DateTime<Utc>, tags: HashMap<String, String>, minutes: &[f64]| {
+            minutes
+                .iter()
+                .enumerate()
+                .map(move |(index, minutes)| {
+                    let mut tags = tags.clone();
+                    tags.insert("zone".to_string(), (index + 1).to_string());
+                    DataPoint {
+                        measurement: HEART_RATE_ZONES_MEASUREMENT.to_string(),
+                        time,
+                        tags,
+                        field_value: (minutes * 10.0).round() / 10.0,
+                        text_fields: BTreeMap::new(),
+                    }
+                })
+                .collect::<Vec<_>>()
+        };
+
+        for (app, app_samples) in &samples {
+            for day in days {
+                let first =
+                    app_samples.partition_point(|sample| local_day(sample, timezone) < *day);
+                let last =
+                    app_samples.partition_point(|sample| local_day(sample, timezone) <= *day);
+                if first == last {
+                    continue;
+                }
+                // The last sample of the day holds until its local midnight
+                let next_day = *day + chrono::Duration::days(1);
+                let end = match app_samples[last - 1].zone_offset {
+                    Some(offset) => {
+                        next_day.and_hms_opt(0, 0, 0).unwrap().and_utc()
+                            - chrono::Duration::seconds(offset.into())
+                    }
+                    None => start_of_day(next_day, timezone),
+                };
+                let mut minutes = vec![0.0; self.lower_bounds.len()];
+                self.add_minutes(&app_samples[first..last], Some(end), &mut minutes);
+                let start = start_of_day(*day, timezone);
+                let tags = HashMap::from([
+                    ("period".to_string(), "day".to_string()),
+                    ("app_name".to_string(), app.to_string()),
+                ]);
+                points.extend(zone_points(start, tags, &minutes));
+            }
+        }
+
+        for session in records.iter().filter(|record| {
+            record.record_type == HealthDataType::ExerciseSession
+                && days.contains(&local_day(record, timezone))
+        }) {
+            let Some(end) = session
+                .metadata
+                .get("end_time_millis")
+                .and_then(|millis| millis.parse().ok())
+                .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+            else {
+                continue;
+            };
+            let during = samples
+                .values()
+                .map(|app_samples| {
+                    let first =
+                        app_samples.partition_point(|sample| sample.timestamp < session.timestamp);
+                    let last = app_samples.partition_point(|sample| sample.timestamp < end);
+                    &app_samples[first..last]
+                })
+                .max_by_key(|during| during.len());
+            let Some(during) = during.filter(|during| !during.is_empty()) else {
+                continue;
+            };
+            let mut minutes = vec![0.0; self.lower_bounds.len()];
+            self.add_minutes(during, Some(end), &mut minutes);
+            let mut tags = HashMap::from([
+                ("period".to_string(), "workout".to_string()),
+                ("app_name".to_string(), app_name(session).to_string()),
+            ]);
+            if let Some(exercise_type) = session.metadata.get("exercise_type") {
+                tags.insert("exercise_type".to_string(), exercise_type.clone());
+            }
+            points.extend(zone_points(session.timestamp, tags, &minutes));
+        }
+        points
+    }
+}
+
+/// Gets the app that wrote a record
+fn app_name(record: &HealthRecord) -> &str {
+    record
+        .metadata
+        .get("app_name")
+        .map(String::as_str)
+        .unwrap_or("unknown")
+}
+
+/// Gets the day a record was taken on, in its local time
+fn local_day(record: &HealthRecord, timezone: Tz) -> NaiveDate {
+    record.local_time(timezone).date()
+}
+
+/// Gets the days whose zone minutes change with `records`: those of their heart rate
+/// samples and workouts
+pub fn days_touched(records: &[HealthRecord], timezone: Tz) -> BTreeSet<NaiveDate> {
+    records
+        .iter()
+        .filter(|record| {
+            matches!(
+                record.record_type,
+                HealthDataType::HeartRate | HealthDataType::ExerciseSession
+            )
+        })
+        .map(|record| local_day(record, timezone))
+        .collect()
+}
+
+/// Writes the zone minutes again for the days the samples and workouts written fall on
+pub struct HeartRateZonesStage {
+    zones: HeartRateZones,
+    timezone: Tz,
+    days: BTreeSet<NaiveDate>,
+}
+
+impl HeartRateZonesStage {
+    /// Creates the stage for `zones`, with days in `timezone`
+    pub fn new(zones: HeartRateZones, timezone: Tz) -> Self {
+        HeartRateZonesStage {
+            zones,
+            timezone,
+            days: BTreeSet::new(),
+        }
+    }
+}
+
+impl DerivedStage for HeartRateZonesStage {
+    fn name(&self) -> &str {
+        "heart rate zones"
+    }
+
+    fn inputs(&self) -> &[HealthDataType] {
+        &[HealthDataType::HeartRate, HealthDataType::ExerciseSession]
+    }
+
+    fn prepare(
+        &mut self,
+        _reader: &HealthDataReader,
+        written: &[HealthRecord],
+    ) -> Result<Option<ReadFrom>, Box<dyn Error>> {
+        self.days = days_touched(written, self.timezone);
+        Ok(self
+            .days
+            .first()
+            .map(|first| ReadFrom::Timestamp(read_from(*first))))
+    }
+
+    fn compute(&self, records: &[HealthRecord]) -> DerivedPoints {
+        DerivedPoints::Single(self.zones.points(records, &self.days, self.timezone))
+    }
+
+    fn describe(&self) -> String {
+        format!(" for {} days", self.days.len())
+    }
+}
@@ -0,0 +1,279 @@
+use chrono::{NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use home_db_importer::aggregate::{
+    daily_aggregates, days_touched, read_from, sleep_debt, sleep_debt_days, sleep_debt_read_from,
+    start_of_day, DailyAggregate, DailyAggregateStage,
+};
+use home_db_importer::derived::{DerivedPoints, DerivedStage};
+use home_db_importer::health_data::{HealthDataReader, HealthDataType, HealthRecord, ReadFrom};
+use home_db_importer::influx_client::DataPoint;
+
+fn record(
+    record_type: HealthDataType,
+    (day, hour, minute): (u32, u32, u32),
+    value: f64,
+    metadata: &[(&str, &str)],
+) -> HealthRecord {
+    HealthRecord {
+        record_type,
+        timestamp: Utc.with_ymd_and_hms(2024, 3, day, hour, minute, 0).unwrap(),
+        value,
+        metadata: metadata
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .chain([("app_name".to_string(), "watch".to_string())])
+            .collect(),
+        row_id: Some(day as i64),
+        zone_offset: None,
+    }
+}
+
+fn value(points: &[DataPoint], measurement: &str, day: u32) -> Option<f64> {
+    let time = Utc.with_ymd_and_hms(2024, 3, day, 0, 0, 0).unwrap();
+    points
+        .iter()
+        .find(|point| point.measurement == measurement && point.time == time)
+        .map(|point| point.field_value)
+}
+
+// Test that steps and calories are summed per day
+#[test]
+fn test_daily_totals() {
+    let records = vec![
+        record(HealthDataType::Steps, (1, 8, 0), 1000.0, &[]),
+        record(HealthDataType::Steps, (1, 23, 59), 500.0, &[]),
+        record(HealthDataType::Steps, (2, 0, 0), 200.0, &[]),
+        record(HealthDataType::ActiveCalories, (1, 9, 0), 120.5, &[]),
+        record(HealthDataType::TotalCalories, (1, 9, 0), 2100.0, &[]),
+    ];
+
+    let points = daily_aggregates(&records, &[DailyAggregate::Steps], Tz::UTC);
+    assert_eq!(points.len(), 2);
+    assert_eq!(value(&points, "daily_steps", 1), Some(1500.0));
+    assert_eq!(value(&points, "daily_steps", 2), Some(200.0));
+    assert_eq!(points[0].tags["app_name"], "watch");
+
+    let points = daily_aggregates(&records, &DailyAggregate::ALL, Tz::UTC);
+    assert_eq!(value(&points, "daily_active_calories", 1), Some(120.5));
+    assert_eq!(value(&points, "daily_total_calories", 1), Some(2100.0));
+}
+
+// Test that records count towards the day of the local time they were taken at
+#[test]
+fn test_local_days() {
+    let in_zone = |time, value, hours: i32| HealthRecord {
+        zone_offset: Some(hours * 3600),
+        ..record(HealthDataType::Steps, time, value, &[])
+    };
+    let records = vec![
+        // 23:30 UTC is already the next day at UTC+2
+        in_zone((1, 23, 30), 300.0, 2),
+        // 01:00 UTC is still the day before at UTC-5
+        in_zone((3, 1, 0), 700.0, -5),
+        record(HealthDataType::Steps, (2, 12, 0), 1000.0, &[]),
+    ];
+
+    let points = daily_aggregates(&records, &[DailyAggregate::Steps], Tz::UTC);
+    assert_eq!(value(&points, "daily_steps", 1), None);
+    assert_eq!(value(&points, "daily_steps", 2), Some(2000.0));
+    assert_eq!(value(&points, "daily_steps", 3), None);
+    let day = |day| NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+    assert_eq!(
+        days_touched(&records, &DailyAggregate::ALL, Tz::UTC),
+        [day(2)].into()
+    );
+}
+
+// Test that records without a zone offset count towards the day of the time zone given,
+// and that days are written at its midnight
+#[test]
+fn test_timezone_days() {
+    let rome: Tz = "Europe/Rome".parse().unwrap();
+    let records = vec![
+        // 23:30 UTC is already the next day in Rome (UTC+1 in March)
+        record(HealthDataType::Steps, (1, 23, 30), 300.0, &[]),
+        record(HealthDataType::Steps, (2, 12, 0), 1000.0, &[]),
+    ];
+
+    let points = daily_aggregates(&records, &[DailyAggregate::Steps], rome);
+    assert_eq!(points.len(), 1);
+    assert_eq!(points[0].field_value, 1300.0);
+    assert_eq!(
+        points[0].time,
+        Utc.with_ymd_and_hms(2024, 3, 1, 23, 0, 0).unwrap()
+    );
+    let day = |day| NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+    assert_eq!(
+        days_touched(&records, &DailyAggregate::ALL, rome),
+        [day(2)].into()
+    );
+    assert_eq!(
+        start_of_day(day(2), Tz::UTC),
+        Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap()
+    );
+}
+
+// Test that a night counts once towards the day it ended, however many stage records it
+// was read as
+#[test]
+fn test_daily_sleep() {
+    let stage = |event_type, time, stage| {
+        record(
+            HealthDataType::Sleep,
+            time,
+            2.0,
+            &[
+                ("event_type", event_type),
+                ("stage", stage),
+                ("duration_minutes", "480"),
+            ],
+        )
+    };
+    let records = vec![
+        stage("start", (1, 23, 0), "LIGHT"),
+        stage("start", (1, 23, 0), "LIGHT"),
+        stage("end", (2, 7, 0), "LIGHT"),
+        // Time awake is not sleep
+        stage("start", (2, 7, 0), "AWAKE"),
+    ];
+
+    let points = daily_aggregates(&records, &[DailyAggregate::Sleep], Tz::UTC);
+    assert_eq!(points.len(), 1);
+    assert_eq!(value(&points, "daily_sleep_minutes", 2), Some(480.0));
+    assert_eq!(
+        days_touched(&records, &[DailyAggregate::Sleep], Tz::UTC),
+        [NaiveDate::from_ymd_opt(2024, 3, 2).unwrap()].into()
+    );
+    // The night started the day before
+    assert!(read_from(NaiveDate::from_ymd_opt(2024, 3, 2).unwrap()) < records[0].timestamp);
+}
+
+// Test the average heart rate of a day, and its resting heart rate as the lowest average of
+// the hours with enough samples
+#[test]
+fn test_daily_heart_rate() {
+    let mut records = Vec::new();
+    for (minute, bpm) in [(0, 55.0), (20, 57.0), (40, 59.0)] {
+        records.push(record(HealthDataType::HeartRate, (1, 4, minute), bpm, &[]));
+    }
+    for (minute, bpm) in [(0, 90.0), (30, 100.0), (45, 110.0)] {
+        records.push(record(HealthDataType::HeartRate, (1, 12, minute), bpm, &[]));
+    }
+    // A single low reading is not a resting heart rate
+    records.push(record(HealthDataType::HeartRate, (1, 20, 0), 40.0, &[]));
+
+    let points = daily_aggregates(&records, &[DailyAggregate::HeartRate], Tz::UTC);
+    assert_eq!(value(&points, "daily_heart_rate", 1), Some(73.0));
+    assert_eq!(value(&points, "daily_resting_heart_rate", 1), Some(57.0));
+    assert!(days_touched(&records, &[DailyAggregate::Steps], Tz::UTC).is_empty());
+}
+
+// Test days across DST changes: the hour repeated when DST ends counts as two, and a day
+// whose midnight is skipped starts at the change
+#[test]
+fn test_dst_days() {
+    let rome: Tz = "Europe/Rome".parse().unwrap();
+    let sample = |hour, minute, bpm| HealthRecord {
+        timestamp: Utc.with_ymd_and_hms(2024, 10, 27, hour, minute, 0).unwrap(),
+        ..record(HealthDataType::HeartRate, (1, 0, 0), bpm, &[])
+    };
+    // 00:xx UTC is 02:xx CEST, and 01:xx UTC is 02:xx again once clocks went back
+    let mut records = Vec::new();
+    for (hour, bpm) in [(0, 50.0), (1, 70.0)] {
+        for minute in [0, 20, 40] {
+            records.push(sample(hour, minute, bpm));
+        }
+    }
+
+    let points = daily_aggregates(&records, &[DailyAggregate::HeartRate], rome);
+    let resting = points
+        .iter()
+        .find(|point| point.measurement == "daily_resting_heart_rate")
+        .unwrap();
+    assert_eq!(resting.field_value, 50.0);
+    assert_eq!(
+        resting.time,
+        Utc.with_ymd_and_hms(2024, 10, 26, 22, 0, 0).unwrap()
+    );
+
+    // Clocks in Santiago went from midnight to 01:00 on 8 September 2024
+    let santiago: Tz = "America/Santiago".parse().unwrap();
+    let day = |day| NaiveDate::from_ymd_opt(2024, 9, day).unwrap();
+    assert_eq!(
+        start_of_day(day(8), santiago),
+        Utc.with_ymd_and_hms(2024, 9, 8, 4, 0, 0).unwrap()
+    );
+    assert_eq!(
+        start_of_day(day(9), santiago),
+        Utc.with_ymd_and_hms(2024, 9, 9, 3, 0, 0).unwrap()
+    );
+}
+
+// Test the balance of each night against the target, and the debt of the last 7 nights
+#[test]
+fn test_sleep_debt() {
+    let night = |day, minutes: &str, app: &str| {
+        let mut record = record(
+            HealthDataType::Sleep,
+            (day, 0, 0),
+            2.0,
+            &[("event_type", "start"), ("duration_minutes", minutes)],
+        );
+        record
+            .metadata
+            .insert("app_name".to_string(), app.to_string());
+        record
+    };
+    let records = vec![
+        night(1, "420", "watch"),
+        // Recorded by two apps, counting as the longest
+        night(2, "500", "watch"),
+        night(2, "450", "phone"),
+        night(9, "480", "watch"),
+    ];
+    let day = |day| NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+
+    let points = sleep_debt(&records, 480.0, day(1), day(9), Tz::UTC);
+    assert_eq!(value(&points, "sleep_balance", 1), Some(-60.0));
+    assert_eq!(value(&points, "sleep_balance", 2), Some(20.0));
+    // Nights without records have no balance and add no debt
+    assert_eq!(value(&points, "sleep_balance", 3), None);
+    assert_eq!(value(&points, "sleep_debt", 7), Some(40.0));
+    assert_eq!(value(&points, "sleep_debt", 8), Some(-20.0));
+    assert_eq!(value(&points, "sleep_debt", 9), Some(0.0));
+
+    let touched = [day(2), day(4)].into();
+    assert_eq!(sleep_debt_days(&touched, day(30)), Some((day(2), day(10))));
+    assert_eq!(sleep_debt_days(&touched, day(5)), Some((day(2), day(5))));
+    assert_eq!(sleep_debt_days(&[].into(), day(5)), None);
+    assert!(sleep_debt_read_from(day(8)) < records[0].timestamp);
+}
+
+// Test that the daily aggregate stage reads from the first day the records written touch,
+// and writes only the days they touch
+#[test]
+fn test_daily_aggregate_stage() {
+    let records = vec![
+        record(HealthDataType::Steps, (1, 8, 0), 1000.0, &[]),
+        record(HealthDataType::Steps, (2, 8, 0), 200.0, &[]),
+        record(HealthDataType::Steps, (3, 8, 0), 300.0, &[]),
+        record(HealthDataType::Steps, (3, 9, 0), 50.0, &[]),
+    ];
+    let reader = HealthDataReader::new("missing.db");
+    let mut stage = DailyAggregateStage::new(&[DailyAggregate::Steps], Tz::UTC);
+    assert_eq!(stage.inputs(), [HealthDataType::Steps]);
+
+    // Records of other data types touch nothing
+    let heart_rate = [record(HealthDataType::HeartRate, (2, 8, 0), 60.0, &[])];
+    assert_eq!(stage.prepare(&reader, &heart_rate).unwrap(), None);
+
+    let since = stage.prepare(&reader, &records[2..]).unwrap();
+    let day = NaiveDate::from_ymd_opt(2024, 3, 3).unwrap();
+    assert_eq!(since, Some(ReadFrom::Timestamp(read_from(day))));
+    let DerivedPoints::Single(points) = stage.compute(&records) else {
+        panic!("daily aggregates are single value points");
+    };
+    assert_eq!(points.len(), 1);
+    assert_eq!(value(&points, "daily_steps", 3), Some(350.0));
+    assert_eq!(stage.describe(), " for 1 days");
+}
@@ -0,0 +1,45 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::anonymize::{pseudonym, Anonymize};
+use home_db_importer::influx_client::DataPoint;
+use std::collections::{BTreeMap, HashMap};
+
+fn point() -> DataPoint {
+    DataPoint {
+        measurement: "ExerciseSession".to_string(),
+        time: Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap(),
+        tags: HashMap::from([
+            (
+                "app_name".to_string(),
+                "com.sec.android.app.shealth".to_string(),
+            ),
+            ("title".to_string(), "Run to Anna's".to_string()),
+            ("exercise_type".to_string(), "56".to_string()),
+        ]),
+        field_value: 45.0,
+        text_fields: BTreeMap::new(),
+    }
+}
+
+// Test that hashing replaces identifying tags with stable pseudonyms
+#[test]
+fn test_hash() {
+    let anonymized = Anonymize::Hash.point(&point());
+    let app = &anonymized.tags["app_name"];
+    assert_eq!(app.len(), 12);
+    assert_eq!(*app, pseudonym("com.sec.android.app.shealth"));
+    assert_ne!(anonymized.tags["title"], *app);
+    // Other tags and the value are left alone
+    assert_eq!(anonymized.tags["exercise_type"], "56");
+    assert_eq!(anonymized.field_value, 45.0);
+    assert_eq!(Anonymize::Hash.point(&point()).tags, anonymized.tags);
+}
+
+// Test that stripping leaves identifying tags out
+#[test]
+fn test_strip() {
+    let anonymized = Anonymize::Strip.point(&point());
+    assert_eq!(
+        anonymized.tags,
+        HashMap::from([("exercise_type".to_string(), "56".to_string())])
+    );
+}
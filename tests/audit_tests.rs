@@ -0,0 +1,82 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::audit::{batch_entries, read_audit_log, AuditLog};
+use home_db_importer::influx_client::{Precision, STATE_MEASUREMENT};
+use tempfile::tempdir;
+
+// Test that a write request gets an entry per measurement, with the time range of its
+// points, leaving out the import state
+#[test]
+fn test_batch_entries() {
+    let body = format!(
+        "HeartRate,app=fit value=60 1709280000000\n\
+         Body\\ Fat value=20 1709283600000\n\
+         HeartRate value=70 1709276400000\n\
+         {},name=health document=\"{{}}\" 1709280000000",
+        STATE_MEASUREMENT
+    );
+    let written_at = Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap();
+    let entries = batch_entries(
+        "run-1",
+        "home",
+        Some("autogen"),
+        &body,
+        Precision::Milliseconds,
+        written_at,
+    );
+
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].measurement, "Body Fat");
+    assert_eq!(entries[0].points, 1);
+    let heart_rate = &entries[1];
+    assert_eq!(heart_rate.measurement, "HeartRate");
+    assert_eq!(heart_rate.run_id, "run-1");
+    assert_eq!(heart_rate.retention_policy.as_deref(), Some("autogen"));
+    assert_eq!(heart_rate.points, 2);
+    assert_eq!(
+        heart_rate.start,
+        Utc.with_ymd_and_hms(2024, 3, 1, 7, 0, 0).unwrap()
+    );
+    assert_eq!(
+        heart_rate.end,
+        Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap()
+    );
+    assert!(heart_rate.covers(
+        "HeartRate",
+        Utc.with_ymd_and_hms(2024, 3, 1, 7, 30, 0).unwrap()
+    ));
+    assert!(!heart_rate.covers(
+        "HeartRate",
+        Utc.with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap()
+    ));
+    assert!(!heart_rate.covers("Steps", Utc.with_ymd_and_hms(2024, 3, 1, 7, 30, 0).unwrap()));
+}
+
+// Test that entries are appended to the log and read back in order
+#[test]
+fn test_audit_log_appends() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let path = path.to_str().unwrap();
+
+    AuditLog::new(path, "first")
+        .record("home", None, "Steps value=1 1709280000", Precision::Seconds)
+        .unwrap();
+    let second = AuditLog::new(path, "second");
+    second.record("home", None, "", Precision::Seconds).unwrap();
+    second
+        .record(
+            "home",
+            None,
+            "Weight value=80 1709280000",
+            Precision::Seconds,
+        )
+        .unwrap();
+
+    let entries = read_audit_log(path).unwrap();
+    let runs: Vec<(&str, &str)> = entries
+        .iter()
+        .map(|entry| (entry.run_id.as_str(), entry.measurement.as_str()))
+        .collect();
+    assert_eq!(runs, [("first", "Steps"), ("second", "Weight")]);
+    assert_eq!(entries[1].retention_policy, None);
+}
@@ -0,0 +1,63 @@
+use chrono::Utc;
+use home_db_importer::bench;
+use home_db_importer::csv_parser::CsvParser;
+use home_db_importer::influx_client::DataPoint;
+use home_db_importer::sink::{NullSink, Sink};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::Arc;
+
+// Test that every stage of a funds CSV is measured, with one pipeline run per batch size
+#[tokio::test]
+async fn test_bench_funds_csv() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("funds.csv");
+    fs::write(
+        &path,
+        "timestamp,Fund A,Fund B\n,price,price\n\
+         2024-01-01 00:00:00,1.5,2\n2024-01-02 00:00:00,1.75,n/a\n",
+    )
+    .unwrap();
+    let parser = CsvParser::new(path.to_str().unwrap()).with_header_rows(2);
+
+    let results = bench::run(Arc::new(parser), &[1, 100]).await.unwrap();
+
+    let stages: Vec<(&str, usize)> = results
+        .iter()
+        .map(|result| (result.stage.as_str(), result.items))
+        .collect();
+    assert_eq!(
+        stages,
+        [
+            ("Read records", 2),
+            ("Convert to points", 3),
+            ("Serialize line protocol", 3),
+            ("Pipeline, batches of 1", 3),
+            ("Pipeline, batches of 100", 3),
+        ]
+    );
+    assert!(results.iter().all(|result| result.per_second() >= 0.0));
+}
+
+// Test that a missing source fails the benchmark
+#[tokio::test]
+async fn test_bench_missing_source() {
+    let parser = CsvParser::new("does_not_exist.csv");
+    assert!(bench::run(Arc::new(parser), &[1000]).await.is_err());
+}
+
+// Test that the null sink counts the requests a write would need
+#[tokio::test]
+async fn test_null_sink_counts_requests() {
+    let point = DataPoint {
+        measurement: "price".to_string(),
+        tags: HashMap::new(),
+        field_value: 1.0,
+        time: Utc::now(),
+        text_fields: BTreeMap::new(),
+    };
+    let sink = NullSink::new(2);
+    sink.write_points(&vec![point; 5]).await.unwrap();
+    assert_eq!(sink.points(), 5);
+    assert_eq!(sink.requests(), 3);
+}
@@ -0,0 +1,12 @@
+use std::fs;
+use tempfile::TempDir;
+
+/// Writes `contents` to a file named `name` in a new temporary directory, giving the
+/// directory (which removes the file when dropped) and the path of the file
+pub fn write_file(name: &str, contents: &str) -> (TempDir, String) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join(name);
+    fs::write(&path, contents).unwrap();
+    let path = path.to_str().unwrap().to_string();
+    (dir, path)
+}
@@ -0,0 +1,621 @@
+use home_db_importer::config::{Config, CONFIG_TEMPLATE};
+
+// Test that the template written by `init` is a valid configuration
+#[test]
+fn test_config_template_parses() {
+    let config = Config::parse(CONFIG_TEMPLATE).unwrap();
+
+    assert_eq!(config.influxdb.org.as_deref(), Some("myorg"));
+    assert_eq!(config.funds.header_rows, Some(2));
+    assert_eq!(
+        config.health.state_file.as_deref(),
+        Some(".health_import_state.json")
+    );
+}
+
+// Test which settings apply to each command, most specific section first
+#[test]
+fn test_settings_for_command() {
+    let config = Config::parse(
+        r#"
+        [influxdb]
+        url = "http://influx:8086"
+        bucket = "home"
+        connect_timeout = 5
+
+        [state]
+        backups = 1
+        source_cache = "/var/cache/home-db-importer"
+
+        [health]
+        bucket = "health_data"
+        data_types = ["HeartRate", "Steps"]
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![
+            ("data_types", "HeartRate,Steps".to_string()),
+            ("bucket", "health_data".to_string()),
+            ("state_backups", "1".to_string()),
+            ("source_cache", "/var/cache/home-db-importer".to_string()),
+            ("url", "http://influx:8086".to_string()),
+            ("bucket", "home".to_string()),
+            ("connect_timeout", "5".to_string()),
+        ]
+    );
+    assert_eq!(
+        config.settings_for("state reset"),
+        vec![
+            ("state_backups", "1".to_string()),
+            ("source_cache", "/var/cache/home-db-importer".to_string())
+        ]
+    );
+    assert!(config.settings_for("history").is_empty());
+}
+
+// Test that misspelled sections and settings are rejected
+#[test]
+fn test_unknown_config_settings() {
+    assert!(Config::parse("[influx]\nurl = \"http://localhost:8086\"").is_err());
+    assert!(Config::parse("[health]\ndatatypes = [\"Steps\"]").is_err());
+    assert!(Config::parse("[funds]\nheader_rows = \"two\"").is_err());
+}
+
+// Test reading scheduled jobs and their settings
+#[test]
+fn test_job_settings() {
+    let config = Config::parse(
+        r#"
+        [[jobs]]
+        name = "health"
+        command = "import-health-data"
+        schedule = "0 * * * *"
+        data_types = ["HeartRate", "Steps"]
+        checkpoint_every = 5
+        dry_run = false
+
+        [[jobs]]
+        name = "funds"
+        command = "import-funds"
+        state_file = "funds_state.json"
+        source = "funds.csv"
+
+        [health]
+        source = "exports/"
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+
+    // Each job gets its own state and spool files unless it sets them
+    assert_eq!(
+        config.job("health").unwrap().settings(),
+        vec![
+            ("checkpoint_every".to_string(), "5".to_string()),
+            ("data_types".to_string(), "HeartRate,Steps".to_string()),
+            ("state_file".to_string(), ".health_state.json".to_string()),
+            ("spool_file".to_string(), ".health_spool.lp".to_string()),
+        ]
+    );
+    assert_eq!(
+        config.job("funds").unwrap().settings()[1],
+        ("state_file".to_string(), "funds_state.json".to_string())
+    );
+
+    // A job's own settings win over those of its command's section
+    let source = |job: &str| config.job_setting(config.job(job).unwrap(), "source");
+    assert_eq!(source("health").as_deref(), Some("exports/"));
+    assert_eq!(source("funds").as_deref(), Some("funds.csv"));
+}
+
+// Test that invalid jobs are rejected
+#[test]
+fn test_invalid_jobs() {
+    let duplicate = Config::parse(
+        "[[jobs]]\nname = \"a\"\ncommand = \"import-funds\"\n\n[[jobs]]\nname = \"a\"\ncommand = \"import-funds\"",
+    )
+    .unwrap();
+    assert!(duplicate.validate().is_err());
+
+    let unknown_command = Config::parse("[[jobs]]\nname = \"a\"\ncommand = \"state\"").unwrap();
+    assert!(unknown_command.validate().is_err());
+
+    let bad_schedule = Config::parse(
+        "[[jobs]]\nname = \"a\"\ncommand = \"import-funds\"\nschedule = \"every night\"",
+    )
+    .unwrap();
+    assert!(bad_schedule.validate().is_err());
+}
+
+// Test that MQTT topics are read from the config file and checked when loading it
+#[test]
+fn test_mqtt_topics() {
+    let config = Config::parse(
+        r#"
+        [mqtt]
+        host = "broker"
+        flush_interval = 30
+
+        [[mqtt.topics]]
+        topic = "zigbee2mqtt/{device}"
+        measurement = "{field}"
+        fields = ["temperature"]
+        tags = { device = "{device}" }
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.mqtt.topics.len(), 1);
+    assert_eq!(config.mqtt.topics[0].tags["device"], "{device}");
+    assert_eq!(
+        config.settings_for("mqtt"),
+        vec![
+            ("host", "broker".to_string()),
+            ("flush_interval", "30".to_string()),
+        ]
+    );
+
+    let config = Config::parse(
+        r#"
+        [[mqtt.topics]]
+        topic = "zigbee2mqtt/{device}"
+        measurement = "{room}"
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().unwrap_err().contains("{room}"));
+}
+
+// Test that the [prices] section is passed to import-prices, which can run as a job
+#[test]
+fn test_prices_settings() {
+    let config = Config::parse(
+        r#"
+        [prices]
+        zone = "AT"
+        days = 3
+
+        [[jobs]]
+        name = "prices"
+        command = "import-prices"
+        schedule = "0 14 * * *"
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-prices"),
+        vec![("zone", "AT".to_string()), ("days", "3".to_string())]
+    );
+}
+
+// Test that the [nav] funds are read and its settings passed to fetch-nav
+#[test]
+fn test_nav_funds() {
+    let config = Config::parse(
+        r#"
+        [nav]
+        api_url = "https://api.example.com/{isin}"
+        price_path = "$.nav"
+
+        [[nav.funds]]
+        isin = "IE00B4L5Y983"
+        name = "Fund A"
+
+        [[nav.funds]]
+        isin = "LU0000000001"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.nav.funds.len(), 2);
+    assert_eq!(config.nav.funds[0].tag(), "Fund_A");
+    assert_eq!(config.nav.funds[1].name, None);
+    assert_eq!(
+        config.settings_for("fetch-nav"),
+        vec![
+            ("api_url", "https://api.example.com/{isin}".to_string()),
+            ("price_path", "$.nav".to_string()),
+        ]
+    );
+
+    assert!(Config::parse("[[nav.funds]]\nisin = \"X\"\nticker = \"Y\"\n").is_err());
+}
+
+// Test that the [fx] section builds a conversion only when it sets rates or fetching, with
+// fund names turned into their tags
+#[test]
+fn test_fx_conversion() {
+    let config = Config::parse(
+        r#"
+        [fx]
+        base = "EUR"
+
+        [fx.rates]
+        USD = 0.5
+
+        [fx.funds]
+        "Fund B" = "USD"
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    let conversion = config.fx.conversion().unwrap();
+    assert_eq!(conversion.rates().base(), "EUR");
+    assert_eq!(conversion.measurement("price"), "price_eur");
+
+    assert!(Config::parse("[fx]\nbase = \"USD\"\n")
+        .unwrap()
+        .fx
+        .conversion()
+        .is_none());
+    assert!(Config::parse("[fx.rates]\nUSD = 0.0\n")
+        .unwrap()
+        .validate()
+        .is_err());
+}
+
+// Test that holdings are read, and that a fund held twice is rejected
+#[test]
+fn test_portfolio_holdings() {
+    let config = Config::parse(
+        r#"
+        [portfolio]
+        measurement = "price_eur"
+
+        [[portfolio.holdings]]
+        fund = "Fund A"
+        quantity = 12.5
+        cost = 1000.0
+
+        [[portfolio.holdings]]
+        fund = "Fund B"
+        quantity = 3
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.portfolio.holdings.len(), 2);
+    assert_eq!(config.portfolio.holdings[1].quantity, 3.0);
+    assert_eq!(config.portfolio.holdings[1].cost, None);
+
+    let config = Config::parse(
+        "[[portfolio.holdings]]\nfund = \"A\"\nquantity = 1\n\
+         [[portfolio.holdings]]\nfund = \"A\"\nquantity = 2\n",
+    )
+    .unwrap();
+    assert!(config
+        .validate()
+        .unwrap_err()
+        .contains("more than one holding"));
+}
+
+// Test that funds column tags are read, and need a column or header and a tag
+#[test]
+fn test_funds_column_tags() {
+    let config = Config::parse(
+        r#"
+        [funds]
+        source = "funds.csv"
+
+        [[funds.columns]]
+        header = "Fund A"
+        account = "broker"
+
+        [[funds.columns]]
+        column = 4
+        currency = "USD"
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(config.funds.columns.len(), 2);
+    assert_eq!(config.funds.columns[1].column, Some(4));
+    // The columns are not a command line setting
+    assert_eq!(
+        config.settings_for("import-funds"),
+        vec![("source", "funds.csv".to_string())]
+    );
+
+    for invalid in [
+        "[[funds.columns]]\naccount = \"a\"\n",
+        "[[funds.columns]]\ncolumn = 1\n",
+        "[[funds.columns]]\ncolumn = 0\naccount = \"a\"\n",
+    ] {
+        assert!(Config::parse(invalid).unwrap().validate().is_err());
+    }
+}
+
+// Test that funds header roles are passed on to import-funds, and unknown roles refused
+#[test]
+fn test_funds_header_roles() {
+    let config = Config::parse(
+        r#"
+        [funds]
+        header_rows = 3
+        header_roles = ["fund", "account", "tag:isin"]
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-funds"),
+        vec![
+            ("header_rows", "3".to_string()),
+            ("header_roles", "fund,account,tag:isin".to_string()),
+        ]
+    );
+
+    let config = Config::parse("[funds]\nheader_roles = [\"fund\", \"price\"]\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("price"));
+}
+
+// Test that the fund tag key is passed on to import-funds and fetch-nav
+#[test]
+fn test_tag_key() {
+    let config = Config::parse(
+        r#"
+        [funds]
+        tag_key = "fund"
+
+        [nav]
+        tag_key = "security"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        config.settings_for("import-funds"),
+        vec![("tag_key", "fund".to_string())]
+    );
+    assert_eq!(
+        config.settings_for("fetch-nav"),
+        vec![("tag_key", "security".to_string())]
+    );
+}
+
+// Test that the Grafana settings are passed on to import-health-data, and unknown sessions
+// refused
+#[test]
+fn test_grafana_annotations() {
+    let config = Config::parse(
+        r#"
+        [grafana]
+        url = "http://localhost:3000"
+        token = "secret"
+        annotate = ["exercise", "sleep"]
+        tags = ["health"]
+        "#,
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![
+            ("grafana_url", "http://localhost:3000".to_string()),
+            ("grafana_token", "secret".to_string()),
+            ("annotate", "exercise,sleep".to_string()),
+            ("annotation_tags", "health".to_string()),
+        ]
+    );
+
+    let config = Config::parse("[grafana]\nannotate = [\"naps\"]\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("naps"));
+}
+
+// Test that daily aggregates are passed on to import-health-data, and unknown ones refused
+#[test]
+fn test_daily_aggregates() {
+    let config =
+        Config::parse("[health]\ndaily_aggregates = [\"steps\", \"heart-rate\"]\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("daily_aggregates", "steps,heart-rate".to_string())]
+    );
+
+    let config = Config::parse("[health]\ndaily_aggregates = [\"weight\"]\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("weight"));
+}
+
+// Test that the heart rates for the training load are passed on, and a resting heart rate
+// above the maximum refused
+#[test]
+fn test_training_load_heart_rates() {
+    let config =
+        Config::parse("[health]\nmax_heart_rate = 185\nresting_heart_rate = 52\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![
+            ("max_heart_rate", "185".to_string()),
+            ("resting_heart_rate", "52".to_string())
+        ]
+    );
+
+    let config = Config::parse("[health]\nmax_heart_rate = 60\nresting_heart_rate = 70\n").unwrap();
+    assert!(config
+        .validate()
+        .unwrap_err()
+        .contains("resting_heart_rate"));
+}
+
+// Test that the sleep target is passed on, and one that is not an interval refused
+#[test]
+fn test_sleep_target() {
+    let config = Config::parse("[health]\nsleep_target = \"450m\"\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("sleep_target", "450m".to_string())]
+    );
+
+    let config = Config::parse("[health]\nsleep_target = \"8 hours\"\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("sleep_target"));
+}
+
+// Test that the chunk period is passed on, and an invalid one refused
+#[test]
+fn test_chunk_period() {
+    let config = Config::parse("[health]\nchunk_period = \"7d\"\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("chunk_period", "7d".to_string())]
+    );
+
+    let config = Config::parse("[health]\nchunk_period = \"1w\"\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("chunk_period"));
+}
+
+// Test that the excluded data types are passed on, and an unknown one refused
+#[test]
+fn test_exclude_types() {
+    let config =
+        Config::parse("[health]\nexclude_types = [\"HeartRate\", \"SleepState\"]\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("exclude_types", "HeartRate,SleepState".to_string())]
+    );
+
+    let config = Config::parse("[health]\nexclude_types = [\"Pulse\"]\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("exclude_types"));
+}
+
+// Test that downsampling is passed on, and unknown data types refused
+#[test]
+fn test_downsample_settings() {
+    let config = Config::parse(
+        "[health]\ndownsample_bucket = \"long_term\"\ndownsample_interval = \"15m\"\ndownsample_types = [\"HeartRate\", \"Steps\"]\n",
+    )
+    .unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![
+            ("downsample_bucket", "long_term".to_string()),
+            ("downsample_interval", "15m".to_string()),
+            ("downsample_types", "HeartRate,Steps".to_string()),
+        ]
+    );
+
+    let config = Config::parse("[health]\ndownsample_types = [\"Pulse\"]\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("Pulse"));
+}
+
+// Test that the sleep app priority is passed on to import-health-data
+#[test]
+fn test_sleep_priority() {
+    let config = Config::parse("[health]\nsleep_priority = [\"Watch\", \"Fit\"]\n").unwrap();
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("sleep_priority", "Watch,Fit".to_string())]
+    );
+}
+
+// Test that the older exports to merge are passed on to import-health-data
+#[test]
+fn test_merge_sources() {
+    let config = Config::parse("[health]\nmerge_sources = [\"2024-01.db\", \"old/\"]\n").unwrap();
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("merge_sources", "2024-01.db,old/".to_string())]
+    );
+}
+
+// Test that the hypnogram interval is passed on, and one that is not an interval refused
+#[test]
+fn test_sleep_hypnogram() {
+    let config = Config::parse("[health]\nsleep_hypnogram = \"1m\"\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("sleep_hypnogram", "1m".to_string())]
+    );
+
+    let config = Config::parse("[health]\nsleep_hypnogram = \"1 minute\"\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("sleep_hypnogram"));
+}
+
+// Test that derived metrics are passed on to import-health-data, and unknown ones refused
+#[test]
+fn test_derived_metrics() {
+    let config = Config::parse("[health]\nderived_metrics = [\"body-composition\"]\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("derived_metrics", "body-composition".to_string())]
+    );
+
+    let config = Config::parse("[health]\nderived_metrics = [\"vo2max\"]\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("vo2max"));
+}
+
+// Test that heart rate zone boundaries are passed on, and decreasing ones refused
+#[test]
+fn test_zone_boundaries() {
+    let config = Config::parse("[health]\nzone_boundaries = [100, 120, 140]\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("zone_boundaries", "100,120,140".to_string())]
+    );
+
+    let config = Config::parse("[health]\nzone_boundaries = [140, 120]\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("zone_boundaries"));
+}
+
+// Test that the time zone days start in is passed on, and an unknown one refused
+#[test]
+fn test_timezone() {
+    let config = Config::parse("[health]\ntimezone = \"Europe/Rome\"\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("timezone", "Europe/Rome".to_string())]
+    );
+
+    let config = Config::parse("[health]\ntimezone = \"Mars/Olympus\"\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("timezone"));
+}
+
+// Test that the height for the BMI is passed on, and one not in meters refused
+#[test]
+fn test_height() {
+    let config = Config::parse("[health]\nheight = 1.75\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("height", "1.75".to_string())]
+    );
+
+    let config = Config::parse("[health]\nheight = 175.0\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("meters"));
+}
+
+// Test that the anonymization mode is passed on, and an unknown one refused
+#[test]
+fn test_anonymize() {
+    let config = Config::parse("[health]\nanonymize = \"hash\"\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("anonymize", "hash".to_string())]
+    );
+
+    let config = Config::parse("[health]\nanonymize = \"blur\"\n").unwrap();
+    assert!(config.validate().unwrap_err().contains("anonymize"));
+}
+
+// Test that the days to reconcile are passed on
+#[test]
+fn test_reconcile_days() {
+    let config = Config::parse("[health]\nreconcile_days = 30\n").unwrap();
+    assert!(config.validate().is_ok());
+    assert_eq!(
+        config.settings_for("import-health-data"),
+        vec![("reconcile_days", "30".to_string())]
+    );
+}
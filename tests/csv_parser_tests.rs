@@ -1,4 +1,9 @@
-use home_db_importer::csv_parser::CsvParser;
+use chrono::{TimeZone, Utc};
+use home_db_importer::convert::{ColumnTags, HeaderRole};
+use home_db_importer::csv_parser::{detect_delimiter, oldest_records, CsvParser, CsvRecord};
+use home_db_importer::source::Source;
+use home_db_importer::state_management::ImportState;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::Write;
 use std::path::PathBuf;
@@ -230,7 +235,7 @@ fn test_format_parsed_data() {
     let test_file = create_test_csv(content);
     let parser = CsvParser::new(test_file.path.to_str().unwrap());
 
-    let parse_result = parser.parse().unwrap();
+    let _parse_result = parser.parse().unwrap();
     let result = parser.format_parsed_data();
     assert!(result.is_ok());
 
@@ -242,3 +247,302 @@ fn test_format_parsed_data() {
     assert!(formatted.contains("name: Jane"));
     assert!(formatted.contains("city: Boston"));
 }
+
+// Test that a limit keeps the oldest records, plus those sharing the last timestamp kept
+#[test]
+fn test_oldest_records() {
+    let record = |date: &str| CsvRecord {
+        header_values: Vec::new(),
+        column_indexes: HashMap::from([("Date".to_string(), 0)]),
+        values: vec![date.to_string(), "1.0".to_string()],
+        time_column_index: Some(0),
+    };
+    let records = vec![
+        record("03/01/2024 09:00"),
+        record("01/01/2024 09:00"),
+        record("02/01/2024 09:00"),
+        record("02/01/2024 09:00"),
+    ];
+
+    let dates = |records: &[CsvRecord]| {
+        records
+            .iter()
+            .map(|r| r.values[0].clone())
+            .collect::<Vec<_>>()
+    };
+    let kept = oldest_records(records.clone(), 2, "Date", "%d/%m/%Y %H:%M");
+    assert_eq!(
+        dates(&kept),
+        ["01/01/2024 09:00", "02/01/2024 09:00", "02/01/2024 09:00"]
+    );
+
+    // Nothing is dropped when the limit covers every record
+    let kept = oldest_records(records, 10, "Date", "%d/%m/%Y %H:%M");
+    assert_eq!(kept.len(), 4);
+}
+
+// Test reading the records after the watermark through the Source trait
+#[test]
+fn test_records_since_watermark() {
+    let test_file =
+        create_test_csv("Date,Value\n01/01/2024 09:00,1.0\n02/01/2024 09:00,2.0\nnot a date,3.0\n");
+    let path = test_file.path.to_str().unwrap();
+    let parser = CsvParser::new(path).with_time_column("Date", "%d/%m/%Y %H:%M");
+    assert!(Source::validate(&parser).is_ok());
+
+    let mut watermark = ImportState::new(path);
+    let records: Vec<CsvRecord> = parser.records_since(&watermark).unwrap().collect();
+    assert_eq!(records.len(), 3);
+    assert_eq!(
+        parser.latest_timestamp(&records),
+        Some(Utc.with_ymd_and_hms(2024, 1, 2, 9, 0, 0).unwrap())
+    );
+
+    // Records at or before the watermark are skipped; ones without a valid time are kept
+    watermark.last_imported_timestamp = Some(Utc.with_ymd_and_hms(2024, 1, 1, 9, 0, 0).unwrap());
+    let stream = parser.records_since(&watermark).unwrap();
+    assert_eq!(stream.already_imported(), 1);
+    let values: Vec<String> = stream.map(|record| record.values[1].clone()).collect();
+    assert_eq!(values, ["2.0", "3.0"]);
+
+    // The file is parsed once, however often the records are read
+    std::fs::remove_file(&test_file.path).unwrap();
+    assert_eq!(parser.records_since(&watermark).unwrap().count(), 2);
+}
+
+// Test that a fund appearing in two accounts is written as two series, told apart by the
+// tags of their columns
+#[test]
+fn test_column_tags() {
+    let test_file = create_test_csv(
+        "timestamp,Fund A,Fund A,Fund B\n,price,price,price\n\
+         2024-01-01 00:00:00,10,11,$20\n",
+    );
+    let parser = CsvParser::new(test_file.path.to_str().unwrap())
+        .with_header_rows(2)
+        .with_column_tags(vec![
+            ColumnTags {
+                column: None,
+                header: Some("Fund A".to_string()),
+                account: Some("broker".to_string()),
+                currency: None,
+            },
+            ColumnTags {
+                column: Some(3),
+                header: None,
+                account: Some("pension".to_string()),
+                currency: None,
+            },
+            ColumnTags {
+                column: None,
+                header: Some("Fund B".to_string()),
+                account: None,
+                currency: Some("usd".to_string()),
+            },
+        ]);
+
+    let records = parser.parse().unwrap();
+    assert_eq!(records[0].column_indexes.len(), 4);
+    let mut points: Vec<(String, Option<String>, Option<String>, f64)> = parser
+        .to_points(&records[0])
+        .unwrap()
+        .into_iter()
+        .map(|point| {
+            (
+                point.tags["fondo"].clone(),
+                point.tags.get("account").cloned(),
+                point.tags.get("currency").cloned(),
+                point.field_value,
+            )
+        })
+        .collect();
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        points,
+        [
+            ("Fund_A".to_string(), Some("broker".to_string()), None, 10.0),
+            (
+                "Fund_A".to_string(),
+                Some("pension".to_string()),
+                None,
+                11.0
+            ),
+            ("Fund_B".to_string(), None, Some("USD".to_string()), 20.0),
+        ]
+    );
+}
+
+// Test that a funds export with a single header row is written to the configured
+// measurement, with the fund from the header
+#[test]
+fn test_single_header_row() {
+    let test_file = create_test_csv("timestamp,Fund A,Fund B\n2024-01-01 00:00:00,10,20\n");
+    let parser = CsvParser::new(test_file.path.to_str().unwrap()).with_measurement("nav");
+
+    let records = parser.parse().unwrap();
+    let mut points: Vec<(String, String, f64)> = parser
+        .to_points(&records[0])
+        .unwrap()
+        .into_iter()
+        .map(|point| {
+            (
+                point.measurement,
+                point.tags["fondo"].clone(),
+                point.field_value,
+            )
+        })
+        .collect();
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        points,
+        [
+            ("nav".to_string(), "Fund_A".to_string(), 10.0),
+            ("nav".to_string(), "Fund_B".to_string(), 20.0),
+        ]
+    );
+}
+
+// Test that three header rows are read by their roles, and that more roles than header rows
+// are refused
+#[test]
+fn test_header_roles() {
+    let test_file = create_test_csv(
+        "timestamp,IE00B4L5Y983,IE00B4L5Y983,LU0274208692\n\
+         ,broker,pension,broker\n\
+         ,Fund A,Fund A,Fund B\n\
+         2024-01-01 00:00:00,10,11,20\n",
+    );
+    let roles: Vec<HeaderRole> = ["tag:isin", "account", "fund"]
+        .iter()
+        .map(|role| role.parse().unwrap())
+        .collect();
+    let parser = CsvParser::new(test_file.path.to_str().unwrap())
+        .with_header_rows(3)
+        .with_header_roles(roles)
+        .with_measurement("price");
+
+    let records = parser.parse().unwrap();
+    assert_eq!(records[0].column_indexes.len(), 4);
+    let mut points: Vec<(String, String, String, String, f64)> = parser
+        .to_points(&records[0])
+        .unwrap()
+        .into_iter()
+        .map(|point| {
+            (
+                point.measurement,
+                point.tags["fondo"].clone(),
+                point.tags["account"].clone(),
+                point.tags["isin"].clone(),
+                point.field_value,
+            )
+        })
+        .collect();
+    points.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let point = |fund: &str, account: &str, isin: &str, value| {
+        (
+            "price".to_string(),
+            fund.to_string(),
+            account.to_string(),
+            isin.to_string(),
+            value,
+        )
+    };
+    assert_eq!(
+        points,
+        [
+            point("Fund_A", "broker", "IE00B4L5Y983", 10.0),
+            point("Fund_A", "pension", "IE00B4L5Y983", 11.0),
+            point("Fund_B", "broker", "LU0274208692", 20.0),
+        ]
+    );
+
+    assert!("tag:".parse::<HeaderRole>().is_err());
+    assert!("price".parse::<HeaderRole>().is_err());
+    let parser = CsvParser::new(test_file.path.to_str().unwrap())
+        .with_header_rows(2)
+        .with_header_roles(vec![HeaderRole::Fund; 3]);
+    assert!(Source::validate(&parser).is_err());
+}
+
+// Test that values that are not numbers are dropped by default, and kept as string fields
+// of the row's points with text fields, leaving the tags alone
+#[test]
+fn test_text_fields() {
+    let test_file = create_test_csv(
+        "timestamp,Fund A,Fund B,status,notes\n\
+         2024-01-01 00:00:00,10,20,rebalanced,\"Sold \"\"half\"\", see C:\\fx\"\n\
+         2024-01-02 00:00:00,11,21,,\n",
+    );
+    let parser = CsvParser::new(test_file.path.to_str().unwrap()).with_measurement("nav");
+    let records = parser.parse().unwrap();
+    let points = parser.to_points(&records[0]).unwrap();
+    assert_eq!(points.len(), 2);
+    assert!(points.iter().all(|point| point.text_fields.is_empty()));
+
+    let parser = parser.with_text_fields(true);
+    let mut lines: Vec<String> = parser
+        .to_points(&records[0])
+        .unwrap()
+        .iter()
+        .map(|point| {
+            assert_eq!(point.tags.len(), 1);
+            point.to_line_protocol()
+        })
+        .collect();
+    lines.sort();
+    assert_eq!(
+        lines,
+        [
+            "nav,fondo=Fund_A value=10,notes=\"Sold \\\"half\\\", see C:\\\\fx\",status=\"rebalanced\" 1704067200000000000",
+            "nav,fondo=Fund_B value=20,notes=\"Sold \\\"half\\\", see C:\\\\fx\",status=\"rebalanced\" 1704067200000000000",
+        ]
+    );
+
+    // Empty values add no field
+    let points = parser.to_points(&records[1]).unwrap();
+    assert!(points.iter().all(|point| point.text_fields.is_empty()));
+}
+
+// Test that a semicolon-separated file is rewritten with commas and trimmed headers and
+// values, without the rows that cannot be imported
+#[test]
+fn test_clean() {
+    let test_file = create_test_csv(
+        "\u{feff}timestamp;\"Fund\n A\";Fund B\n\
+         2024-01-01 00:00:00; 10 ;\"1,5\"\n\
+         2024-01-02 00:00:00;11\n\
+         ;12;13\n\
+         ;;\n\
+         2024-01-03 00:00:00;12;14;;\n",
+    );
+    let parser = CsvParser::new(test_file.path.to_str().unwrap());
+
+    let mut output = Vec::new();
+    let report = parser.clean(&mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "timestamp,Fund A,Fund B\n\
+         2024-01-01 00:00:00,10,\"1,5\"\n\
+         2024-01-03 00:00:00,12,14\n"
+    );
+    assert_eq!(report.delimiter, ';');
+    assert_eq!(report.header_rows, 1);
+    assert_eq!(report.rows_written, 2);
+    let dropped: Vec<(u64, &str)> = report
+        .dropped
+        .iter()
+        .map(|row| (row.line, row.reason.as_str()))
+        .collect();
+    assert_eq!(
+        dropped,
+        [
+            (4, "2 values for 3 columns"),
+            (5, "no timestamp"),
+            (6, "empty row"),
+        ]
+    );
+
+    assert_eq!(detect_delimiter("a,b;c,d"), b',');
+    assert_eq!(detect_delimiter("\"a;b\"\tc"), b'\t');
+    assert_eq!(detect_delimiter("timestamp"), b',');
+}
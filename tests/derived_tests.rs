@@ -0,0 +1,156 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::derived::{
+    bmi, body_composition, run_stage, workout_summaries, BmiStage, BodyCompositionStage,
+    DerivedStage,
+};
+use home_db_importer::health_data::{Distance, HealthDataReader, HealthDataType, HealthRecord};
+use home_db_importer::influx_client::InfluxClient;
+use std::collections::HashMap;
+
+fn record(record_type: HealthDataType, minute: u32, value: f64, app: &str) -> HealthRecord {
+    HealthRecord {
+        record_type,
+        timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 7, minute, 0).unwrap(),
+        value,
+        metadata: HashMap::from([("app_name".to_string(), app.to_string())]),
+        row_id: None,
+        zone_offset: None,
+    }
+}
+
+// Test that the values an app recorded at the same time become one point
+#[test]
+fn test_body_composition() {
+    let records = vec![
+        record(HealthDataType::Weight, 0, 70500.0, "scale"),
+        record(HealthDataType::BodyFat, 0, 18.5, "scale"),
+        record(HealthDataType::LeanBodyMass, 0, 57400.0, "scale"),
+        // Another app at the same time, and a weight recorded alone
+        record(HealthDataType::BodyFat, 0, 19.0, "watch"),
+        record(HealthDataType::Weight, 30, 70400.0, "scale"),
+        record(HealthDataType::HeartRate, 0, 60.0, "scale"),
+    ];
+
+    let points = body_composition(&records);
+    assert_eq!(points.len(), 1);
+    assert_eq!(points[0].measurement, "BodyComposition");
+    assert_eq!(points[0].tags["app_name"], "scale");
+    assert_eq!(points[0].fields["weight"], 70500.0);
+    assert_eq!(points[0].fields["body_fat"], 18.5);
+    assert_eq!(points[0].fields["lean_body_mass"], 57400.0);
+}
+
+// Test the BMI at each weight, with the height recorded before it or a configured one
+#[test]
+fn test_bmi() {
+    let records = vec![
+        record(HealthDataType::Weight, 0, 72000.0, "scale"),
+        record(HealthDataType::Weight, 30, 81000.0, "scale"),
+        record(HealthDataType::BodyFat, 30, 18.5, "scale"),
+    ];
+    // Recorded between the two weights; the first weight uses it too
+    let heights = vec![
+        record(HealthDataType::Height, 10, 1.8, "phone"),
+        record(HealthDataType::Height, 40, 2.0, "phone"),
+    ];
+
+    let points = bmi(&records, &heights, None);
+    let values: Vec<f64> = points.iter().map(|point| point.field_value).collect();
+    assert_eq!(values, vec![22.2, 25.0]);
+    assert_eq!(points[0].measurement, "BMI");
+    assert_eq!(points[0].tags["app_name"], "scale");
+
+    let points = bmi(&records, &heights, Some(2.0));
+    assert_eq!(points[0].field_value, 18.0);
+    assert!(bmi(&records, &[], None).is_empty());
+}
+
+// Test that each workout is summarized with the heart rate, calories and distance recorded
+// during it, prorating the records that span its start or end
+#[test]
+fn test_workout_summaries() {
+    let span = |record_type: HealthDataType, minute: u32, minutes: i64, value: f64| {
+        let mut record = record(record_type, minute, value, "watch");
+        let end = record.timestamp + chrono::Duration::minutes(minutes);
+        record.metadata.insert(
+            "end_time_millis".to_string(),
+            end.timestamp_millis().to_string(),
+        );
+        record
+    };
+    let mut run = span(HealthDataType::ExerciseSession, 10, 30, 30.0);
+    run.metadata
+        .insert("exercise_type".to_string(), "56".to_string());
+    let records = vec![
+        run,
+        // A workout with nothing recorded during it
+        span(HealthDataType::ExerciseSession, 50, 5, 5.0),
+        record(HealthDataType::HeartRate, 5, 70.0, "watch"),
+        record(HealthDataType::HeartRate, 10, 120.0, "watch"),
+        record(HealthDataType::HeartRate, 20, 151.0, "watch"),
+        record(HealthDataType::HeartRate, 40, 90.0, "watch"),
+        // Half of it during the run
+        span(HealthDataType::TotalCalories, 0, 20, 100.0),
+        span(HealthDataType::TotalCalories, 20, 20, 200.0),
+    ];
+    let distances = vec![Distance {
+        app_name: "watch".to_string(),
+        start: Utc.with_ymd_and_hms(2024, 3, 1, 7, 30, 0).unwrap(),
+        end: Utc.with_ymd_and_hms(2024, 3, 1, 7, 50, 0).unwrap(),
+        meters: 4000.0,
+    }];
+
+    let points = workout_summaries(&records, &distances);
+    assert_eq!(points.len(), 2);
+    assert_eq!(points[0].measurement, "WorkoutSummary");
+    assert_eq!(
+        points[0].time,
+        Utc.with_ymd_and_hms(2024, 3, 1, 7, 10, 0).unwrap()
+    );
+    assert_eq!(points[0].tags["exercise_type"], "56");
+    assert_eq!(points[0].fields["duration_minutes"], 30.0);
+    assert_eq!(points[0].fields["avg_heart_rate"], 135.5);
+    assert_eq!(points[0].fields["max_heart_rate"], 151.0);
+    assert_eq!(points[0].fields["calories"], 250.0);
+    assert_eq!(points[0].fields["distance_meters"], 2000.0);
+    assert_eq!(
+        points[1].fields.keys().collect::<Vec<_>>(),
+        vec!["duration_minutes"]
+    );
+}
+
+// Test running stages without inputs on the records written, and the warning about weights
+// without a height
+#[tokio::test]
+async fn test_run_stage() {
+    let written = vec![
+        record(HealthDataType::Weight, 0, 70500.0, "scale"),
+        record(HealthDataType::BodyFat, 0, 18.5, "scale"),
+        record(HealthDataType::Weight, 30, 70400.0, "scale"),
+    ];
+    // Stages without inputs don't read the export
+    let reader = HealthDataReader::new("missing.db");
+    let client = InfluxClient::builder("http://localhost:8086", "bucket")
+        .dry_run(true)
+        .build()
+        .unwrap();
+
+    let mut stage = BodyCompositionStage;
+    let written_points = run_stage(&mut stage, &reader, &client, &written).await;
+    assert_eq!(written_points.unwrap(), Some(1));
+
+    let mut stage = BmiStage::new(Some(1.8));
+    let written_points = run_stage(&mut stage, &reader, &client, &written).await;
+    assert_eq!(written_points.unwrap(), Some(2));
+    assert_eq!(stage.warning(2), None);
+    assert_eq!(
+        stage.warning(1).unwrap(),
+        "no height for 1 weight records; import Height records or pass --height"
+    );
+
+    // Heights are read from the export when none is configured
+    let mut stage = BmiStage::new(None);
+    assert!(run_stage(&mut stage, &reader, &client, &written)
+        .await
+        .is_err());
+}
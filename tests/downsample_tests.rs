@@ -0,0 +1,88 @@
+use chrono::{DateTime, TimeZone, Utc};
+use home_db_importer::downsample::Downsampler;
+use home_db_importer::influx_client::DataPoint;
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+fn time(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, second)
+        .unwrap()
+}
+
+fn point(time: DateTime<Utc>, app: &str, value: f64) -> DataPoint {
+    DataPoint {
+        measurement: "HeartRate".to_string(),
+        time,
+        tags: HashMap::from([("app_name".to_string(), app.to_string())]),
+        field_value: value,
+        text_fields: BTreeMap::new(),
+    }
+}
+
+fn value(points: &[DataPoint], time: DateTime<Utc>, app: &str, aggregate: &str) -> Option<f64> {
+    points
+        .iter()
+        .find(|point| {
+            point.time == time
+                && point.tags["app_name"] == app
+                && point.tags["aggregate"] == aggregate
+        })
+        .map(|point| point.field_value)
+}
+
+// Test that each series is reduced to the min, mean and max of every window
+#[test]
+fn test_downsample() {
+    let downsampler = Downsampler::new(Duration::from_secs(5 * 60));
+    let points = vec![
+        point(time(8, 0, 0), "watch", 60.0),
+        point(time(8, 2, 30), "watch", 70.0),
+        point(time(8, 4, 59), "watch", 65.0),
+        point(time(8, 5, 0), "watch", 90.0),
+        point(time(8, 1, 0), "phone", 100.0),
+    ];
+
+    let downsampled = downsampler.downsample(&points);
+    assert_eq!(downsampled.len(), 9);
+    assert_eq!(
+        value(&downsampled, time(8, 0, 0), "watch", "min"),
+        Some(60.0)
+    );
+    assert_eq!(
+        value(&downsampled, time(8, 0, 0), "watch", "mean"),
+        Some(65.0)
+    );
+    assert_eq!(
+        value(&downsampled, time(8, 0, 0), "watch", "max"),
+        Some(70.0)
+    );
+    assert_eq!(
+        value(&downsampled, time(8, 5, 0), "watch", "mean"),
+        Some(90.0)
+    );
+    assert_eq!(
+        value(&downsampled, time(8, 0, 0), "phone", "max"),
+        Some(100.0)
+    );
+    assert!(downsampled
+        .iter()
+        .all(|point| point.measurement == "HeartRate"));
+}
+
+// Test that windows are aligned to the epoch, whatever the first point
+#[test]
+fn test_windows() {
+    let downsampler = Downsampler::new(Duration::from_secs(15 * 60));
+    assert_eq!(downsampler.window(time(8, 14, 59)), time(8, 0, 0));
+    assert_eq!(downsampler.window(time(8, 15, 0)), time(8, 15, 0));
+
+    let points = vec![
+        point(time(8, 20, 0), "watch", 60.0),
+        point(time(8, 1, 0), "watch", 60.0),
+        point(time(8, 2, 0), "watch", 60.0),
+    ];
+    assert_eq!(
+        downsampler.windows(&points),
+        [time(8, 0, 0), time(8, 15, 0)].into()
+    );
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::write_file;
+use home_db_importer::dsmr::{intervals, DsmrReader, Register};
+use home_db_importer::source::Source;
+use home_db_importer::state_management::ImportState;
+
+const EXPORT: &str = "\
+timestamp,electricity_delivered_1,electricity_delivered_2,electricity_returned_1,electricity_returned_2,extra_device_delivered
+2024-03-01 00:15:00,1000.500,2000.000,10.000,20.000,500.100
+2024-03-01 00:00:00,1000.000,2000.000,10.000,20.000,500.000
+2024-03-01 00:30:00,1001.250,2000.000,10.000,20.500,500.100
+";
+
+fn usage(interval: &home_db_importer::dsmr::DsmrInterval, register: Register) -> Option<f64> {
+    interval
+        .usage
+        .iter()
+        .find(|(r, _)| *r == register)
+        .map(|(_, usage)| (usage * 1000.0).round() / 1000.0)
+}
+
+// Test that consecutive readings become the usage of each interval, oldest first
+#[test]
+fn test_register_deltas() {
+    let (_dir, path) = write_file("p1.csv", EXPORT);
+    let reader = DsmrReader::new(&path);
+
+    let readings = reader.readings().unwrap();
+    assert_eq!(readings.len(), 3);
+    let intervals = intervals(&readings);
+    assert_eq!(intervals.len(), 2);
+
+    assert_eq!(
+        intervals[0].start,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()
+    );
+    assert_eq!(
+        intervals[0].end,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 15, 0).unwrap()
+    );
+    assert_eq!(usage(&intervals[0], Register::DeliveredTariff1), Some(0.5));
+    assert_eq!(usage(&intervals[0], Register::DeliveredTariff2), Some(0.0));
+    assert_eq!(usage(&intervals[0], Register::Gas), Some(0.1));
+    assert_eq!(usage(&intervals[1], Register::DeliveredTariff1), Some(0.75));
+    assert_eq!(usage(&intervals[1], Register::ReturnedTariff2), Some(0.5));
+    assert_eq!(usage(&intervals[1], Register::Gas), Some(0.0));
+}
+
+// Test that semicolons, OBIS column names, units and RFC 3339 times are understood, and
+// that a register going backwards (a replaced meter) gives no usage for that interval
+#[test]
+fn test_obis_columns_with_units() {
+    let (_dir, path) = write_file(
+        "p1.csv",
+        "time;1.8.1;1.8.2\n\
+         2024-03-01T01:00:00+01:00;000100.000*kWh;000050.000*kWh\n\
+         2024-03-01T01:10:00+01:00;000100.200*kWh;000000.100*kWh\n",
+    );
+    let reader = DsmrReader::new(&path).with_time_column("time", "%Y-%m-%d %H:%M:%S");
+
+    let intervals = intervals(&reader.readings().unwrap());
+    assert_eq!(intervals.len(), 1);
+    assert_eq!(
+        intervals[0].start,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()
+    );
+    assert_eq!(usage(&intervals[0], Register::DeliveredTariff1), Some(0.2));
+    assert_eq!(usage(&intervals[0], Register::DeliveredTariff2), None);
+}
+
+// Test that an export without register columns is rejected
+#[test]
+fn test_missing_registers() {
+    let (_dir, path) = write_file("p1.csv", "timestamp,power\n2024-03-01 00:00:00,1\n");
+    let error = DsmrReader::new(&path).validate().unwrap_err();
+    assert!(error.to_string().contains("register"));
+}
+
+// Test that the intervals after the watermark are read, starting from the last reading
+// imported, and become points tagged with direction and tariff
+#[test]
+fn test_records_since_watermark() {
+    let (_dir, path) = write_file("p1.csv", EXPORT);
+    let reader = DsmrReader::new(&path);
+    let mut state = ImportState::new(&path);
+    state.last_imported_timestamp = Some(Utc.with_ymd_and_hms(2024, 3, 1, 0, 15, 0).unwrap());
+
+    let stream = reader.records_since(&state).unwrap();
+    assert_eq!(stream.already_imported(), 1);
+    let intervals: Vec<_> = stream.collect();
+    assert_eq!(intervals.len(), 1);
+    assert_eq!(
+        intervals[0].start,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 15, 0).unwrap()
+    );
+
+    let points = reader.to_points(&intervals[0]).unwrap();
+    assert_eq!(points.len(), 5);
+    let delivered = points
+        .iter()
+        .find(|point| {
+            point.measurement == "electricity"
+                && point.tags["direction"] == "delivered"
+                && point.tags["tariff"] == "1"
+        })
+        .unwrap();
+    assert_eq!(delivered.time, intervals[0].end);
+    assert!((delivered.field_value - 0.75).abs() < 1e-9);
+    assert!(points.iter().any(|point| point.measurement == "gas"));
+
+    // --limit keeps the oldest intervals
+    let all: Vec<_> = reader
+        .records_since(&ImportState::new(&path))
+        .unwrap()
+        .collect();
+    let oldest = reader.oldest(all, 1);
+    assert_eq!(oldest.len(), 1);
+    assert_eq!(
+        oldest[0].end,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 15, 0).unwrap()
+    );
+}
@@ -0,0 +1,67 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::extract::{by_measurement, write_points, ExtractFormat};
+use home_db_importer::influx_client::DataPoint;
+use std::collections::{BTreeMap, HashMap};
+
+fn point(measurement: &str, second: u32, value: f64, tags: &[(&str, &str)]) -> DataPoint {
+    DataPoint {
+        measurement: measurement.to_string(),
+        time: Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, second).unwrap(),
+        tags: tags
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>(),
+        field_value: value,
+        text_fields: BTreeMap::new(),
+    }
+}
+
+// Test CSV files: a column for every tag, empty for the points without it
+#[test]
+fn test_csv() {
+    let points = vec![
+        point("HeartRate", 0, 62.0, &[("app_name", "watch")]),
+        point(
+            "HeartRate",
+            5,
+            64.0,
+            &[("app_name", "watch"), ("unit", "bpm")],
+        ),
+    ];
+    let mut output = Vec::new();
+    write_points(&points, ExtractFormat::Csv, &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "time,measurement,value,app_name,unit\n\
+         2024-03-01T08:00:00.000Z,HeartRate,62,watch,\n\
+         2024-03-01T08:00:05.000Z,HeartRate,64,watch,bpm\n"
+    );
+}
+
+// Test NDJSON files: one object per point
+#[test]
+fn test_ndjson() {
+    let points = vec![point("Weight", 0, 72000.0, &[("app_name", "scale")])];
+    let mut output = Vec::new();
+    write_points(&points, ExtractFormat::Ndjson, &mut output).unwrap();
+    let output = String::from_utf8(output).unwrap();
+    assert_eq!(output.lines().count(), 1);
+    let line: serde_json::Value = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(line["time"], "2024-03-01T08:00:00.000Z");
+    assert_eq!(line["measurement"], "Weight");
+    assert_eq!(line["value"], 72000.0);
+    assert_eq!(line["tags"]["app_name"], "scale");
+}
+
+// Test that each measurement goes to a file of its own
+#[test]
+fn test_by_measurement() {
+    let measurements = by_measurement(vec![
+        point("SleepState", 0, 2.0, &[]),
+        point("Sleep", 0, 2.0, &[]),
+        point("SleepState", 30, 4.0, &[]),
+    ]);
+    let names: Vec<&String> = measurements.keys().collect();
+    assert_eq!(names, vec!["Sleep", "SleepState"]);
+    assert_eq!(measurements["SleepState"].len(), 2);
+}
@@ -0,0 +1,172 @@
+use chrono::{NaiveDate, TimeZone, Utc};
+use home_db_importer::csv_parser::CsvParser;
+use home_db_importer::fx::{parse_frankfurter, FxConversion, FxRates};
+use home_db_importer::influx_client::DataPoint;
+use home_db_importer::source::Source;
+use home_db_importer::state_management::ImportState;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+fn day(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap()
+}
+
+fn point(fund: &str, day: u32, value: f64) -> DataPoint {
+    DataPoint {
+        measurement: "price".to_string(),
+        time: Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap(),
+        tags: HashMap::from([("fondo".to_string(), fund.to_string())]),
+        field_value: value,
+        text_fields: BTreeMap::new(),
+    }
+}
+
+// Test that a day without a published rate uses the last one before it, falling back to
+// the fixed rate before the first one
+#[test]
+fn test_rate_lookup() {
+    let mut rates = FxRates::new("eur").with_rate("USD", 0.9);
+    rates.add_daily_rates("usd", BTreeMap::from([(day(2024, 1, 5), 0.91)]));
+    let at = |day| Utc.with_ymd_and_hms(2024, 1, day, 12, 0, 0).unwrap();
+
+    assert_eq!(rates.base(), "EUR");
+    assert_eq!(rates.rate("EUR", at(1)), Some(1.0));
+    assert_eq!(rates.rate("USD", at(5)), Some(0.91));
+    // Saturday the 6th
+    assert_eq!(rates.rate("USD", at(6)), Some(0.91));
+    assert_eq!(rates.rate("USD", at(4)), Some(0.9));
+    assert_eq!(rates.rate("GBP", at(5)), None);
+}
+
+// Test that a Frankfurter time series is read by day, keeping the rate to the base currency
+#[test]
+fn test_parse_frankfurter() {
+    let body = r#"{"amount":1.0,"base":"USD","start_date":"2024-01-02","end_date":"2024-01-03",
+        "rates":{"2024-01-02":{"EUR":0.9123},"2024-01-03":{"EUR":0.9155}}}"#;
+    let rates = parse_frankfurter(body, "EUR").unwrap();
+    assert_eq!(
+        rates,
+        BTreeMap::from([(day(2024, 1, 2), 0.9123), (day(2024, 1, 3), 0.9155)])
+    );
+
+    assert!(parse_frankfurter("{}", "EUR").is_err());
+}
+
+// Test that each point gets a converted copy tagged with its currency: the fund's, else
+// the one it was written in, else the base currency
+#[test]
+fn test_add_normalized() {
+    let conversion = FxConversion::new(FxRates::new("EUR").with_rate("USD", 0.5))
+        .with_fund_currency("Fund_B", "usd");
+    assert_eq!(conversion.measurement("price"), "price_eur");
+    assert_eq!(
+        conversion.currencies().into_iter().collect::<Vec<_>>(),
+        ["USD"]
+    );
+
+    let points = conversion
+        .add_normalized(vec![
+            (point("Fund_A", 1, 10.0), Some("USD")),
+            (point("Fund_B", 1, 10.0), None),
+            (point("Fund_C", 1, 10.0), None),
+        ])
+        .unwrap();
+    assert_eq!(points.len(), 6);
+
+    let converted: Vec<(&str, &str, f64)> = points
+        .iter()
+        .filter(|point| point.measurement == "price_eur")
+        .map(|point| {
+            (
+                point.tags["fondo"].as_str(),
+                point.tags["currency"].as_str(),
+                point.field_value,
+            )
+        })
+        .collect();
+    assert_eq!(
+        converted,
+        [
+            ("Fund_A", "USD", 5.0),
+            ("Fund_B", "USD", 5.0),
+            ("Fund_C", "EUR", 10.0)
+        ]
+    );
+    // The original points are written unchanged
+    assert!(points
+        .iter()
+        .filter(|point| point.measurement == "price")
+        .all(|point| point.field_value == 10.0 && !point.tags.contains_key("currency")));
+
+    let error = FxConversion::new(FxRates::new("EUR"))
+        .add_normalized(vec![(point("Fund_A", 1, 10.0), Some("USD"))])
+        .unwrap_err();
+    assert!(error.to_string().contains("No USD to EUR rate"));
+}
+
+// Test that a funds CSV import writes dollar values in euros too, at the rate of their day
+#[test]
+fn test_funds_import_with_fx() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("funds.csv");
+    fs::write(
+        &path,
+        "timestamp,Fund A,Fund B\n,price,price\n\
+         2024-01-01 00:00:00,$100,€50\n2024-01-02 00:00:00,$110,€51\n",
+    )
+    .unwrap();
+
+    let mut rates = FxRates::new("EUR");
+    rates.add_daily_rates(
+        "USD",
+        BTreeMap::from([(day(2024, 1, 1), 0.9), (day(2024, 1, 2), 0.8)]),
+    );
+    let parser = CsvParser::new(path.to_str().unwrap())
+        .with_header_rows(2)
+        .with_fx(FxConversion::new(rates));
+
+    let records: Vec<_> = parser
+        .records_since(&ImportState::new("funds.csv"))
+        .unwrap()
+        .collect();
+    let mut converted: Vec<(String, String, f64)> = records
+        .iter()
+        .flat_map(|record| parser.to_points(record).unwrap())
+        .filter(|point| point.measurement == "price_eur")
+        .map(|point| {
+            (
+                point.tags["fondo"].clone(),
+                point.tags["currency"].clone(),
+                (point.field_value * 1e6).round() / 1e6,
+            )
+        })
+        .collect();
+    converted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(
+        converted,
+        [
+            ("Fund_A".to_string(), "USD".to_string(), 88.0),
+            ("Fund_A".to_string(), "USD".to_string(), 90.0),
+            ("Fund_B".to_string(), "EUR".to_string(), 50.0),
+            ("Fund_B".to_string(), "EUR".to_string(), 51.0),
+        ]
+    );
+}
+
+// Test that a currency tag set for the column wins over the fund's currency and the symbol
+#[test]
+fn test_currency_tag() {
+    let conversion = FxConversion::new(FxRates::new("EUR").with_rate("GBP", 2.0))
+        .with_fund_currency("Fund_A", "USD");
+    let mut tagged = point("Fund_A", 1, 10.0);
+    tagged
+        .tags
+        .insert("currency".to_string(), "GBP".to_string());
+
+    let points = conversion
+        .add_normalized(vec![(tagged, Some("EUR"))])
+        .unwrap();
+    assert_eq!(points[1].measurement, "price_eur");
+    assert_eq!(points[1].tags["currency"], "GBP");
+    assert_eq!(points[1].field_value, 20.0);
+}
@@ -0,0 +1,107 @@
+use chrono::{TimeZone, Timelike, Utc};
+use home_db_importer::extract::ExtractFormat;
+use home_db_importer::gaps::{
+    count_per_window, find_gaps, missing_records, write_gaps, Gap, Presence,
+};
+use home_db_importer::health_data::{HealthDataType, HealthRecord};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+
+fn sample(hour: u32, minute: u32) -> HealthRecord {
+    HealthRecord {
+        record_type: HealthDataType::HeartRate,
+        timestamp: Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, 0).unwrap(),
+        value: 60.0,
+        metadata: HashMap::new(),
+        row_id: None,
+        zone_offset: None,
+    }
+}
+
+// Test that records are counted by the start of their window, counted from the epoch
+#[test]
+fn test_count_per_window() {
+    let records = vec![sample(8, 0), sample(8, 59), sample(10, 30)];
+    let counts = count_per_window(&records, Duration::from_secs(3600));
+    assert_eq!(
+        counts.into_iter().collect::<Vec<_>>(),
+        vec![
+            (Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap(), 2),
+            (Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap(), 1),
+        ]
+    );
+}
+
+// Test that missing records are grouped into runs that a present record splits, and that
+// records outside the checked ranges count as present
+#[test]
+fn test_find_gaps() {
+    let records = vec![
+        sample(8, 3),
+        sample(8, 0),
+        sample(8, 1),
+        sample(8, 2),
+        sample(8, 4),
+        sample(8, 4),
+        sample(9, 0),
+    ];
+    let checked = vec![(
+        Utc.with_ymd_and_hms(2024, 3, 1, 8, 0, 0).unwrap(),
+        Utc.with_ymd_and_hms(2024, 3, 1, 9, 0, 0).unwrap(),
+    )];
+    let existing: HashSet<i64> = [sample(8, 2).timestamp.timestamp_millis()].into();
+    let presence = Presence::new(checked, existing);
+
+    let gaps = find_gaps(HealthDataType::HeartRate, &records, &presence);
+    assert_eq!(
+        gaps,
+        vec![
+            Gap {
+                data_type: HealthDataType::HeartRate,
+                start: sample(8, 0).timestamp,
+                end: sample(8, 1).timestamp,
+                missing: 2,
+            },
+            Gap {
+                data_type: HealthDataType::HeartRate,
+                start: sample(8, 3).timestamp,
+                end: sample(8, 4).timestamp,
+                missing: 2,
+            },
+        ]
+    );
+    assert!(find_gaps(HealthDataType::Steps, &records, &presence).is_empty());
+
+    // Every copy of a missing record is kept
+    let missing: Vec<u32> = missing_records(records, &presence)
+        .iter()
+        .map(|record| record.timestamp.minute())
+        .collect();
+    assert_eq!(missing, vec![3, 0, 1, 4, 4]);
+}
+
+// Test writing gaps as CSV and NDJSON
+#[test]
+fn test_write_gaps() {
+    let gaps = vec![Gap {
+        data_type: HealthDataType::HeartRate,
+        start: sample(8, 0).timestamp,
+        end: sample(8, 1).timestamp,
+        missing: 2,
+    }];
+
+    let mut output = Vec::new();
+    write_gaps(&gaps, ExtractFormat::Csv, &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "data_type,start,end,missing\n\
+         HeartRate,2024-03-01T08:00:00.000Z,2024-03-01T08:01:00.000Z,2\n"
+    );
+
+    let mut output = Vec::new();
+    write_gaps(&gaps, ExtractFormat::Ndjson, &mut output).unwrap();
+    let line: serde_json::Value = serde_json::from_slice(&output).unwrap();
+    assert_eq!(line["data_type"], "HeartRate");
+    assert_eq!(line["start"], "2024-03-01T08:00:00.000Z");
+    assert_eq!(line["missing"], 2);
+}
@@ -0,0 +1,183 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::grafana::{
+    exercise_type_name, session_annotations, AnnotatedSession, Annotation, GrafanaAnnotator,
+};
+use home_db_importer::health_data::{HealthDataType, HealthRecord};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+fn record(record_type: HealthDataType, minute: u32, metadata: &[(&str, &str)]) -> HealthRecord {
+    HealthRecord {
+        record_type,
+        timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 7, minute, 0).unwrap(),
+        value: 0.0,
+        metadata: metadata
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>(),
+        row_id: Some(1),
+        zone_offset: None,
+    }
+}
+
+// Helper that serves Grafana's annotations API: GET returns `existing` and POST bodies are
+// recorded. Returns the URL and the bodies posted
+fn spawn_fake_grafana(existing: &'static str) -> (String, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let posted = Arc::new(Mutex::new(Vec::new()));
+    let received = posted.clone();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut content_length = 0;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let response = if request_line.starts_with("POST") {
+                received
+                    .lock()
+                    .unwrap()
+                    .push(String::from_utf8(body).unwrap());
+                r#"{"id":1,"message":"Annotation added"}"#
+            } else {
+                existing
+            };
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                response.len(),
+                response
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+    });
+    (url, posted)
+}
+
+// Test that exercise sessions become annotations with their type, title and duration, and
+// that sleep sessions are annotated once however many records they were read as
+#[test]
+fn test_session_annotations() {
+    assert_eq!(exercise_type_name(56), Some("Running"));
+    assert_eq!(exercise_type_name(1), None);
+
+    let records = vec![
+        record(
+            HealthDataType::ExerciseSession,
+            0,
+            &[
+                ("exercise_type", "56"),
+                ("title", "Morning run"),
+                ("duration_minutes", "45"),
+                ("end_time_millis", "1709279100000"),
+            ],
+        ),
+        record(
+            HealthDataType::ExerciseSession,
+            50,
+            &[
+                ("exercise_type", "1"),
+                ("title", "Unknown"),
+                ("duration_minutes", "75"),
+            ],
+        ),
+        record(
+            HealthDataType::Sleep,
+            0,
+            &[("event_type", "start"), ("duration_minutes", "450")],
+        ),
+        record(
+            HealthDataType::Sleep,
+            0,
+            &[("event_type", "start"), ("duration_minutes", "450")],
+        ),
+        record(
+            HealthDataType::Sleep,
+            30,
+            &[("event_type", "end"), ("duration_minutes", "450")],
+        ),
+    ];
+
+    let annotations = session_annotations(&records, &[AnnotatedSession::Exercise]);
+    assert_eq!(annotations.len(), 2);
+    assert_eq!(annotations[0].text, "Running: Morning run (45 min)");
+    assert_eq!(
+        annotations[0].tags,
+        ["home-db-importer", "exercise", "running"]
+    );
+    assert_eq!(
+        annotations[0].time_end - annotations[0].time,
+        45 * 60 * 1000
+    );
+    assert_eq!(annotations[1].text, "Exercise (1 h 15 min)");
+    assert_eq!(
+        annotations[1].time_end - annotations[1].time,
+        75 * 60 * 1000
+    );
+
+    let annotations = session_annotations(
+        &records,
+        &[AnnotatedSession::Exercise, AnnotatedSession::Sleep],
+    );
+    assert_eq!(annotations.len(), 3);
+    assert_eq!(annotations[2].text, "Sleep (7 h 30 min)");
+    assert_eq!(annotations[2].tags, ["home-db-importer", "sleep"]);
+}
+
+// Test that annotations Grafana already has are not pushed again, and the others are pushed
+// on the dashboard with the extra tags
+#[tokio::test]
+async fn test_push_annotations() {
+    let (url, posted) = spawn_fake_grafana(r#"[{"id":7,"time":1000,"text":"Sleep (8 h)"}]"#);
+    let annotator = GrafanaAnnotator::new(&url, "token")
+        .with_dashboard("health")
+        .with_tags(vec!["health".to_string()]);
+    let annotation = |time: i64, text: &str| Annotation {
+        time,
+        time_end: time + 60_000,
+        text: text.to_string(),
+        tags: vec!["home-db-importer".to_string()],
+    };
+
+    let pushed = annotator
+        .push(&[
+            annotation(1000, "Sleep (8 h)"),
+            annotation(2000, "Yoga (1 min)"),
+        ])
+        .await
+        .unwrap();
+    assert_eq!(pushed, 1);
+    // Nothing to push, nothing requested
+    assert_eq!(annotator.push(&[]).await.unwrap(), 0);
+
+    let posted = posted.lock().unwrap();
+    assert_eq!(posted.len(), 1);
+    let body: serde_json::Value = serde_json::from_str(&posted[0]).unwrap();
+    assert_eq!(body["time"], 2000);
+    assert_eq!(body["timeEnd"], 62000);
+    assert_eq!(body["dashboardUID"], "health");
+    assert_eq!(
+        body["tags"],
+        serde_json::json!(["home-db-importer", "health"])
+    );
+}
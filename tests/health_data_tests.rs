@@ -0,0 +1,976 @@
+use chrono::{NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use home_db_importer::health_data::{
+    count_per_day, export_files, gap_ranges, group_by_type, safe_row_ids, split_by_time,
+    stale_ranges, stale_timestamps, take_oldest, DataTypeSelector, HealthDataReader,
+    HealthDataType, HealthRecord, LatestRecord, ReadFrom, RenamedColumn, TableStats, TypeMismatch,
+};
+use home_db_importer::source::Source;
+use home_db_importer::state_management::ImportState;
+use rusqlite::Connection;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use tempfile::tempdir;
+
+// Helper function to create a health record at the given minute
+fn create_record(record_type: HealthDataType, minute: u32) -> HealthRecord {
+    HealthRecord {
+        record_type,
+        timestamp: Utc.with_ymd_and_hms(2023, 7, 15, 10, minute, 0).unwrap(),
+        value: minute as f64,
+        metadata: HashMap::new(),
+        row_id: None,
+        zone_offset: None,
+    }
+}
+
+// Helper function to create a health record with a source row_id
+fn create_row(record_type: HealthDataType, minute: u32, row_id: i64) -> HealthRecord {
+    HealthRecord {
+        row_id: Some(row_id),
+        ..create_record(record_type, minute)
+    }
+}
+
+// Test splitting records into time-ordered chunks for checkpointing
+#[test]
+fn test_split_by_time() {
+    let mut records_map = HashMap::new();
+    records_map.insert(
+        HealthDataType::HeartRate,
+        vec![
+            create_record(HealthDataType::HeartRate, 5),
+            create_record(HealthDataType::HeartRate, 1),
+            create_record(HealthDataType::HeartRate, 3),
+        ],
+    );
+    records_map.insert(
+        HealthDataType::Steps,
+        vec![
+            create_record(HealthDataType::Steps, 2),
+            create_record(HealthDataType::Steps, 3),
+        ],
+    );
+
+    // Everything fits in one chunk
+    let chunks = split_by_time(records_map.clone(), 0, None);
+    assert_eq!(chunks.len(), 1);
+
+    let minutes = |chunks: &[HashMap<HealthDataType, Vec<HealthRecord>>]| {
+        chunks
+            .iter()
+            .map(|chunk| {
+                let mut minutes: Vec<u32> = chunk
+                    .values()
+                    .flatten()
+                    .map(|record| record.value as u32)
+                    .collect();
+                minutes.sort();
+                minutes
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // Chunks of 2 minutes each, counted from the epoch, however many records they hold
+    let period = Some(std::time::Duration::from_secs(120));
+    let chunks = split_by_time(records_map.clone(), 0, period);
+    assert_eq!(minutes(&chunks), vec![vec![1], vec![2, 3, 3], vec![5]]);
+
+    let chunks = split_by_time(records_map, 1, None);
+    // Both records at minute 3 stay together, even though that exceeds the chunk size
+    assert_eq!(
+        minutes(&chunks),
+        vec![vec![1], vec![2], vec![3, 3], vec![5]]
+    );
+    assert_eq!(chunks[2][&HealthDataType::HeartRate].len(), 1);
+    assert_eq!(chunks[2][&HealthDataType::Steps].len(), 1);
+}
+
+// Test that reading from a row_id picks up records synced late with an earlier timestamp
+#[test]
+fn test_read_steps_from_row_id() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE steps_record_table (
+             row_id INTEGER PRIMARY KEY, start_time INTEGER, count INTEGER, app_info_id INTEGER
+         );
+         INSERT INTO application_info_table VALUES (1, 'Fit');
+         INSERT INTO steps_record_table VALUES (1, 1689415200000, 100, 1);
+         INSERT INTO steps_record_table VALUES (2, 1689418800000, 200, 1);
+         -- Synced late: inserted last, but earlier than the record above
+         INSERT INTO steps_record_table VALUES (3, 1689416000000, 300, 1);",
+    )
+    .unwrap();
+
+    let reader = HealthDataReader::new(db_path.to_str().unwrap());
+
+    let all = reader.get_steps_since(ReadFrom::Beginning).unwrap();
+    assert_eq!(all.len(), 3);
+    assert_eq!(all[0].row_id, Some(1));
+    assert_eq!(all[0].metadata["app_name"], "Fit");
+
+    // The timestamp of the latest record skips the late one
+    let by_time = reader.get_steps_since(Some(all[2].timestamp)).unwrap();
+    assert!(by_time.is_empty());
+
+    // The row_id of the last imported record does not
+    let by_row_id = reader.get_steps_since(ReadFrom::RowId(2)).unwrap();
+    assert_eq!(by_row_id.len(), 1);
+    assert_eq!(by_row_id[0].value, 300.0);
+
+    // The newest record and highest row_id are used to check watermarks
+    assert_eq!(
+        reader.latest_record(HealthDataType::Steps).unwrap(),
+        Some(LatestRecord {
+            timestamp: all[2].timestamp,
+            row_id: 3
+        })
+    );
+    assert_eq!(reader.latest_record(HealthDataType::Weight).unwrap(), None);
+}
+
+// Test that reading changes picks up records edited after they were imported
+#[test]
+fn test_read_steps_changes() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE steps_record_table (
+             row_id INTEGER PRIMARY KEY, last_modified_time INTEGER, start_time INTEGER,
+             count INTEGER, app_info_id INTEGER
+         );
+         INSERT INTO steps_record_table VALUES (1, 1689415300000, 1689415200000, 100, NULL);
+         -- Edited after the import that read row 2
+         INSERT INTO steps_record_table VALUES (2, 1689500000000, 1689418800000, 250, NULL);
+         INSERT INTO steps_record_table VALUES (3, 1689422500000, 1689422400000, 300, NULL);",
+    )
+    .unwrap();
+    let reader = HealthDataReader::new(db_path.to_str().unwrap());
+    let imported = Utc.timestamp_millis_opt(1689422500000).unwrap();
+
+    let by_row_id = reader.get_steps_since(ReadFrom::RowId(3)).unwrap();
+    assert!(by_row_id.is_empty());
+    let changes = reader
+        .get_steps_since(ReadFrom::Changes {
+            row_id: 3,
+            modified: imported,
+        })
+        .unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0].value, 250.0);
+
+    assert_eq!(
+        reader.latest_modified(HealthDataType::Steps).unwrap(),
+        Some(Utc.timestamp_millis_opt(1689500000000).unwrap())
+    );
+    assert_eq!(
+        reader.latest_modified(HealthDataType::Weight).unwrap(),
+        None
+    );
+}
+
+// Test that the records of older exports are merged in, each record once, with the values of
+// the newest export it is in
+#[test]
+fn test_merge_exports() {
+    let dir = tempdir().unwrap();
+    let create = |name: &str, rows: &str| {
+        let path = dir.path().join(name);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(&format!(
+            "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+             CREATE TABLE steps_record_table (
+                 row_id INTEGER PRIMARY KEY, last_modified_time INTEGER, start_time INTEGER,
+                 count INTEGER, app_info_id INTEGER
+             );
+             {}",
+            rows
+        ))
+        .unwrap();
+        path.to_str().unwrap().to_string()
+    };
+    let january = create(
+        "2023-01.db",
+        "INSERT INTO steps_record_table VALUES (1, 0, 1672567200000, 100, NULL);
+         INSERT INTO steps_record_table VALUES (2, 0, 1672570800000, 200, NULL);",
+    );
+    // Row 2 of January, edited since, and a record of February under the same row_id
+    let february = create(
+        "2023-02.db",
+        "INSERT INTO steps_record_table VALUES (1, 0, 1672570800000, 250, NULL);
+         INSERT INTO steps_record_table VALUES (2, 0, 1675245600000, 300, NULL);",
+    );
+    std::fs::write(dir.path().join("notes.txt"), "not an export").unwrap();
+
+    let reader = HealthDataReader::new(&february).with_merged_sources(vec![january.clone()]);
+    let steps = reader.get_steps_since(ReadFrom::Beginning).unwrap();
+    let values: Vec<f64> = steps.iter().map(|record| record.value).collect();
+    assert_eq!(values, vec![100.0, 250.0, 300.0]);
+    assert_eq!(steps[0].row_id, None);
+    assert_eq!(steps[1].row_id, Some(1));
+
+    assert_eq!(
+        export_files(dir.path().to_str().unwrap()).unwrap(),
+        vec![january.clone(), february]
+    );
+    assert_eq!(export_files(&january).unwrap(), vec![january]);
+}
+
+// Test that the database is opened read-only, and as immutable in a directory that cannot
+// be written, leaving no write-ahead log files behind
+#[test]
+fn test_read_only_access() {
+    let dir = tempdir().unwrap();
+    let export_dir = dir.path().join("backup");
+    std::fs::create_dir(&export_dir).unwrap();
+    let db_path = export_dir.join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE steps_record_table (
+             row_id INTEGER PRIMARY KEY, last_modified_time INTEGER, start_time INTEGER,
+             count INTEGER, app_info_id INTEGER
+         );
+         INSERT INTO steps_record_table VALUES (1, 0, 1689415200000, 100, NULL);",
+    )
+    .unwrap();
+    drop(conn);
+    let reader = HealthDataReader::new(db_path.to_str().unwrap());
+
+    let writer = reader.open_connection().unwrap();
+    assert!(writer
+        .execute("DELETE FROM steps_record_table", [])
+        .is_err());
+    drop(writer);
+
+    let mut permissions = std::fs::metadata(&export_dir).unwrap().permissions();
+    permissions.set_readonly(true);
+    std::fs::set_permissions(&export_dir, permissions.clone()).unwrap();
+    let steps = reader.get_steps_since(ReadFrom::Beginning);
+    let files = std::fs::read_dir(&export_dir).unwrap().count();
+    #[allow(clippy::permissions_set_readonly_false)]
+    permissions.set_readonly(false);
+    std::fs::set_permissions(&export_dir, permissions).unwrap();
+
+    assert_eq!(steps.unwrap().len(), 1);
+    assert_eq!(files, 1);
+}
+
+// Test that step cadence samples are read as an intraday series, and that exports without
+// cadence tables have none
+#[test]
+fn test_read_steps_cadence() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE steps_record_table (
+             row_id INTEGER PRIMARY KEY, start_time INTEGER, count INTEGER, app_info_id INTEGER
+         );
+         INSERT INTO application_info_table VALUES (1, 'Watch');
+         INSERT INTO steps_record_table VALUES (1, 1689415200000, 100, 1);",
+    )
+    .unwrap();
+    let reader = HealthDataReader::new(db_path.to_str().unwrap());
+    assert!(reader
+        .get_steps_cadence_since(ReadFrom::Beginning)
+        .unwrap()
+        .is_empty());
+
+    conn.execute_batch(
+        "CREATE TABLE StepsCadenceRecordTable (
+             row_id INTEGER PRIMARY KEY, last_modified_time INTEGER, app_info_id INTEGER
+         );
+         CREATE TABLE steps_cadence_record_table (
+             parent_key INTEGER, rate REAL, epoch_millis INTEGER
+         );
+         INSERT INTO StepsCadenceRecordTable VALUES (1, 0, 1);
+         INSERT INTO steps_cadence_record_table VALUES (1, 96.5, 1689415260000);
+         INSERT INTO steps_cadence_record_table VALUES (1, 110.0, 1689415200000);",
+    )
+    .unwrap();
+    let cadence = reader.get_steps_cadence_since(ReadFrom::Beginning).unwrap();
+    let values: Vec<f64> = cadence.iter().map(|record| record.value).collect();
+    assert_eq!(values, vec![110.0, 96.5]);
+    assert_eq!(cadence[0].record_type, HealthDataType::StepsCadence);
+    assert_eq!(cadence[0].metadata["app_name"], "Watch");
+    assert_eq!(
+        reader
+            .latest_record(HealthDataType::StepsCadence)
+            .unwrap()
+            .map(|latest| latest.row_id),
+        Some(1)
+    );
+}
+
+// Test that the zone offset of records is read, and left unknown in exports without it
+#[test]
+fn test_read_zone_offsets() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE steps_record_table (
+             row_id INTEGER PRIMARY KEY, start_time INTEGER, count INTEGER, app_info_id INTEGER
+         );
+         CREATE TABLE weight_record_table (
+             row_id INTEGER PRIMARY KEY, time INTEGER, weight REAL, app_info_id INTEGER,
+             zone_offset INTEGER
+         );
+         INSERT INTO steps_record_table VALUES (1, 1689415200000, 100, 1);
+         INSERT INTO weight_record_table VALUES (1, 1689415200000, 70000.0, 1, 7200);
+         INSERT INTO weight_record_table VALUES (2, 1689415260000, 70100.0, 1, NULL);",
+    )
+    .unwrap();
+    let reader = HealthDataReader::new(db_path.to_str().unwrap());
+
+    let steps = reader.get_steps_since(ReadFrom::Beginning).unwrap();
+    assert_eq!(steps[0].zone_offset, None);
+    assert_eq!(steps[0].local_time(Tz::UTC), steps[0].timestamp.naive_utc());
+
+    let weights = reader.get_weight_since(ReadFrom::Beginning).unwrap();
+    let offsets: Vec<Option<i32>> = weights.iter().map(|record| record.zone_offset).collect();
+    assert_eq!(offsets, vec![Some(7200), None]);
+    assert_eq!(
+        weights[0].local_time(Tz::UTC),
+        weights[0].timestamp.naive_utc() + chrono::Duration::hours(2)
+    );
+    // The offset is not a tag, so series do not split when travelling
+    assert!(!weights[0].metadata.contains_key("zone_offset"));
+}
+
+// Test that heart rate samples taken during an exercise session are tagged with it
+#[test]
+fn test_link_workouts() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE heart_rate_record_table (row_id INTEGER PRIMARY KEY, app_info_id INTEGER);
+         CREATE TABLE heart_rate_record_series_table (
+             parent_key INTEGER, epoch_millis INTEGER, beats_per_minute INTEGER
+         );
+         CREATE TABLE exercise_session_record_table (
+             row_id INTEGER PRIMARY KEY, uuid BLOB, start_time INTEGER, end_time INTEGER,
+             exercise_type INTEGER, title TEXT, app_info_id INTEGER
+         );
+         INSERT INTO heart_rate_record_table VALUES (1, NULL);
+         INSERT INTO heart_rate_record_series_table VALUES (1, 1689415140000, 60);
+         INSERT INTO heart_rate_record_series_table VALUES (1, 1689415200000, 120);
+         INSERT INTO heart_rate_record_series_table VALUES (1, 1689417000000, 140);
+         -- The end of a session is not part of it
+         INSERT INTO heart_rate_record_series_table VALUES (1, 1689418800000, 70);
+         INSERT INTO exercise_session_record_table
+             VALUES (1, x'0A0B', 1689415200000, 1689418800000, 56, 'Run', NULL);",
+    )
+    .unwrap();
+    let reader = HealthDataReader::new(db_path.to_str().unwrap());
+
+    let unlinked = reader.get_heart_rate_since(ReadFrom::Beginning).unwrap();
+    assert!(unlinked
+        .iter()
+        .all(|record| !record.metadata.contains_key("exercise_session")));
+
+    let linked = reader
+        .with_workout_links(true)
+        .get_heart_rate_since(ReadFrom::Beginning)
+        .unwrap();
+    let sessions: Vec<Option<&str>> = linked
+        .iter()
+        .map(|record| record.metadata.get("exercise_session").map(String::as_str))
+        .collect();
+    assert_eq!(sessions, vec![None, Some("0a0b"), Some("0a0b"), None]);
+    assert_eq!(linked[1].metadata["exercise_type"], "56");
+}
+
+// Test that only the windows whose counts differ are compared one by one, in as few ranges
+// as the windows whose counts agree allow
+#[test]
+fn test_gap_ranges() {
+    let hour = |h| Utc.with_ymd_and_hms(2023, 7, 15, h, 0, 0).unwrap();
+    let source = BTreeMap::from([
+        (hour(0), 60),
+        (hour(1), 60),
+        (hour(2), 60),
+        (hour(4), 30),
+        (hour(5), 20),
+        (hour(7), 10),
+    ]);
+    let sink = BTreeMap::from([(hour(0), 60), (hour(1), 58), (hour(5), 20), (hour(6), 10)]);
+    let window = std::time::Duration::from_secs(3600);
+
+    // Hours without samples do not split a range, hours whose counts agree do
+    assert_eq!(
+        gap_ranges(&source, &sink, window),
+        vec![(hour(1), hour(5)), (hour(7), hour(8))]
+    );
+    assert!(gap_ranges(&source, &source, window).is_empty());
+    // Nothing counted in InfluxDB compares every window, in a single range
+    assert_eq!(
+        gap_ranges(&source, &BTreeMap::new(), window),
+        vec![(hour(0), hour(8))]
+    );
+}
+
+// Test that a series streamed through a cursor reads the new samples in time order, in
+// pages, and that only series can be streamed
+#[test]
+fn test_stream_series() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE heart_rate_record_table (row_id INTEGER PRIMARY KEY, app_info_id INTEGER);
+         CREATE TABLE heart_rate_record_series_table (
+             parent_key INTEGER, epoch_millis INTEGER, beats_per_minute INTEGER
+         );
+         INSERT INTO heart_rate_record_table VALUES (1, NULL);
+         INSERT INTO heart_rate_record_table VALUES (2, NULL);
+         INSERT INTO heart_rate_record_table VALUES (3, NULL);
+         INSERT INTO heart_rate_record_series_table VALUES (1, 1689415140000, 60);
+         INSERT INTO heart_rate_record_series_table VALUES (2, 1689415320000, 64);
+         INSERT INTO heart_rate_record_series_table VALUES (2, 1689415200000, 62);
+         -- Synced late, with an earlier time
+         INSERT INTO heart_rate_record_series_table VALUES (3, 1689415170000, 61);
+         INSERT INTO heart_rate_record_series_table VALUES (3, 1689415260000, 63);",
+    )
+    .unwrap();
+    let path = db_path.to_str().unwrap();
+    let reader = HealthDataReader::new(path);
+
+    let mut watermark = ImportState::new(path);
+    watermark.record_row_id("HeartRate", 1);
+    let (records, series_reader) = reader
+        .stream_series(HealthDataType::HeartRate, &watermark, 2)
+        .unwrap();
+    let values: Vec<f64> = records.map(|record| record.value).collect();
+    assert_eq!(values, vec![61.0, 62.0, 63.0, 64.0]);
+    assert_eq!(series_reader.join().unwrap(), Ok(4));
+
+    // Reading stops once the records are no longer taken, after the page it holds
+    let (records, series_reader) = reader
+        .stream_series(HealthDataType::HeartRate, &ImportState::new(path), 2)
+        .unwrap();
+    assert_eq!(records.take(1).count(), 1);
+    assert_eq!(series_reader.join().unwrap(), Ok(4));
+
+    assert!(reader
+        .stream_series(HealthDataType::Steps, &watermark, 2)
+        .is_err());
+}
+
+// Test that the tables read concurrently each start from their own data type's watermark
+#[test]
+fn test_read_data_types_concurrently() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE steps_record_table (
+             row_id INTEGER PRIMARY KEY, start_time INTEGER, count INTEGER, app_info_id INTEGER
+         );
+         CREATE TABLE weight_record_table (
+             row_id INTEGER PRIMARY KEY, time INTEGER, weight REAL, app_info_id INTEGER
+         );
+         INSERT INTO steps_record_table VALUES (1, 1689415200000, 100, NULL);
+         INSERT INTO steps_record_table VALUES (2, 1689418800000, 200, NULL);
+         INSERT INTO steps_record_table VALUES (3, 1689422400000, 300, NULL);
+         INSERT INTO weight_record_table VALUES (1, 1689415200000, 70000, NULL);",
+    )
+    .unwrap();
+    let path = db_path.to_str().unwrap();
+    let reader = HealthDataReader::new(path);
+
+    let mut watermark = ImportState::new(path);
+    watermark.record_row_id("Steps", 1);
+    let since = |data_type: HealthDataType| match watermark.last_row_id_for(data_type.as_str()) {
+        Some(row_id) => ReadFrom::RowId(row_id),
+        None => ReadFrom::Beginning,
+    };
+
+    let records_map = reader.get_health_data_since_per_type(since, None).unwrap();
+    assert_eq!(records_map.len(), 2);
+    assert_eq!(records_map[&HealthDataType::Steps].len(), 2);
+    assert_eq!(records_map[&HealthDataType::Steps][0].row_id, Some(2));
+    assert_eq!(records_map[&HealthDataType::Weight].len(), 1);
+
+    // Streaming the records reads the same ones, in the same order within each type
+    let streamed = group_by_type(reader.records_since(&watermark).unwrap());
+    let row_ids = |records: &[HealthRecord]| records.iter().map(|r| r.row_id).collect::<Vec<_>>();
+    for (data_type, records) in &records_map {
+        assert_eq!(row_ids(&streamed[data_type]), row_ids(records));
+    }
+    assert_eq!(streamed.len(), records_map.len());
+}
+
+// Test combining the starting points of data types read by one query
+#[test]
+fn test_read_from_earliest() {
+    let early = Utc.with_ymd_and_hms(2023, 7, 15, 10, 0, 0).unwrap();
+    let late = Utc.with_ymd_and_hms(2023, 7, 16, 10, 0, 0).unwrap();
+
+    assert_eq!(
+        ReadFrom::Timestamp(late).earliest(ReadFrom::Timestamp(early)),
+        ReadFrom::Timestamp(early)
+    );
+    assert_eq!(
+        ReadFrom::RowId(7).earliest(ReadFrom::RowId(3)),
+        ReadFrom::RowId(3)
+    );
+    assert_eq!(
+        ReadFrom::RowId(7).earliest(ReadFrom::Timestamp(early)),
+        ReadFrom::Beginning
+    );
+    // A type reading changes keeps reading them alongside one resuming by row_id
+    assert_eq!(
+        ReadFrom::Changes {
+            row_id: 7,
+            modified: late
+        }
+        .earliest(ReadFrom::RowId(3)),
+        ReadFrom::Changes {
+            row_id: 3,
+            modified: late
+        }
+    );
+}
+
+// Test which row_ids are safe to resume from after writing some chunks
+#[test]
+fn test_safe_row_ids() {
+    let chunks = vec![
+        vec![
+            create_row(HealthDataType::Steps, 1, 4),
+            create_row(HealthDataType::Steps, 2, 6),
+        ],
+        vec![
+            // Synced late: written after row 6, so row 6 is not safe yet
+            create_row(HealthDataType::Steps, 3, 5),
+            create_row(HealthDataType::Weight, 3, 9),
+        ],
+    ];
+
+    let row_ids = safe_row_ids(&chunks, 1);
+    assert_eq!(row_ids, HashMap::from([(HealthDataType::Steps, 4)]));
+
+    let row_ids = safe_row_ids(&chunks, 2);
+    assert_eq!(
+        row_ids,
+        HashMap::from([(HealthDataType::Steps, 6), (HealthDataType::Weight, 9)])
+    );
+}
+
+// Test limiting an import to the oldest records across data types
+#[test]
+fn test_take_oldest() {
+    let records_map = HashMap::from([
+        (
+            HealthDataType::HeartRate,
+            vec![
+                create_row(HealthDataType::HeartRate, 4, 1),
+                create_row(HealthDataType::HeartRate, 1, 2),
+            ],
+        ),
+        (
+            HealthDataType::Steps,
+            vec![
+                create_row(HealthDataType::Steps, 2, 1),
+                create_row(HealthDataType::Steps, 2, 2),
+            ],
+        ),
+    ]);
+
+    // The second record at minute 2 is taken along with the first
+    let (taken, held_back) = take_oldest(records_map.clone(), 2);
+    assert_eq!(taken[&HealthDataType::HeartRate].len(), 1);
+    assert_eq!(taken[&HealthDataType::Steps].len(), 2);
+    assert_eq!(held_back[&HealthDataType::HeartRate][0].value, 4.0);
+    assert!(!held_back.contains_key(&HealthDataType::Steps));
+
+    // Held back records keep their row_ids from being resumed past
+    let chunks: Vec<Vec<HealthRecord>> = [taken, held_back]
+        .into_iter()
+        .map(|chunk| chunk.into_values().flatten().collect())
+        .collect();
+    assert_eq!(
+        safe_row_ids(&chunks, 1),
+        HashMap::from([(HealthDataType::HeartRate, 0), (HealthDataType::Steps, 2)])
+    );
+
+    let (taken, held_back) = take_oldest(records_map, 10);
+    assert_eq!(taken.values().map(|v| v.len()).sum::<usize>(), 4);
+    assert!(held_back.is_empty());
+}
+
+// Test summarizing the record tables of an export
+#[test]
+fn test_table_stats() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE weight_record_table (
+             row_id INTEGER PRIMARY KEY, time INTEGER, weight REAL, app_info_id INTEGER
+         );
+         INSERT INTO application_info_table VALUES (1, 'Scale');
+         INSERT INTO weight_record_table VALUES (1, 1689415200000, 70000, 1);
+         INSERT INTO weight_record_table VALUES (2, 1689588000000, 70500, 1);
+         INSERT INTO weight_record_table VALUES (3, 1689760800000, 70200, NULL);",
+    )
+    .unwrap();
+
+    let reader = HealthDataReader::new(db_path.to_str().unwrap());
+    let all_stats = reader.table_stats().unwrap();
+    assert_eq!(all_stats.len(), 12);
+
+    let weight = all_stats
+        .iter()
+        .find(|s| s.data_type == HealthDataType::Weight)
+        .unwrap();
+    assert_eq!(
+        weight,
+        &TableStats {
+            data_type: HealthDataType::Weight,
+            table: "weight_record_table",
+            exists: true,
+            records: 3,
+            earliest: Some(Utc.with_ymd_and_hms(2023, 7, 15, 10, 0, 0).unwrap()),
+            latest: Some(Utc.with_ymd_and_hms(2023, 7, 19, 10, 0, 0).unwrap()),
+            apps: vec!["Scale".to_string(), "unknown".to_string()],
+        }
+    );
+    // Three records over four days
+    assert_eq!(weight.records_per_day(), Some(0.75));
+
+    let steps = all_stats
+        .iter()
+        .find(|s| s.data_type == HealthDataType::Steps)
+        .unwrap();
+    assert!(!steps.exists);
+    assert_eq!(steps.records_per_day(), None);
+
+    assert!(HealthDataReader::new("missing.db").table_stats().is_err());
+}
+
+// Test finding the points whose records were deleted or moved in the source
+#[test]
+fn test_stale_timestamps() {
+    let millis = |minute| {
+        create_record(HealthDataType::Weight, minute)
+            .timestamp
+            .timestamp_millis()
+    };
+    let records = vec![
+        create_record(HealthDataType::Weight, 1),
+        // Edited from minute 3 to minute 4
+        create_record(HealthDataType::Weight, 4),
+        // Other data types don't keep a weight point
+        create_record(HealthDataType::BodyFat, 5),
+    ];
+    let existing = HashSet::from([millis(1), millis(3), millis(5)]);
+
+    assert_eq!(
+        stale_timestamps(&existing, &records, HealthDataType::Weight),
+        vec![millis(3), millis(5)]
+    );
+}
+
+// Test grouping stale points into ranges that skip the points and records kept
+#[test]
+fn test_stale_ranges() {
+    let millis = |minute| {
+        create_record(HealthDataType::Weight, minute)
+            .timestamp
+            .timestamp_millis()
+    };
+    let records = vec![
+        create_record(HealthDataType::Weight, 1),
+        // Not written yet, so only the export has it
+        create_record(HealthDataType::Weight, 6),
+    ];
+    let existing = HashSet::from([millis(1), millis(2), millis(3), millis(5), millis(7)]);
+
+    assert_eq!(
+        stale_ranges(&existing, &records, HealthDataType::Weight),
+        vec![(millis(2), millis(5) + 1), (millis(7), millis(7) + 1)]
+    );
+}
+
+// Test counting records per UTC day for comparing with InfluxDB
+#[test]
+fn test_count_per_day() {
+    let late = HealthRecord {
+        timestamp: Utc.with_ymd_and_hms(2023, 7, 16, 23, 59, 0).unwrap(),
+        ..create_record(HealthDataType::Steps, 0)
+    };
+    let records = vec![
+        create_record(HealthDataType::Steps, 1),
+        create_record(HealthDataType::Steps, 2),
+        late,
+    ];
+
+    let day = |d| NaiveDate::from_ymd_opt(2023, 7, d).unwrap();
+    assert_eq!(
+        count_per_day(&records),
+        BTreeMap::from([(day(15), 2), (day(16), 1)])
+    );
+}
+
+// Test comparing an export's schema with the columns the importer reads
+#[test]
+fn test_check_schema() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE steps_record_table (row_id INTEGER PRIMARY KEY, start_time INTEGER, app_info_id INTEGER);",
+    )
+    .unwrap();
+
+    let check = HealthDataReader::new(db_path.to_str().unwrap())
+        .check_schema()
+        .unwrap();
+    assert_eq!(check.missing_columns, vec![("steps_record_table", "count")]);
+    assert_eq!(check.missing_tables.len(), 14);
+    assert!(!check.missing_tables.contains(&"application_info_table"));
+}
+
+// Test that renamed columns and columns of another type are told apart from missing ones,
+// and reported per data type by validate_db
+#[test]
+fn test_schema_diff() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE steps_record_table (
+             row_id INTEGER PRIMARY KEY, last_modified_time INTEGER, start_time INTEGER,
+             step_count INTEGER, app_info_id INTEGER
+         );
+         CREATE TABLE weight_record_table (
+             row_id INTEGER PRIMARY KEY, time INTEGER, weight TEXT, app_info_id INTEGER
+         );
+         CREATE TABLE height_record_table (
+             row_id INTEGER PRIMARY KEY, time INTEGER, height NUMERIC, app_info_id INTEGER
+         );
+         INSERT INTO height_record_table VALUES (1, 1689415200000, 1.8, NULL);",
+    )
+    .unwrap();
+    let reader = HealthDataReader::new(db_path.to_str().unwrap());
+
+    let check = reader.check_schema().unwrap();
+    assert!(!check.is_ok());
+    assert!(check.missing_columns.is_empty());
+    assert_eq!(
+        check.renamed_columns,
+        vec![RenamedColumn {
+            table: "steps_record_table",
+            expected: "count",
+            found: "step_count".to_string(),
+        }]
+    );
+    assert_eq!(
+        check.type_mismatches,
+        vec![TypeMismatch {
+            table: "weight_record_table",
+            column: "weight",
+            expected: "REAL",
+            found: "TEXT".to_string(),
+        }]
+    );
+    assert_eq!(
+        check.problems_for(HealthDataType::Steps),
+        vec!["column steps_record_table.count missing, renamed to step_count?"]
+    );
+    assert!(check.problems_for(HealthDataType::Height).is_empty());
+
+    let summary = reader.validate_db().unwrap();
+    assert!(summary.contains("  - Height: 1 records"));
+    assert!(summary.contains("  - Weight: cannot be read"));
+    assert!(summary.contains("  - BodyFat: no body_fat_record_table, skipped by imports"));
+}
+
+// Test that data type names round-trip, ignoring case, and match the command line values
+#[test]
+fn test_health_data_type_names() {
+    use clap::ValueEnum;
+
+    for data_type in HealthDataType::ALL {
+        assert_eq!(data_type.to_string().parse(), Ok(data_type));
+        assert_eq!(
+            data_type.to_possible_value().unwrap().get_name(),
+            data_type.as_str()
+        );
+    }
+    assert_eq!("heartrate".parse(), Ok(HealthDataType::HeartRate));
+    assert_eq!(
+        "Mood".parse::<HealthDataType>(),
+        Err("Unknown health data type: Mood".to_string())
+    );
+}
+
+// Test that group names expand to their data types, each once, and that a data type name
+// in any case is that type, even when a group has the same name
+#[test]
+fn test_data_type_groups() {
+    use HealthDataType::*;
+
+    let expand = |names: &[&str]| {
+        let selectors: Vec<DataTypeSelector> =
+            names.iter().map(|name| name.parse().unwrap()).collect();
+        DataTypeSelector::expand(&selectors)
+    };
+    assert_eq!(
+        expand(&["body"]),
+        vec![Weight, BodyFat, LeanBodyMass, Height]
+    );
+    assert_eq!(
+        expand(&["HeartRate", "group:SLEEP", "group:activity", "steps"]),
+        vec![
+            HeartRate,
+            Sleep,
+            SleepDuration,
+            SleepState,
+            Steps,
+            StepsCadence,
+            ActiveCalories,
+            TotalCalories,
+            ExerciseSession
+        ]
+    );
+    assert_eq!(expand(&["Sleep"]), vec![Sleep]);
+    assert_eq!(expand(&["sleep"]), vec![Sleep]);
+    assert_eq!(expand(&["SLEEP"]), vec![Sleep]);
+    assert_eq!(
+        "vitals".parse::<DataTypeSelector>(),
+        Err("Unknown health data type or group: vitals".to_string())
+    );
+    assert_eq!(
+        "group:Steps".parse::<DataTypeSelector>(),
+        Err("Unknown health data type group: Steps".to_string())
+    );
+}
+
+// Test that excluded data types are left out of the requested ones, or of every type
+#[test]
+fn test_select_data_types() {
+    use HealthDataType::*;
+
+    assert_eq!(HealthDataType::select(None, &[]), None);
+    assert_eq!(
+        HealthDataType::select(Some(vec![Steps, Weight]), &[]),
+        Some(vec![Steps, Weight])
+    );
+    assert_eq!(
+        HealthDataType::select(Some(vec![Steps, Weight]), &[Weight]),
+        Some(vec![Steps])
+    );
+    let selected = HealthDataType::select(None, &[HeartRate, SleepState]).unwrap();
+    assert_eq!(selected.len(), HealthDataType::ALL.len() - 2);
+    assert!(!selected.contains(&HeartRate) && !selected.contains(&SleepState));
+    assert_eq!(
+        HealthDataType::select(Some(vec![Steps]), &[Steps]),
+        Some(vec![])
+    );
+}
+
+// Test that only one of the overlapping sleep sessions of several apps is read, by app
+// priority or else the most detailed one
+#[test]
+fn test_dedupe_overlapping_sleep_sessions() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE sleep_session_record_table (
+             row_id INTEGER PRIMARY KEY, start_time INTEGER, end_time INTEGER, app_info_id INTEGER
+         );
+         CREATE TABLE sleep_stages_table (
+             parent_key INTEGER, stage_type INTEGER, stage_start_time INTEGER
+         );
+         INSERT INTO application_info_table VALUES (1, 'Watch');
+         INSERT INTO application_info_table VALUES (2, 'Fit');
+         -- The same night from both apps, the watch with more stages
+         INSERT INTO sleep_session_record_table VALUES (1, 1689379200000, 1689408000000, 1);
+         INSERT INTO sleep_session_record_table VALUES (2, 1689381000000, 1689406200000, 2);
+         -- The next night, only from Fit
+         INSERT INTO sleep_session_record_table VALUES (3, 1689465600000, 1689494400000, 2);
+         INSERT INTO sleep_stages_table VALUES (1, 4, 1689379200000);
+         INSERT INTO sleep_stages_table VALUES (1, 5, 1689390000000);
+         INSERT INTO sleep_stages_table VALUES (2, 2, 1689381000000);
+         INSERT INTO sleep_stages_table VALUES (3, 2, 1689465600000);",
+    )
+    .unwrap();
+    let path = db_path.to_str().unwrap();
+    let sessions = |reader: HealthDataReader| {
+        let mut row_ids: Vec<i64> = reader
+            .get_sleep_since(ReadFrom::Beginning)
+            .unwrap()
+            .iter()
+            .filter_map(|record| record.row_id)
+            .collect();
+        row_ids.dedup();
+        row_ids
+    };
+
+    assert_eq!(sessions(HealthDataReader::new(path)), vec![1, 3]);
+    assert_eq!(
+        sessions(HealthDataReader::new(path).with_sleep_priority(vec!["fit".to_string()])),
+        vec![2, 3]
+    );
+}
+
+// Test that sleep stages are read with their own start times, each ending where the next
+// one starts and the last with its session
+#[test]
+fn test_read_sleep_stages() {
+    let dir = tempdir().unwrap();
+    let db_path = dir.path().join("health_connect_export.db");
+    let conn = Connection::open(&db_path).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE application_info_table (row_id INTEGER PRIMARY KEY, app_name TEXT);
+         CREATE TABLE sleep_session_record_table (
+             row_id INTEGER PRIMARY KEY, start_time INTEGER, end_time INTEGER, app_info_id INTEGER
+         );
+         CREATE TABLE sleep_stages_table (
+             parent_key INTEGER, stage_type INTEGER, stage_start_time INTEGER
+         );
+         INSERT INTO application_info_table VALUES (1, 'Watch');
+         INSERT INTO sleep_session_record_table VALUES (1, 1689379200000, 1689408000000, 1);
+         INSERT INTO sleep_session_record_table VALUES (2, 1689465600000, 1689494400000, 1);
+         INSERT INTO sleep_stages_table VALUES (1, 5, 1689390000000);
+         INSERT INTO sleep_stages_table VALUES (1, 4, 1689379200000);
+         INSERT INTO sleep_stages_table VALUES (2, 2, 1689465600000);",
+    )
+    .unwrap();
+    let reader = HealthDataReader::new(db_path.to_str().unwrap());
+    let millis = |millis| Utc.timestamp_millis_opt(millis).unwrap();
+
+    let stages = reader.get_sleep_stages_since(ReadFrom::Beginning).unwrap();
+    assert_eq!(stages.len(), 3);
+    assert_eq!(
+        (stages[0].stage_type, stages[0].start, stages[0].end),
+        (4, millis(1689379200000), millis(1689390000000))
+    );
+    assert_eq!(stages[1].end, millis(1689408000000));
+    assert_eq!(stages[1].app_name, "Watch");
+    assert_eq!(stages[2].session_row_id, 2);
+
+    let stages = reader
+        .get_sleep_stages_since(Some(millis(1689379200000)))
+        .unwrap();
+    assert_eq!(stages.len(), 1);
+}
@@ -0,0 +1,71 @@
+use chrono::{DateTime, TimeZone, Utc};
+use home_db_importer::health_data::SleepStage;
+use home_db_importer::hypnogram::hypnogram;
+use std::time::Duration;
+
+fn time(hour: u32, minute: u32, second: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, second)
+        .unwrap()
+}
+
+fn stage(
+    session_row_id: i64,
+    stage_type: i64,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> SleepStage {
+    SleepStage {
+        session_row_id,
+        app_name: "watch".to_string(),
+        stage_type,
+        start,
+        end,
+    }
+}
+
+// Test that every minute of a stage gets a point with its value, and the session ends awake
+#[test]
+fn test_hypnogram() {
+    let stages = vec![
+        stage(1, 4, time(0, 0, 30), time(0, 3, 0)),
+        stage(1, 5, time(0, 3, 0), time(0, 5, 0)),
+    ];
+    let points = hypnogram(&stages, Duration::from_secs(60));
+
+    let series: Vec<(DateTime<Utc>, f64, &str)> = points
+        .iter()
+        .map(|point| (point.time, point.field_value, point.tags["stage"].as_str()))
+        .collect();
+    assert_eq!(
+        series,
+        vec![
+            // Aligned to the minute, from the first one into the stage
+            (time(0, 1, 0), 2.0, "LIGHT"),
+            (time(0, 2, 0), 2.0, "LIGHT"),
+            (time(0, 3, 0), 3.0, "DEEP"),
+            (time(0, 4, 0), 3.0, "DEEP"),
+            (time(0, 5, 0), 0.0, "AWAKE"),
+        ]
+    );
+    assert!(points
+        .iter()
+        .all(|point| point.measurement == "SleepHypnogram"));
+    assert_eq!(points[0].tags["app_name"], "watch");
+}
+
+// Test that each session of several ends with its own awake point
+#[test]
+fn test_hypnogram_sessions() {
+    let stages = vec![
+        stage(1, 2, time(0, 0, 0), time(0, 2, 0)),
+        stage(2, 6, time(3, 0, 0), time(3, 1, 0)),
+    ];
+    let points = hypnogram(&stages, Duration::from_secs(60));
+    let awake: Vec<DateTime<Utc>> = points
+        .iter()
+        .filter(|point| point.tags["stage"] == "AWAKE")
+        .map(|point| point.time)
+        .collect();
+    assert_eq!(awake, vec![time(0, 2, 0), time(3, 1, 0)]);
+    assert_eq!(points.len(), 5);
+}
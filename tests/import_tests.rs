@@ -0,0 +1,74 @@
+use chrono::{TimeZone, Utc};
+use clap::error::ErrorKind;
+use clap::Parser;
+use home_db_importer::import::health::HealthImportArgs;
+use home_db_importer::import::{resolve_database, ImportHooks, SingleWatermark};
+use home_db_importer::prices::{Price, PriceSeries};
+use home_db_importer::source::Source;
+use home_db_importer::state_management::ImportState;
+
+// The health import arguments on their own
+#[derive(Parser)]
+struct HealthCli {
+    #[command(flatten)]
+    args: HealthImportArgs,
+}
+
+fn price(hour: u32) -> Price {
+    Price {
+        start: Utc.with_ymd_and_hms(2024, 3, 1, hour, 0, 0).unwrap(),
+        zone: "AT".to_string(),
+        price: hour as f64,
+        unit: "EUR/MWh".to_string(),
+    }
+}
+
+// Test that --database takes precedence over --bucket
+#[test]
+fn test_resolve_database() {
+    assert_eq!(
+        resolve_database(Some("bucket".to_string()), Some("db".to_string())),
+        "db"
+    );
+    assert_eq!(resolve_database(Some("bucket".to_string()), None), "bucket");
+}
+
+// Test that a file with one watermark writes the oldest records at once and holds none back
+#[test]
+fn test_single_watermark_limit() {
+    let series = PriceSeries::new("test", vec![price(2), price(0), price(1)]);
+    let records: Vec<Price> = series
+        .records_since(&ImportState::new("test"))
+        .unwrap()
+        .collect();
+
+    let mut hooks = SingleWatermark;
+    let (taken, held_back) = hooks.limit(&series, records, 2);
+    assert_eq!(
+        taken.iter().map(|price| price.price).collect::<Vec<_>>(),
+        vec![0.0, 1.0]
+    );
+    assert!(held_back.is_empty());
+}
+
+// Test that --max-in-flight-points is refused when too small to split between the pipeline's
+// pages and channels
+#[test]
+fn test_max_in_flight_points_minimum() {
+    let parse = |points: &str| {
+        HealthCli::try_parse_from([
+            "import-health-data",
+            "--source=export.db",
+            "--org=home",
+            "--token=token",
+            "--bucket=health",
+            "--max-in-flight-points",
+            points,
+        ])
+    };
+    for points in ["0", "3"] {
+        let error = parse(points).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::ValueValidation);
+    }
+    assert_eq!(parse("4").unwrap().args.max_in_flight_points, Some(4));
+}
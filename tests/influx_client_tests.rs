@@ -1,7 +1,14 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use home_db_importer::convert::{funds_record_to_points, health_record_to_point};
 use home_db_importer::csv_parser::CsvRecord;
-use home_db_importer::influx_client::{DataPoint, InfluxClient};
-use std::collections::HashMap;
+use home_db_importer::health_data::{HealthDataType, HealthRecord};
+use home_db_importer::influx_client::{
+    diff_points, escape_flux_string, flux_delete_body, influxql_delete, parse_flux_daily_counts,
+    parse_flux_points, parse_flux_timestamps, parse_flux_value, parse_flux_values,
+    parse_flux_window_counts, parse_influxql_points, split_conflicts, DataPoint, InfluxClient,
+    MultiFieldPoint, PointDiff, Precision, BATCH_SIZE,
+};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 // Helper function to create a sample DataPoint
 fn create_sample_datapoint(measurement: &str, value: f64, timestamp: &str) -> DataPoint {
@@ -17,6 +24,7 @@ fn create_sample_datapoint(measurement: &str, value: f64, timestamp: &str) -> Da
         time: dt,
         tags,
         field_value: value,
+        text_fields: BTreeMap::new(),
     }
 }
 
@@ -59,10 +67,9 @@ fn create_sample_csv_record() -> CsvRecord {
 // Just test the conversion functionality, which is synchronous
 #[test]
 fn test_convert_funds_record() {
-    let client = InfluxClient::new("http://localhost:8086", "bucket", "token");
     let record = create_sample_csv_record();
 
-    let result = client.convert_funds_record(&record, "timestamp", "%Y-%m-%d %H:%M:%S");
+    let result = funds_record_to_points(&record, "timestamp", "%Y-%m-%d %H:%M:%S");
 
     assert!(result.is_ok());
     let data_points = result.unwrap();
@@ -101,13 +108,11 @@ fn test_convert_funds_record() {
 
 #[test]
 fn test_convert_funds_record_with_invalid_timestamp() {
-    let client = InfluxClient::new("http://localhost:8086", "bucket", "token");
-
     // Create a record with an invalid timestamp format
     let mut record = create_sample_csv_record();
     record.values[0] = "invalid-timestamp".to_string();
 
-    let result = client.convert_funds_record(&record, "timestamp", "%Y-%m-%d %H:%M:%S");
+    let result = funds_record_to_points(&record, "timestamp", "%Y-%m-%d %H:%M:%S");
 
     assert!(result.is_err());
     let error_message = result.unwrap_err().to_string();
@@ -116,13 +121,11 @@ fn test_convert_funds_record_with_invalid_timestamp() {
 
 #[test]
 fn test_convert_funds_record_with_non_numeric_values() {
-    let client = InfluxClient::new("http://localhost:8086", "bucket", "token");
-
     // Create a record with non-numeric values
     let mut record = create_sample_csv_record();
     record.values[1] = "not-a-number".to_string();
 
-    let result = client.convert_funds_record(&record, "timestamp", "%Y-%m-%d %H:%M:%S");
+    let result = funds_record_to_points(&record, "timestamp", "%Y-%m-%d %H:%M:%S");
 
     // The function should still succeed but skip the non-numeric column
     assert!(result.is_ok());
@@ -133,12 +136,37 @@ fn test_convert_funds_record_with_non_numeric_values() {
     assert!(!data_points.iter().any(|p| p.measurement == "price"));
 }
 
+// Test that health records become points tagged with their metadata and type
+#[test]
+fn test_convert_health_record() {
+    let record = HealthRecord {
+        record_type: HealthDataType::Steps,
+        timestamp: Utc.with_ymd_and_hms(2023, 7, 15, 10, 0, 0).unwrap(),
+        value: 120.0,
+        metadata: HashMap::from([("app_name".to_string(), "Fit".to_string())]),
+        row_id: Some(1),
+        zone_offset: None,
+    };
+
+    let point = health_record_to_point(&record);
+    assert_eq!(point.measurement, "Steps");
+    assert_eq!(point.time, record.timestamp);
+    assert_eq!(point.field_value, 120.0);
+    assert_eq!(point.tags.len(), 2);
+    assert_eq!(point.tags["app_name"], "Fit");
+    assert_eq!(point.tags["record_type"], "Steps");
+}
+
 // Since we can't easily run async code in unit tests without setting up a runtime,
 // we'll modify these tests to just check constructor functionality
 #[test]
 fn test_dry_run_mode() {
     // Create a client in dry-run mode
-    let client = InfluxClient::new_dry_run("http://localhost:8086", "bucket", "token");
+    let _client = InfluxClient::builder("http://localhost:8086", "bucket")
+        .token("token")
+        .dry_run(true)
+        .build()
+        .unwrap();
     // Test that the client was created with dry_run flag set
     // We can only test this indirectly in the unit tests
 
@@ -155,7 +183,11 @@ fn test_dry_run_mode() {
 #[test]
 fn test_write_points_dry_run() {
     // Create a client in dry-run mode
-    let client = InfluxClient::new_dry_run("http://localhost:8086", "bucket", "token");
+    let _client = InfluxClient::builder("http://localhost:8086", "bucket")
+        .token("token")
+        .dry_run(true)
+        .build()
+        .unwrap();
 
     // Create sample data points
     let points = [
@@ -169,3 +201,325 @@ fn test_write_points_dry_run() {
 
     // Note: Can't test async methods in unit tests without a runtime
 }
+
+#[test]
+fn test_to_line_protocol() {
+    let mut point = create_sample_datapoint("heart rate", 72.5, "2023-01-15 10:00:00");
+    point
+        .tags
+        .insert("app name".to_string(), "Fit,App".to_string());
+    point.tags.insert("empty".to_string(), String::new());
+
+    assert_eq!(
+        point.to_line_protocol(),
+        "heart\\ rate,app\\ name=Fit\\,App,tag1=value1,tag2=value2 value=72.5 1673776800000000000"
+    );
+}
+
+// Test that a point with several fields writes them all on one line, in name order
+#[test]
+fn test_multi_field_line_protocol() {
+    let point = MultiFieldPoint {
+        measurement: "BodyComposition".to_string(),
+        time: Utc.with_ymd_and_hms(2023, 1, 15, 10, 0, 0).unwrap(),
+        tags: HashMap::from([("app_name".to_string(), "Smart Scale".to_string())]),
+        fields: BTreeMap::from([
+            ("weight".to_string(), 70500.0),
+            ("body_fat".to_string(), 18.5),
+        ]),
+    };
+    assert_eq!(
+        point.to_line_protocol(),
+        "BodyComposition,app_name=Smart\\ Scale body_fat=18.5,weight=70500 1673776800000000000"
+    );
+}
+
+#[test]
+fn test_parse_flux_timestamps() {
+    // Annotated CSV with two tables, each with its own header row
+    let csv = "#datatype,string,long,dateTime:RFC3339\r\n\
+               #group,false,false,false\r\n\
+               #default,_result,,\r\n\
+               ,result,table,_time\r\n\
+               ,,0,2023-01-15T10:00:00Z\r\n\
+               ,,0,2023-01-15T10:00:01.500Z\r\n\
+               \r\n\
+               ,result,table,_time\r\n\
+               ,,1,2023-01-15T10:00:02Z\r\n";
+
+    let timestamps = parse_flux_timestamps(csv);
+    assert_eq!(timestamps.len(), 3);
+    assert!(timestamps.contains(&1673776800000));
+    assert!(timestamps.contains(&1673776801500));
+    assert!(timestamps.contains(&1673776802000));
+}
+
+#[test]
+fn test_parse_flux_timestamps_empty_response() {
+    assert!(parse_flux_timestamps("").is_empty());
+    assert!(parse_flux_timestamps("\r\n").is_empty());
+}
+
+// Test extracting a string value containing commas and quotes from a Flux response
+#[test]
+fn test_parse_flux_value() {
+    let csv = "#datatype,string,long,string\r\n\
+               #group,false,false,false\r\n\
+               #default,_result,,\r\n\
+               ,result,table,_value\r\n\
+               ,,0,\"{\"\"sources\"\":{},\"\"journal\"\":[]}\"\r\n\
+               \r\n";
+    assert_eq!(
+        parse_flux_value(csv),
+        Some(r#"{"sources":{},"journal":[]}"#.to_string())
+    );
+    assert_eq!(parse_flux_value(""), None);
+}
+
+// Test extracting per-day counts from a Flux aggregateWindow response
+#[test]
+fn test_parse_flux_daily_counts() {
+    let csv = "#datatype,string,long,dateTime:RFC3339,long\r\n\
+               #group,false,false,false,false\r\n\
+               #default,_result,,,\r\n\
+               ,result,table,_time,_value\r\n\
+               ,,0,2023-07-15T00:00:00Z,120\r\n\
+               ,,0,2023-07-16T00:00:00Z,95\r\n\
+               \r\n\
+               ,result,table,_time,_value\r\n\
+               ,,1,2023-07-16T00:00:00Z,5\r\n";
+
+    let day = |d| NaiveDate::from_ymd_opt(2023, 7, d).unwrap();
+    assert_eq!(
+        parse_flux_daily_counts(csv),
+        BTreeMap::from([(day(15), 120), (day(16), 100)])
+    );
+    assert!(parse_flux_daily_counts("").is_empty());
+}
+
+// Test extracting per-window counts from a Flux aggregateWindow response
+#[test]
+fn test_parse_flux_window_counts() {
+    let csv = "#datatype,string,long,dateTime:RFC3339,long\r\n\
+               #group,false,false,false,false\r\n\
+               #default,_result,,,\r\n\
+               ,result,table,_time,_value\r\n\
+               ,,0,2023-07-15T10:00:00Z,58\r\n\
+               ,,0,2023-07-15T11:00:00Z,60\r\n";
+
+    let hour = |h| Utc.with_ymd_and_hms(2023, 7, 15, h, 0, 0).unwrap();
+    assert_eq!(
+        parse_flux_window_counts(csv),
+        BTreeMap::from([(hour(10), 58), (hour(11), 60)])
+    );
+}
+
+// Test classifying points by whether their timestamp is already in InfluxDB
+#[test]
+fn test_diff_points() {
+    let points = vec![
+        create_sample_datapoint("HeartRate", 60.0, "2023-07-15 10:00:00"),
+        create_sample_datapoint("HeartRate", 61.0, "2023-07-15 10:01:00"),
+        create_sample_datapoint("Steps", 100.0, "2023-07-15 10:00:00"),
+    ];
+    let present = points[0].time.timestamp_millis();
+    let existing = HashMap::from([("HeartRate".to_string(), HashSet::from([present]))]);
+
+    assert_eq!(
+        diff_points(&points, &existing),
+        BTreeMap::from([
+            (
+                "HeartRate".to_string(),
+                PointDiff {
+                    new: 1,
+                    existing: 1
+                }
+            ),
+            (
+                "Steps".to_string(),
+                PointDiff {
+                    new: 1,
+                    existing: 0
+                }
+            ),
+        ])
+    );
+}
+
+// Test that points conflict with existing points of the same measurement, tags and time
+#[test]
+fn test_split_conflicts() {
+    let points = vec![
+        create_sample_datapoint("HeartRate", 60.0, "2023-07-15 10:00:00"),
+        create_sample_datapoint("HeartRate", 61.0, "2023-07-15 10:01:00"),
+        create_sample_datapoint("Steps", 100.0, "2023-07-15 10:00:00"),
+    ];
+    let existing_point = |point: &DataPoint, tags: HashMap<String, String>| MultiFieldPoint {
+        measurement: point.measurement.clone(),
+        time: point.time,
+        tags,
+        fields: BTreeMap::from([("value".to_string(), 0.0)]),
+    };
+    let mut other_tags = points[1].tags.clone();
+    other_tags.insert("tag2".to_string(), "other".to_string());
+    let existing = vec![
+        existing_point(&points[0], points[0].tags.clone()),
+        existing_point(&points[1], other_tags),
+    ];
+
+    let (new, conflicting) = split_conflicts(&points, &existing);
+    let values = |points: Vec<DataPoint>| -> Vec<f64> {
+        points.iter().map(|point| point.field_value).collect()
+    };
+    assert_eq!(values(new), vec![61.0, 100.0]);
+    assert_eq!(values(conflicting), vec![60.0]);
+}
+
+// Test the builder defaults and that invalid configurations are rejected
+#[test]
+fn test_builder() {
+    let client = InfluxClient::builder("http://localhost:8086", "bucket")
+        .build()
+        .unwrap();
+    assert_eq!(client.batch_size(), BATCH_SIZE);
+    assert_eq!(client.retention_policy(), None);
+    assert_eq!(client.connect_timeout(), None);
+
+    let client = InfluxClient::builder("http://localhost:8086", "bucket")
+        .basic_auth("user", "password")
+        .batch_size(250)
+        .build()
+        .unwrap();
+    assert_eq!(client.batch_size(), 250);
+
+    let error = InfluxClient::builder("http://localhost:8086", "bucket")
+        .batch_size(0)
+        .build()
+        .err()
+        .unwrap();
+    assert_eq!(error.to_string(), "The batch size must be at least 1");
+
+    let error = InfluxClient::builder("https://localhost:8086", "bucket")
+        .ca_certificate("does/not/exist.pem")
+        .build()
+        .err()
+        .unwrap();
+    assert!(error
+        .to_string()
+        .starts_with("Failed to read CA certificate does/not/exist.pem"));
+}
+
+// Test serializing a point with a coarser timestamp precision
+#[test]
+fn test_to_line_protocol_with_precision() {
+    let point = create_sample_datapoint("test", 42.0, "2023-01-15 10:00:00");
+
+    assert_eq!(
+        point.to_line_protocol_with_precision(Precision::Milliseconds),
+        "test,tag1=value1,tag2=value2 value=42 1673776800000"
+    );
+    assert_eq!(Precision::Microseconds.as_str(), "us");
+}
+
+// Test the delete requests that prune a measurement before a time or delete a point
+#[test]
+fn test_delete_requests() {
+    let before = Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap();
+    let body: serde_json::Value = serde_json::from_str(&flux_delete_body(
+        "HeartRate",
+        &[],
+        DateTime::UNIX_EPOCH,
+        before,
+    ))
+    .unwrap();
+    assert_eq!(body["start"], "1970-01-01T00:00:00Z");
+    assert_eq!(body["stop"], "2024-02-29T23:59:59.999999999Z");
+    assert_eq!(body["predicate"], "_measurement=\"HeartRate\"");
+
+    assert_eq!(
+        influxql_delete("HeartRate", &[], DateTime::UNIX_EPOCH, before),
+        "DELETE FROM \"HeartRate\" WHERE time >= 0ms AND time < 1709251200000ms"
+    );
+
+    // Tags limit the delete to the matching series
+    let tags = [("record_type", "Weight"), ("app_name", "it's \"scale\"")];
+    let body: serde_json::Value = serde_json::from_str(&flux_delete_body(
+        "Weight",
+        &tags,
+        DateTime::UNIX_EPOCH,
+        before,
+    ))
+    .unwrap();
+    assert_eq!(
+        body["predicate"],
+        "_measurement=\"Weight\" AND record_type=\"Weight\" AND app_name=\"it's \\\"scale\\\"\""
+    );
+    assert_eq!(
+        influxql_delete("Weight", &tags, DateTime::UNIX_EPOCH, before),
+        "DELETE FROM \"Weight\" WHERE \"record_type\" = 'Weight' AND \"app_name\" = 'it\\'s \"scale\"' \
+         AND time >= 0ms AND time < 1709251200000ms"
+    );
+}
+
+// Test that quotes and backslashes cannot end a Flux string literal early
+#[test]
+fn test_escape_flux_string() {
+    assert_eq!(escape_flux_string("HeartRate"), "HeartRate");
+    assert_eq!(escape_flux_string("a \"b\""), "a \\\"b\\\"");
+    assert_eq!(escape_flux_string("a\\\""), "a\\\\\\\"");
+}
+
+// Test reading back points from a Flux response, a row per field
+#[test]
+fn test_parse_flux_points() {
+    let csv = ",result,table,_start,_stop,_time,_value,_field,_measurement,app_name\r\n\
+               ,_result,0,1970-01-01T00:00:00Z,2024-03-02T00:00:00Z,2024-03-01T08:00:00Z,72000,weight,BodyComposition,scale\r\n\
+               ,_result,1,1970-01-01T00:00:00Z,2024-03-02T00:00:00Z,2024-03-01T08:00:00Z,18.5,body_fat,BodyComposition,scale\r\n\
+               \r\n\
+               ,result,table,_start,_stop,_time,_value,_field,_measurement\r\n\
+               ,_result,2,1970-01-01T00:00:00Z,2024-03-02T00:00:00Z,2024-03-01T09:00:00.5Z,1,value,BodyComposition\r\n";
+
+    let points = parse_flux_points(csv);
+    assert_eq!(points.len(), 2);
+    assert_eq!(
+        points[0].to_line_protocol(),
+        "BodyComposition,app_name=scale body_fat=18.5,weight=72000 1709280000000000000"
+    );
+    assert!(points[1].tags.is_empty());
+    assert_eq!(points[1].time.timestamp_millis(), 1709283600500);
+    assert!(parse_flux_points("").is_empty());
+}
+
+// Test reading back points from an InfluxQL response grouped by tags
+#[test]
+fn test_parse_influxql_points() {
+    let results: Vec<serde_json::Value> = vec![serde_json::json!({
+        "statement_id": 0,
+        "series": [{
+            "name": "HeartRate",
+            "tags": {"app_name": "watch", "unit": ""},
+            "columns": ["time", "note", "value"],
+            "values": [
+                ["2024-03-01T08:00:00Z", "resting", 62],
+                ["2024-03-01T08:00:05Z", null, 64.5]
+            ]
+        }]
+    })];
+
+    let points = parse_influxql_points(&results);
+    let lines: Vec<String> = points.iter().map(|p| p.to_line_protocol()).collect();
+    assert_eq!(
+        lines,
+        vec![
+            "HeartRate,app_name=watch value=62 1709280000000000000",
+            "HeartRate,app_name=watch value=64.5 1709280005000000000",
+        ]
+    );
+}
+
+// Test listing every value of a Flux response, such as measurement names
+#[test]
+fn test_parse_flux_values() {
+    let csv = ",result,table,_value\r\n,_result,0,HeartRate\r\n,_result,0,Steps\r\n";
+    assert_eq!(parse_flux_values(csv), vec!["HeartRate", "Steps"]);
+}
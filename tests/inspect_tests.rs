@@ -0,0 +1,72 @@
+use home_db_importer::config::Config;
+use home_db_importer::health_data::HealthDataType;
+use home_db_importer::inspect::{inspect_csv, suggest_measurement, HealthInspection};
+use std::fs;
+use tempfile::tempdir;
+
+// Test that the header rows, columns and timestamp format of a funds export are found and
+// filled into the [funds] section
+#[test]
+fn test_inspect_csv() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("My Funds 2024.csv");
+    fs::write(
+        &path,
+        "Date,Fund A,Fund B\n\
+         ,nav,nav\n\
+         01/02/2024 00:00,10,20\n\
+         02/02/2024 00:00,11,21\n",
+    )
+    .unwrap();
+    let path = path.to_str().unwrap();
+
+    let inspection = inspect_csv(path).unwrap();
+    assert_eq!(inspection.header_rows, 2);
+    assert_eq!(inspection.columns, ["Date", "Fund_A.nav", "Fund_B.nav"]);
+    assert_eq!(inspection.time_format, Some("%d/%m/%Y %H:%M"));
+    assert_eq!(inspection.measurement, "my_funds_2024");
+
+    let config = Config::parse(&inspection.config()).unwrap();
+    assert_eq!(config.funds.source.as_deref(), Some(path));
+    assert_eq!(config.funds.header_rows, Some(2));
+    assert_eq!(config.funds.time_column.as_deref(), Some("Date"));
+    assert_eq!(config.funds.time_format.as_deref(), Some("%d/%m/%Y %H:%M"));
+    assert_eq!(config.funds.measurement.as_deref(), Some("my_funds_2024"));
+
+    // Dates without a time of day mark the data rows, but import-funds cannot read them
+    fs::write(path, "Date,Fund A\n2024-01-01,10\n").unwrap();
+    let inspection = inspect_csv(path).unwrap();
+    assert_eq!(inspection.header_rows, 1);
+    assert_eq!(inspection.time_format, None);
+    assert!(inspection
+        .config()
+        .contains("No known timestamp format fits '2024-01-01'"));
+
+    fs::write(path, "Date;Fund A\n2024-01-01 00:00:00;10\n").unwrap();
+    assert!(inspect_csv(path).is_err());
+
+    assert_eq!(suggest_measurement("exports/--.csv"), "funds");
+}
+
+// Test that the data types found in an export are filled into the [health] section
+#[test]
+fn test_health_inspection_config() {
+    let inspection = HealthInspection {
+        source: "export.db".to_string(),
+        data_types: vec![
+            (HealthDataType::HeartRate, 100),
+            (HealthDataType::Steps, 20),
+        ],
+    };
+    let text = inspection.config();
+    assert!(text.contains("# Found in export.db: HeartRate (100 records), Steps (20 records)"));
+
+    let config = Config::parse(&text).unwrap();
+    assert_eq!(config.health.source.as_deref(), Some("export.db"));
+    assert_eq!(
+        config.health.data_types,
+        Some(vec!["HeartRate".to_string(), "Steps".to_string()])
+    );
+    // Only the first setting of a key is filled in
+    assert!(text.contains("# source = \"exports/\""));
+}
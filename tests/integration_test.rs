@@ -1,6 +1,9 @@
 use chrono::{DateTime, NaiveDateTime, Utc};
-use home_db_importer::influx_client::{DataPoint, InfluxClient};
-use std::collections::HashMap;
+use home_db_importer::influx_client::{DataPoint, InfluxClient, Precision};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::thread::{self, JoinHandle};
 
 // Helper function to create test DataPoints
 fn create_test_point(measurement: &str, value: f64, timestamp: &str) -> DataPoint {
@@ -15,13 +18,57 @@ fn create_test_point(measurement: &str, value: f64, timestamp: &str) -> DataPoin
         time: dt,
         tags,
         field_value: value,
+        text_fields: BTreeMap::new(),
     }
 }
 
+// Starts a fake InfluxDB that accepts a single request, answers 204 and
+// returns the request line and body
+fn spawn_fake_influx() -> (String, JoinHandle<(String, String)>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let handle = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+
+        let mut content_length = 0;
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse().unwrap();
+                }
+            }
+        }
+
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body).unwrap();
+        stream
+            .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+            .unwrap();
+
+        (request_line, String::from_utf8(body).unwrap())
+    });
+
+    (url, handle)
+}
+
 #[tokio::test]
 async fn test_dry_run_write_point() {
     // Create a client in dry-run mode
-    let client = InfluxClient::new_dry_run("http://localhost:8086", "bucket", "token");
+    let client = InfluxClient::builder("http://localhost:8086", "bucket")
+        .token("token")
+        .dry_run(true)
+        .build()
+        .unwrap();
 
     // Create a sample data point
     let data_point = create_test_point("test_measurement", 42.0, "2023-01-15 10:00:00");
@@ -35,7 +82,11 @@ async fn test_dry_run_write_point() {
 #[tokio::test]
 async fn test_dry_run_write_points() {
     // Create a client in dry-run mode
-    let client = InfluxClient::new_dry_run("http://localhost:8086", "bucket", "token");
+    let client = InfluxClient::builder("http://localhost:8086", "bucket")
+        .token("token")
+        .dry_run(true)
+        .build()
+        .unwrap();
 
     // Create sample data points
     let points = vec![
@@ -48,3 +99,137 @@ async fn test_dry_run_write_points() {
     let result = client.write_points(&points).await;
     assert!(result.is_ok());
 }
+
+#[tokio::test]
+async fn test_request_timeout_is_reported() {
+    // Accept connections but never answer, so the request can only end by timing out
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+
+    let client = InfluxClient::builder(&url, "bucket")
+        .token("token")
+        .connect_timeout(Some(std::time::Duration::from_secs(1)))
+        .request_timeout(Some(std::time::Duration::from_secs(1)))
+        .build()
+        .unwrap();
+    assert_eq!(
+        client.request_timeout(),
+        Some(std::time::Duration::from_secs(1))
+    );
+
+    let points = vec![create_test_point("test1", 42.0, "2023-01-15 10:00:00")];
+    let result = client.write_points(&points).await;
+
+    let message = result.unwrap_err().to_string();
+    assert!(message.contains("--request-timeout"));
+
+    drop(listener);
+}
+
+#[tokio::test]
+async fn test_failed_batches_are_spooled() {
+    // Bind and release a port so the connection is refused
+    let url = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    };
+
+    let temp_dir = tempfile::tempdir().unwrap();
+    let spool_path = temp_dir.path().join("spool.lp");
+    let spool_file = spool_path.to_str().unwrap();
+
+    let client = InfluxClient::builder(&url, "bucket")
+        .token("token")
+        .spool_file(spool_file)
+        .build()
+        .unwrap();
+    let points = vec![
+        create_test_point("test1", 42.0, "2023-01-15 10:00:00"),
+        create_test_point("test2", 43.0, "2023-01-15 10:01:00"),
+    ];
+
+    // The write succeeds because the unsent points were saved to the spool
+    assert!(client.write_points(&points).await.is_ok());
+    assert_eq!(client.spooled_points(), 2);
+
+    let lines = home_db_importer::spool::load_spool(spool_file).unwrap();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0], points[0].to_line_protocol());
+}
+
+#[tokio::test]
+async fn test_write_points_sends_line_protocol() {
+    let (url, server) = spawn_fake_influx();
+    let client = InfluxClient::builder(&url, "bucket")
+        .token("token")
+        .build()
+        .unwrap();
+
+    let points = vec![
+        create_test_point("test1", 42.0, "2023-01-15 10:00:00"),
+        create_test_point("test2", 43.5, "2023-01-15 10:01:00"),
+    ];
+    client.write_points(&points).await.unwrap();
+
+    let (request_line, body) = server.join().unwrap();
+    assert!(request_line.starts_with("POST /write?db=bucket&precision=ns "));
+    assert_eq!(
+        body,
+        "test1,test_tag=test_value value=42 1673776800000000000\n\
+         test2,test_tag=test_value value=43.5 1673776860000000000"
+    );
+}
+
+#[tokio::test]
+async fn test_write_points_uses_retention_policy() {
+    let (url, server) = spawn_fake_influx();
+    let client = InfluxClient::builder(&url, "homedb")
+        .token("token")
+        .retention_policy("one_year")
+        .build()
+        .unwrap();
+
+    let points = vec![create_test_point("test1", 42.0, "2023-01-15 10:00:00")];
+    client.write_points(&points).await.unwrap();
+
+    let (request_line, _) = server.join().unwrap();
+    assert!(request_line.starts_with("POST /write?db=homedb&precision=ns&rp=one_year "));
+}
+
+// Test that points are written with the configured timestamp precision
+#[tokio::test]
+async fn test_write_points_uses_precision() {
+    let (url, server) = spawn_fake_influx();
+    let client = InfluxClient::builder(&url, "homedb")
+        .token("token")
+        .precision(Precision::Seconds)
+        .build()
+        .unwrap();
+
+    let points = vec![create_test_point("test1", 42.0, "2023-01-15 10:00:00")];
+    client.write_points(&points).await.unwrap();
+
+    let (request_line, body) = server.join().unwrap();
+    assert!(request_line.starts_with("POST /write?db=homedb&precision=s "));
+    assert_eq!(body, "test1,test_tag=test_value value=42 1673776800");
+}
+
+// Test that state documents are written as an escaped string field
+#[tokio::test]
+async fn test_write_state_document() {
+    let (url, server) = spawn_fake_influx();
+    let client = InfluxClient::builder(&url, "homedb")
+        .token("token")
+        .build()
+        .unwrap();
+
+    client
+        .write_state_document("my state.json", r#"{"path":"C:\\data","note":"a \"b\""}"#)
+        .await
+        .unwrap();
+
+    let (_, body) = server.join().unwrap();
+    assert!(body.starts_with(
+        r#"importer_state,name=my\ state.json document="{\"path\":\"C:\\\\data\",\"note\":\"a \\\"b\\\"\"}" "#
+    ));
+}
@@ -0,0 +1,29 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::influx_client::{DataPoint, InfluxClient};
+use home_db_importer::interrupt::{self, Interrupted};
+use std::collections::{BTreeMap, HashMap};
+
+// Test that a requested interruption stops a write before its next batch
+// Nothing listens at the URL, so the write fails if it tries to send anything
+#[tokio::test]
+async fn test_write_stops_when_interrupted() {
+    let client = InfluxClient::builder("http://127.0.0.1:1", "home")
+        .token("token")
+        .build()
+        .unwrap();
+    let points = vec![DataPoint {
+        measurement: "steps".to_string(),
+        time: Utc.with_ymd_and_hms(2024, 1, 1, 8, 0, 0).unwrap(),
+        tags: HashMap::new(),
+        field_value: 100.0,
+        text_fields: BTreeMap::new(),
+    }];
+
+    interrupt::request();
+    assert!(interrupt::requested());
+
+    let error = client.write_points(&points).await.unwrap_err();
+    let interrupted = error.downcast_ref::<Interrupted>().unwrap();
+    assert_eq!(interrupted.written_points, 0);
+    assert_eq!(error.to_string(), "interrupted after writing 0 points");
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use chrono::{NaiveDate, TimeZone, Utc};
+use common::write_file;
+use home_db_importer::influx_client::DataPoint;
+use home_db_importer::meter::{
+    daily_consumption, MeterReader, MeterReading, DAILY_CONSUMPTION_MEASUREMENT,
+    READING_MEASUREMENT,
+};
+use home_db_importer::source::Source;
+use home_db_importer::state_management::ImportState;
+
+const LOG: &str = "\
+date,Water (m³),Gas
+2024-03-01,100.0,2000.0
+2024-03-03,104.0,
+2024-03-05 12:00:00,107.0,2010.0
+";
+
+fn day(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2024, 3, day).unwrap()
+}
+
+fn daily(points: &[DataPoint], meter: &str, day: u32) -> Option<f64> {
+    points
+        .iter()
+        .find(|point| {
+            point.measurement == DAILY_CONSUMPTION_MEASUREMENT
+                && point.tags["meter"] == meter
+                && point.time == Utc.with_ymd_and_hms(2024, 3, day, 0, 0, 0).unwrap()
+        })
+        .map(|point| point.field_value)
+}
+
+// Test that the consumption between readings is spread evenly over the days between them
+#[test]
+fn test_daily_consumption() {
+    let (_dir, path) = write_file("readings.csv", LOG);
+    let readings = MeterReader::new(&path).readings().unwrap();
+    assert_eq!(readings.len(), 5);
+
+    let days = daily_consumption(&readings);
+    let water = |d: u32| {
+        days.get(&("Water".to_string(), day(d)))
+            .map(|usage| (usage * 1e6).round() / 1e6)
+    };
+    assert_eq!(water(1), Some(2.0));
+    assert_eq!(water(2), Some(2.0));
+    // 3 m³ over 2.5 days
+    assert_eq!(water(3), Some(1.2));
+    assert_eq!(water(4), Some(1.2));
+    assert_eq!(water(5), Some(0.6));
+    assert_eq!(water(6), None);
+
+    let gas = |d: u32| days.get(&("Gas".to_string(), day(d))).copied();
+    assert_eq!(gas(1), Some(10.0 / 4.5));
+    assert_eq!(gas(5), Some(10.0 / 9.0));
+}
+
+// Test that a reading lower than the previous one, as after replacing the meter, gives no
+// consumption for that interval
+#[test]
+fn test_replaced_meter() {
+    let at = |d: u32, value: f64| MeterReading {
+        timestamp: Utc.with_ymd_and_hms(2024, 3, d, 0, 0, 0).unwrap(),
+        meter: "water".to_string(),
+        value,
+    };
+    let days = daily_consumption(&[at(1, 100.0), at(2, 5.0), at(3, 7.0)]);
+    assert_eq!(days.len(), 1);
+    assert_eq!(days[&("water".to_string(), day(2))], 2.0);
+}
+
+// Test that each reading is written along with the days since the previous one, the
+// first day including the previous interval's share
+#[test]
+fn test_records() {
+    let (_dir, path) = write_file("readings.csv", LOG);
+    let records = MeterReader::new(&path).records().unwrap();
+    assert_eq!(records.len(), 3);
+
+    // The first readings have nothing to compare with
+    assert_eq!(records[0].points.len(), 2);
+    assert!(records[0]
+        .points
+        .iter()
+        .all(|point| point.measurement == READING_MEASUREMENT));
+
+    let points = &records[2].points;
+    let reading = points
+        .iter()
+        .find(|point| point.measurement == READING_MEASUREMENT && point.tags["meter"] == "Water")
+        .unwrap();
+    assert_eq!(reading.field_value, 107.0);
+    assert_eq!(daily(points, "Water", 3), Some(1.2));
+    assert_eq!(daily(points, "Water", 5), Some(0.6));
+    assert_eq!(daily(points, "Water", 2), None);
+    assert_eq!(daily(points, "Gas", 1), Some(2.222222));
+}
+
+// Test that readings up to the watermark are skipped
+#[test]
+fn test_records_since_watermark() {
+    let (_dir, path) = write_file("readings.csv", LOG);
+    let reader = MeterReader::new(&path);
+    let mut state = ImportState::new(&path);
+    state.last_imported_timestamp = Some(Utc.with_ymd_and_hms(2024, 3, 3, 0, 0, 0).unwrap());
+
+    let stream = reader.records_since(&state).unwrap();
+    assert_eq!(stream.already_imported(), 2);
+    let records: Vec<_> = stream.collect();
+    assert_eq!(records.len(), 1);
+    assert_eq!(
+        reader.timestamp(&records[0]),
+        Some(Utc.with_ymd_and_hms(2024, 3, 5, 12, 0, 0).unwrap())
+    );
+    assert!(reader.validate().unwrap().contains("meters: Gas, Water"));
+}
@@ -0,0 +1,93 @@
+use chrono::Utc;
+use home_db_importer::metrics::{Metrics, RunResult};
+use home_db_importer::state_management::JournalEntry;
+use std::time::Duration;
+use tempfile::tempdir;
+
+// Helper function to create the journal entry of a run that wrote some records
+fn create_entry(records: usize, errors: usize) -> JournalEntry {
+    let mut entry = JournalEntry::new("import-health-data", "export.db", "http://localhost:8086");
+    entry.records.insert("HeartRate".to_string(), records);
+    entry.records.insert("Steps".to_string(), 1);
+    entry.errors = vec!["4 points were spooled".to_string(); errors];
+    entry
+}
+
+// Test counting runs, records and errors per job
+#[test]
+fn test_record_run() {
+    let mut metrics = Metrics::default();
+    metrics.add_job("funds");
+    assert_eq!(metrics.job("funds").unwrap().last_success, None);
+
+    let before = Utc::now();
+    metrics.record_run(
+        "health",
+        RunResult::Success,
+        Duration::from_millis(1500),
+        Some(&create_entry(10, 1)),
+    );
+    metrics.record_run(
+        "health",
+        RunResult::Failure,
+        Duration::from_secs(2),
+        Some(&create_entry(4, 2)),
+    );
+    metrics.record_run(
+        "health",
+        RunResult::NothingNew,
+        Duration::from_secs(1),
+        None,
+    );
+
+    let mut dry_run = create_entry(7, 0);
+    dry_run.dry_run = true;
+    metrics.record_run(
+        "health",
+        RunResult::Success,
+        Duration::from_secs(1),
+        Some(&dry_run),
+    );
+
+    let health = metrics.job("health").unwrap();
+    assert_eq!(health.successful_runs, 2);
+    assert_eq!(health.failed_runs, 1);
+    assert_eq!(health.nothing_new_runs, 1);
+    // Dry runs do not write their records
+    assert_eq!(health.records_imported, 16);
+    assert_eq!(health.errors, 3);
+    assert!(health.last_success.unwrap() >= before);
+    assert_eq!(health.last_run_duration, Some(Duration::from_secs(1)));
+}
+
+// Test rendering the metrics in the Prometheus text format
+#[test]
+fn test_render() {
+    let mut metrics = Metrics::default();
+    metrics.add_job("new \"job\"");
+    metrics.record_run(
+        "health",
+        RunResult::Failure,
+        Duration::from_millis(1500),
+        Some(&create_entry(10, 1)),
+    );
+
+    let text = metrics.render();
+    assert!(text.contains("# TYPE home_db_importer_runs_total counter\n"));
+    assert!(text.contains("home_db_importer_runs_total{job=\"health\",result=\"failure\"} 1\n"));
+    assert!(text.contains("home_db_importer_runs_total{job=\"health\",result=\"success\"} 0\n"));
+    assert!(text.contains("home_db_importer_records_imported_total{job=\"health\"} 11\n"));
+    assert!(text.contains("home_db_importer_errors_total{job=\"health\"} 1\n"));
+    assert!(text.contains("home_db_importer_last_run_duration_seconds{job=\"health\"} 1.500\n"));
+    // Label values are escaped
+    assert!(text.contains("home_db_importer_errors_total{job=\"new \\\"job\\\"\"} 0\n"));
+    // Jobs that never succeeded have no last success time
+    assert!(!text.contains("home_db_importer_last_success_timestamp_seconds{"));
+
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("home_db_importer.prom");
+    let path = path.to_str().unwrap();
+    metrics.write_textfile(path).unwrap();
+    assert_eq!(std::fs::read_to_string(path).unwrap(), text);
+    assert!(!std::path::Path::new(&format!("{}.tmp", path)).exists());
+}
@@ -0,0 +1,147 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::mqtt::{message_to_points, TopicMapping};
+use std::collections::BTreeMap;
+
+fn mapping(topic: &str, measurement: &str, fields: &[&str]) -> TopicMapping {
+    TopicMapping {
+        topic: topic.to_string(),
+        measurement: measurement.to_string(),
+        fields: fields.iter().map(|field| field.to_string()).collect(),
+        tags: BTreeMap::new(),
+        time_field: None,
+    }
+}
+
+// Test that captured levels match any value and wildcards are subscribed to as such
+#[test]
+fn test_topic_captures() {
+    let sensor = mapping("home/{room}/+/{sensor}", "temperature", &[]);
+    assert_eq!(sensor.filter(), "home/+/+/+");
+
+    let captures = sensor.captures("home/kitchen/zigbee/t1").unwrap();
+    assert_eq!(captures["room"], "kitchen");
+    assert_eq!(captures["sensor"], "t1");
+    assert_eq!(captures["topic"], "home/kitchen/zigbee/t1");
+    assert!(sensor.captures("home/kitchen/zigbee").is_none());
+    assert!(sensor.captures("home/kitchen/zigbee/t1/extra").is_none());
+    assert!(sensor.captures("garden/kitchen/zigbee/t1").is_none());
+
+    let everything = mapping("home/#", "value", &[]);
+    assert_eq!(everything.filter(), "home/#");
+    assert!(everything.captures("home/a/b/c").is_some());
+}
+
+// Test that invalid patterns and templates using unknown placeholders are rejected
+#[test]
+fn test_validate_mapping() {
+    assert!(mapping("home/{room}", "{field}", &["t"]).validate().is_ok());
+    assert!(mapping("home/#/x", "t", &[]).validate().is_err());
+    assert!(mapping("home/ro+om", "t", &[]).validate().is_err());
+    assert!(mapping("home/{room}", "{sensor}", &[]).validate().is_err());
+
+    let mut tagged = mapping("home/{room}", "t", &[]);
+    tagged.tags.insert("room".to_string(), "{room}".to_string());
+    assert!(tagged.validate().is_ok());
+    // {field} names the field of a point, which tags are shared by
+    tagged
+        .tags
+        .insert("kind".to_string(), "{field}".to_string());
+    assert!(tagged.validate().is_err());
+}
+
+// Test that each payload field present becomes a point, with templated tags and measurement
+#[test]
+fn test_json_payload_to_points() {
+    let mut sensor = mapping(
+        "zigbee2mqtt/{device}",
+        "{field}",
+        &["temperature", "humidity", "power.watts"],
+    );
+    sensor
+        .tags
+        .insert("device".to_string(), "{device}".to_string());
+    sensor
+        .tags
+        .insert("source".to_string(), "zigbee".to_string());
+    let received = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+
+    let points = sensor
+        .to_points(
+            "zigbee2mqtt/living_room",
+            br#"{"temperature": 21.5, "power": {"watts": "12"}, "linkquality": 80}"#,
+            received,
+        )
+        .unwrap();
+
+    // humidity is missing from this message and skipped
+    assert_eq!(points.len(), 2);
+    assert_eq!(points[0].measurement, "temperature");
+    assert_eq!(points[0].field_value, 21.5);
+    assert_eq!(points[0].time, received);
+    assert_eq!(points[0].tags["device"], "living_room");
+    assert_eq!(points[0].tags["source"], "zigbee");
+    assert_eq!(points[1].measurement, "watts");
+    assert_eq!(points[1].field_value, 12.0);
+
+    assert!(sensor
+        .to_points(
+            "zigbee2mqtt/living_room",
+            br#"{"linkquality": 80}"#,
+            received
+        )
+        .is_err());
+}
+
+// Test that a bare number is a payload, and that the time can come from the payload
+#[test]
+fn test_number_payload_and_time_field() {
+    let received = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+    let power = mapping("shellies/{device}/power", "power", &[]);
+    let points = power
+        .to_points("shellies/boiler/power", b"1500.5", received)
+        .unwrap();
+    assert_eq!(points.len(), 1);
+    assert_eq!(points[0].field_value, 1500.5);
+    assert!(power
+        .to_points("shellies/boiler/power", b"on", received)
+        .is_err());
+
+    let mut timed = mapping("meter", "energy", &["kwh"]);
+    timed.time_field = Some("ts".to_string());
+    let points = timed
+        .to_points("meter", br#"{"kwh": 3.2, "ts": 1714564800}"#, received)
+        .unwrap();
+    assert_eq!(
+        points[0].time,
+        Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap()
+    );
+    let points = timed
+        .to_points(
+            "meter",
+            br#"{"kwh": 3.2, "ts": "2024-05-01T14:00:00+02:00"}"#,
+            received,
+        )
+        .unwrap();
+    assert_eq!(points[0].time, received);
+    assert!(timed
+        .to_points("meter", br#"{"kwh": 3.2}"#, received)
+        .is_err());
+}
+
+// Test that a message is converted by the first matching mapping, and ignored by none
+#[test]
+fn test_message_to_points_picks_first_match() {
+    let mappings = vec![
+        mapping("home/kitchen/{sensor}", "kitchen", &[]),
+        mapping("home/{room}/{sensor}", "other", &[]),
+    ];
+    let received = Utc::now();
+
+    let points = message_to_points(&mappings, "home/kitchen/t1", b"1", received).unwrap();
+    assert_eq!(points[0].measurement, "kitchen");
+    let points = message_to_points(&mappings, "home/office/t1", b"1", received).unwrap();
+    assert_eq!(points[0].measurement, "other");
+    assert!(message_to_points(&mappings, "garden/t1", b"1", received)
+        .unwrap()
+        .is_empty());
+}
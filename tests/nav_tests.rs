@@ -0,0 +1,101 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::nav::{json_path, NavApi, NavFund};
+use serde_json::json;
+
+fn fund(isin: &str, name: Option<&str>) -> NavFund {
+    NavFund {
+        isin: isin.to_string(),
+        name: name.map(String::from),
+        currency: None,
+    }
+}
+
+// Test the supported JSONPath subset: members, quoted members and array indexes
+#[test]
+fn test_json_path() {
+    let value = json!({"data": [{"nav": 12.5, "the date": "2024-03-01"}]});
+
+    assert_eq!(
+        json_path(&value, "$.data[0].nav").unwrap(),
+        Some(&json!(12.5))
+    );
+    assert_eq!(
+        json_path(&value, "data[0]['the date']").unwrap(),
+        Some(&json!("2024-03-01"))
+    );
+    assert_eq!(json_path(&value, "$").unwrap(), Some(&value));
+    assert_eq!(json_path(&value, "$.data[1].nav").unwrap(), None);
+    assert_eq!(json_path(&value, "$.missing").unwrap(), None);
+    assert!(json_path(&value, "$.data[x]").is_err());
+    assert!(json_path(&value, "$.data[0").is_err());
+}
+
+// Test that a response becomes a point tagged like the funds CSV import, at the NAV's date
+#[test]
+fn test_to_point() {
+    let api = NavApi::new("https://api.example.com/funds/{isin}", "$.quote.nav")
+        .with_time_path("$.quote.date");
+    let fund = fund("IE00B4L5Y983", Some("Fund A"));
+    assert_eq!(
+        api.url_for(&fund),
+        "https://api.example.com/funds/IE00B4L5Y983"
+    );
+
+    let body = r#"{"quote": {"nav": "€1,234.50", "date": "2024-03-01"}}"#;
+    let point = api.to_point(&fund, body, Utc::now()).unwrap();
+    assert_eq!(point.measurement, "price");
+    assert_eq!(point.tags["fondo"], "Fund_A");
+    assert_eq!(point.field_value, 1234.5);
+    assert_eq!(
+        point.time,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()
+    );
+
+    let body = r#"{"quote": {"nav": 10.0, "date": 1709251200}}"#;
+    let point = api.to_point(&fund, body, Utc::now()).unwrap();
+    assert_eq!(
+        point.time,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()
+    );
+
+    assert!(api
+        .to_point(&fund, r#"{"quote": {"date": "2024-03-01"}}"#, Utc::now())
+        .is_err());
+    assert!(api.to_point(&fund, "not json", Utc::now()).is_err());
+}
+
+// Test that without a time path NAVs are written at midnight of the day they were fetched,
+// tagged with the ISIN when the fund has no name
+#[test]
+fn test_to_point_without_time_path() {
+    let api = NavApi::new("https://api.example.com/{isin}", "nav").with_measurement("nav");
+    let fetched = Utc.with_ymd_and_hms(2024, 3, 1, 17, 30, 0).unwrap();
+
+    let point = api
+        .to_point(&fund("LU0000000001", None), r#"{"nav": 99.1}"#, fetched)
+        .unwrap();
+    assert_eq!(point.measurement, "nav");
+    assert_eq!(point.tags["fondo"], "LU0000000001");
+    assert_eq!(
+        point.time,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()
+    );
+}
+
+// Test that NAVs are tagged with the configured tag key
+#[test]
+fn test_to_point_tag_key() {
+    let api = NavApi::new("https://api.example.com/{isin}", "nav").with_tag_key("fund");
+
+    let point = api
+        .to_point(
+            &fund("LU0000000001", Some("Fund A")),
+            r#"{"nav": 99.1}"#,
+            Utc::now(),
+        )
+        .unwrap();
+    assert_eq!(
+        point.tags,
+        [("fund".to_string(), "Fund_A".to_string())].into()
+    );
+}
@@ -0,0 +1,21 @@
+use home_db_importer::output::{success, table, warning};
+
+// Test aligning a table: names to the left, counts to the right
+#[test]
+fn test_table() {
+    let rows = vec![
+        vec!["HeartRate".to_string(), "1200".to_string()],
+        vec!["Steps".to_string(), "8".to_string()],
+    ];
+    assert_eq!(
+        table(&["Data type", "Records"], &rows),
+        "  Data type  Records\n  HeartRate     1200\n  Steps            8"
+    );
+}
+
+// Test that status lines are plain text when colors are off
+#[test]
+fn test_status_lines_without_color() {
+    assert_eq!(success("All checks passed"), "✔ All checks passed");
+    assert_eq!(warning("2 days differ"), "⚠ 2 days differ");
+}
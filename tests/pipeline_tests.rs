@@ -0,0 +1,157 @@
+use chrono::{TimeZone, Timelike, Utc};
+use home_db_importer::csv_parser::CsvParser;
+use home_db_importer::health_data::{HealthDataReader, HealthDataType, HealthRecord};
+use home_db_importer::influx_client::InfluxClient;
+use home_db_importer::pipeline::{Pipeline, PipelineSummary};
+use home_db_importer::sink::MemorySink;
+use home_db_importer::source::Source;
+use home_db_importer::spool::load_spool;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+fn steps(minute: u32, value: f64) -> HealthRecord {
+    HealthRecord {
+        record_type: HealthDataType::Steps,
+        timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 8, minute, 0).unwrap(),
+        value,
+        metadata: HashMap::new(),
+        row_id: Some(minute as i64),
+        zone_offset: None,
+    }
+}
+
+// Test that a dry run converts every record without writing anything
+#[tokio::test]
+async fn test_pipeline_dry_run() {
+    let client = InfluxClient::builder("http://127.0.0.1:1", "home")
+        .dry_run(true)
+        .batch_size(2)
+        .build()
+        .unwrap();
+    let records: Vec<HealthRecord> = (0..5).map(|minute| steps(minute, 100.0)).collect();
+
+    let summary = Pipeline::new(&client)
+        .with_capacity(1)
+        .run(Arc::new(HealthDataReader::new("unused.db")), records)
+        .await
+        .unwrap();
+    assert_eq!(
+        summary,
+        PipelineSummary {
+            records: 5,
+            failed_records: 0,
+            points: 5
+        }
+    );
+}
+
+// Test that converted points are regrouped into batches of the sink's batch size
+#[tokio::test]
+async fn test_pipeline_batches_points() {
+    let sink = MemorySink::new().with_batch_size(2);
+    let records: Vec<HealthRecord> = (0..5).map(|minute| steps(minute, 100.0)).collect();
+
+    let summary = Pipeline::new(&sink)
+        .run(Arc::new(HealthDataReader::new("unused.db")), records)
+        .await
+        .unwrap();
+    assert_eq!(summary.points, 5);
+
+    let batch_sizes: Vec<usize> = sink.requests().iter().map(Vec::len).collect();
+    assert_eq!(batch_sizes, [2, 2, 1]);
+    let minutes: Vec<i64> = sink
+        .points()
+        .iter()
+        .map(|p| p.time.timestamp() / 60 % 60)
+        .collect();
+    assert_eq!(minutes, [0, 1, 2, 3, 4]);
+}
+
+// Test that records that cannot be converted are counted and skipped
+#[tokio::test]
+async fn test_pipeline_skips_failed_conversions() {
+    let client = InfluxClient::builder("http://127.0.0.1:1", "home")
+        .dry_run(true)
+        .build()
+        .unwrap();
+    let parser = CsvParser::new("unused.csv").with_time_column("Date", "%Y-%m-%d");
+    let record = home_db_importer::csv_parser::CsvRecord {
+        header_values: vec![Vec::new(), Vec::new()],
+        column_indexes: HashMap::from([("Date".to_string(), 0)]),
+        values: vec!["not a date".to_string()],
+        time_column_index: Some(0),
+    };
+
+    let summary = Pipeline::new(&client)
+        .run(Arc::new(parser), vec![record])
+        .await
+        .unwrap();
+    assert_eq!(summary.records, 1);
+    assert_eq!(summary.failed_records, 1);
+    assert_eq!(summary.points, 0);
+}
+
+// Test that once a batch is spooled, the batches after it go straight to the spool
+#[tokio::test]
+async fn test_pipeline_spools_after_failed_write() {
+    // Bind and release a port so the connection is refused
+    let url = {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    };
+    let temp_dir = tempfile::tempdir().unwrap();
+    let spool_path = temp_dir.path().join("spool.lp");
+    let spool_file = spool_path.to_str().unwrap();
+
+    let client = InfluxClient::builder(&url, "home")
+        .batch_size(2)
+        .spool_file(spool_file)
+        .build()
+        .unwrap();
+    let records: Vec<HealthRecord> = (0..5).map(|minute| steps(minute, 100.0)).collect();
+
+    let summary = Pipeline::new(&client)
+        .run(Arc::new(HealthDataReader::new("unused.db")), records)
+        .await
+        .unwrap();
+    assert_eq!(summary.points, 5);
+    assert_eq!(client.spooled_points(), 5);
+    assert_eq!(load_spool(spool_file).unwrap().len(), 5);
+}
+
+// Test that records sorted by time are written oldest first across data types
+#[tokio::test]
+async fn test_pipeline_writes_sorted_records_in_order() {
+    let sink = MemorySink::new().with_batch_size(2);
+    let reader = Arc::new(HealthDataReader::new("unused.db"));
+    let mut heart_rate = steps(1, 60.0);
+    heart_rate.record_type = HealthDataType::HeartRate;
+    let mut records = vec![
+        steps(3, 100.0),
+        steps(0, 100.0),
+        heart_rate,
+        steps(2, 100.0),
+    ];
+    reader.sort_by_time(&mut records);
+
+    Pipeline::new(&sink)
+        .run(Arc::clone(&reader), records)
+        .await
+        .unwrap();
+
+    let written: Vec<(String, u32)> = sink
+        .requests()
+        .iter()
+        .flatten()
+        .map(|point| (point.measurement.clone(), point.time.minute()))
+        .collect();
+    assert_eq!(
+        written,
+        [
+            ("Steps".to_string(), 0),
+            ("HeartRate".to_string(), 1),
+            ("Steps".to_string(), 2),
+            ("Steps".to_string(), 3)
+        ]
+    );
+}
@@ -0,0 +1,165 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::csv_parser::CsvParser;
+use home_db_importer::influx_client::DataPoint;
+use home_db_importer::portfolio::{Holding, Portfolio, PortfolioSource};
+use home_db_importer::source::Source;
+use home_db_importer::state_management::ImportState;
+use home_db_importer::transactions::TransactionReader;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+const FUNDS: &str = "\
+timestamp,Fund A,Fund A,Fund B
+,price,performance,price
+2024-01-02 00:00:00,€10,1%,€20
+2024-01-01 00:00:00,€8,0%,
+2024-01-03 00:00:00,€11,2%,€18
+";
+
+fn holdings() -> Vec<Holding> {
+    vec![
+        Holding {
+            fund: "Fund A".to_string(),
+            quantity: 10.0,
+            cost: Some(90.0),
+        },
+        Holding {
+            fund: "Fund B".to_string(),
+            quantity: 5.0,
+            cost: None,
+        },
+    ]
+}
+
+fn nav(fund: &str, value: f64) -> DataPoint {
+    DataPoint {
+        measurement: "price".to_string(),
+        time: Utc::now(),
+        tags: HashMap::from([("fondo".to_string(), fund.to_string())]),
+        field_value: value,
+        text_fields: BTreeMap::new(),
+    }
+}
+
+fn metric(points: &[DataPoint], measurement: &str, fund: &str) -> Option<f64> {
+    points
+        .iter()
+        .find(|point| point.measurement == measurement && point.tags["fondo"] == fund)
+        .map(|point| point.field_value)
+}
+
+// Test the value, return and profit or loss of each position, and the totals once every
+// holding has a NAV
+#[test]
+fn test_metrics() {
+    let mut portfolio = Portfolio::new(&holdings(), "price");
+    let time = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+    // Fund B has no NAV yet, so there are no totals
+    let metrics = portfolio
+        .metrics(&[nav("Fund_A", 8.0), nav("Fund_C", 1.0)], time)
+        .unwrap();
+    assert_eq!(metric(&metrics, "portfolio_value", "Fund_A"), Some(80.0));
+    assert_eq!(metric(&metrics, "portfolio_pnl", "Fund_A"), Some(-10.0));
+    assert_eq!(metric(&metrics, "portfolio_return", "Fund_A"), None);
+    assert_eq!(metric(&metrics, "portfolio_value", "total"), None);
+    assert!(metrics.iter().all(|point| point.time == time));
+
+    let metrics = portfolio
+        .metrics(&[nav("Fund_A", 10.0), nav("Fund_B", 20.0)], time)
+        .unwrap();
+    assert_eq!(metric(&metrics, "portfolio_return", "Fund_A"), Some(25.0));
+    assert_eq!(metric(&metrics, "portfolio_value", "Fund_B"), Some(100.0));
+    assert_eq!(metric(&metrics, "portfolio_pnl", "Fund_B"), None);
+    assert_eq!(metric(&metrics, "portfolio_value", "total"), Some(200.0));
+    // Fund B has no cost, so neither has the portfolio
+    assert_eq!(metric(&metrics, "portfolio_pnl", "total"), None);
+
+    // Fund B keeps its last NAV
+    let metrics = portfolio.metrics(&[nav("Fund_A", 11.0)], time).unwrap();
+    assert_eq!(metric(&metrics, "portfolio_value", "total"), Some(210.0));
+    assert_eq!(metric(&metrics, "portfolio_return", "total"), Some(5.0));
+}
+
+// Test that a funds import gets the metrics of the records after the watermark, with
+// returns counted from the NAVs before it
+#[test]
+fn test_portfolio_source() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("funds.csv");
+    fs::write(&path, FUNDS).unwrap();
+    let parser = CsvParser::new(path.to_str().unwrap()).with_header_rows(2);
+    let source = PortfolioSource::new(parser, Portfolio::new(&holdings(), "price"));
+
+    let mut state = ImportState::new("funds.csv");
+    state.last_imported_timestamp = Some(Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap());
+    let stream = source.records_since(&state).unwrap();
+    assert_eq!(stream.already_imported(), 2);
+    let records: Vec<_> = stream.collect();
+    assert_eq!(records.len(), 1);
+
+    let points = source.to_points(&records[0]).unwrap();
+    // The funds' own points are written too
+    assert_eq!(metric(&points, "performance", "Fund_A"), Some(2.0));
+    assert_eq!(metric(&points, "portfolio_return", "Fund_A"), Some(10.0));
+    assert_eq!(metric(&points, "portfolio_return", "Fund_B"), Some(-10.0));
+    assert_eq!(metric(&points, "portfolio_value", "total"), Some(200.0));
+    assert_eq!(metric(&points, "portfolio_return", "total"), Some(0.0));
+    assert_eq!(metric(&points, "portfolio_pnl", "Fund_A"), Some(20.0));
+}
+
+// Test that holdings follow the transactions up to each NAV, with the profit or loss on
+// their cost basis
+#[test]
+fn test_portfolio_with_transactions() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("transactions.csv");
+    fs::write(
+        &path,
+        "Date,Fund,Type,Units,Price\n2024-01-02,Fund A,Buy,10,10\n2024-01-03,Fund A,Sell,10,11\n",
+    )
+    .unwrap();
+    let transactions = TransactionReader::new(path.to_str().unwrap())
+        .transactions()
+        .unwrap();
+    let mut portfolio = Portfolio::new(&[], "price").with_transactions(transactions);
+    let at = |day| Utc.with_ymd_and_hms(2024, 1, day, 0, 0, 0).unwrap();
+
+    // Nothing is held yet
+    let metrics = portfolio.metrics(&[nav("Fund_A", 8.0)], at(1)).unwrap();
+    assert!(metrics.is_empty());
+
+    let metrics = portfolio.metrics(&[nav("Fund_A", 10.5)], at(2)).unwrap();
+    assert_eq!(metric(&metrics, "portfolio_value", "Fund_A"), Some(105.0));
+    assert_eq!(metric(&metrics, "portfolio_pnl", "Fund_A"), Some(5.0));
+    assert_eq!(metric(&metrics, "portfolio_return", "Fund_A"), Some(31.25));
+
+    // Sold out
+    let metrics = portfolio.metrics(&[nav("Fund_A", 11.0)], at(3)).unwrap();
+    assert!(metrics.is_empty());
+}
+
+// Test that with another tag key the funds, NAVs and metrics are all tagged with it
+#[test]
+fn test_tag_key() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("funds.csv");
+    fs::write(&path, FUNDS).unwrap();
+    let parser = CsvParser::new(path.to_str().unwrap())
+        .with_header_rows(2)
+        .with_tag_key("fund");
+    let portfolio = Portfolio::new(&holdings(), "price").with_tag_key("fund");
+    let source = PortfolioSource::new(parser, portfolio);
+
+    let records: Vec<_> = source
+        .records_since(&ImportState::new("funds.csv"))
+        .unwrap()
+        .collect();
+    let points = source.to_points(&records[2]).unwrap();
+    assert!(points.iter().all(|point| !point.tags.contains_key("fondo")));
+    let value = points
+        .iter()
+        .find(|point| point.measurement == "portfolio_value" && point.tags["fund"] == "total")
+        .map(|point| point.field_value);
+    assert_eq!(value, Some(200.0));
+}
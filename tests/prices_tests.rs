@@ -0,0 +1,110 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::write_file;
+use home_db_importer::prices::{awattar_url, parse_awattar, PriceSeries, PRICE_MEASUREMENT};
+use home_db_importer::source::Source;
+use home_db_importer::state_management::ImportState;
+
+// Test that an aWATTar response becomes prices in EUR/MWh for the zone asked for
+#[test]
+fn test_parse_awattar() {
+    let body = r#"{"object":"list","data":[
+        {"start_timestamp":1709251200000,"end_timestamp":1709254800000,"marketprice":85.32,"unit":"Eur/MWh"},
+        {"start_timestamp":1709254800000,"end_timestamp":1709258400000,"marketprice":-1.5,"unit":"Eur/MWh"}
+    ],"url":"/de/v1/marketdata"}"#;
+
+    let prices = parse_awattar(body, "DE-LU").unwrap();
+    assert_eq!(prices.len(), 2);
+    assert_eq!(
+        prices[0].start,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()
+    );
+    assert_eq!(prices[1].price, -1.5);
+
+    let point = prices[0].to_point();
+    assert_eq!(point.measurement, PRICE_MEASUREMENT);
+    assert_eq!(point.tags["zone"], "DE-LU");
+    assert_eq!(point.tags["unit"], "EUR/MWh");
+    assert_eq!(point.field_value, 85.32);
+
+    assert!(parse_awattar("<html>", "AT").is_err());
+}
+
+// Test that only AT and DE-LU can be fetched from aWATTar
+#[test]
+fn test_awattar_zones() {
+    assert!(awattar_url("AT").unwrap().contains("awattar.at"));
+    assert!(awattar_url("de-lu").unwrap().contains("awattar.de"));
+    assert!(awattar_url("NO1").is_err());
+}
+
+// Test that a Nord Pool style export gives a price per zone column, with decimal commas
+// and the currency from the header, skipping empty cells
+#[test]
+fn test_nord_pool_csv() {
+    let (_dir, path) = write_file(
+        "prices.csv",
+        "\
+Delivery Start;Delivery End;NO1 (EUR);SE3 (EUR)
+2024-03-01T01:00:00+01:00;2024-03-01T02:00:00+01:00;45,10;40,05
+2024-03-01T00:00:00+01:00;2024-03-01T01:00:00+01:00;46,00;
+",
+    );
+    let series = PriceSeries::from_csv(&path, None, "%Y-%m-%d %H:%M:%S").unwrap();
+
+    let prices = series.prices();
+    assert_eq!(prices.len(), 3);
+    assert_eq!(
+        prices[0].start,
+        Utc.with_ymd_and_hms(2024, 2, 29, 23, 0, 0).unwrap()
+    );
+    assert_eq!(prices[0].zone, "NO1");
+    assert_eq!(prices[0].price, 46.0);
+    assert_eq!(prices[0].unit, "EUR/MWh");
+    assert!(prices[1..]
+        .iter()
+        .any(|price| price.zone == "SE3" && price.price == 40.05));
+    assert!(series.validate().unwrap().contains("zones: NO1, SE3"));
+}
+
+// Test that a single price column needs a zone, and that a unit column is used
+#[test]
+fn test_single_price_column() {
+    let (_dir, path) = write_file(
+        "prices.csv",
+        "\
+start_timestamp,end_timestamp,marketprice,unit
+1709251200000,1709254800000,85.32,Eur/MWh
+",
+    );
+    assert!(PriceSeries::from_csv(&path, None, "%Y-%m-%d %H:%M:%S").is_err());
+
+    let series = PriceSeries::from_csv(&path, Some("AT"), "%Y-%m-%d %H:%M:%S").unwrap();
+    assert_eq!(series.prices().len(), 1);
+    assert_eq!(series.prices()[0].zone, "AT");
+    assert_eq!(series.prices()[0].unit, "EUR/MWh");
+}
+
+// Test that prices up to the watermark are skipped and counted
+#[test]
+fn test_records_since_watermark() {
+    let (_dir, path) = write_file(
+        "prices.csv",
+        "\
+time,price
+2024-03-01 00:00:00,10
+2024-03-01 01:00:00,20
+2024-03-01 02:00:00,30
+",
+    );
+    let series = PriceSeries::from_csv(&path, Some("NL"), "%Y-%m-%d %H:%M:%S").unwrap();
+
+    let mut state = ImportState::new(&path);
+    state.last_imported_timestamp = Some(Utc.with_ymd_and_hms(2024, 3, 1, 1, 0, 0).unwrap());
+    let stream = series.records_since(&state).unwrap();
+    assert_eq!(stream.already_imported(), 2);
+    let prices: Vec<_> = stream.collect();
+    assert_eq!(prices.len(), 1);
+    assert_eq!(series.to_points(&prices[0]).unwrap()[0].field_value, 30.0);
+}
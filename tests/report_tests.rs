@@ -0,0 +1,93 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::health_data::{HealthDataReader, HealthDataType, HealthRecord};
+use home_db_importer::pipeline::Pipeline;
+use home_db_importer::report::{JsonReporter, Phase, Reporter, SkipReason};
+use home_db_importer::sink::MemorySink;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+
+/// Collects what a reporter writes, so a test can read it afterwards
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedBuffer {
+    fn lines(&self) -> Vec<serde_json::Value> {
+        let text = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        text.lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+// Test that the JSON reporter writes one object per event, tagged with its kind
+#[test]
+fn test_json_reporter_events() {
+    let buffer = SharedBuffer::default();
+    let reporter = JsonReporter::new(buffer.clone());
+
+    reporter.phase_started(Phase::SaveState, None);
+    reporter.phase_finished(Phase::Read, 12);
+    reporter.records_skipped(3, &SkipReason::AlreadyImported);
+    reporter.records_skipped(1, &SkipReason::ConversionFailed("bad value".to_string()));
+
+    let events = buffer.lines();
+    assert_eq!(events.len(), 4);
+    assert_eq!(events[0]["event"], "phase_started");
+    assert_eq!(events[0]["phase"], "save_state");
+    assert!(events[0]["items"].is_null());
+    assert!(events[0]["time"].is_string());
+    assert_eq!(events[1]["event"], "phase_finished");
+    assert_eq!(events[1]["items"], 12);
+    assert_eq!(events[2]["reason"]["kind"], "already_imported");
+    assert_eq!(events[2]["count"], 3);
+    assert_eq!(events[3]["reason"]["kind"], "conversion_failed");
+    assert_eq!(events[3]["reason"]["error"], "bad value");
+}
+
+// Test that the pipeline reports every batch it writes, with the running total
+#[tokio::test]
+async fn test_pipeline_reports_batches() {
+    let buffer = SharedBuffer::default();
+    let reporter = JsonReporter::new(buffer.clone());
+    let sink = MemorySink::new().with_batch_size(2);
+    let records: Vec<HealthRecord> = (0..5)
+        .map(|minute| HealthRecord {
+            record_type: HealthDataType::Steps,
+            timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 8, minute, 0).unwrap(),
+            value: 100.0,
+            metadata: HashMap::new(),
+            row_id: Some(minute as i64),
+            zone_offset: None,
+        })
+        .collect();
+
+    Pipeline::new(&sink)
+        .with_reporter(&reporter)
+        .run(Arc::new(HealthDataReader::new("unused.db")), records)
+        .await
+        .unwrap();
+
+    let batches: Vec<(u64, u64)> = buffer
+        .lines()
+        .iter()
+        .filter(|event| event["event"] == "batch_written")
+        .map(|event| {
+            (
+                event["points"].as_u64().unwrap(),
+                event["total"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(batches, [(2, 2), (2, 4), (1, 5)]);
+}
@@ -0,0 +1,55 @@
+use home_db_importer::run_lock::RunLock;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tempfile::tempdir;
+
+// Test that a second run cannot take the lock until the first releases it
+#[test]
+fn test_try_acquire() {
+    let dir = tempdir().unwrap();
+    let lock_path = dir.path().join("import.lock");
+    let lock_file = lock_path.to_str().unwrap();
+
+    let lock = RunLock::try_acquire(lock_file, "import-funds of 'funds.csv'")
+        .unwrap()
+        .ok()
+        .unwrap();
+
+    // The waiting run is told who holds the lock
+    let holder = RunLock::try_acquire(lock_file, "resume-spool")
+        .unwrap()
+        .err()
+        .unwrap();
+    assert!(holder.starts_with("import-funds of 'funds.csv' (pid "));
+
+    drop(lock);
+    assert!(RunLock::try_acquire(lock_file, "resume-spool")
+        .unwrap()
+        .is_ok());
+}
+
+// Test that acquiring waits for the current holder to finish
+#[test]
+fn test_acquire_waits() {
+    let dir = tempdir().unwrap();
+    let lock_path = dir.path().join("import.lock");
+    let lock_file = lock_path.to_str().unwrap().to_string();
+
+    let lock = RunLock::acquire(&lock_file, "first", |_| panic!("nothing to wait for")).unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    let waiter = thread::spawn(move || {
+        let _lock = RunLock::acquire(&lock_file, "second", |holder| {
+            sender.send(holder.to_string()).unwrap()
+        })
+        .unwrap();
+    });
+
+    let holder = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert!(holder.starts_with("first (pid "));
+    assert!(!waiter.is_finished());
+
+    drop(lock);
+    waiter.join().unwrap();
+}
@@ -0,0 +1,53 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::schedule::{parse_interval, source_version, CronSchedule};
+use std::fs;
+use std::time::Duration;
+use tempfile::tempdir;
+
+// Test parsing watch intervals
+#[test]
+fn test_parse_interval() {
+    assert_eq!(parse_interval("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_interval("30s"), Ok(Duration::from_secs(30)));
+    assert_eq!(parse_interval("15m"), Ok(Duration::from_secs(15 * 60)));
+    assert_eq!(parse_interval("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+    assert_eq!(parse_interval("1d"), Ok(Duration::from_secs(24 * 60 * 60)));
+
+    assert!(parse_interval("0s").is_err());
+    assert!(parse_interval("m").is_err());
+    assert!(parse_interval("5w").is_err());
+}
+
+// Test that appending to a source file changes its version
+#[test]
+fn test_source_version() {
+    let temp_dir = tempdir().unwrap();
+    let source_path = temp_dir.path().join("data.csv");
+    let source = source_path.to_str().unwrap();
+
+    assert_eq!(source_version(source), None);
+
+    fs::write(source, "Date,Value\n").unwrap();
+    let version = source_version(source).unwrap();
+    assert_eq!(source_version(source), Some(version));
+
+    fs::write(source, "Date,Value\n2024-01-01,1\n").unwrap();
+    assert_ne!(source_version(source), Some(version));
+}
+
+// Test finding the next run of a cron schedule
+#[test]
+fn test_cron_schedule() {
+    let schedule = CronSchedule::parse("0 3 * * *").unwrap();
+    let before = Utc.with_ymd_and_hms(2024, 6, 1, 2, 59, 0).unwrap();
+    let at = Utc.with_ymd_and_hms(2024, 6, 1, 3, 0, 0).unwrap();
+
+    assert_eq!(schedule.next_after(&before), Some(at));
+    // A run that is due now is not returned again
+    assert_eq!(
+        schedule.next_after(&at),
+        Some(Utc.with_ymd_and_hms(2024, 6, 2, 3, 0, 0).unwrap())
+    );
+
+    assert!(CronSchedule::parse("0 25 * * *").is_err());
+}
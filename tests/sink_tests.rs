@@ -0,0 +1,84 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::csv_parser::CsvParser;
+use home_db_importer::health_data::{group_by_type, HealthDataType, HealthRecord};
+use home_db_importer::sink::{MemorySink, Sink};
+use std::collections::HashMap;
+use std::fs;
+
+// Test that funds records are written with their fund tag and measurement, in batches,
+// and that writing them again stores nothing new
+#[tokio::test]
+async fn test_write_funds_records() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("funds.csv");
+    fs::write(
+        &path,
+        "timestamp,Fund A,Fund B\n,price,price\n\
+         2024-01-01 00:00:00,€1.5,2\n2024-01-02 00:00:00,1.75,n/a\n",
+    )
+    .unwrap();
+    let records = CsvParser::new(path.to_str().unwrap())
+        .with_header_rows(2)
+        .parse()
+        .unwrap();
+
+    let sink = MemorySink::new().with_batch_size(2);
+    let written = sink
+        .write_funds_records(&records, "timestamp", "%Y-%m-%d %H:%M:%S")
+        .await
+        .unwrap();
+
+    // The non-numeric value is skipped
+    assert_eq!(written, 3);
+    let batch_sizes: Vec<usize> = sink.requests().iter().map(Vec::len).collect();
+    assert_eq!(batch_sizes, [2, 1]);
+
+    let stored = sink.stored_points();
+    assert_eq!(stored.len(), 3);
+    assert!(stored.iter().all(|point| point.measurement == "price"));
+    let fund_a: Vec<f64> = stored
+        .iter()
+        .filter(|point| point.tags["fondo"] == "Fund_A")
+        .map(|point| point.field_value)
+        .collect();
+    assert_eq!(fund_a, [1.5, 1.75]);
+
+    sink.write_funds_records(&records, "timestamp", "%Y-%m-%d %H:%M:%S")
+        .await
+        .unwrap();
+    assert_eq!(sink.points().len(), 6);
+    assert_eq!(sink.stored_points().len(), 3);
+}
+
+// Test that health records become one point each, tagged with their type and metadata
+#[tokio::test]
+async fn test_write_health_records() {
+    let record = |record_type: HealthDataType, minute: u32, value: f64| HealthRecord {
+        record_type,
+        timestamp: Utc.with_ymd_and_hms(2024, 1, 1, 8, minute, 0).unwrap(),
+        value,
+        metadata: HashMap::from([("app_name".to_string(), "Fit".to_string())]),
+        row_id: None,
+        zone_offset: None,
+    };
+    let records_map = group_by_type(vec![
+        record(HealthDataType::Steps, 0, 100.0),
+        record(HealthDataType::Steps, 1, 150.0),
+        record(HealthDataType::HeartRate, 0, 62.0),
+        // A corrected value for the same minute replaces the first one
+        record(HealthDataType::Steps, 1, 160.0),
+    ]);
+
+    let sink = MemorySink::new();
+    let written = sink.write_health_records(&records_map).await.unwrap();
+    assert_eq!(written, 4);
+    assert_eq!(sink.requests().len(), 1);
+
+    let stored = sink.stored_points();
+    assert_eq!(stored.len(), 3);
+    let heart_rate = &stored[0];
+    assert_eq!(heart_rate.measurement, "HeartRate");
+    assert_eq!(heart_rate.tags["record_type"], "HeartRate");
+    assert_eq!(heart_rate.tags["app_name"], "Fit");
+    assert_eq!(stored[2].field_value, 160.0);
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::write_file;
+use home_db_importer::influx_client::DataPoint;
+use home_db_importer::solar::{SolarReader, ENERGY_MEASUREMENT, POWER_MEASUREMENT};
+use home_db_importer::source::Source;
+use home_db_importer::state_management::ImportState;
+
+fn value(points: &[DataPoint], measurement: &str, string: &str) -> Option<f64> {
+    points
+        .iter()
+        .find(|point| point.measurement == measurement && point.tags["string"] == string)
+        .map(|point| point.field_value)
+}
+
+// Test that a SolarEdge export of energy per interval is written in kWh, oldest first
+#[test]
+fn test_energy_per_interval() {
+    let (_dir, path) = write_file(
+        "inverter.csv",
+        "\
+Time,Energy (Wh)
+01/03/2024 10:15,250
+01/03/2024 10:00,125.5
+",
+    );
+    let records = SolarReader::new(&path).records().unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(
+        records[0].timestamp,
+        Utc.with_ymd_and_hms(2024, 3, 1, 10, 0, 0).unwrap()
+    );
+    assert_eq!(
+        value(&records[0].points, ENERGY_MEASUREMENT, "total"),
+        Some(0.1255)
+    );
+    assert_eq!(
+        value(&records[1].points, ENERGY_MEASUREMENT, "total"),
+        Some(0.25)
+    );
+}
+
+// Test that Growatt counters become the energy of each interval per string, that a daily
+// counter going back to zero counts as a reset, and that the counter only fills in for
+// the total when no column gives it per interval
+#[test]
+fn test_counters_with_reset() {
+    let (_dir, path) = write_file(
+        "inverter.csv",
+        "\
+Time,Epv1 today(kWh),Epv2 today(kWh),Eac total(kWh),Ppv1(W),Pac(W)
+2024-03-01 23:50:00,10.0,8.0,1000.0,0,0
+2024-03-02 00:00:00,0.0,0.0,1000.0,0,0
+2024-03-02 08:00:00,0.4,0.3,1000.7,350,600
+2024-03-02 08:05:00,0.5,0.35,1000.85,400,700
+",
+    );
+    let records = SolarReader::new(&path).records().unwrap();
+    assert_eq!(records.len(), 4);
+
+    // The first reading only gives the counters a starting point
+    assert_eq!(value(&records[0].points, ENERGY_MEASUREMENT, "1"), None);
+    assert_eq!(value(&records[0].points, POWER_MEASUREMENT, "1"), Some(0.0));
+    assert_eq!(
+        value(&records[1].points, ENERGY_MEASUREMENT, "1"),
+        Some(0.0)
+    );
+    assert_eq!(
+        value(&records[2].points, ENERGY_MEASUREMENT, "1"),
+        Some(0.4)
+    );
+    assert_eq!(
+        value(&records[2].points, ENERGY_MEASUREMENT, "2"),
+        Some(0.3)
+    );
+    assert_eq!(
+        value(&records[2].points, ENERGY_MEASUREMENT, "total"),
+        Some(0.7)
+    );
+    assert_eq!(
+        value(&records[3].points, ENERGY_MEASUREMENT, "1"),
+        Some(0.1)
+    );
+    assert_eq!(
+        value(&records[3].points, ENERGY_MEASUREMENT, "total"),
+        Some(0.15)
+    );
+    assert_eq!(
+        value(&records[3].points, POWER_MEASUREMENT, "total"),
+        Some(700.0)
+    );
+}
+
+// Test that a Fronius export with units in the row under the header is understood, and
+// that a counter is left out when the export also has the energy per interval
+#[test]
+fn test_fronius_units_row() {
+    let (_dir, path) = write_file(
+        "inverter.csv",
+        "\
+Date and time;Energy | Symo 8.2-3-M (1);Total energy | Symo 8.2-3-M (1);Voltage DC String 1 | Symo 8.2-3-M (1)
+[dd.MM.yyyy HH:mm];[Wh];[Wh];[V]
+01.03.2024 12:00;512,5;1000000;410,2
+01.03.2024 12:05;600;1000600;415
+",
+    );
+    let reader = SolarReader::new(&path);
+    let records = reader.records().unwrap();
+    assert_eq!(records.len(), 2);
+    assert_eq!(
+        records[1].timestamp,
+        Utc.with_ymd_and_hms(2024, 3, 1, 12, 5, 0).unwrap()
+    );
+    assert_eq!(
+        value(&records[0].points, ENERGY_MEASUREMENT, "total"),
+        Some(0.5125)
+    );
+    assert_eq!(value(&records[0].points, "solar_voltage", "1"), Some(410.2));
+    assert_eq!(records[1].points.len(), 2);
+    assert!(reader.validate().unwrap().contains("2 readings"));
+}
+
+// Test that readings up to the watermark are skipped, while counters still count from the
+// last reading imported
+#[test]
+fn test_records_since_watermark() {
+    let (_dir, path) = write_file(
+        "inverter.csv",
+        "\
+Time,Total Energy(kWh)
+2024-03-01 10:00:00,100.0
+2024-03-01 10:15:00,100.5
+2024-03-01 10:30:00,101.25
+",
+    );
+    let reader = SolarReader::new(&path);
+    let mut state = ImportState::new(&path);
+    state.last_imported_timestamp = Some(Utc.with_ymd_and_hms(2024, 3, 1, 10, 15, 0).unwrap());
+
+    let stream = reader.records_since(&state).unwrap();
+    assert_eq!(stream.already_imported(), 2);
+    let records: Vec<_> = stream.collect();
+    assert_eq!(records.len(), 1);
+    let points = reader.to_points(&records[0]).unwrap();
+    assert_eq!(value(&points, ENERGY_MEASUREMENT, "total"), Some(0.75));
+}
+
+// Test that an export without any column in a known unit is rejected
+#[test]
+fn test_no_known_columns() {
+    let (_dir, path) = write_file("inverter.csv", "Time,Status\n2024-03-01 10:00:00,OK\n");
+    let error = SolarReader::new(&path).records().unwrap_err();
+    assert!(error.to_string().contains("No energy"));
+}
@@ -0,0 +1,49 @@
+use home_db_importer::spool::{append_to_spool, load_spool, save_spool};
+use std::path::Path;
+use tempfile::tempdir;
+
+#[test]
+fn test_append_and_load_spool() {
+    let temp_dir = tempdir().unwrap();
+    let spool_path = temp_dir.path().join("test.lp");
+    let spool_file = spool_path.to_str().unwrap();
+
+    append_to_spool(spool_file, &["a value=1 1".to_string()]).unwrap();
+    append_to_spool(
+        spool_file,
+        &["b value=2 2".to_string(), "c value=3 3".to_string()],
+    )
+    .unwrap();
+
+    let lines = load_spool(spool_file).unwrap();
+    assert_eq!(lines, vec!["a value=1 1", "b value=2 2", "c value=3 3"]);
+}
+
+#[test]
+fn test_load_missing_spool() {
+    let temp_dir = tempdir().unwrap();
+    let spool_path = temp_dir.path().join("missing.lp");
+
+    let lines = load_spool(spool_path.to_str().unwrap()).unwrap();
+    assert!(lines.is_empty());
+}
+
+#[test]
+fn test_save_spool_keeps_remaining_lines() {
+    let temp_dir = tempdir().unwrap();
+    let spool_path = temp_dir.path().join("test.lp");
+    let spool_file = spool_path.to_str().unwrap();
+
+    append_to_spool(
+        spool_file,
+        &["a value=1 1".to_string(), "b value=2 2".to_string()],
+    )
+    .unwrap();
+    save_spool(spool_file, &["b value=2 2".to_string()]).unwrap();
+
+    assert_eq!(load_spool(spool_file).unwrap(), vec!["b value=2 2"]);
+
+    // Saving an empty spool removes the file
+    save_spool(spool_file, &[]).unwrap();
+    assert!(!Path::new(spool_file).exists());
+}
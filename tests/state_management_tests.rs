@@ -1,5 +1,9 @@
 use chrono::{TimeZone, Utc};
-use home_db_importer::state_management::{load_import_state, save_import_state, ImportState};
+use home_db_importer::state_management::{
+    load_import_state, read_state_file, record_run, rotate_state_backups, save_import_state,
+    state_backup_path, state_lock_path, ImportState, JournalEntry, RunReport, SourceFingerprint,
+    MAX_JOURNAL_ENTRIES,
+};
 use std::fs::{self, File};
 use std::io::Write;
 use std::path::Path;
@@ -108,3 +112,537 @@ fn test_load_corrupted_file() {
     assert_eq!(state.records_imported, 0);
     assert_eq!(state.last_imported_timestamp, None);
 }
+
+// Test that per-type progress is recorded and kept in sync with the global counters
+#[test]
+fn test_record_import_per_type() {
+    let mut state = ImportState::new("health.db");
+    let early = Utc.with_ymd_and_hms(2023, 7, 15, 10, 0, 0).unwrap();
+    let late = Utc.with_ymd_and_hms(2023, 7, 16, 10, 0, 0).unwrap();
+
+    state.record_import("HeartRate", late, 10);
+    state.record_import("Steps", early, 5);
+
+    assert_eq!(state.last_imported_for("HeartRate"), Some(late));
+    assert_eq!(state.last_imported_for("Steps"), Some(early));
+    assert_eq!(state.data_types["Steps"].records_imported, 5);
+    assert_eq!(state.last_imported_timestamp, Some(late));
+    assert_eq!(state.records_imported, 15);
+
+    // A type that was never imported starts from the beginning
+    assert_eq!(state.last_imported_for("Weight"), None);
+
+    // An older import doesn't move the watermark backwards
+    state.record_import("HeartRate", early, 1);
+    assert_eq!(state.last_imported_for("HeartRate"), Some(late));
+    assert_eq!(state.data_types["HeartRate"].records_imported, 11);
+}
+
+// Test tracking the last imported row_id per data type
+#[test]
+fn test_record_row_id() {
+    let timestamp = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+    let mut state = ImportState::new("health.db");
+    state.record_import("Steps", timestamp, 3);
+    assert_eq!(state.last_row_id_for("Steps"), None);
+
+    state.record_row_id("Steps", 42);
+    state.record_row_id("Steps", 17);
+    assert_eq!(state.last_row_id_for("Steps"), Some(42));
+    assert!(state.to_string().contains("(row_id 42)"));
+
+    // Moving or clearing the watermark falls back to timestamps
+    state.set_data_type_watermark("Steps", timestamp).unwrap();
+    assert_eq!(state.last_row_id_for("Steps"), None);
+    state.record_row_id("Steps", 42);
+    state.reset_all();
+    assert_eq!(state.last_row_id_for("Steps"), None);
+}
+
+// Test tracking the modification time records count as changed after
+#[test]
+fn test_record_modified() {
+    let early = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+    let late = Utc.with_ymd_and_hms(2023, 7, 16, 10, 30, 0).unwrap();
+    let mut state = ImportState::new("health.db");
+    assert_eq!(state.last_modified_for("Steps"), None);
+
+    state.record_modified("Steps", late);
+    state.record_modified("Steps", early);
+    assert_eq!(state.last_modified_for("Steps"), Some(late));
+
+    // Resetting a data type reads it all again, changed or not
+    state.reset_data_type("Steps").unwrap();
+    assert_eq!(state.last_modified_for("Steps"), None);
+}
+
+// Test checking watermarks against the newest data in the source
+#[test]
+fn test_check_watermark() {
+    let timestamp = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+    let future = Utc::now() + chrono::Duration::days(365);
+    let mut state = ImportState::new("health.db");
+    state.record_import("Steps", timestamp, 3);
+    state.record_import("Weight", future, 1);
+
+    assert_eq!(
+        state.check_watermark(Some("Steps"), Some(timestamp), None),
+        None
+    );
+    assert!(state
+        .check_watermark(
+            Some("Steps"),
+            Some(timestamp - chrono::Duration::days(1)),
+            None
+        )
+        .unwrap()
+        .contains("newer than the newest record"));
+    assert!(state
+        .check_watermark(Some("Weight"), None, None)
+        .unwrap()
+        .contains("in the future"));
+    assert!(state.check_watermark(None, None, None).is_some());
+
+    // Row ids take precedence over timestamps
+    state.record_row_id("Weight", 10);
+    assert_eq!(state.check_watermark(Some("Weight"), None, Some(10)), None);
+    assert!(state
+        .check_watermark(Some("Weight"), None, Some(5))
+        .is_some());
+
+    state.clear_watermark(Some("Weight"));
+    assert_eq!(state.last_imported_for("Weight"), None);
+    assert_eq!(state.last_row_id_for("Weight"), None);
+    assert_eq!(state.last_imported_for("Steps"), Some(timestamp));
+}
+
+// Test that state files without per-type entries still load and use the global timestamp
+#[test]
+fn test_load_legacy_state_without_data_types() {
+    let temp_dir = tempdir().unwrap();
+    let state_file_path = temp_dir.path().join("legacy_state.json");
+    let state_file = state_file_path.to_str().unwrap();
+
+    let mut file = File::create(state_file).unwrap();
+    file.write_all(
+        br#"{"last_imported_timestamp":"2023-07-15T10:30:00Z","source_file":"health.db","records_imported":7}"#,
+    )
+    .unwrap();
+
+    let state = load_import_state(state_file, "health.db");
+    let timestamp = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+
+    assert_eq!(state.records_imported, 7);
+    assert!(state.data_types.is_empty());
+    assert_eq!(state.last_imported_for("HeartRate"), Some(timestamp));
+}
+
+// Test that per-type entries survive a save/load round trip
+#[test]
+fn test_save_load_per_type_state() {
+    let temp_dir = tempdir().unwrap();
+    let state_file_path = temp_dir.path().join("per_type_state.json");
+    let state_file = state_file_path.to_str().unwrap();
+
+    let timestamp = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+    let mut state = ImportState::new("health.db");
+    state.record_import("Weight", timestamp, 3);
+    save_import_state(&state, state_file).unwrap();
+
+    let loaded_state = load_import_state(state_file, "health.db");
+    assert_eq!(loaded_state, state);
+}
+
+// Test that reading a missing or corrupted state file reports an error
+#[test]
+fn test_read_state_file_errors() {
+    let temp_dir = tempdir().unwrap();
+    let state_file_path = temp_dir.path().join("state.json");
+    let state_file = state_file_path.to_str().unwrap();
+
+    assert!(read_state_file(state_file).is_err());
+
+    fs::write(state_file, "{this is not valid json}").unwrap();
+    assert!(read_state_file(state_file).is_err());
+}
+
+// Test that validation reports inconsistent per-type entries
+#[test]
+fn test_validate_state() {
+    let temp_dir = tempdir().unwrap();
+    let source_path = temp_dir.path().join("health.db");
+    fs::write(&source_path, "").unwrap();
+
+    let timestamp = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+    let mut state = ImportState::new(source_path.to_str().unwrap());
+    state.record_import("HeartRate", timestamp, 3);
+    assert!(state.validate().is_empty());
+
+    // Per-type entries newer than the global timestamp or with more records are inconsistent
+    state.last_imported_timestamp = Some(timestamp - chrono::Duration::days(1));
+    state.records_imported = 1;
+    assert_eq!(state.validate().len(), 2);
+
+    let missing_source = ImportState::new("does_not_exist.db");
+    assert_eq!(missing_source.validate().len(), 1);
+}
+
+// Test resetting a single data type and the whole state
+#[test]
+fn test_reset_state() {
+    let timestamp = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+    let mut state = ImportState::new("health.db");
+
+    // A state without per-type entries can only be reset as a whole
+    state.last_imported_timestamp = Some(timestamp);
+    assert!(state.reset_data_type("HeartRate").is_err());
+
+    state.record_import("HeartRate", timestamp, 3);
+    state.record_import("Steps", timestamp, 4);
+
+    assert_eq!(state.reset_data_type("heartrate").unwrap(), Some(timestamp));
+    assert_eq!(state.last_imported_for("HeartRate"), None);
+    assert_eq!(state.last_imported_for("Steps"), Some(timestamp));
+    assert_eq!(state.data_types["HeartRate"].records_imported, 3);
+
+    assert_eq!(state.reset_all(), Some(timestamp));
+    assert_eq!(state.last_imported_for("Steps"), None);
+    assert_eq!(state.records_imported, 7);
+}
+
+// Test moving watermarks manually
+#[test]
+fn test_set_watermarks() {
+    let timestamp = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+    let earlier = Utc.with_ymd_and_hms(2023, 6, 1, 0, 0, 0).unwrap();
+    let later = Utc.with_ymd_and_hms(2023, 8, 1, 0, 0, 0).unwrap();
+
+    let mut state = ImportState::new("health.db");
+    state.record_import("HeartRate", timestamp, 3);
+    state.record_import("Steps", timestamp, 4);
+
+    // Move one type backward to re-import a range
+    assert_eq!(
+        state.set_data_type_watermark("HeartRate", earlier).unwrap(),
+        Some(timestamp)
+    );
+    assert_eq!(state.last_imported_for("HeartRate"), Some(earlier));
+    assert_eq!(state.last_imported_for("Steps"), Some(timestamp));
+    assert_eq!(state.last_imported_timestamp, Some(timestamp));
+
+    // Moving a type forward keeps the global timestamp consistent
+    state.set_data_type_watermark("Steps", later).unwrap();
+    assert_eq!(state.last_imported_timestamp, Some(later));
+    assert!(state.validate().iter().all(|p| !p.contains("newer")));
+
+    state.set_all_watermarks(earlier);
+    assert_eq!(state.last_imported_for("Steps"), Some(earlier));
+    assert_eq!(state.last_imported_timestamp, Some(earlier));
+}
+
+// Test detecting a replaced source file through its fingerprint
+#[test]
+fn test_source_fingerprint() {
+    let temp_dir = tempdir().unwrap();
+    let source_path = temp_dir.path().join("funds.csv");
+    let source = source_path.to_str().unwrap();
+    fs::write(source, "timestamp,value\n2023-01-01 00:00:00,1\n").unwrap();
+
+    // A state without a fingerprint never reports a replaced file
+    let mut state = ImportState::new(source);
+    assert!(!state.source_replaced(source).unwrap());
+
+    let fingerprint = SourceFingerprint::compute(source).unwrap();
+    assert_eq!(fingerprint.size, 38);
+    assert_eq!(fingerprint.sha256.len(), 64);
+    state.source_fingerprint = Some(fingerprint);
+    assert!(!state.source_replaced(source).unwrap());
+
+    // Appending new rows is a normal incremental update
+    let mut file = fs::OpenOptions::new().append(true).open(source).unwrap();
+    writeln!(file, "2023-01-02 00:00:00,2").unwrap();
+    assert!(!state.source_replaced(source).unwrap());
+
+    // Different contents mean a different export
+    fs::write(source, "timestamp,value\n2024-01-01 00:00:00,5\n").unwrap();
+    assert!(state.source_replaced(source).unwrap());
+
+    // The fingerprint survives a save and load
+    let state_path = temp_dir.path().join("state.json");
+    let state_file = state_path.to_str().unwrap();
+    save_import_state(&state, state_file).unwrap();
+    assert_eq!(load_import_state(state_file, source), state);
+}
+
+// Test tracking several sources in one state file
+#[test]
+fn test_multiple_sources_in_one_state_file() {
+    let temp_dir = tempdir().unwrap();
+    let state_file_path = temp_dir.path().join("state.json");
+    let state_file = state_file_path.to_str().unwrap();
+    let timestamp = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+
+    let mut funds = ImportState::new("funds.csv");
+    funds.record_import("funds", timestamp, 5);
+    save_import_state(&funds, state_file).unwrap();
+
+    let mut health = ImportState::new("health.db");
+    health.record_import("Steps", timestamp, 9);
+    save_import_state(&health, state_file).unwrap();
+
+    // Saving one source keeps the others
+    assert_eq!(load_import_state(state_file, "funds.csv"), funds);
+    assert_eq!(load_import_state(state_file, "health.db"), health);
+    // The file is replaced in one step, leaving no temporary file behind
+    assert!(!Path::new(&format!("{}.tmp", state_file)).exists());
+
+    let mut states = read_state_file(state_file).unwrap();
+    assert_eq!(states.sources.len(), 2);
+    assert!(states.select(None).is_err());
+    assert!(states.select(Some("other.csv")).is_err());
+    assert_eq!(states.select(Some("health.db")).unwrap(), &health);
+}
+
+// Test that a renamed source file keeps its state through its fingerprint
+#[test]
+fn test_renamed_source_matches_fingerprint() {
+    let temp_dir = tempdir().unwrap();
+    let state_file_path = temp_dir.path().join("state.json");
+    let state_file = state_file_path.to_str().unwrap();
+    let old_path = temp_dir.path().join("export-june.csv");
+    let new_path = temp_dir.path().join("export-july.csv");
+    let old_source = old_path.to_str().unwrap();
+    let new_source = new_path.to_str().unwrap();
+
+    fs::write(old_source, "timestamp,value\n2023-06-01 00:00:00,1\n").unwrap();
+    let timestamp = Utc.with_ymd_and_hms(2023, 6, 1, 0, 0, 0).unwrap();
+    let mut state = ImportState::new(old_source);
+    state.record_import("funds", timestamp, 1);
+    state.source_fingerprint = Some(SourceFingerprint::compute(old_source).unwrap());
+    save_import_state(&state, state_file).unwrap();
+
+    // The new export starts with the old one
+    fs::rename(old_source, new_source).unwrap();
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(new_source)
+        .unwrap();
+    writeln!(file, "2023-07-01 00:00:00,2").unwrap();
+
+    let mut loaded_state = load_import_state(state_file, new_source);
+    assert_eq!(loaded_state.source_file, new_source);
+    assert_eq!(loaded_state.last_imported_for("funds"), Some(timestamp));
+
+    // Saving moves the entry to the new path instead of duplicating it
+    loaded_state.source_fingerprint = Some(SourceFingerprint::compute(new_source).unwrap());
+    save_import_state(&loaded_state, state_file).unwrap();
+    let states = read_state_file(state_file).unwrap();
+    assert_eq!(states.sources.keys().collect::<Vec<_>>(), vec![new_source]);
+}
+
+// Test that sources still present keep their state when a new file starts with them
+#[test]
+fn test_present_source_keeps_its_state() {
+    let temp_dir = tempdir().unwrap();
+    let state_file_path = temp_dir.path().join("state.json");
+    let state_file = state_file_path.to_str().unwrap();
+    let older_path = temp_dir.path().join("cumulative-june.csv");
+    let newer_path = temp_dir.path().join("cumulative-july.csv");
+    let empty_path = temp_dir.path().join("empty.csv");
+    let older_source = older_path.to_str().unwrap();
+    let newer_source = newer_path.to_str().unwrap();
+    let empty_source = empty_path.to_str().unwrap();
+
+    // Cumulative exports kept side by side: the newer one is an appended copy
+    fs::write(older_source, "timestamp,value\n2023-06-01 00:00:00,1\n").unwrap();
+    fs::write(
+        newer_source,
+        "timestamp,value\n2023-06-01 00:00:00,1\n2023-07-01 00:00:00,2\n",
+    )
+    .unwrap();
+    let timestamp = Utc.with_ymd_and_hms(2023, 6, 1, 0, 0, 0).unwrap();
+    let mut state = ImportState::new(older_source);
+    state.record_import("funds", timestamp, 1);
+    state.source_fingerprint = Some(SourceFingerprint::compute(older_source).unwrap());
+    save_import_state(&state, state_file).unwrap();
+
+    let newer_state = load_import_state(state_file, newer_source);
+    assert_eq!(newer_state.last_imported_for("funds"), None);
+    save_import_state(&newer_state, state_file).unwrap();
+    assert_eq!(load_import_state(state_file, older_source), state);
+    assert_eq!(read_state_file(state_file).unwrap().sources.len(), 2);
+
+    // The fingerprint of an empty file matches nothing, even once the file is gone
+    fs::write(empty_source, "").unwrap();
+    let mut empty_state = ImportState::new(empty_source);
+    empty_state.record_import("funds", timestamp, 0);
+    empty_state.source_fingerprint = Some(SourceFingerprint::compute(empty_source).unwrap());
+    save_import_state(&empty_state, state_file).unwrap();
+    fs::remove_file(empty_source).unwrap();
+    let other_path = temp_dir.path().join("other.csv");
+    let other_source = other_path.to_str().unwrap();
+    fs::write(other_source, "timestamp,value\n").unwrap();
+    assert_eq!(
+        load_import_state(state_file, other_source).last_imported_for("funds"),
+        None
+    );
+}
+
+// Test recording runs in the journal of a state file
+#[test]
+fn test_record_run_journal() {
+    let temp_dir = tempdir().unwrap();
+    let state_file_path = temp_dir.path().join("state.json");
+    let state_file = state_file_path.to_str().unwrap();
+    let timestamp = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+
+    let mut state = ImportState::new("health.db");
+    state.record_import("Steps", timestamp, 9);
+    save_import_state(&state, state_file).unwrap();
+
+    let mut entry = JournalEntry::new("import-health-data", "health.db", "http://localhost:8086");
+    entry.records.insert("Steps".to_string(), 9);
+    entry.records.insert("HeartRate".to_string(), 3);
+    entry.errors.push("Error writing to InfluxDB".to_string());
+    record_run(state_file, entry).unwrap();
+
+    // The journal is stored next to the import states
+    let states = read_state_file(state_file).unwrap();
+    assert_eq!(states.journal.len(), 1);
+    assert_eq!(states.journal[0].total_records(), 12);
+    assert!(states.journal[0].to_string().contains("FAILED"));
+    assert_eq!(load_import_state(state_file, "health.db"), state);
+
+    // Saving the import state keeps the journal
+    save_import_state(&state, state_file).unwrap();
+    assert_eq!(read_state_file(state_file).unwrap().journal.len(), 1);
+
+    // Only the most recent runs are kept
+    for _ in 0..MAX_JOURNAL_ENTRIES {
+        record_run(
+            state_file,
+            JournalEntry::new("import-funds", "funds.csv", ""),
+        )
+        .unwrap();
+    }
+    let states = read_state_file(state_file).unwrap();
+    assert_eq!(states.journal.len(), MAX_JOURNAL_ENTRIES);
+    assert!(states
+        .journal
+        .iter()
+        .all(|entry| entry.command == "import-funds"));
+}
+
+// Test that imports saving to the same state file at once keep each other's sources
+#[test]
+fn test_concurrent_saves_keep_every_source() {
+    let temp_dir = tempdir().unwrap();
+    let state_file_path = temp_dir.path().join("state.json");
+    let state_file = state_file_path.to_str().unwrap().to_string();
+
+    let threads: Vec<_> = (0..8)
+        .map(|n| {
+            let state_file = state_file.clone();
+            std::thread::spawn(move || {
+                for _ in 0..5 {
+                    save_import_state(&ImportState::new(&format!("source{}.csv", n)), &state_file)
+                        .unwrap();
+                    record_run(&state_file, JournalEntry::new("import-funds", "", "")).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    let states = read_state_file(&state_file).unwrap();
+    assert_eq!(states.sources.len(), 8);
+    assert_eq!(states.journal.len(), 40.min(MAX_JOURNAL_ENTRIES));
+    assert!(Path::new(&state_lock_path(&state_file)).exists());
+}
+
+// Test that a state file that cannot be parsed is not saved over
+#[test]
+fn test_unreadable_state_file_is_kept() {
+    let temp_dir = tempdir().unwrap();
+    let state_file_path = temp_dir.path().join("state.json");
+    let state_file = state_file_path.to_str().unwrap();
+    fs::write(state_file, "{\"sources\": {").unwrap();
+
+    let state = ImportState::new("health.db");
+    assert!(save_import_state(&state, state_file).is_err());
+    let entry = JournalEntry::new("import-health-data", "health.db", "http://localhost:8086");
+    assert!(record_run(state_file, entry).is_err());
+    assert_eq!(fs::read_to_string(state_file).unwrap(), "{\"sources\": {");
+}
+
+// Test keeping rotated copies of a state file
+#[test]
+fn test_rotate_state_backups() {
+    let temp_dir = tempdir().unwrap();
+    let state_file_path = temp_dir.path().join("state.json");
+    let state_file = state_file_path.to_str().unwrap();
+
+    // Nothing to back up before the first save
+    rotate_state_backups(state_file, 2).unwrap();
+    assert!(!Path::new(&state_backup_path(state_file, 1)).exists());
+
+    for version in 1..=3 {
+        fs::write(state_file, format!("version {}", version)).unwrap();
+        rotate_state_backups(state_file, 2).unwrap();
+    }
+
+    // Only the two most recent copies are kept, newest first
+    assert_eq!(
+        fs::read_to_string(state_backup_path(state_file, 1)).unwrap(),
+        "version 3"
+    );
+    assert_eq!(
+        fs::read_to_string(state_backup_path(state_file, 2)).unwrap(),
+        "version 2"
+    );
+    assert!(!Path::new(&state_backup_path(state_file, 3)).exists());
+
+    // Backups can be disabled
+    fs::remove_file(state_backup_path(state_file, 1)).unwrap();
+    rotate_state_backups(state_file, 0).unwrap();
+    assert!(!Path::new(&state_backup_path(state_file, 1)).exists());
+}
+
+// Test writing a run report with the state before and after the run
+#[test]
+fn test_run_report() {
+    let dir = tempdir().unwrap();
+    let report_path = dir.path().join("report.json");
+    let report_file = report_path.to_str().unwrap();
+
+    let mut entry = JournalEntry::new("import-health-data", "health.db", "test");
+    entry.finished_at = Some(entry.started_at + chrono::Duration::milliseconds(2500));
+    entry.records.insert("Steps".to_string(), 9);
+    entry.skipped.insert("over --limit".to_string(), 3);
+
+    let before = ImportState::new("health.db");
+    let mut after = before.clone();
+    after.record_import(
+        "Steps",
+        Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap(),
+        9,
+    );
+
+    let report = RunReport::new(entry.clone(), Some(before), Some(after.clone()));
+    assert_eq!(report.duration_seconds, Some(2.5));
+    report.write(report_file).unwrap();
+
+    let json: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(report_file).unwrap()).unwrap();
+    assert_eq!(json["records"]["Steps"], 9);
+    assert_eq!(json["skipped"]["over --limit"], 3);
+    assert_eq!(json["state_after"]["records_imported"], 9);
+    assert!(!Path::new(&format!("{}.tmp", report_file)).exists());
+
+    // The journal entry can be read back on its own, as --watch and the daemon do
+    let read_back: JournalEntry =
+        serde_json::from_str(&fs::read_to_string(report_file).unwrap()).unwrap();
+    assert_eq!(read_back, entry);
+}
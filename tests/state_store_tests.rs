@@ -0,0 +1,288 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::influx_client::MAX_STRING_FIELD_BYTES;
+use home_db_importer::remote::S3Config;
+use home_db_importer::state_management::{
+    ImportState, JournalEntry, StateFile, MAX_JOURNAL_ENTRIES,
+};
+use home_db_importer::state_store::{influxdb_state_document, StateBackend, StateStore};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// Helper that serves a single JSON document like a WebDAV share: GET returns it with its
+// version as ETag (404 until the first PUT) and PUT replaces it, unless its If-Match or
+// If-None-Match header no longer matches (412). Returns the URL, the received
+// Authorization headers, and a document that replaces the stored one right after the next
+// GET, as if another machine saved its state in the meantime
+#[allow(clippy::type_complexity)]
+fn spawn_fake_webdav() -> (String, Arc<Mutex<Vec<String>>>, Arc<Mutex<Option<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/state.json", listener.local_addr().unwrap());
+    let auth_headers = Arc::new(Mutex::new(Vec::new()));
+    let received = auth_headers.clone();
+    let replacement = Arc::new(Mutex::new(None::<String>));
+    let replace_after_get = replacement.clone();
+
+    thread::spawn(move || {
+        let mut document: Option<String> = None;
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+
+            let mut content_length = 0;
+            let mut if_match = None;
+            let mut if_none_match = None;
+            loop {
+                let mut header = String::new();
+                reader.read_line(&mut header).unwrap();
+                if header.trim().is_empty() {
+                    break;
+                }
+                if let Some((name, value)) = header.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                    if name.eq_ignore_ascii_case("authorization") {
+                        received.lock().unwrap().push(value.trim().to_string());
+                    }
+                    if name.eq_ignore_ascii_case("if-match") {
+                        if_match = Some(value.trim().to_string());
+                    }
+                    if name.eq_ignore_ascii_case("if-none-match") {
+                        if_none_match = Some(value.trim().to_string());
+                    }
+                }
+            }
+
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+
+            let etag = document.as_ref().map(|doc| format!("\"{}\"", doc.len()));
+            let response = if request_line.starts_with("PUT") {
+                let matches = match (&if_match, &if_none_match) {
+                    (Some(if_match), _) => etag.as_ref() == Some(if_match),
+                    (None, Some(_)) => etag.is_none(),
+                    (None, None) => true,
+                };
+                if matches {
+                    document = Some(String::from_utf8(body).unwrap());
+                    "HTTP/1.1 201 Created\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+                        .to_string()
+                } else {
+                    "HTTP/1.1 412 Precondition Failed\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+                        .to_string()
+                }
+            } else {
+                match &document {
+                    Some(doc) => format!(
+                        "HTTP/1.1 200 OK\r\nETag: {}\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+                        etag.unwrap(),
+                        doc.len(),
+                        doc
+                    ),
+                    None => {
+                        "HTTP/1.1 404 Not Found\r\nConnection: close\r\nContent-Length: 0\r\n\r\n"
+                            .to_string()
+                    }
+                }
+            };
+            stream.write_all(response.as_bytes()).unwrap();
+            if request_line.starts_with("GET") {
+                if let Some(replacement) = replace_after_get.lock().unwrap().take() {
+                    document = Some(replacement);
+                }
+            }
+        }
+    });
+
+    (url, auth_headers, replacement)
+}
+
+// Test parsing the --state-backend values
+#[test]
+fn test_parse_state_backend() {
+    assert_eq!("file".parse(), Ok(StateBackend::File));
+    assert_eq!("influxdb".parse(), Ok(StateBackend::InfluxDb));
+    assert_eq!(
+        "https://dav.example.com/state.json".parse(),
+        Ok(StateBackend::Http(
+            "https://dav.example.com/state.json".to_string()
+        ))
+    );
+    assert_eq!(
+        "s3://state/importer/health.json".parse(),
+        Ok(StateBackend::S3(
+            "s3://state/importer/health.json".to_string()
+        ))
+    );
+    assert!("s3".parse::<StateBackend>().is_err());
+    assert!("s3://bucket-only".parse::<StateBackend>().is_err());
+}
+
+// Test keeping state and the journal on an HTTP server
+#[tokio::test]
+async fn test_http_state_round_trip() {
+    let (url, auth_headers, _) = spawn_fake_webdav();
+    let url = url.replace("http://", "http://importer:secret@");
+    let store = StateStore::http(&url, None, None).unwrap();
+    assert!(!store.describe().contains("secret"));
+
+    // Nothing stored yet
+    let mut state = store.load_import_state("health.db").await.unwrap();
+    assert_eq!(state, ImportState::new("health.db"));
+
+    let timestamp = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+    state.record_import("Steps", timestamp, 9);
+    store.save_import_state(&state).await.unwrap();
+    store
+        .record_run(JournalEntry::new("import-health-data", "health.db", "test"))
+        .await
+        .unwrap();
+
+    // Another machine sees the same state
+    let other_store = StateStore::http(&url, None, None).unwrap();
+    assert_eq!(
+        other_store.load_import_state("health.db").await.unwrap(),
+        state
+    );
+
+    // Credentials from the URL are sent with basic authentication
+    let auth_headers = auth_headers.lock().unwrap();
+    assert!(!auth_headers.is_empty());
+    assert!(auth_headers
+        .iter()
+        .all(|header| header == "Basic aW1wb3J0ZXI6c2VjcmV0"));
+}
+
+// Test that an update made while another machine changed the state keeps both changes
+#[tokio::test]
+async fn test_http_state_conflict_is_retried() {
+    let (url, _, replace_after_get) = spawn_fake_webdav();
+    let store = StateStore::http(&url, None, None).unwrap();
+    let timestamp = Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap();
+
+    let mut funds = ImportState::new("funds.csv");
+    funds.record_import("funds", timestamp, 5);
+    store.save_import_state(&funds).await.unwrap();
+
+    // The other machine saves its state between this one's GET and PUT
+    let mut health = ImportState::new("health.db");
+    health.record_import("Steps", timestamp, 9);
+    let mut other_states = StateFile::default();
+    other_states.insert(funds.clone());
+    other_states.insert(health.clone());
+    *replace_after_get.lock().unwrap() = Some(serde_json::to_string_pretty(&other_states).unwrap());
+
+    funds.record_import("funds", timestamp, 6);
+    store.save_import_state(&funds).await.unwrap();
+    assert!(replace_after_get.lock().unwrap().is_none());
+    assert_eq!(store.load_import_state("health.db").await.unwrap(), health);
+    assert_eq!(store.load_import_state("funds.csv").await.unwrap(), funds);
+}
+
+// Test keeping state in an S3 object, on an S3-compatible service addressed by path
+#[tokio::test]
+async fn test_s3_state_round_trip() {
+    let (url, auth_headers, _) = spawn_fake_webdav();
+    let endpoint = url.trim_end_matches("/state.json").to_string();
+    let s3 = S3Config {
+        endpoint: Some(endpoint),
+        region: "eu-west-1".to_string(),
+        access_key_id: Some("AKIDEXAMPLE".to_string()),
+        secret_access_key: Some("secret".to_string()),
+        session_token: None,
+    };
+    // The fake server serves any path, so the bucket and key only show in describe
+    let store = StateStore::s3("s3://state/importer.json", s3, None, None).unwrap();
+    assert_eq!(store.describe(), "s3://state/importer.json");
+
+    let mut state = ImportState::new("health.db");
+    state.record_import(
+        "Steps",
+        Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap(),
+        9,
+    );
+    store.save_import_state(&state).await.unwrap();
+    assert_eq!(store.load_import_state("health.db").await.unwrap(), state);
+    assert!(auth_headers
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|header| header.starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/")));
+}
+
+// Test that an unreachable state server is an error instead of a fresh state
+#[tokio::test]
+async fn test_unreachable_http_state_is_an_error() {
+    // Bind and drop a listener to get a port nothing listens on
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let store =
+        StateStore::http(&format!("http://127.0.0.1:{}/state.json", port), None, None).unwrap();
+
+    assert!(store.load_import_state("health.db").await.is_err());
+}
+
+// Test that a store remembers the state it first loaded and last saved, for run reports
+#[tokio::test]
+async fn test_loaded_and_saved_state() {
+    let dir = tempfile::tempdir().unwrap();
+    let state_path = dir.path().join("state.json");
+    let store = StateStore::file(state_path.to_str().unwrap());
+    assert_eq!(store.loaded_state(), None);
+
+    let mut state = store.load_import_state("health.db").await.unwrap();
+    let before = state.clone();
+    assert_eq!(store.saved_state(), None);
+
+    state.record_import(
+        "Steps",
+        Utc.with_ymd_and_hms(2023, 7, 15, 10, 30, 0).unwrap(),
+        9,
+    );
+    store.save_import_state(&state).await.unwrap();
+    store.load_import_state("health.db").await.unwrap();
+
+    assert_eq!(store.loaded_state(), Some(before));
+    assert_eq!(store.saved_state(), Some(state));
+}
+
+// Test that the InfluxDB state document drops the oldest runs to fit in a string field, and
+// that sources which cannot fit are an error
+#[test]
+fn test_influxdb_state_document_size() {
+    let mut states = StateFile::default();
+    states.insert(ImportState::new("health.db"));
+    for run in 0..MAX_JOURNAL_ENTRIES {
+        let mut entry = JournalEntry::new("import-health-data", "health.db", "influx");
+        entry
+            .errors
+            .push(format!("run {}: {}", run, "x".repeat(1000)));
+        states.append_journal(entry);
+    }
+    assert!(serde_json::to_string(&states).unwrap().len() > MAX_STRING_FIELD_BYTES);
+
+    let document = influxdb_state_document(&states).unwrap();
+    assert!(document.len() <= MAX_STRING_FIELD_BYTES);
+    let stored: StateFile = serde_json::from_str(&document).unwrap();
+    assert_eq!(stored.sources, states.sources);
+    assert!(!stored.journal.is_empty());
+    assert_eq!(stored.journal.last(), states.journal.last());
+
+    for source in 0..2000 {
+        states.insert(ImportState::new(&format!(
+            "exports/{:04}-{}.db",
+            source,
+            "x".repeat(30)
+        )));
+    }
+    let error = influxdb_state_document(&states).unwrap_err();
+    assert!(error.to_string().contains("use another state backend"));
+}
@@ -0,0 +1,89 @@
+use chrono::{TimeZone, Utc};
+use home_db_importer::convert::HeaderRole;
+use home_db_importer::influx_client::MultiFieldPoint;
+use home_db_importer::template::MeasurementTemplate;
+use std::collections::{BTreeMap, HashMap};
+
+fn point(tags: &[(&str, &str)], fields: &[&str]) -> MultiFieldPoint {
+    MultiFieldPoint {
+        measurement: "price".to_string(),
+        time: Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(),
+        tags: tags
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect::<HashMap<_, _>>(),
+        fields: fields
+            .iter()
+            .map(|field| (field.to_string(), 1.0))
+            .collect::<BTreeMap<_, _>>(),
+    }
+}
+
+// Test that the fund tag comes first, then the account and the other tags, with a column per
+// series sorted by its tags
+#[test]
+fn test_template_from_points() {
+    let points = vec![
+        point(&[("fondo", "Fund_B"), ("isin", "LU0274208692")], &["value"]),
+        point(
+            &[
+                ("fondo", "Fund_A"),
+                ("account", "pension"),
+                ("isin", "IE00B4L5Y983"),
+            ],
+            &["value"],
+        ),
+        point(
+            &[
+                ("fondo", "Fund_A"),
+                ("account", "broker"),
+                ("isin", "IE00B4L5Y983"),
+            ],
+            &["value", "units"],
+        ),
+    ];
+    let template = MeasurementTemplate::from_points("price", &points, "fondo");
+    assert_eq!(template.tag_keys, ["fondo", "account", "isin"]);
+    assert_eq!(
+        template.header_roles(),
+        [
+            HeaderRole::Fund,
+            HeaderRole::Account,
+            HeaderRole::Tag("isin".to_string())
+        ]
+    );
+    assert_eq!(template.other_fields(), ["units"]);
+
+    let mut output = Vec::new();
+    template.write_csv_header("Date", &mut output).unwrap();
+    assert_eq!(
+        String::from_utf8(output).unwrap(),
+        "Date,Fund_A,Fund_A,Fund_B\n\
+         ,broker,pension,\n\
+         ,IE00B4L5Y983,IE00B4L5Y983,LU0274208692\n"
+    );
+
+    assert_eq!(
+        template.config_snippet("price.csv", "Date", "%Y-%m-%d"),
+        "[funds]\n\
+         source = \"price.csv\"\n\
+         measurement = \"price\"\n\
+         time_column = \"Date\"\n\
+         time_format = \"%Y-%m-%d\"\n\
+         header_rows = 3\n\
+         header_roles = [\"fund\", \"account\", \"tag:isin\"]\n\
+         # import-funds writes a single `value` field; price also has: units\n"
+    );
+}
+
+// Test that a measurement without tags gets a single column named after it
+#[test]
+fn test_template_without_tags() {
+    let template = MeasurementTemplate::from_points("price", &[point(&[], &["value"])], "fund");
+    let mut output = Vec::new();
+    template.write_csv_header("Date", &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), "Date,price\n");
+    assert!(template
+        .config_snippet("price.csv", "Date", "%Y-%m-%d")
+        .contains("header_rows = 1\nheader_roles = [\"ignore\"]\ntag_key = \"fund\"\n"));
+}
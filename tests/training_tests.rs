@@ -0,0 +1,138 @@
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use home_db_importer::health_data::{HealthDataType, HealthRecord};
+use home_db_importer::influx_client::DataPoint;
+use home_db_importer::training::{first_day_touched, read_from, TrainingLoad};
+use std::collections::HashMap;
+
+fn time(day: u32, hour: u32, minute: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2024, 3, day, hour, minute, 0).unwrap()
+}
+
+fn day(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2024, 3, day).unwrap()
+}
+
+fn session(start: DateTime<Utc>, minutes: i64) -> HealthRecord {
+    let end = start + Duration::minutes(minutes);
+    HealthRecord {
+        record_type: HealthDataType::ExerciseSession,
+        timestamp: start,
+        value: minutes as f64,
+        metadata: HashMap::from([
+            ("app_name".to_string(), "watch".to_string()),
+            ("exercise_type".to_string(), "56".to_string()),
+            (
+                "end_time_millis".to_string(),
+                end.timestamp_millis().to_string(),
+            ),
+        ]),
+        row_id: None,
+        zone_offset: None,
+    }
+}
+
+fn heart_rate(time: DateTime<Utc>, bpm: f64) -> HealthRecord {
+    HealthRecord {
+        record_type: HealthDataType::HeartRate,
+        timestamp: time,
+        value: bpm,
+        metadata: HashMap::new(),
+        row_id: None,
+        zone_offset: None,
+    }
+}
+
+/// A 30 minute workout at the given heart rate, sampled every minute
+fn workout(start: DateTime<Utc>, bpm: f64) -> Vec<HealthRecord> {
+    let mut records = vec![session(start, 30)];
+    records.extend((0..30).map(|minute| heart_rate(start + Duration::minutes(minute), bpm)));
+    records
+}
+
+fn value(points: &[DataPoint], measurement: &str, time: DateTime<Utc>) -> Option<f64> {
+    points
+        .iter()
+        .find(|point| point.measurement == measurement && point.time == time)
+        .map(|point| point.field_value)
+}
+
+// Test the TRIMP of a workout: minutes weighted by the heart rate reserve used
+#[test]
+fn test_session_load() {
+    let load = TrainingLoad::new(180).with_resting_heart_rate(60);
+    // Half the reserve: 30 minutes * 0.5 * 0.64 * e^0.96
+    let records = workout(time(1, 8, 0), 120.0);
+    let points = load.points(&records, day(1), day(1), Tz::UTC);
+    let expected = (30.0 * 0.5 * 0.64 * 0.96f64.exp() * 100.0).round() / 100.0;
+    assert_eq!(
+        value(&points, "training_load", time(1, 8, 0)),
+        Some(expected)
+    );
+    let workout_load = points
+        .iter()
+        .find(|point| point.measurement == "training_load")
+        .unwrap();
+    assert_eq!(workout_load.tags["exercise_type"], "56");
+
+    // A heart rate below resting adds nothing, and samples outside the workout are ignored
+    let mut records = workout(time(1, 8, 0), 50.0);
+    records.push(heart_rate(time(1, 9, 0), 170.0));
+    let points = load.points(&records, day(1), day(1), Tz::UTC);
+    assert_eq!(value(&points, "training_load", time(1, 8, 0)), Some(0.0));
+
+    // A workout without heart rate has no load
+    let points = load.points(&[session(time(1, 8, 0), 30)], day(1), day(1), Tz::UTC);
+    assert_eq!(value(&points, "training_load", time(1, 8, 0)), None);
+}
+
+// Test that the acute and chronic loads average the daily load over 7 and 28 days
+#[test]
+fn test_rolling_loads() {
+    let load = TrainingLoad::new(180).with_resting_heart_rate(60);
+    let mut records = workout(time(1, 8, 0), 120.0);
+    records.extend(workout(time(10, 8, 0), 120.0));
+    let session_load = load.points(&records, day(1), day(1), Tz::UTC)[0].field_value;
+
+    let points = load.points(&records, day(1), day(10), Tz::UTC);
+    let midnight = |day| time(day, 0, 0);
+    assert_eq!(
+        points
+            .iter()
+            .filter(|point| point.measurement == "training_load_acute")
+            .count(),
+        10
+    );
+    let rounded = |value: f64| (value * 100.0).round() / 100.0;
+    assert_eq!(
+        value(&points, "training_load_acute", midnight(7)),
+        Some(rounded(session_load / 7.0))
+    );
+    // The first workout left the acute window, but not the chronic one
+    assert_eq!(
+        value(&points, "training_load_acute", midnight(8)),
+        Some(0.0)
+    );
+    assert_eq!(
+        value(&points, "training_load_ratio", midnight(8)),
+        Some(0.0)
+    );
+    assert_eq!(
+        value(&points, "training_load_chronic", midnight(10)),
+        Some(rounded(2.0 * session_load / 28.0))
+    );
+}
+
+// Test the days whose loads change with newly imported records
+#[test]
+fn test_days_touched() {
+    let records = vec![
+        session(time(5, 8, 0), 30),
+        // May belong to a workout started the day before
+        heart_rate(time(3, 0, 10), 140.0),
+    ];
+    let first = first_day_touched(&records, Tz::UTC).unwrap();
+    assert_eq!(first, day(2));
+    assert!(read_from(first) < time(2, 0, 0) - Duration::days(27));
+    assert_eq!(first_day_touched(&[], Tz::UTC), None);
+}
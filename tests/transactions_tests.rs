@@ -0,0 +1,120 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::write_file;
+use home_db_importer::influx_client::DataPoint;
+use home_db_importer::source::Source;
+use home_db_importer::state_management::ImportState;
+use home_db_importer::transactions::{CostBasis, TransactionKind, TransactionReader};
+
+const TRANSACTIONS: &str = "\
+Date,Fund,Type,Units,Price,Amount,Fees
+2024-01-10,Fund A,Buy,10,10,,1
+2024-01-01,Fund A,Buy,10,8,,1
+2024-02-01,Fund A,Sell,5,12,,1
+2024-02-01,Fund B,Buy,2,50,,
+2024-03-01,Fund A,Dividend,,,3,
+";
+
+fn value(points: &[DataPoint], measurement: &str, fund: &str) -> Option<f64> {
+    points
+        .iter()
+        .find(|point| point.measurement == measurement && point.tags["fondo"] == fund)
+        .map(|point| point.field_value)
+}
+
+// Test that transactions are read oldest first, with amounts from quantity times price
+#[test]
+fn test_transactions() {
+    let (_dir, path) = write_file("transactions.csv", TRANSACTIONS);
+    let transactions = TransactionReader::new(&path).transactions().unwrap();
+
+    assert_eq!(transactions.len(), 5);
+    assert_eq!(
+        transactions[0].timestamp,
+        Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap()
+    );
+    assert_eq!(transactions[0].fund, "Fund_A");
+    assert_eq!(transactions[0].amount, 80.0);
+    assert_eq!(transactions[0].fees, 1.0);
+    assert_eq!(transactions[2].kind, TransactionKind::Sell);
+    assert_eq!(transactions[4].kind, TransactionKind::Dividend);
+    assert_eq!(transactions[4].price, None);
+}
+
+// Test the average cost method: a sale takes its share of the cost and realizes the rest
+// of its proceeds, and selling more than is held is an error
+#[test]
+fn test_cost_basis() {
+    let (_dir, path) = write_file("transactions.csv", TRANSACTIONS);
+    let transactions = TransactionReader::new(&path).transactions().unwrap();
+    let mut basis = CostBasis::new();
+    for transaction in &transactions[..3] {
+        basis.apply(transaction).unwrap();
+    }
+
+    // 20 units cost 182, so the 5 sold cost 45.5 and fetched 60 - 1
+    let position = basis.position("Fund_A").unwrap();
+    assert_eq!(position.units, 15.0);
+    assert_eq!(position.cost, 136.5);
+    assert_eq!(position.realized, 13.5);
+    assert_eq!(position.unrealized(12.0), 43.5);
+
+    let mut oversell = transactions[2].clone();
+    oversell.quantity = 16.0;
+    assert!(basis
+        .apply(&oversell)
+        .unwrap_err()
+        .contains("only 15 are held"));
+}
+
+// Test the amounts written for the funds of each transaction and for the total
+#[test]
+fn test_records() {
+    let (_dir, path) = write_file("transactions.csv", TRANSACTIONS);
+    let records = TransactionReader::new(&path).records().unwrap();
+    assert_eq!(records.len(), 4);
+
+    // The sale of Fund A and the buy of Fund B share a record
+    let points = &records[2].points;
+    assert_eq!(value(points, "invested_capital", "Fund_A"), Some(136.5));
+    assert_eq!(value(points, "invested_capital", "Fund_B"), Some(100.0));
+    assert_eq!(value(points, "invested_capital", "total"), Some(236.5));
+    assert_eq!(value(points, "realized_gain", "total"), Some(13.5));
+    assert_eq!(value(points, "unrealized_gain", "Fund_A"), Some(43.5));
+    assert_eq!(value(points, "unrealized_gain", "total"), Some(43.5));
+
+    // The dividend only adds to the realized gain
+    let points = &records[3].points;
+    assert_eq!(value(points, "realized_gain", "Fund_A"), Some(16.5));
+    assert_eq!(value(points, "invested_capital", "Fund_A"), Some(136.5));
+    assert_eq!(value(points, "invested_capital", "Fund_B"), None);
+}
+
+// Test that only the transactions after the watermark are imported, with the cost basis
+// carried on from the earlier ones
+#[test]
+fn test_records_since_watermark() {
+    let (_dir, path) = write_file("transactions.csv", TRANSACTIONS);
+    let reader = TransactionReader::new(&path);
+
+    let mut state = ImportState::new(&path);
+    state.last_imported_timestamp = Some(Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap());
+    let stream = reader.records_since(&state).unwrap();
+    assert_eq!(stream.already_imported(), 3);
+    let records: Vec<_> = stream.collect();
+    assert_eq!(records.len(), 1);
+    let points = reader.to_points(&records[0]).unwrap();
+    assert_eq!(value(&points, "realized_gain", "total"), Some(16.5));
+}
+
+// Test that a row without an amount or a price is rejected
+#[test]
+fn test_missing_amount() {
+    let (_dir, path) = write_file(
+        "transactions.csv",
+        "Date,Fund,Type,Units\n2024-01-01,Fund A,Buy,10\n",
+    );
+    let error = TransactionReader::new(&path).transactions().unwrap_err();
+    assert!(error.to_string().contains("needs an amount"));
+}
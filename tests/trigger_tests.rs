@@ -0,0 +1,206 @@
+use home_db_importer::metrics::RunResult;
+use home_db_importer::state_management::JournalEntry;
+use home_db_importer::trigger::TriggerServer;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::tempdir;
+
+// Helper function to create a server for the jobs "funds" and "health", where "funds" fails
+fn create_server(token: Option<&str>) -> Arc<TriggerServer> {
+    Arc::new(TriggerServer::new(
+        &["funds".to_string(), "health".to_string()],
+        token.map(String::from),
+        Box::new(|job: &str| {
+            if job == "funds" {
+                return (RunResult::Failure, Some(1), None);
+            }
+            let mut entry =
+                JournalEntry::new("import-health-data", "export.db", "http://localhost:8086");
+            entry.records.insert("Steps".to_string(), 12);
+            (RunResult::Success, Some(0), Some(entry))
+        }),
+    ))
+}
+
+// Test that a job run with ?wait answers with its result, which /status then shows
+#[test]
+fn test_run_and_wait() {
+    let server = create_server(None);
+
+    let response = server.handle("POST", "/run/health?wait", None);
+    assert_eq!(response.status, 200);
+    let run: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(run["job"], "health");
+    assert_eq!(run["run"]["result"], "success");
+    assert_eq!(run["run"]["journal"]["records"]["Steps"], 12);
+
+    let response = server.handle("POST", "/run/funds?wait", None);
+    assert_eq!(response.status, 500);
+    assert!(response.body.contains("\"exit_code\":1"));
+
+    let response = server.handle("GET", "/status", None);
+    assert_eq!(response.status, 200);
+    let status: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(status["jobs"]["health"]["running"], false);
+    assert_eq!(status["jobs"]["health"]["last_run"]["result"], "success");
+    assert_eq!(status["jobs"]["funds"]["last_run"]["result"], "failure");
+}
+
+// Test that a running job is not started twice, and that other jobs still can be
+#[test]
+fn test_job_already_running() {
+    let (started_tx, started_rx) = mpsc::channel();
+    let (finish_tx, finish_rx) = mpsc::channel::<()>();
+    let finish_rx = Mutex::new(finish_rx);
+    let server = Arc::new(TriggerServer::new(
+        &["health".to_string(), "funds".to_string()],
+        None,
+        Box::new(move |job: &str| {
+            if job == "health" {
+                started_tx.send(()).unwrap();
+                finish_rx.lock().unwrap().recv().unwrap();
+            }
+            (RunResult::NothingNew, Some(2), None)
+        }),
+    ));
+
+    assert_eq!(server.handle("POST", "/run/health", None).status, 202);
+    started_rx.recv().unwrap();
+    assert!(server.status()["health"].running);
+    assert_eq!(server.handle("POST", "/run/health", None).status, 409);
+    assert_eq!(server.handle("POST", "/run/funds?wait", None).status, 200);
+
+    finish_tx.send(()).unwrap();
+    while server.status()["health"].running {
+        std::thread::yield_now();
+    }
+    let last_run = server.status()["health"].last_run.clone().unwrap();
+    assert_eq!(last_run.result, "nothing_new");
+}
+
+// Test the token check, unknown jobs and endpoints, and wrong methods
+#[test]
+fn test_rejected_requests() {
+    let server = create_server(Some("secret"));
+
+    assert_eq!(server.handle("GET", "/status", None).status, 401);
+    assert_eq!(
+        server.handle("GET", "/status", Some("Bearer wrong")).status,
+        401
+    );
+    assert_eq!(
+        server
+            .handle("GET", "/status", Some("Bearer secret"))
+            .status,
+        200
+    );
+
+    let auth = Some("Bearer secret");
+    assert_eq!(server.handle("POST", "/run/weather", auth).status, 404);
+    assert_eq!(server.handle("GET", "/run/health", auth).status, 405);
+    assert_eq!(server.handle("POST", "/status", auth).status, 405);
+    assert_eq!(server.handle("GET", "/", auth).status, 404);
+    assert!(server.status()["health"].last_run.is_none());
+}
+
+// Test that an upload is stored as the job's source before the job runs, and that bad
+// uploads and jobs without a source are refused
+#[test]
+fn test_upload() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("health_connect_export.db");
+    let uploads = BTreeMap::from([("health".to_string(), source.to_str().unwrap().to_string())]);
+    let server = Arc::new(create_server_with_uploads(uploads));
+
+    let export = b"SQLite format 3\0rest of the database".as_slice();
+    let length = Some(export.len() as u64);
+    let response = server.upload("PUT", "/upload/health?wait", None, length, export);
+    assert_eq!(response.status, 200);
+    let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(body["stored"]["from_zip"], false);
+    assert_eq!(body["run"]["result"], "success");
+    assert_eq!(fs::read(&source).unwrap(), export);
+
+    let csv = b"name,value\n".as_slice();
+    let response = server.upload("POST", "/upload/health", None, Some(11), csv);
+    assert_eq!(response.status, 400);
+    assert!(response.body.contains("not a Health Connect export"));
+    assert_eq!(fs::read(&source).unwrap(), export);
+    assert!(!server.status()["health"].running);
+
+    assert_eq!(
+        server
+            .upload("POST", "/upload/funds", None, length, export)
+            .status,
+        404
+    );
+    assert_eq!(
+        server
+            .upload("GET", "/upload/health", None, length, export)
+            .status,
+        405
+    );
+}
+
+// Test that uploads without a length (chunked) and uploads over the limit are refused
+// before anything is stored
+#[test]
+fn test_upload_limits() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("health_connect_export.db");
+    let uploads = BTreeMap::from([("health".to_string(), source.to_str().unwrap().to_string())]);
+    let server = Arc::new(create_server_with_uploads(uploads).with_max_upload(16));
+    let export = b"SQLite format 3\0rest of the database".as_slice();
+
+    let response = server.upload("POST", "/upload/health", None, None, export);
+    assert_eq!(response.status, 411);
+    let response = server.upload("POST", "/upload/health", None, Some(38), export);
+    assert_eq!(response.status, 413);
+    assert!(!source.exists());
+    assert!(!server.status()["health"].running);
+}
+
+// Test that a connection that sends nothing is closed instead of holding its thread
+#[test]
+fn test_idle_connection_is_closed() {
+    // Bind and drop a listener to get a free port
+    let addr = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .to_string();
+    let server = Arc::new(
+        Arc::into_inner(create_server(None))
+            .unwrap()
+            .with_connection_timeout(Duration::from_millis(200)),
+    );
+    let serve_addr = addr.clone();
+    thread::spawn(move || server.serve(&serve_addr));
+
+    let mut stream = loop {
+        match TcpStream::connect(&addr) {
+            Ok(stream) => break stream,
+            Err(_) => thread::sleep(Duration::from_millis(10)),
+        }
+    };
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let started = Instant::now();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    assert!(response.is_empty());
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+// Helper function to create a server like create_server that takes uploads
+fn create_server_with_uploads(uploads: BTreeMap<String, String>) -> TriggerServer {
+    Arc::into_inner(create_server(None))
+        .unwrap()
+        .with_uploads(uploads)
+}
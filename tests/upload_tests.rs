@@ -0,0 +1,87 @@
+use chrono::{Local, TimeZone};
+use home_db_importer::upload::{store_export, upload_path, EXPORT_DB_NAME};
+use std::fs;
+use std::io::{Cursor, Write};
+use tempfile::tempdir;
+use zip::write::SimpleFileOptions;
+
+const DATABASE: &[u8] = b"SQLite format 3\0tables and records";
+
+// Helper function to create a zip file holding the given files
+fn create_zip(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    for (name, contents) in files {
+        zip.start_file(*name, SimpleFileOptions::default()).unwrap();
+        zip.write_all(contents).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+// Test that the database is taken out of an uploaded zip file, or stored as it is
+#[test]
+fn test_store_export() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("export.db");
+    let source = source.to_str().unwrap();
+
+    let zip = create_zip(&[("readme.txt", b"hello"), (EXPORT_DB_NAME, DATABASE)]);
+    let stored = store_export(zip.as_slice(), source, Local::now(), u64::MAX).unwrap();
+    assert!(stored.from_zip);
+    assert_eq!(stored.path, source);
+    assert_eq!(stored.bytes, DATABASE.len() as u64);
+    assert_eq!(fs::read(source).unwrap(), DATABASE);
+
+    let newer = b"SQLite format 3\0newer records";
+    let stored = store_export(newer.as_slice(), source, Local::now(), u64::MAX).unwrap();
+    assert!(!stored.from_zip);
+    assert_eq!(fs::read(source).unwrap(), newer);
+
+    // Nothing is replaced, nor left behind, by uploads that are not exports
+    let not_sqlite = create_zip(&[(EXPORT_DB_NAME, b"not a database")]);
+    for upload in [
+        b"Date,Fund A\n".to_vec(),
+        create_zip(&[("readme.txt", b"hello")]),
+        create_zip(&[("a.db", DATABASE), ("b.db", DATABASE)]),
+        not_sqlite,
+    ] {
+        assert!(store_export(upload.as_slice(), source, Local::now(), u64::MAX).is_err());
+    }
+    assert_eq!(fs::read(source).unwrap(), newer);
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+// Test that a database larger than the limit is not taken out of a zip file, however small
+// the zip file is
+#[test]
+fn test_extracted_size_limit() {
+    let dir = tempdir().unwrap();
+    let source = dir.path().join("export.db");
+    let source = source.to_str().unwrap();
+
+    let mut database = DATABASE.to_vec();
+    database.resize(1024 * 1024, 0);
+    let zip = create_zip(&[(EXPORT_DB_NAME, &database)]);
+    assert!(zip.len() < 64 * 1024);
+
+    let error = store_export(zip.as_slice(), source, Local::now(), 64 * 1024).unwrap_err();
+    assert!(error.to_string().contains("larger than the limit"));
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+
+    let stored = store_export(zip.as_slice(), source, Local::now(), 1024 * 1024).unwrap();
+    assert_eq!(stored.bytes, database.len() as u64);
+}
+
+// Test that uploads for a directory of exports are added to it as its last file by name
+#[test]
+fn test_upload_to_directory() {
+    let dir = tempdir().unwrap();
+    fs::write(dir.path().join("2024-06.db"), DATABASE).unwrap();
+    let now = Local.with_ymd_and_hms(2025, 3, 1, 7, 30, 0).unwrap();
+    let expected = dir.path().join("2025-03-01-073000.db");
+    assert_eq!(upload_path(dir.path().to_str().unwrap(), now), expected);
+
+    let zip = create_zip(&[("export/other.db", DATABASE)]);
+    let stored = store_export(zip.as_slice(), dir.path().to_str().unwrap(), now, u64::MAX).unwrap();
+    assert_eq!(stored.path, expected.display().to_string());
+    assert_eq!(fs::read(&expected).unwrap(), DATABASE);
+}
@@ -0,0 +1,145 @@
+mod common;
+
+use chrono::{TimeZone, Utc};
+use common::write_file;
+use home_db_importer::influx_client::DataPoint;
+use home_db_importer::source::Source;
+use home_db_importer::state_management::ImportState;
+use home_db_importer::weather::{UnitSystem, WeatherReader};
+
+const ECOWITT_METRIC: &str = "\
+Time,Indoor Temperature(℃),Indoor Humidity(%),Outdoor Temperature(℃),Outdoor Humidity(%),Wind(m/s),Gust(m/s),Wind Direction(°),ABS Pressure(hPa),Rain Rate(mm/hr),Daily Rain(mm)
+2024-03-01 00:05,21.5,45,10.0,80,2.5,5.0,180,1013.2,0.0,1.2
+2024-03-01 00:00,21.4,45,9.5,81,--,4.0,175,1013.1,0.0,1.2
+";
+
+const WUNDERGROUND_IMPERIAL: &str = "\
+Time,TemperatureF,DewpointF,PressureIn,WindDirection,WindDirectionDegrees,WindSpeedMPH,WindSpeedGustMPH,Humidity,HourlyPrecipIn,Conditions,Clouds,dailyrainin,SoftwareType,DateUTC
+2024-03-01 01:00:00,50.0,41.0,29.92,South,180,10.0,15.0,70,0.10,,,1.00,WS-2902,2024-03-01 00:00:00
+2024-03-01 01:05:00,51.8,41.0,29.92,South,180,5.0,10.0,69,0.00,,,1.00,WS-2902,2024-03-01 00:05:00
+";
+
+fn value(points: &[DataPoint], measurement: &str, tag: (&str, &str)) -> Option<f64> {
+    points
+        .iter()
+        .find(|point| {
+            point.measurement == measurement
+                && point.tags.get(tag.0).map(String::as_str) == Some(tag.1)
+        })
+        .map(|point| (point.field_value * 100.0).round() / 100.0)
+}
+
+// Test that an Ecowitt export is read oldest first, with metric speeds in km/h, missing
+// readings left out and unknown columns ignored
+#[test]
+fn test_ecowitt_export() {
+    let (_dir, path) = write_file("garden.csv", ECOWITT_METRIC);
+    let reader = WeatherReader::new(&path);
+
+    let readings = reader.readings().unwrap();
+    assert_eq!(readings.len(), 2);
+    assert_eq!(
+        readings[0].timestamp,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()
+    );
+    assert_eq!(
+        readings[1].timestamp,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 5, 0).unwrap()
+    );
+
+    let points = &readings[1].points;
+    assert_eq!(points.len(), 9);
+    assert!(points.iter().all(|point| point.tags["station"] == "garden"));
+    assert_eq!(
+        value(points, "temperature", ("sensor", "outdoor")),
+        Some(10.0)
+    );
+    assert_eq!(
+        value(points, "temperature", ("sensor", "indoor")),
+        Some(21.5)
+    );
+    assert_eq!(value(points, "humidity", ("sensor", "outdoor")), Some(80.0));
+    assert_eq!(value(points, "wind", ("kind", "speed")), Some(9.0));
+    assert_eq!(value(points, "wind", ("kind", "gust")), Some(18.0));
+    assert_eq!(value(points, "wind", ("kind", "direction")), Some(180.0));
+    assert_eq!(value(points, "rain", ("kind", "daily")), Some(1.2));
+    assert!(points.iter().all(|point| point.measurement != "pressure"));
+
+    // The wind speed was "--" in the first row
+    assert_eq!(readings[0].points.len(), 8);
+    assert_eq!(value(&readings[0].points, "wind", ("kind", "speed")), None);
+}
+
+// Test that a Weather Underground export in imperial units is converted to metric, using
+// the DateUTC column rather than the local Time column
+#[test]
+fn test_wunderground_export_to_metric() {
+    let (_dir, path) = write_file("KXX123.csv", WUNDERGROUND_IMPERIAL);
+    let reader = WeatherReader::new(&path).with_station("backyard");
+
+    let readings = reader.readings().unwrap();
+    assert_eq!(
+        readings[0].timestamp,
+        Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap()
+    );
+
+    let points = &readings[0].points;
+    assert!(points
+        .iter()
+        .all(|point| point.tags["station"] == "backyard"));
+    assert_eq!(
+        value(points, "temperature", ("sensor", "outdoor")),
+        Some(10.0)
+    );
+    assert_eq!(
+        value(points, "temperature", ("sensor", "dew_point")),
+        Some(5.0)
+    );
+    assert_eq!(value(points, "humidity", ("sensor", "outdoor")), Some(70.0));
+    assert_eq!(value(points, "wind", ("kind", "speed")), Some(16.09));
+    assert_eq!(value(points, "rain", ("kind", "rate")), Some(2.54));
+    assert_eq!(value(points, "rain", ("kind", "daily")), Some(25.4));
+}
+
+// Test that metric exports are converted when imperial units are asked for
+#[test]
+fn test_imperial_units() {
+    let (_dir, path) = write_file("garden.csv", ECOWITT_METRIC);
+    let reader = WeatherReader::new(&path).with_units(UnitSystem::Imperial);
+
+    let readings = reader.readings().unwrap();
+    let points = &readings[1].points;
+    assert_eq!(
+        value(points, "temperature", ("sensor", "outdoor")),
+        Some(50.0)
+    );
+    assert_eq!(value(points, "wind", ("kind", "speed")), Some(5.59));
+    assert_eq!(value(points, "rain", ("kind", "daily")), Some(0.05));
+}
+
+// Test that readings up to the watermark are skipped and counted
+#[test]
+fn test_records_since_watermark() {
+    let (_dir, path) = write_file("garden.csv", ECOWITT_METRIC);
+    let reader = WeatherReader::new(&path);
+
+    let mut state = ImportState::new(&path);
+    state.last_imported_timestamp = Some(Utc.with_ymd_and_hms(2024, 3, 1, 0, 0, 0).unwrap());
+    let stream = reader.records_since(&state).unwrap();
+    assert_eq!(stream.already_imported(), 1);
+    let records: Vec<_> = stream.collect();
+    assert_eq!(records.len(), 1);
+    assert_eq!(
+        reader.timestamp(&records[0]),
+        Some(Utc.with_ymd_and_hms(2024, 3, 1, 0, 5, 0).unwrap())
+    );
+    assert_eq!(reader.to_points(&records[0]).unwrap().len(), 9);
+}
+
+// Test that an export without any weather columns is rejected
+#[test]
+fn test_no_weather_columns() {
+    let (_dir, path) = write_file("other.csv", "Time,Pressure(hPa)\n2024-03-01 00:00,1013\n");
+    let error = WeatherReader::new(&path).readings().unwrap_err();
+    assert!(error.to_string().contains("No temperature"));
+}
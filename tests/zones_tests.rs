@@ -0,0 +1,92 @@
+use chrono::{NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use home_db_importer::health_data::{HealthDataType, HealthRecord};
+use home_db_importer::zones::{days_touched, HeartRateZones};
+use std::collections::{BTreeSet, HashMap};
+
+fn sample(hour: u32, minute: u32, bpm: f64) -> HealthRecord {
+    HealthRecord {
+        record_type: HealthDataType::HeartRate,
+        timestamp: Utc.with_ymd_and_hms(2024, 3, 1, hour, minute, 0).unwrap(),
+        value: bpm,
+        metadata: HashMap::from([("app_name".to_string(), "watch".to_string())]),
+        row_id: None,
+        zone_offset: None,
+    }
+}
+
+fn minutes(points: &[home_db_importer::DataPoint], period: &str) -> Vec<f64> {
+    let mut zones: Vec<(String, f64)> = points
+        .iter()
+        .filter(|point| point.tags["period"] == period)
+        .map(|point| (point.tags["zone"].clone(), point.field_value))
+        .collect();
+    zones.sort_by(|a, b| a.0.cmp(&b.0));
+    zones.into_iter().map(|(_, minutes)| minutes).collect()
+}
+
+// Test that the default zones start at 50 to 90% of the maximum heart rate, and that
+// boundaries must be increasing
+#[test]
+fn test_zone_boundaries() {
+    assert_eq!(
+        HeartRateZones::from_max_heart_rate(200),
+        HeartRateZones::new(&[100, 120, 140, 160, 180]).unwrap()
+    );
+    assert!(HeartRateZones::new(&[120, 100]).is_err());
+    assert!(HeartRateZones::new(&[]).is_err());
+}
+
+// Test the minutes in each zone of a day and of a workout, each sample holding until the
+// next one for up to 5 minutes
+#[test]
+fn test_zone_minutes() {
+    let zones = HeartRateZones::new(&[100, 120, 140]).unwrap();
+    let run = HealthRecord {
+        record_type: HealthDataType::ExerciseSession,
+        metadata: HashMap::from([
+            ("app_name".to_string(), "watch".to_string()),
+            ("exercise_type".to_string(), "56".to_string()),
+            (
+                "end_time_millis".to_string(),
+                Utc.with_ymd_and_hms(2024, 3, 1, 7, 10, 0)
+                    .unwrap()
+                    .timestamp_millis()
+                    .to_string(),
+            ),
+        ]),
+        ..sample(7, 0, 10.0)
+    };
+    let records = vec![
+        sample(6, 0, 60.0),
+        run,
+        sample(7, 0, 110.0),
+        sample(7, 2, 130.0),
+        sample(7, 5, 150.0),
+        // A gap of an hour counts as 5 minutes
+        sample(7, 8, 125.0),
+        sample(8, 8, 90.0),
+    ];
+    let days = days_touched(&records, Tz::UTC);
+    assert_eq!(
+        days,
+        BTreeSet::from([NaiveDate::from_ymd_opt(2024, 3, 1).unwrap()])
+    );
+
+    let points = zones.points(&records, &days, Tz::UTC);
+    assert!(points
+        .iter()
+        .all(|point| point.measurement == "HeartRateZones"));
+    assert_eq!(minutes(&points, "day"), vec![2.0, 8.0, 3.0]);
+    // The last sample of the run holds until its end
+    assert_eq!(minutes(&points, "workout"), vec![2.0, 5.0, 3.0]);
+    let workout = points
+        .iter()
+        .find(|point| point.tags["period"] == "workout")
+        .unwrap();
+    assert_eq!(workout.tags["exercise_type"], "56");
+    assert_eq!(
+        workout.time,
+        Utc.with_ymd_and_hms(2024, 3, 1, 7, 0, 0).unwrap()
+    );
+}